@@ -0,0 +1,141 @@
+//! Parser throughput benchmarks
+//!
+//! Large aggregator pages (hundreds of coupons in one HTML page, JSON feed or CSV
+//! export) are where the parser spends its time per page, and product pages with no
+//! coupons at all are most of what a crawl fetches. The pages are built
+//! synthetically so runs are comparable between machines and commits.
+//!
+//! `cargo bench --bench parser` runs them; criterion compares each run with the one
+//! before it, so run it on the base commit first.
+
+use std::fmt::Write as _;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use deal_service::coupon_engine::parser::Parser;
+
+/// Coupons per aggregator page and reviews on the product page
+const ITEMS: usize = 500;
+
+/// One synthetic page
+struct BenchPage {
+    name: &'static str,
+    source_url: &'static str,
+    content: String,
+}
+
+/// An HTML listing, a JSON feed and a CSV export with `items` coupons each
+fn aggregator_pages(items: usize) -> Vec<BenchPage> {
+    let mut html = String::from("<html><head><title>Top coupons</title></head><body><main>");
+    let mut json = Vec::with_capacity(items);
+    let mut csv = String::from("code,title,discount_type,discount_value,expiry\n");
+    for i in 0..items {
+        let code = format!("SAVE{}X{}", i % 90 + 10, i);
+        let percent = i % 60 + 5;
+        let minimum = 25 + i % 8 * 25;
+        let _ = write!(
+            html,
+            "<div class=\"coupon-item\"><h3>{percent}% off sitewide at Store {store}</h3>\
+             <span class=\"coupon-code\" data-title=\"{percent}% off\">{code}</span>\
+             <p>Use code {code} for {percent}% off orders. Minimum order ${minimum}. \
+             Excludes gift cards, clearance and marketplace items.</p>\
+             <a href=\"/out/{i}\" rel=\"nofollow\">Shop now</a></div>",
+            store = i % 40,
+        );
+        json.push(serde_json::json!({
+            "code": code,
+            "title": format!("{}% off sitewide", percent),
+            "description": format!("{}% off orders over ${}, excludes gift cards", percent, minimum),
+            "discountType": "percent",
+            "discountValue": percent,
+            "minimumOrder": minimum,
+            "merchant": format!("Store {}", i % 40),
+            "expires": "2030-01-01T00:00:00Z",
+        }));
+        let _ = writeln!(csv, "{},{}% off sitewide,percentage,{},2030-01-01", code, percent, percent);
+    }
+    html.push_str("</main></body></html>");
+
+    vec![
+        BenchPage {
+            name: "html",
+            source_url: "https://aggregator.example.com/coupons",
+            content: html,
+        },
+        BenchPage {
+            name: "json",
+            source_url: "https://feeds.example.com/coupons",
+            content: serde_json::json!({ "coupons": json }).to_string(),
+        },
+        BenchPage {
+            name: "csv",
+            source_url: "https://exports.example.com/coupons",
+            content: csv,
+        },
+    ]
+}
+
+/// A product page with `items` reviews and no coupon codes
+fn product_page(items: usize) -> BenchPage {
+    let mut html = String::from(
+        "<html><head><title>Wireless Headphones</title>\
+         <script type=\"application/ld+json\">{\"@type\": \"Product\", \"name\": \"Wireless Headphones\", \"sku\": \"WH-1000\"}</script>\
+         </head><body><main><h1>Wireless Headphones</h1><span class=\"price\">$199.99</span>\
+         <button class=\"add-to-cart\">Add to cart</button><section class=\"reviews\">",
+    );
+    for i in 0..items {
+        let _ = write!(
+            html,
+            "<div class=\"review\" data-rating=\"{rating}\"><h4>Great sound, {i} hours of battery</h4>\
+             <p>Bought these for my commute. Noise cancelling works well on the train and \
+             the fit is comfortable for long listening sessions. Shipping took {days} days.</p>\
+             <span class=\"author\">Customer {i}</span></div>",
+            rating = i % 5 + 1,
+            days = i % 7 + 1,
+        );
+    }
+    html.push_str("</section></main></body></html>");
+
+    BenchPage {
+        name: "product",
+        source_url: "https://shop.example.com/products/wireless-headphones",
+        content: html,
+    }
+}
+
+fn extract_coupons(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_current_thread().build().expect("a runtime");
+    let parser = Parser::new();
+    let mut pages = aggregator_pages(ITEMS);
+    pages.push(product_page(ITEMS));
+
+    let mut group = c.benchmark_group("extract_coupons");
+    for page in &pages {
+        group.throughput(Throughput::Bytes(page.content.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(page.name), page, |b, page| {
+            b.iter(|| runtime.block_on(parser.extract_coupons(&page.content, page.source_url)).expect("the page parses"))
+        });
+    }
+    group.finish();
+}
+
+/// The JSON feed read a record at a time, as seeding reads it
+fn extract_feed_coupons(c: &mut Criterion) {
+    let parser = Parser::new();
+    let feed = aggregator_pages(ITEMS).into_iter().find(|page| page.name == "json").expect("a JSON feed");
+
+    let mut group = c.benchmark_group("extract_feed_coupons");
+    group.throughput(Throughput::Bytes(feed.content.len() as u64));
+    group.bench_function("json", |b| {
+        b.iter(|| {
+            let mut coupons = 0;
+            parser
+                .extract_feed_coupons(feed.content.as_bytes(), feed.source_url, |_| coupons += 1)
+                .expect("the feed parses");
+            coupons
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, extract_coupons, extract_feed_coupons);
+criterion_main!(benches);
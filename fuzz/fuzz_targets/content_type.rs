@@ -0,0 +1,8 @@
+#![no_main]
+
+use deal_service::coupon_engine::scraper::detect_content_type;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|content: &str| {
+    detect_content_type(content);
+});
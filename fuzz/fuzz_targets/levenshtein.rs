@@ -0,0 +1,14 @@
+#![no_main]
+
+use deal_service::coupon_engine::deduplicator::Deduplicator;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|pair: (&str, &str)| {
+    let (a, b) = pair;
+    let dedup = Deduplicator::new();
+    let distance = dedup.levenshtein_distance(a, b);
+
+    assert_eq!(distance, dedup.levenshtein_distance(b, a));
+    assert_eq!(distance == 0, a == b);
+    assert!(distance <= a.chars().count().max(b.chars().count()));
+});
@@ -0,0 +1,8 @@
+#![no_main]
+
+use deal_service::coupon_engine::validator::Validator;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|code: &str| {
+    Validator::new().has_repetitive_pattern(code);
+});
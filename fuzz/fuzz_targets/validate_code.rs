@@ -0,0 +1,13 @@
+#![no_main]
+
+use deal_service::coupon_engine::validator::Validator;
+use deal_service::CouponCode;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|raw: &str| {
+    if let Ok(code) = CouponCode::parse(raw) {
+        if Validator::new().validate_code(&code) {
+            assert!(code.as_str().chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit()));
+        }
+    }
+});
@@ -0,0 +1,89 @@
+//! Compile-time checks for `deal-service`
+//!
+//! [`selector!`] parses a CSS selector literal while the crate compiles, so a typo
+//! in a built-in parser definition fails the build, naming the selector, instead
+//! of panicking on the first page that reaches it in production.
+
+use proc_macro::{Delimiter, TokenStream, TokenTree};
+
+/// `selector!("div.coupon > .code")` is a `&'static scraper::Selector`, checked at
+/// compile time and parsed once, on first use
+#[proc_macro]
+pub fn selector(input: TokenStream) -> TokenStream {
+    match checked(input) {
+        Ok(literal) => format!(
+            "{{
+                static SELECTOR: ::std::sync::LazyLock<::scraper::Selector> = ::std::sync::LazyLock::new(|| {{
+                    ::scraper::Selector::parse({literal}).expect(\"selector was checked at compile time\")
+                }});
+                &*SELECTOR
+            }}"
+        )
+        .parse()
+        .expect("expansion is valid Rust"),
+        Err(message) => format!("::core::compile_error!({:?})", message).parse().expect("expansion is valid Rust"),
+    }
+}
+
+/// The literal as written, once its selector parses
+fn checked(input: TokenStream) -> Result<String, String> {
+    let mut tokens = input.into_iter();
+    let literal = match (tokens.next(), tokens.next()) {
+        (Some(TokenTree::Literal(literal)), None) => literal.to_string(),
+        // A literal passed through a `macro_rules!` arrives wrapped in an invisible group
+        (Some(TokenTree::Group(group)), None) if group.delimiter() == Delimiter::None => return checked(group.stream()),
+        _ => return Err("selector! takes one string literal".to_string()),
+    };
+    let source = unquote(&literal).ok_or_else(|| format!("selector! cannot read {} as a plain or raw string literal", literal))?;
+    scraper::Selector::parse(&source).map_err(|e| format!("invalid selector '{}': {}", source, e))?;
+    Ok(literal)
+}
+
+/// The value of a string literal's source: plain with the common escapes, or raw
+fn unquote(literal: &str) -> Option<String> {
+    if let Some(raw) = literal.strip_prefix('r') {
+        let hashes = raw.len() - raw.trim_start_matches('#').len();
+        let body = &raw[hashes..raw.len().checked_sub(hashes)?];
+        return body.strip_prefix('"')?.strip_suffix('"').map(str::to_string);
+    }
+
+    let body = literal.strip_prefix('"')?.strip_suffix('"')?;
+    let mut value = String::with_capacity(body.len());
+    let mut chars = body.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+        match chars.next()? {
+            'n' => value.push('\n'),
+            'r' => value.push('\r'),
+            't' => value.push('\t'),
+            '0' => value.push('\0'),
+            escaped @ ('\\' | '"' | '\'') => value.push(escaped),
+            '\n' => {
+                // A line continuation skips the newline and the next line's indentation
+                let rest = chars.as_str().trim_start();
+                chars = rest.chars();
+            }
+            _ => return None,
+        }
+    }
+    Some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unquotes_literals_before_checking_them() {
+        assert_eq!(unquote(r#""[class*='coupon-code']""#).as_deref(), Some("[class*='coupon-code']"));
+        assert_eq!(unquote(r#""meta[name=\"x\"]""#).as_deref(), Some("meta[name=\"x\"]"));
+        assert_eq!(unquote(r###"r#"a[title="b"]"#"###).as_deref(), Some("a[title=\"b\"]"));
+        assert_eq!(unquote("\".a, \\\n    .b\"").as_deref(), Some(".a, .b"));
+        assert_eq!(unquote("b\"bytes\""), None);
+        assert_eq!(unquote("42"), None);
+        assert_eq!(unquote(r#""\u{41}""#), None);
+    }
+}
@@ -0,0 +1,3 @@
+//! Deal alert creation and parsing
+
+pub mod natural_language;
@@ -0,0 +1,315 @@
+//! Free-text alert parsing
+//!
+//! A rule-based grammar handles the common phrasings ("notify me when X drops
+//! below $N on Y"); requests it cannot interpret confidently are handed to an
+//! OpenAI-compatible LLM endpoint when one is configured.
+
+use chrono::Utc;
+use lazy_static::lazy_static;
+use regex::Regex;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::models::alert::{AlertType, DealAlert};
+
+lazy_static! {
+    static ref LEAD_PATTERN: Regex = Regex::new(
+        r"(?i)^\s*(?:please\s+)?(?:(?:tell|notify|alert|ping|email|text|let)\s+me\s+(?:know\s+)?|watch\s+for\s+)?(?:when(?:ever)?|if|once)?\s*"
+    ).unwrap();
+    static ref PRODUCT_END_PATTERN: Regex = Regex::new(
+        r"(?i)\s+(?:drops?|falls?|goes|gets|is|are|hits?|reaches|costs?|sells?|comes|below|under|less\s+than|cheaper\s+than|at\s+least|on|at|from|for)\b|\s+\d{1,2}\s*%|[,.!?]"
+    ).unwrap();
+    static ref ARTICLE_PATTERN: Regex = Regex::new(r"(?i)^(?:any|a|an|the|my|some)\s+").unwrap();
+    static ref PRICE_PATTERN: Regex = Regex::new(
+        r"(?i)\b(?:below|under|less\s+than|lower\s+than|cheaper\s+than|down\s+to|at\s+most|to|for)\s+(?:[$€£₹]|usd\s*|rs\.?\s*)?\s*(\d[\d,]*(?:\.\d{1,2})?)\s*(k\b)?"
+    ).unwrap();
+    static ref DISCOUNT_PATTERN: Regex = Regex::new(r"(?i)(\d{1,2}(?:\.\d+)?)\s*%").unwrap();
+}
+
+/// Recognised platforms and their canonical names
+const PLATFORMS: &[(&str, &str)] = &[
+    ("amazon", "Amazon"),
+    ("best buy", "Best Buy"),
+    ("bestbuy", "Best Buy"),
+    ("walmart", "Walmart"),
+    ("target", "Target"),
+    ("ebay", "eBay"),
+    ("newegg", "Newegg"),
+    ("costco", "Costco"),
+    ("home depot", "Home Depot"),
+    ("flipkart", "Flipkart"),
+    ("myntra", "Myntra"),
+];
+
+/// Minimum rule-based confidence before the LLM fallback is skipped
+const RULES_CONFIDENCE_THRESHOLD: f64 = 0.6;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ParserKind {
+    Rules,
+    Llm,
+}
+
+/// What we understood from the user's text, returned for confirmation
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AlertInterpretation {
+    pub product_name: String,
+    pub target_price: Option<Decimal>,
+    pub min_discount: Option<f64>,
+    pub platforms: Vec<String>,
+    pub alert_type: AlertType,
+    pub parser: ParserKind,
+    pub confidence: f64,
+    pub summary: String,
+}
+
+impl AlertInterpretation {
+    fn new(
+        product_name: String,
+        target_price: Option<Decimal>,
+        min_discount: Option<f64>,
+        platforms: Vec<String>,
+        parser: ParserKind,
+        confidence: f64,
+    ) -> Self {
+        let alert_type = if target_price.is_some() {
+            AlertType::TargetPrice
+        } else if min_discount.is_some() {
+            AlertType::DiscountThreshold
+        } else {
+            AlertType::AnyDeal
+        };
+
+        let mut summary = format!("Alert when \"{}\"", product_name);
+        match (target_price, min_discount) {
+            (Some(price), _) => summary.push_str(&format!(" is priced at or below {:.2}", price)),
+            (None, Some(discount)) => summary.push_str(&format!(" is at least {}% off", discount)),
+            (None, None) => summary.push_str(" has a new deal"),
+        }
+        if platforms.is_empty() {
+            summary.push_str(" on any platform");
+        } else {
+            summary.push_str(&format!(" on {}", platforms.join(" or ")));
+        }
+
+        Self {
+            product_name,
+            target_price,
+            min_discount,
+            platforms,
+            alert_type,
+            parser,
+            confidence,
+            summary,
+        }
+    }
+
+    pub fn to_alert(&self, user_id: &str) -> DealAlert {
+        DealAlert {
+            id: Uuid::new_v4(),
+            user_id: user_id.to_string(),
+            product_name: self.product_name.clone(),
+            target_price: self.target_price,
+            min_discount: self.min_discount,
+            platforms: self.platforms.clone(),
+            alert_type: self.alert_type.clone(),
+            created_at: Utc::now(),
+            last_triggered: None,
+        }
+    }
+}
+
+pub struct NaturalAlertParser {
+    llm: Option<LlmFallback>,
+}
+
+impl NaturalAlertParser {
+    pub fn new(llm: Option<LlmFallback>) -> Self {
+        Self { llm }
+    }
+
+    /// Parser with the LLM fallback configured from the environment, if any
+    pub fn from_env() -> Self {
+        Self::new(LlmFallback::from_env())
+    }
+
+    pub async fn parse(&self, text: &str) -> Option<AlertInterpretation> {
+        let rules = parse_rules(text);
+        if rules.as_ref().is_some_and(|r| r.confidence >= RULES_CONFIDENCE_THRESHOLD) {
+            return rules;
+        }
+
+        if let Some(llm) = &self.llm {
+            match llm.parse(text).await {
+                Ok(interpretation) => return Some(interpretation),
+                Err(e) => tracing::warn!(error = %e, "LLM alert parsing failed"),
+            }
+        }
+
+        rules
+    }
+}
+
+/// Rule-based grammar: `[lead] <product> [verb] [price clause] [discount] [on <platforms>]`
+pub fn parse_rules(text: &str) -> Option<AlertInterpretation> {
+    let text = text.trim();
+    let lead_end = LEAD_PATTERN.find(text).map_or(0, |m| m.end());
+    let rest = &text[lead_end..];
+
+    let product_end = PRODUCT_END_PATTERN.find(rest).map_or(rest.len(), |m| m.start());
+    let product_name = ARTICLE_PATTERN.replace(rest[..product_end].trim(), "").trim().to_string();
+    if product_name.is_empty() {
+        return None;
+    }
+
+    let constraints = &rest[product_end..];
+    let target_price = PRICE_PATTERN.captures(constraints).and_then(|cap| {
+        let value: Decimal = cap.get(1)?.as_str().replace(',', "").parse().ok()?;
+        Some(if cap.get(2).is_some() { value * Decimal::ONE_THOUSAND } else { value })
+    });
+    let min_discount = DISCOUNT_PATTERN
+        .captures(constraints)
+        .and_then(|cap| cap.get(1)?.as_str().parse().ok());
+
+    let lowered = constraints.to_lowercase();
+    let mut platforms: Vec<String> = Vec::new();
+    for (alias, canonical) in PLATFORMS {
+        let mentioned = Regex::new(&format!(r"\b{}\b", regex::escape(alias)))
+            .map(|re| re.is_match(&lowered))
+            .unwrap_or(false);
+        if mentioned && !platforms.iter().any(|p| p == canonical) {
+            platforms.push(canonical.to_string());
+        }
+    }
+
+    let mut confidence: f64 = 0.4;
+    if lead_end > 0 {
+        confidence += 0.15;
+    }
+    if target_price.is_some() || min_discount.is_some() {
+        confidence += 0.3;
+    }
+    if !platforms.is_empty() {
+        confidence += 0.15;
+    }
+
+    Some(AlertInterpretation::new(
+        product_name,
+        target_price,
+        min_discount,
+        platforms,
+        ParserKind::Rules,
+        confidence.min(1.0),
+    ))
+}
+
+/// OpenAI-compatible chat completion endpoint used when the grammar gives up
+pub struct LlmFallback {
+    client: reqwest::Client,
+    api_url: String,
+    api_key: Option<String>,
+    model: String,
+}
+
+#[derive(Deserialize)]
+struct LlmAlertFields {
+    product_name: String,
+    target_price: Option<Decimal>,
+    min_discount: Option<f64>,
+    #[serde(default)]
+    platforms: Vec<String>,
+}
+
+impl LlmFallback {
+    /// Configured by `ALERT_LLM_API_URL`, `ALERT_LLM_API_KEY` and `ALERT_LLM_MODEL`
+    pub fn from_env() -> Option<Self> {
+        let api_url = std::env::var("ALERT_LLM_API_URL").ok()?;
+
+        Some(Self {
+            client: reqwest::Client::new(),
+            api_url,
+            api_key: std::env::var("ALERT_LLM_API_KEY").ok(),
+            model: std::env::var("ALERT_LLM_MODEL").unwrap_or_else(|_| "gpt-4o-mini".to_string()),
+        })
+    }
+
+    pub async fn parse(&self, text: &str) -> Result<AlertInterpretation, Box<dyn std::error::Error + Send + Sync>> {
+        let body = json!({
+            "model": self.model,
+            "response_format": {"type": "json_object"},
+            "messages": [
+                {
+                    "role": "system",
+                    "content": "Extract a shopping price alert from the user's message. Reply with JSON only: \
+                        {\"product_name\": string, \"target_price\": number|null, \"min_discount\": number|null, \
+                        \"platforms\": [string]}"
+                },
+                {"role": "user", "content": text}
+            ]
+        });
+
+        let mut request = self.client.post(&self.api_url).json(&body);
+        if let Some(key) = &self.api_key {
+            request = request.bearer_auth(key);
+        }
+
+        let response: serde_json::Value = request.send().await?.error_for_status()?.json().await?;
+        let content = response["choices"][0]["message"]["content"]
+            .as_str()
+            .ok_or("LLM response missing message content")?;
+        let fields: LlmAlertFields = serde_json::from_str(content)?;
+
+        if fields.product_name.trim().is_empty() {
+            return Err("LLM could not identify a product".into());
+        }
+
+        Ok(AlertInterpretation::new(
+            fields.product_name.trim().to_string(),
+            fields.target_price,
+            fields.min_discount,
+            fields.platforms,
+            ParserKind::Llm,
+            0.7,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_parse_price_and_platforms() {
+        let parsed = parse_rules("tell me when any 65-inch OLED drops below $900 on Amazon or Best Buy").unwrap();
+
+        assert_eq!(parsed.product_name, "65-inch OLED");
+        assert_eq!(parsed.target_price, Some(dec!(900)));
+        assert_eq!(parsed.platforms, vec!["Amazon", "Best Buy"]);
+        assert_eq!(parsed.alert_type, AlertType::TargetPrice);
+        assert!(parsed.confidence >= RULES_CONFIDENCE_THRESHOLD);
+    }
+
+    #[test]
+    fn test_parse_discount_alert() {
+        let parsed = parse_rules("notify me if the Sony WH-1000XM5 is at least 30% off").unwrap();
+
+        assert_eq!(parsed.product_name, "Sony WH-1000XM5");
+        assert_eq!(parsed.min_discount, Some(30.0));
+        assert_eq!(parsed.alert_type, AlertType::DiscountThreshold);
+        assert!(parsed.platforms.is_empty());
+    }
+
+    #[test]
+    fn test_parse_thousands_shorthand() {
+        let parsed = parse_rules("alert me when a gaming laptop goes under 1.2k at walmart").unwrap();
+
+        assert_eq!(parsed.product_name, "gaming laptop");
+        assert_eq!(parsed.target_price, Some(dec!(1200)));
+        assert_eq!(parsed.platforms, vec!["Walmart"]);
+    }
+}
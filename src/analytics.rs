@@ -0,0 +1,472 @@
+//! Coupon performance analytics
+//!
+//! The internal BI dashboard reads daily rollups instead of the raw stores. Every
+//! [`ROLLUP_INTERVAL`] one instance recomputes each merchant's rows for the last
+//! [`ROLLUP_DAYS`] days (everything on record on the first run) from the coupon
+//! history, the scrape yield, the checkout outcomes and the savings ledger. Earlier
+//! rows keep what they were last given, so the rollups outlive the raw data's
+//! retention. `GET /analytics/coupons` sums them over a date range.
+//!
+//! With `REDIS_URL` set the rows are shared, and other instances re-read them every
+//! [`REFRESH_INTERVAL`]; otherwise they are persisted to `COUPON_ANALYTICS_PATH`
+//! (default `data/coupon_analytics.json`).
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::clock::{self, Clock};
+use crate::coupon_engine::yield_stats::{YieldInterval, YieldStats};
+use crate::coupon_success::CouponSuccessPredictor;
+use crate::models::domain::{Money, MerchantDomain};
+use crate::savings::SavingsLedger;
+use crate::storage::coupon_history::CouponHistory;
+use crate::storage::persisted::{PersistedStore, StoreError};
+
+pub const ROLLUP_INTERVAL: Duration = Duration::from_secs(3600);
+/// Days each rollup recomputes, today included
+pub const ROLLUP_DAYS: i64 = 7;
+/// How often shared rows are re-read
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(300);
+/// Longest range one query may cover
+pub const MAX_RANGE_DAYS: i64 = 366;
+const REDIS_KEY: &str = "coupon_analytics";
+const REDIS_ROLLED_UP_AT_KEY: &str = "coupon_analytics:rolled_up_at";
+const STORE_NAME: &str = "coupon analytics";
+
+/// One merchant's coupon activity on one day (UTC)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DailyRollup {
+    pub merchant: MerchantDomain,
+    pub day: NaiveDate,
+    #[serde(flatten)]
+    pub counts: RollupCounts,
+}
+
+/// Coupon activity counted over a day, or summed over several
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RollupCounts {
+    /// Codes first recorded
+    pub codes_discovered: u32,
+    /// Coupons the scraper extracted, and those of them that passed validation and
+    /// were published
+    pub coupons_extracted: u32,
+    pub coupons_published: u32,
+    /// Codes that expired, and their lifetimes since discovery summed
+    pub codes_expired: u32,
+    pub lifetime_hours: f64,
+    /// Checkout attempts reported, and how many of them worked
+    pub attempts: u32,
+    pub successes: u32,
+    /// Savings shoppers reported, per currency
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub savings: Vec<Money>,
+}
+
+impl RollupCounts {
+    fn add(&mut self, other: &RollupCounts) {
+        self.codes_discovered += other.codes_discovered;
+        self.coupons_extracted += other.coupons_extracted;
+        self.coupons_published += other.coupons_published;
+        self.codes_expired += other.codes_expired;
+        self.lifetime_hours += other.lifetime_hours;
+        self.attempts += other.attempts;
+        self.successes += other.successes;
+        for amount in &other.savings {
+            add_money(&mut self.savings, *amount);
+        }
+    }
+}
+
+/// One day of a report
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct AnalyticsPoint {
+    pub day: NaiveDate,
+    pub codes_discovered: u32,
+    pub attempts: u32,
+    pub success_rate: Option<f64>,
+}
+
+/// Coupon performance over a date range, for one merchant or all of them
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct AnalyticsReport {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub merchant: Option<MerchantDomain>,
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub codes_discovered: u32,
+    /// Share of extracted coupons that were published
+    pub publish_rate: Option<f64>,
+    /// Mean time from discovery to expiry of the codes that expired in the range
+    pub average_lifetime_hours: Option<f64>,
+    /// Share of checkout attempts that worked
+    pub success_rate: Option<f64>,
+    /// Savings shoppers reported through the extension, per currency
+    pub estimated_savings: Vec<Money>,
+    /// Every day of the range, oldest first
+    pub trend: Vec<AnalyticsPoint>,
+    /// When the rows were last recomputed
+    pub rolled_up_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Rollups {
+    rolled_up_at: Option<DateTime<Utc>>,
+    rows: Vec<DailyRollup>,
+}
+
+type Rows = BTreeMap<(MerchantDomain, NaiveDate), DailyRollup>;
+
+pub struct CouponAnalytics {
+    rows: RwLock<Rows>,
+    rolled_up_at: RwLock<Option<DateTime<Utc>>>,
+    /// Every row in the file; one field of a Redis hash each, beside the time of
+    /// the last rollup
+    store: PersistedStore<Rollups>,
+    clock: Arc<dyn Clock>,
+}
+
+fn ratio(part: u32, whole: u32) -> Option<f64> {
+    (whole > 0).then(|| (part as f64 / whole as f64 * 1000.0).round() / 1000.0)
+}
+
+/// The counts of `merchant`'s row for the day of `at`, when `at` is within `window`
+fn counts_on<'a>(
+    rows: &'a mut Rows,
+    window: (DateTime<Utc>, DateTime<Utc>),
+    merchant: &MerchantDomain,
+    at: DateTime<Utc>,
+) -> Option<&'a mut RollupCounts> {
+    if at < window.0 || at > window.1 {
+        return None;
+    }
+    let day = at.date_naive();
+    let row = rows.entry((merchant.clone(), day)).or_insert_with(|| DailyRollup {
+        merchant: merchant.clone(),
+        day,
+        counts: RollupCounts::default(),
+    });
+    Some(&mut row.counts)
+}
+
+/// Add `amount` to the sum of its currency in `sums`
+fn add_money(sums: &mut Vec<Money>, amount: Money) {
+    match sums.iter_mut().find(|sum| sum.currency == amount.currency) {
+        Some(sum) => sum.amount += amount.amount,
+        None => sums.push(amount),
+    }
+}
+
+impl CouponAnalytics {
+    /// Rows persisted to `path`, or kept in memory only
+    pub fn new(path: Option<PathBuf>) -> Self {
+        Self::with_store(PersistedStore::new(STORE_NAME, REDIS_KEY, path))
+    }
+
+    /// Rows shared through Redis
+    pub fn shared(redis_url: &str) -> Result<Self, StoreError> {
+        Ok(Self::with_store(PersistedStore::shared(STORE_NAME, REDIS_KEY, redis_url)?))
+    }
+
+    fn with_store(store: PersistedStore<Rollups>) -> Self {
+        Self {
+            rows: RwLock::new(BTreeMap::new()),
+            rolled_up_at: RwLock::new(None),
+            store,
+            clock: clock::system(),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Share rows through `REDIS_URL` when set, otherwise load them from
+    /// `COUPON_ANALYTICS_PATH` (default `data/coupon_analytics.json`)
+    pub async fn from_env() -> Self {
+        let analytics = Self::with_store(PersistedStore::from_env(STORE_NAME, REDIS_KEY, "COUPON_ANALYTICS_PATH", "data/coupon_analytics.json"));
+        if let Err(e) = analytics.reload().await {
+            tracing::warn!(error = %e, "Starting with no coupon analytics");
+        }
+        analytics
+    }
+
+    /// Replace the local rows with the stored ones
+    pub async fn reload(&self) -> Result<(), StoreError> {
+        let stored = match self.store.redis() {
+            Some(client) => {
+                let rows = self.store.hash_values(REDIS_KEY)?.unwrap_or_default();
+                let mut con = client.get_connection()?;
+                let rolled_up_at: Option<String> = redis::cmd("GET").arg(REDIS_ROLLED_UP_AT_KEY).query(&mut con)?;
+                Rollups {
+                    rolled_up_at: rolled_up_at.and_then(|at| at.parse().ok()),
+                    rows,
+                }
+            }
+            None => match self.store.load().await? {
+                Some(stored) => stored,
+                None => return Ok(()),
+            },
+        };
+        *self.rows.write().unwrap() = stored.rows.into_iter().map(|row| ((row.merchant.clone(), row.day), row)).collect();
+        *self.rolled_up_at.write().unwrap() = stored.rolled_up_at;
+        Ok(())
+    }
+
+    /// Re-read shared rows every [`REFRESH_INTERVAL`], picking up the rollups other
+    /// instances ran
+    pub async fn start_background_tasks(self: Arc<Self>) {
+        self.store.refresh_every(self.clock.as_ref(), REFRESH_INTERVAL, || self.reload()).await
+    }
+
+    /// Recompute the rows of the last [`ROLLUP_DAYS`] days, or of every day on record
+    /// when there are no rows yet, and store them
+    pub async fn roll_up(
+        &self,
+        history: &CouponHistory,
+        yields: &YieldStats,
+        predictor: &CouponSuccessPredictor,
+        savings: &SavingsLedger,
+    ) -> Result<(), String> {
+        let now = self.clock.now();
+        let since = match self.rows.read().unwrap().is_empty() {
+            true => DateTime::<Utc>::MIN_UTC,
+            false => (now - TimeDelta::days(ROLLUP_DAYS - 1)).date_naive().and_hms_opt(0, 0, 0).unwrap_or_default().and_utc(),
+        };
+        let first_day = since.date_naive();
+
+        let mut rows: Rows = BTreeMap::new();
+        for lifetime in history.lifetimes() {
+            if let Some(row) = counts_on(&mut rows, (since, now), &lifetime.merchant, lifetime.known_since) {
+                row.codes_discovered += 1;
+            }
+            if let Some(expired) = lifetime.valid_until.filter(|until| *until > lifetime.known_since) {
+                if let Some(row) = counts_on(&mut rows, (since, now), &lifetime.merchant, expired) {
+                    row.codes_expired += 1;
+                    row.lifetime_hours += (expired - lifetime.known_since).num_minutes() as f64 / 60.0;
+                }
+            }
+        }
+        for merchant in yields.merchants().await {
+            for point in yields.series(&merchant, since, YieldInterval::Day).await {
+                if let Some(row) = counts_on(&mut rows, (since, now), &merchant, point.at) {
+                    row.coupons_extracted += point.counts.coupons_extracted;
+                    row.coupons_published += point.counts.coupons_valid;
+                }
+            }
+        }
+        for outcome in predictor.training_data().await {
+            if let Some(row) = counts_on(&mut rows, (since, now), &outcome.merchant_domain, outcome.recorded_at) {
+                row.attempts += 1;
+                row.successes += u32::from(outcome.worked);
+            }
+        }
+        for entry in savings.entries_since(since).await {
+            if let Some(row) = counts_on(&mut rows, (since, now), &entry.merchant, entry.saved_at) {
+                add_money(&mut row.savings, entry.amount);
+            }
+        }
+
+        // Days before the window keep their rows; the window's are replaced
+        let stale: Vec<(MerchantDomain, NaiveDate)> = self
+            .rows
+            .read()
+            .unwrap()
+            .keys()
+            .filter(|key| key.1 >= first_day && !rows.contains_key(*key))
+            .cloned()
+            .collect();
+        self.write(&rows, &stale, now)
+            .await
+            .map_err(|e| format!("failed to store the coupon analytics: {}", e))?;
+
+        let mut stored = self.rows.write().unwrap();
+        for key in &stale {
+            stored.remove(key);
+        }
+        stored.extend(rows);
+        *self.rolled_up_at.write().unwrap() = Some(now);
+        Ok(())
+    }
+
+    async fn write(
+        &self,
+        rows: &Rows,
+        stale: &[(MerchantDomain, NaiveDate)],
+        now: DateTime<Utc>,
+    ) -> Result<(), StoreError> {
+        let Some(client) = self.store.redis() else {
+            let mut all = self.rows.read().unwrap().clone();
+            for key in stale {
+                all.remove(key);
+            }
+            all.extend(rows.iter().map(|(key, row)| (key.clone(), row.clone())));
+            let rollups = Rollups {
+                rolled_up_at: Some(now),
+                rows: all.into_values().collect(),
+            };
+            return self.store.save(&rollups).await;
+        };
+
+        let field = |(merchant, day): &(MerchantDomain, NaiveDate)| format!("{}|{}", day, merchant);
+        let mut con = client.get_connection()?;
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        if !stale.is_empty() {
+            pipe.cmd("HDEL").arg(REDIS_KEY).arg(stale.iter().map(field).collect::<Vec<_>>()).ignore();
+        }
+        for (key, row) in rows {
+            pipe.cmd("HSET").arg(REDIS_KEY).arg(field(key)).arg(serde_json::to_string(row)?).ignore();
+        }
+        pipe.cmd("SET").arg(REDIS_ROLLED_UP_AT_KEY).arg(now.to_rfc3339()).ignore();
+        pipe.query::<()>(&mut con)?;
+        Ok(())
+    }
+
+    /// Sum the rows from `from` to `to`, both included, of `merchant` or of every merchant
+    pub fn report(&self, merchant: Option<&MerchantDomain>, from: NaiveDate, to: NaiveDate) -> Result<AnalyticsReport, String> {
+        if from > to {
+            return Err("from must not be after to".to_string());
+        }
+        if (to - from).num_days() >= MAX_RANGE_DAYS {
+            return Err(format!("at most {} days per query", MAX_RANGE_DAYS));
+        }
+
+        let rows = self.rows.read().unwrap();
+        let mut days: BTreeMap<NaiveDate, RollupCounts> = from
+            .iter_days()
+            .take_while(|day| *day <= to)
+            .map(|day| (day, RollupCounts::default()))
+            .collect();
+        let mut total = RollupCounts::default();
+        let selected = rows
+            .values()
+            .filter(|row| row.day >= from && row.day <= to)
+            .filter(|row| merchant.is_none_or(|merchant| row.merchant == *merchant));
+        for row in selected {
+            days.entry(row.day).or_default().add(&row.counts);
+            total.add(&row.counts);
+        }
+        total.savings.sort_by(|a, b| a.currency.as_str().cmp(b.currency.as_str()));
+
+        Ok(AnalyticsReport {
+            merchant: merchant.cloned(),
+            from,
+            to,
+            codes_discovered: total.codes_discovered,
+            publish_rate: ratio(total.coupons_published, total.coupons_extracted),
+            average_lifetime_hours: (total.codes_expired > 0)
+                .then(|| (total.lifetime_hours / total.codes_expired as f64 * 10.0).round() / 10.0),
+            success_rate: ratio(total.successes, total.attempts),
+            estimated_savings: total
+                .savings
+                .into_iter()
+                .map(|amount| Money::new(amount.amount.round_dp(2), amount.currency))
+                .filter(|amount| amount.amount != Decimal::ZERO)
+                .collect(),
+            trend: days
+                .into_iter()
+                .map(|(day, row)| AnalyticsPoint {
+                    day,
+                    codes_discovered: row.codes_discovered,
+                    attempts: row.attempts,
+                    success_rate: ratio(row.successes, row.attempts),
+                })
+                .collect(),
+            rolled_up_at: *self.rolled_up_at.read().unwrap(),
+        })
+    }
+
+    /// Today on the analytics clock, for default ranges
+    pub fn today(&self) -> NaiveDate {
+        self.clock.now().date_naive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    use crate::clock::MockClock;
+    use crate::coupon_engine::yield_stats::YieldCounts;
+    use crate::coupon_success::CouponSuccessModel;
+    use crate::models::coupon_listing::{CouponListing, CouponSource};
+    use crate::models::domain::CouponCode;
+    use crate::savings::SavingsReport;
+    use crate::storage::coupon_store::CouponStore;
+    use chrono::TimeZone;
+    use rust_decimal_macros::dec;
+
+    fn listing(code: &str, scraped_at: DateTime<Utc>, valid_until: Option<DateTime<Utc>>) -> CouponListing {
+        CouponListing {
+            code: CouponCode::parse(code).unwrap(),
+            title: code.to_string(),
+            description: None,
+            locale: None,
+            merchant_domain: MerchantDomain::parse("shop.com").unwrap(),
+            discount_type: "percentage".to_string(),
+            discount_value: Some(20.0),
+            minimum_order: None,
+            source: CouponSource::WebScraping,
+            license: None,
+            extraction_confidence: 0.9,
+            scraped_at,
+            valid_until,
+            predicted_success: None,
+            tags: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rollups_sum_into_reports_by_day() {
+        let clock = Arc::new(MockClock::at(Utc.with_ymd_and_hms(2024, 6, 10, 12, 0, 0).unwrap()));
+        let at = |day, hour| Utc.with_ymd_and_hms(2024, 6, day, hour, 0, 0).unwrap();
+        let merchant = MerchantDomain::parse("shop.com").unwrap();
+
+        let store = Arc::new(CouponStore::with_coupons(vec![]));
+        let history = CouponHistory::new(store, None).with_clock(clock.clone());
+        history
+            .sync(vec![listing("SAVE20", at(8, 10), Some(at(9, 10))), listing("FREESHIP", at(9, 10), None)], true)
+            .await;
+        let yields = YieldStats::new(None).with_clock(clock.clone());
+        let counts = YieldCounts { urls_scraped: 5, coupons_extracted: 10, coupons_valid: 4, ..Default::default() };
+        yields.record(HashMap::from([(merchant.clone(), counts)])).await;
+        let savings = SavingsLedger::new(None).with_clock(clock.clone());
+        let report = SavingsReport {
+            merchant: merchant.clone(),
+            amount: Money::usd(dec!(5.50)),
+            code: None,
+            deal_id: None,
+            saved_at: Some(at(10, 9)),
+        };
+        savings.record("u1", report).await.unwrap();
+        let predictor = CouponSuccessPredictor::new(CouponSuccessModel::default());
+
+        let analytics = CouponAnalytics::new(None).with_clock(clock.clone());
+        analytics.roll_up(&history, &yields, &predictor, &savings).await.unwrap();
+
+        let report = analytics.report(Some(&merchant), at(8, 0).date_naive(), at(10, 0).date_naive()).unwrap();
+        assert_eq!(report.codes_discovered, 2);
+        assert_eq!(report.publish_rate, Some(0.4));
+        assert_eq!(report.average_lifetime_hours, Some(24.0));
+        assert_eq!(report.success_rate, None);
+        assert_eq!(report.estimated_savings, vec![Money::usd(dec!(5.50))]);
+        let discovered: Vec<u32> = report.trend.iter().map(|point| point.codes_discovered).collect();
+        assert_eq!(discovered, vec![1, 1, 0]);
+        assert_eq!(report.rolled_up_at, Some(clock.now()));
+
+        // Days outside the range, and other merchants, are left out
+        let other = MerchantDomain::parse("other.com").unwrap();
+        assert_eq!(analytics.report(Some(&other), at(8, 0).date_naive(), at(10, 0).date_naive()).unwrap().codes_discovered, 0);
+        assert_eq!(analytics.report(None, at(10, 0).date_naive(), at(10, 0).date_naive()).unwrap().codes_discovered, 0);
+        assert!(analytics.report(None, at(10, 0).date_naive(), at(8, 0).date_naive()).is_err());
+    }
+}
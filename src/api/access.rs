@@ -0,0 +1,182 @@
+//! Permission checks for the admin endpoints and role assignment
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+
+use axum::{
+    async_trait,
+    extract::{Extension, FromRequestParts, MatchedPath, Path},
+    http::{request::Parts, Method, StatusCode},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::reply::{ApiError, Reply};
+use crate::rbac::{AccessControl, AccessDenied, Permission, Role, Subject};
+use crate::tenant::Caller;
+
+/// The permission an admin route requires, by its unversioned path. Routes missing
+/// here are refused to everyone, so a new admin route stays closed until it is listed.
+fn required_permission(method: &Method, route: &str) -> Option<Permission> {
+    use Permission::*;
+    let read = method == Method::GET || method == Method::HEAD;
+    let permission = match route {
+        "/admin/partner-coupons/pending" | "/admin/partner-coupons/:id/review" => Moderate,
+        "/admin/merchants/:domain/yield" | "/admin/redirects" | "/admin/sla" | "/analytics/coupons" if read => ViewReports,
+        "/admin/jobs/dead-letter" | "/admin/reprocess/:id" | "/admin/seed/:id" | "/admin/experiments" | "/admin/experiments/:id/readout" if read => ViewReports,
+        "/admin/jobs/dead-letter/:id/retry" | "/admin/reprocess" | "/admin/seed" | "/admin/merchants/:domain/liveness" | "/admin/canaries/:domain/check" | "/admin/canaries/:domain/accept" => ManageSources,
+        // Reading these is reporting; changing them operates the scrapers
+        "/admin/parsers/shadow" | "/admin/domain-profiles" | "/admin/domain-profiles/:domain" | "/admin/perf/stages" | "/admin/canaries" => match read {
+            true => ViewReports,
+            false => ManageSources,
+        },
+        "/admin/backfills" | "/admin/backfills/:id" => match read {
+            true => ViewReports,
+            false => ManageSources,
+        },
+        "/admin/backfills/:id/pause" | "/admin/backfills/:id/resume" => ManageSources,
+        "/admin/licenses" | "/admin/licenses/:source" => match read {
+            true => ViewReports,
+            false => ManageSources,
+        },
+        "/admin/opt-outs" | "/admin/opt-outs/:domain" | "/admin/opt-outs/audit" | "/admin/blocklist" | "/admin/blocklist/:id" => match read {
+            true => ViewReports,
+            false => ManageSources,
+        },
+        "/admin/scraper/domains" | "/admin/scraper/config" if read => ViewReports,
+        "/admin/scraper/batches" | "/admin/scraper/domains/:domain/pause" | "/admin/scraper/domains/:domain/resume" | "/admin/scraper/config" => ManageSources,
+        "/admin/shipping-rules" | "/admin/shipping-rules/:domain" | "/admin/tags" | "/admin/tags/:slug" | "/admin/tags/deals/:id" | "/admin/tags/coupons/:id" => match read {
+            true => ViewReports,
+            false => ManageContent,
+        },
+        // Drafts are visible here, so even listing needs the content permission
+        "/admin/collections" | "/admin/collections/:slug" | "/admin/collections/:slug/preview" => ManageContent,
+        "/admin/experiments/:id" if method == Method::PUT => ManageExperiments,
+        "/admin/savings/export" | "/admin/pii" => ExportUserData,
+        "/admin/roles" | "/admin/roles/:subject" => ManageRoles,
+        "/admin/api-keys" | "/admin/api-keys/:id" => ManageApiKeys,
+        _ => return None,
+    };
+    Some(permission)
+}
+
+/// Proof that the caller's roles allow the admin route
+pub(super) struct AdminAccess;
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for AdminAccess {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Some(access) = parts.extensions.get::<Arc<AccessControl>>() else {
+            return Err(ApiError::new(StatusCode::FORBIDDEN, "access control is not configured"));
+        };
+        let required = parts
+            .extensions
+            .get::<MatchedPath>()
+            .and_then(|route| required_permission(&parts.method, super::unversioned(route.as_str())));
+        let subject = access.subject(parts.extensions.get::<Caller>(), &parts.headers);
+
+        match access.authorize(subject, required).await {
+            Ok(_) => Ok(AdminAccess),
+            Err(AccessDenied::Anonymous) => Err(ApiError::new(
+                StatusCode::UNAUTHORIZED,
+                "admin endpoints need an API key or user with a role",
+            )),
+            Err(AccessDenied::Forbidden { required, .. }) => {
+                Err(ApiError::new(StatusCode::FORBIDDEN, "not permitted").with("required", required))
+            }
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct RoleAssignments {
+    assignments: BTreeMap<Subject, BTreeSet<Role>>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/roles",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Role `assignments`", body = RoleAssignments),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn list_roles(Extension(access): Extension<Arc<AccessControl>>) -> Reply<RoleAssignments> {
+    Reply(RoleAssignments {
+        assignments: access.assignments().await,
+    })
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(super) struct RolesRequest {
+    roles: BTreeSet<Role>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct SubjectRoles {
+    subject: Subject,
+    roles: BTreeSet<Role>,
+}
+
+/// Replace the roles of `key:<sha256>` or `user:<id>`
+#[utoipa::path(
+    put,
+    path = "/admin/roles/{subject}",
+    tag = "admin",
+    params(("subject" = String, Path, description = "`key:<sha256>` or `user:<id>`")),
+    request_body = RolesRequest,
+    responses(
+        (status = 200, description = "The subject's new `roles`", body = SubjectRoles),
+        (status = 400, description = "Invalid subject", body = ErrorBody),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+        (status = 409, description = "Would remove the last admin", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn put_roles(
+    Extension(access): Extension<Arc<AccessControl>>,
+    Path(subject): Path<String>,
+    Json(request): Json<RolesRequest>,
+) -> Result<Reply<SubjectRoles>, ApiError> {
+    let subject = Subject::parse(&subject).map_err(ApiError::bad_request)?;
+    match access.assign(subject.clone(), request.roles).await {
+        Ok(roles) => Ok(Reply(SubjectRoles { subject, roles })),
+        Err(e) => Err(ApiError::new(StatusCode::CONFLICT, e)),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/admin/roles/{subject}",
+    tag = "admin",
+    params(("subject" = String, Path, description = "`key:<sha256>` or `user:<id>`")),
+    responses(
+        (status = 204, description = "Roles removed"),
+        (status = 400, description = "Invalid subject", body = ErrorBody),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+        (status = 404, description = "No roles assigned", body = ErrorBody),
+        (status = 409, description = "Would remove the last admin", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn delete_roles(
+    Extension(access): Extension<Arc<AccessControl>>,
+    Path(subject): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    let subject = Subject::parse(&subject).map_err(ApiError::bad_request)?;
+    if access.roles(&subject).await.is_empty() {
+        return Err(ApiError::not_found("no roles assigned"));
+    }
+    match access.assign(subject, BTreeSet::new()).await {
+        Ok(_) => Ok(StatusCode::NO_CONTENT),
+        Err(e) => Err(ApiError::new(StatusCode::CONFLICT, e)),
+    }
+}
@@ -0,0 +1,41 @@
+//! API key usage endpoint
+
+use axum::{extract::Extension, http::StatusCode};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use super::reply::{ApiError, Reply};
+use crate::tenant::usage::{KeyUsage, MeteredKey};
+use crate::tenant::TenantId;
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct AccountUsage {
+    tenant: String,
+    #[serde(flatten)]
+    usage: KeyUsage,
+}
+
+/// The calling key's quota and requests in the current window, and its requests
+/// per day
+#[utoipa::path(
+    get,
+    path = "/account/usage",
+    tag = "status",
+    responses(
+        (status = 200, description = "The key's quota, current window and daily requests", body = AccountUsage),
+        (status = 401, description = "Called without an API key", body = ErrorBody),
+    )
+)]
+pub(super) async fn usage(
+    tenant: TenantId,
+    metered: Option<Extension<MeteredKey>>,
+) -> Result<Reply<AccountUsage>, ApiError> {
+    let Some(Extension(metered)) = metered else {
+        return Err(ApiError::new(StatusCode::UNAUTHORIZED, "an API key is required"));
+    };
+
+    Ok(Reply(AccountUsage {
+        tenant: tenant.0,
+        usage: metered.usage().await,
+    }))
+}
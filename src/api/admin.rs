@@ -0,0 +1,1018 @@
+//! Experiment, parser rollout, domain profile, scrape opt-out, shipping rule, corpus
+//! reprocessing, backfill and seeding administration, and the deal stream SLA
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use axum::{
+    extract::{Extension, Path, Query},
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use super::reply::{ApiError, Reply};
+use crate::backfill::{Backfill, BackfillError, BackfillRequest, Backfills};
+use crate::coupon_engine::profiles::{DomainProfile, DomainProfiles, ProfileSettings};
+use crate::coupon_engine::canary::{CanaryMonitor, CanaryResult};
+use crate::coupon_engine::blocklist::{BlockRule, BlockRuleRequest, ExtractionBlocklist, RuleUsage};
+use crate::coupon_engine::concurrency::ConcurrencySnapshot;
+use crate::coupon_engine::memory::MemorySnapshot;
+use crate::coupon_engine::opt_out::{AuditEntry, OptOut, OptOutRegistry, OptOutRequest};
+use crate::coupon_engine::redirects::FlaggedChain;
+use crate::coupon_engine::shadow::ShadowReport;
+use crate::coupon_engine::stages::StageReport;
+use crate::coupon_engine::CouponEngine;
+use crate::experiments::{Experiment, ExperimentReadout, ExperimentService};
+use crate::models::domain::MerchantDomain;
+use crate::privacy::{ScrubCount, Scrubber};
+use crate::reprocess::{ReprocessError, ReprocessRequest, ReprocessRun, Reprocessor};
+use crate::seeding::{SeedError, SeedRequest, SeedRun, Seeder};
+use crate::sla::{SlaMonitor, SlaReport};
+use crate::storage::shipping_rules::{ShippingRule, ShippingRuleStore};
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct Experiments {
+    experiments: Vec<Experiment>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/experiments",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Every ranking `experiments`", body = Experiments),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn list_experiments(Extension(experiments): Extension<Arc<ExperimentService>>) -> Reply<Experiments> {
+    Reply(Experiments {
+        experiments: experiments.list().await,
+    })
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct StoredExperiment {
+    experiment: Experiment,
+}
+
+#[utoipa::path(
+    put,
+    path = "/admin/experiments/{id}",
+    tag = "admin",
+    params(("id" = String, Path, description = "Experiment id")),
+    request_body = Experiment,
+    responses(
+        (status = 200, description = "The stored `experiment`", body = StoredExperiment),
+        (status = 400, description = "Invalid variants", body = ErrorBody),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn upsert_experiment(
+    Extension(experiments): Extension<Arc<ExperimentService>>,
+    Path(experiment_id): Path<String>,
+    Json(mut experiment): Json<Experiment>,
+) -> Result<Reply<StoredExperiment>, ApiError> {
+    experiment.id = experiment_id;
+
+    match experiments.upsert(experiment.clone()).await {
+        Ok(()) => Ok(Reply(StoredExperiment { experiment })),
+        Err(e) => Err(ApiError::bad_request(e)),
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct Readout {
+    readout: ExperimentReadout,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/experiments/{id}/readout",
+    tag = "admin",
+    params(("id" = String, Path, description = "Experiment id")),
+    responses(
+        (status = 200, description = "Exposures and conversions per variant", body = Readout),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+        (status = 404, description = "No such experiment"),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn experiment_readout(
+    Extension(experiments): Extension<Arc<ExperimentService>>,
+    Path(experiment_id): Path<String>,
+) -> Result<Reply<Readout>, StatusCode> {
+    let readout = experiments.readout(&experiment_id).await.ok_or(StatusCode::NOT_FOUND)?;
+
+    Ok(Reply(Readout { readout }))
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct ShadowParser {
+    shadow: ShadowReport,
+}
+
+/// Per-merchant yield of the shadow parser against the current one
+#[utoipa::path(
+    get,
+    path = "/admin/parsers/shadow",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Per-merchant yield of the shadow parser", body = ShadowParser),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+        (status = 404, description = "No shadow parser is configured", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn shadow_parser_report(
+    Extension(engine): Extension<Arc<CouponEngine>>,
+) -> Result<Reply<ShadowParser>, ApiError> {
+    match engine.shadow_report().await {
+        Some(report) => Ok(Reply(ShadowParser { shadow: report })),
+        None => Err(ApiError::not_found("no shadow parser configured (set PARSER_SHADOW_VERSION)")
+            .with("current_version", engine.parser_version())),
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct StagePerformance {
+    stages: StageReport,
+    concurrency: ConcurrencySnapshot,
+    memory: MemorySnapshot,
+}
+
+/// Where scrape time goes, per pipeline stage, across the batches this instance ran,
+/// the fetch concurrency it has settled on and the memory its batches hold
+#[utoipa::path(
+    get,
+    path = "/admin/perf/stages",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Time per pipeline `stages`, fetch `concurrency` and batch `memory`", body = StagePerformance),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn stage_report(Extension(engine): Extension<Arc<CouponEngine>>) -> Reply<StagePerformance> {
+    Reply(StagePerformance {
+        stages: engine.stages().report(),
+        concurrency: engine.concurrency(),
+        memory: engine.memory(),
+    })
+}
+
+/// Start the stage histograms afresh, e.g. before measuring an optimization
+#[utoipa::path(
+    delete,
+    path = "/admin/perf/stages",
+    tag = "admin",
+    responses(
+        (status = 204, description = "Reset"),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn reset_stage_report(Extension(engine): Extension<Arc<CouponEngine>>) -> StatusCode {
+    engine.stages().reset();
+    StatusCode::NO_CONTENT
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct FlaggedRedirects {
+    flagged: Vec<FlaggedChain>,
+}
+
+/// Suspicious redirect chains the scraper followed recently
+#[utoipa::path(
+    get,
+    path = "/admin/redirects",
+    tag = "admin",
+    responses(
+        (status = 200, description = "`flagged` redirect chains", body = FlaggedRedirects),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn redirect_report(Extension(engine): Extension<Arc<CouponEngine>>) -> Reply<FlaggedRedirects> {
+    Reply(FlaggedRedirects {
+        flagged: engine.flagged_redirects().await,
+    })
+}
+
+#[utoipa::path(
+    delete,
+    path = "/admin/parsers/shadow",
+    tag = "admin",
+    responses(
+        (status = 204, description = "Reset"),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+        (status = 404, description = "No shadow parser is configured"),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn reset_shadow_parser(Extension(engine): Extension<Arc<CouponEngine>>) -> StatusCode {
+    if engine.reset_shadow().await {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct ProfileList {
+    profiles: Vec<DomainProfile>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/domain-profiles",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Every domain's scraping `profiles`", body = ProfileList),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn list_domain_profiles(Extension(profiles): Extension<Arc<DomainProfiles>>) -> Reply<ProfileList> {
+    Reply(ProfileList {
+        profiles: profiles.list(),
+    })
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct StoredDomainProfile {
+    profile: DomainProfile,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/domain-profiles/{domain}",
+    tag = "admin",
+    params(("domain" = String, Path, description = "Merchant domain, e.g. `amazon.com`")),
+    responses(
+        (status = 200, description = "The domain's `profile`", body = StoredDomainProfile),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+        (status = 404, description = "No profile for the domain"),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn get_domain_profile(
+    Extension(profiles): Extension<Arc<DomainProfiles>>,
+    Path(domain): Path<MerchantDomain>,
+) -> Result<Reply<StoredDomainProfile>, StatusCode> {
+    let profile = profiles.get(&domain).ok_or(StatusCode::NOT_FOUND)?;
+
+    Ok(Reply(StoredDomainProfile { profile }))
+}
+
+/// Create or replace a profile; selectors are checked before anything is stored
+#[utoipa::path(
+    put,
+    path = "/admin/domain-profiles/{domain}",
+    tag = "admin",
+    params(("domain" = String, Path, description = "Merchant domain, e.g. `amazon.com`")),
+    request_body = ProfileSettings,
+    responses(
+        (status = 200, description = "The stored `profile`", body = StoredDomainProfile),
+        (status = 400, description = "Invalid selectors or settings", body = ErrorBody),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+        (status = 503, description = "The profile could not be stored", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn put_domain_profile(
+    Extension(profiles): Extension<Arc<DomainProfiles>>,
+    Path(domain): Path<MerchantDomain>,
+    Json(settings): Json<ProfileSettings>,
+) -> Result<Reply<StoredDomainProfile>, ApiError> {
+    if let Err(e) = settings.validate() {
+        return Err(ApiError::bad_request(e));
+    }
+
+    match profiles.put(domain, settings).await {
+        Ok(profile) => Ok(Reply(StoredDomainProfile { profile })),
+        Err(e) => Err(ApiError::new(StatusCode::SERVICE_UNAVAILABLE, e)),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/admin/domain-profiles/{domain}",
+    tag = "admin",
+    params(("domain" = String, Path, description = "Merchant domain, e.g. `amazon.com`")),
+    responses(
+        (status = 204, description = "Deleted"),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+        (status = 404, description = "No profile for the domain"),
+        (status = 503, description = "The profile could not be deleted", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn delete_domain_profile(
+    Extension(profiles): Extension<Arc<DomainProfiles>>,
+    Path(domain): Path<MerchantDomain>,
+) -> Result<StatusCode, ApiError> {
+    match profiles.delete(&domain).await {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Ok(StatusCode::NOT_FOUND),
+        Err(e) => Err(ApiError::new(StatusCode::SERVICE_UNAVAILABLE, e)),
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct Canaries {
+    canaries: Vec<CanaryResult>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/canaries",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Every merchant's `canaries`", body = Canaries),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn canary_report(Extension(canaries): Extension<Arc<CanaryMonitor>>) -> Reply<Canaries> {
+    Reply(Canaries {
+        canaries: canaries.report().await,
+    })
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct CanaryCheck {
+    canary: CanaryResult,
+}
+
+/// Run a merchant's canaries now, e.g. to confirm a selector fix
+#[utoipa::path(
+    post,
+    path = "/admin/canaries/{domain}/check",
+    tag = "admin",
+    params(("domain" = String, Path, description = "Merchant domain, e.g. `amazon.com`")),
+    responses(
+        (status = 200, description = "The merchant's `canary` check", body = CanaryCheck),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+        (status = 404, description = "The merchant has no canaries"),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn check_canary(
+    Extension(canaries): Extension<Arc<CanaryMonitor>>,
+    Path(domain): Path<MerchantDomain>,
+) -> Result<Reply<CanaryCheck>, StatusCode> {
+    let result = canaries.check(&domain).await.ok_or(StatusCode::NOT_FOUND)?;
+
+    Ok(Reply(CanaryCheck {
+        canary: result,
+    }))
+}
+
+/// Take a merchant's latest canary fingerprints as its baselines and resume its scrapes
+#[utoipa::path(
+    post,
+    path = "/admin/canaries/{domain}/accept",
+    tag = "admin",
+    params(("domain" = String, Path, description = "Merchant domain, e.g. `amazon.com`")),
+    responses(
+        (status = 204, description = "Baselines accepted"),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+        (status = 404, description = "The merchant has no canaries"),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn accept_canary(
+    Extension(canaries): Extension<Arc<CanaryMonitor>>,
+    Path(domain): Path<MerchantDomain>,
+) -> StatusCode {
+    match canaries.accept(&domain).await {
+        true => StatusCode::NO_CONTENT,
+        false => StatusCode::NOT_FOUND,
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct OptOuts {
+    opt_outs: Vec<OptOut>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/opt-outs",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Every merchant that opted out of scraping, as `opt_outs`", body = OptOuts),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn list_opt_outs(Extension(opt_outs): Extension<Arc<OptOutRegistry>>) -> Reply<OptOuts> {
+    Reply(OptOuts {
+        opt_outs: opt_outs.list(),
+    })
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct StoredOptOut {
+    opt_out: OptOut,
+}
+
+/// Stop scraping a merchant and its subdomains, e.g. on a takedown request
+#[utoipa::path(
+    put,
+    path = "/admin/opt-outs/{domain}",
+    tag = "admin",
+    params(("domain" = String, Path, description = "Merchant domain, e.g. `amazon.com`")),
+    request_body = OptOutRequest,
+    responses(
+        (status = 200, description = "The stored `opt_out`", body = StoredOptOut),
+        (status = 400, description = "No reason given", body = ErrorBody),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+        (status = 503, description = "The opt-out could not be stored", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn put_opt_out(
+    Extension(opt_outs): Extension<Arc<OptOutRegistry>>,
+    Path(domain): Path<MerchantDomain>,
+    Json(request): Json<OptOutRequest>,
+) -> Result<Reply<StoredOptOut>, ApiError> {
+    if let Err(e) = request.validate() {
+        return Err(ApiError::bad_request(e));
+    }
+
+    match opt_outs.add(domain, request).await {
+        Ok(opt_out) => Ok(Reply(StoredOptOut { opt_out })),
+        Err(e) => Err(ApiError::new(StatusCode::SERVICE_UNAVAILABLE, e)),
+    }
+}
+
+/// Let a merchant be scraped again
+#[utoipa::path(
+    delete,
+    path = "/admin/opt-outs/{domain}",
+    tag = "admin",
+    params(("domain" = String, Path, description = "Merchant domain, e.g. `amazon.com`")),
+    responses(
+        (status = 204, description = "Deleted"),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+        (status = 404, description = "The merchant had not opted out"),
+        (status = 503, description = "The opt-out could not be deleted", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn delete_opt_out(
+    Extension(opt_outs): Extension<Arc<OptOutRegistry>>,
+    Path(domain): Path<MerchantDomain>,
+) -> Result<StatusCode, ApiError> {
+    match opt_outs.remove(&domain).await {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Ok(StatusCode::NOT_FOUND),
+        Err(e) => Err(ApiError::new(StatusCode::SERVICE_UNAVAILABLE, e)),
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(super) struct OptOutAuditQuery {
+    /// Only this merchant's entries
+    domain: Option<MerchantDomain>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct OptOutAudit {
+    entries: Vec<AuditEntry>,
+}
+
+/// This instance's opt-out changes and refused URLs, newest first
+#[utoipa::path(
+    get,
+    path = "/admin/opt-outs/audit",
+    tag = "admin",
+    params(OptOutAuditQuery),
+    responses(
+        (status = 200, description = "The audit `entries`", body = OptOutAudit),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn opt_out_audit(
+    Extension(opt_outs): Extension<Arc<OptOutRegistry>>,
+    Query(query): Query<OptOutAuditQuery>,
+) -> Reply<OptOutAudit> {
+    Reply(OptOutAudit {
+        entries: opt_outs.audit(query.domain.as_ref()),
+    })
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct BlockRules {
+    rules: Vec<RuleUsage>,
+}
+
+/// Every extraction blocklist rule, with its `hits` and `last_hit_at`
+#[utoipa::path(
+    get,
+    path = "/admin/blocklist",
+    tag = "admin",
+    responses(
+        (status = 200, description = "The `rules`, oldest first", body = BlockRules),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn list_block_rules(Extension(blocklist): Extension<Arc<ExtractionBlocklist>>) -> Reply<BlockRules> {
+    Reply(BlockRules {
+        rules: blocklist.list(),
+    })
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct StoredBlockRule {
+    rule: BlockRule,
+}
+
+/// Drop codes, merchants or pages matching a pattern from extraction
+#[utoipa::path(
+    post,
+    path = "/admin/blocklist",
+    tag = "admin",
+    request_body = BlockRuleRequest,
+    responses(
+        (status = 201, description = "The stored `rule`", body = StoredBlockRule),
+        (status = 400, description = "An empty, invalid or match-everything pattern", body = ErrorBody),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+        (status = 503, description = "The rule could not be stored", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn create_block_rule(
+    Extension(blocklist): Extension<Arc<ExtractionBlocklist>>,
+    Json(request): Json<BlockRuleRequest>,
+) -> Result<(StatusCode, Reply<StoredBlockRule>), ApiError> {
+    if let Err(e) = request.validate() {
+        return Err(ApiError::bad_request(e));
+    }
+
+    match blocklist.add(request).await {
+        Ok(rule) => Ok((StatusCode::CREATED, Reply(StoredBlockRule { rule }))),
+        Err(e) => Err(ApiError::new(StatusCode::SERVICE_UNAVAILABLE, e)),
+    }
+}
+
+/// Stop applying a blocklist rule
+#[utoipa::path(
+    delete,
+    path = "/admin/blocklist/{id}",
+    tag = "admin",
+    params(("id" = String, Path, description = "Rule ID")),
+    responses(
+        (status = 204, description = "Deleted"),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+        (status = 404, description = "No such rule"),
+        (status = 503, description = "The rule could not be deleted", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn delete_block_rule(
+    Extension(blocklist): Extension<Arc<ExtractionBlocklist>>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    match blocklist.remove(&id).await {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Ok(StatusCode::NOT_FOUND),
+        Err(e) => Err(ApiError::new(StatusCode::SERVICE_UNAVAILABLE, e)),
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct ShippingRules {
+    rules: BTreeMap<MerchantDomain, ShippingRule>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/shipping-rules",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Every merchant's shipping `rules`", body = ShippingRules),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn list_shipping_rules(Extension(shipping): Extension<Arc<ShippingRuleStore>>) -> Reply<ShippingRules> {
+    Reply(ShippingRules {
+        rules: shipping.list().await,
+    })
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct MerchantShippingRule {
+    domain: MerchantDomain,
+    rule: Option<ShippingRule>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/shipping-rules/{domain}",
+    tag = "admin",
+    params(("domain" = String, Path, description = "Merchant domain, e.g. `amazon.com`")),
+    responses(
+        (status = 200, description = "The merchant's shipping `rule`", body = MerchantShippingRule),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+        (status = 404, description = "No rule for the merchant"),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn get_shipping_rule(
+    Extension(shipping): Extension<Arc<ShippingRuleStore>>,
+    Path(domain): Path<MerchantDomain>,
+) -> Result<Reply<MerchantShippingRule>, StatusCode> {
+    let rule = shipping.get(&domain).await.ok_or(StatusCode::NOT_FOUND)?;
+
+    Ok(Reply(MerchantShippingRule { domain, rule: Some(rule) }))
+}
+
+#[utoipa::path(
+    put,
+    path = "/admin/shipping-rules/{domain}",
+    tag = "admin",
+    params(("domain" = String, Path, description = "Merchant domain, e.g. `amazon.com`")),
+    request_body = ShippingRule,
+    responses(
+        (status = 200, description = "The stored `rule`", body = MerchantShippingRule),
+        (status = 400, description = "Invalid rule", body = ErrorBody),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn put_shipping_rule(
+    Extension(shipping): Extension<Arc<ShippingRuleStore>>,
+    Path(domain): Path<MerchantDomain>,
+    Json(rule): Json<ShippingRule>,
+) -> Result<Reply<MerchantShippingRule>, ApiError> {
+    if let Err(e) = rule.validate() {
+        return Err(ApiError::bad_request(e));
+    }
+
+    shipping.put(domain.clone(), rule).await;
+    let rule = shipping.get(&domain).await;
+    Ok(Reply(MerchantShippingRule { domain, rule }))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/admin/shipping-rules/{domain}",
+    tag = "admin",
+    params(("domain" = String, Path, description = "Merchant domain, e.g. `amazon.com`")),
+    responses(
+        (status = 204, description = "Deleted"),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+        (status = 404, description = "No rule for the merchant"),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn delete_shipping_rule(
+    Extension(shipping): Extension<Arc<ShippingRuleStore>>,
+    Path(domain): Path<MerchantDomain>,
+) -> StatusCode {
+    if shipping.delete(&domain).await {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct ReprocessStatus {
+    run: ReprocessRun,
+}
+
+/// Start rebuilding the coupon corpus from archived snapshots
+#[utoipa::path(
+    post,
+    path = "/admin/reprocess",
+    tag = "admin",
+    request_body = ReprocessRequest,
+    responses(
+        (status = 202, description = "The started `run`", body = ReprocessStatus),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+        (status = 404, description = "No snapshot archive is configured", body = ErrorBody),
+        (status = 409, description = "A run is already in progress", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn start_reprocess(
+    Extension(reprocessor): Extension<Arc<Reprocessor>>,
+    Json(request): Json<ReprocessRequest>,
+) -> Result<(StatusCode, Reply<ReprocessStatus>), ApiError> {
+    match reprocessor.start(request).await {
+        Ok(run) => Ok((StatusCode::ACCEPTED, Reply(ReprocessStatus { run }))),
+        Err(e @ ReprocessError::NoArchive) => Err(ApiError::new(StatusCode::NOT_FOUND, e.to_string())),
+        Err(e @ ReprocessError::AlreadyRunning(_)) => Err(ApiError::new(StatusCode::CONFLICT, e.to_string())),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/reprocess/{id}",
+    tag = "admin",
+    params(("id" = Uuid, Path, description = "Run id")),
+    responses(
+        (status = 200, description = "The reprocessing `run`", body = ReprocessStatus),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+        (status = 404, description = "No such run"),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn get_reprocess(
+    Extension(reprocessor): Extension<Arc<Reprocessor>>,
+    Path(run_id): Path<Uuid>,
+) -> Result<Reply<ReprocessStatus>, StatusCode> {
+    let run = reprocessor.get(run_id).await.ok_or(StatusCode::NOT_FOUND)?;
+
+    Ok(Reply(ReprocessStatus { run }))
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct StoredBackfill {
+    backfill: Backfill,
+}
+
+/// Plan a checkpointed rescrape of every merchant or reprocessing of the archive
+#[utoipa::path(
+    post,
+    path = "/admin/backfills",
+    tag = "admin",
+    request_body = BackfillRequest,
+    responses(
+        (status = 202, description = "The planned `backfill`", body = StoredBackfill),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+        (status = 404, description = "A reprocess backfill without a snapshot archive", body = ErrorBody),
+        (status = 503, description = "The archive could not be listed", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn start_backfill(
+    Extension(backfills): Extension<Arc<Backfills>>,
+    Json(request): Json<BackfillRequest>,
+) -> Result<(StatusCode, Reply<StoredBackfill>), ApiError> {
+    match backfills.start(request).await {
+        Ok(backfill) => Ok((StatusCode::ACCEPTED, Reply(StoredBackfill { backfill }))),
+        Err(e) => Err(ApiError::new(backfill_error_status(&e), e.to_string())),
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct BackfillList {
+    backfills: Vec<Backfill>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/backfills",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Every kept `backfills`, newest first", body = BackfillList),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn list_backfills(Extension(backfills): Extension<Arc<Backfills>>) -> Reply<BackfillList> {
+    Reply(BackfillList {
+        backfills: backfills.list().await,
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/backfills/{id}",
+    tag = "admin",
+    params(("id" = Uuid, Path, description = "Backfill id")),
+    responses(
+        (status = 200, description = "The `backfill` with its checkpointed progress and ETA", body = StoredBackfill),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+        (status = 404, description = "No such backfill"),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn get_backfill(
+    Extension(backfills): Extension<Arc<Backfills>>,
+    Path(backfill_id): Path<Uuid>,
+) -> Result<Reply<StoredBackfill>, StatusCode> {
+    let backfill = backfills.get(backfill_id).await.ok_or(StatusCode::NOT_FOUND)?;
+
+    Ok(Reply(StoredBackfill { backfill }))
+}
+
+/// Stop a backfill after its current chunk
+#[utoipa::path(
+    post,
+    path = "/admin/backfills/{id}/pause",
+    tag = "admin",
+    params(("id" = Uuid, Path, description = "Backfill id")),
+    responses(
+        (status = 200, description = "The paused `backfill`", body = StoredBackfill),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+        (status = 404, description = "No such backfill", body = ErrorBody),
+        (status = 409, description = "The backfill is not running", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn pause_backfill(
+    Extension(backfills): Extension<Arc<Backfills>>,
+    Path(backfill_id): Path<Uuid>,
+) -> Result<Reply<StoredBackfill>, ApiError> {
+    match backfills.pause(backfill_id).await {
+        Ok(backfill) => Ok(Reply(StoredBackfill { backfill })),
+        Err(e) => Err(ApiError::new(backfill_error_status(&e), e.to_string())),
+    }
+}
+
+/// Continue a paused backfill from its last checkpoint
+#[utoipa::path(
+    post,
+    path = "/admin/backfills/{id}/resume",
+    tag = "admin",
+    params(("id" = Uuid, Path, description = "Backfill id")),
+    responses(
+        (status = 200, description = "The resumed `backfill`", body = StoredBackfill),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+        (status = 404, description = "No such backfill", body = ErrorBody),
+        (status = 409, description = "The backfill is not paused", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn resume_backfill(
+    Extension(backfills): Extension<Arc<Backfills>>,
+    Path(backfill_id): Path<Uuid>,
+) -> Result<Reply<StoredBackfill>, ApiError> {
+    match backfills.resume(backfill_id).await {
+        Ok(backfill) => Ok(Reply(StoredBackfill { backfill })),
+        Err(e) => Err(ApiError::new(backfill_error_status(&e), e.to_string())),
+    }
+}
+
+fn backfill_error_status(error: &BackfillError) -> StatusCode {
+    match error {
+        BackfillError::NoArchive | BackfillError::NotFound => StatusCode::NOT_FOUND,
+        BackfillError::InvalidStatus(_) => StatusCode::CONFLICT,
+        BackfillError::Plan(_) => StatusCode::SERVICE_UNAVAILABLE,
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct SeedStatus {
+    run: SeedRun,
+}
+
+/// Seed merchants and coupons from the configured bundles, e.g. for a new region
+#[utoipa::path(
+    post,
+    path = "/admin/seed",
+    tag = "admin",
+    request_body = SeedRequest,
+    responses(
+        (status = 202, description = "The started `run`", body = SeedStatus),
+        (status = 400, description = "No bundle by one of the names", body = ErrorBody),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+        (status = 404, description = "No seed bundles are configured", body = ErrorBody),
+        (status = 409, description = "A run is already in progress", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn start_seed(
+    Extension(seeder): Extension<Arc<Seeder>>,
+    Json(request): Json<SeedRequest>,
+) -> Result<(StatusCode, Reply<SeedStatus>), ApiError> {
+    match seeder.start(request).await {
+        Ok(run) => Ok((StatusCode::ACCEPTED, Reply(SeedStatus { run }))),
+        Err(e @ SeedError::UnknownBundle(_)) => Err(ApiError::bad_request(e.to_string())),
+        Err(e @ SeedError::NoBundles) => Err(ApiError::new(StatusCode::NOT_FOUND, e.to_string())),
+        Err(e @ SeedError::AlreadyRunning(_)) => Err(ApiError::new(StatusCode::CONFLICT, e.to_string())),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/seed/{id}",
+    tag = "admin",
+    params(("id" = Uuid, Path, description = "Run id")),
+    responses(
+        (status = 200, description = "The seed `run` with its progress and failed feeds", body = SeedStatus),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+        (status = 404, description = "No such run"),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn get_seed(
+    Extension(seeder): Extension<Arc<Seeder>>,
+    Path(run_id): Path<Uuid>,
+) -> Result<Reply<SeedStatus>, StatusCode> {
+    let run = seeder.get(run_id).await.ok_or(StatusCode::NOT_FOUND)?;
+
+    Ok(Reply(SeedStatus { run }))
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct SlaStatus {
+    sla: SlaReport,
+}
+
+/// Deal stream latency per platform, and the platforms over budget
+#[utoipa::path(
+    get,
+    path = "/admin/sla",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Deal stream latency per platform", body = SlaStatus),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn sla_report(Extension(sla): Extension<Arc<SlaMonitor>>) -> Reply<SlaStatus> {
+    Reply(SlaStatus {
+        sla: sla.report().await,
+    })
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct PiiAudit {
+    scrubbed: Vec<ScrubCount>,
+}
+
+/// Entities scrubbed from ingested text, by tenant, field and kind
+#[utoipa::path(
+    get,
+    path = "/admin/pii",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Counts of `scrubbed` entities", body = PiiAudit),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn pii_audit(Extension(scrubber): Extension<Arc<Scrubber>>) -> Reply<PiiAudit> {
+    Reply(PiiAudit {
+        scrubbed: scrubber.audit().await,
+    })
+}
+
+/// Prometheus scrape endpoint
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "status",
+    responses(
+        (status = 200, description = "Prometheus text exposition of the deal stream SLA", body = String, content_type = "text/plain"),
+    )
+)]
+pub(super) async fn metrics(Extension(sla): Extension<Arc<SlaMonitor>>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        sla.prometheus().await,
+    )
+}
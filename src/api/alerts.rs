@@ -0,0 +1,60 @@
+//! Natural-language alert creation
+
+use std::sync::Arc;
+
+use axum::{extract::Extension, http::StatusCode, Json};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use super::reply::{ApiError, Reply};
+use super::requests::NaturalAlertRequest;
+use crate::alerts::natural_language::{AlertInterpretation, NaturalAlertParser};
+use crate::auth::UserContext;
+use crate::models::alert::DealAlert;
+
+/// An alert read from text, for the client to confirm
+#[derive(Serialize, ToSchema)]
+pub(super) struct NaturalAlert {
+    alert: DealAlert,
+    interpretation: AlertInterpretation,
+    requires_confirmation: bool,
+}
+
+/// Interpret a free-text alert request; the client confirms before creating the alert.
+/// With JWTs configured, only for the token's user.
+#[utoipa::path(
+    post,
+    path = "/alerts/natural",
+    tag = "users",
+    request_body = NaturalAlertRequest,
+    responses(
+        (status = 200, description = "The `alert` and its `interpretation`, to be confirmed", body = NaturalAlert),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 403, description = "`user_id` is not the token's user", body = ErrorBody),
+        (status = 422, description = "The text was not understood", body = ErrorBody),
+    ),
+    security(("user_jwt" = []), ("user_jwt" = [], "api_key" = []))
+)]
+pub(super) async fn create_natural_alert(
+    Extension(parser): Extension<Arc<NaturalAlertParser>>,
+    user: Option<UserContext>,
+    Json(payload): Json<NaturalAlertRequest>,
+) -> Result<Reply<NaturalAlert>, ApiError> {
+    if user.is_some_and(|user| user.user_id != payload.user_id) {
+        return Err(ApiError::new(
+            StatusCode::FORBIDDEN,
+            "alerts can only be created for the signed-in user",
+        ));
+    }
+    match parser.parse(&payload.text).await {
+        Some(interpretation) => Ok(Reply(NaturalAlert {
+            alert: interpretation.to_alert(&payload.user_id),
+            interpretation,
+            requires_confirmation: true,
+        })),
+        None => Err(
+            ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, "Could not understand the alert request")
+                .with("text", payload.text),
+        ),
+    }
+}
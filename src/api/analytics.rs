@@ -0,0 +1,60 @@
+//! Coupon performance analytics for the internal BI dashboard
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Extension, Query},
+};
+use chrono::{NaiveDate, TimeDelta};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use super::reply::{ApiError, Reply};
+use crate::analytics::{AnalyticsReport, CouponAnalytics};
+use crate::models::domain::MerchantDomain;
+
+/// Days a report covers when `from` is not given
+const DEFAULT_RANGE_DAYS: i64 = 30;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(super) struct AnalyticsQuery {
+    /// Only this merchant; every merchant by default
+    merchant: Option<MerchantDomain>,
+    /// First day (UTC), 30 days before `to` by default
+    from: Option<NaiveDate>,
+    /// Last day (UTC), today by default
+    to: Option<NaiveDate>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct Analytics {
+    analytics: AnalyticsReport,
+}
+
+/// Codes discovered, publish rate, average code lifetime, checkout success rate and
+/// reported savings over a date range, from the hourly rollups
+#[utoipa::path(
+    get,
+    path = "/analytics/coupons",
+    tag = "admin",
+    params(AnalyticsQuery),
+    responses(
+        (status = 200, description = "The `analytics` with their daily `trend`", body = Analytics),
+        (status = 400, description = "`from` after `to`, or a range over a year", body = ErrorBody),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn coupon_analytics(
+    Extension(analytics): Extension<Arc<CouponAnalytics>>,
+    Query(query): Query<AnalyticsQuery>,
+) -> Result<Reply<Analytics>, ApiError> {
+    let to = query.to.unwrap_or_else(|| analytics.today());
+    let from = query.from.unwrap_or(to - TimeDelta::days(DEFAULT_RANGE_DAYS - 1));
+    match analytics.report(query.merchant.as_ref(), from, to) {
+        Ok(report) => Ok(Reply(Analytics { analytics: report })),
+        Err(e) => Err(ApiError::bad_request(e)),
+    }
+}
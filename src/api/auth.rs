@@ -0,0 +1,144 @@
+//! Bearer token authentication of the endpoints that act for a shopper
+
+use std::sync::Arc;
+
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Request},
+    http::{header, request::Parts, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use super::reply::ApiError;
+use crate::auth::{JwtVerifier, UserContext};
+
+fn unauthorized(message: String) -> Response {
+    (
+        [(header::WWW_AUTHENTICATE, "Bearer")],
+        ApiError::new(StatusCode::UNAUTHORIZED, message),
+    )
+        .into_response()
+}
+
+/// Verify the request's bearer token and hand its [`UserContext`] to the handler.
+/// Requests pass untouched while no [`JwtVerifier`] is configured.
+pub(super) async fn require_user(mut request: Request, next: Next) -> Response {
+    let Some(verifier) = request.extensions().get::<Arc<JwtVerifier>>().cloned() else {
+        return next.run(request).await;
+    };
+    if !verifier.is_enabled() {
+        return next.run(request).await;
+    }
+
+    let token = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::trim);
+    let Some(token) = token else {
+        return unauthorized("missing bearer token".to_string());
+    };
+    match verifier.verify(token).await {
+        Ok(user) => {
+            request.extensions_mut().insert(user);
+            next.run(request).await
+        }
+        Err(e) => unauthorized(e.to_string()),
+    }
+}
+
+/// The user [`require_user`] verified; take it as `Option<UserContext>` on routes
+/// that also serve unauthenticated deployments
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for UserContext {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<UserContext>()
+            .cloned()
+            .ok_or_else(|| unauthorized("missing bearer token".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::body::Body;
+    use axum::http::Request;
+    use serde_json::{json, Value};
+
+    use crate::api::tests as api;
+    use crate::app::Services;
+    use crate::auth::tests::{sign, signing_key};
+    use crate::auth::JwtVerifier;
+
+    async fn call(services: &Services, method: &str, path: &str, token: Option<&str>, body: Value) -> (u16, Value) {
+        let mut request = Request::builder().method(method).uri(path).header("content-type", "application/json");
+        if let Some(token) = token {
+            request = request.header("authorization", format!("Bearer {}", token));
+        }
+        api::call(services, request.body(Body::from(body.to_string())).unwrap()).await
+    }
+
+    async fn post(services: &Services, path: &str, token: Option<&str>, body: Value) -> (u16, Value) {
+        call(services, "POST", path, token, body).await
+    }
+
+    #[tokio::test]
+    async fn test_protects_user_endpoints_and_leaves_health_public() {
+        let (pair, jwks) = signing_key("k1");
+        let mut services = api::sandbox().await;
+        services.jwt = Arc::new(JwtVerifier::with_keys(jwks).with_issuer("https://id.example.com/"));
+        let exp = chrono::Utc::now().timestamp() + 300;
+        let token = sign(&pair, "k1", &json!({"sub": "user-1", "iss": "https://id.example.com/", "exp": exp}));
+        let alert = json!({"user_id": "user-1", "text": "tell me when any 65-inch OLED drops below $900"});
+
+        assert_eq!(api::get(&services, "/health").await.0, 200);
+
+        let (status, body) = post(&services, "/coupons/test", None, json!({})).await;
+        assert_eq!((status, body["error"].as_str()), (401, Some("missing bearer token")));
+        let (status, body) = post(&services, "/api/v1/alerts/natural", Some("a.b.c"), alert.clone()).await;
+        assert_eq!((status, body["errors"][0]["message"].as_str()), (401, Some("malformed token")));
+        assert_eq!(post(&services, "/coupons/test", Some(&token), json!({})).await.0, 200);
+
+        // Alerts are created for the token's user only
+        let (status, body) = post(&services, "/alerts/natural", Some(&token), alert).await;
+        assert_eq!(status, 200);
+        assert_eq!(body["alert"]["user_id"], "user-1");
+        let other = json!({"user_id": "user-2", "text": "tell me when any 65-inch OLED drops below $900"});
+        assert_eq!(post(&services, "/alerts/natural", Some(&token), other).await.0, 403);
+    }
+
+    #[tokio::test]
+    async fn test_users_reach_only_their_own_savings_and_preferences() {
+        let (pair, jwks) = signing_key("k1");
+        let mut services = api::sandbox().await;
+        services.jwt = Arc::new(JwtVerifier::with_keys(jwks).with_issuer("https://id.example.com/"));
+        let exp = chrono::Utc::now().timestamp() + 300;
+        let token = sign(&pair, "k1", &json!({"sub": "user-1", "iss": "https://id.example.com/", "exp": exp}));
+        let saving = json!({"merchant": "shop.example.com", "amount": {"amount": "12.50", "currency": "USD"}});
+        let preferences = json!({"channels": []});
+
+        for (method, path, body) in [
+            ("GET", "/users/user-2/savings", Value::Null),
+            ("POST", "/users/user-2/savings", saving.clone()),
+            ("GET", "/users/user-2/savings/summary", Value::Null),
+            ("GET", "/users/user-2/notification-preferences", Value::Null),
+            ("PUT", "/users/user-2/notification-preferences", preferences),
+        ] {
+            let (status, response) = call(&services, method, path, None, body.clone()).await;
+            assert_eq!((status, response["error"].as_str()), (401, Some("missing bearer token")), "{} {}", method, path);
+            assert_eq!(call(&services, method, path, Some(&token), body).await.0, 403, "{} {}", method, path);
+        }
+
+        assert_eq!(post(&services, "/users/user-1/savings", Some(&token), saving).await.0, 201);
+        let (status, body) = call(&services, "GET", "/users/user-1/savings", Some(&token), Value::Null).await;
+        assert_eq!((status, body["entries"].as_array().map(Vec::len)), (200, Some(1)));
+        assert_eq!(call(&services, "GET", "/users/user-1/notification-preferences", Some(&token), Value::Null).await.0, 200);
+    }
+}
@@ -0,0 +1,77 @@
+//! Opt-in coupon clipping to shoppers' platform accounts
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Extension, Path},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use super::reply::{ApiError, Reply};
+use crate::clipping::{AccessToken, ClipError, ClipReport, ClipRequest, ClippingService};
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct ClippingPlatforms {
+    platforms: Vec<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct Clipping {
+    clipping: ClipReport,
+}
+
+#[utoipa::path(
+    get,
+    path = "/clipping/platforms",
+    tag = "users",
+    responses(
+        (status = 200, description = "`platforms` offers can be clipped to", body = ClippingPlatforms),
+    )
+)]
+pub(super) async fn clipping_platforms(Extension(clipping): Extension<Arc<ClippingService>>) -> Reply<ClippingPlatforms> {
+    Reply(ClippingPlatforms {
+        platforms: clipping.platforms().into_iter().map(String::from).collect(),
+    })
+}
+
+/// Clip offers with the token in `X-Platform-Token`; the body must carry `"consent": true`
+#[utoipa::path(
+    post,
+    path = "/clipping/{platform}",
+    tag = "users",
+    params(("platform" = String, Path, description = "Platform offers are clipped on")),
+    request_body = ClipRequest,
+    responses(
+        (status = 200, description = "The `clipping` report", body = Clipping),
+        (status = 400, description = "`consent` was not given", body = ErrorBody),
+        (status = 401, description = "Missing or rejected `X-Platform-Token`", body = ErrorBody),
+        (status = 404, description = "Unknown platform", body = ErrorBody),
+        (status = 502, description = "The platform failed", body = ErrorBody),
+    )
+)]
+pub(super) async fn clip_offers(
+    Extension(clipping): Extension<Arc<ClippingService>>,
+    Path(platform): Path<String>,
+    token: AccessToken,
+    Json(request): Json<ClipRequest>,
+) -> Response {
+    let result = clipping.clip(&platform, &token, &request).await;
+
+    let response = match result {
+        Ok(report) => Reply(Clipping { clipping: report }).into_response(),
+        Err(e) => {
+            let status = match e {
+                ClipError::UnknownPlatform(_) => StatusCode::NOT_FOUND,
+                ClipError::ConsentRequired => StatusCode::BAD_REQUEST,
+                ClipError::Unauthorized => StatusCode::UNAUTHORIZED,
+                ClipError::Upstream(_) => StatusCode::BAD_GATEWAY,
+            };
+            ApiError::new(status, e.to_string()).into_response()
+        }
+    };
+    ([(header::CACHE_CONTROL, "no-store")], response).into_response()
+}
@@ -0,0 +1,199 @@
+//! Curated deal collections: the homepage endpoints and editor administration
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use axum::{
+    extract::{Extension, Path},
+    http::StatusCode,
+    Json,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use super::reply::{ApiError, Reply};
+use crate::collections::{Collection, CollectionService, CollectionView};
+use crate::experiments::RankingStrategy;
+use crate::models::deal::Deal;
+use crate::search::DealSearch;
+use crate::services::ranking::RankingPipeline;
+use crate::tenant::TenantId;
+
+/// Each collection with its current deals, ranking the catalogue once per strategy
+async fn with_deals(
+    collections: Vec<Collection>,
+    ranking: &RankingPipeline,
+    search: &DealSearch,
+    tenant: &str,
+) -> Vec<CollectionView> {
+    let mut ranked: HashMap<RankingStrategy, Vec<Deal>> = HashMap::new();
+    let mut views = Vec::with_capacity(collections.len());
+    for collection in collections {
+        if let Entry::Vacant(slot) = ranked.entry(collection.ranking) {
+            slot.insert(ranking.ranked(tenant, collection.ranking).await);
+        }
+        let deals = collection.members(&ranked[&collection.ranking], search);
+        views.push(CollectionView { collection, deals });
+    }
+    views
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct CollectionViews {
+    collections: Vec<CollectionView>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct CollectionDetail {
+    collection: CollectionView,
+}
+
+/// Collections without their deals
+#[derive(Serialize, ToSchema)]
+pub(super) struct CollectionList {
+    collections: Vec<Collection>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct StoredCollection {
+    collection: Collection,
+}
+
+/// Published collections in homepage order, with their deals
+#[utoipa::path(
+    get,
+    path = "/collections",
+    tag = "deals",
+    responses(
+        (status = 200, description = "`collections`: published collections with their deals", body = CollectionViews),
+    )
+)]
+pub(super) async fn list_collections(
+    Extension(collections): Extension<Arc<CollectionService>>,
+    Extension(ranking): Extension<Arc<RankingPipeline>>,
+    Extension(search): Extension<Arc<DealSearch>>,
+    tenant: TenantId,
+) -> Reply<CollectionViews> {
+    let published = collections.published().await;
+
+    Reply(CollectionViews {
+        collections: with_deals(published, &ranking, &search, &tenant.0).await,
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/collections/{slug}",
+    tag = "deals",
+    params(("slug" = String, Path, description = "Collection slug")),
+    responses(
+        (status = 200, description = "`collection` and its `deals`", body = CollectionDetail),
+        (status = 404, description = "No published collection with this slug"),
+    )
+)]
+pub(super) async fn get_collection(
+    Extension(collections): Extension<Arc<CollectionService>>,
+    Extension(ranking): Extension<Arc<RankingPipeline>>,
+    Extension(search): Extension<Arc<DealSearch>>,
+    tenant: TenantId,
+    Path(slug): Path<String>,
+) -> Result<Reply<CollectionDetail>, StatusCode> {
+    let collection = collections.get_published(&slug).await.ok_or(StatusCode::NOT_FOUND)?;
+    let view = with_deals(vec![collection], &ranking, &search, &tenant.0).await.remove(0);
+
+    Ok(Reply(CollectionDetail { collection: view }))
+}
+
+/// Every collection, drafts and scheduled ones included, without deals
+#[utoipa::path(
+    get,
+    path = "/admin/collections",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Every `collection`, drafts included", body = CollectionList),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn admin_list_collections(Extension(collections): Extension<Arc<CollectionService>>) -> Reply<CollectionList> {
+    Reply(CollectionList {
+        collections: collections.list().await,
+    })
+}
+
+#[utoipa::path(
+    put,
+    path = "/admin/collections/{slug}",
+    tag = "admin",
+    params(("slug" = String, Path, description = "Collection slug")),
+    request_body = Collection,
+    responses(
+        (status = 200, description = "The stored `collection`", body = StoredCollection),
+        (status = 400, description = "Invalid collection", body = ErrorBody),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn put_collection(
+    Extension(collections): Extension<Arc<CollectionService>>,
+    Path(slug): Path<String>,
+    Json(collection): Json<Collection>,
+) -> Result<Reply<StoredCollection>, ApiError> {
+    match collections.put(&slug, collection).await {
+        Ok(collection) => Ok(Reply(StoredCollection { collection })),
+        Err(e) => Err(ApiError::bad_request(e)),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/admin/collections/{slug}",
+    tag = "admin",
+    params(("slug" = String, Path, description = "Collection slug")),
+    responses(
+        (status = 204, description = "Deleted"),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+        (status = 404, description = "No such collection"),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn delete_collection(
+    Extension(collections): Extension<Arc<CollectionService>>,
+    Path(slug): Path<String>,
+) -> StatusCode {
+    match collections.delete(&slug).await {
+        true => StatusCode::NO_CONTENT,
+        false => StatusCode::NOT_FOUND,
+    }
+}
+
+/// A collection's deals as they would be served now, whether or not it is published
+#[utoipa::path(
+    get,
+    path = "/admin/collections/{slug}/preview",
+    tag = "admin",
+    params(("slug" = String, Path, description = "Collection slug")),
+    responses(
+        (status = 200, description = "The `collection` and the `deals` it would serve now", body = CollectionDetail),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+        (status = 404, description = "No such collection"),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn preview_collection(
+    Extension(collections): Extension<Arc<CollectionService>>,
+    Extension(ranking): Extension<Arc<RankingPipeline>>,
+    Extension(search): Extension<Arc<DealSearch>>,
+    tenant: TenantId,
+    Path(slug): Path<String>,
+) -> Result<Reply<CollectionDetail>, StatusCode> {
+    let collection = collections.get(&slug).await.ok_or(StatusCode::NOT_FOUND)?;
+    let view = with_deals(vec![collection], &ranking, &search, &tenant.0).await.remove(0);
+
+    Ok(Reply(CollectionDetail { collection: view }))
+}
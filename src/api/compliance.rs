@@ -0,0 +1,191 @@
+//! Offers filtered by the compliance rules of the requester's country
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Request, State},
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use serde_json::{Map, Value};
+
+use super::shaping::{is_exempt, json_body, json_response};
+use crate::compliance::{ComplianceRules, JurisdictionProfile, COUNTRY_HEADER};
+use crate::tenant::{TenantId, TenantRegistry};
+
+#[derive(Clone)]
+pub(super) struct Compliance {
+    pub rules: Arc<ComplianceRules>,
+    pub tenants: Arc<TenantRegistry>,
+}
+
+/// Apply the profile of the request's country to JSON responses: excluded offers
+/// are dropped and priced offers get its `price_disclosure`. Responses the rules
+/// applied to say so in `X-Compliance-Country`.
+///
+/// An offer is any object with `title` and `merchant_domain`, i.e. a deal or a coupon.
+pub(super) async fn filter_responses(State(compliance): State<Compliance>, request: Request, next: Next) -> Response {
+    let country = match compliance.rules.is_enabled() && !is_exempt(request.uri().path()) {
+        true => {
+            let header = request.headers().get(COUNTRY_HEADER).and_then(|v| v.to_str().ok());
+            let tenant_country = request
+                .extensions()
+                .get::<TenantId>()
+                .and_then(|tenant| compliance.tenants.get(&tenant.0))
+                .and_then(|record| record.country.as_deref());
+            compliance.rules.resolve_country(header, tenant_country)
+        }
+        false => None,
+    };
+
+    let response = next.run(request).await;
+    let Some((country, profile)) = country.and_then(|country| Some((country.clone(), compliance.rules.profile(&country)?.clone())))
+    else {
+        return response;
+    };
+
+    let (mut parts, mut value) = match json_body(response).await {
+        Ok(json) => json,
+        Err(response) => return response,
+    };
+    apply_profile(&profile, &mut value);
+    if let Ok(country) = HeaderValue::from_str(&country) {
+        parts.headers.insert(HeaderName::from_static("x-compliance-country"), country);
+    }
+    json_response(parts, &value)
+}
+
+fn is_offer(object: &Map<String, Value>) -> bool {
+    ["title", "merchant_domain"].iter().all(|key| object.contains_key(*key))
+}
+
+fn excluded(profile: &JurisdictionProfile, value: &Value) -> bool {
+    let Value::Object(object) = value else {
+        return false;
+    };
+    if !is_offer(object) {
+        return false;
+    }
+    let field = |name: &str| object.get(name).and_then(Value::as_str);
+    let texts: Vec<&str> = ["title", "description"].into_iter().filter_map(field).collect();
+    profile.excludes(field("category"), &texts)
+}
+
+fn apply_profile(profile: &JurisdictionProfile, value: &mut Value) {
+    match value {
+        Value::Array(items) => {
+            items.retain(|item| !excluded(profile, item));
+            items.iter_mut().for_each(|item| apply_profile(profile, item));
+        }
+        Value::Object(object) => {
+            if let (true, Some(disclosure)) = (is_offer(object) && object.contains_key("price"), &profile.price_disclosure) {
+                object.insert("price_disclosure".to_string(), Value::String(disclosure.clone()));
+            }
+            // A single offer that is excluded, e.g. `deal` in a response, is nulled
+            for nested in object.values_mut() {
+                if excluded(profile, nested) {
+                    *nested = Value::Null;
+                } else {
+                    apply_profile(profile, nested);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeSet, HashMap};
+    use std::sync::Arc;
+
+    use axum::body::Body;
+    use axum::http::Request;
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::api::tests as api;
+
+    fn profiles() -> HashMap<String, JurisdictionProfile> {
+        HashMap::from([
+            (
+                "IN".to_string(),
+                JurisdictionProfile {
+                    excluded_categories: BTreeSet::from(["Alcohol".to_string(), "Tobacco".to_string()]),
+                    excluded_keywords: vec!["whisky".to_string()],
+                    price_disclosure: None,
+                },
+            ),
+            (
+                "DE".to_string(),
+                JurisdictionProfile {
+                    price_disclosure: Some("Alle Preise inkl. MwSt.".to_string()),
+                    ..Default::default()
+                },
+            ),
+        ])
+    }
+
+    fn offers() -> Value {
+        json!({
+            "deals": [
+                {"title": "Single malt", "merchant_domain": "spirits.example", "category": "alcohol", "price": {"amount": "40"}},
+                {"title": "Headphones", "merchant_domain": "shop.example", "category": "Electronics", "price": {"amount": "99"}},
+            ],
+            "coupons": [
+                {"code": "DRAM10", "title": "10% off whisky gift sets", "merchant_domain": "spirits.example"},
+                {"code": "SAVE5", "title": "5% off sitewide", "merchant_domain": "shop.example"},
+            ],
+            "deal": {"title": "Cigars", "merchant_domain": "smoke.example", "category": "Tobacco"},
+            "total": 4
+        })
+    }
+
+    #[test]
+    fn test_india_excludes_alcohol_and_tobacco_offers() {
+        let mut value = offers();
+        apply_profile(&profiles()["IN"], &mut value);
+        assert_eq!(value["deals"].as_array().unwrap().len(), 1);
+        assert_eq!(value["deals"][0]["title"], "Headphones");
+        assert_eq!(value["coupons"].as_array().unwrap().len(), 1);
+        assert_eq!(value["coupons"][0]["code"], "SAVE5");
+        assert!(value["deal"].is_null());
+        assert!(value["deals"][0].get("price_disclosure").is_none());
+    }
+
+    #[test]
+    fn test_germany_discloses_prices_without_excluding() {
+        let mut value = offers();
+        apply_profile(&profiles()["DE"], &mut value);
+        assert_eq!(value["deals"].as_array().unwrap().len(), 2);
+        assert_eq!(value["deals"][1]["price_disclosure"], "Alle Preise inkl. MwSt.");
+        // Coupons carry no price of their own
+        assert!(value["coupons"][0].get("price_disclosure").is_none());
+        assert_eq!(value["deal"]["title"], "Cigars");
+    }
+
+    #[tokio::test]
+    async fn test_filters_responses_by_the_request_country() {
+        let mut services = api::sandbox().await;
+        services.compliance = Arc::new(ComplianceRules::new(profiles()).with_default_country("DE"));
+        let get = |country: Option<&'static str>| {
+            let mut request = Request::get("/deals?limit=3");
+            if let Some(country) = country {
+                request = request.header(COUNTRY_HEADER, country);
+            }
+            api::send(&services, request.body(Body::empty()).unwrap())
+        };
+
+        // Countries without a profile are served unfiltered
+        let response = get(Some("us")).await;
+        assert!(response.headers().get("x-compliance-country").is_none());
+        let deals = api::json(response).await;
+        assert!(deals["deals"][0].get("price_disclosure").is_none());
+
+        let response = get(None).await;
+        assert_eq!(response.headers()["x-compliance-country"], "DE");
+        let deals = api::json(response).await;
+        assert_eq!(deals["deals"][0]["price_disclosure"], "Alle Preise inkl. MwSt.");
+    }
+}
@@ -0,0 +1,234 @@
+//! Request IDs and W3C trace context for every request
+//!
+//! Each request gets an `x-request-id`, the caller's when it sent a usable one,
+//! and a `traceparent` that continues the caller's trace or starts a new one. Both
+//! are set on the response, recorded on the `request` tracing span the rest of the
+//! stack runs in, and handed to handlers as a [`RequestContext`] extension; the
+//! scraper sends them on with its fetches (see [`crate::telemetry`]). JSON error
+//! bodies carry them as `request_id` and `trace_id` (in the envelope, on each
+//! error), so a failed deal lookup can be matched to the scraper logs of the same
+//! trace. Handlers' errors are built with them (see [`super::reply`]).
+
+use axum::{
+    extract::Request,
+    http::{HeaderMap, HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use tracing::Instrument;
+
+use super::reply::{Rendered, Rendering};
+use super::shaping::{json_body, json_response};
+use crate::telemetry::{TraceContext, REQUEST_ID_HEADER, TRACEPARENT_HEADER};
+
+/// Longer request IDs from callers are replaced with one of ours
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Who to blame for a request, across services
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct RequestContext {
+    pub request_id: String,
+    /// 32 lowercase hex digits, shared by every service on the trace
+    pub trace_id: String,
+    /// This service's span of the trace, 16 lowercase hex digits
+    pub span_id: String,
+    /// Whether the caller records the trace
+    pub sampled: bool,
+}
+
+impl RequestContext {
+    /// Continue the request ID and trace of `headers`, making up what is
+    /// missing or malformed
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let header = |name| headers.get(name).and_then(|value| value.to_str().ok()).map(str::trim);
+        let request_id = header(REQUEST_ID_HEADER)
+            .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN && id.bytes().all(|b| b.is_ascii_graphic()))
+            .map(str::to_string)
+            .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+        let (trace_id, sampled) = header(TRACEPARENT_HEADER)
+            .and_then(parse_traceparent)
+            .unwrap_or_else(|| (uuid::Uuid::new_v4().simple().to_string(), true));
+        Self {
+            request_id,
+            trace_id,
+            span_id: format!("{:016x}", rand::random::<u64>().max(1)),
+            sampled,
+        }
+    }
+
+    /// The `traceparent` naming this service's span as the parent
+    pub fn traceparent(&self) -> String {
+        format!("00-{}-{}-{}", self.trace_id, self.span_id, if self.sampled { "01" } else { "00" })
+    }
+}
+
+/// The trace ID and sampled flag of a `traceparent` header, or `None` when it is malformed
+fn parse_traceparent(value: &str) -> Option<(String, bool)> {
+    let mut fields = value.split('-');
+    let (version, trace_id, parent_id, flags) = (fields.next()?, fields.next()?, fields.next()?, fields.next()?);
+    let hex = |field: &str, len| field.len() == len && field.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'));
+    let zero = |field: &str| field.bytes().all(|b| b == b'0');
+    // Version 00 has exactly four fields; later versions may append more
+    if !hex(version, 2) || version == "ff" || (version == "00" && fields.next().is_some()) {
+        return None;
+    }
+    if !hex(trace_id, 32) || zero(trace_id) || !hex(parent_id, 16) || zero(parent_id) || !hex(flags, 2) {
+        return None;
+    }
+    let flags = u8::from_str_radix(flags, 16).ok()?;
+    Some((trace_id.to_string(), flags & 1 == 1))
+}
+
+/// Run the request in a span carrying its [`RequestContext`], and return the
+/// IDs on the response
+pub(super) async fn correlate(mut request: Request, next: Next) -> Response {
+    let context = RequestContext::from_headers(request.headers());
+    let span = tracing::info_span!(
+        "request",
+        method = %request.method(),
+        path = %request.uri().path(),
+        request_id = %context.request_id,
+        trace_id = %context.trace_id,
+        span_id = %context.span_id,
+        status = tracing::field::Empty,
+    );
+    request.extensions_mut().insert(context.clone());
+    let propagated = TraceContext {
+        request_id: context.request_id.clone(),
+        traceparent: context.traceparent(),
+    };
+
+    let served = Rendering::scope(|rendering| rendering.context = Some(context.clone()), next.run(request));
+    let response = TraceContext::scope(Some(propagated), served).instrument(span.clone()).await;
+    let status = response.status();
+    span.record("status", status.as_u16());
+    let rendered = response.extensions().get::<Rendered>().is_some();
+    let mut response = match !rendered && (status.is_client_error() || status.is_server_error()) {
+        true => with_ids(response, &context).await,
+        false => response,
+    };
+
+    let headers = response.headers_mut();
+    if let Ok(value) = HeaderValue::from_str(&context.request_id) {
+        headers.insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+    }
+    if let Ok(value) = HeaderValue::from_str(&context.traceparent()) {
+        headers.insert(HeaderName::from_static(TRACEPARENT_HEADER), value);
+    }
+    response
+}
+
+/// An `{"error": ...}` body not built by a handler with `request_id` and
+/// `trace_id` added; the envelope carries its own, and other bodies are left alone
+async fn with_ids(response: Response, context: &RequestContext) -> Response {
+    let (parts, mut body) = match json_body(response).await {
+        Ok(json) => json,
+        Err(response) => return response,
+    };
+    if let Some(fields) = body.as_object_mut().filter(|fields| fields.contains_key("error")) {
+        fields.insert("request_id".to_string(), context.request_id.clone().into());
+        fields.insert("trace_id".to_string(), context.trace_id.clone().into());
+    }
+    json_response(parts, &body)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use axum::body::Body;
+    use axum::http::Request;
+    use axum::{routing, Router};
+    use serde_json::Value;
+
+    use super::*;
+    use crate::api::tests as api;
+    use crate::app::Services;
+    use crate::coupon_engine::{CouponEngine, EngineConfig};
+
+    const PARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    #[test]
+    fn test_parse_traceparent() {
+        assert_eq!(parse_traceparent(PARENT), Some(("4bf92f3577b34da6a3ce929d0e0e4736".to_string(), true)));
+        assert_eq!(parse_traceparent(&PARENT.replace("-01", "-00")).map(|(_, sampled)| sampled), Some(false));
+        // Later versions may add fields
+        assert!(parse_traceparent(&format!("01{}-extra", &PARENT[2..])).is_some());
+        for malformed in [
+            "",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+        ] {
+            assert_eq!(parse_traceparent(malformed), None, "{}", malformed);
+        }
+    }
+
+    async fn get(services: &Services, path: &str, headers: &[(&str, &str)]) -> (HeaderMap, Value) {
+        let mut request = Request::get(path);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        let response = api::send(services, request.body(Body::empty()).unwrap()).await;
+        (response.headers().clone(), api::json(response).await)
+    }
+
+    #[tokio::test]
+    async fn test_ids_are_propagated_and_returned_with_errors() {
+        let services = api::sandbox().await;
+        let header = |headers: &HeaderMap, name| headers.get(name).unwrap().to_str().unwrap().to_string();
+
+        let (headers, _) = get(&services, "/deals?limit=1", &[(REQUEST_ID_HEADER, "lookup-42"), (TRACEPARENT_HEADER, PARENT)]).await;
+        assert_eq!(header(&headers, REQUEST_ID_HEADER), "lookup-42");
+        let traceparent = header(&headers, TRACEPARENT_HEADER);
+        assert!(traceparent.starts_with("00-4bf92f3577b34da6a3ce929d0e0e4736-"));
+        assert!(!traceparent.contains("00f067aa0ba902b7"));
+
+        // Missing or malformed IDs are replaced
+        let (headers, _) = get(&services, "/deals?limit=1", &[(REQUEST_ID_HEADER, ""), (TRACEPARENT_HEADER, "garbage")]).await;
+        assert!(!header(&headers, REQUEST_ID_HEADER).is_empty());
+        assert!(parse_traceparent(&header(&headers, TRACEPARENT_HEADER)).is_some());
+
+        let (_, body) = get(&services, "/admin/roles", &[(REQUEST_ID_HEADER, "lookup-43"), (TRACEPARENT_HEADER, PARENT)]).await;
+        assert_eq!(body["request_id"], "lookup-43");
+        assert_eq!(body["trace_id"], "4bf92f3577b34da6a3ce929d0e0e4736");
+        let (_, body) = get(&services, "/api/v1/admin/roles", &[(REQUEST_ID_HEADER, "lookup-44")]).await;
+        assert_eq!(body["errors"][0]["request_id"], "lookup-44");
+        assert!(body["errors"][0]["trace_id"].is_string());
+        assert!(body.get("request_id").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_scraper_fetches_carry_the_request_ids() {
+        let seen: Arc<Mutex<Option<HeaderMap>>> = Arc::default();
+        let recorded = seen.clone();
+        let merchant = Router::new().route(
+            "/deals",
+            routing::get(move |headers: HeaderMap| async move {
+                *recorded.lock().unwrap() = Some(headers);
+                "<html><body>No coupons today</body></html>"
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let page = format!("http://{}/deals", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, merchant).await.unwrap() });
+        let mut services = api::sandbox().await;
+        let config = EngineConfig {
+            retry_attempts: 1,
+            ..EngineConfig::default()
+        };
+        services.coupon_engine = Arc::new(CouponEngine::builder(config).build());
+
+        let path = format!("/coupons/for-url?url={}", page);
+        let (headers, body) = get(&services, &path, &[(REQUEST_ID_HEADER, "lookup-45"), (TRACEPARENT_HEADER, PARENT)]).await;
+        assert_eq!((body["page"]["cached"].as_bool(), body["page"]["error"].as_str()), (Some(false), None));
+        let sent = seen.lock().unwrap().take().unwrap();
+        assert_eq!(sent[REQUEST_ID_HEADER], "lookup-45");
+        // Our span is the merchant's parent, as it is the caller's child
+        assert_eq!(sent[TRACEPARENT_HEADER], headers[TRACEPARENT_HEADER]);
+        assert!(sent[TRACEPARENT_HEADER].to_str().unwrap().starts_with("00-4bf92f3577b34da6a3ce929d0e0e4736-"));
+    }
+}
@@ -0,0 +1,77 @@
+//! Cross-origin headers and preflight answers, per route (see [`crate::cors`])
+
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use tower::{service_fn, Layer, ServiceExt};
+
+use crate::cors::CorsPolicy;
+
+/// Run the request through the CORS layer of its route, answering preflights
+/// before any other layer sees them
+pub(super) async fn apply_cors(State(policy): State<Arc<CorsPolicy>>, request: Request, next: Next) -> Response {
+    let mut next = Some(next);
+    let inner = service_fn(move |request: Request| {
+        let next = next.take().expect("the CORS layer calls the inner service at most once");
+        async move { Ok::<_, Infallible>(next.run(request).await) }
+    });
+
+    match policy.layer_for(request.uri().path()).layer(inner).oneshot(request).await {
+        Ok(response) => response,
+        Err(never) => match never {},
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::http::{header, Method, Request, StatusCode};
+
+    use super::*;
+    use crate::api::tests as api;
+    use crate::app::Services;
+    use crate::cors::CorsConfig;
+
+    async fn preflight(services: &Services, path: &str, origin: &str) -> (StatusCode, Option<String>) {
+        let request = Request::builder()
+            .method(Method::OPTIONS)
+            .uri(path)
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .body(Body::empty())
+            .unwrap();
+        let response = api::send(services, request).await;
+        let allowed = response
+            .headers()
+            .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .map(|value| value.to_str().unwrap().to_string());
+        (response.status(), allowed)
+    }
+
+    #[tokio::test]
+    async fn test_extension_routes_allow_broader_origins_than_admin_routes() {
+        let mut services = api::sandbox().await;
+        let config: CorsConfig = serde_json::from_value(serde_json::json!({
+            "default": {"origins": ["https://dealmate.app"]},
+            "routes": [
+                {"prefix": "/coupons/validate", "origins": ["https://dealmate.app", "chrome-extension://*"]},
+                {"prefix": "/admin", "origins": ["https://admin.dealmate.app"]}
+            ]
+        }))
+        .unwrap();
+        services.cors = Arc::new(CorsPolicy::new(&config).unwrap());
+
+        let extension = "chrome-extension://abcdefghijklmnop";
+        assert_eq!(preflight(&services, "/api/v1/coupons/validate", extension).await.1.as_deref(), Some(extension));
+        assert_eq!(preflight(&services, "/deals", extension).await.1, None);
+        assert_eq!(preflight(&services, "/admin/backfills", "https://dealmate.app").await.1, None);
+        // Answered before the admin permission check
+        let (status, allowed) = preflight(&services, "/admin/backfills", "https://admin.dealmate.app").await;
+        assert_eq!((status, allowed.as_deref()), (StatusCode::OK, Some("https://admin.dealmate.app")));
+    }
+}
@@ -0,0 +1,538 @@
+//! Coupon listing, outcome reporting, extension results and StackSmart endpoints
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Extension, Path, Query},
+    http::StatusCode,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use super::licensing::Licensed;
+use super::reply::{ApiError, Reply};
+use super::requests::{CouponOutcome, ExtensionResult, ValidateCouponRequest};
+use super::tags::TagFilter;
+use crate::coupon_deltas::{CouponDeltas, Subscription, SubscriptionRequest};
+use crate::coupon_engine::{CouponEngine, PageCoupons, OPTED_OUT};
+use crate::coupon_engine::validator::{CouponValidation, FailureReason, ValidationFailure, Validator};
+use crate::coupon_success::features::CouponFeatures;
+use crate::coupon_success::{CouponSuccessModel, CouponSuccessPredictor, TrainingRow};
+use crate::models::coupon_listing::CouponListing;
+use crate::models::domain::{CouponCode, MerchantDomain};
+use crate::models::url::normalize;
+use crate::reputation::{ReputationService, SignalUpdate};
+use crate::stacksmart::{Cart, CartPlan, StackSmartEngine};
+use crate::storage::coupon_history::{CouponHistory, HistoricalCoupon};
+use crate::storage::coupon_store::CouponStore;
+use crate::storage::shipping_rules::ShippingRuleStore;
+use crate::tagging::{self, TagRegistry};
+use crate::tenant::TenantId;
+use crate::top_coupons::TopCoupons;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(super) struct CouponQuery {
+    merchant: Option<MerchantDomain>,
+    /// Comma-separated tags, e.g. `student-discount,app-only`; coupons have every one
+    tags: Option<String>,
+}
+
+/// Coupons the caller may receive, see `GET /coupons`
+#[derive(Serialize, ToSchema)]
+pub(super) struct CouponList {
+    coupons: Vec<CouponListing>,
+}
+
+/// Coupons with their predicted success probability, most likely to work first
+#[utoipa::path(
+    get,
+    path = "/coupons",
+    tag = "coupons",
+    params(CouponQuery),
+    responses(
+        (status = 200, description = "Coupons the caller may receive, most likely to work first", body = CouponList),
+    )
+)]
+pub(super) async fn get_coupons(
+    Extension(coupons): Extension<Arc<CouponStore>>,
+    Extension(predictor): Extension<Arc<CouponSuccessPredictor>>,
+    Extension(reputation): Extension<Arc<ReputationService>>,
+    Extension(tags): Extension<Arc<TagRegistry>>,
+    licensed: Licensed,
+    Query(params): Query<CouponQuery>,
+) -> Reply<CouponList> {
+    let mut listed = coupons.list().await;
+    if let Some(merchant) = &params.merchant {
+        listed.retain(|c| &c.merchant_domain == merchant);
+    }
+    tags.annotate_coupons(&mut listed);
+    let wanted = tagging::parse_filter(params.tags.as_deref());
+    listed.retain(|c| tagging::has_all(&c.tags, &wanted));
+    licensed.retain(&mut listed).await;
+
+    predictor.annotate(&mut listed, &reputation).await;
+    listed.sort_by(|a, b| {
+        b.predicted_success
+            .unwrap_or(0.0)
+            .total_cmp(&a.predicted_success.unwrap_or(0.0))
+    });
+
+    Reply(CouponList { coupons: listed })
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(super) struct AsOfQuery {
+    merchant: MerchantDomain,
+    timestamp: DateTime<Utc>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct CouponsAsOf {
+    merchant: MerchantDomain,
+    timestamp: DateTime<Utc>,
+    /// How many of the `coupons` had not expired
+    valid: usize,
+    coupons: Vec<HistoricalCoupon>,
+}
+
+/// A merchant's codes as they stood at a past time: every code known then, and
+/// whether it had expired, for backtesting savings and resolving disputes
+#[utoipa::path(
+    get,
+    path = "/coupons/asof",
+    tag = "coupons",
+    params(AsOfQuery),
+    responses(
+        (status = 200, description = "`coupons` known at `timestamp`, each with whether it was `valid`", body = CouponsAsOf),
+        (status = 400, description = "No history for the time asked", body = ErrorBody),
+    )
+)]
+pub(super) async fn coupons_as_of(
+    Extension(history): Extension<Arc<CouponHistory>>,
+    licensed: Licensed,
+    Query(params): Query<AsOfQuery>,
+) -> Result<Reply<CouponsAsOf>, ApiError> {
+    let known = history.as_of(&params.merchant, params.timestamp).map_err(ApiError::bad_request)?;
+    let mut coupons = Vec::with_capacity(known.len());
+    for mut coupon in known {
+        if licensed.permits(&mut coupon.listing).await {
+            coupons.push(coupon);
+        }
+    }
+    Ok(Reply(CouponsAsOf {
+        merchant: params.merchant,
+        timestamp: params.timestamp,
+        valid: coupons.iter().filter(|coupon| coupon.valid).count(),
+        coupons,
+    }))
+}
+
+/// A merchant's best unexpired codes for the extension, served from the top coupons cache
+#[utoipa::path(
+    get,
+    path = "/merchants/{domain}/coupons",
+    tag = "merchants",
+    params(("domain" = String, Path, description = "Merchant domain, e.g. `amazon.com`"), TagFilter),
+    responses(
+        (status = 200, description = "The merchant's best unexpired codes", body = CouponList),
+    )
+)]
+pub(super) async fn top_coupons(
+    Extension(top): Extension<Arc<TopCoupons>>,
+    Extension(tags): Extension<Arc<TagRegistry>>,
+    licensed: Licensed,
+    Path(domain): Path<MerchantDomain>,
+    Query(filter): Query<TagFilter>,
+) -> Reply<CouponList> {
+    let mut coupons = top.get(&domain).await.to_vec();
+    tags.annotate_coupons(&mut coupons);
+    let wanted = filter.tags();
+    coupons.retain(|c| tagging::has_all(&c.tags, &wanted));
+    licensed.retain(&mut coupons).await;
+    Reply(CouponList { coupons })
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(super) struct PageQuery {
+    /// The page the shopper is on
+    url: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct PageResult {
+    page: PageCoupons,
+}
+
+/// Coupons on the page the shopper is on, for the extension's on-page discovery.
+/// The page is scraped live, or parsed from a recently fetched copy.
+#[utoipa::path(
+    get,
+    path = "/coupons/for-url",
+    tag = "coupons",
+    params(PageQuery),
+    responses(
+        (status = 200, description = "The `page`: its `coupons`, whether it was `cached`, and the `error` if it could not be scraped", body = PageResult),
+        (status = 400, description = "Invalid URL", body = ErrorBody),
+        (status = 451, description = "The merchant opted out of scraping", body = ErrorBody),
+    )
+)]
+pub(super) async fn coupons_for_url(
+    Extension(engine): Extension<Arc<CouponEngine>>,
+    Query(query): Query<PageQuery>,
+) -> Result<Reply<PageResult>, ApiError> {
+    let url = normalize(&query.url).map_err(ApiError::bad_request)?;
+    let page = engine.process_url(&url).await;
+    if page.result.error.as_deref() == Some(OPTED_OUT) {
+        return Err(ApiError::new(StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS, OPTED_OUT));
+    }
+    Ok(Reply(PageResult { page }))
+}
+
+/// Attempts one [`ExtensionResult`] may report
+const MAX_EXTENSION_ATTEMPTS: usize = 50;
+
+/// Feed one checkout result to the merchant's reputation, the code's stats and the
+/// success model's training data. The caller marks the merchant's top coupons stale.
+async fn apply_outcome(
+    coupon: &CouponListing,
+    worked: bool,
+    predictor: &CouponSuccessPredictor,
+    reputation: &ReputationService,
+) {
+    // Snapshot features before this outcome updates the merchant's track record
+    let features = predictor.features(coupon, reputation).await;
+    reputation
+        .record_signals(
+            &coupon.merchant_domain,
+            SignalUpdate {
+                coupon_successes: worked as u32,
+                coupon_failures: !worked as u32,
+                ..Default::default()
+            },
+        )
+        .await;
+    predictor.record_outcome(coupon, features, worked).await;
+}
+
+/// Result of trying a code at checkout; feeds merchant reputation and the success model
+#[utoipa::path(
+    post,
+    path = "/coupons/outcomes",
+    tag = "coupons",
+    request_body = CouponOutcome,
+    responses(
+        (status = 202, description = "Recorded"),
+        (status = 404, description = "The catalogue does not list the code"),
+    )
+)]
+pub(super) async fn record_coupon_outcome(
+    Extension(coupons): Extension<Arc<CouponStore>>,
+    Extension(predictor): Extension<Arc<CouponSuccessPredictor>>,
+    Extension(reputation): Extension<Arc<ReputationService>>,
+    Extension(top): Extension<Arc<TopCoupons>>,
+    Json(outcome): Json<CouponOutcome>,
+) -> Result<StatusCode, StatusCode> {
+    let coupon = coupons
+        .find(&outcome.merchant_domain, &outcome.code)
+        .await
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    apply_outcome(&coupon, outcome.worked, &predictor, &reputation).await;
+    top.mark_stale(&coupon.merchant_domain);
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct RecordedAttempts {
+    recorded: usize,
+    unknown_codes: Vec<CouponCode>,
+}
+
+/// Every code the extension applied at one checkout. Each result counts like a
+/// reported outcome, and the merchant's top coupons are re-ranked with them.
+/// Codes the catalogue does not list are skipped and returned as `unknown_codes`.
+#[utoipa::path(
+    post,
+    path = "/extension/result",
+    tag = "coupons",
+    request_body = ExtensionResult,
+    responses(
+        (status = 202, description = "`recorded` attempts and the `unknown_codes` skipped", body = RecordedAttempts),
+        (status = 400, description = "No attempts, or too many", body = ErrorBody),
+    )
+)]
+pub(super) async fn record_extension_result(
+    Extension(coupons): Extension<Arc<CouponStore>>,
+    Extension(predictor): Extension<Arc<CouponSuccessPredictor>>,
+    Extension(reputation): Extension<Arc<ReputationService>>,
+    Extension(top): Extension<Arc<TopCoupons>>,
+    Json(result): Json<ExtensionResult>,
+) -> Result<(StatusCode, Reply<RecordedAttempts>), ApiError> {
+    if result.attempts.is_empty() || result.attempts.len() > MAX_EXTENSION_ATTEMPTS {
+        return Err(ApiError::bad_request(format!("expected 1 to {} attempts", MAX_EXTENSION_ATTEMPTS)));
+    }
+
+    let mut recorded = 0;
+    let mut unknown_codes = Vec::new();
+    for attempt in &result.attempts {
+        match coupons.find(&result.merchant_domain, &attempt.code).await {
+            Some(coupon) => {
+                apply_outcome(&coupon, attempt.worked, &predictor, &reputation).await;
+                recorded += 1;
+            }
+            None => unknown_codes.push(attempt.code.clone()),
+        }
+    }
+    if recorded > 0 {
+        top.mark_stale(&result.merchant_domain);
+    }
+
+    Ok((StatusCode::ACCEPTED, Reply(RecordedAttempts { recorded, unknown_codes })))
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct CouponSubscription {
+    subscription: Subscription,
+}
+
+/// Subscribe to changes of merchants' best coupon codes
+#[utoipa::path(
+    post,
+    path = "/coupons/subscriptions",
+    tag = "coupons",
+    request_body = SubscriptionRequest,
+    responses(
+        (status = 201, description = "The new `subscription`", body = CouponSubscription),
+        (status = 400, description = "Invalid merchants or webhook URL", body = ErrorBody),
+    )
+)]
+pub(super) async fn create_coupon_subscription(
+    Extension(deltas): Extension<Arc<CouponDeltas>>,
+    tenant: TenantId,
+    Json(request): Json<SubscriptionRequest>,
+) -> Result<(StatusCode, Reply<CouponSubscription>), ApiError> {
+    match deltas.subscribe(&tenant.0, request).await {
+        Ok(subscription) => Ok((StatusCode::CREATED, Reply(CouponSubscription { subscription }))),
+        Err(e) => Err(ApiError::bad_request(e)),
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct CouponSubscriptions {
+    subscriptions: Vec<Subscription>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/coupons/subscriptions",
+    tag = "coupons",
+    responses(
+        (status = 200, description = "The tenant's `subscriptions`", body = CouponSubscriptions),
+    )
+)]
+pub(super) async fn list_coupon_subscriptions(
+    Extension(deltas): Extension<Arc<CouponDeltas>>,
+    tenant: TenantId,
+) -> Reply<CouponSubscriptions> {
+    Reply(CouponSubscriptions {
+        subscriptions: deltas.subscriptions(&tenant.0).await,
+    })
+}
+
+#[utoipa::path(
+    delete,
+    path = "/coupons/subscriptions/{id}",
+    tag = "coupons",
+    params(("id" = Uuid, Path, description = "Subscription id")),
+    responses(
+        (status = 204, description = "Unsubscribed"),
+        (status = 404, description = "No such subscription"),
+    )
+)]
+pub(super) async fn delete_coupon_subscription(
+    Extension(deltas): Extension<Arc<CouponDeltas>>,
+    tenant: TenantId,
+    Path(id): Path<Uuid>,
+) -> StatusCode {
+    match deltas.unsubscribe(&tenant.0, id).await {
+        true => StatusCode::NO_CONTENT,
+        false => StatusCode::NOT_FOUND,
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct CouponModel {
+    model: CouponSuccessModel,
+    feature_names: [&'static str; 5],
+}
+
+#[utoipa::path(
+    get,
+    path = "/coupons/model",
+    tag = "coupons",
+    responses(
+        (status = 200, description = "The coupon success `model` and its `feature_names`", body = CouponModel),
+    )
+)]
+pub(super) async fn coupon_model(Extension(predictor): Extension<Arc<CouponSuccessPredictor>>) -> Reply<CouponModel> {
+    Reply(CouponModel {
+        model: predictor.model().await,
+        feature_names: CouponFeatures::NAMES,
+    })
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct TrainingData {
+    feature_names: [&'static str; 5],
+    rows: Vec<TrainingRow>,
+    model_version: String,
+}
+
+/// Labelled checkout outcomes for the offline coupon model trainer
+#[utoipa::path(
+    get,
+    path = "/coupons/model/training-data",
+    tag = "coupons",
+    responses(
+        (status = 200, description = "Labelled `rows` for the model trainer", body = TrainingData),
+    )
+)]
+pub(super) async fn export_coupon_training_data(
+    Extension(predictor): Extension<Arc<CouponSuccessPredictor>>,
+) -> Reply<TrainingData> {
+    Reply(TrainingData {
+        feature_names: CouponFeatures::NAMES,
+        rows: predictor.training_data().await,
+        model_version: predictor.model().await.version,
+    })
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct CouponTest {
+    valid: bool,
+    discount: u32,
+    message: &'static str,
+}
+
+#[utoipa::path(
+    post,
+    path = "/coupons/test",
+    tag = "coupons",
+    responses(
+        (status = 200, description = "A canned test result", body = CouponTest),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+    ),
+    security(("user_jwt" = []), ("user_jwt" = [], "api_key" = []))
+)]
+pub(super) async fn test_coupons() -> Reply<CouponTest> {
+    Reply(CouponTest {
+        valid: true,
+        discount: 20,
+        message: "Coupon tested by Deal Service",
+    })
+}
+
+/// Check a shopper's code against the catalogue: its format, whether the merchant
+/// issued it, its expiry and its minimum order. Callers whose license does not
+/// cover the code get the verdict without the listing.
+#[utoipa::path(
+    post,
+    path = "/coupons/validate",
+    tag = "coupons",
+    request_body = ValidateCouponRequest,
+    responses(
+        (status = 200, description = "Whether the code is `valid`, the `failures`, the `coupon` and the estimated `discount`", body = CouponValidation),
+    )
+)]
+pub(super) async fn validate_coupon(
+    Extension(store): Extension<Arc<CouponStore>>,
+    licensed: Licensed,
+    Json(request): Json<ValidateCouponRequest>,
+) -> Reply<CouponValidation> {
+    let validator = Validator::new();
+    let (coupon, failures) = match check_coupon(&validator, &store, &request).await {
+        Ok(coupon) => {
+            let failures = validator.check_listing(&coupon, request.order_total);
+            (Some(coupon), failures)
+        }
+        Err(failure) => (None, vec![failure]),
+    };
+    let discount = match (&coupon, request.order_total) {
+        (Some(coupon), Some(total)) if failures.is_empty() => estimated_discount(coupon, total),
+        _ => None,
+    };
+
+    let coupon = licensed.filter(coupon).await;
+
+    Reply(CouponValidation {
+        valid: failures.is_empty(),
+        failures,
+        coupon,
+        discount,
+    })
+}
+
+/// The catalogue's listing for the requested code
+async fn check_coupon(validator: &Validator, store: &CouponStore, request: &ValidateCouponRequest) -> Result<CouponListing, ValidationFailure> {
+    let merchant = MerchantDomain::parse(&request.merchant_domain).map_err(|e| ValidationFailure::new(FailureReason::InvalidMerchant, e))?;
+    let code = validator.check_format(&request.code)?;
+    if let Some(coupon) = store.find(&merchant, &code).await {
+        return Ok(coupon);
+    }
+    Err(match store.for_merchant(&merchant).await.is_empty() {
+        true => ValidationFailure::new(FailureReason::UnknownMerchant, format!("no coupons are known for {}", merchant)),
+        false => ValidationFailure::new(FailureReason::UnknownCode, format!("{} did not issue {}", merchant, code)),
+    })
+}
+
+/// What the code takes off an order of `total`, for discounts with a known amount
+pub(super) fn estimated_discount(coupon: &CouponListing, total: Decimal) -> Option<Decimal> {
+    let value = Decimal::try_from(coupon.discount_value?).ok()?;
+    let discount = match coupon.discount_type.as_str() {
+        "percentage" => total * value / Decimal::ONE_HUNDRED,
+        "fixed" => value.min(total),
+        _ => return None,
+    };
+    Some(discount.round_dp(2))
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct OptimizedCart {
+    plan: CartPlan,
+    message: &'static str,
+}
+
+/// Cheapest combination of a cart's coupons, counting shipping and cashback
+#[utoipa::path(
+    post,
+    path = "/stacksmart",
+    tag = "coupons",
+    request_body = Cart,
+    responses(
+        (status = 200, description = "The cheapest `plan` for the cart", body = OptimizedCart),
+        (status = 400, description = "Invalid cart", body = ErrorBody),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+    ),
+    security(("user_jwt" = []), ("user_jwt" = [], "api_key" = []))
+)]
+pub(super) async fn optimize_deals(
+    Extension(engine): Extension<Arc<StackSmartEngine>>,
+    Extension(shipping_rules): Extension<Arc<ShippingRuleStore>>,
+    Json(cart): Json<Cart>,
+) -> Result<Reply<OptimizedCart>, ApiError> {
+    cart.validate().map_err(ApiError::bad_request)?;
+    let shipping = shipping_rules.get(&cart.merchant).await;
+
+    Ok(Reply(OptimizedCart {
+        plan: engine.optimize_cart(&cart, shipping.as_ref()),
+        message: "StackSmart optimization by Deal Service",
+    }))
+}
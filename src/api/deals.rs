@@ -0,0 +1,625 @@
+//! Deal listing, search, recommendation and community endpoints
+
+use std::sync::Arc;
+
+use axum::{
+    body::Body,
+    extract::{Extension, Path, Query},
+    http::StatusCode,
+    Json,
+};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use super::reply::{ApiError, Reply};
+use super::tags::TagFilter;
+use crate::community::{CommunityService, CommunitySummary, IngestReport};
+use crate::experiments::{ExperimentService, ExperimentSubject, RankingStrategy};
+use crate::models::comment::CommunityComment;
+use crate::models::deal::Deal;
+use crate::models::experiment::ExperimentAssignment;
+use crate::models::interaction::Interaction;
+use crate::pricing::rewards::{EffectivePrice, RewardsValuator};
+use crate::pricing::verification::PriceVerifier;
+use crate::privacy::{Scrubber, COMMENT_AUTHOR, COMMENT_BODY};
+use crate::recommendations::RecommendationService;
+use crate::scoring::features::DealFeatures;
+use crate::scoring::{DealScorer, FeatureRow};
+use crate::search::facets::{compute_facets, Facets};
+use crate::search::query::{ParsedQuery, SearchFilters};
+use crate::search::{DealSearch, SearchHit};
+use crate::services::dedup::{find_duplicates, DuplicatePair};
+use crate::services::ranking::RankingPipeline;
+use crate::storage::deal_store::DealStore;
+use crate::storage::import::{import_ndjson, ImportError, ImportLimits, ImportReport};
+use crate::stream::DealStream;
+use crate::tagging;
+use crate::tenant::{TenantId, TenantRegistry};
+
+/// Deals per page unless `limit` says otherwise
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 200;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(super) struct DealsQuery {
+    limit: Option<usize>,
+    #[serde(default)]
+    offset: usize,
+    /// An explicit order, e.g. `honest_discount` or `newest`, instead of the
+    /// caller's experiment variant
+    sort: Option<RankingStrategy>,
+    /// Comma-separated tags, e.g. `student-discount,app-only`; deals have every one
+    tags: Option<String>,
+}
+
+/// One page of deals, see `GET /deals`
+#[derive(Serialize, ToSchema)]
+pub(super) struct DealsPage {
+    deals: Vec<Deal>,
+    total: usize,
+    offset: usize,
+    limit: usize,
+    /// The ranking variant the page was served with; echo it in interactions
+    experiment: Option<ExperimentAssignment>,
+}
+
+/// One page of the catalogue, ranked by the caller's experiment variant unless
+/// `sort` picks the order
+#[utoipa::path(
+    get,
+    path = "/deals",
+    tag = "deals",
+    params(DealsQuery),
+    responses(
+        (status = 200, description = "One page of ranked deals", body = DealsPage),
+    )
+)]
+pub(super) async fn get_deals(
+    Extension(ranking): Extension<Arc<RankingPipeline>>,
+    Extension(experiments): Extension<Arc<ExperimentService>>,
+    tenant: TenantId,
+    subject: ExperimentSubject,
+    Query(params): Query<DealsQuery>,
+) -> Reply<DealsPage> {
+    let (strategy, assignment) = match params.sort {
+        Some(sort) => (sort, None),
+        None => experiments.assign(&subject).await,
+    };
+    let mut ranked = ranking.ranked(&tenant.0, strategy).await;
+    let tags = tagging::parse_filter(params.tags.as_deref());
+    ranked.retain(|deal| tagging::has_all(&deal.tags, &tags));
+    let total = ranked.len();
+    let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let deals: Vec<_> = ranked.into_iter().skip(params.offset).take(limit).collect();
+    if let Some(assignment) = &assignment {
+        experiments.record_exposure(assignment, deals.len()).await;
+    }
+
+    Reply(DealsPage {
+        deals,
+        total,
+        offset: params.offset,
+        limit,
+        experiment: assignment,
+    })
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(super) struct SearchQuery {
+    #[serde(default)]
+    q: String,
+    limit: Option<usize>,
+    #[serde(default)]
+    offset: usize,
+    category: Option<String>,
+    store: Option<String>,
+    min_discount: Option<f64>,
+    min_price: Option<Decimal>,
+    max_price: Option<Decimal>,
+    /// Comma-separated tags, all required; `#tag` in `q` works too
+    tags: Option<String>,
+}
+
+impl SearchQuery {
+    fn filters(&self) -> SearchFilters {
+        SearchFilters {
+            category: self.category.clone(),
+            store: self.store.clone(),
+            min_discount: self.min_discount,
+            min_price: self.min_price,
+            max_price: self.max_price,
+            tags: self.tags.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct SearchResults {
+    results: Vec<SearchHit>,
+    total: usize,
+    offset: usize,
+    facets: Facets,
+    query: String,
+    interpreted: ParsedQuery,
+    experiment: Option<ExperimentAssignment>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/deals/search",
+    tag = "deals",
+    params(SearchQuery),
+    responses(
+        (status = 200, description = "`results` with their matching terms, `facets`, `total` and the `interpreted` query", body = SearchResults),
+    )
+)]
+pub(super) async fn search_deals(
+    Extension(ranking): Extension<Arc<RankingPipeline>>,
+    Extension(search): Extension<Arc<DealSearch>>,
+    Extension(experiments): Extension<Arc<ExperimentService>>,
+    tenant: TenantId,
+    subject: ExperimentSubject,
+    Query(params): Query<SearchQuery>,
+) -> Reply<SearchResults> {
+    let (strategy, assignment) = experiments.assign(&subject).await;
+    let deals = ranking.ranked(&tenant.0, strategy).await;
+    let (interpreted, results) = search.search_filtered(&params.q, &params.filters(), deals);
+    let facets = compute_facets(results.iter().map(|hit| &hit.deal));
+    let total = results.len();
+    let results: Vec<_> = results.into_iter().skip(params.offset).take(params.limit.unwrap_or(20).min(100)).collect();
+    if let Some(assignment) = &assignment {
+        experiments.record_exposure(assignment, results.len()).await;
+    }
+
+    Reply(SearchResults {
+        results,
+        total,
+        offset: params.offset,
+        facets,
+        query: params.q,
+        interpreted,
+        experiment: assignment,
+    })
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct SearchFacets {
+    facets: Facets,
+    total: usize,
+    query: String,
+    interpreted: ParsedQuery,
+}
+
+/// Filter sidebar counts for the deals matching `q`
+#[utoipa::path(
+    get,
+    path = "/deals/facets",
+    tag = "deals",
+    params(SearchQuery),
+    responses(
+        (status = 200, description = "`facets`: counts by category, store, price and discount", body = SearchFacets),
+    )
+)]
+pub(super) async fn deal_facets(
+    Extension(ranking): Extension<Arc<RankingPipeline>>,
+    Extension(search): Extension<Arc<DealSearch>>,
+    tenant: TenantId,
+    Query(params): Query<SearchQuery>,
+) -> Reply<SearchFacets> {
+    // Order does not matter for counts
+    let deals = ranking.ranked(&tenant.0, RankingStrategy::default()).await;
+    let (interpreted, results) = search.search_filtered(&params.q, &params.filters(), deals);
+
+    Reply(SearchFacets {
+        facets: compute_facets(results.iter().map(|hit| &hit.deal)),
+        total: results.len(),
+        query: params.q,
+        interpreted,
+    })
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct TrendingDeals {
+    trending: Vec<Deal>,
+    model_version: String,
+    experiment: Option<ExperimentAssignment>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/deals/trending",
+    tag = "deals",
+    params(TagFilter),
+    responses(
+        (status = 200, description = "`deals`: the most engaged-with deals", body = TrendingDeals),
+    )
+)]
+pub(super) async fn trending_deals(
+    Extension(ranking): Extension<Arc<RankingPipeline>>,
+    Extension(experiments): Extension<Arc<ExperimentService>>,
+    tenant: TenantId,
+    subject: ExperimentSubject,
+    Query(filter): Query<TagFilter>,
+) -> Reply<TrendingDeals> {
+    let (strategy, assignment) = experiments.assign(&subject).await;
+    let mut trending = ranking.ranked(&tenant.0, strategy).await;
+    let tags = filter.tags();
+    trending.retain(|deal| tagging::has_all(&deal.tags, &tags));
+    trending.truncate(10);
+    if let Some(assignment) = &assignment {
+        experiments.record_exposure(assignment, trending.len()).await;
+    }
+
+    Reply(TrendingDeals {
+        trending,
+        model_version: ranking.model_version().to_string(),
+        experiment: assignment,
+    })
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct DealFeatureExport {
+    feature_names: [&'static str; 4],
+    rows: Vec<FeatureRow>,
+    model_version: String,
+}
+
+/// Feature export consumed by the offline scoring-model trainer
+#[utoipa::path(
+    get,
+    path = "/deals/features",
+    tag = "deals",
+    responses(
+        (status = 200, description = "`rows` of deal features and `feature_names`", body = DealFeatureExport),
+    )
+)]
+pub(super) async fn export_deal_features(
+    Extension(store): Extension<Arc<DealStore>>,
+    Extension(scorer): Extension<Arc<DealScorer>>,
+) -> Reply<DealFeatureExport> {
+    let deals = store.list().await;
+    let rows = scorer.export_features(&store, &deals).await;
+
+    Reply(DealFeatureExport {
+        feature_names: DealFeatures::NAMES,
+        rows,
+        model_version: scorer.model_version().to_string(),
+    })
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct DealImport {
+    import: ImportReport,
+}
+
+/// Bulk NDJSON feed from partners, one deal per line.
+///
+/// The body may be gzip or zstd encoded; it is decoded and imported as it streams in.
+#[utoipa::path(
+    post,
+    path = "/deals/import",
+    tag = "deals",
+    request_body(content = String, content_type = "application/x-ndjson", description = "One deal per line; may be gzip or zstd encoded"),
+    responses(
+        (status = 200, description = "How many deals were imported, how many are held off the stream until their price is verified, and the lines that were rejected", body = DealImport),
+        (status = 400, description = "The feed could not be read", body = ErrorBody),
+        (status = 413, description = "The feed is over the import limits", body = ErrorBody),
+    )
+)]
+pub(super) async fn import_deals(
+    Extension(store): Extension<Arc<DealStore>>,
+    Extension(limits): Extension<Arc<ImportLimits>>,
+    Extension(deal_stream): Extension<Arc<DealStream>>,
+    Extension(verifier): Extension<Arc<PriceVerifier>>,
+    body: Body,
+) -> Result<Reply<DealImport>, ApiError> {
+    match import_ndjson(&store, body.into_data_stream(), &limits, &deal_stream, &verifier).await {
+        Ok(report) => Ok(Reply(DealImport { import: report })),
+        Err(ImportError::TooLarge { reason, report }) => {
+            Err(ApiError::new(StatusCode::PAYLOAD_TOO_LARGE, reason).with("import", report))
+        }
+        Err(ImportError::Body(e)) => Err(ApiError::bad_request(e)),
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct DuplicateDeals {
+    duplicates: Vec<DuplicatePair>,
+}
+
+/// Listings that look like re-posts of an earlier deal, by title or product image
+#[utoipa::path(
+    get,
+    path = "/deals/duplicates",
+    tag = "deals",
+    responses(
+        (status = 200, description = "`duplicates`: groups of deals that look like re-posts", body = DuplicateDeals),
+    )
+)]
+pub(super) async fn duplicate_deals(Extension(store): Extension<Arc<DealStore>>) -> Reply<DuplicateDeals> {
+    let deals = store.list().await;
+
+    Reply(DuplicateDeals {
+        duplicates: find_duplicates(&deals),
+    })
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(super) struct RecommendationQuery {
+    limit: Option<usize>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/deals/interactions",
+    tag = "deals",
+    request_body = Interaction,
+    responses(
+        (status = 202, description = "Recorded"),
+    )
+)]
+pub(super) async fn record_interaction(
+    Extension(recommendations): Extension<Arc<RecommendationService>>,
+    Extension(experiments): Extension<Arc<ExperimentService>>,
+    subject: ExperimentSubject,
+    Json(mut interaction): Json<Interaction>,
+) -> StatusCode {
+    if interaction.experiment.is_none() {
+        interaction.experiment = experiments.assign(&subject).await.1;
+    }
+    if let Some(assignment) = &interaction.experiment {
+        experiments.record_interaction(assignment, interaction.kind).await;
+    }
+
+    recommendations.record_interaction(interaction).await;
+    StatusCode::ACCEPTED
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct SimilarDeals {
+    deal_id: String,
+    similar: Vec<SimilarDeal>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct SimilarDeal {
+    deal: Deal,
+    similarity: f32,
+}
+
+#[utoipa::path(
+    get,
+    path = "/deals/{id}/similar",
+    tag = "deals",
+    params(
+        ("id" = String, Path, description = "Deal id"),
+        RecommendationQuery,
+    ),
+    responses(
+        (status = 200, description = "`deals` similar to this one", body = SimilarDeals),
+        (status = 404, description = "Unknown deal"),
+    )
+)]
+pub(super) async fn similar_deals(
+    Extension(store): Extension<Arc<DealStore>>,
+    Extension(recommendations): Extension<Arc<RecommendationService>>,
+    Path(deal_id): Path<String>,
+    Query(params): Query<RecommendationQuery>,
+) -> Result<Reply<SimilarDeals>, StatusCode> {
+    store.get(&deal_id).await.ok_or(StatusCode::NOT_FOUND)?;
+
+    let mut similar = Vec::new();
+    for (id, similarity) in recommendations.similar(&deal_id, params.limit.unwrap_or(10).min(50)).await {
+        if let Some(deal) = store.get(&id).await {
+            similar.push(SimilarDeal { deal, similarity });
+        }
+    }
+
+    Ok(Reply(SimilarDeals { deal_id, similar }))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(super) struct EffectivePriceQuery {
+    #[serde(default)]
+    redeem_points: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct DealPricing {
+    deal_id: String,
+    pricing: EffectivePrice,
+}
+
+/// The deal's price after the best gift card and the merchant's loyalty points
+#[utoipa::path(
+    get,
+    path = "/deals/{id}/effective-price",
+    tag = "deals",
+    params(
+        ("id" = String, Path, description = "Deal id"),
+        EffectivePriceQuery,
+    ),
+    responses(
+        (status = 200, description = "`effective_price` after gift cards and points", body = DealPricing),
+        (status = 404, description = "Unknown deal"),
+    )
+)]
+pub(super) async fn effective_price(
+    Extension(store): Extension<Arc<DealStore>>,
+    Extension(rewards): Extension<Arc<RewardsValuator>>,
+    Path(deal_id): Path<String>,
+    Query(params): Query<EffectivePriceQuery>,
+) -> Result<Reply<DealPricing>, StatusCode> {
+    let deal = store.get(&deal_id).await.ok_or(StatusCode::NOT_FOUND)?;
+
+    Ok(Reply(DealPricing {
+        pricing: rewards.effective_price(&deal.merchant_domain, deal.price, params.redeem_points),
+        deal_id,
+    }))
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct BoughtTogether {
+    deal_id: String,
+    frequently_bought_with: Vec<BoughtWith>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct BoughtWith {
+    deal: Deal,
+    strength: f64,
+}
+
+#[utoipa::path(
+    get,
+    path = "/deals/{id}/frequently-bought-with",
+    tag = "deals",
+    params(
+        ("id" = String, Path, description = "Deal id"),
+        RecommendationQuery,
+    ),
+    responses(
+        (status = 200, description = "`deals` bought in the same sessions", body = BoughtTogether),
+        (status = 404, description = "Unknown deal"),
+    )
+)]
+pub(super) async fn frequently_bought_with(
+    Extension(store): Extension<Arc<DealStore>>,
+    Extension(recommendations): Extension<Arc<RecommendationService>>,
+    Path(deal_id): Path<String>,
+    Query(params): Query<RecommendationQuery>,
+) -> Result<Reply<BoughtTogether>, StatusCode> {
+    store.get(&deal_id).await.ok_or(StatusCode::NOT_FOUND)?;
+
+    let mut items = Vec::new();
+    for (id, strength) in recommendations.frequently_bought_with(&deal_id, params.limit.unwrap_or(10).min(50)).await {
+        if let Some(deal) = store.get(&id).await {
+            items.push(BoughtWith { deal, strength });
+        }
+    }
+
+    Ok(Reply(BoughtTogether {
+        deal_id,
+        frequently_bought_with: items,
+    }))
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct IngestedComments {
+    report: IngestReport,
+}
+
+/// Ingest a batch of community comments and re-annotate the deals they reference
+#[utoipa::path(
+    post,
+    path = "/deals/comments",
+    tag = "deals",
+    request_body = Vec<CommunityComment>,
+    responses(
+        (status = 200, description = "How many comments were ingested and the deals re-annotated", body = IngestedComments),
+    )
+)]
+pub(super) async fn ingest_comments(
+    Extension(store): Extension<Arc<DealStore>>,
+    Extension(community): Extension<Arc<CommunityService>>,
+    Extension(tenants): Extension<Arc<TenantRegistry>>,
+    Extension(scrubber): Extension<Arc<Scrubber>>,
+    tenant: TenantId,
+    Json(mut comments): Json<Vec<CommunityComment>>,
+) -> Reply<IngestedComments> {
+    let policy = tenants.pii_policy(&tenant);
+    let fields = comments
+        .iter_mut()
+        .flat_map(|comment| {
+            let author = comment.author.as_mut().map(|text| (COMMENT_AUTHOR, text));
+            std::iter::once((COMMENT_BODY, &mut comment.body)).chain(author)
+        })
+        .collect();
+    scrubber.scrub(&tenant, &policy, fields).await;
+
+    let report = community.ingest(&store, comments).await;
+
+    Reply(IngestedComments { report })
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct DealCommunity {
+    community: CommunitySummary,
+}
+
+#[utoipa::path(
+    get,
+    path = "/deals/{id}/community",
+    tag = "deals",
+    params(("id" = String, Path, description = "Deal id")),
+    responses(
+        (status = 200, description = "`summary` of the deal's community sentiment and status", body = DealCommunity),
+        (status = 404, description = "No comments about the deal"),
+    )
+)]
+pub(super) async fn community_summary(
+    Extension(community): Extension<Arc<CommunityService>>,
+    Path(deal_id): Path<String>,
+) -> Result<Reply<DealCommunity>, StatusCode> {
+    let summary = community.summary(&deal_id).await.ok_or(StatusCode::NOT_FOUND)?;
+
+    Ok(Reply(DealCommunity { community: summary }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::tests as api;
+
+    #[tokio::test]
+    async fn test_pages_are_bounded_and_past_the_end_is_empty() {
+        let services = api::sandbox().await;
+
+        let (status, body) = api::get(&services, "/deals").await;
+        assert_eq!(status, 200);
+        assert_eq!(body["limit"], 50);
+        let total = body["total"].as_u64().unwrap() as usize;
+        assert_eq!(body["deals"].as_array().unwrap().len(), total.min(50));
+        assert_eq!(api::get(&services, "/deals?limit=500").await.1["limit"], 200);
+        assert_eq!(api::get(&services, "/deals?limit=0").await.1["limit"], 1);
+
+        let (status, body) = api::get(&services, &format!("/deals?offset={}", total + 10)).await;
+        assert_eq!(status, 200);
+        assert_eq!(body["deals"], serde_json::json!([]));
+        assert_eq!(body["total"], total);
+    }
+
+    #[tokio::test]
+    async fn test_sort_picks_the_order_over_the_experiment() {
+        let services = api::sandbox().await;
+
+        for sort in ["scored", "scored_without_events", "honest_discount", "newest"] {
+            let (status, body) = api::get(&services, &format!("/deals?limit=200&sort={}", sort)).await;
+            assert_eq!(status, 200, "{}", sort);
+            assert!(body["experiment"].is_null(), "{}", sort);
+            assert!(!body["deals"].as_array().unwrap().is_empty(), "{}", sort);
+        }
+        let (_, body) = api::get(&services, "/deals?limit=200&sort=newest").await;
+        let posted: Vec<&str> = body["deals"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|deal| deal["posted_at"].as_str().unwrap())
+            .collect();
+        assert!(posted.windows(2).all(|pair| pair[0] >= pair[1]));
+        let (_, body) = api::get(&services, "/deals?limit=200&sort=honest_discount").await;
+        let discounts: Vec<f64> = body["deals"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|deal| deal["honest_discount"].as_f64().or_else(|| deal["discount"].as_f64()).unwrap())
+            .collect();
+        assert!(discounts.windows(2).all(|pair| pair[0] >= pair[1]));
+
+        assert_eq!(api::get(&services, "/deals?sort=cheapest").await.0, 400);
+    }
+}
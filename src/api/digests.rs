@@ -0,0 +1,163 @@
+//! Daily digest and scheduled digest subscriptions
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Extension, Path},
+    http::StatusCode,
+    Json,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::licensing::Licensed;
+use super::reply::{ApiError, Reply};
+use crate::digest::scheduled::{
+    DigestScheduler, DigestSubscription, DigestSubscriptionRequest, RenderedDigest, ScheduledDigest,
+};
+use crate::digest::{DailyDigest, DigestService};
+use crate::experiments::RankingStrategy;
+use crate::services::ranking::RankingPipeline;
+use crate::storage::coupon_store::CouponStore;
+use crate::storage::deal_store::DealStore;
+use crate::tenant::{TenantId, DEFAULT_TENANT};
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct Daily {
+    digest: DailyDigest,
+}
+
+#[utoipa::path(
+    get,
+    path = "/digests/daily",
+    tag = "digests",
+    responses(
+        (status = 200, description = "Today's `digest`", body = Daily),
+    )
+)]
+pub(super) async fn daily_digest(
+    Extension(ranking): Extension<Arc<RankingPipeline>>,
+    Extension(digests): Extension<Arc<DigestService>>,
+) -> Reply<Daily> {
+    Reply(Daily {
+        digest: digests.daily(&ranking).await,
+    })
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct ScheduledSubscription {
+    subscription: DigestSubscription,
+}
+
+/// Subscribe a user or channel to a daily or weekly digest
+#[utoipa::path(
+    post,
+    path = "/digests/subscriptions",
+    tag = "digests",
+    request_body = DigestSubscriptionRequest,
+    responses(
+        (status = 201, description = "The new `subscription`", body = ScheduledSubscription),
+        (status = 400, description = "Invalid subscription", body = ErrorBody),
+    )
+)]
+pub(super) async fn create_digest_subscription(
+    Extension(scheduler): Extension<Arc<DigestScheduler>>,
+    tenant: TenantId,
+    Json(request): Json<DigestSubscriptionRequest>,
+) -> Result<(StatusCode, Reply<ScheduledSubscription>), ApiError> {
+    match scheduler.subscribe(&tenant.0, request).await {
+        Ok(subscription) => Ok((StatusCode::CREATED, Reply(ScheduledSubscription { subscription }))),
+        Err(e) => Err(ApiError::bad_request(e)),
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct DigestSubscriptions {
+    subscriptions: Vec<DigestSubscription>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/digests/subscriptions",
+    tag = "digests",
+    responses(
+        (status = 200, description = "The tenant's `subscriptions`", body = DigestSubscriptions),
+    )
+)]
+pub(super) async fn list_digest_subscriptions(
+    Extension(scheduler): Extension<Arc<DigestScheduler>>,
+    tenant: TenantId,
+) -> Reply<DigestSubscriptions> {
+    Reply(DigestSubscriptions {
+        subscriptions: scheduler.subscriptions(&tenant.0).await,
+    })
+}
+
+#[utoipa::path(
+    delete,
+    path = "/digests/subscriptions/{id}",
+    tag = "digests",
+    params(("id" = Uuid, Path, description = "Subscription id")),
+    responses(
+        (status = 204, description = "Unsubscribed"),
+        (status = 404, description = "No such subscription"),
+    )
+)]
+pub(super) async fn delete_digest_subscription(
+    Extension(scheduler): Extension<Arc<DigestScheduler>>,
+    tenant: TenantId,
+    Path(id): Path<Uuid>,
+) -> StatusCode {
+    match scheduler.unsubscribe(&tenant.0, id).await {
+        true => StatusCode::NO_CONTENT,
+        false => StatusCode::NOT_FOUND,
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct DigestPreview {
+    digest: ScheduledDigest,
+    rendered: RenderedDigest,
+}
+
+/// The digest the subscription would get now, rendered but not sent, without the
+/// coupons the caller's license does not cover
+#[utoipa::path(
+    get,
+    path = "/digests/subscriptions/{id}/preview",
+    tag = "digests",
+    params(("id" = Uuid, Path, description = "Subscription id")),
+    responses(
+        (status = 200, description = "The rendered `digest`", body = DigestPreview),
+        (status = 404, description = "No such subscription", body = ErrorBody),
+    )
+)]
+pub(super) async fn preview_digest(
+    Extension(scheduler): Extension<Arc<DigestScheduler>>,
+    Extension(ranking): Extension<Arc<RankingPipeline>>,
+    Extension(deals): Extension<Arc<DealStore>>,
+    Extension(coupons): Extension<Arc<CouponStore>>,
+    licensed: Licensed,
+    tenant: TenantId,
+    Path(id): Path<Uuid>,
+) -> Result<Reply<DigestPreview>, ApiError> {
+    let ranked = ranking.ranked(DEFAULT_TENANT, RankingStrategy::ScoredWithoutEvents).await;
+    let Some(mut digest) = scheduler.preview(&tenant.0, id, &ranked, &deals, &coupons).await else {
+        return Err(ApiError::not_found("digest subscription not found"));
+    };
+    let mut expiring = Vec::with_capacity(digest.expiring_coupons.len());
+    for coupon in digest.expiring_coupons.drain(..) {
+        if licensed.filter(coupons.find(&coupon.merchant_domain, &coupon.code).await).await.is_some() {
+            expiring.push(coupon);
+        }
+    }
+    digest.expiring_coupons = expiring;
+    match digest.render() {
+        Ok(rendered) => Ok(Reply(DigestPreview { digest, rendered })),
+        Err(e) => {
+            tracing::warn!(subscription = %id, error = %e, "Failed to render a digest");
+            Err(ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "failed to render digest"))
+        }
+    }
+}
@@ -0,0 +1,222 @@
+//! The response envelope of the versioned API
+//!
+//! JSON responses under [`V1`] are wrapped as `{"data", "meta", "errors"}`. A
+//! success carries the handler's response in `data`; an error carries `data: null`
+//! and one [`ErrorDetail`]. Handlers build the envelope with their response (see
+//! [`super::reply`]); [`envelope_responses`] wraps the rest. Errors without a JSON
+//! body (rejected extractors, unknown routes) get one from their text or status,
+//! and every error names the request (see [`super::correlation`]). Other
+//! successful responses (the SSE stream, metrics, QR codes) and the exempt paths
+//! are passed through.
+
+use axum::{
+    body::to_bytes,
+    extract::Request,
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+
+use super::correlation::RequestContext;
+use super::reply::{Rendered, Rendering};
+use super::shaping::{is_exempt, json_body, json_response};
+use super::V1;
+
+/// Longest plain text error body carried over as a message
+const MAX_ERROR_TEXT_BYTES: usize = 64 * 1024;
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub(super) struct Envelope<T = Value> {
+    /// The endpoint's response; `null` on errors
+    #[schema(value_type = Object, nullable)]
+    pub data: Option<T>,
+    pub meta: Meta,
+    /// Empty on success
+    pub errors: Vec<ErrorDetail>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub(super) struct Meta {
+    #[schema(example = "deal-service")]
+    pub service: String,
+    #[schema(example = "v1")]
+    pub version: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub(super) struct ErrorDetail {
+    /// The response's HTTP status
+    pub status: u16,
+    pub message: String,
+    /// Whatever else the endpoint said about the error, e.g. the `required` permission
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    pub details: Option<Value>,
+    /// The `x-request-id` of the request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// The W3C trace the request belongs to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(example = "4bf92f3577b34da6a3ce929d0e0e4736")]
+    pub trace_id: Option<String>,
+}
+
+impl Meta {
+    fn v1() -> Self {
+        Meta {
+            service: "deal-service".to_string(),
+            version: "v1".to_string(),
+        }
+    }
+}
+
+impl<T> Envelope<T> {
+    pub fn success(data: T) -> Self {
+        Envelope {
+            data: Some(data),
+            meta: Meta::v1(),
+            errors: Vec::new(),
+        }
+    }
+
+    pub fn failure(error: ErrorDetail) -> Self {
+        Envelope {
+            data: None,
+            meta: Meta::v1(),
+            errors: vec![error],
+        }
+    }
+}
+
+impl Envelope {
+    /// From a response body not built by a handler's reply
+    fn new(status: StatusCode, body: Value, context: Option<&RequestContext>) -> Self {
+        match status.is_client_error() || status.is_server_error() {
+            true => Envelope::failure(ErrorDetail::new(status, body, context)),
+            false => {
+                let mut data = body;
+                if let Value::Object(fields) = &mut data {
+                    fields.remove("service");
+                }
+                Envelope::success(data)
+            }
+        }
+    }
+}
+
+impl ErrorDetail {
+    /// From an error body: a handler's `{"error": ...}` object, or plain text
+    fn new(status: StatusCode, body: Value, context: Option<&RequestContext>) -> Self {
+        let reason = || status.canonical_reason().unwrap_or("error").to_string();
+        let (message, details) = match body {
+            Value::Object(mut fields) => {
+                fields.remove("service");
+                let message = match fields.remove("error") {
+                    Some(Value::String(message)) => message,
+                    Some(other) => other.to_string(),
+                    None => reason(),
+                };
+                (message, (!fields.is_empty()).then_some(Value::Object(fields)))
+            }
+            Value::String(text) if !text.is_empty() => (text, None),
+            _ => (reason(), None),
+        };
+        ErrorDetail {
+            status: status.as_u16(),
+            message,
+            details,
+            request_id: context.map(|context| context.request_id.clone()),
+            trace_id: context.map(|context| context.trace_id.clone()),
+        }
+    }
+}
+
+/// Have the replies to [`V1`] requests built in an [`Envelope`], and wrap the
+/// JSON responses that were not
+pub(super) async fn envelope_responses(request: Request, next: Next) -> Response {
+    let path = request.uri().path();
+    if !path.starts_with(&format!("{}/", V1)) || is_exempt(path) {
+        return next.run(request).await;
+    }
+
+    let context = request.extensions().get::<RequestContext>().cloned();
+    let response = Rendering::scope(|rendering| rendering.versioned = true, next.run(request)).await;
+    if response.extensions().get::<Rendered>().is_some() {
+        return response;
+    }
+    let status = response.status();
+    let failed = status.is_client_error() || status.is_server_error();
+    let (mut parts, body) = match json_body(response).await {
+        Ok(json) => json,
+        Err(response) if failed => {
+            let (parts, body) = response.into_parts();
+            let text = to_bytes(body, MAX_ERROR_TEXT_BYTES)
+                .await
+                .map(|bytes| String::from_utf8_lossy(&bytes).trim().to_string())
+                .unwrap_or_default();
+            (parts, Value::String(text))
+        }
+        Err(response) => return response,
+    };
+    parts.headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    let envelope = serde_json::to_value(Envelope::new(status, body, context.as_ref())).unwrap_or_default();
+    json_response(parts, &envelope)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::http::Request;
+    use serde_json::{json, Value};
+
+    use crate::api::tests as api;
+    use crate::app::Services;
+
+    async fn get(services: &Services, path: &str, key: Option<&str>) -> (u16, Value) {
+        let mut request = Request::get(path);
+        if let Some(key) = key {
+            request = request.header("x-api-key", key);
+        }
+        api::call(services, request.body(Body::empty()).unwrap()).await
+    }
+
+    #[tokio::test]
+    async fn test_wraps_versioned_responses_and_leaves_unversioned_ones() {
+        let services = api::sandbox().await;
+
+        let (status, legacy) = get(&services, "/deals?limit=2", None).await;
+        assert_eq!(status, 200);
+        assert_eq!(legacy["service"], "deal-service");
+        let (status, body) = get(&services, "/api/v1/deals?limit=2", None).await;
+        assert_eq!(status, 200);
+        assert_eq!(body["meta"], json!({"service": "deal-service", "version": "v1"}));
+        assert_eq!(body["errors"], json!([]));
+        assert_eq!(body["data"]["total"], legacy["total"]);
+        assert!(body["data"].get("service").is_none());
+
+        // Extractor rejections and unknown routes get a message from their text or status
+        let (status, body) = get(&services, "/api/v1/jobs/not-a-uuid", None).await;
+        assert_eq!(status, 400);
+        assert!(body["data"].is_null());
+        assert_eq!(body["errors"][0]["status"], 400);
+        assert!(!body["errors"][0]["message"].as_str().unwrap().is_empty());
+        let (status, body) = get(&services, "/api/v1/nowhere", None).await;
+        assert_eq!((status, body["errors"][0]["message"].as_str()), (404, Some("Not Found")));
+        let (status, body) = get(&services, "/api/v1/admin/roles", None).await;
+        assert_eq!(status, 401);
+        assert_eq!(
+            body["errors"][0]["message"],
+            "admin endpoints need an API key or user with a role"
+        );
+        assert!(body["errors"][0]["request_id"].is_string());
+        let (status, legacy) = get(&services, "/admin/roles", None).await;
+        assert_eq!(status, 401);
+        assert_eq!(legacy["error"], body["errors"][0]["message"]);
+        assert!(legacy["request_id"].is_string());
+        let (status, body) = get(&services, "/api/v1/deals", Some("unknown")).await;
+        assert_eq!((status, body["errors"][0]["message"].as_str()), (401, Some("unknown API key")));
+    }
+}
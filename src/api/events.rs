@@ -0,0 +1,93 @@
+//! Shopping-event calendar and event collections
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Extension, Path, Query},
+    http::StatusCode,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use super::reply::Reply;
+use super::tags::TagFilter;
+use crate::events::{EventCalendar, EventOccurrence};
+use crate::experiments::RankingStrategy;
+use crate::models::deal::Deal;
+use crate::services::ranking::RankingPipeline;
+use crate::tagging;
+use crate::tenant::TenantId;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(super) struct UpcomingEventsQuery {
+    days: Option<i64>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct UpcomingEvents {
+    events: Vec<EventOccurrence>,
+    tenant: String,
+}
+
+#[utoipa::path(
+    get,
+    path = "/events/upcoming",
+    tag = "deals",
+    params(UpcomingEventsQuery),
+    responses(
+        (status = 200, description = "Shopping `events` starting soon", body = UpcomingEvents),
+    )
+)]
+pub(super) async fn upcoming_events(
+    Extension(events): Extension<Arc<EventCalendar>>,
+    tenant: TenantId,
+    Query(params): Query<UpcomingEventsQuery>,
+) -> Reply<UpcomingEvents> {
+    let days = params.days.unwrap_or(60).clamp(0, 366);
+
+    Reply(UpcomingEvents {
+        events: events.upcoming(&tenant.0, Utc::now().date_naive(), days),
+        tenant: tenant.0,
+    })
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct EventDeals {
+    event: EventOccurrence,
+    deals: Vec<Deal>,
+}
+
+/// Curated collection of deals for an event page; served ahead of the event too
+#[utoipa::path(
+    get,
+    path = "/events/{id}/deals",
+    tag = "deals",
+    params(("id" = String, Path, description = "Event id"), TagFilter),
+    responses(
+        (status = 200, description = "The `event` and its `deals`", body = EventDeals),
+        (status = 404, description = "Unknown event"),
+    )
+)]
+pub(super) async fn event_deals(
+    Extension(ranking): Extension<Arc<RankingPipeline>>,
+    Extension(events): Extension<Arc<EventCalendar>>,
+    tenant: TenantId,
+    Path(event_id): Path<String>,
+    Query(filter): Query<TagFilter>,
+) -> Result<Reply<EventDeals>, StatusCode> {
+    let event = events
+        .find(&tenant.0, &event_id, Utc::now().date_naive())
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let tags = filter.tags();
+    let deals: Vec<Deal> = ranking
+        .ranked(&tenant.0, RankingStrategy::Scored)
+        .await
+        .into_iter()
+        .filter(|deal| event.matches(deal) && tagging::has_all(&deal.tags, &tags))
+        .collect();
+
+    Ok(Reply(EventDeals { event, deals }))
+}
@@ -0,0 +1,57 @@
+//! Shared fetch endpoint for sister services
+
+use std::sync::Arc;
+
+use axum::{
+    extract::Extension,
+    http::{header, HeaderName, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use super::reply::ApiError;
+use super::requests::FetchRequest;
+use crate::fetch_service::{CallerId, FetchError, FetchService};
+
+/// The page body with the merchant's content type; `X-Cache` tells whether the
+/// merchant was contacted
+#[utoipa::path(
+    post,
+    path = "/fetch",
+    tag = "jobs",
+    request_body = FetchRequest,
+    responses(
+        (status = 200, description = "The page body with the merchant's content type; `X-Cache` tells whether the merchant was contacted", body = Vec<u8>, content_type = "application/octet-stream"),
+        (status = 400, description = "Invalid URL", body = ErrorBody),
+        (status = 429, description = "The caller's fetch quota is used up", body = ErrorBody),
+        (status = 451, description = "The merchant opted out of scraping", body = ErrorBody),
+        (status = 502, description = "The merchant could not be fetched", body = ErrorBody),
+    )
+)]
+pub(super) async fn fetch_page(
+    Extension(fetcher): Extension<Arc<FetchService>>,
+    caller: CallerId,
+    Json(request): Json<FetchRequest>,
+) -> Response {
+    match fetcher.fetch(&caller.0, &request.url).await {
+        Ok(page) => {
+            let content_type = page.content_type.unwrap_or_else(|| "application/octet-stream".to_string());
+            let mut response = (
+                [(header::CONTENT_TYPE, content_type), (HeaderName::from_static("x-cache"), page.cache.as_str().to_string())],
+                page.body,
+            )
+                .into_response();
+            if let Some(etag) = page.etag.and_then(|etag| etag.parse().ok()) {
+                response.headers_mut().insert(header::ETAG, etag);
+            }
+            response
+        }
+        Err(e @ FetchError::InvalidUrl(_)) => ApiError::bad_request(e.to_string()).into_response(),
+        Err(FetchError::QuotaExceeded { retry_after }) => (
+            [(header::RETRY_AFTER, retry_after.as_secs().max(1).to_string())],
+            ApiError::new(StatusCode::TOO_MANY_REQUESTS, "fetch quota exceeded").with("caller", caller.0),
+        )
+            .into_response(),
+        Err(e @ FetchError::OptedOut) => ApiError::new(StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS, e.to_string()).into_response(),
+        Err(e @ FetchError::Upstream(_)) => ApiError::new(StatusCode::BAD_GATEWAY, e.to_string()).into_response(),
+    }
+}
@@ -0,0 +1,536 @@
+//! The GraphQL schema at `/graphql`
+//!
+//! Frontends select the fields they need from deals, coupons, price history and
+//! alert interpretations in one round trip, e.g. a deal with its merchant's coupons
+//! and whether each one is valid for the cart. Responses are GraphQL's own
+//! `data`/`errors` document, so the route is exempt from the v1 envelope, tenant
+//! shaping, localization and the compliance filter; the resolvers apply coupon
+//! licenses and the compliance rules of the requester's country themselves.
+//! `GET /graphql` serves the schema in SDL, for client code generators.
+
+use std::sync::Arc;
+
+use async_graphql::{
+    ComplexObject, Context, EmptyMutation, EmptySubscription, Error, Object, Result, Schema, SimpleObject, ID,
+};
+use axum::{extract::Extension, http::HeaderMap, Json};
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use serde_json::Value;
+
+use super::coupons::estimated_discount;
+use super::licensing::Licensed;
+use crate::alerts::natural_language::{AlertInterpretation, NaturalAlertParser};
+use crate::app::Services;
+use crate::compliance::{ComplianceRules, JurisdictionProfile, COUNTRY_HEADER};
+use crate::coupon_engine::validator::Validator;
+use crate::coupon_success::CouponSuccessPredictor;
+use crate::experiments::RankingStrategy;
+use crate::models::coupon_listing::CouponListing;
+use crate::models::deal::{Deal, PricePoint};
+use crate::models::domain::{MerchantDomain, Money};
+use crate::reputation::ReputationService;
+use crate::services::ranking::RankingPipeline;
+use crate::storage::coupon_store::CouponStore;
+use crate::storage::deal_store::DealStore;
+use crate::tagging::{self, TagRegistry};
+use crate::tenant::{TenantId, TenantRegistry};
+use crate::top_coupons::TopCoupons;
+
+pub(super) type DealSchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+/// Deepest selection a query may make, e.g. deal → merchant → coupons → validation → failures
+const MAX_DEPTH: usize = 8;
+/// Fields a query may select in all
+const MAX_COMPLEXITY: usize = 1000;
+/// Deals per list unless `limit` says otherwise
+const DEFAULT_LIMIT: usize = 50;
+const MAX_LIMIT: usize = 200;
+
+/// The schema over `services`, with the query limits applied
+pub(super) fn schema(services: &Services) -> DealSchema {
+    Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+        .data(services.deal_store.clone())
+        .data(services.coupon_store.clone())
+        .data(services.top_coupons.clone())
+        .data(services.ranking.clone())
+        .data(services.coupon_predictor.clone())
+        .data(services.reputation.clone())
+        .data(services.alert_parser.clone())
+        .data(services.tags.clone())
+        .limit_depth(MAX_DEPTH)
+        .limit_complexity(MAX_COMPLEXITY)
+        .finish()
+}
+
+/// Run a GraphQL query. Coupons are those the caller's license allows, and offers
+/// follow the compliance rules of `X-Country` or the tenant's country.
+#[utoipa::path(
+    post,
+    path = "/graphql",
+    tag = "deals",
+    request_body(content = GraphqlRequest, description = "A GraphQL request: `query`, and optionally `variables` and `operationName`"),
+    responses(
+        (status = 200, description = "The GraphQL response: `data`, and `errors` if any field failed", body = GraphqlResponse),
+    )
+)]
+pub(super) async fn graphql(
+    Extension(schema): Extension<DealSchema>,
+    Extension(compliance): Extension<Arc<ComplianceRules>>,
+    Extension(tenants): Extension<Arc<TenantRegistry>>,
+    licensed: Licensed,
+    tenant: TenantId,
+    headers: HeaderMap,
+    Json(request): Json<async_graphql::Request>,
+) -> Json<async_graphql::Response> {
+    let header = headers.get(COUNTRY_HEADER).and_then(|v| v.to_str().ok());
+    let tenant_country = tenants.get(&tenant.0).and_then(|record| record.country.as_deref());
+    let profile = compliance
+        .resolve_country(header, tenant_country)
+        .and_then(|country| compliance.profile(&country).cloned());
+    let viewer = Viewer {
+        tenant: tenant.0,
+        licensed,
+        profile,
+    };
+    Json(schema.execute(request.data(viewer)).await)
+}
+
+/// The schema in SDL
+#[utoipa::path(
+    get,
+    path = "/graphql",
+    tag = "deals",
+    responses(
+        (status = 200, description = "The GraphQL schema", body = String, content_type = "text/plain"),
+    )
+)]
+pub(super) async fn graphql_sdl(Extension(schema): Extension<DealSchema>) -> String {
+    schema.sdl()
+}
+
+/// What the request's caller may see
+struct Viewer {
+    tenant: String,
+    licensed: Licensed,
+    profile: Option<JurisdictionProfile>,
+}
+
+impl Viewer {
+    fn shows(&self, deal: &Deal) -> bool {
+        self.profile
+            .as_ref()
+            .is_none_or(|profile| !profile.excludes(Some(&deal.category), &[&deal.title]))
+    }
+
+    fn deal(&self, deal: Deal) -> DealNode {
+        let disclosure = self.profile.as_ref().and_then(|profile| profile.price_disclosure.clone());
+        DealNode::new(deal, disclosure)
+    }
+
+    /// The `coupons` the caller may receive
+    async fn coupons(&self, mut coupons: Vec<CouponListing>) -> Vec<CouponNode> {
+        self.licensed.retain(&mut coupons).await;
+        if let Some(profile) = &self.profile {
+            coupons.retain(|coupon| {
+                let texts: Vec<&str> = [Some(coupon.title.as_str()), coupon.description.as_deref()].into_iter().flatten().collect();
+                !profile.excludes(None, &texts)
+            });
+        }
+        coupons.into_iter().map(CouponNode::new).collect()
+    }
+}
+
+fn merchant_domain(domain: &str) -> Result<MerchantDomain> {
+    MerchantDomain::parse(domain).map_err(Error::new)
+}
+
+/// A unit enum's serialized name, e.g. `out_of_stock`
+fn variant<T: Serialize>(value: &T) -> String {
+    match serde_json::to_value(value) {
+        Ok(Value::String(name)) => name,
+        _ => String::new(),
+    }
+}
+
+pub(super) struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// Deals in production ranking order
+    async fn deals(
+        &self,
+        ctx: &Context<'_>,
+        merchant: Option<String>,
+        category: Option<String>,
+        #[graphql(default, desc = "Only deals with every one of these tags")] tags: Vec<String>,
+        #[graphql(default)] offset: usize,
+        limit: Option<usize>,
+    ) -> Result<Vec<DealNode>> {
+        let viewer = ctx.data::<Viewer>()?;
+        let merchant = merchant.as_deref().map(merchant_domain).transpose()?;
+        let limit = limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+        let ranked = ctx.data::<Arc<RankingPipeline>>()?.ranked(&viewer.tenant, RankingStrategy::Scored).await;
+        Ok(ranked
+            .into_iter()
+            .filter(|deal| merchant.as_ref().is_none_or(|merchant| &deal.merchant_domain == merchant))
+            .filter(|deal| category.as_ref().is_none_or(|category| &deal.category == category))
+            .filter(|deal| tagging::has_all(&deal.tags, &tags))
+            .filter(|deal| viewer.shows(deal))
+            .skip(offset)
+            .take(limit)
+            .map(|deal| viewer.deal(deal))
+            .collect())
+    }
+
+    async fn deal(&self, ctx: &Context<'_>, id: ID) -> Result<Option<DealNode>> {
+        let viewer = ctx.data::<Viewer>()?;
+        let mut deal = ctx.data::<Arc<DealStore>>()?.get(&id).await;
+        if let Some(deal) = deal.as_mut() {
+            deal.tags = ctx.data::<Arc<TagRegistry>>()?.deal_tags(deal);
+        }
+        Ok(deal.filter(|deal| viewer.shows(deal)).map(|deal| viewer.deal(deal)))
+    }
+
+    /// Coupons the caller may receive, most likely to work first
+    async fn coupons(
+        &self,
+        ctx: &Context<'_>,
+        merchant: Option<String>,
+        #[graphql(default, desc = "Only coupons with every one of these tags")] tags: Vec<String>,
+    ) -> Result<Vec<CouponNode>> {
+        let viewer = ctx.data::<Viewer>()?;
+        let store = ctx.data::<Arc<CouponStore>>()?;
+        let mut listed = match merchant {
+            Some(merchant) => store.for_merchant(&merchant_domain(&merchant)?).await,
+            None => store.list().await,
+        };
+        ctx.data::<Arc<TagRegistry>>()?.annotate_coupons(&mut listed);
+        listed.retain(|coupon| tagging::has_all(&coupon.tags, &tags));
+        let reputation = ctx.data::<Arc<ReputationService>>()?;
+        ctx.data::<Arc<CouponSuccessPredictor>>()?.annotate(&mut listed, reputation).await;
+        listed.sort_by(|a, b| {
+            b.predicted_success
+                .unwrap_or(0.0)
+                .total_cmp(&a.predicted_success.unwrap_or(0.0))
+        });
+        Ok(viewer.coupons(listed).await)
+    }
+
+    async fn merchant(&self, domain: String) -> Result<MerchantNode> {
+        merchant_domain(&domain).map(MerchantNode)
+    }
+
+    /// Observed prices of a product, oldest first
+    async fn price_history(&self, ctx: &Context<'_>, product_id: String) -> Result<Vec<PricePointNode>> {
+        price_history(ctx, &product_id).await
+    }
+
+    /// The alert a free-text request describes, for the client to confirm before
+    /// creating it with `POST /alerts/natural`
+    async fn interpret_alert(&self, ctx: &Context<'_>, text: String) -> Result<Option<AlertNode>> {
+        let interpretation = ctx.data::<Arc<NaturalAlertParser>>()?.parse(&text).await;
+        Ok(interpretation.map(AlertNode::from))
+    }
+}
+
+async fn price_history(ctx: &Context<'_>, product_id: &str) -> Result<Vec<PricePointNode>> {
+    let history = ctx.data::<Arc<DealStore>>()?.price_history(product_id).await;
+    Ok(history.into_iter().map(PricePointNode::from).collect())
+}
+
+#[derive(SimpleObject)]
+#[graphql(name = "Money")]
+struct MoneyNode {
+    amount: Decimal,
+    currency: String,
+}
+
+impl From<Money> for MoneyNode {
+    fn from(money: Money) -> Self {
+        Self {
+            amount: money.amount,
+            currency: money.currency.to_string(),
+        }
+    }
+}
+
+#[derive(SimpleObject)]
+#[graphql(name = "PricePoint")]
+struct PricePointNode {
+    /// In the currency of the product's deals
+    price: Decimal,
+    observed_at: DateTime<Utc>,
+}
+
+impl From<PricePoint> for PricePointNode {
+    fn from(point: PricePoint) -> Self {
+        Self {
+            price: point.price,
+            observed_at: point.observed_at,
+        }
+    }
+}
+
+#[derive(SimpleObject)]
+#[graphql(name = "Deal", complex)]
+struct DealNode {
+    id: ID,
+    product_id: String,
+    title: String,
+    store: String,
+    merchant_domain: String,
+    category: String,
+    brand: Option<String>,
+    price: MoneyNode,
+    original_price: MoneyNode,
+    /// Advertised discount percentage
+    discount: f64,
+    /// Discount percentage against the product's typical selling price
+    honest_discount: Option<f64>,
+    discount_inflated: bool,
+    /// When the product page was last checked for the advertised price
+    verified_at: Option<DateTime<Utc>>,
+    /// The product page did not show the advertised price or discount
+    price_mismatch: bool,
+    /// `active`, `dead`, `out_of_stock` or `price_increased`
+    status: String,
+    image_url: Option<String>,
+    free_shipping: bool,
+    posted_at: DateTime<Utc>,
+    score: Option<f64>,
+    events: Vec<String>,
+    tags: Vec<String>,
+    /// To be shown next to the price where the requester's country requires it
+    price_disclosure: Option<String>,
+    #[graphql(skip)]
+    domain: MerchantDomain,
+}
+
+impl DealNode {
+    fn new(deal: Deal, price_disclosure: Option<String>) -> Self {
+        Self {
+            id: ID(deal.id),
+            product_id: deal.product_id,
+            title: deal.title,
+            store: deal.store,
+            merchant_domain: deal.merchant_domain.to_string(),
+            category: deal.category,
+            brand: deal.brand,
+            price: deal.price.into(),
+            original_price: deal.original_price.into(),
+            discount: deal.discount,
+            honest_discount: deal.honest_discount,
+            discount_inflated: deal.discount_inflated,
+            verified_at: deal.verified_at,
+            price_mismatch: deal.price_mismatch,
+            status: variant(&deal.status),
+            image_url: deal.image_url,
+            free_shipping: deal.free_shipping,
+            posted_at: deal.posted_at,
+            score: deal.score,
+            events: deal.events,
+            tags: deal.tags,
+            price_disclosure,
+            domain: deal.merchant_domain,
+        }
+    }
+}
+
+#[ComplexObject]
+impl DealNode {
+    /// Observed prices of the deal's product, oldest first
+    async fn price_history(&self, ctx: &Context<'_>) -> Result<Vec<PricePointNode>> {
+        price_history(ctx, &self.product_id).await
+    }
+
+    async fn merchant(&self) -> MerchantNode {
+        MerchantNode(self.domain.clone())
+    }
+}
+
+struct MerchantNode(MerchantDomain);
+
+#[Object(name = "Merchant")]
+impl MerchantNode {
+    async fn domain(&self) -> &str {
+        self.0.as_str()
+    }
+
+    /// The merchant's best unexpired codes, from the top coupons cache
+    async fn coupons(&self, ctx: &Context<'_>) -> Result<Vec<CouponNode>> {
+        let viewer = ctx.data::<Viewer>()?;
+        let top = ctx.data::<Arc<TopCoupons>>()?.get(&self.0).await;
+        Ok(viewer.coupons(top.to_vec()).await)
+    }
+
+    async fn deals(&self, ctx: &Context<'_>, limit: Option<usize>) -> Result<Vec<DealNode>> {
+        let viewer = ctx.data::<Viewer>()?;
+        let limit = limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+        let deals = ctx.data::<Arc<DealStore>>()?.list().await;
+        Ok(deals
+            .into_iter()
+            .filter(|deal| deal.merchant_domain == self.0 && viewer.shows(deal))
+            .take(limit)
+            .map(|deal| viewer.deal(deal))
+            .collect())
+    }
+}
+
+#[derive(SimpleObject)]
+#[graphql(name = "Coupon", complex)]
+struct CouponNode {
+    code: String,
+    title: String,
+    description: Option<String>,
+    merchant_domain: String,
+    /// `percentage`, `fixed`, `free_shipping`, ...
+    discount_type: String,
+    discount_value: Option<f64>,
+    minimum_order: Option<Decimal>,
+    valid_until: Option<DateTime<Utc>>,
+    /// Probability that the code works, from the coupon success model
+    predicted_success: Option<f64>,
+    /// To be shown with the code, as its license requires
+    attribution: Option<String>,
+    tags: Vec<String>,
+    #[graphql(skip)]
+    listing: CouponListing,
+}
+
+impl CouponNode {
+    fn new(listing: CouponListing) -> Self {
+        Self {
+            code: listing.code.to_string(),
+            title: listing.title.clone(),
+            description: listing.description.clone(),
+            merchant_domain: listing.merchant_domain.to_string(),
+            discount_type: listing.discount_type.clone(),
+            discount_value: listing.discount_value,
+            minimum_order: listing.minimum_order,
+            valid_until: listing.valid_until,
+            predicted_success: listing.predicted_success,
+            attribution: listing.license.as_ref().and_then(|tag| tag.attribution.clone()),
+            tags: listing.tags.clone(),
+            listing,
+        }
+    }
+}
+
+#[ComplexObject]
+impl CouponNode {
+    /// Whether the code works now, as `POST /coupons/validate` would answer for an
+    /// order of `order_total`
+    async fn validation(&self, order_total: Option<Decimal>) -> ValidationNode {
+        let failures = Validator::new().check_listing(&self.listing, order_total);
+        let discount = match order_total {
+            Some(total) if failures.is_empty() => estimated_discount(&self.listing, total),
+            _ => None,
+        };
+        ValidationNode {
+            valid: failures.is_empty(),
+            failures: failures
+                .into_iter()
+                .map(|failure| FailureNode {
+                    reason: variant(&failure.reason),
+                    message: failure.message,
+                })
+                .collect(),
+            discount,
+        }
+    }
+
+    async fn merchant(&self) -> MerchantNode {
+        MerchantNode(self.listing.merchant_domain.clone())
+    }
+}
+
+#[derive(SimpleObject)]
+#[graphql(name = "CouponValidation")]
+struct ValidationNode {
+    valid: bool,
+    /// Empty when `valid`
+    failures: Vec<FailureNode>,
+    /// What the code takes off the order, when valid and `order_total` was given
+    discount: Option<Decimal>,
+}
+
+#[derive(SimpleObject)]
+#[graphql(name = "ValidationFailure")]
+struct FailureNode {
+    /// e.g. `expired` or `below_minimum_order`
+    reason: String,
+    message: String,
+}
+
+#[derive(SimpleObject)]
+#[graphql(name = "AlertInterpretation")]
+struct AlertNode {
+    product_name: String,
+    target_price: Option<Decimal>,
+    min_discount: Option<f64>,
+    platforms: Vec<String>,
+    /// `target_price`, `discount_threshold` or `any_deal`
+    alert_type: String,
+    confidence: f64,
+    summary: String,
+}
+
+impl From<AlertInterpretation> for AlertNode {
+    fn from(interpretation: AlertInterpretation) -> Self {
+        Self {
+            alert_type: variant(&interpretation.alert_type),
+            product_name: interpretation.product_name,
+            target_price: interpretation.target_price,
+            min_discount: interpretation.min_discount,
+            platforms: interpretation.platforms,
+            confidence: interpretation.confidence,
+            summary: interpretation.summary,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use crate::api::tests as api;
+    use crate::app::Services;
+
+    async fn query(services: &Services, query: &str) -> Value {
+        let (status, body) = api::post(services, "/api/v1/graphql", &json!({"query": query})).await;
+        assert_eq!(status, 200);
+        body
+    }
+
+    #[tokio::test]
+    async fn test_resolves_nested_selections_in_one_request() {
+        let services = api::sandbox().await;
+        let merchant = services.coupon_store.list().await[0].merchant_domain.clone();
+
+        let body = query(
+            &services,
+            &format!(
+                r#"{{
+                    deals(limit: 2) {{ id title price {{ amount currency }} priceHistory {{ price }} merchant {{ domain }} }}
+                    merchant(domain: "{}") {{ coupons {{ code validation(orderTotal: "1000") {{ valid failures {{ reason }} }} }} }}
+                    interpretAlert(text: "tell me when airpods drop below $150") {{ productName alertType }}
+                }}"#,
+                merchant
+            ),
+        )
+        .await;
+        // GraphQL's own document, not the v1 envelope
+        assert!(body.get("errors").is_none(), "{}", body);
+        let deals = body["data"]["deals"].as_array().unwrap();
+        assert_eq!(deals.len(), 2);
+        assert!(deals[0]["price"]["amount"].is_string());
+        assert!(deals[0]["merchant"]["domain"].is_string());
+        assert!(!body["data"]["merchant"]["coupons"].as_array().unwrap().is_empty());
+        assert_eq!(body["data"]["interpretAlert"]["alertType"], "target_price");
+
+        let body = query(&services, "{ deals { merchant { deals { merchant { deals { merchant { deals { merchant { domain } } } } } } } } }").await;
+        assert!(body["errors"][0]["message"].as_str().unwrap().contains("nested too deep"));
+    }
+}
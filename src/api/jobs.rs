@@ -0,0 +1,157 @@
+//! Scrape job endpoints
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Extension, Path, Query},
+    http::StatusCode,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use super::reply::{ApiError, Reply};
+use super::requests::JobRequest;
+use crate::jobs::{CancelError, DeadLetter, ScrapeJob, ScrapeQueue};
+use crate::models::domain::MerchantDomain;
+use crate::tenant::TenantId;
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct JobResponse {
+    job: ScrapeJob,
+}
+
+impl JobResponse {
+    fn new(job: ScrapeJob) -> Reply<Self> {
+        Reply(JobResponse { job })
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/jobs",
+    tag = "jobs",
+    request_body = JobRequest,
+    responses(
+        (status = 202, description = "The queued job", body = JobResponse),
+        (status = 400, description = "No URLs, or too many", body = ErrorBody),
+    )
+)]
+pub(super) async fn submit_job(
+    Extension(queue): Extension<Arc<ScrapeQueue>>,
+    tenant: TenantId,
+    Json(request): Json<JobRequest>,
+) -> Result<(StatusCode, Reply<JobResponse>), ApiError> {
+    match queue.submit(&tenant.0, request.urls, request.priority).await {
+        Ok(job) => Ok((StatusCode::ACCEPTED, JobResponse::new(job))),
+        Err(e) => Err(ApiError::bad_request(e)),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/jobs/{id}",
+    tag = "jobs",
+    params(("id" = Uuid, Path, description = "Job id")),
+    responses(
+        (status = 200, description = "The job and, once completed, its coupons", body = JobResponse),
+        (status = 404, description = "No such job"),
+    )
+)]
+pub(super) async fn get_job(
+    Extension(queue): Extension<Arc<ScrapeQueue>>,
+    tenant: TenantId,
+    Path(job_id): Path<Uuid>,
+) -> Result<Reply<JobResponse>, StatusCode> {
+    let job = queue.get(&tenant.0, job_id).await.ok_or(StatusCode::NOT_FOUND)?;
+
+    Ok(JobResponse::new(job))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/jobs/{id}",
+    tag = "jobs",
+    params(("id" = Uuid, Path, description = "Job id")),
+    responses(
+        (status = 200, description = "The cancelled job", body = JobResponse),
+        (status = 404, description = "No such job", body = ErrorBody),
+        (status = 409, description = "The job already finished", body = ErrorBody),
+    )
+)]
+pub(super) async fn cancel_job(
+    Extension(queue): Extension<Arc<ScrapeQueue>>,
+    tenant: TenantId,
+    Path(job_id): Path<Uuid>,
+) -> Result<Reply<JobResponse>, ApiError> {
+    match queue.cancel(&tenant.0, job_id).await {
+        Ok(job) => Ok(JobResponse::new(job)),
+        Err(CancelError::NotFound) => Err(ApiError::not_found("job not found")),
+        Err(CancelError::AlreadyFinished(status)) => {
+            Err(ApiError::new(StatusCode::CONFLICT, "job already finished").with("status", status))
+        }
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(super) struct DeadLetterQuery {
+    tenant: Option<String>,
+    merchant: Option<MerchantDomain>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct DeadLetters {
+    count: usize,
+    dead_letters: Vec<DeadLetter>,
+}
+
+/// URLs that failed every retry, newest first
+#[utoipa::path(
+    get,
+    path = "/admin/jobs/dead-letter",
+    tag = "admin",
+    params(DeadLetterQuery),
+    responses(
+        (status = 200, description = "`dead_letters`, newest first", body = DeadLetters),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn dead_letters(
+    Extension(queue): Extension<Arc<ScrapeQueue>>,
+    Query(query): Query<DeadLetterQuery>,
+) -> Reply<DeadLetters> {
+    let dead_letters = queue.dead_letters(query.tenant.as_deref(), query.merchant.as_ref()).await;
+
+    Reply(DeadLetters {
+        count: dead_letters.len(),
+        dead_letters,
+    })
+}
+
+/// Queue a dead-lettered URL again, e.g. once its merchant is back up
+#[utoipa::path(
+    post,
+    path = "/admin/jobs/dead-letter/{id}/retry",
+    tag = "admin",
+    params(("id" = Uuid, Path, description = "Dead letter id")),
+    responses(
+        (status = 202, description = "The job retrying the URL", body = JobResponse),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+        (status = 404, description = "No such dead letter"),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn retry_dead_letter(
+    Extension(queue): Extension<Arc<ScrapeQueue>>,
+    Path(dead_letter_id): Path<Uuid>,
+) -> Result<(StatusCode, Reply<JobResponse>), ApiError> {
+    match queue.retry_dead_letter(dead_letter_id).await {
+        Some(job) => Ok((StatusCode::ACCEPTED, JobResponse::new(job))),
+        None => Err(ApiError::not_found("dead letter not found")),
+    }
+}
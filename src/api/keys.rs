@@ -0,0 +1,210 @@
+//! Scope checks for partner API keys, and their issuance and revocation
+
+use std::sync::Arc;
+
+use axum::{
+    async_trait,
+    extract::{Extension, FromRequestParts, MatchedPath, Path},
+    http::{request::Parts, Method, StatusCode},
+    Json,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::reply::{ApiError, Reply};
+use crate::api_keys::{ApiKeyRecord, ApiKeys, IssuedKey, KeyRequest, Scope};
+
+/// Routes any partner key may call
+const UNSCOPED: &[&str] = &["/health", "/health/live", "/openapi.json", "/docs", "/account/usage"];
+
+/// The scope a partner key needs on a route, by its unversioned path. Other
+/// writes are refused to partner keys, so a new one stays closed until it is listed.
+fn required_scope(method: &Method, route: &str) -> Option<Scope> {
+    let read = method == Method::GET || method == Method::HEAD;
+    let scope = match route {
+        _ if route.starts_with("/admin/") || route.starts_with("/analytics/") => Scope::Admin,
+        "/partners/feed" | "/partners/feed/:id" | "/deals/import" => Scope::SubmitCoupons,
+        "/widget/:merchant" => Scope::Widget,
+        // Reads sent as a POST body
+        "/coupons/validate" | "/stacksmart" | "/graphql" => Scope::ReadDeals,
+        _ if read => Scope::ReadDeals,
+        _ => return None,
+    };
+    Some(scope)
+}
+
+/// Proof that the request's partner key, if it was made with one, has the route's scope
+pub(super) struct KeyScope;
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for KeyScope {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Some(key) = parts.extensions.get::<ApiKeyRecord>() else {
+            return Ok(KeyScope);
+        };
+        let route = parts.extensions.get::<MatchedPath>().map(|route| super::unversioned(route.as_str()));
+        if route.is_some_and(|route| UNSCOPED.contains(&route)) {
+            return Ok(KeyScope);
+        }
+        match route.and_then(|route| required_scope(&parts.method, route)) {
+            Some(scope) if key.allows(scope) => Ok(KeyScope),
+            Some(scope) => Err(ApiError::new(StatusCode::FORBIDDEN, "API key lacks the scope").with("required", scope)),
+            None => Err(ApiError::new(StatusCode::FORBIDDEN, "not available to partner API keys")),
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct ApiKeyList {
+    api_keys: Vec<ApiKeyRecord>,
+}
+
+/// Every partner key, revoked ones included
+#[utoipa::path(
+    get,
+    path = "/admin/api-keys",
+    tag = "admin",
+    responses(
+        (status = 200, description = "The `api_keys`", body = ApiKeyList),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn list_api_keys(Extension(keys): Extension<Arc<ApiKeys>>) -> Reply<ApiKeyList> {
+    Reply(ApiKeyList {
+        api_keys: keys.list().await,
+    })
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct IssuedApiKey {
+    api_key: IssuedKey,
+}
+
+/// Issue a key to a partner integration; the key is only shown in this response
+#[utoipa::path(
+    post,
+    path = "/admin/api-keys",
+    tag = "admin",
+    request_body = KeyRequest,
+    responses(
+        (status = 201, description = "The issued `api_key`, with the key itself", body = IssuedApiKey),
+        (status = 400, description = "No partner or no scopes", body = ErrorBody),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn issue_api_key(
+    Extension(keys): Extension<Arc<ApiKeys>>,
+    Json(request): Json<KeyRequest>,
+) -> Result<(StatusCode, Reply<IssuedApiKey>), ApiError> {
+    match keys.issue(request).await {
+        Ok(issued) => Ok((StatusCode::CREATED, Reply(IssuedApiKey { api_key: issued }))),
+        Err(e) => Err(ApiError::bad_request(e)),
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct RevokedApiKey {
+    api_key: ApiKeyRecord,
+}
+
+/// Revoke a key; requests made with it are refused from then on
+#[utoipa::path(
+    delete,
+    path = "/admin/api-keys/{id}",
+    tag = "admin",
+    params(("id" = Uuid, Path, description = "Key id")),
+    responses(
+        (status = 200, description = "The revoked `api_key`", body = RevokedApiKey),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+        (status = 404, description = "No such key", body = ErrorBody),
+        (status = 500, description = "Revoked, but not stored", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn revoke_api_key(
+    Extension(keys): Extension<Arc<ApiKeys>>,
+    Path(id): Path<Uuid>,
+) -> Result<Reply<RevokedApiKey>, ApiError> {
+    match keys.revoke(id).await {
+        Ok(Some(key)) => Ok(Reply(RevokedApiKey { api_key: key })),
+        Ok(None) => Err(ApiError::not_found("no such key")),
+        Err(e) => Err(ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::body::Body;
+    use axum::http::Request;
+    use serde_json::{json, Value};
+
+    use crate::api::tests as api;
+    use crate::api_keys::{ApiKeys, KeyRequest, Scope};
+    use crate::app::Services;
+
+    async fn call(services: &Services, method: &str, path: &str, key: &str) -> (u16, Value) {
+        let request = Request::builder()
+            .method(method)
+            .uri(path)
+            .header("x-api-key", key)
+            .header("content-type", "application/json")
+            .body(Body::from(json!({"merchant_domain": "shop.example", "code": "SAVE10"}).to_string()))
+            .unwrap();
+        api::call(services, request).await
+    }
+
+    #[tokio::test]
+    async fn test_partner_keys_are_scoped_metered_and_revocable() {
+        let mut services = api::sandbox().await;
+        let keys = Arc::new(ApiKeys::new(None));
+        services.api_keys = keys.clone();
+        let reader = keys
+            .issue(KeyRequest {
+                partner: "price-widget".to_string(),
+                scopes: [Scope::ReadDeals].into(),
+                requests_per_minute: Some(4),
+                referrers: Default::default(),
+            })
+            .await
+            .unwrap();
+        let feeder = keys
+            .issue(KeyRequest {
+                partner: "feed-sync".to_string(),
+                scopes: [Scope::SubmitCoupons].into(),
+                requests_per_minute: None,
+                referrers: Default::default(),
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(call(&services, "GET", "/health", &feeder.key).await.0, 200);
+        assert_eq!(call(&services, "GET", "/api/v1/deals", &reader.key).await.0, 200);
+        assert_eq!(call(&services, "POST", "/coupons/validate", &reader.key).await.0, 200);
+        let (status, body) = call(&services, "GET", "/deals", &feeder.key).await;
+        assert_eq!((status, body["required"].as_str()), (403, Some("read-deals")));
+        let (status, body) = call(&services, "GET", "/admin/roles", &reader.key).await;
+        assert_eq!((status, body["required"].as_str()), (403, Some("admin")));
+        let (status, body) = call(&services, "POST", "/deals/interactions", &reader.key).await;
+        assert_eq!((status, body["error"].as_str()), (403, Some("not available to partner API keys")));
+
+        // The reader's own quota of 4 is used up; the feeder's is untouched
+        assert_eq!(call(&services, "GET", "/deals", &reader.key).await.0, 429);
+        assert_eq!(call(&services, "GET", "/health", &feeder.key).await.0, 200);
+
+        keys.revoke(feeder.record.id).await.unwrap();
+        let (status, body) = call(&services, "GET", "/health", &feeder.key).await;
+        assert_eq!((status, body["error"].as_str()), (401, Some("API key revoked")));
+        let (status, body) = call(&services, "GET", "/health", "dmk_unknown").await;
+        assert_eq!((status, body["error"].as_str()), (401, Some("unknown API key")));
+    }
+}
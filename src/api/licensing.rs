@@ -0,0 +1,169 @@
+//! Coupon license filtering and the source license records
+
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use axum::{
+    async_trait,
+    extract::{Extension, FromRequestParts, Path, Query},
+    http::{request::Parts, StatusCode},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use super::reply::{ApiError, Reply};
+use crate::licensing::{default_terms, SourceLicenses, SourceRecord, SourceTermsRequest};
+use crate::models::coupon_listing::{CouponListing, CouponSource, License};
+use crate::models::domain::MerchantDomain;
+use crate::tenant::{Caller, TenantRegistry};
+
+/// The coupon licenses the caller may receive; handlers pass every coupon they
+/// respond with through it
+pub(super) struct Licensed {
+    licenses: Arc<SourceLicenses>,
+    /// `None` when the caller may receive everything
+    allowed: Option<BTreeSet<License>>,
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Licensed {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Some(licenses) = parts.extensions.get::<Arc<SourceLicenses>>().cloned() else {
+            return Err(ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "coupon licenses are not configured"));
+        };
+        let allowed = parts.extensions.get::<Caller>().and_then(|caller| match parts.extensions.get::<Arc<TenantRegistry>>() {
+            Some(tenants) => tenants.licenses(caller),
+            None => TenantRegistry::default().licenses(caller),
+        });
+        Ok(Licensed { licenses, allowed })
+    }
+}
+
+impl Licensed {
+    /// Tag `coupon` with its license and tell whether the caller may receive it
+    pub async fn permits(&self, coupon: &mut CouponListing) -> bool {
+        self.licenses.permits(coupon, self.allowed.as_ref()).await
+    }
+
+    /// `coupon`, tagged, if the caller may receive it
+    pub async fn filter(&self, coupon: Option<CouponListing>) -> Option<CouponListing> {
+        let mut coupon = coupon?;
+        self.permits(&mut coupon).await.then_some(coupon)
+    }
+
+    /// Tag `coupons` and drop those the caller may not receive
+    pub async fn retain(&self, coupons: &mut Vec<CouponListing>) {
+        self.licenses.retain_permitted(coupons, self.allowed.as_ref()).await
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct Licenses {
+    sources: Vec<SourceRecord>,
+    defaults: Vec<DefaultLicense>,
+}
+
+/// The license of a source's coupons while it has no record
+#[derive(Serialize, ToSchema)]
+pub(super) struct DefaultLicense {
+    source: CouponSource,
+    license: License,
+}
+
+/// Every source's terms: the records, and the defaults of sources without one
+#[utoipa::path(
+    get,
+    path = "/admin/licenses",
+    tag = "admin",
+    responses(
+        (status = 200, description = "License records of the `sources`, and the `defaults`", body = Licenses),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn list_licenses(Extension(licenses): Extension<Arc<SourceLicenses>>) -> Reply<Licenses> {
+    let defaults = [
+        CouponSource::AffiliateApi,
+        CouponSource::PartnerApi,
+        CouponSource::WebScraping,
+        CouponSource::UserSubmitted,
+    ]
+    .into_iter()
+    .map(|source| DefaultLicense {
+        source,
+        license: default_terms(source).license,
+    })
+    .collect();
+    Reply(Licenses {
+        sources: licenses.records().await,
+        defaults,
+    })
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct StoredLicense {
+    source: SourceRecord,
+}
+
+#[utoipa::path(
+    put,
+    path = "/admin/licenses/{source}",
+    tag = "admin",
+    params(("source" = CouponSource, Path, description = "Coupon source")),
+    request_body = SourceTermsRequest,
+    responses(
+        (status = 200, description = "The stored `source` record", body = StoredLicense),
+        (status = 400, description = "Attribution license without the text", body = ErrorBody),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn put_license(
+    Extension(licenses): Extension<Arc<SourceLicenses>>,
+    Path(source): Path<CouponSource>,
+    Json(request): Json<SourceTermsRequest>,
+) -> Result<Reply<StoredLicense>, ApiError> {
+    match licenses.set(source, request).await {
+        Ok(record) => Ok(Reply(StoredLicense { source: record })),
+        Err(e) => Err(ApiError::bad_request(e)),
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(super) struct LicenseQuery {
+    merchant_domain: Option<MerchantDomain>,
+}
+
+/// Drop the record of a source, or of one of its merchants with `?merchant_domain=`
+#[utoipa::path(
+    delete,
+    path = "/admin/licenses/{source}",
+    tag = "admin",
+    params(
+        ("source" = CouponSource, Path, description = "Coupon source"),
+        LicenseQuery,
+    ),
+    responses(
+        (status = 204, description = "Deleted"),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+        (status = 404, description = "No such record"),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn delete_license(
+    Extension(licenses): Extension<Arc<SourceLicenses>>,
+    Path(source): Path<CouponSource>,
+    Query(query): Query<LicenseQuery>,
+) -> StatusCode {
+    match licenses.remove(source, query.merchant_domain.as_ref()).await {
+        true => StatusCode::NO_CONTENT,
+        false => StatusCode::NOT_FOUND,
+    }
+}
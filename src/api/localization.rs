@@ -0,0 +1,117 @@
+//! Coupon text in the requester's language
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use serde_json::{Map, Value};
+
+use super::shaping::{is_exempt, json_body, json_response};
+use crate::localization::{Locale, Translator};
+
+/// Fields of a coupon that are translated
+const TRANSLATED_FIELDS: [&str; 2] = ["title", "description"];
+
+/// Translate the coupons in JSON responses to the `Accept-Language` locale.
+///
+/// A coupon is any object with `code`, `title` and `merchant_domain`. Translated
+/// coupons get `locale` set to the served language and `original_locale` to the one
+/// they were written in. When the provider fails, coupons are served as written.
+pub(super) async fn localize_responses(State(translator): State<Arc<Translator>>, request: Request, next: Next) -> Response {
+    let target = request
+        .headers()
+        .get(header::ACCEPT_LANGUAGE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(Locale::from_accept_language)
+        .filter(|_| translator.is_enabled() && !is_exempt(request.uri().path()));
+
+    let mut response = next.run(request).await;
+    if translator.is_enabled() {
+        response.headers_mut().append(header::VARY, HeaderValue::from_static("accept-language"));
+    }
+    let Some(target) = target else {
+        return response;
+    };
+
+    let (mut parts, mut value) = match json_body(response).await {
+        Ok(json) => json,
+        Err(response) => return response,
+    };
+    if translate_coupons(&translator, &mut value, &target).await {
+        if let Ok(language) = HeaderValue::from_str(target.as_str()) {
+            parts.headers.insert(header::CONTENT_LANGUAGE, language);
+        }
+    }
+    json_response(parts, &value)
+}
+
+/// Whether anything was translated
+async fn translate_coupons(translator: &Translator, value: &mut Value, target: &Locale) -> bool {
+    // Texts to translate, grouped by the language they are written in
+    let mut pending: HashMap<Locale, Vec<String>> = HashMap::new();
+    visit_coupons(value, &mut |coupon| {
+        let source = source_locale(translator, coupon);
+        if translator.needs_translation(&source, target) {
+            let texts = pending.entry(source).or_default();
+            texts.extend(TRANSLATED_FIELDS.iter().filter_map(|field| coupon.get(*field)?.as_str().map(String::from)));
+        }
+    });
+
+    let mut translations: HashMap<(Locale, String), String> = HashMap::new();
+    for (source, texts) in pending {
+        match translator.translate(&texts, &source, target).await {
+            Ok(translated) => translations.extend(texts.into_iter().map(|t| (source.clone(), t)).zip(translated)),
+            Err(e) => tracing::warn!(%source, %target, error = %e, "Serving coupons untranslated"),
+        }
+    }
+    if translations.is_empty() {
+        return false;
+    }
+
+    visit_coupons(value, &mut |coupon| {
+        let source = source_locale(translator, coupon);
+        let mut translated = false;
+        for field in TRANSLATED_FIELDS {
+            let Some(Value::String(text)) = coupon.get_mut(field) else {
+                continue;
+            };
+            if let Some(translation) = translations.get(&(source.clone(), text.clone())) {
+                *text = translation.clone();
+                translated = true;
+            }
+        }
+        if translated {
+            coupon.insert("original_locale".to_string(), Value::String(source.to_string()));
+            coupon.insert("locale".to_string(), Value::String(target.to_string()));
+        }
+    });
+    true
+}
+
+fn source_locale(translator: &Translator, coupon: &Map<String, Value>) -> Locale {
+    coupon
+        .get("locale")
+        .and_then(Value::as_str)
+        .and_then(Locale::parse)
+        .unwrap_or_else(|| translator.default_locale().clone())
+}
+
+fn visit_coupons(value: &mut Value, visit: &mut impl FnMut(&mut Map<String, Value>)) {
+    match value {
+        Value::Array(items) => items.iter_mut().for_each(|item| visit_coupons(item, visit)),
+        Value::Object(object) => {
+            let is_coupon = ["code", "title", "merchant_domain"].iter().all(|key| object.contains_key(*key));
+            if is_coupon {
+                visit(object);
+            } else {
+                object.values_mut().for_each(|nested| visit_coupons(nested, visit));
+            }
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,228 @@
+//! Merchant reputation and liveness endpoints
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Extension, Path, Query},
+    http::StatusCode,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use super::reply::{ApiError, Reply};
+use super::requests::MerchantFeedback;
+use crate::coupon_engine::budget::{BudgetUsage, ScrapeBudgets};
+use crate::coupon_engine::liveness::{LivenessMonitor, MerchantLiveness};
+use crate::coupon_engine::yield_stats::{YieldInterval, YieldPoint, YieldStats, RETENTION_DAYS};
+use crate::models::domain::MerchantDomain;
+use crate::pricing::discount_audit::DiscountAuditor;
+use crate::reputation::{MerchantReputation, ReputationService, SignalUpdate};
+use crate::storage::deal_store::DealStore;
+use crate::top_coupons::TopCoupons;
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct Reputation {
+    reputation: MerchantReputation,
+    liveness: Option<MerchantLiveness>,
+}
+
+/// The merchant's reputation, and its latest liveness check if it has had one
+#[utoipa::path(
+    get,
+    path = "/merchants/{domain}/reputation",
+    tag = "merchants",
+    params(("domain" = String, Path, description = "Merchant domain, e.g. `amazon.com`")),
+    responses(
+        (status = 200, description = "The merchant's `reputation` and `liveness`", body = Reputation),
+    )
+)]
+pub(super) async fn merchant_reputation(
+    Extension(store): Extension<Arc<DealStore>>,
+    Extension(auditor): Extension<Arc<DiscountAuditor>>,
+    Extension(reputation): Extension<Arc<ReputationService>>,
+    Extension(liveness): Extension<Arc<LivenessMonitor>>,
+    Path(domain): Path<MerchantDomain>,
+) -> Reply<Reputation> {
+    let mut deals = store.list().await;
+    auditor.annotate(&store, &mut deals).await;
+
+    Reply(Reputation {
+        reputation: reputation.reputation(&domain, &deals).await,
+        liveness: liveness.get(&domain).await,
+    })
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct LivenessReport {
+    merchants: Vec<MerchantLiveness>,
+}
+
+/// Every checked merchant's latest liveness check
+#[utoipa::path(
+    get,
+    path = "/merchants/liveness",
+    tag = "merchants",
+    responses(
+        (status = 200, description = "`merchants`: every checked merchant's latest liveness check", body = LivenessReport),
+    )
+)]
+pub(super) async fn merchant_liveness(Extension(liveness): Extension<Arc<LivenessMonitor>>) -> Reply<LivenessReport> {
+    Reply(LivenessReport {
+        merchants: liveness.report().await,
+    })
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct LivenessCheck {
+    liveness: MerchantLiveness,
+}
+
+/// Check a merchant's site now, e.g. after it reports being back up
+#[utoipa::path(
+    post,
+    path = "/admin/merchants/{domain}/liveness",
+    tag = "admin",
+    params(("domain" = String, Path, description = "Merchant domain, e.g. `amazon.com`")),
+    responses(
+        (status = 200, description = "The merchant's new `liveness` check", body = LivenessCheck),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn check_merchant_liveness(
+    Extension(liveness): Extension<Arc<LivenessMonitor>>,
+    Path(domain): Path<MerchantDomain>,
+) -> Reply<LivenessCheck> {
+    Reply(LivenessCheck {
+        liveness: liveness.check(&domain).await,
+    })
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct MerchantRankings {
+    merchants: Vec<MerchantReputation>,
+}
+
+/// Merchants in the order the crawl scheduler should prioritise their sources
+#[utoipa::path(
+    get,
+    path = "/merchants/reputation",
+    tag = "merchants",
+    responses(
+        (status = 200, description = "`merchants` in crawl priority order", body = MerchantRankings),
+    )
+)]
+pub(super) async fn merchant_rankings(
+    Extension(store): Extension<Arc<DealStore>>,
+    Extension(auditor): Extension<Arc<DiscountAuditor>>,
+    Extension(reputation): Extension<Arc<ReputationService>>,
+) -> Reply<MerchantRankings> {
+    let mut deals = store.list().await;
+    auditor.annotate(&store, &mut deals).await;
+
+    Reply(MerchantRankings {
+        merchants: reputation.ranked(&deals).await,
+    })
+}
+
+#[utoipa::path(
+    post,
+    path = "/merchants/{domain}/feedback",
+    tag = "merchants",
+    params(("domain" = String, Path, description = "Merchant domain, e.g. `amazon.com`")),
+    request_body = MerchantFeedback,
+    responses(
+        (status = 202, description = "Recorded"),
+        (status = 400, description = "Rating outside 1 - 5", body = ErrorBody),
+    )
+)]
+pub(super) async fn merchant_feedback(
+    Extension(reputation): Extension<Arc<ReputationService>>,
+    Path(domain): Path<MerchantDomain>,
+    Json(payload): Json<MerchantFeedback>,
+) -> Result<StatusCode, ApiError> {
+    if !(1.0..=5.0).contains(&payload.rating) {
+        return Err(ApiError::bad_request("rating must be between 1 and 5"));
+    }
+
+    reputation.record_feedback(&domain, payload.rating).await;
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Coupon-test and scrape outcome counts reported by internal workers
+#[utoipa::path(
+    post,
+    path = "/merchants/{domain}/signals",
+    tag = "merchants",
+    params(("domain" = String, Path, description = "Merchant domain, e.g. `amazon.com`")),
+    request_body = SignalUpdate,
+    responses(
+        (status = 202, description = "Recorded"),
+    )
+)]
+pub(super) async fn merchant_signals(
+    Extension(reputation): Extension<Arc<ReputationService>>,
+    Extension(top): Extension<Arc<TopCoupons>>,
+    Path(domain): Path<MerchantDomain>,
+    Json(update): Json<SignalUpdate>,
+) -> StatusCode {
+    reputation.record_signals(&domain, update).await;
+    // Coupon success signals move the merchant's codes' predicted success
+    top.mark_stale(&domain);
+    StatusCode::ACCEPTED
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(super) struct YieldQuery {
+    /// How far back to look (default 30, at most the retention period)
+    days: Option<i64>,
+    #[serde(default)]
+    interval: YieldInterval,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct MerchantYield {
+    domain: MerchantDomain,
+    since: DateTime<Utc>,
+    interval: YieldInterval,
+    points: Vec<YieldPoint>,
+    budget: BudgetUsage,
+}
+
+/// Scrape yield over time, for spotting merchants whose pages stopped parsing
+#[utoipa::path(
+    get,
+    path = "/admin/merchants/{domain}/yield",
+    tag = "admin",
+    params(
+        ("domain" = String, Path, description = "Merchant domain, e.g. `amazon.com`"),
+        YieldQuery,
+    ),
+    responses(
+        (status = 200, description = "The merchant's scrape `yield` and `budget`", body = MerchantYield),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn merchant_yield(
+    Extension(yield_stats): Extension<Arc<YieldStats>>,
+    Extension(budgets): Extension<Arc<ScrapeBudgets>>,
+    Path(domain): Path<MerchantDomain>,
+    Query(query): Query<YieldQuery>,
+) -> Reply<MerchantYield> {
+    let days = query.days.unwrap_or(30).clamp(1, RETENTION_DAYS);
+    let since = yield_stats.now() - chrono::TimeDelta::days(days);
+
+    Reply(MerchantYield {
+        points: yield_stats.series(&domain, since, query.interval).await,
+        budget: budgets.usage(&domain).await,
+        domain,
+        since,
+        interval: query.interval,
+    })
+}
@@ -0,0 +1,449 @@
+//! HTTP API
+//!
+//! [`router`] wires every endpoint to the shared [`Services`]; handlers receive
+//! the services they need through `Extension` layers. JSON responses are shaped
+//! for the request's tenant (see [`crate::tenant::shaping`]), and requests made with
+//! a sandbox API key are served by the tenant's sandbox (see [`crate::sandbox`]).
+//! Coupon text follows `Accept-Language` (see [`crate::localization`]), and offers
+//! follow the compliance rules of the requester's country (see [`crate::compliance`]). The
+//! `/admin/*` endpoints need a role that grants their permission (see [`crate::rbac`]),
+//! and partner API keys only reach the routes of their scopes (see [`crate::api_keys`]);
+//! the endpoints acting for a shopper need their JWT when one is configured (see
+//! [`crate::auth`]).
+//! Coupons are only served to API keys whose tenant may receive their license (see
+//! [`crate::licensing`]). `/widget/{merchant}` serves partner pages, with the caching
+//! headers CDNs need (see [`crate::widget`]), and `/graphql` serves the same data to
+//! frontends as a GraphQL schema (see [`graphql`]). Every handler is described in the OpenAPI document served
+//! at `/openapi.json` (see [`openapi`]). Browsers may call each route from the
+//! origins its CORS rule allows (see [`crate::cors`]). Every request carries an
+//! `x-request-id` and W3C trace context, returned with errors (see [`correlation`]).
+//!
+//! Every endpoint is served under [`V1`], where JSON responses are wrapped in the
+//! `data`/`meta`/`errors` envelope (see [`envelope`]); a breaking change gets a
+//! `/api/v2` tree next to it. The unversioned paths stay as they were for the
+//! callers that predate it.
+
+mod access;
+mod account;
+mod admin;
+mod alerts;
+mod analytics;
+mod auth;
+mod clipping;
+mod collections;
+mod compliance;
+mod correlation;
+mod cors;
+mod coupons;
+mod deals;
+mod digests;
+mod envelope;
+mod events;
+mod fetch;
+mod graphql;
+mod jobs;
+mod keys;
+mod licensing;
+mod localization;
+mod merchants;
+pub mod openapi;
+mod partners;
+mod products;
+mod reply;
+pub mod requests;
+mod scraper;
+mod shaping;
+mod sharing;
+mod status;
+mod stream;
+mod tags;
+mod users;
+mod widget;
+
+use std::sync::Arc;
+
+use axum::{
+    extract::Extension,
+    http::StatusCode,
+    middleware,
+    routing::{delete, get, post, put},
+    Router,
+};
+use serde::Serialize;
+use tower_http::compression::predicate::{DefaultPredicate, Predicate, SizeAbove};
+use tower_http::compression::CompressionLayer;
+use tower_http::decompression::RequestDecompressionLayer;
+use utoipa::ToSchema;
+
+use self::reply::Reply;
+use crate::app::Services;
+use crate::health::{HealthMonitor, HealthReport, HealthStatus};
+
+/// Where the current version of the API is nested
+pub const V1: &str = "/api/v1";
+
+/// `path` without the [`V1`] prefix, for checks that apply to every version
+pub(crate) fn unversioned(path: &str) -> &str {
+    path.strip_prefix(V1).filter(|rest| rest.starts_with('/')).unwrap_or(path)
+}
+
+pub fn router(services: &Services) -> Router {
+    let tenancy = shaping::Tenancy {
+        tenants: services.tenants.clone(),
+        sandboxes: services.sandboxes.clone(),
+        usage: services.usage.clone(),
+        api_keys: services.api_keys.clone(),
+    };
+
+    routes(services)
+        .layer(middleware::from_fn_with_state(tenancy, shaping::shape_responses))
+        // Outside shaping, so tenant field renames apply inside `data`
+        .layer(middleware::from_fn(envelope::envelope_responses))
+        // Outside the envelope, which takes the IDs from it, and inside compression,
+        // so error bodies can still be read
+        .layer(middleware::from_fn(correlation::correlate))
+        // Bodies may be gzip/zstd encoded; large responses (exports, price history) are compressed
+        .layer(RequestDecompressionLayer::new())
+        .layer(CompressionLayer::new().compress_when(DefaultPredicate::new().and(SizeAbove::new(1024))))
+        // Outermost, so preflights are answered before keys and roles are checked
+        .layer(middleware::from_fn_with_state(services.cors.clone(), cors::apply_cors))
+}
+
+/// Every endpoint over `services` under [`V1`] and unversioned, without the
+/// envelope, tenant and transport layers
+fn routes(services: &Services) -> Router {
+    let endpoints = endpoints(services);
+    Router::new().nest(V1, endpoints.clone()).merge(endpoints)
+}
+
+fn endpoints(services: &Services) -> Router {
+    Router::new()
+        .route("/health", get(health))
+        .route("/health/live", get(live))
+        .route("/openapi.json", get(openapi::openapi_json))
+        .route("/docs", get(openapi::swagger_ui))
+        .route("/metrics", get(admin::metrics))
+        .route("/account/usage", get(account::usage))
+        .route("/status/freshness", get(status::freshness))
+        .route("/deals", get(deals::get_deals))
+        .route("/collections", get(collections::list_collections))
+        .route("/collections/:slug", get(collections::get_collection))
+        .route("/deals/search", get(deals::search_deals))
+        .route("/deals/stream", get(stream::deal_stream))
+        .route("/deals/facets", get(deals::deal_facets))
+        .route("/deals/trending", get(deals::trending_deals))
+        .route("/deals/features", get(deals::export_deal_features))
+        .route("/deals/duplicates", get(deals::duplicate_deals))
+        .route("/deals/import", post(deals::import_deals))
+        .route("/deals/interactions", post(deals::record_interaction))
+        .route("/deals/:id/similar", get(deals::similar_deals))
+        .route("/deals/:id/frequently-bought-with", get(deals::frequently_bought_with))
+        .route("/deals/:id/effective-price", get(deals::effective_price))
+        .route("/deals/comments", post(deals::ingest_comments))
+        .route("/deals/:id/community", get(deals::community_summary))
+        .route("/coupons", get(coupons::get_coupons))
+        .route("/coupons/asof", get(coupons::coupons_as_of))
+        .route("/coupons/for-url", get(coupons::coupons_for_url))
+        .route("/coupons/outcomes", post(coupons::record_coupon_outcome))
+        .route("/coupons/model", get(coupons::coupon_model))
+        .route("/coupons/model/training-data", get(coupons::export_coupon_training_data))
+        .route("/extension/result", post(coupons::record_extension_result))
+        .route(
+            "/coupons/subscriptions",
+            get(coupons::list_coupon_subscriptions).post(coupons::create_coupon_subscription),
+        )
+        .route("/coupons/subscriptions/:id", delete(coupons::delete_coupon_subscription))
+        .route("/coupons/validate", post(coupons::validate_coupon))
+        .route("/coupons/:id/share", post(sharing::share_coupon))
+        .route("/share/:token", get(sharing::open_share))
+        .route("/share/:token/qr", get(sharing::share_qr))
+        .route("/products/:id/forecast", get(products::forecast_price))
+        .route("/products/:id/compare", get(products::compare_prices))
+        .route("/merchants/reputation", get(merchants::merchant_rankings))
+        .route("/merchants/liveness", get(merchants::merchant_liveness))
+        .route("/merchants/:domain/coupons", get(coupons::top_coupons))
+        .route("/merchants/:domain/reputation", get(merchants::merchant_reputation))
+        .route("/merchants/:domain/feedback", post(merchants::merchant_feedback))
+        .route("/merchants/:domain/signals", post(merchants::merchant_signals))
+        .route("/events/upcoming", get(events::upcoming_events))
+        .route("/events/:id/deals", get(events::event_deals))
+        .route("/digests/daily", get(digests::daily_digest))
+        .route(
+            "/digests/subscriptions",
+            get(digests::list_digest_subscriptions).post(digests::create_digest_subscription),
+        )
+        .route("/digests/subscriptions/:id", delete(digests::delete_digest_subscription))
+        .route("/digests/subscriptions/:id/preview", get(digests::preview_digest))
+        .route("/fetch", post(fetch::fetch_page))
+        .route("/graphql", get(graphql::graphql_sdl).post(graphql::graphql))
+        .route("/clipping/platforms", get(clipping::clipping_platforms))
+        .route("/clipping/:platform", post(clipping::clip_offers))
+        .route("/jobs", post(jobs::submit_job))
+        .route("/jobs/:id", get(jobs::get_job).delete(jobs::cancel_job))
+        .route("/partners/merchants", post(partners::register_merchant))
+        .route("/partners/merchants/:id", get(partners::get_merchant))
+        .route("/partners/merchants/:id/verify", post(partners::verify_merchant))
+        .route("/partners/feed", post(partners::submit_feed))
+        .route("/partners/feed/:id", get(partners::get_feed_submission))
+        .route(
+            "/widget/:merchant",
+            get(widget::widget).route_layer(middleware::from_extractor::<widget::WidgetKey>()),
+        )
+        .merge(user_routes())
+        .merge(admin_routes())
+        .route_layer(middleware::from_extractor::<keys::KeyScope>())
+        .layer(Extension(services.deal_store.clone()))
+        .layer(Extension(services.coupon_store.clone()))
+        .layer(Extension(services.coupon_history.clone()))
+        .layer(Extension(services.coupon_predictor.clone()))
+        .layer(Extension(services.coupon_deltas.clone()))
+        .layer(Extension(services.top_coupons.clone()))
+        .layer(Extension(services.scorer.clone()))
+        .layer(Extension(services.forecaster.clone()))
+        .layer(Extension(services.discount_auditor.clone()))
+        .layer(Extension(services.alert_parser.clone()))
+        .layer(Extension(services.recommendations.clone()))
+        .layer(Extension(services.community.clone()))
+        .layer(Extension(services.reputation.clone()))
+        .layer(Extension(services.events.clone()))
+        .layer(Extension(services.search.clone()))
+        .layer(Extension(services.experiments.clone()))
+        .layer(Extension(services.ranking.clone()))
+        .layer(Extension(services.digests.clone()))
+        .layer(Extension(services.coupon_engine.clone()))
+        .layer(Extension(services.scrape_jobs.clone()))
+        .layer(Extension(services.import_limits.clone()))
+        .layer(Extension(services.price_verifier.clone()))
+        .layer(Extension(services.onboarding.clone()))
+        .layer(Extension(services.yield_stats.clone()))
+        .layer(Extension(services.scrape_budgets.clone()))
+        .layer(Extension(services.canaries.clone()))
+        .layer(Extension(services.liveness.clone()))
+        .layer(Extension(services.reprocessor.clone()))
+        .layer(Extension(services.backfills.clone()))
+        .layer(Extension(services.seeder.clone()))
+        .layer(Extension(services.fetch_service.clone()))
+        .layer(Extension(services.domain_profiles.clone()))
+        .layer(Extension(services.opt_outs.clone()))
+        .layer(Extension(services.extraction_blocklist.clone()))
+        .layer(Extension(services.tags.clone()))
+        .layer(Extension(services.scraper_controls.clone()))
+        .layer(Extension(services.coupon_analytics.clone()))
+        .layer(Extension(services.health.clone()))
+        .layer(Extension(services.savings.clone()))
+        .layer(Extension(services.rewards.clone()))
+        .layer(Extension(services.shipping_rules.clone()))
+        .layer(Extension(services.stacksmart.clone()))
+        .layer(Extension(services.notifications.clone()))
+        .layer(Extension(services.scheduled_digests.clone()))
+        .layer(Extension(services.shares.clone()))
+        .layer(Extension(services.collections.clone()))
+        .layer(Extension(services.clipping.clone()))
+        .layer(Extension(services.sla.clone()))
+        .layer(Extension(services.deal_stream.clone()))
+        .layer(Extension(services.tenants.clone()))
+        .layer(Extension(services.access.clone()))
+        .layer(Extension(services.api_keys.clone()))
+        .layer(Extension(services.jwt.clone()))
+        .layer(Extension(services.licenses.clone()))
+        .layer(Extension(services.compliance.clone()))
+        .layer(Extension(services.scrubber.clone()))
+        .layer(Extension(graphql::schema(services)))
+        // Inside localization, so excluded keywords match the text as written
+        .layer(middleware::from_fn_with_state(
+            compliance::Compliance {
+                rules: services.compliance.clone(),
+                tenants: services.tenants.clone(),
+            },
+            compliance::filter_responses,
+        ))
+        // Inside tenant shaping, which may rename the translated fields
+        .layer(middleware::from_fn_with_state(services.translator.clone(), localization::localize_responses))
+}
+
+/// The endpoints acting for a shopper, behind their bearer token
+fn user_routes() -> Router {
+    Router::new()
+        .route("/coupons/test", post(coupons::test_coupons))
+        .route("/stacksmart", post(coupons::optimize_deals))
+        .route("/alerts/natural", post(alerts::create_natural_alert))
+        .route("/users/:id/savings", get(users::list_savings).post(users::record_savings))
+        .route("/users/:id/savings/summary", get(users::savings_summary))
+        .route(
+            "/users/:id/notification-preferences",
+            get(users::get_notification_preferences).put(users::put_notification_preferences),
+        )
+        .route_layer(middleware::from_fn(auth::require_user))
+}
+
+/// The `/admin/*` endpoints and the internal analytics, each behind the permission
+/// [`access`] requires for it
+fn admin_routes() -> Router {
+    Router::new()
+        .route("/admin/partner-coupons/pending", get(partners::pending_partner_coupons))
+        .route("/admin/partner-coupons/:id/review", post(partners::review_partner_coupon))
+        .route("/admin/merchants/:domain/yield", get(merchants::merchant_yield))
+        .route("/admin/merchants/:domain/liveness", post(merchants::check_merchant_liveness))
+        .route("/admin/jobs/dead-letter", get(jobs::dead_letters))
+        .route("/admin/jobs/dead-letter/:id/retry", post(jobs::retry_dead_letter))
+        .route("/admin/savings/export", get(users::export_savings))
+        .route("/admin/parsers/shadow", get(admin::shadow_parser_report).delete(admin::reset_shadow_parser))
+        .route("/admin/domain-profiles", get(admin::list_domain_profiles))
+        .route(
+            "/admin/domain-profiles/:domain",
+            get(admin::get_domain_profile)
+                .put(admin::put_domain_profile)
+                .delete(admin::delete_domain_profile),
+        )
+        .route("/admin/perf/stages", get(admin::stage_report).delete(admin::reset_stage_report))
+        .route("/admin/redirects", get(admin::redirect_report))
+        .route("/admin/canaries", get(admin::canary_report))
+        .route("/admin/canaries/:domain/check", post(admin::check_canary))
+        .route("/admin/canaries/:domain/accept", post(admin::accept_canary))
+        .route("/admin/opt-outs", get(admin::list_opt_outs))
+        .route("/admin/opt-outs/audit", get(admin::opt_out_audit))
+        .route("/admin/opt-outs/:domain", put(admin::put_opt_out).delete(admin::delete_opt_out))
+        .route("/admin/blocklist", get(admin::list_block_rules).post(admin::create_block_rule))
+        .route("/admin/blocklist/:id", delete(admin::delete_block_rule))
+        .route("/admin/tags", get(tags::list_tags))
+        .route("/admin/tags/:slug", put(tags::put_tag).delete(tags::delete_tag))
+        .route("/admin/tags/deals/:id", get(tags::get_deal_tags).put(tags::put_deal_tags))
+        .route("/admin/tags/coupons/:id", get(tags::get_coupon_tags).put(tags::put_coupon_tags))
+        .route("/admin/scraper/batches", post(scraper::enqueue_batch))
+        .route("/admin/scraper/domains", get(scraper::scraper_domains))
+        .route("/admin/scraper/domains/:domain/pause", post(scraper::pause_domain))
+        .route("/admin/scraper/domains/:domain/resume", post(scraper::resume_domain))
+        .route("/admin/scraper/config", get(scraper::scraper_config).put(scraper::put_scraper_config))
+        .route("/admin/collections", get(collections::admin_list_collections))
+        .route(
+            "/admin/collections/:slug",
+            put(collections::put_collection).delete(collections::delete_collection),
+        )
+        .route("/admin/collections/:slug/preview", get(collections::preview_collection))
+        .route("/admin/shipping-rules", get(admin::list_shipping_rules))
+        .route(
+            "/admin/shipping-rules/:domain",
+            get(admin::get_shipping_rule)
+                .put(admin::put_shipping_rule)
+                .delete(admin::delete_shipping_rule),
+        )
+        .route("/admin/sla", get(admin::sla_report))
+        .route("/admin/pii", get(admin::pii_audit))
+        .route("/admin/reprocess", post(admin::start_reprocess))
+        .route("/admin/reprocess/:id", get(admin::get_reprocess))
+        .route("/admin/backfills", get(admin::list_backfills).post(admin::start_backfill))
+        .route("/admin/backfills/:id", get(admin::get_backfill))
+        .route("/admin/backfills/:id/pause", post(admin::pause_backfill))
+        .route("/admin/backfills/:id/resume", post(admin::resume_backfill))
+        .route("/admin/seed", post(admin::start_seed))
+        .route("/admin/seed/:id", get(admin::get_seed))
+        .route("/admin/experiments", get(admin::list_experiments))
+        .route("/admin/experiments/:id", put(admin::upsert_experiment))
+        .route("/admin/experiments/:id/readout", get(admin::experiment_readout))
+        .route("/admin/licenses", get(licensing::list_licenses))
+        .route(
+            "/admin/licenses/:source",
+            put(licensing::put_license).delete(licensing::delete_license),
+        )
+        .route("/admin/api-keys", get(keys::list_api_keys).post(keys::issue_api_key))
+        .route("/admin/api-keys/:id", delete(keys::revoke_api_key))
+        .route("/admin/roles", get(access::list_roles))
+        .route("/admin/roles/:subject", put(access::put_roles).delete(access::delete_roles))
+        .route("/analytics/coupons", get(analytics::coupon_analytics))
+        .route_layer(middleware::from_extractor::<access::AdminAccess>())
+}
+
+#[derive(Serialize, ToSchema)]
+struct Readiness {
+    #[serde(flatten)]
+    report: HealthReport,
+    features: [&'static str; 3],
+}
+
+/// Readiness: probes Redis and Postgres and reports each background task
+#[utoipa::path(
+    get,
+    path = "/health",
+    tag = "status",
+    responses(
+        (status = 200, description = "`healthy`, or `degraded` with a background task down; the `checks` by dependency", body = Readiness),
+        (status = 503, description = "`unhealthy`: a critical dependency is down", body = Readiness),
+    )
+)]
+async fn health(Extension(monitor): Extension<Arc<HealthMonitor>>) -> (StatusCode, Reply<Readiness>) {
+    let report = monitor.check().await;
+    let status = match report.status {
+        HealthStatus::Unhealthy => StatusCode::SERVICE_UNAVAILABLE,
+        HealthStatus::Healthy | HealthStatus::Degraded => StatusCode::OK,
+    };
+    (
+        status,
+        Reply(Readiness {
+            report,
+            features: ["deals", "coupons", "stacksmart"],
+        }),
+    )
+}
+
+#[derive(Serialize, ToSchema)]
+struct Liveness {
+    status: &'static str,
+}
+
+/// Liveness: answers while the process serves requests, without probing anything
+#[utoipa::path(
+    get,
+    path = "/health/live",
+    tag = "status",
+    responses(
+        (status = 200, description = "The process is up", body = Liveness),
+    )
+)]
+async fn live() -> Reply<Liveness> {
+    Reply(Liveness { status: "alive" })
+}
+
+/// Requests through the full [`router`] over sandbox services, for the endpoint tests
+#[cfg(test)]
+pub(crate) mod tests {
+    use axum::body::{to_bytes, Body};
+    use axum::http::Request;
+    use axum::response::Response;
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    use crate::app::Services;
+
+    /// Services over the seeded sandbox catalogue
+    pub async fn sandbox() -> Services {
+        Services::builder().sandbox(7).build().await
+    }
+
+    pub async fn send(services: &Services, request: Request<Body>) -> Response {
+        super::router(services).oneshot(request).await.unwrap()
+    }
+
+    /// The response body as JSON, `null` when it is not
+    pub async fn json(response: Response) -> Value {
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice(&body).unwrap_or_default()
+    }
+
+    /// Status and JSON body of `request`
+    pub async fn call(services: &Services, request: Request<Body>) -> (u16, Value) {
+        let response = send(services, request).await;
+        (response.status().as_u16(), json(response).await)
+    }
+
+    pub async fn get(services: &Services, path: &str) -> (u16, Value) {
+        call(services, Request::get(path).body(Body::empty()).unwrap()).await
+    }
+
+    pub async fn post(services: &Services, path: &str, body: &Value) -> (u16, Value) {
+        let request = Request::post(path)
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        call(services, request).await
+    }
+}
@@ -0,0 +1,786 @@
+//! OpenAPI document of the HTTP API
+//!
+//! Every handler carries a `#[utoipa::path]` annotation; [`ApiDoc`] collects them
+//! with the request and model schemas into the document served at `/openapi.json`,
+//! and `/docs` renders it with Swagger UI. Paths are relative to the `/api/v1`
+//! server, so every JSON response is described inside the envelope (see
+//! [`super::envelope`]) with the handler's response struct as its payload.
+
+use axum::{
+    http::header,
+    response::{Html, IntoResponse},
+};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::openapi::schema::{ArrayBuilder, ObjectBuilder, Ref};
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, Http, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::openapi::{Content, RefOr, Server};
+use utoipa::{Modify, OpenApi, ToSchema};
+
+use super::requests::{
+    CodeAttempt, CouponOutcome, ExtensionResult, FetchRequest, JobRequest, MerchantFeedback, NaturalAlertRequest,
+    ValidateCouponRequest,
+};
+use crate::alerts::natural_language::{AlertInterpretation, ParserKind};
+use crate::api_keys::{ApiKeyRecord, IssuedKey, KeyRequest, Scope};
+use crate::backfill::{Backfill, BackfillKind, BackfillRequest, BackfillStatus};
+use crate::clipping::{ClipFailure, ClipReport, ClipRequest, PlatformOffer};
+use crate::collections::{Collection, CollectionView};
+use crate::community::{CommunitySummary, IngestReport};
+use crate::coupon_deltas::{BestCode, CorpusDelta, Delivery, DeltaKind, Subscription, SubscriptionRequest};
+use crate::analytics::{AnalyticsPoint, AnalyticsReport};
+use crate::coupon_engine::archive::SnapshotFilter;
+use crate::coupon_engine::budget::{BudgetUsage, MerchantSize};
+use crate::coupon_engine::canary::{CanaryResult, PageCheck, PageFingerprint};
+use crate::coupon_engine::concurrency::ConcurrencySnapshot;
+use crate::coupon_engine::controls::{EngineSettings, Pause, PauseRequest};
+use crate::coupon_engine::blocklist::{BlockRule, BlockRuleRequest, PatternSyntax, RuleTarget, RuleUsage};
+use crate::coupon_engine::liveness::{LandingFingerprint, MerchantLiveness, MerchantStatus};
+use crate::coupon_engine::memory::{BatchMemorySnapshot, MemorySnapshot};
+use crate::coupon_engine::opt_out::{AuditEntry, BlockedAt, OptOut, OptOutEvent, OptOutRequest};
+use crate::coupon_engine::normalize::EmojiPolicy;
+use crate::coupon_engine::profiles::{DomainProfile, ProfileSettings};
+use crate::coupon_engine::redirects::{FlaggedChain, RedirectAudit, RedirectFinding};
+use crate::coupon_engine::shadow::{MerchantShadowDiff, ShadowReport};
+use crate::coupon_engine::stages::{Bucket, Stage, StageReport, StageStats};
+use crate::coupon_engine::validator::{CouponValidation, FailureReason, ValidationFailure};
+use crate::coupon_engine::yield_stats::{YieldCounts, YieldInterval, YieldPoint};
+use crate::coupon_engine::{DiscountType, EngineConfig, PageCoupons, RawCoupon, SourceType, UrlResult};
+use crate::coupon_success::features::CouponFeatures;
+use crate::coupon_success::{CouponSuccessModel, TrainingRow};
+use crate::digest::{DailyDigest, DigestTopic};
+use crate::digest::scheduled::{
+    DigestSubscription, DigestSubscriptionRequest, ExpiringCoupon, Frequency, PriceChange, Recipient, RenderedDigest,
+    SavedCoupon, ScheduledDigest,
+};
+use crate::events::EventOccurrence;
+use crate::experiments::{Experiment, ExperimentReadout, RankingStrategy, Variant, VariantReadout};
+use crate::forecast::{ForecastPoint, PriceForecast, Recommendation};
+use crate::freshness::{FreshnessReport, MerchantFreshness, PlatformFreshness};
+use crate::health::{DependencyHealth, HealthReport, HealthStatus};
+use crate::jobs::{DeadLetter, JobPriority, JobStatus, ScrapeJob};
+use crate::licensing::{SourceRecord, SourceTerms, SourceTermsRequest};
+use crate::models::alert::{AlertType, DealAlert};
+use crate::models::comment::CommunityComment;
+use crate::models::coupon_listing::{CouponListing, CouponSource, License, LicenseTag};
+use crate::models::deal::{Deal, DealStatus};
+use crate::models::domain::{CouponCode, Currency, MerchantDomain, Money};
+use crate::models::experiment::ExperimentAssignment;
+use crate::models::interaction::{Interaction, InteractionKind};
+use crate::notifications::{Channel, NotificationPreferences, QuietHours};
+use crate::onboarding::verification::VerificationMethod;
+use crate::onboarding::{
+    AccountStatus, FeedCoupon, FeedItem, FeedSubmission, MerchantAccount, ModerationStatus, Registration, TxtRecord,
+    VerificationInstructions,
+};
+use crate::pricing::rewards::{EffectivePrice, GiftCardPurchase};
+use crate::pricing::shipping::{DeliveredPrice, FreeShipping, ShippingContext, ShippingEstimate};
+use crate::privacy::{PiiKind, ScrubCount};
+use crate::rbac::{Permission, Role, Subject};
+use crate::reprocess::{CorpusChange, CorpusDiff, ReprocessRequest, ReprocessRun, RunStatus};
+use crate::reputation::{MerchantReputation, SignalUpdate};
+use crate::savings::{MerchantSavings, MonthSavings, SavingsEntry, SavingsExportRow, SavingsReport, SavingsSummary, SavingsTotal};
+use crate::scoring::FeatureRow;
+use crate::scoring::features::DealFeatures;
+use crate::search::SearchHit;
+use crate::search::facets::{DiscountRange, Facets, PriceRange, ValueCount};
+use crate::search::query::ParsedQuery;
+use crate::seeding::{FeedFailure, SeedRequest, SeedRun};
+use crate::services::dedup::{DuplicatePair, MatchReason};
+use crate::sharing::{Share, ShareLink, ShareRequest};
+use crate::sla::{PlatformSla, SlaAlert, SlaReport};
+use crate::stacksmart::optimizer::SavingsBreakdown;
+use crate::stacksmart::{Cart, CartItem, CartPlan, Deal as StackableDeal, DealType};
+use crate::storage::coupon_history::HistoricalCoupon;
+use crate::storage::import::ImportReport;
+use crate::storage::shipping_rules::ShippingRule;
+use crate::tagging::{TagAssignment, TagDefinition, TagRequest, TagTarget};
+use crate::telemetry::TraceContext;
+use crate::tenant::API_KEY_HEADER;
+use crate::tenant::usage::{DailyUsage, KeyUsage, WindowUsage};
+use crate::widget::{Widget, WidgetCoupon, WidgetDeal};
+
+use super::shaping::is_exempt;
+use super::V1;
+
+/// Swagger UI release the `/docs` page loads its assets from
+const SWAGGER_UI_ASSETS: &str = "https://unpkg.com/swagger-ui-dist@5";
+
+/// The body of every error response
+#[derive(Serialize, ToSchema)]
+pub(super) struct ErrorBody {
+    error: String,
+}
+
+/// A GraphQL request, which `/graphql` takes as is. Only here to be described:
+/// the handler hands the body to `async_graphql` to parse
+#[allow(dead_code)]
+#[derive(Deserialize, ToSchema)]
+pub(super) struct GraphqlRequest {
+    query: String,
+    #[schema(value_type = Option<Object>)]
+    variables: Option<Value>,
+    #[serde(rename = "operationName")]
+    operation_name: Option<String>,
+}
+
+/// A GraphQL response, which `/graphql` returns without the envelope
+#[derive(Serialize, ToSchema)]
+pub(super) struct GraphqlResponse {
+    #[schema(value_type = Object, nullable)]
+    data: Value,
+    /// Only when a field failed
+    #[schema(value_type = Option<Vec<Object>>)]
+    errors: Option<Vec<Value>>,
+}
+
+/// `api_key` is the `X-Api-Key` tenants call with (our own apps call without one);
+/// `partner_key` the bearer key a merchant got when it registered; `user_jwt` the
+/// identity provider's token the shopper endpoints need when JWTs are configured
+struct Credentials;
+
+impl Modify for Credentials {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme("api_key", SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new(API_KEY_HEADER))));
+        components.add_security_scheme("partner_key", SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)));
+        components.add_security_scheme(
+            "user_jwt",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).bearer_format("JWT").build()),
+        );
+    }
+}
+
+/// Describe [`V1`]: its server, and each JSON response as its envelope. Successes
+/// carry the payload in `data`; errors, with or without a body of their own, are
+/// an `Envelope` with `errors`.
+struct Versioned;
+
+impl Modify for Versioned {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        openapi.servers = Some(vec![Server::new(V1)]);
+        let operations = openapi
+            .paths
+            .paths
+            .iter_mut()
+            .filter(|(path, _)| !is_exempt(path))
+            .flat_map(|(_, item)| item.operations.values_mut());
+        for operation in operations {
+            for (status, response) in operation.responses.responses.iter_mut() {
+                let RefOr::T(response) = response else {
+                    continue;
+                };
+                if status.starts_with('4') || status.starts_with('5') {
+                    response
+                        .content
+                        .insert("application/json".to_string(), Content::new(Ref::from_schema_name("Envelope")));
+                } else if let Some(content) = response.content.get_mut("application/json") {
+                    let payload = std::mem::replace(&mut content.schema, Ref::from_schema_name("Envelope").into());
+                    content.schema = ObjectBuilder::new()
+                        .property("data", payload)
+                        .required("data")
+                        .property("meta", Ref::from_schema_name("Meta"))
+                        .required("meta")
+                        .property("errors", ArrayBuilder::new().items(Ref::from_schema_name("ErrorDetail")))
+                        .required("errors")
+                        .into();
+                }
+            }
+        }
+    }
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Deal Service", description = "Deals, coupons and price intelligence for DealMate"),
+    paths(
+        super::health,
+        super::live,
+        super::admin::metrics,
+        super::account::usage,
+        super::status::freshness,
+        super::deals::get_deals,
+        super::collections::list_collections,
+        super::collections::get_collection,
+        super::deals::search_deals,
+        super::stream::deal_stream,
+        super::deals::deal_facets,
+        super::deals::trending_deals,
+        super::deals::export_deal_features,
+        super::deals::duplicate_deals,
+        super::deals::import_deals,
+        super::deals::record_interaction,
+        super::deals::similar_deals,
+        super::deals::frequently_bought_with,
+        super::deals::effective_price,
+        super::deals::ingest_comments,
+        super::deals::community_summary,
+        super::coupons::get_coupons,
+        super::coupons::coupons_as_of,
+        super::coupons::coupons_for_url,
+        super::coupons::record_coupon_outcome,
+        super::coupons::coupon_model,
+        super::coupons::export_coupon_training_data,
+        super::coupons::record_extension_result,
+        super::coupons::list_coupon_subscriptions,
+        super::coupons::create_coupon_subscription,
+        super::coupons::delete_coupon_subscription,
+        super::coupons::test_coupons,
+        super::coupons::validate_coupon,
+        super::sharing::share_coupon,
+        super::sharing::open_share,
+        super::sharing::share_qr,
+        super::coupons::optimize_deals,
+        super::products::forecast_price,
+        super::products::compare_prices,
+        super::users::list_savings,
+        super::users::record_savings,
+        super::users::savings_summary,
+        super::users::get_notification_preferences,
+        super::users::put_notification_preferences,
+        super::alerts::create_natural_alert,
+        super::merchants::merchant_rankings,
+        super::merchants::merchant_liveness,
+        super::coupons::top_coupons,
+        super::merchants::merchant_reputation,
+        super::merchants::merchant_feedback,
+        super::merchants::merchant_signals,
+        super::events::upcoming_events,
+        super::events::event_deals,
+        super::digests::daily_digest,
+        super::digests::list_digest_subscriptions,
+        super::digests::create_digest_subscription,
+        super::digests::delete_digest_subscription,
+        super::digests::preview_digest,
+        super::fetch::fetch_page,
+        super::graphql::graphql,
+        super::graphql::graphql_sdl,
+        super::clipping::clipping_platforms,
+        super::clipping::clip_offers,
+        super::jobs::submit_job,
+        super::jobs::get_job,
+        super::jobs::cancel_job,
+        super::partners::register_merchant,
+        super::partners::get_merchant,
+        super::partners::verify_merchant,
+        super::partners::submit_feed,
+        super::partners::get_feed_submission,
+        super::partners::pending_partner_coupons,
+        super::partners::review_partner_coupon,
+        super::merchants::merchant_yield,
+        super::merchants::check_merchant_liveness,
+        super::jobs::dead_letters,
+        super::jobs::retry_dead_letter,
+        super::users::export_savings,
+        super::admin::shadow_parser_report,
+        super::admin::reset_shadow_parser,
+        super::admin::list_domain_profiles,
+        super::admin::get_domain_profile,
+        super::admin::put_domain_profile,
+        super::admin::delete_domain_profile,
+        super::admin::stage_report,
+        super::admin::reset_stage_report,
+        super::admin::redirect_report,
+        super::admin::canary_report,
+        super::admin::check_canary,
+        super::admin::accept_canary,
+        super::admin::list_opt_outs,
+        super::admin::put_opt_out,
+        super::admin::delete_opt_out,
+        super::admin::opt_out_audit,
+        super::admin::list_block_rules,
+        super::admin::create_block_rule,
+        super::admin::delete_block_rule,
+        super::tags::list_tags,
+        super::tags::put_tag,
+        super::tags::delete_tag,
+        super::tags::get_deal_tags,
+        super::tags::put_deal_tags,
+        super::tags::get_coupon_tags,
+        super::tags::put_coupon_tags,
+        super::analytics::coupon_analytics,
+        super::scraper::enqueue_batch,
+        super::scraper::scraper_domains,
+        super::scraper::pause_domain,
+        super::scraper::resume_domain,
+        super::scraper::scraper_config,
+        super::scraper::put_scraper_config,
+        super::collections::admin_list_collections,
+        super::collections::put_collection,
+        super::collections::delete_collection,
+        super::collections::preview_collection,
+        super::admin::list_shipping_rules,
+        super::admin::get_shipping_rule,
+        super::admin::put_shipping_rule,
+        super::admin::delete_shipping_rule,
+        super::admin::sla_report,
+        super::admin::pii_audit,
+        super::admin::start_reprocess,
+        super::admin::get_reprocess,
+        super::admin::start_backfill,
+        super::admin::list_backfills,
+        super::admin::get_backfill,
+        super::admin::pause_backfill,
+        super::admin::resume_backfill,
+        super::admin::start_seed,
+        super::admin::get_seed,
+        super::admin::list_experiments,
+        super::admin::upsert_experiment,
+        super::admin::experiment_readout,
+        super::licensing::list_licenses,
+        super::licensing::put_license,
+        super::licensing::delete_license,
+        super::access::list_roles,
+        super::access::put_roles,
+        super::access::delete_roles,
+        super::keys::list_api_keys,
+        super::keys::issue_api_key,
+        super::keys::revoke_api_key,
+        super::widget::widget,
+    ),
+    components(schemas(
+        ErrorBody,
+        super::deals::DealsPage,
+        super::coupons::CouponList,
+        super::jobs::JobResponse,
+        super::Readiness,
+        super::Liveness,
+        super::admin::Experiments,
+        super::admin::StoredExperiment,
+        super::admin::Readout,
+        super::admin::ShadowParser,
+        super::admin::StagePerformance,
+        super::admin::FlaggedRedirects,
+        super::admin::ProfileList,
+        super::admin::StoredDomainProfile,
+        super::admin::Canaries,
+        super::admin::CanaryCheck,
+        super::admin::OptOuts,
+        super::admin::StoredOptOut,
+        super::admin::OptOutAudit,
+        super::admin::BlockRules,
+        super::admin::StoredBlockRule,
+        super::admin::ShippingRules,
+        super::admin::MerchantShippingRule,
+        super::admin::ReprocessStatus,
+        super::admin::StoredBackfill,
+        super::admin::BackfillList,
+        super::admin::SeedStatus,
+        super::admin::SlaStatus,
+        super::admin::PiiAudit,
+        super::account::AccountUsage,
+        super::status::Freshness,
+        super::collections::CollectionViews,
+        super::collections::CollectionDetail,
+        super::deals::SearchResults,
+        super::deals::SearchFacets,
+        super::deals::TrendingDeals,
+        super::deals::DealFeatureExport,
+        super::deals::DealImport,
+        super::deals::DuplicateDeals,
+        super::deals::SimilarDeals,
+        super::deals::DealPricing,
+        super::deals::BoughtTogether,
+        super::deals::IngestedComments,
+        super::deals::DealCommunity,
+        super::coupons::CouponsAsOf,
+        super::coupons::PageResult,
+        super::coupons::RecordedAttempts,
+        super::coupons::CouponSubscription,
+        super::coupons::CouponSubscriptions,
+        super::coupons::CouponModel,
+        super::coupons::TrainingData,
+        super::coupons::CouponTest,
+        super::sharing::SharedLink,
+        super::sharing::OpenedShare,
+        super::coupons::OptimizedCart,
+        super::products::ProductForecast,
+        super::products::PriceComparison,
+        super::users::RecordedSaving,
+        super::users::Savings,
+        super::users::Summary,
+        super::users::SavingsExport,
+        super::users::Preferences,
+        super::alerts::NaturalAlert,
+        super::merchants::Reputation,
+        super::merchants::LivenessReport,
+        super::merchants::LivenessCheck,
+        super::merchants::MerchantRankings,
+        super::events::UpcomingEvents,
+        super::events::EventDeals,
+        super::digests::Daily,
+        super::digests::ScheduledSubscription,
+        super::digests::DigestSubscriptions,
+        super::digests::DigestPreview,
+        GraphqlRequest,
+        GraphqlResponse,
+        super::clipping::ClippingPlatforms,
+        super::clipping::Clipping,
+        super::partners::MerchantRegistration,
+        super::partners::VerifiedMerchant,
+        super::partners::SubmittedFeed,
+        super::partners::Submission,
+        super::partners::PendingSubmissions,
+        super::merchants::MerchantYield,
+        super::jobs::DeadLetters,
+        super::tags::Vocabulary,
+        super::tags::StoredTag,
+        super::tags::DealTags,
+        super::tags::CouponTags,
+        super::analytics::Analytics,
+        super::scraper::QueuedBatch,
+        super::scraper::ScraperDomains,
+        super::scraper::DomainPause,
+        super::scraper::ScraperConfig,
+        super::collections::CollectionList,
+        super::collections::StoredCollection,
+        super::licensing::Licenses,
+        super::licensing::StoredLicense,
+        super::access::RoleAssignments,
+        super::access::SubjectRoles,
+        super::keys::ApiKeyList,
+        super::keys::IssuedApiKey,
+        super::keys::RevokedApiKey,
+        super::widget::WidgetReply,
+        super::envelope::Envelope,
+        super::envelope::Meta,
+        super::envelope::ErrorDetail,
+        Deal,
+        DealStatus,
+        Money,
+        Currency,
+        MerchantDomain,
+        CouponCode,
+        CouponListing,
+        CouponSource,
+        License,
+        LicenseTag,
+        RawCoupon,
+        DiscountType,
+        SourceType,
+        UrlResult,
+        PageCoupons,
+        CouponValidation,
+        ScrapeJob,
+        TraceContext,
+        JobPriority,
+        JobStatus,
+        DeadLetter,
+        ExperimentAssignment,
+        Experiment,
+        Variant,
+        RankingStrategy,
+        Interaction,
+        InteractionKind,
+        CommunityComment,
+        CouponOutcome,
+        ExtensionResult,
+        CodeAttempt,
+        ValidateCouponRequest,
+        SubscriptionRequest,
+        Delivery,
+        ShareRequest,
+        Cart,
+        CartItem,
+        StackableDeal,
+        DealType,
+        ShippingContext,
+        SavingsReport,
+        NotificationPreferences,
+        Channel,
+        QuietHours,
+        NaturalAlertRequest,
+        MerchantFeedback,
+        SignalUpdate,
+        DigestSubscriptionRequest,
+        Recipient,
+        Frequency,
+        SavedCoupon,
+        FetchRequest,
+        ClipRequest,
+        JobRequest,
+        Registration,
+        VerificationMethod,
+        FeedCoupon,
+        super::partners::VerifyRequest,
+        super::partners::FeedRequest,
+        super::partners::ReviewRequest,
+        YieldInterval,
+        ProfileSettings,
+        MerchantSize,
+        Collection,
+        ShippingRule,
+        OptOut,
+        OptOutRequest,
+        AuditEntry,
+        OptOutEvent,
+        BlockRule,
+        BlockRuleRequest,
+        RuleUsage,
+        RuleTarget,
+        PatternSyntax,
+        TagDefinition,
+        TagTarget,
+        TagAssignment,
+        TagRequest,
+        BlockedAt,
+        Pause,
+        PauseRequest,
+        AnalyticsReport,
+        AnalyticsPoint,
+        HealthReport,
+        HealthStatus,
+        DependencyHealth,
+        EngineConfig,
+        EmojiPolicy,
+        EngineSettings,
+        ReprocessRequest,
+        BackfillRequest,
+        BackfillKind,
+        SeedRequest,
+        SnapshotFilter,
+        SourceTermsRequest,
+        SourceTerms,
+        SourceRecord,
+        super::access::RolesRequest,
+        Role,
+        Permission,
+        KeyRequest,
+        Scope,
+        Widget,
+        WidgetCoupon,
+        WidgetDeal,
+        AlertInterpretation,
+        ApiKeyRecord,
+        Backfill,
+        super::deals::BoughtWith,
+        BudgetUsage,
+        CanaryResult,
+        CartPlan,
+        ClipReport,
+        CollectionView,
+        CommunitySummary,
+        ConcurrencySnapshot,
+        CouponSuccessModel,
+        DailyDigest,
+        DealAlert,
+        super::licensing::DefaultLicense,
+        DeliveredPrice,
+        DigestSubscription,
+        super::scraper::DomainActivity,
+        DomainProfile,
+        DuplicatePair,
+        EffectivePrice,
+        EventOccurrence,
+        ExperimentReadout,
+        Facets,
+        FeatureRow,
+        FeedSubmission,
+        FlaggedChain,
+        FreshnessReport,
+        HistoricalCoupon,
+        ImportReport,
+        IngestReport,
+        IssuedKey,
+        KeyUsage,
+        MemorySnapshot,
+        MerchantAccount,
+        MerchantLiveness,
+        MerchantReputation,
+        ParsedQuery,
+        PriceForecast,
+        RenderedDigest,
+        ReprocessRun,
+        SavingsEntry,
+        SavingsExportRow,
+        SavingsSummary,
+        ScheduledDigest,
+        ScrubCount,
+        SearchHit,
+        SeedRun,
+        ShadowReport,
+        Share,
+        ShareLink,
+        super::deals::SimilarDeal,
+        SlaReport,
+        StageReport,
+        Subject,
+        Subscription,
+        TrainingRow,
+        ValidationFailure,
+        VerificationInstructions,
+        YieldPoint,
+        AccountStatus,
+        AlertType,
+        BatchMemorySnapshot,
+        ClipFailure,
+        CorpusDelta,
+        CorpusDiff,
+        CouponFeatures,
+        DailyUsage,
+        DealFeatures,
+        DigestTopic,
+        ExpiringCoupon,
+        FailureReason,
+        FeedFailure,
+        FeedItem,
+        ForecastPoint,
+        GiftCardPurchase,
+        LandingFingerprint,
+        MatchReason,
+        MerchantFreshness,
+        MerchantSavings,
+        MerchantShadowDiff,
+        MerchantStatus,
+        MonthSavings,
+        PageCheck,
+        ParserKind,
+        PiiKind,
+        PlatformFreshness,
+        PlatformOffer,
+        PlatformSla,
+        PriceChange,
+        DiscountRange,
+        PriceRange,
+        Recommendation,
+        RedirectAudit,
+        RunStatus,
+        SavingsBreakdown,
+        SavingsTotal,
+        ShippingEstimate,
+        SlaAlert,
+        Stage,
+        StageStats,
+        TxtRecord,
+        ValueCount,
+        VariantReadout,
+        WindowUsage,
+        YieldCounts,
+        BestCode,
+        Bucket,
+        CorpusChange,
+        DeltaKind,
+        FreeShipping,
+        ModerationStatus,
+        PageFingerprint,
+        RedirectFinding,
+        BackfillStatus,
+    )),
+    modifiers(&Credentials, &Versioned),
+    security((), ("api_key" = [])),
+    tags(
+        (name = "status", description = "Health, metrics, usage and data freshness"),
+        (name = "deals", description = "The deal catalogue, search and recommendations"),
+        (name = "coupons", description = "Coupon codes, their validation and checkout outcomes"),
+        (name = "merchants", description = "Merchant reputation, liveness, top coupons and savings widgets"),
+        (name = "products", description = "Price forecasts and comparisons"),
+        (name = "users", description = "Savings and notification preferences"),
+        (name = "digests", description = "Daily and scheduled digests"),
+        (name = "jobs", description = "Scrape jobs"),
+        (name = "partners", description = "Merchant self-service onboarding and feeds"),
+        (name = "admin", description = "Operations; each endpoint needs a role granting its permission"),
+    )
+)]
+pub struct ApiDoc;
+
+lazy_static! {
+    static ref DOCUMENT: String = ApiDoc::openapi().to_json().expect("the OpenAPI document serializes");
+}
+
+pub(super) async fn openapi_json() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "application/json")], DOCUMENT.as_str())
+}
+
+/// Swagger UI over `/openapi.json`
+pub(super) async fn swagger_ui() -> Html<String> {
+    Html(format!(
+        r##"<!DOCTYPE html>
+<html>
+<head>
+<title>Deal Service API</title>
+<link rel="stylesheet" href="{assets}/swagger-ui.css">
+</head>
+<body>
+<div id="swagger-ui"></div>
+<script src="{assets}/swagger-ui-bundle.js"></script>
+<script>SwaggerUIBundle({{ url: "/openapi.json", dom_id: "#swagger-ui" }});</script>
+</body>
+</html>
+"##,
+        assets = SWAGGER_UI_ASSETS
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use axum::body::Body;
+    use axum::http::Request;
+    use regex::Regex;
+    use serde_json::Value;
+
+    use crate::api::tests as api;
+
+    fn collect_refs<'a>(value: &'a Value, refs: &mut Vec<&'a str>) {
+        match value {
+            Value::Object(object) => {
+                if let Some(Value::String(target)) = object.get("$ref") {
+                    refs.push(target);
+                }
+                object.values().for_each(|value| collect_refs(value, refs));
+            }
+            Value::Array(values) => values.iter().for_each(|value| collect_refs(value, refs)),
+            _ => {}
+        }
+    }
+
+    #[tokio::test]
+    async fn test_documents_every_route_and_resolves_every_schema() {
+        let services = api::sandbox().await;
+        // Passed through untouched, whatever the caller's language
+        let request = Request::get("/openapi.json").header("accept-language", "de").body(Body::empty()).unwrap();
+        let (status, document) = api::call(&services, request).await;
+        assert_eq!(status, 200);
+
+        // The router's paths, in OpenAPI's `{param}` form, and its method handlers
+        let router = include_str!("mod.rs");
+        let router = &router[..router.find("\n#[utoipa::path").unwrap()];
+        let param = Regex::new(r":(\w+)").unwrap();
+        let routed: BTreeSet<String> = Regex::new(r#"\.route\(\s*"([^"]+)""#)
+            .unwrap()
+            .captures_iter(router)
+            .map(|route| param.replace_all(&route[1], "{$1}").into_owned())
+            .filter(|path| path != "/openapi.json" && path != "/docs")
+            .collect();
+        let handlers = Regex::new(r"\b(get|post|put|delete)\(").unwrap().find_iter(router).count() - 2;
+
+        assert_eq!(document["servers"][0]["url"], "/api/v1");
+        let paths = document["paths"].as_object().unwrap();
+        let listed = &paths["/deals"]["get"]["responses"]["200"]["content"]["application/json"]["schema"];
+        assert_eq!(listed["properties"]["data"]["$ref"], "#/components/schemas/DealsPage");
+        // Every JSON payload is typed
+        for (path, operations) in paths {
+            for (method, operation) in operations.as_object().unwrap() {
+                for (status, response) in operation["responses"].as_object().unwrap() {
+                    let schema = &response["content"]["application/json"]["schema"];
+                    let payload = if schema["properties"]["data"].is_null() { schema } else { &schema["properties"]["data"] };
+                    let typed = !payload["$ref"].is_null() || !payload["items"]["$ref"].is_null();
+                    assert!(schema.is_null() || typed, "{} {} {} is free-form", method, path, status);
+                }
+            }
+        }
+        assert_eq!(paths.keys().cloned().collect::<BTreeSet<_>>(), routed);
+        assert_eq!(paths.values().map(|operations| operations.as_object().unwrap().len()).sum::<usize>(), handlers);
+
+        let schemas = document["components"]["schemas"].as_object().unwrap();
+        assert!(schemas["RawCoupon"]["properties"]["merchant_domain"].is_object());
+        let mut refs = Vec::new();
+        collect_refs(&document, &mut refs);
+        for target in refs {
+            let name = target.strip_prefix("#/components/schemas/").unwrap();
+            assert!(schemas.contains_key(name), "{} is not among the schemas", name);
+        }
+    }
+}
@@ -0,0 +1,284 @@
+//! Merchant onboarding and first-party coupon feed endpoints
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Extension, Path},
+    http::{header, HeaderMap, StatusCode},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::reply::{ApiError, Reply};
+use crate::models::domain::CouponCode;
+use crate::onboarding::verification::VerificationMethod;
+use crate::onboarding::{
+    FeedCoupon, FeedSubmission, MerchantAccount, ModerationStatus, OnboardingError, OnboardingService, Registration,
+    VerificationInstructions,
+};
+use crate::privacy::{Scrubber, COUPON_DESCRIPTION, COUPON_TITLE};
+use crate::tenant::{TenantId, TenantRegistry};
+
+fn error_response(error: OnboardingError) -> ApiError {
+    let (status, message) = match error {
+        OnboardingError::Invalid(message) => (StatusCode::BAD_REQUEST, message),
+        OnboardingError::Conflict(message) => (StatusCode::CONFLICT, message),
+        OnboardingError::NotFound => (StatusCode::NOT_FOUND, "not found".to_string()),
+        OnboardingError::Unauthorized => (StatusCode::UNAUTHORIZED, "missing or invalid API key".to_string()),
+        OnboardingError::VerificationFailed(message) => (StatusCode::UNPROCESSABLE_ENTITY, message),
+    };
+    ApiError::new(status, message)
+}
+
+fn bearer_key(headers: &HeaderMap) -> Result<&str, ApiError> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::trim)
+        .ok_or_else(|| error_response(OnboardingError::Unauthorized))
+}
+
+/// A merchant account and how to verify it
+#[derive(Serialize, ToSchema)]
+pub(super) struct MerchantRegistration {
+    merchant: MerchantAccount,
+    verification: VerificationInstructions,
+}
+
+impl MerchantRegistration {
+    fn new(merchant: MerchantAccount) -> Self {
+        MerchantRegistration {
+            verification: merchant.verification_instructions(),
+            merchant,
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/partners/merchants",
+    tag = "partners",
+    request_body = Registration,
+    responses(
+        (status = 201, description = "The merchant `account`, its API key and verification instructions", body = MerchantRegistration),
+        (status = 400, description = "Invalid registration", body = ErrorBody),
+        (status = 409, description = "The domain is already registered", body = ErrorBody),
+    )
+)]
+pub(super) async fn register_merchant(
+    Extension(onboarding): Extension<Arc<OnboardingService>>,
+    Json(registration): Json<Registration>,
+) -> Result<(StatusCode, Reply<MerchantRegistration>), ApiError> {
+    let account = onboarding.register(registration).await.map_err(error_response)?;
+
+    Ok((StatusCode::CREATED, Reply(MerchantRegistration::new(account))))
+}
+
+#[utoipa::path(
+    get,
+    path = "/partners/merchants/{id}",
+    tag = "partners",
+    params(("id" = Uuid, Path, description = "Merchant id")),
+    responses(
+        (status = 200, description = "The merchant `account`", body = MerchantRegistration),
+        (status = 404, description = "No such merchant"),
+    )
+)]
+pub(super) async fn get_merchant(
+    Extension(onboarding): Extension<Arc<OnboardingService>>,
+    Path(merchant_id): Path<Uuid>,
+) -> Result<Reply<MerchantRegistration>, StatusCode> {
+    let account = onboarding.account(merchant_id).await.ok_or(StatusCode::NOT_FOUND)?;
+
+    Ok(Reply(MerchantRegistration::new(account)))
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(super) struct VerifyRequest {
+    method: VerificationMethod,
+}
+
+/// A verified merchant and its API key, shown only here
+#[derive(Serialize, ToSchema)]
+pub(super) struct VerifiedMerchant {
+    merchant: MerchantAccount,
+    api_key: String,
+}
+
+#[utoipa::path(
+    post,
+    path = "/partners/merchants/{id}/verify",
+    tag = "partners",
+    params(("id" = Uuid, Path, description = "Merchant id")),
+    request_body = VerifyRequest,
+    responses(
+        (status = 200, description = "The verified `account`", body = VerifiedMerchant),
+        (status = 404, description = "No such merchant", body = ErrorBody),
+        (status = 422, description = "The DNS record or meta tag was not found", body = ErrorBody),
+    )
+)]
+pub(super) async fn verify_merchant(
+    Extension(onboarding): Extension<Arc<OnboardingService>>,
+    Path(merchant_id): Path<Uuid>,
+    Json(request): Json<VerifyRequest>,
+) -> Result<Reply<VerifiedMerchant>, ApiError> {
+    let (account, api_key) = onboarding
+        .verify(merchant_id, request.method)
+        .await
+        .map_err(error_response)?;
+
+    Ok(Reply(VerifiedMerchant {
+        merchant: account,
+        api_key,
+    }))
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(super) struct FeedRequest {
+    coupons: Vec<FeedCoupon>,
+}
+
+/// A feed submission with its coupons counted by moderation status
+#[derive(Serialize, ToSchema)]
+pub(super) struct SubmittedFeed {
+    published: usize,
+    pending_review: usize,
+    rejected: usize,
+    submission: FeedSubmission,
+}
+
+#[utoipa::path(
+    post,
+    path = "/partners/feed",
+    tag = "partners",
+    request_body = FeedRequest,
+    responses(
+        (status = 202, description = "The feed `submission`", body = SubmittedFeed),
+        (status = 400, description = "Invalid coupons", body = ErrorBody),
+        (status = 401, description = "Missing or invalid merchant API key", body = ErrorBody),
+    ),
+    security(("partner_key" = []))
+)]
+pub(super) async fn submit_feed(
+    Extension(onboarding): Extension<Arc<OnboardingService>>,
+    Extension(tenants): Extension<Arc<TenantRegistry>>,
+    Extension(scrubber): Extension<Arc<Scrubber>>,
+    tenant: TenantId,
+    headers: HeaderMap,
+    Json(mut feed): Json<FeedRequest>,
+) -> Result<(StatusCode, Reply<SubmittedFeed>), ApiError> {
+    let api_key = bearer_key(&headers)?;
+
+    let policy = tenants.pii_policy(&tenant);
+    let fields = feed
+        .coupons
+        .iter_mut()
+        .flat_map(|coupon| {
+            let description = coupon.description.as_mut().map(|text| (COUPON_DESCRIPTION, text));
+            std::iter::once((COUPON_TITLE, &mut coupon.title)).chain(description)
+        })
+        .collect();
+    scrubber.scrub(&tenant, &policy, fields).await;
+
+    let submission = onboarding.submit_feed(api_key, feed.coupons).await.map_err(error_response)?;
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Reply(SubmittedFeed {
+            published: submission.count(ModerationStatus::Published),
+            pending_review: submission.count(ModerationStatus::PendingReview),
+            rejected: submission.count(ModerationStatus::Rejected),
+            submission,
+        }),
+    ))
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct Submission {
+    submission: FeedSubmission,
+}
+
+#[utoipa::path(
+    get,
+    path = "/partners/feed/{id}",
+    tag = "partners",
+    params(("id" = Uuid, Path, description = "Submission id")),
+    responses(
+        (status = 200, description = "The feed `submission` and its moderation status", body = Submission),
+        (status = 401, description = "Missing or invalid merchant API key", body = ErrorBody),
+        (status = 404, description = "No such submission", body = ErrorBody),
+    ),
+    security(("partner_key" = []))
+)]
+pub(super) async fn get_feed_submission(
+    Extension(onboarding): Extension<Arc<OnboardingService>>,
+    headers: HeaderMap,
+    Path(submission_id): Path<Uuid>,
+) -> Result<Reply<Submission>, ApiError> {
+    let api_key = bearer_key(&headers)?;
+    let submission = onboarding
+        .submission(api_key, submission_id)
+        .await
+        .map_err(error_response)?;
+
+    Ok(Reply(Submission { submission }))
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct PendingSubmissions {
+    submissions: Vec<FeedSubmission>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/partner-coupons/pending",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Partner coupons awaiting review", body = PendingSubmissions),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn pending_partner_coupons(Extension(onboarding): Extension<Arc<OnboardingService>>) -> Reply<PendingSubmissions> {
+    Reply(PendingSubmissions {
+        submissions: onboarding.pending_reviews().await,
+    })
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(super) struct ReviewRequest {
+    code: CouponCode,
+    approve: bool,
+    reason: Option<String>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/partner-coupons/{id}/review",
+    tag = "admin",
+    params(("id" = Uuid, Path, description = "Submission id")),
+    request_body = ReviewRequest,
+    responses(
+        (status = 200, description = "The reviewed `submission`", body = Submission),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+        (status = 404, description = "No such submission or code", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn review_partner_coupon(
+    Extension(onboarding): Extension<Arc<OnboardingService>>,
+    Path(submission_id): Path<Uuid>,
+    Json(review): Json<ReviewRequest>,
+) -> Result<Reply<Submission>, ApiError> {
+    let submission = onboarding
+        .review(submission_id, &review.code, review.approve, review.reason)
+        .await
+        .map_err(error_response)?;
+
+    Ok(Reply(Submission { submission }))
+}
@@ -0,0 +1,120 @@
+//! Product price forecasts and merchant comparison
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Extension, Path, Query},
+    http::StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use super::reply::{ApiError, Reply};
+use crate::forecast::{PriceForecast, PriceForecaster};
+use crate::pricing::shipping::{rank_by_delivered_price, DeliveredPrice, ShippingContext};
+use crate::storage::deal_store::DealStore;
+use crate::storage::shipping_rules::ShippingRuleStore;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(super) struct ForecastQuery {
+    days: Option<usize>,
+    model: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct ProductForecast {
+    product_id: String,
+    forecast: PriceForecast,
+}
+
+#[utoipa::path(
+    get,
+    path = "/products/{id}/forecast",
+    tag = "products",
+    params(
+        ("id" = String, Path, description = "Product id"),
+        ForecastQuery,
+    ),
+    responses(
+        (status = 200, description = "The product's price `forecast`", body = ProductForecast),
+        (status = 400, description = "Unknown model or too many days", body = ErrorBody),
+        (status = 404, description = "Not enough price history", body = ErrorBody),
+    )
+)]
+pub(super) async fn forecast_price(
+    Extension(store): Extension<Arc<DealStore>>,
+    Extension(forecaster): Extension<Arc<PriceForecaster>>,
+    Path(product_id): Path<String>,
+    Query(params): Query<ForecastQuery>,
+) -> Result<Reply<ProductForecast>, ApiError> {
+    if let Some(model) = &params.model {
+        if !forecaster.model_names().contains(&model.as_str()) {
+            return Err(ApiError::bad_request(format!("Unknown model '{}'", model)).with("models", forecaster.model_names()));
+        }
+    }
+
+    let horizon = params.days.unwrap_or(14).min(90);
+    let history = store.price_history(&product_id).await;
+
+    match forecaster.forecast(&history, horizon, params.model.as_deref()) {
+        Some(forecast) => Ok(Reply(ProductForecast { product_id, forecast })),
+        None => Err(ApiError::not_found("No price history for product")),
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(super) struct CompareQuery {
+    region: Option<String>,
+    /// Comma-separated, e.g. `prime,plus`
+    memberships: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct PriceComparison {
+    product_id: String,
+    offers: Vec<DeliveredPrice>,
+}
+
+/// The product's listings, cheapest delivered price first
+#[utoipa::path(
+    get,
+    path = "/products/{id}/compare",
+    tag = "products",
+    params(
+        ("id" = String, Path, description = "Product id"),
+        CompareQuery,
+    ),
+    responses(
+        (status = 200, description = "`listings`, cheapest delivered price first", body = PriceComparison),
+        (status = 404, description = "Unknown product"),
+    )
+)]
+pub(super) async fn compare_prices(
+    Extension(store): Extension<Arc<DealStore>>,
+    Extension(shipping): Extension<Arc<ShippingRuleStore>>,
+    Path(product_id): Path<String>,
+    Query(params): Query<CompareQuery>,
+) -> Result<Reply<PriceComparison>, StatusCode> {
+    let deals = store.for_product(&product_id).await;
+    if deals.is_empty() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let context = ShippingContext {
+        region: params.region,
+        memberships: params
+            .memberships
+            .iter()
+            .flat_map(|m| m.split(','))
+            .map(|m| m.trim().to_string())
+            .filter(|m| !m.is_empty())
+            .collect(),
+    };
+
+    Ok(Reply(PriceComparison {
+        offers: rank_by_delivered_price(deals, &shipping.list().await, &context),
+        product_id,
+    }))
+}
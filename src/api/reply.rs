@@ -0,0 +1,151 @@
+//! Typed JSON responses and errors
+//!
+//! Handlers return their response struct as a [`Reply`] and fail with an
+//! [`ApiError`]. Each is serialized once, in the form of the request's path:
+//! under [`V1`](super::V1) inside the [`Envelope`], on the unversioned paths as the
+//! struct's fields with the `service` tag. Errors name the request either way (see
+//! [`super::correlation`]). Responses rendered here carry [`Rendered`], so the
+//! envelope and correlation layers pass them on without reading their body.
+
+use std::future::Future;
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use super::correlation::RequestContext;
+use super::envelope::{Envelope, ErrorDetail};
+
+/// Tag of the unversioned responses
+const SERVICE: &str = "deal-service";
+
+tokio::task_local! {
+    static RENDERING: Rendering;
+}
+
+/// How the request being served wants its responses
+#[derive(Debug, Clone, Default)]
+pub(super) struct Rendering {
+    pub context: Option<RequestContext>,
+    /// Whether the request was made under [`V1`](super::V1)
+    pub versioned: bool,
+}
+
+impl Rendering {
+    fn current() -> Self {
+        RENDERING.try_with(Clone::clone).unwrap_or_default()
+    }
+
+    /// Run `future` with the current rendering as `change` leaves it
+    pub async fn scope<F: Future>(change: impl FnOnce(&mut Rendering), future: F) -> F::Output {
+        let mut rendering = Self::current();
+        change(&mut rendering);
+        RENDERING.scope(rendering, future).await
+    }
+}
+
+/// Set on the responses of [`Reply`] and [`ApiError`]
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Rendered {
+    /// Whether the body is an [`Envelope`]
+    pub versioned: bool,
+}
+
+/// A handler's response body
+pub(super) struct Reply<T>(pub T);
+
+/// An unversioned response: the body's fields and the service tag
+#[derive(Serialize)]
+struct Tagged<T> {
+    #[serde(flatten)]
+    data: T,
+    service: &'static str,
+}
+
+impl<T: Serialize> IntoResponse for Reply<T> {
+    fn into_response(self) -> Response {
+        let rendering = Rendering::current();
+        let mut response = match rendering.versioned {
+            true => Json(Envelope::success(self.0)).into_response(),
+            false => Json(Tagged {
+                data: self.0,
+                service: SERVICE,
+            })
+            .into_response(),
+        };
+        response.extensions_mut().insert(Rendered {
+            versioned: rendering.versioned,
+        });
+        response
+    }
+}
+
+/// A failed request: `{"error": message}` and its details, or one
+/// [`ErrorDetail`] in the envelope
+#[derive(Debug)]
+pub(super) struct ApiError {
+    status: StatusCode,
+    message: String,
+    details: Map<String, Value>,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+            details: Map::new(),
+        }
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::NOT_FOUND, message)
+    }
+
+    /// Say more about the error, e.g. the permission that was `required`
+    pub fn with(mut self, field: &str, value: impl Serialize) -> Self {
+        self.details.insert(field.to_string(), serde_json::to_value(value).unwrap_or_default());
+        self
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let rendering = Rendering::current();
+        let context = rendering.context.as_ref();
+        let mut response = match rendering.versioned {
+            true => {
+                let detail = ErrorDetail {
+                    status: self.status.as_u16(),
+                    message: self.message,
+                    details: (!self.details.is_empty()).then_some(Value::Object(self.details)),
+                    request_id: context.map(|context| context.request_id.clone()),
+                    trace_id: context.map(|context| context.trace_id.clone()),
+                };
+                (self.status, Json(Envelope::<()>::failure(detail))).into_response()
+            }
+            false => {
+                let mut fields = Map::new();
+                fields.insert("error".to_string(), Value::String(self.message));
+                fields.extend(self.details);
+                if let Some(context) = context {
+                    fields.insert("request_id".to_string(), context.request_id.clone().into());
+                    fields.insert("trace_id".to_string(), context.trace_id.clone().into());
+                }
+                (self.status, Json(Value::Object(fields))).into_response()
+            }
+        };
+        response.extensions_mut().insert(Rendered {
+            versioned: rendering.versioned,
+        });
+        response
+    }
+}
@@ -0,0 +1,75 @@
+//! Request bodies of the public endpoints
+//!
+//! The handlers deserialize these and the typed client (`client` feature) sends
+//! them, so the two cannot drift apart. Bodies that are domain types, such as
+//! [`crate::models::interaction::Interaction`] or [`crate::savings::SavingsReport`],
+//! live with their module instead.
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::jobs::JobPriority;
+use crate::models::domain::{CouponCode, MerchantDomain};
+
+/// `POST /coupons/outcomes`
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CouponOutcome {
+    pub merchant_domain: MerchantDomain,
+    pub code: CouponCode,
+    pub worked: bool,
+}
+
+/// One code the extension applied at checkout
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CodeAttempt {
+    pub code: CouponCode,
+    pub worked: bool,
+}
+
+/// `POST /extension/result`: the codes the extension applied at one checkout, in
+/// the order it tried them
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ExtensionResult {
+    pub merchant_domain: MerchantDomain,
+    pub attempts: Vec<CodeAttempt>,
+}
+
+/// `POST /coupons/validate`: a code as the shopper typed it, so malformed codes
+/// are reported as such rather than rejected with the body
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ValidateCouponRequest {
+    pub merchant_domain: String,
+    pub code: String,
+    /// Checked against the code's minimum order when given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub order_total: Option<Decimal>,
+}
+
+/// `POST /alerts/natural`
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct NaturalAlertRequest {
+    pub user_id: String,
+    pub text: String,
+}
+
+/// `POST /merchants/:domain/feedback`
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct MerchantFeedback {
+    /// 1 - 5
+    pub rating: f64,
+}
+
+/// `POST /jobs`
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct JobRequest {
+    pub urls: Vec<String>,
+    #[serde(default)]
+    pub priority: JobPriority,
+}
+
+/// `POST /fetch`
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct FetchRequest {
+    pub url: String,
+}
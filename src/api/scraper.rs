@@ -0,0 +1,305 @@
+//! Operator control of the scraping engine: batch enqueueing, per-merchant pauses,
+//! runtime engine settings and per-merchant counters
+
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+
+use axum::{
+    extract::{Extension, Path, Query},
+    http::StatusCode,
+    Json,
+};
+use chrono::TimeDelta;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use super::reply::{ApiError, Reply};
+use super::requests::JobRequest;
+use crate::coupon_engine::controls::{EngineSettings, Pause, PauseRequest, ScraperControls};
+use crate::coupon_engine::yield_stats::YieldStats;
+use crate::coupon_engine::{CouponEngine, EngineConfig};
+use crate::jobs::{ScrapeJob, ScrapeQueue};
+use crate::models::domain::MerchantDomain;
+use crate::tenant::TenantId;
+
+/// Longest window `/admin/scraper/domains` sums over, matching yield retention
+const MAX_HOURS: i64 = 90 * 24;
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct QueuedBatch {
+    jobs: Vec<ScrapeJob>,
+}
+
+/// Queue a batch of URLs, split into jobs of at most 100 URLs each
+#[utoipa::path(
+    post,
+    path = "/admin/scraper/batches",
+    tag = "admin",
+    request_body = JobRequest,
+    responses(
+        (status = 202, description = "The queued `jobs`", body = QueuedBatch),
+        (status = 400, description = "No URLs, too many, an invalid one, or only opted-out merchants", body = ErrorBody),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn enqueue_batch(
+    Extension(queue): Extension<Arc<ScrapeQueue>>,
+    tenant: TenantId,
+    Json(request): Json<JobRequest>,
+) -> Result<(StatusCode, Reply<QueuedBatch>), ApiError> {
+    match queue.submit_batch(&tenant.0, request.urls, request.priority).await {
+        Ok(jobs) => Ok((StatusCode::ACCEPTED, Reply(QueuedBatch { jobs }))),
+        Err(e) => Err(ApiError::bad_request(e)),
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(super) struct DomainsQuery {
+    /// Hours of runs to sum, 24 by default
+    hours: Option<i64>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct ScraperDomains {
+    hours: i64,
+    domains: BTreeMap<String, DomainActivity>,
+    paused: Vec<Pause>,
+}
+
+/// One merchant's scraping over the window
+#[derive(Serialize, ToSchema)]
+pub(super) struct DomainActivity {
+    urls_scraped: u32,
+    fetch_successes: u32,
+    fetch_failures: u32,
+    /// Of the fetches, to three decimals; `null` without any
+    success_rate: Option<f64>,
+    coupons_extracted: u32,
+    coupons_valid: u32,
+    coupons_new: u32,
+    paused: bool,
+}
+
+/// Per merchant, the URLs scraped, failed fetches and coupons found over the last
+/// `hours`, and whether it is paused
+#[utoipa::path(
+    get,
+    path = "/admin/scraper/domains",
+    tag = "admin",
+    params(DomainsQuery),
+    responses(
+        (status = 200, description = "The `domains`, by merchant, and the `paused` merchants", body = ScraperDomains),
+        (status = 400, description = "`hours` out of range", body = ErrorBody),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn scraper_domains(
+    Extension(yields): Extension<Arc<YieldStats>>,
+    Extension(controls): Extension<Arc<ScraperControls>>,
+    Query(query): Query<DomainsQuery>,
+) -> Result<Reply<ScraperDomains>, ApiError> {
+    let hours = query.hours.unwrap_or(24);
+    if !(1..=MAX_HOURS).contains(&hours) {
+        return Err(ApiError::bad_request(format!("hours must be between 1 and {}", MAX_HOURS)));
+    }
+
+    let paused = controls.paused();
+    let paused_domains: HashSet<&MerchantDomain> = paused.iter().map(|pause| &pause.domain).collect();
+    let domains = yields
+        .totals(yields.now() - TimeDelta::hours(hours))
+        .await
+        .into_iter()
+        .map(|(domain, counts)| {
+            let succeeded = counts.urls_scraped.saturating_sub(counts.fetch_failures);
+            let success_rate = (counts.urls_scraped > 0)
+                .then(|| (succeeded as f64 / counts.urls_scraped as f64 * 1000.0).round() / 1000.0);
+            let activity = DomainActivity {
+                urls_scraped: counts.urls_scraped,
+                fetch_successes: succeeded,
+                fetch_failures: counts.fetch_failures,
+                success_rate,
+                coupons_extracted: counts.coupons_extracted,
+                coupons_valid: counts.coupons_valid,
+                coupons_new: counts.coupons_new,
+                paused: paused_domains.contains(&domain),
+            };
+            (domain.to_string(), activity)
+        })
+        .collect();
+
+    Ok(Reply(ScraperDomains { hours, domains, paused }))
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct DomainPause {
+    pause: Pause,
+}
+
+/// Stop starting fetches for a merchant; its queued URLs wait until it is resumed
+#[utoipa::path(
+    post,
+    path = "/admin/scraper/domains/{domain}/pause",
+    tag = "admin",
+    params(("domain" = String, Path, description = "Merchant domain, e.g. `amazon.com`")),
+    request_body(content = PauseRequest, description = "Optional"),
+    responses(
+        (status = 200, description = "The `pause`, or the one already in place", body = DomainPause),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+        (status = 503, description = "The pause could not be stored", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn pause_domain(
+    Extension(controls): Extension<Arc<ScraperControls>>,
+    Path(domain): Path<MerchantDomain>,
+    request: Option<Json<PauseRequest>>,
+) -> Result<Reply<DomainPause>, ApiError> {
+    let request = request.map(|Json(request)| request).unwrap_or_default();
+    match controls.pause(domain, request).await {
+        Ok(pause) => Ok(Reply(DomainPause { pause })),
+        Err(e) => Err(ApiError::new(StatusCode::SERVICE_UNAVAILABLE, e)),
+    }
+}
+
+/// Let a paused merchant's URLs be fetched again
+#[utoipa::path(
+    post,
+    path = "/admin/scraper/domains/{domain}/resume",
+    tag = "admin",
+    params(("domain" = String, Path, description = "Merchant domain, e.g. `amazon.com`")),
+    responses(
+        (status = 204, description = "Resumed"),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+        (status = 404, description = "The merchant was not paused"),
+        (status = 503, description = "The change could not be stored", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn resume_domain(
+    Extension(controls): Extension<Arc<ScraperControls>>,
+    Path(domain): Path<MerchantDomain>,
+) -> Result<StatusCode, ApiError> {
+    match controls.resume(&domain).await {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Ok(StatusCode::NOT_FOUND),
+        Err(e) => Err(ApiError::new(StatusCode::SERVICE_UNAVAILABLE, e)),
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct ScraperConfig {
+    config: EngineConfig,
+    overrides: EngineSettings,
+}
+
+/// The engine's effective `config` and the operator's `overrides` behind it
+#[utoipa::path(
+    get,
+    path = "/admin/scraper/config",
+    tag = "admin",
+    responses(
+        (status = 200, description = "The effective `config` and the `overrides`", body = ScraperConfig),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn scraper_config(
+    Extension(engine): Extension<Arc<CouponEngine>>,
+    Extension(controls): Extension<Arc<ScraperControls>>,
+) -> Reply<ScraperConfig> {
+    Reply(ScraperConfig {
+        config: engine.config(),
+        overrides: controls.settings(),
+    })
+}
+
+/// Replace the overrides; batches started from then on use them, on every instance
+#[utoipa::path(
+    put,
+    path = "/admin/scraper/config",
+    tag = "admin",
+    request_body = EngineSettings,
+    responses(
+        (status = 200, description = "The effective `config` and the stored `overrides`", body = ScraperConfig),
+        (status = 400, description = "A setting out of range", body = ErrorBody),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+        (status = 503, description = "The settings could not be stored", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn put_scraper_config(
+    Extension(engine): Extension<Arc<CouponEngine>>,
+    Extension(controls): Extension<Arc<ScraperControls>>,
+    Json(settings): Json<EngineSettings>,
+) -> Result<Reply<ScraperConfig>, ApiError> {
+    if let Err(e) = settings.validate() {
+        return Err(ApiError::bad_request(e));
+    }
+
+    match controls.set_settings(settings).await {
+        Ok(overrides) => Ok(Reply(ScraperConfig {
+            config: engine.config(),
+            overrides,
+        })),
+        Err(e) => Err(ApiError::new(StatusCode::SERVICE_UNAVAILABLE, e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::body::Body;
+    use axum::http::{HeaderName, Request};
+    use serde_json::{json, Value};
+
+    use crate::api::tests as api;
+    use crate::app::Services;
+    use crate::rbac::{AccessControl, Role, Subject};
+
+    async fn call(services: &Services, method: &str, path: &str, body: Option<Value>) -> (u16, Value) {
+        let mut request = Request::builder().method(method).uri(path).header("x-forwarded-user", "ops");
+        if body.is_some() {
+            request = request.header("content-type", "application/json");
+        }
+        let body = body.map_or_else(Body::empty, |body| Body::from(body.to_string()));
+        api::call(services, request.body(body).unwrap()).await
+    }
+
+    #[tokio::test]
+    async fn test_operators_pause_merchants_and_retune_the_engine() {
+        let mut services = api::sandbox().await;
+        let access = AccessControl::new(None).with_user_header(HeaderName::from_static("x-forwarded-user"));
+        access.assign(Subject::User("ops".to_string()), [Role::Editor].into()).await.unwrap();
+        services.access = Arc::new(access);
+
+        let (status, body) = call(&services, "PUT", "/admin/scraper/config", Some(json!({"retry_attempts": 50}))).await;
+        assert_eq!((status, body["error"].is_string()), (400, true));
+        let (status, body) = call(&services, "PUT", "/admin/scraper/config", Some(json!({"max_concurrent_requests": 4}))).await;
+        assert_eq!(status, 200);
+        assert_eq!(body["config"]["max_concurrent_requests"], 4);
+        assert_eq!(services.coupon_engine.config().max_concurrent_requests, 4);
+
+        let (status, body) = call(&services, "POST", "/admin/scraper/domains/shop.example.com/pause", None).await;
+        assert_eq!((status, body["pause"]["domain"].as_str()), (200, Some("shop.example.com")));
+        let (_, body) = call(&services, "GET", "/admin/scraper/domains", None).await;
+        assert_eq!(body["paused"][0]["domain"], "shop.example.com");
+        assert_eq!(call(&services, "POST", "/admin/scraper/domains/shop.example.com/resume", None).await.0, 204);
+        assert_eq!(call(&services, "POST", "/admin/scraper/domains/shop.example.com/resume", None).await.0, 404);
+
+        let urls: Vec<String> = (0..250).map(|i| format!("https://shop.example.com/{}", i)).collect();
+        let (status, body) = call(&services, "POST", "/admin/scraper/batches", Some(json!({"urls": urls}))).await;
+        assert_eq!((status, body["jobs"].as_array().map(Vec::len)), (202, Some(3)));
+        let (status, _) = call(&services, "POST", "/admin/scraper/batches", Some(json!({"urls": ["not a url"]}))).await;
+        assert_eq!(status, 400);
+    }
+}
@@ -0,0 +1,211 @@
+//! Tenant resolution, rate limiting, sandbox dispatch and response shaping for
+//! every route
+
+use std::sync::Arc;
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::{MatchedPath, Request, State},
+    http::{header, response::Parts, HeaderName, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use serde_json::Value;
+use tower::ServiceExt;
+
+use super::reply::{ApiError, Rendered, Reply};
+use crate::api_keys::{hash_key, ApiKeys, KeyRejected};
+use crate::sandbox::Sandboxes;
+use crate::tenant::usage::{Admitted, MeteredKey, UsageMeter};
+use crate::tenant::{Caller, TenantId, TenantRegistry, API_KEY_HEADER};
+
+/// Largest JSON response that is reshaped or localized
+const MAX_SHAPED_BYTES: usize = 32 * 1024 * 1024;
+
+#[derive(Clone)]
+pub(super) struct Tenancy {
+    pub tenants: Arc<TenantRegistry>,
+    pub sandboxes: Arc<Sandboxes>,
+    pub usage: Arc<UsageMeter>,
+    pub api_keys: Arc<ApiKeys>,
+}
+
+/// `POST /sandbox/reset`
+#[derive(Serialize)]
+struct SandboxReset {
+    reset: bool,
+    seed: u64,
+}
+
+/// Resolve the tenant for the handlers and apply its response shape.
+///
+/// Requests made with an API key are counted against the key's quota and refused
+/// with a 429 once it is used up; their responses carry `X-RateLimit-Limit`,
+/// `X-RateLimit-Remaining` and `X-RateLimit-Reset`. Keys that are not a tenant's are
+/// looked up among the partner keys, which count against their own quota and whose
+/// record is handed on for the scope check. Sandbox requests are routed to the tenant's sandbox instead, where
+/// `POST /sandbox/reset` restores the seeded catalogue. `/fetch` passes merchant
+/// pages through untouched, whatever their content type, and `/openapi.json` is
+/// served as generated.
+pub(super) async fn shape_responses(
+    State(tenancy): State<Tenancy>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let mut partner_quota = None;
+    let caller = match tenancy.tenants.resolve(request.headers()) {
+        Ok(caller) => caller,
+        Err(_) => {
+            let key = request.headers().get(API_KEY_HEADER).and_then(|v| v.to_str().ok()).unwrap_or_default();
+            let record = match tenancy.api_keys.authenticate(key).await {
+                Ok(record) => record,
+                Err(rejected) => {
+                    let error = match rejected {
+                        KeyRejected::Unknown => "unknown API key",
+                        KeyRejected::Revoked => "API key revoked",
+                    };
+                    return ApiError::new(StatusCode::UNAUTHORIZED, error).into_response();
+                }
+            };
+            partner_quota = record.requests_per_minute;
+            let caller = Caller {
+                tenant: TenantId::from_headers(request.headers()),
+                sandbox: false,
+                api_key: Some(hash_key(key.trim())),
+            };
+            request.extensions_mut().insert(record);
+            caller
+        }
+    };
+
+    let Some(key) = caller.api_key.clone() else {
+        return serve(tenancy, caller, request, next).await;
+    };
+    let metered = MeteredKey {
+        meter: tenancy.usage.clone(),
+        key,
+        quota: partner_quota.or_else(|| tenancy.tenants.quota(&caller.tenant)),
+    };
+    let endpoint = match request.extensions().get::<MatchedPath>() {
+        // Both versions of a route count as one endpoint
+        Some(path) => format!("{} {}", request.method(), super::unversioned(path.as_str())),
+        None => format!("{} other", request.method()),
+    };
+    let admitted = match metered.meter.admit(&metered.key, &endpoint, metered.quota).await {
+        Ok(admitted) => admitted,
+        Err(exceeded) => {
+            return (
+                [
+                    (header::RETRY_AFTER, exceeded.retry_after.as_secs().max(1).to_string()),
+                    (HeaderName::from_static("x-ratelimit-limit"), exceeded.limit.to_string()),
+                    (HeaderName::from_static("x-ratelimit-remaining"), "0".to_string()),
+                ],
+                ApiError::new(StatusCode::TOO_MANY_REQUESTS, "rate limit exceeded").with("limit_per_minute", exceeded.limit),
+            )
+                .into_response()
+        }
+    };
+    request.extensions_mut().insert(metered);
+
+    let mut response = serve(tenancy, caller, request, next).await;
+    rate_limit_headers(&mut response, &admitted);
+    response
+}
+
+fn rate_limit_headers(response: &mut Response, admitted: &Admitted) {
+    let headers = response.headers_mut();
+    for (name, value) in [
+        ("x-ratelimit-limit", admitted.limit.to_string()),
+        ("x-ratelimit-remaining", admitted.remaining.to_string()),
+        ("x-ratelimit-reset", admitted.resets_at.timestamp().to_string()),
+    ] {
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            headers.insert(HeaderName::from_static(name), value);
+        }
+    }
+}
+
+async fn serve(tenancy: Tenancy, caller: Caller, mut request: Request, next: Next) -> Response {
+    let Tenancy { tenants, sandboxes, .. } = tenancy;
+    let tenant = caller.tenant.clone();
+    let exempt = is_exempt(request.uri().path());
+    request.extensions_mut().insert(tenant.clone());
+    // For the admin endpoints' role checks
+    request.extensions_mut().insert(caller.clone());
+
+    let response = match caller.sandbox {
+        false => next.run(request).await,
+        true if request.method() == Method::POST && super::unversioned(request.uri().path()) == "/sandbox/reset" => {
+            sandboxes.reset(&tenant).await;
+            Reply(SandboxReset {
+                reset: true,
+                seed: sandboxes.seed(),
+            })
+            .into_response()
+        }
+        true => {
+            let services = sandboxes.services_for(&tenant).await;
+            match super::routes(&services).oneshot(request).await {
+                Ok(response) => response,
+                Err(never) => match never {},
+            }
+        }
+    };
+    let Some(shape) = tenants.get(&tenant.0).map(|record| &record.response) else {
+        return response;
+    };
+    if exempt || shape.is_identity() {
+        return response;
+    }
+
+    let (parts, mut value) = match json_body(response).await {
+        Ok(json) => json,
+        Err(response) => return response,
+    };
+    match parts.extensions.get::<Rendered>() {
+        // Inside the envelope, as the tenant's records are
+        Some(Rendered { versioned: true }) => match value.get_mut("data") {
+            Some(data) if !data.is_null() => shape.apply(data),
+            _ => {}
+        },
+        _ => shape.apply(&mut value),
+    }
+    json_response(parts, &value)
+}
+
+/// Whether responses on `path`, in any version, are passed through untouched
+pub(super) fn is_exempt(path: &str) -> bool {
+    let path = super::unversioned(path);
+    path.starts_with("/fetch") || path == "/openapi.json" || path == "/graphql"
+}
+
+/// A JSON response's parts and parsed body; any other response is handed back as it is
+pub(super) async fn json_body(response: Response) -> Result<(Parts, Value), Response> {
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"));
+    if !is_json {
+        return Err(response);
+    }
+
+    let (parts, body) = response.into_parts();
+    let bytes = match to_bytes(body, MAX_SHAPED_BYTES).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            tracing::warn!(error = %e, "Failed to buffer a JSON response");
+            return Err(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+        }
+    };
+    match serde_json::from_slice::<Value>(&bytes) {
+        Ok(value) => Ok((parts, value)),
+        Err(_) => Err(Response::from_parts(parts, Body::from(bytes))),
+    }
+}
+
+pub(super) fn json_response(mut parts: Parts, value: &Value) -> Response {
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, Body::from(value.to_string()))
+}
@@ -0,0 +1,143 @@
+//! Coupon share link endpoints
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Extension, Path, Query},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use super::licensing::Licensed;
+use super::reply::{ApiError, Reply};
+use crate::models::coupon_listing::CouponListing;
+use crate::sharing::qr::{self, QrCode};
+use crate::sharing::{parse_coupon_id, Share, ShareError, ShareLink, ShareRequest, ShareService};
+use crate::storage::coupon_store::CouponStore;
+
+/// Pixels per QR module
+const QR_SCALE: u32 = 8;
+
+fn error_response(error: ShareError) -> ApiError {
+    match error {
+        ShareError::NotFound => ApiError::not_found("share link not found"),
+        ShareError::Expired => ApiError::new(StatusCode::GONE, "share link expired"),
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct SharedLink {
+    link: ShareLink,
+    /// Path of the link's QR code
+    qr: String,
+}
+
+/// Create a share link for the coupon `{code}@{merchant}`
+#[utoipa::path(
+    post,
+    path = "/coupons/{id}/share",
+    tag = "coupons",
+    params(("id" = String, Path, description = "The coupon as `{code}@{merchant}`")),
+    request_body = Option<ShareRequest>,
+    responses(
+        (status = 201, description = "The share `link`", body = SharedLink),
+        (status = 400, description = "Not `{code}@{merchant}`", body = ErrorBody),
+        (status = 404, description = "Unknown coupon", body = ErrorBody),
+    )
+)]
+pub(super) async fn share_coupon(
+    Extension(shares): Extension<Arc<ShareService>>,
+    Extension(coupons): Extension<Arc<CouponStore>>,
+    licensed: Licensed,
+    Path(id): Path<String>,
+    request: Option<Json<ShareRequest>>,
+) -> Result<(StatusCode, Reply<SharedLink>), ApiError> {
+    let (merchant, code) = parse_coupon_id(&id).map_err(ApiError::bad_request)?;
+    let Some(coupon) = licensed.filter(coupons.find(&merchant, &code).await).await else {
+        return Err(ApiError::not_found("coupon not found"));
+    };
+
+    let Json(request) = request.unwrap_or_default();
+    let link = shares.create(coupon.merchant_domain, coupon.code, request).await;
+    Ok((
+        StatusCode::CREATED,
+        Reply(SharedLink {
+            qr: format!("{}/qr", link.path),
+            link,
+        }),
+    ))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(super) struct OpenQuery {
+    /// Counted once towards the share's unique visitors
+    session_id: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct OpenedShare {
+    share: Share,
+    coupon: Option<CouponListing>,
+}
+
+/// The shared coupon, counting the open towards the sharer's attribution
+#[utoipa::path(
+    get,
+    path = "/share/{token}",
+    tag = "coupons",
+    params(
+        ("token" = String, Path, description = "Share token"),
+        OpenQuery,
+    ),
+    responses(
+        (status = 200, description = "The shared `coupon` and the share's visitor counts", body = OpenedShare),
+        (status = 404, description = "Unknown or expired share", body = ErrorBody),
+    )
+)]
+pub(super) async fn open_share(
+    Extension(shares): Extension<Arc<ShareService>>,
+    Extension(coupons): Extension<Arc<CouponStore>>,
+    licensed: Licensed,
+    Path(token): Path<String>,
+    Query(query): Query<OpenQuery>,
+) -> Result<Reply<OpenedShare>, ApiError> {
+    let share = shares.open(&token, query.session_id.as_deref()).await.map_err(error_response)?;
+    let coupon = licensed.filter(coupons.find(&share.merchant_domain, &share.code).await).await;
+    Ok(Reply(OpenedShare { share, coupon }))
+}
+
+/// The share link's URL as a PNG QR code; opening it is not counted
+#[utoipa::path(
+    get,
+    path = "/share/{token}/qr",
+    tag = "coupons",
+    params(("token" = String, Path, description = "Share token")),
+    responses(
+        (status = 200, description = "PNG QR code of the share URL", body = Vec<u8>, content_type = "image/png"),
+        (status = 404, description = "Unknown or expired share", body = ErrorBody),
+    )
+)]
+pub(super) async fn share_qr(
+    Extension(shares): Extension<Arc<ShareService>>,
+    Path(token): Path<String>,
+) -> Result<Response, ApiError> {
+    let link = shares.get(&token).await.map_err(error_response)?;
+    let Some(url) = link.url else {
+        return Err(ApiError::not_found("QR codes need SHARE_BASE_URL to be set"));
+    };
+    let Some(code) = QrCode::encode(url.as_bytes()) else {
+        return Err(ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "share URL too long for a QR code"));
+    };
+
+    match qr::png(&code, QR_SCALE) {
+        Ok(png) => Ok(([(header::CONTENT_TYPE, "image/png")], png).into_response()),
+        Err(e) => {
+            tracing::warn!(error = %e, "Failed to render a QR code");
+            Err(ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "failed to render QR code"))
+        }
+    }
+}
@@ -0,0 +1,37 @@
+//! Public service status
+
+use std::sync::Arc;
+
+use axum::extract::Extension;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use super::reply::Reply;
+use crate::coupon_engine::yield_stats::YieldStats;
+use crate::freshness::{self, FreshnessReport};
+use crate::storage::coupon_store::CouponStore;
+use crate::storage::deal_store::DealStore;
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct Freshness {
+    freshness: FreshnessReport,
+}
+
+/// When each merchant and platform was last ingested, and how much we hold for it
+#[utoipa::path(
+    get,
+    path = "/status/freshness",
+    tag = "status",
+    responses(
+        (status = 200, description = "`freshness`: per merchant and platform, when it was last ingested and what we hold", body = Freshness),
+    )
+)]
+pub(super) async fn freshness(
+    Extension(deals): Extension<Arc<DealStore>>,
+    Extension(coupons): Extension<Arc<CouponStore>>,
+    Extension(yields): Extension<Arc<YieldStats>>,
+) -> Reply<Freshness> {
+    Reply(Freshness {
+        freshness: freshness::report(&deals, &coupons, &yields, yields.now()).await,
+    })
+}
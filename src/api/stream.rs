@@ -0,0 +1,107 @@
+//! Server-sent deal stream, replayed from `Last-Event-ID` on reconnect
+
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use axum::{
+    extract::{Extension, Query},
+    http::HeaderMap,
+    response::sse::{Event, KeepAlive, Sse},
+};
+use futures_util::{stream, Stream, StreamExt};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::broadcast::error::RecvError;
+use utoipa::IntoParams;
+
+use super::reply::ApiError;
+use crate::stream::{DealStream, EventId, StreamEvent};
+use crate::tenant::{ResponseShape, TenantId, TenantRegistry};
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(super) struct StreamQuery {
+    /// For clients that cannot set the `Last-Event-ID` header
+    last_event_id: Option<String>,
+}
+
+/// `new_deal` and `price_drop` events, shaped for the tenant.
+///
+/// A reconnecting client gets the events after its `Last-Event-ID` first. When some
+/// may be missing, a `reset` event tells it to reload `/deals` before carrying on.
+#[utoipa::path(
+    get,
+    path = "/deals/stream",
+    tag = "deals",
+    params(StreamQuery),
+    responses(
+        (status = 200, description = "Server-sent `new_deal`, `price_drop` and `reset` events", body = String, content_type = "text/event-stream"),
+        (status = 503, description = "The stream is not available", body = ErrorBody),
+    )
+)]
+pub(super) async fn deal_stream(
+    Extension(deal_stream): Extension<Arc<DealStream>>,
+    Extension(tenants): Extension<Arc<TenantRegistry>>,
+    tenant: TenantId,
+    headers: HeaderMap,
+    Query(params): Query<StreamQuery>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    let last_event_id = headers
+        .get("last-event-id")
+        .and_then(|v| v.to_str().ok())
+        .map(String::from)
+        .or(params.last_event_id)
+        .filter(|id| !id.trim().is_empty());
+    let after = match last_event_id.map(|id| id.parse::<EventId>()) {
+        Some(Ok(id)) => Some(id),
+        Some(Err(e)) => return Err(ApiError::bad_request(e)),
+        None => None,
+    };
+    let shape = tenants.get(&tenant.0).map(|record| record.response.clone()).unwrap_or_default();
+
+    // Subscribe before replaying so nothing published in between is lost
+    let live = deal_stream.subscribe();
+    let mut backlog = Vec::new();
+    let mut last_sent = after;
+    if let Some(after) = after {
+        let replay = deal_stream.replay(after).await;
+        if replay.gap {
+            backlog.push(reset("some events since Last-Event-ID are no longer available"));
+        }
+        for event in &replay.events {
+            backlog.push(to_sse(event, &shape));
+        }
+        last_sent = replay.events.last().map(|e| e.id).or(last_sent);
+    }
+
+    let live = stream::unfold((live, last_sent, shape), |(mut live, mut last_sent, shape)| async move {
+        loop {
+            let sse = match live.recv().await {
+                // Already replayed
+                Ok(event) if last_sent.is_some_and(|last| event.id <= last) => continue,
+                Ok(event) => {
+                    last_sent = Some(event.id);
+                    to_sse(&event, &shape)
+                }
+                Err(RecvError::Lagged(_)) => reset("the client fell behind and missed events"),
+                Err(RecvError::Closed) => return None,
+            };
+            return Some((Ok(sse), (live, last_sent, shape)));
+        }
+    });
+
+    Ok(Sse::new(stream::iter(backlog.into_iter().map(Ok)).chain(live)).keep_alive(KeepAlive::default()))
+}
+
+fn to_sse(event: &StreamEvent, shape: &ResponseShape) -> Event {
+    let mut data = serde_json::to_value(event).unwrap_or(Value::Null);
+    shape.apply(&mut data);
+    Event::default()
+        .id(event.id.to_string())
+        .event(event.body.kind.as_str())
+        .data(data.to_string())
+}
+
+fn reset(reason: &str) -> Event {
+    Event::default().event("reset").data(json!({"reason": reason}).to_string())
+}
@@ -0,0 +1,277 @@
+//! Tag vocabulary and editor tagging of deals and coupons
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Extension, Path},
+    http::StatusCode,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use super::reply::{ApiError, Reply};
+use crate::sharing::parse_coupon_id;
+use crate::storage::coupon_store::CouponStore;
+use crate::storage::deal_store::DealStore;
+use crate::tagging::{self, TagAssignment, TagDefinition, TagError, TagRegistry, TagRequest, TagTarget};
+
+/// The `tags` filter shared by the list endpoints
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(super) struct TagFilter {
+    /// Comma-separated tags, e.g. `student-discount,app-only`; results have every one
+    tags: Option<String>,
+}
+
+impl TagFilter {
+    pub(super) fn tags(&self) -> Vec<String> {
+        tagging::parse_filter(self.tags.as_deref())
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct Vocabulary {
+    tags: Vec<TagDefinition>,
+}
+
+/// The controlled vocabulary
+#[utoipa::path(
+    get,
+    path = "/admin/tags",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Every defined tag, by slug", body = Vocabulary),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn list_tags(Extension(tags): Extension<Arc<TagRegistry>>) -> Reply<Vocabulary> {
+    Reply(Vocabulary {
+        tags: tags.vocabulary(),
+    })
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct StoredTag {
+    tag: TagDefinition,
+}
+
+/// Add a tag to the vocabulary or change it; tags with keywords apply themselves
+/// to the deals and coupons mentioning them
+#[utoipa::path(
+    put,
+    path = "/admin/tags/{slug}",
+    tag = "admin",
+    params(("slug" = String, Path, description = "Tag, e.g. `student-discount`")),
+    request_body = TagDefinition,
+    responses(
+        (status = 200, description = "The stored `tag`", body = StoredTag),
+        (status = 400, description = "Invalid slug, label or keywords", body = ErrorBody),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+        (status = 503, description = "The tag could not be stored", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn put_tag(
+    Extension(tags): Extension<Arc<TagRegistry>>,
+    Path(slug): Path<String>,
+    Json(definition): Json<TagDefinition>,
+) -> Result<Reply<StoredTag>, ApiError> {
+    match tags.define(&slug, definition).await {
+        Ok(tag) => Ok(Reply(StoredTag { tag })),
+        Err(e) => Err(tag_error(e)),
+    }
+}
+
+fn tag_error(e: TagError) -> ApiError {
+    let status = match e {
+        TagError::Invalid(_) => StatusCode::BAD_REQUEST,
+        TagError::Storage(_) => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    ApiError::new(status, e.to_string())
+}
+
+/// Take a tag out of the vocabulary, ending its keyword tagging; where editors
+/// applied it, it stays as a free-form tag
+#[utoipa::path(
+    delete,
+    path = "/admin/tags/{slug}",
+    tag = "admin",
+    params(("slug" = String, Path, description = "Tag, e.g. `student-discount`")),
+    responses(
+        (status = 204, description = "Deleted"),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+        (status = 404, description = "The tag is not in the vocabulary"),
+        (status = 503, description = "The tag could not be deleted", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn delete_tag(
+    Extension(tags): Extension<Arc<TagRegistry>>,
+    Path(slug): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    match tags.undefine(&slug).await {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Ok(StatusCode::NOT_FOUND),
+        Err(e) => Err(tag_error(e)),
+    }
+}
+
+fn not_found(what: &str) -> ApiError {
+    ApiError::not_found(format!("{} not found", what))
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct DealTags {
+    deal_id: String,
+    /// What editors set, if anything
+    assignment: Option<TagAssignment>,
+    tags: Vec<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct CouponTags {
+    /// `{code}@{merchant}`
+    coupon_id: String,
+    /// What editors set, if anything
+    assignment: Option<TagAssignment>,
+    tags: Vec<String>,
+}
+
+/// A deal's tags: what editors set and the resulting `tags`, keyword tags included
+#[utoipa::path(
+    get,
+    path = "/admin/tags/deals/{id}",
+    tag = "admin",
+    params(("id" = String, Path, description = "Deal ID")),
+    responses(
+        (status = 200, description = "The editors' `assignment`, if any, and the deal's `tags`", body = DealTags),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+        (status = 404, description = "Unknown deal", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn get_deal_tags(
+    Extension(tags): Extension<Arc<TagRegistry>>,
+    Extension(deals): Extension<Arc<DealStore>>,
+    Path(id): Path<String>,
+) -> Result<Reply<DealTags>, ApiError> {
+    let deal = deals.get(&id).await.ok_or_else(|| not_found("deal"))?;
+    Ok(Reply(DealTags {
+        assignment: tags.assignment(TagTarget::Deal, &id),
+        tags: tags.deal_tags(&deal),
+        deal_id: id,
+    }))
+}
+
+/// Replace the tags editors set on a deal, and the keyword tags they suppress on it
+#[utoipa::path(
+    put,
+    path = "/admin/tags/deals/{id}",
+    tag = "admin",
+    params(("id" = String, Path, description = "Deal ID")),
+    request_body = TagRequest,
+    responses(
+        (status = 200, description = "The stored `assignment` and the deal's `tags`", body = DealTags),
+        (status = 400, description = "Invalid tags", body = ErrorBody),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+        (status = 404, description = "Unknown deal", body = ErrorBody),
+        (status = 503, description = "The tags could not be stored", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn put_deal_tags(
+    Extension(tags): Extension<Arc<TagRegistry>>,
+    Extension(deals): Extension<Arc<DealStore>>,
+    Path(id): Path<String>,
+    Json(request): Json<TagRequest>,
+) -> Result<Reply<DealTags>, ApiError> {
+    let deal = deals.get(&id).await.ok_or_else(|| not_found("deal"))?;
+    let assignment = assign(&tags, TagTarget::Deal, &id, request).await?;
+    Ok(Reply(DealTags {
+        deal_id: id,
+        assignment,
+        tags: tags.deal_tags(&deal),
+    }))
+}
+
+/// A coupon's tags: what editors set and the resulting `tags`, keyword tags included
+#[utoipa::path(
+    get,
+    path = "/admin/tags/coupons/{id}",
+    tag = "admin",
+    params(("id" = String, Path, description = "Coupon ID, `{code}@{merchant}`")),
+    responses(
+        (status = 200, description = "The editors' `assignment`, if any, and the coupon's `tags`", body = CouponTags),
+        (status = 400, description = "Invalid coupon ID", body = ErrorBody),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+        (status = 404, description = "Unknown coupon", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn get_coupon_tags(
+    Extension(tags): Extension<Arc<TagRegistry>>,
+    Extension(coupons): Extension<Arc<CouponStore>>,
+    Path(id): Path<String>,
+) -> Result<Reply<CouponTags>, ApiError> {
+    let (merchant, code) = parse_coupon_id(&id).map_err(ApiError::bad_request)?;
+    let coupon = coupons.find(&merchant, &code).await.ok_or_else(|| not_found("coupon"))?;
+    let id = format!("{}@{}", code, merchant);
+    Ok(Reply(CouponTags {
+        assignment: tags.assignment(TagTarget::Coupon, &id),
+        tags: tags.coupon_tags(&coupon),
+        coupon_id: id,
+    }))
+}
+
+/// Replace the tags editors set on a coupon, and the keyword tags they suppress on it
+#[utoipa::path(
+    put,
+    path = "/admin/tags/coupons/{id}",
+    tag = "admin",
+    params(("id" = String, Path, description = "Coupon ID, `{code}@{merchant}`")),
+    request_body = TagRequest,
+    responses(
+        (status = 200, description = "The stored `assignment` and the coupon's `tags`", body = CouponTags),
+        (status = 400, description = "Invalid coupon ID or tags", body = ErrorBody),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+        (status = 404, description = "Unknown coupon", body = ErrorBody),
+        (status = 503, description = "The tags could not be stored", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn put_coupon_tags(
+    Extension(tags): Extension<Arc<TagRegistry>>,
+    Extension(coupons): Extension<Arc<CouponStore>>,
+    Path(id): Path<String>,
+    Json(request): Json<TagRequest>,
+) -> Result<Reply<CouponTags>, ApiError> {
+    let (merchant, code) = parse_coupon_id(&id).map_err(ApiError::bad_request)?;
+    let coupon = coupons.find(&merchant, &code).await.ok_or_else(|| not_found("coupon"))?;
+    let id = format!("{}@{}", code, merchant);
+    let assignment = assign(&tags, TagTarget::Coupon, &id, request).await?;
+    Ok(Reply(CouponTags {
+        coupon_id: id,
+        assignment,
+        tags: tags.coupon_tags(&coupon),
+    }))
+}
+
+/// The stored assignment, none once cleared
+async fn assign(
+    tags: &TagRegistry,
+    target: TagTarget,
+    id: &str,
+    request: TagRequest,
+) -> Result<Option<TagAssignment>, ApiError> {
+    tags.assign(target, id, request).await.map_err(tag_error)?;
+    Ok(tags.assignment(target, id))
+}
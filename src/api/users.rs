@@ -0,0 +1,227 @@
+//! Per-user savings ledger and notification preference endpoints
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Extension, Path, Query},
+    http::StatusCode,
+    Json,
+};
+use chrono::Datelike;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use super::reply::{ApiError, Reply};
+use crate::auth::UserContext;
+use crate::notifications::{NotificationDispatcher, NotificationPreferences};
+use crate::savings::{SavingsEntry, SavingsExportRow, SavingsLedger, SavingsReport, SavingsSummary};
+
+/// With JWTs configured, a user's ledger and preferences are the token's user's only
+fn only_own(user: Option<UserContext>, user_id: &str) -> Result<(), ApiError> {
+    match user.is_some_and(|user| user.user_id != user_id) {
+        true => Err(ApiError::new(
+            StatusCode::FORBIDDEN,
+            "only the signed-in user's savings and preferences are available",
+        )),
+        false => Ok(()),
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct RecordedSaving {
+    entry: SavingsEntry,
+}
+
+#[utoipa::path(
+    post,
+    path = "/users/{id}/savings",
+    tag = "users",
+    params(("id" = String, Path, description = "User id")),
+    request_body = SavingsReport,
+    responses(
+        (status = 201, description = "The recorded `saving`", body = RecordedSaving),
+        (status = 400, description = "Invalid amount", body = ErrorBody),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 403, description = "`id` is not the token's user", body = ErrorBody),
+    ),
+    security(("user_jwt" = []), ("user_jwt" = [], "api_key" = []))
+)]
+pub(super) async fn record_savings(
+    Extension(ledger): Extension<Arc<SavingsLedger>>,
+    user: Option<UserContext>,
+    Path(user_id): Path<String>,
+    Json(report): Json<SavingsReport>,
+) -> Result<(StatusCode, Reply<RecordedSaving>), ApiError> {
+    only_own(user, &user_id)?;
+    match ledger.record(&user_id, report).await {
+        Ok(entry) => Ok((StatusCode::CREATED, Reply(RecordedSaving { entry }))),
+        Err(e) => Err(ApiError::bad_request(e)),
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct Savings {
+    user_id: String,
+    entries: Vec<SavingsEntry>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/users/{id}/savings",
+    tag = "users",
+    params(("id" = String, Path, description = "User id")),
+    responses(
+        (status = 200, description = "The user's recorded `savings`", body = Savings),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 403, description = "`id` is not the token's user", body = ErrorBody),
+    ),
+    security(("user_jwt" = []), ("user_jwt" = [], "api_key" = []))
+)]
+pub(super) async fn list_savings(
+    Extension(ledger): Extension<Arc<SavingsLedger>>,
+    user: Option<UserContext>,
+    Path(user_id): Path<String>,
+) -> Result<Reply<Savings>, ApiError> {
+    only_own(user, &user_id)?;
+    Ok(Reply(Savings {
+        entries: ledger.entries(&user_id).await,
+        user_id,
+    }))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(super) struct SummaryQuery {
+    /// Calendar year; defaults to the current one
+    year: Option<i32>,
+    #[serde(default)]
+    all_time: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct Summary {
+    summary: SavingsSummary,
+}
+
+/// Totals behind "you saved $X this year", by merchant and by month
+#[utoipa::path(
+    get,
+    path = "/users/{id}/savings/summary",
+    tag = "users",
+    params(
+        ("id" = String, Path, description = "User id"),
+        SummaryQuery,
+    ),
+    responses(
+        (status = 200, description = "`summary` by merchant and by month", body = Summary),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 403, description = "`id` is not the token's user", body = ErrorBody),
+    ),
+    security(("user_jwt" = []), ("user_jwt" = [], "api_key" = []))
+)]
+pub(super) async fn savings_summary(
+    Extension(ledger): Extension<Arc<SavingsLedger>>,
+    user: Option<UserContext>,
+    Path(user_id): Path<String>,
+    Query(query): Query<SummaryQuery>,
+) -> Result<Reply<Summary>, ApiError> {
+    only_own(user, &user_id)?;
+    let year = (!query.all_time).then(|| query.year.unwrap_or_else(|| ledger.now().year()));
+
+    Ok(Reply(Summary {
+        summary: ledger.summary(&user_id, year).await,
+    }))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(super) struct ExportQuery {
+    year: Option<i32>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct SavingsExport {
+    year: Option<i32>,
+    rows: Vec<SavingsExportRow>,
+}
+
+/// Per-user savings totals for marketing; all time unless `year` is given
+#[utoipa::path(
+    get,
+    path = "/admin/savings/export",
+    tag = "admin",
+    params(ExportQuery),
+    responses(
+        (status = 200, description = "Per-user savings totals", body = SavingsExport),
+        (status = 401, description = "No API key or trusted user", body = ErrorBody),
+        (status = 403, description = "The caller's roles do not grant the permission", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+pub(super) async fn export_savings(
+    Extension(ledger): Extension<Arc<SavingsLedger>>,
+    Query(query): Query<ExportQuery>,
+) -> Reply<SavingsExport> {
+    Reply(SavingsExport {
+        year: query.year,
+        rows: ledger.export(query.year).await,
+    })
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct Preferences {
+    user_id: String,
+    preferences: NotificationPreferences,
+}
+
+/// The user's preferences, or the defaults if they never set any
+#[utoipa::path(
+    get,
+    path = "/users/{id}/notification-preferences",
+    tag = "users",
+    params(("id" = String, Path, description = "User id")),
+    responses(
+        (status = 200, description = "The user's `preferences`", body = Preferences),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 403, description = "`id` is not the token's user", body = ErrorBody),
+    ),
+    security(("user_jwt" = []), ("user_jwt" = [], "api_key" = []))
+)]
+pub(super) async fn get_notification_preferences(
+    Extension(notifications): Extension<Arc<NotificationDispatcher>>,
+    user: Option<UserContext>,
+    Path(user_id): Path<String>,
+) -> Result<Reply<Preferences>, ApiError> {
+    only_own(user, &user_id)?;
+    Ok(Reply(Preferences {
+        preferences: notifications.preferences(&user_id).await,
+        user_id,
+    }))
+}
+
+#[utoipa::path(
+    put,
+    path = "/users/{id}/notification-preferences",
+    tag = "users",
+    params(("id" = String, Path, description = "User id")),
+    request_body = NotificationPreferences,
+    responses(
+        (status = 200, description = "The stored `preferences`", body = Preferences),
+        (status = 400, description = "Invalid preferences", body = ErrorBody),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorBody),
+        (status = 403, description = "`id` is not the token's user", body = ErrorBody),
+    ),
+    security(("user_jwt" = []), ("user_jwt" = [], "api_key" = []))
+)]
+pub(super) async fn put_notification_preferences(
+    Extension(notifications): Extension<Arc<NotificationDispatcher>>,
+    user: Option<UserContext>,
+    Path(user_id): Path<String>,
+    Json(preferences): Json<NotificationPreferences>,
+) -> Result<Reply<Preferences>, ApiError> {
+    only_own(user, &user_id)?;
+    match notifications.set_preferences(&user_id, preferences).await {
+        Ok(preferences) => Ok(Reply(Preferences { user_id, preferences })),
+        Err(e) => Err(ApiError::bad_request(e)),
+    }
+}
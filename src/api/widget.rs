@@ -0,0 +1,226 @@
+//! Savings widgets for partner pages, cached by CDNs
+//!
+//! A widget is requested with the partner's widget key in `?key=`, from a page of
+//! one of the key's referrers, named by the browser in `Origin` (script embeds) or
+//! `Referer` (iframes). Both are part of `Vary`, as is `X-Country`, so a CDN keeps
+//! one copy per embedding site and country and never serves one site's copy to
+//! another.
+
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use axum::{
+    async_trait,
+    extract::{Extension, FromRequestParts, Path, Query},
+    http::{header, request::Parts, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use utoipa::{IntoParams, ToSchema};
+
+use super::reply::{ApiError, Reply};
+use crate::api_keys::{ApiKeys, KeyRejected, Scope};
+use crate::compliance::{ComplianceRules, COUNTRY_HEADER};
+use crate::licensing::SourceLicenses;
+use crate::models::coupon_listing::License;
+use crate::models::domain::MerchantDomain;
+use crate::storage::deal_store::DealStore;
+use crate::top_coupons::TopCoupons;
+use crate::widget::Widget;
+
+/// Browsers keep a widget for 5 minutes and CDNs for an hour; a CDN serves its stale
+/// copy for up to a day while it revalidates, or while the API is down
+const CACHE_CONTROL: &str = "public, max-age=300, s-maxage=3600, stale-while-revalidate=86400, stale-if-error=86400";
+const VARY: &str = "Origin, Referer, X-Country";
+
+#[derive(Deserialize, IntoParams)]
+pub(super) struct WidgetParams {
+    /// The partner's widget key
+    key: Option<String>,
+    /// `json` (default) or `html`, the latter in builds with the `widget-html` feature
+    format: Option<String>,
+}
+
+fn error(status: StatusCode, message: &str) -> ApiError {
+    ApiError::new(status, message)
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct WidgetReply {
+    widget: Widget,
+}
+
+/// Proof that the request's `?key=` is a widget key of the page it came from; the
+/// widget route's layer
+pub(super) struct WidgetKey;
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for WidgetKey {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Some(keys) = parts.extensions.get::<Arc<ApiKeys>>().cloned() else {
+            return Err(error(StatusCode::INTERNAL_SERVER_ERROR, "API keys are not configured"));
+        };
+        let params = Query::<WidgetParams>::try_from_uri(&parts.uri).map_err(|e| error(StatusCode::BAD_REQUEST, &e.body_text()))?;
+        let Some(key) = params.key.as_deref() else {
+            return Err(error(StatusCode::UNAUTHORIZED, "missing widget key"));
+        };
+        let record = match keys.authenticate(key).await {
+            Ok(record) => record,
+            Err(KeyRejected::Unknown) => return Err(error(StatusCode::UNAUTHORIZED, "unknown API key")),
+            Err(KeyRejected::Revoked) => return Err(error(StatusCode::UNAUTHORIZED, "API key revoked")),
+        };
+        if !record.allows(Scope::Widget) {
+            return Err(error(StatusCode::FORBIDDEN, "API key lacks the scope").with("required", Scope::Widget));
+        }
+
+        let page = [header::ORIGIN, header::REFERER]
+            .iter()
+            .find_map(|name| parts.headers.get(name).and_then(|v| v.to_str().ok()));
+        match page.is_some_and(|page| record.allows_referrer(page)) {
+            true => Ok(WidgetKey),
+            false => Err(error(StatusCode::FORBIDDEN, "widget key not allowed on this page")),
+        }
+    }
+}
+
+/// A merchant's top coupons and best live deal, for embedding in a partner's page
+#[utoipa::path(
+    get,
+    path = "/widget/{merchant}",
+    tag = "merchants",
+    params(("merchant" = String, Path, description = "Merchant domain, e.g. `amazon.com`"), WidgetParams),
+    responses(
+        (status = 200, description = "The `widget`, or its HTML fragment with `format=html`", body = WidgetReply),
+        (status = 304, description = "Unchanged since the `If-None-Match` ETag"),
+        (status = 400, description = "Unknown format", body = ErrorBody),
+        (status = 401, description = "No, unknown or revoked widget key", body = ErrorBody),
+        (status = 403, description = "The key has no `widget` scope, or the page is not one of its referrers", body = ErrorBody),
+        (status = 406, description = "HTML widgets are not built in", body = ErrorBody),
+    )
+)]
+pub(super) async fn widget(
+    Extension(top): Extension<Arc<TopCoupons>>,
+    Extension(deals): Extension<Arc<DealStore>>,
+    Extension(licenses): Extension<Arc<SourceLicenses>>,
+    Extension(compliance): Extension<Arc<ComplianceRules>>,
+    Path(merchant): Path<MerchantDomain>,
+    Query(params): Query<WidgetParams>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let html = match params.format.as_deref() {
+        None | Some("json") => false,
+        Some("html") => true,
+        Some(_) => return Err(error(StatusCode::BAD_REQUEST, "format must be json or html")),
+    };
+
+    // Partner pages are outside our apps, whatever the key's tenant may receive
+    let mut coupons = top.get(&merchant).await.to_vec();
+    licenses
+        .retain_permitted(&mut coupons, Some(&BTreeSet::from(License::REDISTRIBUTABLE)))
+        .await;
+    let mut widget = Widget::build(merchant, &coupons, &deals.list().await);
+    let country = headers.get(COUNTRY_HEADER).and_then(|v| v.to_str().ok());
+    if let Some(profile) = compliance.resolve_country(country, None).and_then(|country| compliance.profile(&country)) {
+        widget.comply(profile);
+    }
+
+    let html = match html {
+        true => Some(render_html(&widget)?),
+        false => None,
+    };
+    // Of the widget, and weak, as the envelope and tenant shaping may still rewrite the body
+    let rendered = match &html {
+        Some(html) => html.as_bytes().to_vec(),
+        None => serde_json::to_vec(&widget).unwrap_or_default(),
+    };
+    let digest: String = Sha256::digest(&rendered)[..16].iter().map(|b| format!("{:02x}", b)).collect();
+    let etag = format!("W/\"{}\"", digest);
+    let fresh = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|tags| tags.split(',').any(|tag| tag.trim() == etag || tag.trim() == "*"));
+
+    let mut response = match (fresh, html) {
+        (true, _) => StatusCode::NOT_MODIFIED.into_response(),
+        (false, Some(html)) => ([(header::CONTENT_TYPE, "text/html; charset=utf-8")], html).into_response(),
+        (false, None) => Reply(WidgetReply { widget }).into_response(),
+    };
+    let response_headers = response.headers_mut();
+    response_headers.insert(header::CACHE_CONTROL, HeaderValue::from_static(CACHE_CONTROL));
+    response_headers.insert(header::VARY, HeaderValue::from_static(VARY));
+    if let Ok(etag) = HeaderValue::from_str(&etag) {
+        response_headers.insert(header::ETAG, etag);
+    }
+    Ok(response)
+}
+
+#[cfg(feature = "widget-html")]
+fn render_html(widget: &Widget) -> Result<String, ApiError> {
+    widget.render_html().map_err(|e| {
+        tracing::warn!(error = %e, "Failed to render the widget");
+        error(StatusCode::INTERNAL_SERVER_ERROR, "failed to render widget")
+    })
+}
+
+#[cfg(not(feature = "widget-html"))]
+fn render_html(_widget: &Widget) -> Result<String, ApiError> {
+    Err(error(StatusCode::NOT_ACCEPTABLE, "HTML widgets are not enabled"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::body::Body;
+    use axum::http::Request;
+
+    use crate::api::tests as api;
+    use crate::api_keys::{ApiKeys, KeyRequest, Scope};
+
+    #[tokio::test]
+    async fn test_serves_cacheable_widgets_to_allowed_pages_only() {
+        let mut services = api::sandbox().await;
+        let keys = Arc::new(ApiKeys::new(None));
+        services.api_keys = keys.clone();
+        let issue = |scope: Scope| {
+            keys.issue(KeyRequest {
+                partner: "deals-blog".to_string(),
+                scopes: [scope].into(),
+                requests_per_minute: None,
+                referrers: ["blog.example.com".to_string()].into(),
+            })
+        };
+        let widget_key = issue(Scope::Widget).await.unwrap().key;
+        let reader_key = issue(Scope::ReadDeals).await.unwrap().key;
+        let merchant = services.deal_store.list().await[0].merchant_domain.clone();
+        let get = |key: &str, page: Option<&str>, etag: Option<&str>| {
+            let mut request = Request::get(format!("/widget/{}?key={}", merchant, key));
+            if let Some(page) = page {
+                request = request.header("referer", page);
+            }
+            if let Some(etag) = etag {
+                request = request.header("if-none-match", etag);
+            }
+            api::send(&services, request.body(Body::empty()).unwrap())
+        };
+
+        let response = get(&widget_key, Some("https://blog.example.com/best-laptops"), None).await;
+        assert_eq!(response.status(), 200);
+        assert!(response.headers()["cache-control"].to_str().unwrap().contains("s-maxage=3600"));
+        assert!(response.headers()["vary"].to_str().unwrap().contains("Referer"));
+        let etag = response.headers()["etag"].to_str().unwrap().to_string();
+        let body = api::json(response).await;
+        assert_eq!(body["widget"]["merchant"], merchant.as_str());
+        assert!(body["widget"]["best_deal"]["price"].is_object());
+
+        let response = get(&widget_key, Some("https://blog.example.com/other"), Some(&etag)).await;
+        assert_eq!(response.status(), 304);
+        assert_eq!(get(&widget_key, Some("https://copycat.example.net/"), None).await.status(), 403);
+        assert_eq!(get(&widget_key, None, None).await.status(), 403);
+        assert_eq!(get(&reader_key, Some("https://blog.example.com/"), None).await.status(), 403);
+        assert_eq!(get("dmk_unknown", Some("https://blog.example.com/"), None).await.status(), 401);
+    }
+}
@@ -0,0 +1,412 @@
+//! API keys issued to partner integrations
+//!
+//! Tenant keys are configured with their tenant (see [`crate::tenant`]); partner
+//! integrations instead get keys issued through `/admin/api-keys`, one per
+//! integration, so each can be rate limited and revoked without touching the
+//! others. A key is shown once when issued and stored as its SHA-256 only.
+//!
+//! Each key carries [`Scope`]s, and the API refuses it on routes outside them (see
+//! [`crate::api`]). Requests made with it are metered like any other key, against
+//! its own `requests_per_minute` when set (see [`crate::tenant::usage`]).
+//!
+//! Keys with the [`Scope::Widget`] scope are embedded in partner pages, where anyone
+//! can read them, so they are only honored on pages of their `referrers`.
+//!
+//! With the `postgres` feature and `DATABASE_URL` set, keys live in the
+//! `partner_api_keys` table (see [`postgres`]) and every instance re-reads it each
+//! [`REFRESH_INTERVAL`], so a revocation reaches all of them. Otherwise they are
+//! shared through Redis the same way when `REDIS_URL` is set, or persisted to
+//! `API_KEYS_PATH` (default `data/api_keys.json`).
+
+#[cfg(feature = "postgres")]
+pub mod postgres;
+
+use std::collections::{BTreeSet, HashMap};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::{Mutex, RwLock};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::clock::{self, Clock};
+use crate::models::domain::MerchantDomain;
+use crate::storage::persisted::{PersistedStore, StoreError};
+
+const REDIS_KEY: &str = "partner_api_keys";
+const STORE_NAME: &str = "partner API keys";
+/// How often keys stored in Postgres or Redis are re-read
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+/// Characters of a key kept on its record to tell keys apart, e.g. `dmk_3f9a01c2`
+const PREFIX_LEN: usize = 12;
+
+/// What a key may be used for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum Scope {
+    /// Deals, coupons and the other read endpoints
+    ReadDeals,
+    /// Coupon feeds and deal imports
+    SubmitCoupons,
+    /// The `/admin/*` endpoints, subject to the key's roles (see [`crate::rbac`])
+    Admin,
+    /// Savings widgets, on pages of the key's `referrers` (see [`crate::widget`])
+    Widget,
+}
+
+impl Scope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scope::ReadDeals => "read-deals",
+            Scope::SubmitCoupons => "submit-coupons",
+            Scope::Admin => "admin",
+            Scope::Widget => "widget",
+        }
+    }
+
+    pub fn parse(scope: &str) -> Option<Self> {
+        [Scope::ReadDeals, Scope::SubmitCoupons, Scope::Admin, Scope::Widget]
+            .into_iter()
+            .find(|s| s.as_str() == scope)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct ApiKeyRecord {
+    pub id: Uuid,
+    /// The integration the key was issued to
+    pub partner: String,
+    /// The key's first characters, to recognize it by
+    pub prefix: String,
+    pub scopes: BTreeSet<Scope>,
+    /// Quota of the key; `API_QUOTA_PER_MINUTE` when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requests_per_minute: Option<u32>,
+    /// Hosts whose pages may embed the key's widgets; each also covers its subdomains
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub referrers: BTreeSet<String>,
+    pub created_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl ApiKeyRecord {
+    pub fn allows(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope)
+    }
+
+    /// Whether the page at `url` (an `Origin` or `Referer`) is one of the key's referrers
+    pub fn allows_referrer(&self, url: &str) -> bool {
+        let Ok(host) = MerchantDomain::parse(url) else {
+            return false;
+        };
+        let host = host.as_str();
+        self.referrers
+            .iter()
+            .any(|referrer| host == referrer || host.strip_suffix(referrer.as_str()).is_some_and(|sub| sub.ends_with('.')))
+    }
+}
+
+/// A key as stored, by its hash
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct StoredKey {
+    #[serde(flatten)]
+    pub record: ApiKeyRecord,
+    pub key_sha256: String,
+}
+
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct KeyRequest {
+    pub partner: String,
+    pub scopes: BTreeSet<Scope>,
+    #[serde(default)]
+    pub requests_per_minute: Option<u32>,
+    /// Required with the `widget` scope, e.g. `["blog.example.com"]`
+    #[serde(default)]
+    pub referrers: BTreeSet<String>,
+}
+
+/// A newly issued key; `key` is not shown again
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct IssuedKey {
+    pub key: String,
+    #[serde(flatten)]
+    pub record: ApiKeyRecord,
+}
+
+/// Why a key was turned away
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyRejected {
+    Unknown,
+    Revoked,
+}
+
+/// Where keys live between restarts
+enum KeyStore {
+    /// In memory, in a file, or in Redis
+    Persisted(PersistedStore<Vec<StoredKey>>),
+    /// Shared by every instance using the same database
+    #[cfg(feature = "postgres")]
+    Postgres(tokio_postgres::Client),
+}
+
+pub struct ApiKeys {
+    /// By key hash
+    keys: RwLock<HashMap<String, StoredKey>>,
+    store: KeyStore,
+    /// Serializes writes so the store never goes back to an older state
+    writes: Mutex<()>,
+    clock: Arc<dyn Clock>,
+}
+
+impl ApiKeys {
+    /// Keys persisted to `path`, or kept in memory only
+    pub fn new(path: Option<PathBuf>) -> Self {
+        Self::with_store(KeyStore::Persisted(PersistedStore::new(STORE_NAME, REDIS_KEY, path).pretty()))
+    }
+
+    /// Keys kept in the `partner_api_keys` table of `client`'s database
+    #[cfg(feature = "postgres")]
+    pub async fn shared(client: tokio_postgres::Client) -> Result<Self, tokio_postgres::Error> {
+        client.batch_execute(postgres::SCHEMA).await?;
+        Ok(Self::with_store(KeyStore::Postgres(client)))
+    }
+
+    fn with_store(store: KeyStore) -> Self {
+        Self {
+            keys: RwLock::new(HashMap::new()),
+            store,
+            writes: Mutex::new(()),
+            clock: clock::system(),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Keys in Postgres when built with `postgres` and `DATABASE_URL` is set,
+    /// otherwise shared through `REDIS_URL` when set, or in `API_KEYS_PATH` (default
+    /// `data/api_keys.json`)
+    pub async fn from_env() -> Self {
+        #[cfg(feature = "postgres")]
+        if let Ok(url) = std::env::var("DATABASE_URL") {
+            let shared = match crate::storage::postgres::connect(&url).await {
+                Ok(client) => Self::shared(client).await,
+                Err(e) => Err(e),
+            };
+            match shared {
+                Ok(keys) => return keys.loaded().await,
+                Err(e) => tracing::warn!(error = %e, "Postgres unavailable, keeping API keys in Redis or a file"),
+            }
+        }
+
+        let store = PersistedStore::from_env(STORE_NAME, REDIS_KEY, "API_KEYS_PATH", "data/api_keys.json");
+        Self::with_store(KeyStore::Persisted(store.pretty())).loaded().await
+    }
+
+    async fn loaded(self) -> Self {
+        if let Err(e) = self.reload().await {
+            tracing::warn!(error = %e, "Starting without partner API keys");
+        }
+        self
+    }
+
+    /// Replace the local copy with every stored key
+    pub async fn reload(&self) -> Result<(), StoreError> {
+        let stored: Vec<StoredKey> = match &self.store {
+            KeyStore::Persisted(store) => match store.hash_values(REDIS_KEY)? {
+                Some(stored) => stored,
+                None => match store.load().await? {
+                    Some(stored) => stored,
+                    None => return Ok(()),
+                },
+            },
+            #[cfg(feature = "postgres")]
+            KeyStore::Postgres(client) => postgres::load(client).await?,
+        };
+        *self.keys.write().await = stored.into_iter().map(|key| (key.key_sha256.clone(), key)).collect();
+        Ok(())
+    }
+
+    /// Re-read shared keys every [`REFRESH_INTERVAL`], picking up keys issued or
+    /// revoked by other instances
+    pub async fn start_background_tasks(self: Arc<Self>) {
+        match &self.store {
+            KeyStore::Persisted(store) => store.refresh_every(self.clock.as_ref(), REFRESH_INTERVAL, || self.reload()).await,
+            #[cfg(feature = "postgres")]
+            KeyStore::Postgres(_) => loop {
+                self.clock.sleep(REFRESH_INTERVAL).await;
+                if let Err(e) = self.reload().await {
+                    tracing::warn!(error = %e, "Failed to refresh partner API keys");
+                }
+            },
+        }
+    }
+
+    /// Store `key`; a file is rewritten whole, from the local copy
+    async fn persist(&self, key: &StoredKey) -> Result<(), StoreError> {
+        match &self.store {
+            KeyStore::Persisted(store) => {
+                let mut all: Vec<StoredKey> = self.keys.read().await.values().cloned().collect();
+                all.sort_by_key(|key| key.record.created_at);
+                store.write_field(REDIS_KEY, &key.key_sha256, Some(key), || all).await
+            }
+            #[cfg(feature = "postgres")]
+            KeyStore::Postgres(client) => postgres::save(client, key).await,
+        }
+    }
+
+    /// Issue a key for `request.partner` with its scopes
+    pub async fn issue(&self, request: KeyRequest) -> Result<IssuedKey, String> {
+        let partner = request.partner.trim();
+        if partner.is_empty() {
+            return Err("partner must not be empty".to_string());
+        }
+        if request.scopes.is_empty() {
+            return Err("a key needs at least one scope".to_string());
+        }
+        if request.requests_per_minute == Some(0) {
+            return Err("requests_per_minute must be positive".to_string());
+        }
+        let referrers = request
+            .referrers
+            .iter()
+            .map(|referrer| MerchantDomain::parse(referrer).map(String::from).map_err(|_| format!("'{}' is not a valid referrer host", referrer.trim())))
+            .collect::<Result<BTreeSet<_>, _>>()?;
+        if request.scopes.contains(&Scope::Widget) && referrers.is_empty() {
+            return Err("widget keys need at least one referrer".to_string());
+        }
+
+        let key = format!("dmk_{}", random_hex(24));
+        let stored = StoredKey {
+            record: ApiKeyRecord {
+                id: Uuid::new_v4(),
+                partner: partner.to_string(),
+                prefix: key[..PREFIX_LEN].to_string(),
+                scopes: request.scopes,
+                requests_per_minute: request.requests_per_minute,
+                referrers,
+                created_at: self.clock.now(),
+                revoked_at: None,
+            },
+            key_sha256: hash_key(&key),
+        };
+
+        let _write = self.writes.lock().await;
+        self.keys.write().await.insert(stored.key_sha256.clone(), stored.clone());
+        if let Err(e) = self.persist(&stored).await {
+            self.keys.write().await.remove(&stored.key_sha256);
+            return Err(format!("could not store the key: {}", e));
+        }
+        Ok(IssuedKey { key, record: stored.record })
+    }
+
+    /// The record of `key`, if it was issued and is not revoked
+    pub async fn authenticate(&self, key: &str) -> Result<ApiKeyRecord, KeyRejected> {
+        match self.keys.read().await.get(&hash_key(key.trim())) {
+            None => Err(KeyRejected::Unknown),
+            Some(stored) if stored.record.revoked_at.is_some() => Err(KeyRejected::Revoked),
+            Some(stored) => Ok(stored.record.clone()),
+        }
+    }
+
+    /// Every key, revoked ones included, oldest first
+    pub async fn list(&self) -> Vec<ApiKeyRecord> {
+        let mut records: Vec<ApiKeyRecord> = self.keys.read().await.values().map(|key| key.record.clone()).collect();
+        records.sort_by_key(|record| record.created_at);
+        records
+    }
+
+    /// Revoke key `id`; revoking it again keeps the first revocation time
+    pub async fn revoke(&self, id: Uuid) -> Result<Option<ApiKeyRecord>, String> {
+        let _write = self.writes.lock().await;
+        let stored = {
+            let mut keys = self.keys.write().await;
+            let Some(stored) = keys.values_mut().find(|key| key.record.id == id) else {
+                return Ok(None);
+            };
+            stored.record.revoked_at.get_or_insert(self.clock.now());
+            stored.clone()
+        };
+        self.persist(&stored)
+            .await
+            .map_err(|e| format!("revoked on this instance only, could not store it: {}", e))?;
+        Ok(Some(stored.record))
+    }
+}
+
+/// SHA-256 (hex) of a key, as tenant keys are hashed
+pub fn hash_key(key: &str) -> String {
+    Sha256::digest(key.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn random_hex(bytes: usize) -> String {
+    let mut rng = rand::thread_rng();
+    (0..bytes).map(|_| format!("{:02x}", rng.gen::<u8>())).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_issues_persists_and_revokes_keys() {
+        let path = std::env::temp_dir().join(format!("api_keys_{}.json", Uuid::new_v4()));
+        let keys = ApiKeys::new(Some(path.clone()));
+        let request = |partner: &str, scopes: &[Scope]| KeyRequest {
+            partner: partner.to_string(),
+            scopes: scopes.iter().copied().collect(),
+            requests_per_minute: Some(120),
+            referrers: BTreeSet::new(),
+        };
+        assert!(keys.issue(request(" ", &[Scope::ReadDeals])).await.is_err());
+        assert!(keys.issue(request("acme-feed", &[])).await.is_err());
+
+        let issued = keys.issue(request(" acme-feed ", &[Scope::ReadDeals, Scope::SubmitCoupons])).await.unwrap();
+        assert!(issued.key.starts_with("dmk_") && issued.key.len() == 52);
+        assert!(issued.key.starts_with(&issued.record.prefix));
+        assert_eq!(issued.record.partner, "acme-feed");
+        let other = keys.issue(request("globex", &[Scope::ReadDeals])).await.unwrap();
+
+        let record = keys.authenticate(&issued.key).await.unwrap();
+        assert!(record.allows(Scope::SubmitCoupons) && !record.allows(Scope::Admin));
+        assert_eq!(keys.authenticate("dmk_guess").await, Err(KeyRejected::Unknown));
+        // Only hashes are stored
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(!content.contains(&issued.key) && content.contains(&hash_key(&issued.key)));
+
+        let revoked = keys.revoke(issued.record.id).await.unwrap().unwrap();
+        let revoked_at = revoked.revoked_at.unwrap();
+        assert_eq!(keys.authenticate(&issued.key).await, Err(KeyRejected::Revoked));
+        assert!(keys.authenticate(&other.key).await.is_ok());
+        assert_eq!(keys.revoke(issued.record.id).await.unwrap().unwrap().revoked_at, Some(revoked_at));
+        assert_eq!(keys.revoke(Uuid::new_v4()).await, Ok(None));
+
+        let widget = |referrers: &[&str]| KeyRequest {
+            referrers: referrers.iter().map(|r| r.to_string()).collect(),
+            ..request("acme-blog", &[Scope::Widget])
+        };
+        assert!(keys.issue(widget(&[])).await.is_err());
+        assert!(keys.issue(widget(&["not a host"])).await.is_err());
+        let embedded = keys.issue(widget(&["https://www.Blog.Example.com/"])).await.unwrap().record;
+        assert_eq!(embedded.referrers, BTreeSet::from(["blog.example.com".to_string()]));
+        assert!(embedded.allows_referrer("https://blog.example.com/posts/1"));
+        assert!(embedded.allows_referrer("https://deals.blog.example.com"));
+        assert!(!embedded.allows_referrer("https://evilblog.example.com/"));
+        assert!(!embedded.allows_referrer("null"));
+
+        let restarted = ApiKeys::new(Some(path.clone()));
+        restarted.reload().await.unwrap();
+        assert_eq!(restarted.list().await, keys.list().await);
+        assert_eq!(restarted.authenticate(&issued.key).await, Err(KeyRejected::Revoked));
+        assert_eq!(restarted.authenticate(&other.key).await.unwrap().partner, "globex");
+        let _ = std::fs::remove_file(path);
+    }
+}
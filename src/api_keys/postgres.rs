@@ -0,0 +1,118 @@
+//! The `partner_api_keys` table
+
+use std::collections::BTreeSet;
+
+use tokio_postgres::{Client, Row};
+
+use super::{ApiKeyRecord, Scope, StoredKey};
+
+/// Creates the `partner_api_keys` table
+pub const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS partner_api_keys (
+    id TEXT PRIMARY KEY,
+    partner TEXT NOT NULL,
+    prefix TEXT NOT NULL,
+    key_sha256 TEXT NOT NULL UNIQUE,
+    scopes TEXT[] NOT NULL,
+    requests_per_minute INTEGER,
+    referrers TEXT[] NOT NULL DEFAULT '{}',
+    created_at TIMESTAMPTZ NOT NULL,
+    revoked_at TIMESTAMPTZ
+)";
+
+const UPSERT: &str = "
+INSERT INTO partner_api_keys (id, partner, prefix, key_sha256, scopes, requests_per_minute, referrers, created_at, revoked_at)
+VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+ON CONFLICT (id) DO UPDATE SET
+    scopes = EXCLUDED.scopes,
+    requests_per_minute = EXCLUDED.requests_per_minute,
+    referrers = EXCLUDED.referrers,
+    revoked_at = EXCLUDED.revoked_at";
+
+pub(super) async fn load(client: &Client) -> Result<Vec<StoredKey>, Box<dyn std::error::Error + Send + Sync>> {
+    let rows = client
+        .query(
+            "SELECT id, partner, prefix, key_sha256, scopes, requests_per_minute, referrers, created_at, revoked_at
+             FROM partner_api_keys",
+            &[],
+        )
+        .await?;
+    rows.iter().map(stored_key).collect()
+}
+
+fn stored_key(row: &Row) -> Result<StoredKey, Box<dyn std::error::Error + Send + Sync>> {
+    let id: String = row.try_get("id")?;
+    let scopes: Vec<String> = row.try_get("scopes")?;
+    let requests_per_minute: Option<i32> = row.try_get("requests_per_minute")?;
+    let referrers: Vec<String> = row.try_get("referrers")?;
+    Ok(StoredKey {
+        record: ApiKeyRecord {
+            id: id.parse()?,
+            partner: row.try_get("partner")?,
+            prefix: row.try_get("prefix")?,
+            // Scopes a newer release added are ignored rather than failing the load
+            scopes: scopes.iter().filter_map(|scope| Scope::parse(scope)).collect::<BTreeSet<_>>(),
+            requests_per_minute: requests_per_minute.and_then(|quota| u32::try_from(quota).ok()),
+            referrers: referrers.into_iter().collect(),
+            created_at: row.try_get("created_at")?,
+            revoked_at: row.try_get("revoked_at")?,
+        },
+        key_sha256: row.try_get("key_sha256")?,
+    })
+}
+
+pub(super) async fn save(client: &Client, key: &StoredKey) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let record = &key.record;
+    let scopes: Vec<&str> = record.scopes.iter().map(|scope| scope.as_str()).collect();
+    let requests_per_minute = record.requests_per_minute.map(|quota| quota.min(i32::MAX as u32) as i32);
+    let referrers: Vec<&str> = record.referrers.iter().map(String::as_str).collect();
+    client
+        .execute(
+            UPSERT,
+            &[
+                &record.id.to_string(),
+                &record.partner,
+                &record.prefix,
+                &key.key_sha256,
+                &scopes,
+                &requests_per_minute,
+                &referrers,
+                &record.created_at,
+                &record.revoked_at,
+            ],
+        )
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{ApiKeys, KeyRequest, Scope};
+
+    /// Runs against `DATABASE_URL`, and passes without checking anything when it is unset
+    #[tokio::test]
+    async fn test_keys_round_trip_through_the_table() {
+        let Ok(url) = std::env::var("DATABASE_URL") else {
+            eprintln!("DATABASE_URL is not set, skipping the Postgres API key test");
+            return;
+        };
+        let connect = || async { crate::storage::postgres::connect(&url).await.unwrap() };
+        let keys = ApiKeys::shared(connect().await).await.unwrap();
+        let issued = keys
+            .issue(KeyRequest {
+                partner: "postgres-test".to_string(),
+                scopes: [Scope::ReadDeals].into(),
+                requests_per_minute: Some(30),
+                referrers: Default::default(),
+            })
+            .await
+            .unwrap();
+
+        let other_instance = ApiKeys::shared(connect().await).await.unwrap();
+        other_instance.reload().await.unwrap();
+        assert_eq!(other_instance.authenticate(&issued.key).await.unwrap(), issued.record);
+        keys.revoke(issued.record.id).await.unwrap();
+        other_instance.reload().await.unwrap();
+        assert!(other_instance.authenticate(&issued.key).await.is_err());
+    }
+}
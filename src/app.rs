@@ -0,0 +1,687 @@
+//! Service construction
+//!
+//! [`Services`] bundles every component the HTTP API needs. The binary builds it
+//! with [`Services::from_env`]; other consumers (batch workers, the extension
+//! backend) can use [`ServicesBuilder`] to supply their own stores.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::runtime::Handle;
+
+use crate::alerts::natural_language::NaturalAlertParser;
+use crate::analytics::{CouponAnalytics, ROLLUP_INTERVAL};
+use crate::api_keys::ApiKeys;
+use crate::auth::JwtVerifier;
+use crate::backfill::{Backfills, BACKFILL_TICK};
+use crate::clipping::ClippingService;
+use crate::collections::CollectionService;
+use crate::cluster::{LeaderElection, Role, Shards};
+use crate::community::CommunityService;
+use crate::compliance::ComplianceRules;
+use crate::cors::CorsPolicy;
+use crate::coupon_deltas::CouponDeltas;
+use crate::coupon_engine::archive::SnapshotArchive;
+use crate::coupon_engine::blocklist::ExtractionBlocklist;
+use crate::coupon_engine::budget::ScrapeBudgets;
+use crate::coupon_engine::canary::CanaryMonitor;
+use crate::coupon_engine::controls::ScraperControls;
+use crate::coupon_engine::opt_out::OptOutRegistry;
+use crate::coupon_engine::liveness::{HttpProbe, LivenessMonitor};
+use crate::coupon_engine::frontier::ScrapeFrontier;
+use crate::coupon_engine::profiles::DomainProfiles;
+use crate::coupon_engine::proxy_manager::{ProxyManager, ProxySource};
+use crate::coupon_engine::rate_limiter::RateLimiter;
+use crate::coupon_engine::scraper::Scraper;
+use crate::coupon_engine::yield_stats::YieldStats;
+use crate::coupon_engine::{CouponEngine, EngineConfig};
+use crate::coupon_success::CouponSuccessPredictor;
+use crate::digest::scheduled::{DigestScheduler, DIGEST_TICK};
+use crate::digest::DigestService;
+use crate::events::EventCalendar;
+use crate::experiments::ExperimentService;
+use crate::fetch_service::{FetchQuotas, FetchService};
+use crate::forecast::PriceForecaster;
+use crate::health::HealthMonitor;
+use crate::images::ImagePipeline;
+use crate::jobs::ScrapeQueue;
+use crate::licensing::SourceLicenses;
+use crate::localization::Translator;
+use crate::notifications::NotificationDispatcher;
+use crate::rbac::AccessControl;
+use crate::onboarding::verification::{DohResolver, DomainVerifier};
+use crate::onboarding::OnboardingService;
+use crate::pricing::discount_audit::DiscountAuditor;
+use crate::pricing::rewards::RewardsValuator;
+use crate::pricing::verification::{PriceVerifier, VerificationPolicy};
+use crate::privacy::Scrubber;
+use crate::recommendations::RecommendationService;
+use crate::reprocess::Reprocessor;
+use crate::reputation::ReputationService;
+use crate::sandbox::faker::Faker;
+use crate::sandbox::{Sandboxes, SANDBOX_COUPONS, SANDBOX_PRODUCTS};
+use crate::savings::SavingsLedger;
+use crate::scoring::DealScorer;
+use crate::search::DealSearch;
+use crate::seeding::Seeder;
+use crate::sharing::ShareService;
+use crate::sla::SlaMonitor;
+use crate::services::ranking::RankingPipeline;
+use crate::storage::coupon_history::CouponHistory;
+use crate::storage::coupon_store::CouponStore;
+use crate::storage::deal_store::DealStore;
+use crate::storage::import::ImportLimits;
+use crate::storage::shipping_rules::ShippingRuleStore;
+use crate::stacksmart::{StackRules, StackSmartEngine};
+use crate::stream::{DealStream, EventJournal};
+use crate::tagging::TagRegistry;
+use crate::tenant::usage::UsageMeter;
+use crate::tenant::TenantRegistry;
+use crate::top_coupons::{TopCoupons, DEFAULT_LIMIT as DEFAULT_TOP_COUPONS};
+
+/// Shared handles to every service; cheap to clone
+#[derive(Clone)]
+pub struct Services {
+    pub deal_store: Arc<DealStore>,
+    pub coupon_store: Arc<CouponStore>,
+    /// Every version of the coupon store's listings, for questions about the past
+    pub coupon_history: Arc<CouponHistory>,
+    pub coupon_deltas: Arc<CouponDeltas>,
+    /// Each merchant's best codes, ranked ahead of requests
+    pub top_coupons: Arc<TopCoupons>,
+    pub scorer: Arc<DealScorer>,
+    pub forecaster: Arc<PriceForecaster>,
+    pub discount_auditor: Arc<DiscountAuditor>,
+    pub alert_parser: Arc<NaturalAlertParser>,
+    pub community: Arc<CommunityService>,
+    pub reputation: Arc<ReputationService>,
+    pub coupon_predictor: Arc<CouponSuccessPredictor>,
+    pub events: Arc<EventCalendar>,
+    pub search: Arc<DealSearch>,
+    pub experiments: Arc<ExperimentService>,
+    pub ranking: Arc<RankingPipeline>,
+    /// Editors' and keyword tags on deals and coupons, see `/admin/tags`
+    pub tags: Arc<TagRegistry>,
+    pub recommendations: Arc<RecommendationService>,
+    pub image_pipeline: Arc<ImagePipeline>,
+    pub digests: Arc<DigestService>,
+    pub coupon_engine: Arc<CouponEngine>,
+    pub scrape_jobs: Arc<ScrapeQueue>,
+    pub leader: Arc<LeaderElection>,
+    /// Dependency probes and background task liveness behind `/health`
+    pub health: Arc<HealthMonitor>,
+    /// Which merchants this instance's workers scrape
+    pub shards: Arc<Shards>,
+    pub import_limits: Arc<ImportLimits>,
+    /// Product page checks of imported high-value deals, when `PRICE_VERIFICATION_MIN_PRICE` is set
+    pub price_verifier: Arc<PriceVerifier>,
+    pub onboarding: Arc<OnboardingService>,
+    pub yield_stats: Arc<YieldStats>,
+    pub scrape_budgets: Arc<ScrapeBudgets>,
+    pub canaries: Arc<CanaryMonitor>,
+    /// Daily checks that merchants' sites are still up and still shops
+    pub liveness: Arc<LivenessMonitor>,
+    /// Page snapshots the default engine archives, when `SNAPSHOT_ARCHIVE_DIR` is set
+    pub snapshots: Option<Arc<SnapshotArchive>>,
+    pub reprocessor: Arc<Reprocessor>,
+    /// Checkpointed rescrapes and reprocessing of the whole archive, see `/admin/backfills`
+    pub backfills: Arc<Backfills>,
+    /// Cold-start seeding from the bundles at `SEED_BUNDLES_PATH`
+    pub seeder: Arc<Seeder>,
+    pub fetch_service: Arc<FetchService>,
+    pub domain_profiles: Arc<DomainProfiles>,
+    pub opt_outs: Arc<OptOutRegistry>,
+    /// Code, merchant and page patterns extraction drops, see `/admin/blocklist`
+    pub extraction_blocklist: Arc<ExtractionBlocklist>,
+    /// Operators' merchant pauses and engine settings, see `/admin/scraper`
+    pub scraper_controls: Arc<ScraperControls>,
+    /// Daily coupon performance rollups behind `/analytics/coupons`
+    pub coupon_analytics: Arc<CouponAnalytics>,
+    pub savings: Arc<SavingsLedger>,
+    pub rewards: Arc<RewardsValuator>,
+    pub shipping_rules: Arc<ShippingRuleStore>,
+    /// Cart optimizer behind `/stacksmart`
+    pub stacksmart: Arc<StackSmartEngine>,
+    pub clipping: Arc<ClippingService>,
+    /// Curated deal lists for the homepage
+    pub collections: Arc<CollectionService>,
+    /// Per-user notification preferences, checked before every send
+    pub notifications: Arc<NotificationDispatcher>,
+    /// Daily or weekly digests per user or channel, sent through `notifications`
+    pub scheduled_digests: Arc<DigestScheduler>,
+    /// Signed coupon share links and their opens
+    pub shares: Arc<ShareService>,
+    pub tenants: Arc<TenantRegistry>,
+    /// Roles of API keys and users on the admin endpoints
+    pub access: Arc<AccessControl>,
+    /// Scoped keys issued to partner integrations
+    pub api_keys: Arc<ApiKeys>,
+    /// Verifies the shoppers' bearer tokens; disabled without a JWKS URL
+    pub jwt: Arc<JwtVerifier>,
+    /// Redistribution terms of each coupon source
+    pub licenses: Arc<SourceLicenses>,
+    /// Per-API-key rate limits and usage counters
+    pub usage: Arc<UsageMeter>,
+    pub sandboxes: Arc<Sandboxes>,
+    pub sla: Arc<SlaMonitor>,
+    pub deal_stream: Arc<DealStream>,
+    pub translator: Arc<Translator>,
+    /// Offers excluded and prices disclosed per country
+    pub compliance: Arc<ComplianceRules>,
+    pub scrubber: Arc<Scrubber>,
+    /// Origins, methods and headers browsers may use, per route
+    pub cors: Arc<CorsPolicy>,
+    /// Where scrape jobs, canaries and reprocess runs execute; see [`crate::runtimes`]
+    pub scrape_runtime: Handle,
+}
+
+impl Services {
+    pub fn builder() -> ServicesBuilder {
+        ServicesBuilder::default()
+    }
+
+    /// Build every service from environment configuration, using the sample catalogue
+    pub async fn from_env() -> Self {
+        Self::builder().build().await
+    }
+
+    /// Start the background jobs for every role; see [`Services::spawn_tasks_for`].
+    ///
+    /// Must be called from within a Tokio runtime.
+    pub async fn spawn_background_tasks(&self) {
+        self.spawn_tasks_for(Role::All).await;
+    }
+
+    /// Start the background jobs a deployment role needs.
+    ///
+    /// API instances warm the recommendation index, run the recommendation, image
+    /// and digest jobs that keep their in-memory state fresh, record coupon history,
+    /// diff the coupon corpus for partner alerts, and roll up coupon analytics; only
+    /// the leading instance sends scheduled digests and rolls up analytics.
+    /// Workers run scrape jobs and compete for the singleton tasks, on the scrape
+    /// runtime. Each task is [watched](HealthMonitor::watch) for `/health`. Must be
+    /// called from within a Tokio runtime.
+    pub async fn spawn_tasks_for(&self, role: Role) {
+        self.health.watch("domain-profiles", tokio::spawn(self.domain_profiles.clone().start_background_tasks()));
+        self.health.watch("opt-outs", tokio::spawn(self.opt_outs.clone().start_background_tasks()));
+        self.health.watch("extraction-blocklist", tokio::spawn(self.extraction_blocklist.clone().start_background_tasks()));
+        self.health.watch("scraper-controls", tokio::spawn(self.scraper_controls.clone().start_background_tasks()));
+        self.health.watch("coupon-licenses", tokio::spawn(self.licenses.clone().start_background_tasks()));
+        self.health.watch("scrape-yield", tokio::spawn(self.yield_stats.clone().start_background_tasks()));
+        self.health.watch("merchant-reputation", tokio::spawn(self.reputation.clone().start_background_tasks()));
+
+        if role.serves_api() {
+            self.health.watch("deal-stream", tokio::spawn(self.deal_stream.clone().start_background_tasks()));
+            self.recommendations.refresh(&self.deal_store).await;
+            self.health.watch("recommendations", tokio::spawn(self.recommendations.clone().start_background_tasks(self.deal_store.clone())));
+            self.health.watch("image-pipeline", tokio::spawn(self.image_pipeline.clone().start_background_tasks(self.deal_store.clone())));
+            self.health.watch("digests", tokio::spawn(self.digests.clone().start_background_tasks(self.ranking.clone())));
+            let (scheduler, ranking, deals, coupons) =
+                (self.scheduled_digests.clone(), self.ranking.clone(), self.deal_store.clone(), self.coupon_store.clone());
+            self.health.watch("scheduled-digests", tokio::spawn(self.leader.clone().run_singleton("scheduled-digests", DIGEST_TICK, move || {
+                let (scheduler, ranking, deals, coupons) = (scheduler.clone(), ranking.clone(), deals.clone(), coupons.clone());
+                async move {
+                    scheduler.run_due(&ranking, &deals, &coupons).await;
+                }
+            })));
+            self.health.watch("coupon-deltas", tokio::spawn(self.coupon_deltas.clone().start_background_tasks(self.coupon_store.clone(), self.leader.clone())));
+            self.health.watch("top-coupons", tokio::spawn(self.top_coupons.clone().start_background_tasks()));
+            self.health.watch("coupon-history", tokio::spawn(self.coupon_history.clone().start_background_tasks()));
+            self.health.watch("api-keys", tokio::spawn(self.api_keys.clone().start_background_tasks()));
+            self.health.watch("shipping-rules", tokio::spawn(self.shipping_rules.clone().start_background_tasks()));
+            self.health.watch("collections", tokio::spawn(self.collections.clone().start_background_tasks()));
+            self.health.watch("notification-preferences", tokio::spawn(self.notifications.clone().start_background_tasks()));
+            self.health.watch("digest-subscriptions", tokio::spawn(self.scheduled_digests.clone().start_background_tasks()));
+            self.health.watch("admin-roles", tokio::spawn(self.access.clone().start_background_tasks()));
+            self.health.watch("coupon-analytics", tokio::spawn(self.coupon_analytics.clone().start_background_tasks()));
+            self.health.watch("tags", tokio::spawn(self.tags.clone().start_background_tasks()));
+            let (analytics, history, yields, predictor, savings) = (
+                self.coupon_analytics.clone(),
+                self.coupon_history.clone(),
+                self.yield_stats.clone(),
+                self.coupon_predictor.clone(),
+                self.savings.clone(),
+            );
+            self.health.watch("coupon-analytics-rollup", tokio::spawn(self.leader.clone().run_singleton("coupon-analytics-rollup", ROLLUP_INTERVAL, move || {
+                let (analytics, history, yields, predictor, savings) =
+                    (analytics.clone(), history.clone(), yields.clone(), predictor.clone(), savings.clone());
+                async move {
+                    if let Err(e) = analytics.roll_up(&history, &yields, &predictor, &savings).await {
+                        tracing::error!(error = %e, "Coupon analytics rollup failed");
+                    }
+                }
+            })));
+        }
+
+        if role.runs_workers() {
+            // Join the other workers before taking jobs, so merchants are divided from the start
+            self.shards.heartbeat(Duration::from_secs(15));
+            self.health.watch("worker-heartbeat", tokio::spawn(self.shards.clone().run_heartbeat(Duration::from_secs(5))));
+            self.health.watch("scrape-jobs", self.scrape_runtime.spawn(self.scrape_jobs.clone().start_background_tasks(self.coupon_engine.clone())));
+            let queue = self.scrape_jobs.clone();
+            self.health.watch("scrape-job-sweeper", tokio::spawn(self.leader.clone().run_singleton("scrape-job-sweeper", Duration::from_secs(60), move || {
+                let queue = queue.clone();
+                async move {
+                    queue.sweep(Duration::from_secs(15 * 60)).await;
+                }
+            })));
+            let backfills = self.backfills.clone();
+            self.health.watch("backfills", self.scrape_runtime.spawn(self.leader.clone().run_singleton("backfills", BACKFILL_TICK, move || {
+                let backfills = backfills.clone();
+                async move {
+                    backfills.run_due().await;
+                }
+            })));
+            // Each merchant's canaries run daily; the hourly tick picks up the ones due
+            let canaries = self.canaries.clone();
+            self.health.watch("scrape-canaries", self.scrape_runtime.spawn(self.leader.clone().run_singleton("scrape-canaries", Duration::from_secs(3600), move || {
+                let canaries = canaries.clone();
+                async move {
+                    canaries.run_due().await;
+                }
+            })));
+            // Each merchant is checked daily; the hourly tick picks up the ones due
+            let liveness = self.liveness.clone();
+            self.health.watch("merchant-liveness", self.scrape_runtime.spawn(self.leader.clone().run_singleton("merchant-liveness", Duration::from_secs(3600), move || {
+                let liveness = liveness.clone();
+                async move {
+                    liveness.run_due().await;
+                }
+            })));
+        }
+    }
+}
+
+/// Builds [`Services`], reading configuration from the environment for anything not supplied
+#[derive(Default)]
+pub struct ServicesBuilder {
+    deal_store: Option<Arc<DealStore>>,
+    coupon_store: Option<Arc<CouponStore>>,
+    scorer: Option<Arc<DealScorer>>,
+    reputation: Option<Arc<ReputationService>>,
+    coupon_engine: Option<Arc<CouponEngine>>,
+    scrape_jobs: Option<Arc<ScrapeQueue>>,
+    yield_stats: Option<Arc<YieldStats>>,
+    savings: Option<Arc<SavingsLedger>>,
+    sandbox: Option<u64>,
+    scrape_runtime: Option<Handle>,
+}
+
+impl ServicesBuilder {
+    pub fn deal_store(mut self, store: Arc<DealStore>) -> Self {
+        self.deal_store = Some(store);
+        self
+    }
+
+    pub fn coupon_store(mut self, store: Arc<CouponStore>) -> Self {
+        self.coupon_store = Some(store);
+        self
+    }
+
+    pub fn scorer(mut self, scorer: Arc<DealScorer>) -> Self {
+        self.scorer = Some(scorer);
+        self
+    }
+
+    pub fn reputation(mut self, reputation: Arc<ReputationService>) -> Self {
+        self.reputation = Some(reputation);
+        self
+    }
+
+    pub fn coupon_engine(mut self, engine: Arc<CouponEngine>) -> Self {
+        self.coupon_engine = Some(engine);
+        self
+    }
+
+    pub fn scrape_jobs(mut self, queue: Arc<ScrapeQueue>) -> Self {
+        self.scrape_jobs = Some(queue);
+        self
+    }
+
+    /// Yield history; also given to the default coupon engine
+    pub fn yield_stats(mut self, yield_stats: Arc<YieldStats>) -> Self {
+        self.yield_stats = Some(yield_stats);
+        self
+    }
+
+    pub fn savings(mut self, savings: Arc<SavingsLedger>) -> Self {
+        self.savings = Some(savings);
+        self
+    }
+
+    /// Run scraping on `runtime` instead of the runtime `build` is called on
+    pub fn scrape_runtime(mut self, runtime: Handle) -> Self {
+        self.scrape_runtime = Some(runtime);
+        self
+    }
+
+    /// Build an isolated sandbox: a synthetic catalogue generated from `seed` and
+    /// in-memory state throughout, so nothing is persisted or shared with production
+    pub fn sandbox(mut self, seed: u64) -> Self {
+        self.sandbox = Some(seed);
+        self
+    }
+
+    pub async fn build(self) -> Services {
+        let sandboxed = self.sandbox.is_some();
+        let scrape_runtime = self.scrape_runtime.unwrap_or_else(Handle::current);
+        let (default_deals, default_coupons) = match self.sandbox {
+            Some(seed) => {
+                let mut faker = Faker::new(seed);
+                let catalogue = faker.catalogue(SANDBOX_PRODUCTS);
+                (
+                    Some(DealStore::with_deals(catalogue.deals, catalogue.price_history)),
+                    CouponStore::with_coupons(faker.coupons(SANDBOX_COUPONS)),
+                )
+            }
+            None => (None, CouponStore::with_sample_data()),
+        };
+        let deal_store = match (self.deal_store, default_deals) {
+            (Some(store), _) => store,
+            (None, Some(deals)) => Arc::new(deals),
+            (None, None) => Arc::new(DealStore::from_env().await),
+        };
+        let coupon_store = self.coupon_store.unwrap_or_else(|| Arc::new(default_coupons));
+        let licenses = Arc::new(match sandboxed {
+            true => SourceLicenses::new(None),
+            false => SourceLicenses::from_env().await,
+        });
+        let scorer = self.scorer.unwrap_or_else(|| Arc::new(DealScorer::from_env()));
+        let reputation = match self.reputation {
+            Some(reputation) => reputation,
+            None if sandboxed => Arc::new(ReputationService::new(None)),
+            None => Arc::new(ReputationService::from_env().await),
+        };
+        let yield_stats = match self.yield_stats {
+            Some(yield_stats) => yield_stats,
+            None if sandboxed => Arc::new(YieldStats::new(None)),
+            None => Arc::new(YieldStats::from_env().await),
+        };
+        let savings = match self.savings {
+            Some(savings) => savings,
+            None if sandboxed => Arc::new(SavingsLedger::new(None)),
+            None => Arc::new(SavingsLedger::from_env().await),
+        };
+        let snapshots = match sandboxed {
+            true => None,
+            false => SnapshotArchive::from_env().map(Arc::new),
+        };
+        // The default engine and the fetch service share one rate limit and proxy pool
+        let engine_config = EngineConfig::from_env();
+        let rate_limiter = Arc::new(RateLimiter::new(engine_config.rate_limit_per_domain));
+        let mut proxies = None;
+        if engine_config.proxy_rotation_enabled {
+            let manager = ProxyManager::new();
+            if let Ok(path) = std::env::var("PROXY_LIST_PATH") {
+                if let Err(e) = manager.load_from_file(&path).await {
+                    tracing::warn!(path = %path, error = %e, "Failed to load proxies");
+                }
+            }
+            proxies = Some(Arc::new(manager) as Arc<dyn ProxySource>);
+        }
+        let domain_profiles = match sandboxed {
+            true => Arc::new(DomainProfiles::new(None)),
+            false => Arc::new(DomainProfiles::from_env(rate_limiter.clone()).await),
+        };
+        let opt_outs = Arc::new(match sandboxed {
+            true => OptOutRegistry::new(None),
+            false => OptOutRegistry::from_env().await,
+        });
+        let extraction_blocklist = Arc::new(match sandboxed {
+            true => ExtractionBlocklist::new(None),
+            false => ExtractionBlocklist::from_env().await,
+        });
+        let scraper_controls = Arc::new(match sandboxed {
+            true => ScraperControls::new(None),
+            false => ScraperControls::from_env().await,
+        });
+        let frontier = match sandboxed || self.coupon_engine.is_some() {
+            true => None,
+            false => Some(Arc::new(ScrapeFrontier::from_env().await)),
+        };
+        let coupon_engine = self.coupon_engine.unwrap_or_else(|| {
+            let mut engine = CouponEngine::builder(engine_config.clone())
+                .rate_limiter(rate_limiter.clone())
+                .domain_profiles(domain_profiles.clone())
+                .opt_outs(opt_outs.clone())
+                .blocklist(extraction_blocklist.clone())
+                .controls(scraper_controls.clone())
+                .parsers_from_env()
+                .yield_stats(yield_stats.clone());
+            if let Some(proxies) = &proxies {
+                engine = engine.proxies(proxies.clone());
+            }
+            if let Some(snapshots) = &snapshots {
+                engine = engine.archive(snapshots.clone());
+            }
+            if let Some(frontier) = frontier {
+                engine = engine.frontier(frontier);
+            }
+            Arc::new(engine.build())
+        });
+        let mut fetch_service = FetchService::new(
+            Arc::new(Scraper::new(engine_config.clone())),
+            rate_limiter,
+            Duration::from_secs(engine_config.cache_duration_secs),
+        )
+        .with_quotas(FetchQuotas::from_env())
+        .with_opt_outs(opt_outs.clone());
+        if let Some(proxies) = proxies {
+            fetch_service = fetch_service.with_proxies(proxies, engine_config.retry_attempts);
+        }
+        let reprocessor = Arc::new(
+            Reprocessor::new(snapshots.clone(), coupon_store.clone())
+                .with_runtime(scrape_runtime.clone())
+                .with_licenses(licenses.clone()),
+        );
+        let seeder = match sandboxed {
+            true => Seeder::new(Vec::new(), coupon_store.clone(), domain_profiles.clone()),
+            false => Seeder::from_env(coupon_store.clone(), domain_profiles.clone()),
+        };
+        let seeder = Arc::new(seeder.with_runtime(scrape_runtime.clone()).with_licenses(licenses.clone()));
+        let scrape_budgets = match sandboxed {
+            true => Arc::new(ScrapeBudgets::new(None).with_profiles(domain_profiles.clone())),
+            false => Arc::new(ScrapeBudgets::from_env(domain_profiles.clone()).await),
+        };
+        let canary_fetcher = Arc::new(Scraper::new(engine_config.clone()));
+        let canaries = match sandboxed {
+            true => CanaryMonitor::new(canary_fetcher, domain_profiles.clone(), None),
+            false => CanaryMonitor::from_env(canary_fetcher, domain_profiles.clone()).await,
+        };
+        let canaries = Arc::new(canaries.with_opt_outs(opt_outs.clone()));
+        let rewards = Arc::new(RewardsValuator::from_env());
+        let (leader, shipping_rules, collections, notifications) = match sandboxed {
+            true => (
+                LeaderElection::new(None),
+                ShippingRuleStore::new(None),
+                CollectionService::new(None),
+                NotificationDispatcher::new(None),
+            ),
+            false => (
+                LeaderElection::from_env(),
+                ShippingRuleStore::from_env().await,
+                CollectionService::from_env().await,
+                NotificationDispatcher::from_env().await,
+            ),
+        };
+        let shares = match sandboxed {
+            true => ShareService::ephemeral(None),
+            false => ShareService::from_env().await,
+        };
+        let notifications = Arc::new(notifications);
+        let access = match sandboxed {
+            true => AccessControl::new(None),
+            false => AccessControl::from_env().await,
+        };
+        let api_keys = match sandboxed {
+            true => ApiKeys::new(None),
+            false => ApiKeys::from_env().await,
+        };
+        let scheduled_digests = match sandboxed {
+            true => DigestScheduler::new(None, notifications.clone()),
+            false => DigestScheduler::from_env(notifications.clone()).await,
+        };
+        let shards = Arc::new(match sandboxed {
+            true => Shards::new(None, leader.instance_id()),
+            false => Shards::from_env(leader.instance_id()),
+        });
+        let scrape_jobs = match self.scrape_jobs {
+            Some(queue) => queue,
+            None if sandboxed => Arc::new(
+                ScrapeQueue::new(None)
+                    .with_budgets(scrape_budgets.clone())
+                    .with_canaries(canaries.clone())
+                    .with_shards(shards.clone())
+                    .with_opt_outs(opt_outs.clone())
+                    .with_controls(scraper_controls.clone()),
+            ),
+            None => Arc::new(
+                ScrapeQueue::from_env()
+                    .await
+                    .with_budgets(scrape_budgets.clone())
+                    .with_canaries(canaries.clone())
+                    .with_shards(shards.clone())
+                    .with_opt_outs(opt_outs.clone())
+                    .with_controls(scraper_controls.clone()),
+            ),
+        };
+        let backfills = Arc::new(Backfills::new(
+            scrape_jobs.clone(),
+            coupon_store.clone(),
+            snapshots.clone(),
+            reprocessor.clone(),
+        ));
+        let coupon_history = Arc::new(match sandboxed {
+            true => CouponHistory::new(coupon_store.clone(), None),
+            false => CouponHistory::from_env(coupon_store.clone()).await,
+        });
+        let coupon_predictor = Arc::new(CouponSuccessPredictor::from_env());
+        let top_coupons = Arc::new(match sandboxed {
+            true => TopCoupons::new(coupon_store.clone(), coupon_predictor.clone(), reputation.clone(), DEFAULT_TOP_COUPONS),
+            false => TopCoupons::from_env(coupon_store.clone(), coupon_predictor.clone(), reputation.clone()),
+        });
+        let probe = Arc::new(HttpProbe::new());
+        let liveness = match sandboxed {
+            true => LivenessMonitor::new(probe, coupon_store.clone(), None),
+            false => LivenessMonitor::from_env(probe, coupon_store.clone()).await,
+        };
+        let liveness = Arc::new(liveness.with_top_coupons(top_coupons.clone()));
+        let verifier = DomainVerifier::new(
+            Arc::new(DohResolver::from_env()),
+            Arc::new(Scraper::new(EngineConfig::default())),
+        );
+        let onboarding = match sandboxed {
+            true => OnboardingService::new(None, verifier, coupon_store.clone()),
+            false => OnboardingService::from_env(verifier, coupon_store.clone()).await,
+        };
+        let onboarding = Arc::new(onboarding.with_top_coupons(top_coupons.clone()).with_licenses(licenses.clone()));
+        let discount_auditor = Arc::new(DiscountAuditor::new());
+        let events = Arc::new(EventCalendar::from_env());
+        let tags = Arc::new(match sandboxed {
+            true => TagRegistry::new(None),
+            false => TagRegistry::from_env().await,
+        });
+        let ranking = Arc::new(
+            RankingPipeline::new(deal_store.clone(), scorer.clone(), discount_auditor.clone(), events.clone())
+                .with_tags(tags.clone()),
+        );
+
+        // Sandbox deliveries are not part of the production stream
+        let (sla, journal) = match sandboxed {
+            true => (SlaMonitor::default(), EventJournal::new(Duration::from_secs(3600))),
+            false => (SlaMonitor::from_env(), EventJournal::from_env()),
+        };
+        let sla = Arc::new(sla);
+        let deal_stream = Arc::new(DealStream::new(journal, sla.clone()));
+
+        let coupon_deltas = match sandboxed {
+            true => CouponDeltas::new(None),
+            false => CouponDeltas::from_env().await,
+        };
+        let coupon_deltas = Arc::new(coupon_deltas.with_top_coupons(top_coupons.clone()).with_licenses(licenses.clone()));
+
+        Services {
+            deal_store,
+            coupon_store,
+            coupon_history,
+            coupon_deltas,
+            top_coupons,
+            scorer,
+            forecaster: Arc::new(PriceForecaster::new()),
+            discount_auditor,
+            alert_parser: Arc::new(NaturalAlertParser::from_env()),
+            community: Arc::new(CommunityService::new()),
+            reputation,
+            coupon_predictor,
+            events,
+            search: Arc::new(DealSearch::new()),
+            experiments: Arc::new(ExperimentService::from_env()),
+            ranking,
+            tags,
+            recommendations: Arc::new(RecommendationService::new()),
+            image_pipeline: Arc::new(ImagePipeline::new()),
+            digests: Arc::new(DigestService::new()),
+            coupon_engine,
+            scrape_jobs,
+            leader: Arc::new(leader),
+            health: Arc::new(match sandboxed {
+                true => HealthMonitor::new(None),
+                false => HealthMonitor::from_env(),
+            }),
+            shards,
+            import_limits: Arc::new(ImportLimits::from_env()),
+            price_verifier: Arc::new(match sandboxed {
+                true => PriceVerifier::disabled(),
+                false => PriceVerifier::new(Arc::new(Scraper::new(engine_config.clone())), VerificationPolicy::from_env())
+                    .with_opt_outs(opt_outs.clone()),
+            }),
+            onboarding,
+            yield_stats,
+            scrape_budgets,
+            canaries,
+            liveness,
+            snapshots,
+            reprocessor,
+            backfills,
+            seeder,
+            fetch_service: Arc::new(fetch_service),
+            domain_profiles,
+            opt_outs,
+            extraction_blocklist,
+            scraper_controls,
+            coupon_analytics: Arc::new(match sandboxed {
+                true => CouponAnalytics::new(None),
+                false => CouponAnalytics::from_env().await,
+            }),
+            savings,
+            stacksmart: Arc::new(StackSmartEngine::new().with_rules(StackRules::from_env()).with_rewards(rewards.clone())),
+            rewards,
+            shipping_rules: Arc::new(shipping_rules),
+            clipping: Arc::new(ClippingService::from_env()),
+            collections: Arc::new(collections),
+            notifications,
+            scheduled_digests: Arc::new(scheduled_digests),
+            shares: Arc::new(shares),
+            tenants: Arc::new(TenantRegistry::from_env()),
+            access: Arc::new(access),
+            api_keys: Arc::new(api_keys),
+            jwt: Arc::new(match sandboxed {
+                true => JwtVerifier::disabled(),
+                false => JwtVerifier::from_env(),
+            }),
+            licenses,
+            usage: Arc::new(UsageMeter::from_env()),
+            sandboxes: Arc::new(match self.sandbox {
+                Some(seed) => Sandboxes::new(seed),
+                None => Sandboxes::from_env(),
+            }),
+            sla,
+            deal_stream,
+            translator: Arc::new(Translator::from_env()),
+            compliance: Arc::new(ComplianceRules::from_env()),
+            scrubber: Arc::new(Scrubber::from_env()),
+            cors: Arc::new(CorsPolicy::from_env()),
+            scrape_runtime,
+        }
+    }
+}
@@ -0,0 +1,445 @@
+//! End-user authentication with JWTs
+//!
+//! The endpoints that act for a shopper (testing coupons, optimizing a cart,
+//! creating alerts) take a bearer token from the identity provider. [`JwtVerifier`]
+//! checks its signature against the provider's JWKS at `JWT_JWKS_URL` (RS256 or
+//! ES256 keys), its expiry, and that it was issued by `JWT_ISSUER` and, with
+//! `JWT_AUDIENCE` set, for this service. The verified claims become the request's
+//! [`UserContext`].
+//!
+//! The key set is cached for [`JWKS_TTL`] and fetched again early when a token
+//! names a key it does not have, so rotated keys are picked up, but at most once
+//! per [`JWKS_MIN_REFRESH`]. Without `JWT_JWKS_URL` the verifier is disabled and the
+//! endpoints stay open, as they were before.
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, TimeZone, Utc};
+use ring::signature::{self, RsaPublicKeyComponents, UnparsedPublicKey};
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+use crate::clock::{self, Clock};
+
+/// How long a fetched key set is used before it is fetched again
+pub const JWKS_TTL: Duration = Duration::from_secs(10 * 60);
+/// Least time between two fetches of the key set
+pub const JWKS_MIN_REFRESH: Duration = Duration::from_secs(30);
+/// Clock skew tolerated on `exp` and `nbf`
+const LEEWAY_SECS: i64 = 60;
+
+/// The authenticated shopper a request acts for
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UserContext {
+    /// The token's `sub`
+    pub user_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    /// From the space separated `scope` claim
+    pub scopes: Vec<String>,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Why a token was refused
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuthError {
+    Malformed,
+    UnsupportedAlgorithm(String),
+    UnknownKey,
+    BadSignature,
+    Expired,
+    NotYetValid,
+    WrongIssuer,
+    WrongAudience,
+    /// The key set could not be fetched
+    KeysUnavailable(String),
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::Malformed => write!(f, "malformed token"),
+            AuthError::UnsupportedAlgorithm(alg) => write!(f, "unsupported token algorithm {}", alg),
+            AuthError::UnknownKey => write!(f, "token signed with an unknown key"),
+            AuthError::BadSignature => write!(f, "invalid token signature"),
+            AuthError::Expired => write!(f, "token expired"),
+            AuthError::NotYetValid => write!(f, "token not yet valid"),
+            AuthError::WrongIssuer => write!(f, "token issued by another issuer"),
+            AuthError::WrongAudience => write!(f, "token issued for another audience"),
+            AuthError::KeysUnavailable(e) => write!(f, "signing keys unavailable: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+/// A key of a JWKS, as published by the identity provider
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Jwk {
+    pub kty: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kid: Option<String>,
+    /// RSA modulus and exponent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub n: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub e: Option<String>,
+    /// EC curve and point
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crv: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub x: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub y: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Jwks {
+    pub keys: Vec<Jwk>,
+}
+
+#[derive(Deserialize)]
+struct Header {
+    alg: String,
+    #[serde(default)]
+    kid: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Audience {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Audience {
+    fn contains(&self, audience: &str) -> bool {
+        match self {
+            Audience::One(one) => one == audience,
+            Audience::Many(many) => many.iter().any(|a| a == audience),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Claims {
+    sub: String,
+    exp: i64,
+    #[serde(default)]
+    nbf: Option<i64>,
+    #[serde(default)]
+    iss: Option<String>,
+    #[serde(default)]
+    aud: Option<Audience>,
+    #[serde(default)]
+    email: Option<String>,
+    #[serde(default)]
+    scope: Option<String>,
+}
+
+#[derive(Default)]
+struct KeyCache {
+    keys: Vec<Jwk>,
+    fetched_at: Option<Instant>,
+}
+
+pub struct JwtVerifier {
+    jwks_url: Option<String>,
+    /// Keys given up front, never fetched
+    pinned: bool,
+    issuer: Option<String>,
+    audience: Option<String>,
+    keys: RwLock<KeyCache>,
+    client: reqwest::Client,
+    clock: Arc<dyn Clock>,
+}
+
+impl JwtVerifier {
+    /// Verify against the key set at `jwks_url`, fetched when first needed
+    pub fn new(jwks_url: Option<String>) -> Self {
+        Self {
+            jwks_url,
+            pinned: false,
+            issuer: None,
+            audience: None,
+            keys: RwLock::new(KeyCache::default()),
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .unwrap_or_default(),
+            clock: clock::system(),
+        }
+    }
+
+    /// A verifier that lets every request through
+    pub fn disabled() -> Self {
+        Self::new(None)
+    }
+
+    pub fn from_env() -> Self {
+        let Ok(jwks_url) = std::env::var("JWT_JWKS_URL") else {
+            return Self::disabled();
+        };
+        let mut verifier = Self::new(Some(jwks_url));
+        if let Ok(issuer) = std::env::var("JWT_ISSUER") {
+            verifier = verifier.with_issuer(issuer);
+        }
+        if let Ok(audience) = std::env::var("JWT_AUDIENCE") {
+            verifier = verifier.with_audience(audience);
+        }
+        verifier
+    }
+
+    /// Verify against `jwks` instead of fetching a key set
+    pub fn with_keys(jwks: Jwks) -> Self {
+        Self {
+            pinned: true,
+            keys: RwLock::new(KeyCache {
+                keys: jwks.keys,
+                fetched_at: None,
+            }),
+            ..Self::new(None)
+        }
+    }
+
+    pub fn with_issuer(mut self, issuer: impl Into<String>) -> Self {
+        self.issuer = Some(issuer.into());
+        self
+    }
+
+    pub fn with_audience(mut self, audience: impl Into<String>) -> Self {
+        self.audience = Some(audience.into());
+        self
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Whether tokens are required: a key set is configured or was given
+    pub fn is_enabled(&self) -> bool {
+        self.jwks_url.is_some() || self.pinned
+    }
+
+    /// The user a compact JWS `token` was issued to, if it holds
+    pub async fn verify(&self, token: &str) -> Result<UserContext, AuthError> {
+        let mut parts = token.split('.');
+        let (Some(header), Some(payload), Some(signature), None) = (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(AuthError::Malformed);
+        };
+        let header: Header = decode_json(header)?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).map_err(|_| AuthError::Malformed)?;
+        let key = self.key(header.kid.as_deref(), &header.alg).await?;
+        let (signed, _) = token.rsplit_once('.').ok_or(AuthError::Malformed)?;
+        verify_signature(&key, &header.alg, signed.as_bytes(), &signature)?;
+
+        let claims: Claims = decode_json(payload)?;
+        let now = self.clock.now().timestamp();
+        if claims.exp + LEEWAY_SECS < now {
+            return Err(AuthError::Expired);
+        }
+        if claims.nbf.is_some_and(|nbf| nbf - LEEWAY_SECS > now) {
+            return Err(AuthError::NotYetValid);
+        }
+        if self.issuer.is_some() && claims.iss != self.issuer {
+            return Err(AuthError::WrongIssuer);
+        }
+        if let Some(audience) = &self.audience {
+            if !claims.aud.is_some_and(|aud| aud.contains(audience)) {
+                return Err(AuthError::WrongAudience);
+            }
+        }
+
+        Ok(UserContext {
+            user_id: claims.sub,
+            email: claims.email,
+            scopes: claims
+                .scope
+                .map(|scope| scope.split_whitespace().map(str::to_string).collect())
+                .unwrap_or_default(),
+            expires_at: Utc.timestamp_opt(claims.exp, 0).single().ok_or(AuthError::Malformed)?,
+        })
+    }
+
+    /// The key for `kid`, fetching the key set when it is stale or lacks the key
+    async fn key(&self, kid: Option<&str>, alg: &str) -> Result<Jwk, AuthError> {
+        let now = self.clock.instant();
+        let (found, stale, may_refresh) = {
+            let cache = self.keys.read().await;
+            let age = cache.fetched_at.map(|at| now.saturating_duration_since(at));
+            (
+                find_key(&cache.keys, kid, alg),
+                age.is_none_or(|age| age >= JWKS_TTL),
+                age.is_none_or(|age| age >= JWKS_MIN_REFRESH),
+            )
+        };
+        let Some(url) = &self.jwks_url else {
+            return found.ok_or(AuthError::UnknownKey);
+        };
+        match found {
+            Some(key) if !stale => return Ok(key),
+            None if !may_refresh => return Err(AuthError::UnknownKey),
+            _ => {}
+        }
+
+        match self.fetch(url).await {
+            Ok(jwks) => {
+                let key = find_key(&jwks.keys, kid, alg);
+                *self.keys.write().await = KeyCache {
+                    keys: jwks.keys,
+                    fetched_at: Some(now),
+                };
+                key.ok_or(AuthError::UnknownKey)
+            }
+            // Keep using a stale key set while the provider is unreachable
+            Err(e) => found.ok_or(AuthError::KeysUnavailable(e.to_string())),
+        }
+    }
+
+    async fn fetch(&self, url: &str) -> Result<Jwks, reqwest::Error> {
+        self.client.get(url).send().await?.error_for_status()?.json().await
+    }
+}
+
+fn decode_json<T: for<'de> Deserialize<'de>>(part: &str) -> Result<T, AuthError> {
+    let bytes = URL_SAFE_NO_PAD.decode(part).map_err(|_| AuthError::Malformed)?;
+    serde_json::from_slice(&bytes).map_err(|_| AuthError::Malformed)
+}
+
+/// The key matching `kid` (or the only key of the algorithm's type when the token names none)
+fn find_key(keys: &[Jwk], kid: Option<&str>, alg: &str) -> Option<Jwk> {
+    let kty = match alg {
+        "RS256" => "RSA",
+        "ES256" => "EC",
+        _ => return None,
+    };
+    let mut candidates = keys.iter().filter(|key| key.kty == kty);
+    match kid {
+        Some(kid) => candidates.find(|key| key.kid.as_deref() == Some(kid)).cloned(),
+        None => match (candidates.next(), candidates.next()) {
+            (Some(only), None) => Some(only.clone()),
+            _ => None,
+        },
+    }
+}
+
+fn verify_signature(key: &Jwk, alg: &str, message: &[u8], signature: &[u8]) -> Result<(), AuthError> {
+    let component = |value: &Option<String>| {
+        value
+            .as_deref()
+            .and_then(|value| URL_SAFE_NO_PAD.decode(value).ok())
+            .ok_or(AuthError::UnknownKey)
+    };
+    let verified = match alg {
+        "RS256" => RsaPublicKeyComponents {
+            n: component(&key.n)?,
+            e: component(&key.e)?,
+        }
+        .verify(&signature::RSA_PKCS1_2048_8192_SHA256, message, signature),
+        "ES256" => {
+            if key.crv.as_deref() != Some("P-256") {
+                return Err(AuthError::UnknownKey);
+            }
+            // Uncompressed SEC1 point
+            let mut point = vec![0x04];
+            point.extend(component(&key.x)?);
+            point.extend(component(&key.y)?);
+            UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_FIXED, point).verify(message, signature)
+        }
+        other => return Err(AuthError::UnsupportedAlgorithm(other.to_string())),
+    };
+    verified.map_err(|_| AuthError::BadSignature)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use ring::rand::SystemRandom;
+    use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+    use serde_json::{json, Value};
+
+    /// A P-256 signing key and the JWKS publishing it as `kid`
+    pub(crate) fn signing_key(kid: &str) -> (EcdsaKeyPair, Jwks) {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+        let pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng).unwrap();
+        let point = pair.public_key().as_ref();
+        let jwk = Jwk {
+            kty: "EC".to_string(),
+            kid: Some(kid.to_string()),
+            n: None,
+            e: None,
+            crv: Some("P-256".to_string()),
+            x: Some(URL_SAFE_NO_PAD.encode(&point[1..33])),
+            y: Some(URL_SAFE_NO_PAD.encode(&point[33..])),
+        };
+        (pair, Jwks { keys: vec![jwk] })
+    }
+
+    pub(crate) fn sign(pair: &EcdsaKeyPair, kid: &str, claims: &Value) -> String {
+        let header = json!({"alg": "ES256", "typ": "JWT", "kid": kid});
+        let signed = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(header.to_string()),
+            URL_SAFE_NO_PAD.encode(claims.to_string())
+        );
+        let signature = pair.sign(&SystemRandom::new(), signed.as_bytes()).unwrap();
+        format!("{}.{}", signed, URL_SAFE_NO_PAD.encode(signature.as_ref()))
+    }
+
+    #[tokio::test]
+    async fn test_verifies_signature_expiry_issuer_and_audience() {
+        let clock = Arc::new(MockClock::new());
+        let (pair, jwks) = signing_key("k1");
+        let verifier = JwtVerifier::with_keys(jwks)
+            .with_issuer("https://id.example.com/")
+            .with_audience("deal-service")
+            .with_clock(clock.clone());
+        assert!(verifier.is_enabled());
+        assert!(!JwtVerifier::disabled().is_enabled());
+
+        let exp = clock.now().timestamp() + 300;
+        let claims = json!({
+            "sub": "user-1",
+            "iss": "https://id.example.com/",
+            "aud": ["deal-service", "other"],
+            "exp": exp,
+            "email": "a@example.com",
+            "scope": "alerts:write coupons:test",
+        });
+        let token = sign(&pair, "k1", &claims);
+        let user = verifier.verify(&token).await.unwrap();
+        assert_eq!(user.user_id, "user-1");
+        assert_eq!(user.email.as_deref(), Some("a@example.com"));
+        assert_eq!(user.scopes, vec!["alerts:write", "coupons:test"]);
+        assert_eq!(user.expires_at.timestamp(), exp);
+
+        // A payload swapped under the signature
+        let forged = json!({"sub": "admin", "iss": "https://id.example.com/", "aud": "deal-service", "exp": exp});
+        let mut parts: Vec<String> = token.split('.').map(str::to_string).collect();
+        parts[1] = URL_SAFE_NO_PAD.encode(forged.to_string());
+        assert_eq!(verifier.verify(&parts.join(".")).await, Err(AuthError::BadSignature));
+
+        let (other, _) = signing_key("k1");
+        assert_eq!(verifier.verify(&sign(&other, "k1", &claims)).await, Err(AuthError::BadSignature));
+        assert_eq!(verifier.verify(&sign(&pair, "k2", &claims)).await, Err(AuthError::UnknownKey));
+        assert_eq!(verifier.verify("not.a-token").await, Err(AuthError::Malformed));
+
+        let mut wrong_issuer = claims.clone();
+        wrong_issuer["iss"] = json!("https://evil.example.com/");
+        assert_eq!(verifier.verify(&sign(&pair, "k1", &wrong_issuer)).await, Err(AuthError::WrongIssuer));
+        let mut wrong_audience = claims.clone();
+        wrong_audience["aud"] = json!("billing");
+        assert_eq!(verifier.verify(&sign(&pair, "k1", &wrong_audience)).await, Err(AuthError::WrongAudience));
+
+        clock.advance(Duration::from_secs(300 + LEEWAY_SECS as u64 + 1));
+        assert_eq!(verifier.verify(&token).await, Err(AuthError::Expired));
+    }
+}
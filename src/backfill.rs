@@ -0,0 +1,492 @@
+//! Backfill orchestration
+//!
+//! A backfill is a long historical job: re-scraping every merchant in the coupon
+//! store, or replaying the whole snapshot archive through the current parser (see
+//! [`crate::reprocess`]). When it starts, its work is planned as a list of chunks,
+//! and each chunk is checkpointed as it completes. Plan and checkpoints are kept
+//! with the [`ScrapeQueue`], so a backfill is visible to every instance and resumes
+//! at its next chunk after a restart.
+//!
+//! A rescrape chunk is one [`JobPriority::Backfill`] job, so interactive and
+//! scheduled scrapes go first; the backfill waits for it to finish before queueing
+//! the next. A reprocess chunk is replayed directly. Chunks are driven by
+//! [`Backfills::run_due`], which should run on a single instance; see
+//! [`LeaderElection`](crate::cluster::LeaderElection). A paused backfill finishes
+//! the chunk it is on and takes no further one until it is resumed. The ETA is the
+//! average time of the chunks done so far times the chunks left.
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::clock::{self, Clock};
+use crate::coupon_engine::archive::{SnapshotArchive, SnapshotFilter, SnapshotRef};
+use crate::jobs::{JobPriority, JobStatus, ScrapeQueue, MAX_URLS_PER_JOB};
+use crate::reprocess::Reprocessor;
+use crate::storage::coupon_store::CouponStore;
+
+/// How often [`Backfills::run_due`] should run
+pub const BACKFILL_TICK: Duration = Duration::from_secs(5);
+/// Tenant the scrape jobs of rescrapes are queued under
+pub const BACKFILL_TENANT: &str = "backfill";
+const DEFAULT_SNAPSHOTS_PER_CHUNK: usize = 50;
+/// Finished backfills kept for status lookups; older ones are dropped first
+const MAX_FINISHED_BACKFILLS: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BackfillKind {
+    /// Scrape the home page of every merchant in the coupon store
+    Rescrape,
+    /// Replay archived snapshots through the current parser
+    Reprocess,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BackfillStatus {
+    Running,
+    Paused,
+    Completed,
+    Failed,
+}
+
+impl BackfillStatus {
+    pub fn is_finished(self) -> bool {
+        matches!(self, Self::Completed | Self::Failed)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct BackfillRequest {
+    pub kind: BackfillKind,
+    /// Snapshots to replay; a rescrape only uses `merchant`
+    #[serde(flatten)]
+    pub filter: SnapshotFilter,
+    /// URLs or snapshots per chunk (default 100 and 50)
+    pub chunk_size: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Backfill {
+    pub id: Uuid,
+    pub kind: BackfillKind,
+    pub status: BackfillStatus,
+    pub chunks_total: u32,
+    /// Chunks checkpointed as done; a restarted backfill resumes after the last one
+    pub chunks_done: u32,
+    /// Merchant URLs or snapshots
+    pub items_total: u32,
+    pub items_done: u32,
+    /// URLs that failed or were dropped, or snapshots that could not be read
+    pub items_failed: u32,
+    pub coupons_found: u32,
+    /// Time spent on chunks, not counting pauses
+    pub busy_seconds: f64,
+    /// Estimated completion while running, once a chunk is done
+    #[serde(default)]
+    pub eta: Option<DateTime<Utc>>,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+impl Backfill {
+    fn with_eta(mut self, now: DateTime<Utc>) -> Self {
+        self.eta = None;
+        if self.status == BackfillStatus::Running && self.chunks_done > 0 {
+            let per_chunk = self.busy_seconds / self.chunks_done as f64;
+            let remaining = per_chunk * self.chunks_total.saturating_sub(self.chunks_done) as f64;
+            self.eta = chrono::Duration::from_std(Duration::from_secs_f64(remaining)).ok().map(|left| now + left);
+        }
+        self
+    }
+}
+
+/// A backfill with its plan, as kept with the scrape job queue
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct StoredBackfill {
+    pub(crate) backfill: Backfill,
+    /// Merchant URLs or snapshot paths of each chunk
+    chunks: Vec<Vec<String>>,
+    /// The job scraping the current chunk of a rescrape
+    #[serde(default)]
+    pending_job: Option<Uuid>,
+    /// When the current chunk started
+    #[serde(default)]
+    chunk_started_at: Option<DateTime<Utc>>,
+}
+
+impl StoredBackfill {
+    /// Record the current chunk as done at `now`, completing the backfill after its last chunk
+    fn checkpoint(&mut self, done: u32, failed: u32, coupons: u32, now: DateTime<Utc>) {
+        let backfill = &mut self.backfill;
+        backfill.chunks_done += 1;
+        backfill.items_done += done;
+        backfill.items_failed += failed;
+        backfill.coupons_found += coupons;
+        if let Some(started_at) = self.chunk_started_at.take() {
+            backfill.busy_seconds += (now - started_at).to_std().unwrap_or_default().as_secs_f64();
+        }
+        self.pending_job = None;
+        if backfill.chunks_done >= backfill.chunks_total && !backfill.status.is_finished() {
+            backfill.status = BackfillStatus::Completed;
+            backfill.finished_at = Some(now);
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum BackfillError {
+    /// A reprocess backfill needs `SNAPSHOT_ARCHIVE_DIR`
+    NoArchive,
+    NotFound,
+    /// Only a running backfill can be paused, and only a paused one resumed
+    InvalidStatus(BackfillStatus),
+    Plan(String),
+}
+
+impl fmt::Display for BackfillError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoArchive => write!(f, "no snapshot archive configured (set SNAPSHOT_ARCHIVE_DIR)"),
+            Self::NotFound => write!(f, "no such backfill"),
+            Self::InvalidStatus(status) => write!(f, "backfill is {:?}", status),
+            Self::Plan(e) => write!(f, "could not plan the backfill: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for BackfillError {}
+
+pub struct Backfills {
+    queue: Arc<ScrapeQueue>,
+    store: Arc<CouponStore>,
+    archive: Option<Arc<SnapshotArchive>>,
+    reprocessor: Arc<Reprocessor>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Backfills {
+    pub fn new(
+        queue: Arc<ScrapeQueue>,
+        store: Arc<CouponStore>,
+        archive: Option<Arc<SnapshotArchive>>,
+        reprocessor: Arc<Reprocessor>,
+    ) -> Self {
+        Self {
+            queue,
+            store,
+            archive,
+            reprocessor,
+            clock: clock::system(),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Plan a backfill; its chunks run on the next ticks of [`run_due`](Self::run_due)
+    pub async fn start(&self, request: BackfillRequest) -> Result<Backfill, BackfillError> {
+        let (items, chunk_size) = match request.kind {
+            BackfillKind::Rescrape => {
+                let merchants: BTreeSet<String> = self
+                    .store
+                    .list()
+                    .await
+                    .into_iter()
+                    .filter(|coupon| request.filter.merchant.as_ref().is_none_or(|merchant| coupon.merchant_domain == *merchant))
+                    .map(|coupon| format!("https://{}/", coupon.merchant_domain))
+                    .collect();
+                (merchants.into_iter().collect::<Vec<_>>(), request.chunk_size.unwrap_or(MAX_URLS_PER_JOB).min(MAX_URLS_PER_JOB))
+            }
+            BackfillKind::Reprocess => {
+                let archive = self.archive.as_ref().ok_or(BackfillError::NoArchive)?;
+                let snapshots = archive.list(&request.filter).await.map_err(|e| BackfillError::Plan(e.to_string()))?;
+                let paths = snapshots.into_iter().map(|snapshot| snapshot.path.display().to_string()).collect();
+                (paths, request.chunk_size.unwrap_or(DEFAULT_SNAPSHOTS_PER_CHUNK))
+            }
+        };
+        let chunks: Vec<Vec<String>> = items.chunks(chunk_size.max(1)).map(|chunk| chunk.to_vec()).collect();
+
+        let now = self.clock.now();
+        let backfill = Backfill {
+            id: Uuid::new_v4(),
+            kind: request.kind,
+            status: match chunks.is_empty() {
+                true => BackfillStatus::Completed,
+                false => BackfillStatus::Running,
+            },
+            chunks_total: chunks.len() as u32,
+            chunks_done: 0,
+            items_total: items.len() as u32,
+            items_done: 0,
+            items_failed: 0,
+            coupons_found: 0,
+            busy_seconds: 0.0,
+            eta: None,
+            error: None,
+            created_at: now,
+            finished_at: chunks.is_empty().then_some(now),
+        };
+        let stored = StoredBackfill {
+            backfill: backfill.clone(),
+            chunks,
+            pending_job: None,
+            chunk_started_at: None,
+        };
+        self.queue.insert_backfill(stored, MAX_FINISHED_BACKFILLS).await;
+        Ok(backfill)
+    }
+
+    pub async fn get(&self, id: Uuid) -> Option<Backfill> {
+        let stored = self.queue.backfill(id).await?;
+        Some(stored.backfill.with_eta(self.clock.now()))
+    }
+
+    /// Every backfill, newest first
+    pub async fn list(&self) -> Vec<Backfill> {
+        let now = self.clock.now();
+        let mut backfills: Vec<Backfill> = self.queue.backfills().await.into_iter().map(|stored| stored.backfill.with_eta(now)).collect();
+        backfills.sort_by_key(|backfill| std::cmp::Reverse(backfill.created_at));
+        backfills
+    }
+
+    /// Stop taking chunks; the current one still finishes
+    pub async fn pause(&self, id: Uuid) -> Result<Backfill, BackfillError> {
+        self.transition(id, BackfillStatus::Running, BackfillStatus::Paused).await
+    }
+
+    pub async fn resume(&self, id: Uuid) -> Result<Backfill, BackfillError> {
+        self.transition(id, BackfillStatus::Paused, BackfillStatus::Running).await
+    }
+
+    async fn transition(&self, id: Uuid, from: BackfillStatus, to: BackfillStatus) -> Result<Backfill, BackfillError> {
+        let backfill = self
+            .queue
+            .update_backfill(id, |stored| {
+                if stored.backfill.status != from {
+                    return Err(BackfillError::InvalidStatus(stored.backfill.status));
+                }
+                stored.backfill.status = to;
+                Ok(stored.backfill.clone())
+            })
+            .await
+            .ok_or(BackfillError::NotFound)??;
+        Ok(backfill.with_eta(self.clock.now()))
+    }
+
+    /// Advance every running backfill by a step, oldest first, and return how many
+    /// chunks were completed
+    pub async fn run_due(&self) -> usize {
+        let mut running: Vec<StoredBackfill> = self
+            .queue
+            .backfills()
+            .await
+            .into_iter()
+            .filter(|stored| stored.backfill.status == BackfillStatus::Running)
+            .collect();
+        running.sort_by_key(|stored| stored.backfill.created_at);
+
+        let mut completed = 0;
+        for stored in running {
+            let result = match stored.backfill.kind {
+                BackfillKind::Rescrape => self.step_rescrape(&stored).await,
+                BackfillKind::Reprocess => self.step_reprocess(&stored).await,
+            };
+            match result {
+                Ok(done) => completed += usize::from(done),
+                Err(e) => {
+                    tracing::warn!(backfill = %stored.backfill.id, error = %e, "Backfill failed");
+                    let now = self.clock.now();
+                    self.queue
+                        .update_backfill(stored.backfill.id, |stored| {
+                            stored.backfill.status = BackfillStatus::Failed;
+                            stored.backfill.error = Some(e.to_string());
+                            stored.backfill.finished_at = Some(now);
+                        })
+                        .await;
+                }
+            }
+        }
+        completed
+    }
+
+    /// Queue the next chunk's job, or checkpoint the chunk once its job has finished
+    async fn step_rescrape(&self, stored: &StoredBackfill) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let id = stored.backfill.id;
+        let Some(urls) = stored.chunks.get(stored.backfill.chunks_done as usize) else {
+            return Ok(false);
+        };
+
+        let Some(job_id) = stored.pending_job else {
+            let now = self.clock.now();
+            match self.queue.submit(BACKFILL_TENANT, urls.clone(), JobPriority::Backfill).await {
+                Ok(job) => {
+                    self.queue
+                        .update_backfill(id, |stored| {
+                            stored.pending_job = Some(job.id);
+                            stored.chunk_started_at = Some(now);
+                        })
+                        .await;
+                    return Ok(false);
+                }
+                // e.g. every merchant of the chunk opted out since it was planned
+                Err(e) => {
+                    tracing::warn!(backfill = %id, error = %e, "Backfill skipped a chunk");
+                    let failed = urls.len() as u32;
+                    self.queue.update_backfill(id, |stored| stored.checkpoint(0, failed, 0, now)).await;
+                    return Ok(true);
+                }
+            }
+        };
+
+        let (done, failed, coupons) = match self.queue.get(BACKFILL_TENANT, job_id).await {
+            Some(job) if !job.status.is_finished() => return Ok(false),
+            Some(job) if job.status == JobStatus::Completed => {
+                let failed = job.blocked_urls.len() + job.retried_urls.len() + job.dead_lettered_urls.len();
+                let done = urls.len().saturating_sub(failed);
+                (done as u32, failed as u32, job.coupons.len() as u32)
+            }
+            // Failed jobs are retried by the queue; cancelled or pruned ones are not
+            _ => (0, urls.len() as u32, 0),
+        };
+        let now = self.clock.now();
+        self.queue.update_backfill(id, |stored| stored.checkpoint(done, failed, coupons, now)).await;
+        Ok(true)
+    }
+
+    /// Replay the next chunk of snapshots
+    async fn step_reprocess(&self, stored: &StoredBackfill) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let Some(paths) = stored.chunks.get(stored.backfill.chunks_done as usize) else {
+            return Ok(false);
+        };
+        let started_at = self.clock.now();
+        let mut snapshots = Vec::with_capacity(paths.len());
+        let mut unreadable = 0;
+        for path in paths {
+            match SnapshotRef::from_path(Path::new(path)) {
+                Some(snapshot) => snapshots.push(snapshot),
+                None => unreadable += 1,
+            }
+        }
+
+        let (coupons, failed) = self.reprocessor.replay(&snapshots).await?;
+        let failed = failed + unreadable;
+        let done = paths.len() as u32 - failed;
+        let now = self.clock.now();
+        self.queue
+            .update_backfill(stored.backfill.id, |stored| {
+                stored.chunk_started_at = Some(started_at);
+                stored.checkpoint(done, failed, coupons, now);
+            })
+            .await;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    const PAGE: &str = "<p>Use code SAVE20 for 20% off your order</p>";
+
+    #[tokio::test]
+    async fn test_reprocess_checkpoints_chunks_and_resumes_after_restart() {
+        let dir = std::env::temp_dir().join(format!("backfill_{}", Uuid::new_v4()));
+        let path = dir.join("scrape_jobs.json");
+        let archive = Arc::new(SnapshotArchive::new(dir.join("snapshots")));
+        for merchant in ["a.example.com", "b.example.com", "c.example.com"] {
+            archive.store(&format!("https://{}/deals", merchant), PAGE).await.unwrap();
+        }
+        let store = Arc::new(CouponStore::new());
+        let reprocessor = Arc::new(Reprocessor::new(Some(archive.clone()), store.clone()));
+        let clock = Arc::new(MockClock::new());
+        let backfills = |queue: Arc<ScrapeQueue>| {
+            Backfills::new(queue, store.clone(), Some(archive.clone()), reprocessor.clone()).with_clock(clock.clone())
+        };
+
+        let first = backfills(Arc::new(ScrapeQueue::new(Some(path.clone()))));
+        let request = BackfillRequest {
+            kind: BackfillKind::Reprocess,
+            filter: SnapshotFilter::default(),
+            chunk_size: Some(2),
+        };
+        let started = first.start(request).await.unwrap();
+        assert_eq!((started.chunks_total, started.items_total), (2, 3));
+        assert_eq!(first.run_due().await, 1);
+        assert_eq!(store.list().await.len(), 2);
+
+        let queue = Arc::new(ScrapeQueue::new(Some(path)));
+        queue.load().await.unwrap();
+        let restarted = backfills(queue);
+        let resumed = restarted.get(started.id).await.unwrap();
+        assert_eq!((resumed.chunks_done, resumed.items_done), (1, 2));
+
+        restarted.pause(started.id).await.unwrap();
+        assert_eq!(restarted.run_due().await, 0);
+        assert_eq!(restarted.pause(started.id).await.unwrap_err(), BackfillError::InvalidStatus(BackfillStatus::Paused));
+        restarted.resume(started.id).await.unwrap();
+        assert_eq!(restarted.run_due().await, 1);
+
+        let finished = restarted.get(started.id).await.unwrap();
+        assert_eq!(finished.status, BackfillStatus::Completed);
+        assert_eq!((finished.items_done, finished.coupons_found), (3, 3));
+        assert_eq!(store.list().await.len(), 3);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn test_rescrape_queues_one_chunk_at_a_time_and_reports_eta() {
+        let store = Arc::new(CouponStore::with_sample_data());
+        let merchants: BTreeSet<_> = store.list().await.into_iter().map(|coupon| coupon.merchant_domain).collect();
+        let queue = Arc::new(ScrapeQueue::default());
+        let clock = Arc::new(MockClock::new());
+        let reprocessor = Arc::new(Reprocessor::new(None, store.clone()));
+        let backfills = Backfills::new(queue.clone(), store, None, reprocessor).with_clock(clock.clone());
+
+        let request = BackfillRequest {
+            kind: BackfillKind::Rescrape,
+            filter: SnapshotFilter::default(),
+            chunk_size: Some(1),
+        };
+        let started = backfills.start(request).await.unwrap();
+        assert_eq!(started.chunks_total as usize, merchants.len());
+        assert!(merchants.len() > 1);
+
+        assert_eq!(backfills.run_due().await, 0);
+        let job_id = queue.backfill(started.id).await.unwrap().pending_job.unwrap();
+        let job = queue.get(BACKFILL_TENANT, job_id).await.unwrap();
+        assert_eq!((job.priority, job.urls.len()), (JobPriority::Backfill, 1));
+        // Still waiting on the job
+        assert_eq!(backfills.run_due().await, 0);
+
+        clock.advance(Duration::from_secs(60));
+        queue.cancel(BACKFILL_TENANT, job_id).await.unwrap();
+        assert_eq!(backfills.run_due().await, 1);
+        let progress = backfills.get(started.id).await.unwrap();
+        assert_eq!((progress.chunks_done, progress.items_failed), (1, 1));
+        let left = (merchants.len() - 1) as i64 * 60;
+        assert_eq!(progress.eta, Some(clock.now() + chrono::Duration::seconds(left)));
+
+        let no_archive = backfills
+            .start(BackfillRequest {
+                kind: BackfillKind::Reprocess,
+                filter: SnapshotFilter::default(),
+                chunk_size: None,
+            })
+            .await;
+        assert_eq!(no_archive.unwrap_err(), BackfillError::NoArchive);
+    }
+}
@@ -0,0 +1,439 @@
+//! Subcommands of the `deal-service` binary
+//!
+//! The binary only picks the subcommand and the runtime to run it on. Each one
+//! returns the process exit code: 0 when it succeeded, 1 when it failed and 2 on
+//! a usage error.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::runtime::Handle;
+use utoipa::OpenApi;
+
+use crate::api::{self, openapi::ApiDoc};
+use crate::cluster::Role;
+use crate::config::ConfigReport;
+use crate::coupon_engine::archive::SnapshotArchive;
+use crate::coupon_engine::profiles::DomainProfiles;
+use crate::coupon_engine::rate_limiter::RateLimiter;
+use crate::coupon_engine::{golden, EngineConfig};
+use crate::licensing::SourceLicenses;
+use crate::models::domain::MerchantDomain;
+use crate::reprocess::{ReprocessRequest, Reprocessor, RunStatus};
+use crate::runtimes::{self, RuntimeConfig};
+use crate::seeding::{SeedRequest, Seeder};
+use crate::server::ServerConfig;
+use crate::storage::coupon_store::CouponStore;
+use crate::Services;
+
+const SEED_USAGE: &str = "usage: deal-service seed [--bundle NAME]...";
+const REPROCESS_USAGE: &str =
+    "usage: deal-service reprocess [--dry-run] [--since RFC3339] [--until RFC3339] [--merchant DOMAIN] [--batch-size N]";
+
+/// `[--role api|worker|all]`: run the service, scraping on `scrape_runtime`; returns only when it
+/// cannot start or stops serving
+pub async fn serve(args: Vec<String>, runtimes: RuntimeConfig, scrape_runtime: Handle) -> i32 {
+    let role = match Role::from_args(args) {
+        Ok(role) => role,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+
+    let server = match ServerConfig::from_env() {
+        Ok(server) => server,
+        Err(e) => {
+            eprintln!("Invalid server configuration: {}", e);
+            return 2;
+        }
+    };
+
+    let report = ConfigReport::from_env(role);
+    if !report.diagnostics.is_empty() {
+        eprint!("{}", report);
+    }
+    if report.refuses_start() {
+        eprintln!("Refusing to start in production with fatal configuration errors");
+        return 1;
+    }
+
+    let services = Services::builder().scrape_runtime(scrape_runtime).build().await;
+    println!("📈 Deal scoring model: {}", services.scorer.model_version());
+    services.spawn_tasks_for(role).await;
+
+    if !role.serves_api() {
+        println!("🛠️ Deal Service worker {} running", services.leader.instance_id());
+        std::future::pending::<()>().await;
+    }
+
+    let app = api::router(&services);
+
+    println!(
+        "💰 Deal Service running on {}://{} ({:?} role, {} API / {} scrape worker threads)",
+        server.scheme(),
+        server.addr(),
+        role,
+        runtimes.api_threads,
+        runtimes.scrape_threads
+    );
+    if let Err(e) = server.serve(app).await {
+        eprintln!("Failed to serve on {}: {}", server.addr(), e);
+        return 1;
+    }
+    0
+}
+
+/// `openapi`: print the document served at `/openapi.json`, for generating clients
+/// without a running service
+pub fn openapi() -> i32 {
+    println!("{}", ApiDoc::openapi().to_pretty_json().expect("the OpenAPI document serializes"));
+    0
+}
+
+/// `parser bless [CORPUS]`: rewrite the parser goldens from the current parser output
+pub async fn parser(args: &[String]) -> i32 {
+    if args.first().map(String::as_str) != Some("bless") {
+        eprintln!("usage: deal-service parser bless [CORPUS] (default {})", golden::DEFAULT_CORPUS);
+        return 2;
+    }
+
+    let corpus = PathBuf::from(args.get(1).map_or(golden::DEFAULT_CORPUS, String::as_str));
+    match golden::bless(&corpus).await {
+        Ok(updated) => {
+            for page in &updated {
+                println!("blessed {}", page.display());
+            }
+            println!("{} golden(s) updated in {}", updated.len(), corpus.display());
+            0
+        }
+        Err(e) => {
+            eprintln!("Failed to bless {}: {}", corpus.display(), e);
+            1
+        }
+    }
+}
+
+/// `runtime bench [--seconds N]`: request latency on the API runtime while the
+/// parser saturates the scrape pool, shared and separate (default 10 seconds each)
+pub fn runtime(args: &[String], config: RuntimeConfig) -> i32 {
+    const USAGE: &str = "usage: deal-service runtime bench [--seconds N]";
+    let seconds = match args {
+        [command] if command == "bench" => 10,
+        [command, flag, value] if command == "bench" && flag == "--seconds" => match value.parse() {
+            Ok(seconds) => seconds,
+            Err(_) => {
+                eprintln!("{}", USAGE);
+                return 2;
+            }
+        },
+        _ => {
+            eprintln!("{}", USAGE);
+            return 2;
+        }
+    };
+
+    println!(
+        "{} API worker threads, {} scrape worker threads, {}s per mode",
+        config.api_threads, config.scrape_threads, seconds
+    );
+    match runtimes::isolation_bench(config, Duration::from_secs(seconds)) {
+        Ok(summaries) => {
+            for summary in summaries {
+                println!("{}", summary);
+            }
+            0
+        }
+        Err(e) => {
+            eprintln!("Runtime benchmark failed: {}", e);
+            1
+        }
+    }
+}
+
+/// `storage bench [--coupons N]`: time batched upserts of N synthetic listings
+/// (default 200000) into `DATABASE_URL`, first as inserts and then as updates
+#[cfg(feature = "postgres")]
+pub async fn storage(args: &[String]) -> i32 {
+    use crate::models::coupon_listing::{CouponListing, CouponSource};
+    use crate::models::domain::CouponCode;
+    use crate::storage::postgres::{self, BatchConfig, CouponWriter};
+
+    const USAGE: &str = "usage: deal-service storage bench [--coupons N]";
+    let coupons = match args {
+        [bench] if bench == "bench" => 200_000,
+        [bench, flag, value] if bench == "bench" && flag == "--coupons" => match value.parse::<usize>() {
+            Ok(coupons) => coupons,
+            Err(_) => {
+                eprintln!("{}", USAGE);
+                return 2;
+            }
+        },
+        _ => {
+            eprintln!("{}", USAGE);
+            return 2;
+        }
+    };
+    let Ok(url) = std::env::var("DATABASE_URL") else {
+        eprintln!("DATABASE_URL must be set");
+        return 2;
+    };
+
+    let config = BatchConfig::from_env();
+    let run = uuid::Uuid::new_v4().simple().to_string();
+    let scraped_at = chrono::Utc::now();
+    for pass in ["insert", "update"] {
+        let client = match postgres::connect(&url).await {
+            Ok(client) => client,
+            Err(e) => {
+                eprintln!("Failed to connect to Postgres: {}", e);
+                return 1;
+            }
+        };
+        let writer = CouponWriter::spawn(client, config.clone());
+        let started = std::time::Instant::now();
+        for i in 0..coupons {
+            let listing = CouponListing {
+                code: CouponCode::parse(&format!("SAVE{}X{}", i % 90 + 10, i)).expect("bench codes are valid"),
+                title: format!("{}% off sitewide ({})", i % 60 + 5, pass),
+                description: Some("Excludes gift cards and clearance".to_string()),
+                locale: None,
+                merchant_domain: MerchantDomain::parse(&format!("store{}.{}.bench.example", i % 1000, run)).expect("bench domains are valid"),
+                discount_type: "percentage".to_string(),
+                discount_value: Some((i % 60 + 5) as f64),
+                minimum_order: None,
+                source: CouponSource::WebScraping,
+                license: None,
+                extraction_confidence: 0.8,
+                scraped_at,
+                valid_until: None,
+                predicted_success: None,
+                tags: Vec::new(),
+            };
+            if let Err(e) = writer.write(listing).await {
+                eprintln!("{}", e);
+                return 1;
+            }
+        }
+        let stats = match writer.finish().await {
+            Ok(stats) => stats,
+            Err(e) => {
+                eprintln!("{}", e);
+                return 1;
+            }
+        };
+        let elapsed = started.elapsed();
+        println!(
+            "{:<6} {:>8} coupons {:>5} batches {:>6} failed {:>8.2}s {:>10.0} coupons/s",
+            pass,
+            stats.written,
+            stats.batches,
+            stats.failed,
+            elapsed.as_secs_f64(),
+            stats.written as f64 / elapsed.as_secs_f64()
+        );
+    }
+    println!("Bench rows are under merchant domains ending in .{}.bench.example", run);
+    0
+}
+
+fn reprocess_request(args: &[String]) -> Result<ReprocessRequest, String> {
+    let mut request = ReprocessRequest::default();
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        if flag == "--dry-run" {
+            request.dry_run = true;
+            continue;
+        }
+        let value = args.next().ok_or_else(|| format!("{} needs a value", flag))?;
+        let time = || {
+            chrono::DateTime::parse_from_rfc3339(value)
+                .map(|time| time.to_utc())
+                .map_err(|e| format!("{}: {}", flag, e))
+        };
+        match flag.as_str() {
+            "--since" => request.filter.since = Some(time()?),
+            "--until" => request.filter.until = Some(time()?),
+            "--merchant" => request.filter.merchant = Some(MerchantDomain::parse(value)?),
+            "--batch-size" => request.batch_size = Some(value.parse().map_err(|e| format!("{}: {}", flag, e))?),
+            other => return Err(format!("unknown option {}", other)),
+        }
+    }
+    Ok(request)
+}
+
+/// `reprocess [OPTIONS]`: replay the snapshot archive through the current parser,
+/// printing progress to stderr and the rebuilt listings to stdout as JSON lines
+pub async fn reprocess(args: &[String]) -> i32 {
+    let request = match reprocess_request(args) {
+        Ok(request) => request,
+        Err(e) => {
+            eprintln!("{}\n{}", e, REPROCESS_USAGE);
+            return 2;
+        }
+    };
+    let dry_run = request.dry_run;
+
+    let store = Arc::new(CouponStore::new());
+    let archive = SnapshotArchive::from_env().map(Arc::new);
+    let reprocessor = Arc::new(Reprocessor::new(archive, store.clone()));
+    let started = match reprocessor.start(request).await {
+        Ok(run) => run,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+
+    let mut reported = None;
+    let run = loop {
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        let Some(run) = reprocessor.get(started.id).await else {
+            eprintln!("Reprocess run {} disappeared", started.id);
+            return 1;
+        };
+        if reported != Some(run.snapshots_done) {
+            eprintln!(
+                "batch {}/{}: {}/{} snapshots, {} coupons",
+                run.batches_done, run.batches_total, run.snapshots_done, run.snapshots_total, run.coupons_found
+            );
+            reported = Some(run.snapshots_done);
+        }
+        if run.status != RunStatus::Running {
+            break run;
+        }
+    };
+
+    if let Some(e) = &run.error {
+        eprintln!("Reprocess failed: {}", e);
+        return 1;
+    }
+    if !dry_run {
+        for listing in store.list().await {
+            match serde_json::to_string(&listing) {
+                Ok(line) => println!("{}", line),
+                Err(e) => eprintln!("Failed to serialize {}: {}", listing.code, e),
+            }
+        }
+    }
+    eprintln!(
+        "{} coupons from {} snapshots ({} unreadable) with parser {}",
+        run.coupons_found, run.snapshots_done, run.snapshots_failed, run.parser_version
+    );
+    0
+}
+
+/// `seed [--bundle NAME]...`: seed the bundles at `SEED_BUNDLES_PATH` (all of them
+/// unless named), creating the missing domain profiles where the service keeps them,
+/// printing progress to stderr and the seeded listings to stdout as JSON lines
+pub async fn seed(args: &[String]) -> i32 {
+    let mut request = SeedRequest::default();
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        match (flag.as_str(), args.next()) {
+            ("--bundle", Some(name)) => request.bundles.push(name.clone()),
+            _ => {
+                eprintln!("{}", SEED_USAGE);
+                return 2;
+            }
+        }
+    }
+
+    let store = Arc::new(CouponStore::new());
+    let rate_limiter = Arc::new(RateLimiter::new(EngineConfig::default().rate_limit_per_domain));
+    let profiles = Arc::new(DomainProfiles::from_env(rate_limiter).await);
+    let licenses = Arc::new(SourceLicenses::from_env().await);
+    let seeder = Arc::new(Seeder::from_env(store.clone(), profiles).with_licenses(licenses));
+    let started = match seeder.start(request).await {
+        Ok(run) => run,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+
+    let mut reported = None;
+    let run = loop {
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        let Some(run) = seeder.get(started.id).await else {
+            eprintln!("Seed run {} disappeared", started.id);
+            return 1;
+        };
+        if reported != Some(run.feeds_done) {
+            eprintln!(
+                "feeds {}/{} ({} failed): {} merchants added, {} coupons added, {} changed, {} unchanged",
+                run.feeds_done,
+                run.feeds_total,
+                run.feeds_failed,
+                run.merchants_added,
+                run.coupons_added,
+                run.coupons_changed,
+                run.coupons_unchanged
+            );
+            reported = Some(run.feeds_done);
+        }
+        if run.status != RunStatus::Running {
+            break run;
+        }
+    };
+
+    for failure in &run.failures {
+        eprintln!("failed: {} from {}: {}", failure.merchant, failure.feed, failure.error);
+    }
+    if let Some(e) = &run.error {
+        eprintln!("Seeding failed: {}", e);
+        return 1;
+    }
+    for listing in store.list().await {
+        match serde_json::to_string(&listing) {
+            Ok(line) => println!("{}", line),
+            Err(e) => eprintln!("Failed to serialize {}: {}", listing.code, e),
+        }
+    }
+    eprintln!(
+        "{} of {} merchants already had a profile; {} of {} feeds failed",
+        run.merchants_existing, run.merchants_total, run.feeds_failed, run.feeds_total
+    );
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_reprocess_request() {
+        let request = reprocess_request(&args(&[
+            "--dry-run",
+            "--since",
+            "2024-01-01T00:00:00Z",
+            "--merchant",
+            "shop.example.com",
+            "--batch-size",
+            "25",
+        ]))
+        .unwrap();
+        assert!(request.dry_run);
+        assert_eq!(request.filter.since.unwrap().to_rfc3339(), "2024-01-01T00:00:00+00:00");
+        assert_eq!(request.filter.merchant.unwrap().as_str(), "shop.example.com");
+        assert_eq!(request.batch_size, Some(25));
+
+        assert!(reprocess_request(&args(&["--since"])).unwrap_err().contains("needs a value"));
+        assert!(reprocess_request(&args(&["--until", "yesterday"])).is_err());
+        assert!(reprocess_request(&args(&["--all", "yes"])).unwrap_err().contains("unknown option"));
+    }
+
+    #[tokio::test]
+    async fn test_usage_errors_exit_with_2() {
+        assert_eq!(parser(&args(&["polish"])).await, 2);
+        assert_eq!(reprocess(&args(&["--batch-size", "-1"])).await, 2);
+        assert_eq!(seed(&args(&["--bundle"])).await, 2);
+        assert_eq!(runtime(&args(&["bench", "--seconds", "ten"]), RuntimeConfig::from_env()), 2);
+        assert_eq!(serve(args(&["--role", "mainframe"]), RuntimeConfig::from_env(), Handle::current()).await, 2);
+    }
+}
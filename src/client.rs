@@ -0,0 +1,697 @@
+//! Typed async client for the public HTTP API
+//!
+//! Built with the `client` feature. Requests and responses use the same types the
+//! handlers serialize (request bodies are in [`crate::api::requests`]), so a field
+//! renamed on the server breaks the client's build rather than its callers at
+//! runtime. Requests go to the `/api/v1` tree, whose envelope is unwrapped to its
+//! `data` (or its first error); endpoints that return several values get a small
+//! struct here.
+//!
+//! Responses are expected in the default field shape: tenants with a response
+//! schema (see [`crate::tenant::shaping`]) rename fields and should read the raw
+//! JSON instead. The admin endpoints, the partner onboarding flow, coupon clipping
+//! and the SSE deal stream are not covered.
+
+use std::fmt;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use reqwest::{Method, RequestBuilder, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::alerts::natural_language::AlertInterpretation;
+use crate::api::V1;
+use crate::api::requests::{
+    CouponOutcome, ExtensionResult, FetchRequest, JobRequest, MerchantFeedback, NaturalAlertRequest, ValidateCouponRequest,
+};
+use crate::collections::CollectionView;
+use crate::community::{CommunitySummary, IngestReport};
+use crate::coupon_deltas::{Subscription, SubscriptionRequest};
+use crate::coupon_engine::liveness::MerchantLiveness;
+use crate::coupon_engine::validator::CouponValidation;
+use crate::digest::scheduled::{DigestSubscription, DigestSubscriptionRequest, RenderedDigest, ScheduledDigest};
+use crate::digest::DailyDigest;
+use crate::events::EventOccurrence;
+use crate::experiments::RankingStrategy;
+use crate::forecast::PriceForecast;
+use crate::freshness::FreshnessReport;
+use crate::jobs::{JobPriority, ScrapeJob};
+use crate::models::alert::DealAlert;
+use crate::models::comment::CommunityComment;
+use crate::models::coupon_listing::CouponListing;
+use crate::models::deal::Deal;
+use crate::models::domain::{CouponCode, MerchantDomain};
+use crate::models::experiment::ExperimentAssignment;
+use crate::models::interaction::Interaction;
+use crate::notifications::NotificationPreferences;
+use crate::pricing::rewards::EffectivePrice;
+use crate::pricing::shipping::DeliveredPrice;
+use crate::reputation::{MerchantReputation, SignalUpdate};
+use crate::savings::{SavingsEntry, SavingsReport, SavingsSummary};
+use crate::search::facets::Facets;
+use crate::search::query::{ParsedQuery, SearchFilters};
+use crate::search::SearchHit;
+use crate::sharing::{Share, ShareLink, ShareRequest};
+use crate::stacksmart::{Cart, CartPlan};
+use crate::storage::coupon_history::HistoricalCoupon;
+use crate::storage::import::ImportReport;
+use crate::tenant::usage::KeyUsage;
+use crate::tenant::API_KEY_HEADER;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+pub enum ClientError {
+    /// The request could not be sent or the response not read
+    Http(reqwest::Error),
+    /// The service answered with an error status; `error` is its message, if any
+    Api { status: StatusCode, error: Option<String> },
+    /// The response did not have the expected shape
+    Decode(String),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Http(e) => write!(f, "request failed: {}", e),
+            ClientError::Api { status, error: Some(error) } => write!(f, "{}: {}", status, error),
+            ClientError::Api { status, error: None } => write!(f, "{}", status),
+            ClientError::Decode(e) => write!(f, "unexpected response: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<reqwest::Error> for ClientError {
+    fn from(e: reqwest::Error) -> Self {
+        ClientError::Http(e)
+    }
+}
+
+pub type ClientResult<T> = Result<T, ClientError>;
+
+/// `GET /deals` and `GET /deals/trending`
+#[derive(Debug, Clone, Deserialize)]
+pub struct DealList {
+    #[serde(alias = "trending")]
+    pub deals: Vec<Deal>,
+    #[serde(default)]
+    pub model_version: Option<String>,
+    pub experiment: Option<ExperimentAssignment>,
+    /// Deals across all pages; `GET /deals` only
+    #[serde(default)]
+    pub total: Option<usize>,
+}
+
+/// `GET /deals/search`
+#[derive(Debug, Deserialize)]
+pub struct SearchResults {
+    pub results: Vec<SearchHit>,
+    pub facets: Facets,
+    pub query: String,
+    pub interpreted: ParsedQuery,
+    pub experiment: Option<ExperimentAssignment>,
+    /// Matches across all pages
+    #[serde(default)]
+    pub total: usize,
+}
+
+/// `GET /deals/facets`
+#[derive(Debug, Clone, Deserialize)]
+pub struct FacetResults {
+    pub facets: Facets,
+    pub total: usize,
+    pub query: String,
+    pub interpreted: ParsedQuery,
+}
+
+/// A deal related to another, from `GET /deals/:id/similar` (`similarity`) or
+/// `GET /deals/:id/frequently-bought-with` (`strength`)
+#[derive(Debug, Clone, Deserialize)]
+pub struct RelatedDeal {
+    pub deal: Deal,
+    #[serde(alias = "similarity", alias = "strength")]
+    pub score: f64,
+}
+
+/// `POST /alerts/natural`
+#[derive(Debug, Clone, Deserialize)]
+pub struct NaturalAlert {
+    pub alert: DealAlert,
+    pub interpretation: AlertInterpretation,
+    pub requires_confirmation: bool,
+}
+
+/// `GET /events/:id/deals`
+#[derive(Debug, Clone, Deserialize)]
+pub struct EventDeals {
+    pub event: EventOccurrence,
+    pub deals: Vec<Deal>,
+}
+
+/// `GET /digests/subscriptions/:id/preview`
+#[derive(Debug, Clone, Deserialize)]
+pub struct DigestPreview {
+    pub digest: ScheduledDigest,
+    pub rendered: RenderedDigest,
+}
+
+/// A page fetched through `POST /fetch`
+#[derive(Debug, Clone)]
+pub struct FetchedPage {
+    pub body: String,
+    pub content_type: Option<String>,
+    /// `hit`, `revalidated` or `miss`
+    pub cache: Option<String>,
+}
+
+/// Client for one deal service; cheap to clone
+#[derive(Clone)]
+pub struct DealMateClient {
+    http: reqwest::Client,
+    base_url: String,
+    api_key: Option<String>,
+    tenant: Option<String>,
+    caller: Option<String>,
+    user_token: Option<String>,
+}
+
+impl DealMateClient {
+    /// Client for the service at `base_url`, e.g. `http://deal-service:8001`
+    pub fn new(base_url: &str) -> Self {
+        Self {
+            http: reqwest::Client::builder()
+                .timeout(DEFAULT_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key: None,
+            tenant: None,
+            caller: None,
+            user_token: None,
+        }
+    }
+
+    /// Send `X-Api-Key`, which also selects the key's tenant
+    pub fn with_api_key(mut self, key: &str) -> Self {
+        self.api_key = Some(key.to_string());
+        self
+    }
+
+    /// Send `X-Tenant-Id`, for callers without an API key
+    pub fn with_tenant(mut self, tenant: &str) -> Self {
+        self.tenant = Some(tenant.to_string());
+        self
+    }
+
+    /// Send `X-Caller-Id`, the service `POST /fetch` quotas are counted for
+    pub fn with_caller(mut self, caller: &str) -> Self {
+        self.caller = Some(caller.to_string());
+        self
+    }
+
+    /// Send the shopper's JWT as a bearer token, for the endpoints acting for them
+    pub fn with_user_token(mut self, token: &str) -> Self {
+        self.user_token = Some(token.to_string());
+        self
+    }
+
+    /// Use `http` instead of the default client, e.g. for other timeouts or a proxy
+    pub fn with_http_client(mut self, http: reqwest::Client) -> Self {
+        self.http = http;
+        self
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        let mut request = self.http.request(method, format!("{}{}{}", self.base_url, V1, path));
+        if let Some(key) = &self.api_key {
+            request = request.header(API_KEY_HEADER, key);
+        }
+        if let Some(tenant) = &self.tenant {
+            request = request.header("x-tenant-id", tenant);
+        }
+        if let Some(caller) = &self.caller {
+            request = request.header("x-caller-id", caller);
+        }
+        if let Some(token) = &self.user_token {
+            request = request.bearer_auth(token);
+        }
+        request
+    }
+
+    /// Send `request`, turning error statuses into [`ClientError::Api`]
+    async fn send(request: RequestBuilder) -> ClientResult<reqwest::Response> {
+        let response = request.send().await?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+        let body: Option<Value> = response.json().await.ok();
+        // The envelope's first error; `/fetch` is not enveloped
+        let error = body
+            .as_ref()
+            .and_then(|body| body.pointer("/errors/0/message").or_else(|| body.get("error")))
+            .and_then(Value::as_str)
+            .map(String::from);
+        Err(ClientError::Api { status, error })
+    }
+
+    /// The `data` of the response to `request`
+    async fn data(request: RequestBuilder) -> ClientResult<Value> {
+        let mut body: Value = Self::send(request).await?.json().await?;
+        body.get_mut("data")
+            .map(Value::take)
+            .ok_or_else(|| ClientError::Decode("missing envelope data".to_string()))
+    }
+
+    /// The whole `data` of the response to `request`
+    async fn json<T: DeserializeOwned>(request: RequestBuilder) -> ClientResult<T> {
+        let data = Self::data(request).await?;
+        serde_json::from_value(data).map_err(|e| ClientError::Decode(e.to_string()))
+    }
+
+    /// Field `field` of the `data` of the response to `request`
+    async fn field<T: DeserializeOwned>(request: RequestBuilder, field: &str) -> ClientResult<T> {
+        let mut data = Self::data(request).await?;
+        let value = data
+            .get_mut(field)
+            .map(Value::take)
+            .ok_or_else(|| ClientError::Decode(format!("missing field '{}'", field)))?;
+        serde_json::from_value(value).map_err(|e| ClientError::Decode(format!("field '{}': {}", field, e)))
+    }
+
+    async fn accepted(request: RequestBuilder) -> ClientResult<()> {
+        Self::send(request).await.map(|_| ())
+    }
+
+    pub async fn health(&self) -> ClientResult<Value> {
+        Self::json(self.request(Method::GET, "/health")).await
+    }
+
+    /// Quota and requests of the key set with [`with_api_key`](Self::with_api_key)
+    pub async fn account_usage(&self) -> ClientResult<KeyUsage> {
+        Self::json(self.request(Method::GET, "/account/usage")).await
+    }
+
+    pub async fn freshness(&self) -> ClientResult<FreshnessReport> {
+        Self::field(self.request(Method::GET, "/status/freshness"), "freshness").await
+    }
+
+    /// Ranked deals, with the experiment the caller was assigned to if any
+    pub async fn deals(&self) -> ClientResult<DealList> {
+        Self::json(self.request(Method::GET, "/deals")).await
+    }
+
+    /// Published collections in homepage order, with their deals
+    pub async fn collections(&self) -> ClientResult<Vec<CollectionView>> {
+        Self::field(self.request(Method::GET, "/collections"), "collections").await
+    }
+
+    pub async fn collection(&self, slug: &str) -> ClientResult<CollectionView> {
+        let path = format!("/collections/{}", segment(slug));
+        Self::field(self.request(Method::GET, &path), "collection").await
+    }
+
+    /// One page of deals, in `sort` order or the caller's experiment variant's
+    pub async fn deals_page(&self, sort: Option<RankingStrategy>, limit: usize, offset: usize) -> ClientResult<DealList> {
+        #[derive(Serialize)]
+        struct Page {
+            #[serde(skip_serializing_if = "Option::is_none")]
+            sort: Option<RankingStrategy>,
+            limit: usize,
+            offset: usize,
+        }
+        let request = self.request(Method::GET, "/deals").query(&Page { sort, limit, offset });
+        Self::json(request).await
+    }
+
+    pub async fn search_deals(&self, query: &str, limit: Option<usize>) -> ClientResult<SearchResults> {
+        let mut params = vec![("q", query.to_string())];
+        params.extend(limit.map(|limit| ("limit", limit.to_string())));
+        Self::json(self.request(Method::GET, "/deals/search").query(&params)).await
+    }
+
+    /// [`DealMateClient::search_deals`] with filters given apart from the text, from `offset`
+    pub async fn search_deals_filtered(
+        &self,
+        query: &str,
+        filters: &SearchFilters,
+        limit: Option<usize>,
+        offset: usize,
+    ) -> ClientResult<SearchResults> {
+        let mut params = vec![("q", query.to_string()), ("offset", offset.to_string())];
+        params.extend(limit.map(|limit| ("limit", limit.to_string())));
+        let request = self.request(Method::GET, "/deals/search").query(&params).query(filters);
+        Self::json(request).await
+    }
+
+    pub async fn deal_facets(&self, query: &str) -> ClientResult<FacetResults> {
+        Self::json(self.request(Method::GET, "/deals/facets").query(&[("q", query)])).await
+    }
+
+    pub async fn trending_deals(&self) -> ClientResult<DealList> {
+        Self::json(self.request(Method::GET, "/deals/trending")).await
+    }
+
+    /// Import an NDJSON feed, one deal per line
+    pub async fn import_deals(&self, ndjson: String) -> ClientResult<ImportReport> {
+        let request = self
+            .request(Method::POST, "/deals/import")
+            .header(reqwest::header::CONTENT_TYPE, "application/x-ndjson")
+            .body(ndjson);
+        Self::field(request, "import").await
+    }
+
+    pub async fn record_interaction(&self, interaction: &Interaction) -> ClientResult<()> {
+        Self::accepted(self.request(Method::POST, "/deals/interactions").json(interaction)).await
+    }
+
+    pub async fn similar_deals(&self, deal_id: &str, limit: Option<usize>) -> ClientResult<Vec<RelatedDeal>> {
+        let path = format!("/deals/{}/similar", segment(deal_id));
+        let request = self.request(Method::GET, &path).query(&limit.map(|limit| [("limit", limit)]));
+        Self::field(request, "similar").await
+    }
+
+    pub async fn frequently_bought_with(&self, deal_id: &str, limit: Option<usize>) -> ClientResult<Vec<RelatedDeal>> {
+        let path = format!("/deals/{}/frequently-bought-with", segment(deal_id));
+        let request = self.request(Method::GET, &path).query(&limit.map(|limit| [("limit", limit)]));
+        Self::field(request, "frequently_bought_with").await
+    }
+
+    pub async fn effective_price(&self, deal_id: &str, redeem_points: u64) -> ClientResult<EffectivePrice> {
+        let path = format!("/deals/{}/effective-price", segment(deal_id));
+        let request = self.request(Method::GET, &path).query(&[("redeem_points", redeem_points)]);
+        Self::field(request, "pricing").await
+    }
+
+    pub async fn ingest_comments(&self, comments: &[CommunityComment]) -> ClientResult<IngestReport> {
+        Self::field(self.request(Method::POST, "/deals/comments").json(comments), "report").await
+    }
+
+    pub async fn community_summary(&self, deal_id: &str) -> ClientResult<CommunitySummary> {
+        let path = format!("/deals/{}/community", segment(deal_id));
+        Self::field(self.request(Method::GET, &path), "community").await
+    }
+
+    /// Coupons, most likely to work first
+    pub async fn coupons(&self, merchant: Option<&MerchantDomain>) -> ClientResult<Vec<CouponListing>> {
+        let request = self
+            .request(Method::GET, "/coupons")
+            .query(&merchant.map(|merchant| [("merchant", merchant.as_str())]));
+        Self::field(request, "coupons").await
+    }
+
+    /// A merchant's codes as they stood at `at`, valid ones first
+    pub async fn coupons_as_of(&self, merchant: &MerchantDomain, at: DateTime<Utc>) -> ClientResult<Vec<HistoricalCoupon>> {
+        let request = self
+            .request(Method::GET, "/coupons/asof")
+            .query(&[("merchant", merchant.to_string()), ("timestamp", at.to_rfc3339())]);
+        Self::field(request, "coupons").await
+    }
+
+    /// A merchant's best unexpired codes, most likely to work first
+    pub async fn top_coupons(&self, domain: &MerchantDomain) -> ClientResult<Vec<CouponListing>> {
+        let path = format!("/merchants/{}/coupons", segment(domain.as_str()));
+        Self::field(self.request(Method::GET, &path), "coupons").await
+    }
+
+    /// Share `merchant`'s `code`; the link's `path` is relative to the API
+    pub async fn share_coupon(&self, merchant: &MerchantDomain, code: &CouponCode, request: &ShareRequest) -> ClientResult<ShareLink> {
+        let path = format!("/coupons/{}/share", segment(&format!("{}@{}", code, merchant)));
+        Self::field(self.request(Method::POST, &path).json(request), "link").await
+    }
+
+    /// The share behind `token`, counting an open by `session_id`
+    pub async fn open_share(&self, token: &str, session_id: Option<&str>) -> ClientResult<Share> {
+        let mut request = self.request(Method::GET, &format!("/share/{}", segment(token)));
+        if let Some(session_id) = session_id {
+            request = request.query(&[("session_id", session_id)]);
+        }
+        Self::field(request, "share").await
+    }
+
+    pub async fn record_coupon_outcome(&self, outcome: &CouponOutcome) -> ClientResult<()> {
+        Self::accepted(self.request(Method::POST, "/coupons/outcomes").json(outcome)).await
+    }
+
+    /// Check a code against the catalogue before applying it
+    pub async fn validate_coupon(&self, request: &ValidateCouponRequest) -> ClientResult<CouponValidation> {
+        Self::json(self.request(Method::POST, "/coupons/validate").json(request)).await
+    }
+
+    /// Report the codes applied at one checkout; returns how many were recorded
+    pub async fn report_extension_result(&self, result: &ExtensionResult) -> ClientResult<usize> {
+        Self::field(self.request(Method::POST, "/extension/result").json(result), "recorded").await
+    }
+
+    /// Cheapest combination of the cart's coupons, counting shipping and cashback
+    pub async fn optimize_cart(&self, cart: &Cart) -> ClientResult<CartPlan> {
+        Self::field(self.request(Method::POST, "/stacksmart").json(cart), "plan").await
+    }
+
+    pub async fn coupon_subscriptions(&self) -> ClientResult<Vec<Subscription>> {
+        Self::field(self.request(Method::GET, "/coupons/subscriptions"), "subscriptions").await
+    }
+
+    pub async fn subscribe_to_coupons(&self, request: &SubscriptionRequest) -> ClientResult<Subscription> {
+        Self::field(self.request(Method::POST, "/coupons/subscriptions").json(request), "subscription").await
+    }
+
+    pub async fn unsubscribe_from_coupons(&self, id: Uuid) -> ClientResult<()> {
+        Self::accepted(self.request(Method::DELETE, &format!("/coupons/subscriptions/{}", id))).await
+    }
+
+    /// Forecast over `days` (default 14), with `model` or the best backtested one
+    pub async fn forecast_price(&self, product_id: &str, days: Option<usize>, model: Option<&str>) -> ClientResult<PriceForecast> {
+        let mut params = Vec::new();
+        params.extend(days.map(|days| ("days", days.to_string())));
+        params.extend(model.map(|model| ("model", model.to_string())));
+        let path = format!("/products/{}/forecast", segment(product_id));
+        Self::field(self.request(Method::GET, &path).query(&params), "forecast").await
+    }
+
+    /// The product's listings, cheapest delivered price first
+    pub async fn compare_prices(&self, product_id: &str, region: Option<&str>, memberships: &[&str]) -> ClientResult<Vec<DeliveredPrice>> {
+        let mut params = Vec::new();
+        params.extend(region.map(|region| ("region", region.to_string())));
+        if !memberships.is_empty() {
+            params.push(("memberships", memberships.join(",")));
+        }
+        let path = format!("/products/{}/compare", segment(product_id));
+        Self::field(self.request(Method::GET, &path).query(&params), "offers").await
+    }
+
+    pub async fn savings(&self, user_id: &str) -> ClientResult<Vec<SavingsEntry>> {
+        let path = format!("/users/{}/savings", segment(user_id));
+        Self::field(self.request(Method::GET, &path), "entries").await
+    }
+
+    pub async fn record_savings(&self, user_id: &str, report: &SavingsReport) -> ClientResult<SavingsEntry> {
+        let path = format!("/users/{}/savings", segment(user_id));
+        Self::field(self.request(Method::POST, &path).json(report), "entry").await
+    }
+
+    /// Totals for `year`, or all time
+    pub async fn savings_summary(&self, user_id: &str, year: Option<i32>) -> ClientResult<SavingsSummary> {
+        let path = format!("/users/{}/savings/summary", segment(user_id));
+        let request = self.request(Method::GET, &path).query(&year.map(|year| [("year", year)]));
+        Self::field(request, "summary").await
+    }
+
+    pub async fn notification_preferences(&self, user_id: &str) -> ClientResult<NotificationPreferences> {
+        let path = format!("/users/{}/notification-preferences", segment(user_id));
+        Self::field(self.request(Method::GET, &path), "preferences").await
+    }
+
+    pub async fn set_notification_preferences(
+        &self,
+        user_id: &str,
+        preferences: &NotificationPreferences,
+    ) -> ClientResult<NotificationPreferences> {
+        let path = format!("/users/{}/notification-preferences", segment(user_id));
+        Self::field(self.request(Method::PUT, &path).json(preferences), "preferences").await
+    }
+
+    pub async fn natural_alert(&self, request: &NaturalAlertRequest) -> ClientResult<NaturalAlert> {
+        Self::json(self.request(Method::POST, "/alerts/natural").json(request)).await
+    }
+
+    pub async fn merchant_rankings(&self) -> ClientResult<Vec<MerchantReputation>> {
+        Self::field(self.request(Method::GET, "/merchants/reputation"), "merchants").await
+    }
+
+    pub async fn merchant_reputation(&self, domain: &MerchantDomain) -> ClientResult<MerchantReputation> {
+        let path = format!("/merchants/{}/reputation", domain.as_str());
+        Self::field(self.request(Method::GET, &path), "reputation").await
+    }
+
+    pub async fn merchant_liveness(&self) -> ClientResult<Vec<MerchantLiveness>> {
+        Self::field(self.request(Method::GET, "/merchants/liveness"), "merchants").await
+    }
+
+    pub async fn merchant_feedback(&self, domain: &MerchantDomain, feedback: &MerchantFeedback) -> ClientResult<()> {
+        let path = format!("/merchants/{}/feedback", domain.as_str());
+        Self::accepted(self.request(Method::POST, &path).json(feedback)).await
+    }
+
+    pub async fn merchant_signals(&self, domain: &MerchantDomain, update: &SignalUpdate) -> ClientResult<()> {
+        let path = format!("/merchants/{}/signals", domain.as_str());
+        Self::accepted(self.request(Method::POST, &path).json(update)).await
+    }
+
+    pub async fn upcoming_events(&self, days: Option<i64>) -> ClientResult<Vec<EventOccurrence>> {
+        let request = self
+            .request(Method::GET, "/events/upcoming")
+            .query(&days.map(|days| [("days", days)]));
+        Self::field(request, "events").await
+    }
+
+    pub async fn event_deals(&self, event_id: &str) -> ClientResult<EventDeals> {
+        let path = format!("/events/{}/deals", segment(event_id));
+        Self::json(self.request(Method::GET, &path)).await
+    }
+
+    pub async fn daily_digest(&self) -> ClientResult<DailyDigest> {
+        Self::field(self.request(Method::GET, "/digests/daily"), "digest").await
+    }
+
+    pub async fn digest_subscriptions(&self) -> ClientResult<Vec<DigestSubscription>> {
+        Self::field(self.request(Method::GET, "/digests/subscriptions"), "subscriptions").await
+    }
+
+    pub async fn subscribe_to_digest(&self, request: &DigestSubscriptionRequest) -> ClientResult<DigestSubscription> {
+        Self::field(self.request(Method::POST, "/digests/subscriptions").json(request), "subscription").await
+    }
+
+    pub async fn unsubscribe_from_digest(&self, id: Uuid) -> ClientResult<()> {
+        Self::accepted(self.request(Method::DELETE, &format!("/digests/subscriptions/{}", id))).await
+    }
+
+    /// The digest the subscription would get now, rendered but not sent
+    pub async fn preview_digest(&self, id: Uuid) -> ClientResult<DigestPreview> {
+        Self::json(self.request(Method::GET, &format!("/digests/subscriptions/{}/preview", id))).await
+    }
+
+    pub async fn submit_job(&self, urls: Vec<String>, priority: JobPriority) -> ClientResult<ScrapeJob> {
+        let request = JobRequest { urls, priority };
+        Self::field(self.request(Method::POST, "/jobs").json(&request), "job").await
+    }
+
+    pub async fn job(&self, id: Uuid) -> ClientResult<ScrapeJob> {
+        Self::field(self.request(Method::GET, &format!("/jobs/{}", id)), "job").await
+    }
+
+    pub async fn cancel_job(&self, id: Uuid) -> ClientResult<ScrapeJob> {
+        Self::field(self.request(Method::DELETE, &format!("/jobs/{}", id)), "job").await
+    }
+
+    /// Fetch a merchant page through the shared fetch cache; see [`Self::with_caller`]
+    pub async fn fetch_page(&self, url: &str) -> ClientResult<FetchedPage> {
+        let request = FetchRequest { url: url.to_string() };
+        let response = Self::send(self.request(Method::POST, "/fetch").json(&request)).await?;
+        let header = |name: &str| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(String::from)
+        };
+        let content_type = header("content-type");
+        let cache = header("x-cache");
+        Ok(FetchedPage {
+            body: response.text().await?,
+            content_type,
+            cache,
+        })
+    }
+}
+
+/// `value` as a single URL path segment
+fn segment(value: &str) -> String {
+    url::form_urlencoded::byte_serialize(value.as_bytes())
+        .collect::<String>()
+        .replace('+', "%20")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api;
+    use crate::app::Services;
+    use crate::digest::scheduled::{Frequency, Recipient};
+    use crate::notifications::Channel;
+
+    #[tokio::test]
+    async fn test_round_trips_public_endpoints_against_the_router() {
+        let services = Services::builder().sandbox(7).build().await;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = api::router(&services);
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let client = DealMateClient::new(&format!("http://{}/", addr)).with_tenant("acme");
+
+        let deals = client.deals().await.unwrap().deals;
+        assert!(!deals.is_empty());
+        let search = client.search_deals("laptop", Some(5)).await.unwrap();
+        assert!(search.results.len() <= 5);
+        assert!(!client.compare_prices(&deals[0].product_id, None, &[]).await.unwrap().is_empty());
+        assert!(!client.freshness().await.unwrap().merchants.is_empty());
+        let coupons = client.coupons(None).await.unwrap();
+        let link = client
+            .share_coupon(&coupons[0].merchant_domain, &coupons[0].code, &ShareRequest::default())
+            .await
+            .unwrap();
+        assert_eq!(client.open_share(&link.token, Some("visitor")).await.unwrap().unique_visitors, 1);
+        let mut request = ValidateCouponRequest {
+            merchant_domain: coupons[0].merchant_domain.to_string(),
+            code: coupons[0].code.as_str().to_lowercase(),
+            order_total: None,
+        };
+        assert!(client.validate_coupon(&request).await.unwrap().valid);
+        request.code = "NOSUCHCODE9".to_string();
+        let validation = client.validate_coupon(&request).await.unwrap();
+        assert_eq!(validation.failures[0].reason, crate::coupon_engine::validator::FailureReason::UnknownCode);
+
+        let subscription = client
+            .subscribe_to_coupons(&SubscriptionRequest {
+                merchants: vec![deals[0].merchant_domain.clone()],
+                webhook_url: "https://partner.example.com/hooks".to_string(),
+                delivery: Default::default(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(client.coupon_subscriptions().await.unwrap().len(), 1);
+        client.unsubscribe_from_coupons(subscription.id).await.unwrap();
+
+        let digest = client
+            .subscribe_to_digest(&DigestSubscriptionRequest {
+                recipient: Recipient::User { user_id: "ana".to_string() },
+                frequency: Frequency::Daily,
+                channel: Channel::Email,
+                categories: Vec::new(),
+                watched_products: vec![deals[0].product_id.clone()],
+                saved_coupons: Vec::new(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(client.digest_subscriptions().await.unwrap().len(), 1);
+        let preview = client.preview_digest(digest.id).await.unwrap();
+        assert_eq!(preview.rendered.subject, preview.digest.subject());
+        client.unsubscribe_from_digest(digest.id).await.unwrap();
+        // Nothing is checked until the worker's first liveness run
+        assert!(client.merchant_liveness().await.unwrap().is_empty());
+
+        match client.community_summary("no-such-deal").await {
+            Err(ClientError::Api { status, .. }) => assert_eq!(status, StatusCode::NOT_FOUND),
+            other => panic!("expected a 404, got {:?}", other.map(|_| ())),
+        }
+        match client.account_usage().await {
+            Err(ClientError::Api { status, .. }) => assert_eq!(status, StatusCode::UNAUTHORIZED),
+            other => panic!("expected a 401 without an API key, got {:?}", other.map(|_| ())),
+        }
+    }
+}
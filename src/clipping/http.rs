@@ -0,0 +1,179 @@
+//! Clipping through a platform's JSON offer API
+//!
+//! Platforms are configured in the JSON file at `CLIPPING_PLATFORMS_PATH`: a list of
+//! `{"platform", "offers_url", "clip_url"}` entries. The token is sent as a bearer
+//! token, only to HTTPS endpoints (plain HTTP is allowed for loopback test servers),
+//! and redirects are not followed so it cannot be forwarded elsewhere.
+
+use std::time::Duration;
+
+use axum::async_trait;
+use reqwest::{redirect, StatusCode};
+use serde::Deserialize;
+use url::Url;
+
+use super::{AccessToken, ClipAdapter, ClipError, PlatformOffer};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct HttpPlatformConfig {
+    pub platform: String,
+    /// `GET`; returns the account's offers as a JSON array or `{"offers": [...]}`
+    pub offers_url: String,
+    /// `POST`; `{offer_id}` is replaced with the offer's id
+    pub clip_url: String,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OffersResponse {
+    List(Vec<PlatformOffer>),
+    Wrapped { offers: Vec<PlatformOffer> },
+}
+
+pub struct HttpClipAdapter {
+    config: HttpPlatformConfig,
+    client: reqwest::Client,
+}
+
+impl HttpClipAdapter {
+    pub fn new(config: HttpPlatformConfig) -> Result<Self, String> {
+        for url in [&config.offers_url, &config.clip_url] {
+            check_endpoint(url).map_err(|e| format!("{}: {}", config.platform, e))?;
+        }
+        if !config.clip_url.contains("{offer_id}") {
+            return Err(format!("{}: clip_url has no {{offer_id}} placeholder", config.platform));
+        }
+
+        let client = reqwest::Client::builder()
+            .redirect(redirect::Policy::none())
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .map_err(|e| e.to_string())?;
+        Ok(Self { config, client })
+    }
+
+    pub(super) fn load_all(path: &str) -> Result<Vec<Self>, Box<dyn std::error::Error + Send + Sync>> {
+        let content = std::fs::read_to_string(path)?;
+        let configs: Vec<HttpPlatformConfig> = serde_json::from_str(&content)?;
+        Ok(configs.into_iter().map(Self::new).collect::<Result<_, _>>()?)
+    }
+}
+
+/// Tokens only travel over TLS, except to a local test server
+fn check_endpoint(raw: &str) -> Result<(), String> {
+    let url = Url::parse(&raw.replace("{offer_id}", "x")).map_err(|e| format!("invalid URL '{}': {}", raw, e))?;
+    let loopback = matches!(url.host_str(), Some("localhost" | "127.0.0.1" | "[::1]"));
+    match url.scheme() {
+        "https" => Ok(()),
+        "http" if loopback => Ok(()),
+        _ => Err(format!("'{}' must use https", raw)),
+    }
+}
+
+fn check_status(status: StatusCode, what: &str) -> Result<(), ClipError> {
+    match status {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Err(ClipError::Unauthorized),
+        status if status.is_success() => Ok(()),
+        status => Err(ClipError::Upstream(format!("{} returned {}", what, status))),
+    }
+}
+
+#[async_trait]
+impl ClipAdapter for HttpClipAdapter {
+    fn platform(&self) -> &str {
+        &self.config.platform
+    }
+
+    async fn offers(&self, token: &AccessToken) -> Result<Vec<PlatformOffer>, ClipError> {
+        let response = self
+            .client
+            .get(&self.config.offers_url)
+            .bearer_auth(token.expose())
+            .send()
+            .await
+            .map_err(|e| ClipError::Upstream(e.to_string()))?;
+        check_status(response.status(), "offer listing")?;
+
+        match response.json::<OffersResponse>().await {
+            Ok(OffersResponse::List(offers) | OffersResponse::Wrapped { offers }) => Ok(offers),
+            Err(e) => Err(ClipError::Upstream(format!("unreadable offer listing: {}", e))),
+        }
+    }
+
+    async fn clip(&self, token: &AccessToken, offer_id: &str) -> Result<(), ClipError> {
+        let safe = !offer_id.is_empty() && offer_id.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c));
+        if !safe {
+            return Err(ClipError::Upstream(format!("offer id '{}' cannot be put in a URL", offer_id)));
+        }
+
+        let response = self
+            .client
+            .post(self.config.clip_url.replace("{offer_id}", offer_id))
+            .bearer_auth(token.expose())
+            .send()
+            .await
+            .map_err(|e| ClipError::Upstream(e.to_string()))?;
+        check_status(response.status(), "clip")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::Path;
+    use axum::http::HeaderMap;
+    use axum::routing::{get, post};
+    use axum::{Json, Router};
+    use serde_json::{json, Value};
+
+    fn authorized(headers: &HeaderMap) -> bool {
+        headers.get("authorization").and_then(|v| v.to_str().ok()) == Some("Bearer good")
+    }
+
+    async fn offers(headers: HeaderMap) -> Result<Json<Value>, StatusCode> {
+        if !authorized(&headers) {
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+        Ok(Json(json!({"offers": [{"id": "milk-1", "title": "$1 off milk"}, {"id": "eggs-2", "clipped": true}]})))
+    }
+
+    async fn clip(headers: HeaderMap, Path(id): Path<String>) -> StatusCode {
+        match (authorized(&headers), id.as_str()) {
+            (false, _) => StatusCode::UNAUTHORIZED,
+            (true, "milk-1") => StatusCode::NO_CONTENT,
+            (true, _) => StatusCode::GONE,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lists_and_clips_with_bearer_token() {
+        let app = Router::new().route("/offers", get(offers)).route("/offers/:id/clip", post(clip));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://127.0.0.1:{}", listener.local_addr().unwrap().port());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let adapter = HttpClipAdapter::new(HttpPlatformConfig {
+            platform: "grocer".to_string(),
+            offers_url: format!("{}/offers", base),
+            clip_url: format!("{}/offers/{{offer_id}}/clip", base),
+        })
+        .unwrap();
+        let token = AccessToken::new("good");
+
+        let listed = adapter.offers(&token).await.unwrap();
+        assert_eq!(listed.iter().map(|o| (o.id.as_str(), o.clipped)).collect::<Vec<_>>(), vec![("milk-1", false), ("eggs-2", true)]);
+        assert_eq!(adapter.clip(&token, "milk-1").await, Ok(()));
+        assert!(matches!(adapter.clip(&token, "eggs-2").await, Err(ClipError::Upstream(_))));
+        assert!(adapter.clip(&token, "../admin").await.is_err());
+        assert_eq!(adapter.offers(&AccessToken::new("bad")).await, Err(ClipError::Unauthorized));
+
+        let insecure = HttpPlatformConfig {
+            platform: "grocer".to_string(),
+            offers_url: "http://grocer.example.com/offers".to_string(),
+            clip_url: "https://grocer.example.com/offers/{offer_id}/clip".to_string(),
+        };
+        assert!(HttpClipAdapter::new(insecure).is_err());
+    }
+}
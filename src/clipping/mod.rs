@@ -0,0 +1,286 @@
+//! Coupon clipping for account-bound offers
+//!
+//! Some platforms only honour an offer once it has been "clipped" to the shopper's
+//! account. With a token the shopper delegates to us, a [`ClipAdapter`] lists the
+//! account's offers through the platform API and clips the ones not yet attached.
+//!
+//! Tokens are handled strictly: an [`AccessToken`] is never stored, logged,
+//! serialized or echoed back, its `Debug` output is redacted, and adapters only send
+//! it over HTTPS (see [`http::HttpClipAdapter`]). Clipping only runs when the request
+//! carries the shopper's explicit consent.
+
+pub mod http;
+
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
+
+use axum::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum::http::StatusCode;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// Header carrying the shopper's platform token
+pub const TOKEN_HEADER: &str = "x-platform-token";
+/// Offers clipped per request at most
+const MAX_CLIPS_PER_REQUEST: usize = 50;
+
+/// A token the shopper delegated for one platform; redacted in `Debug`
+pub struct AccessToken(String);
+
+impl AccessToken {
+    pub fn new(token: impl Into<String>) -> Self {
+        Self(token.into())
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for AccessToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("AccessToken([redacted])")
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for AccessToken {
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .headers
+            .get(TOKEN_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(AccessToken::new)
+            .ok_or((StatusCode::UNAUTHORIZED, "missing X-Platform-Token header"))
+    }
+}
+
+/// An offer on the shopper's platform account
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct PlatformOffer {
+    pub id: String,
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub clipped: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClipError {
+    UnknownPlatform(String),
+    ConsentRequired,
+    /// The platform rejected the token
+    Unauthorized,
+    Upstream(String),
+}
+
+impl fmt::Display for ClipError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClipError::UnknownPlatform(platform) => write!(f, "no clipping adapter for platform '{}'", platform),
+            ClipError::ConsentRequired => write!(f, "clipping needs the shopper's consent"),
+            ClipError::Unauthorized => write!(f, "the platform rejected the token"),
+            ClipError::Upstream(e) => write!(f, "platform request failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ClipError {}
+
+/// A platform's offer API
+#[async_trait]
+pub trait ClipAdapter: Send + Sync {
+    fn platform(&self) -> &str;
+
+    async fn offers(&self, token: &AccessToken) -> Result<Vec<PlatformOffer>, ClipError>;
+
+    async fn clip(&self, token: &AccessToken, offer_id: &str) -> Result<(), ClipError>;
+}
+
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct ClipRequest {
+    /// The shopper agreed to have offers clipped to their account
+    #[serde(default)]
+    pub consent: bool,
+    /// Only clip these offers; every unclipped offer otherwise
+    #[serde(default)]
+    pub offer_ids: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct ClipFailure {
+    pub offer_id: String,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, ToSchema)]
+pub struct ClipReport {
+    pub platform: String,
+    /// Clipped by this request
+    pub attached: Vec<PlatformOffer>,
+    pub already_clipped: Vec<String>,
+    /// Requested ids the account does not have
+    pub not_found: Vec<String>,
+    pub failed: Vec<ClipFailure>,
+}
+
+#[derive(Default)]
+pub struct ClippingService {
+    adapters: BTreeMap<String, Arc<dyn ClipAdapter>>,
+}
+
+impl ClippingService {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_adapter(mut self, adapter: Arc<dyn ClipAdapter>) -> Self {
+        self.adapters.insert(adapter.platform().to_lowercase(), adapter);
+        self
+    }
+
+    /// HTTP adapters from `CLIPPING_PLATFORMS_PATH`; none when unset
+    pub fn from_env() -> Self {
+        let Ok(path) = std::env::var("CLIPPING_PLATFORMS_PATH") else {
+            return Self::new();
+        };
+
+        match http::HttpClipAdapter::load_all(&path) {
+            Ok(adapters) => adapters
+                .into_iter()
+                .fold(Self::new(), |service, adapter| service.with_adapter(Arc::new(adapter))),
+            Err(e) => {
+                tracing::warn!(path = %path, error = %e, "Failed to load clipping platforms");
+                Self::new()
+            }
+        }
+    }
+
+    pub fn platforms(&self) -> Vec<&str> {
+        self.adapters.keys().map(String::as_str).collect()
+    }
+
+    /// Clip the applicable offers on `platform` to the account behind `token`
+    pub async fn clip(&self, platform: &str, token: &AccessToken, request: &ClipRequest) -> Result<ClipReport, ClipError> {
+        let adapter = self
+            .adapters
+            .get(&platform.to_lowercase())
+            .ok_or_else(|| ClipError::UnknownPlatform(platform.to_string()))?;
+        if !request.consent {
+            return Err(ClipError::ConsentRequired);
+        }
+
+        let offers = adapter.offers(token).await?;
+        let mut report = ClipReport {
+            platform: adapter.platform().to_string(),
+            ..ClipReport::default()
+        };
+
+        let wanted: Option<HashSet<&str>> = request.offer_ids.as_ref().map(|ids| ids.iter().map(String::as_str).collect());
+        if let Some(wanted) = &wanted {
+            let known: HashSet<&str> = offers.iter().map(|o| o.id.as_str()).collect();
+            report.not_found = wanted.iter().filter(|id| !known.contains(*id)).map(|id| id.to_string()).collect();
+            report.not_found.sort();
+        }
+
+        let applicable = offers
+            .into_iter()
+            .filter(|offer| wanted.as_ref().is_none_or(|wanted| wanted.contains(offer.id.as_str())));
+        for offer in applicable {
+            if offer.clipped {
+                report.already_clipped.push(offer.id);
+                continue;
+            }
+            if report.attached.len() + report.failed.len() >= MAX_CLIPS_PER_REQUEST {
+                break;
+            }
+            match adapter.clip(token, &offer.id).await {
+                Ok(()) => report.attached.push(PlatformOffer { clipped: true, ..offer }),
+                // A revoked token fails every later clip too
+                Err(ClipError::Unauthorized) => return Err(ClipError::Unauthorized),
+                Err(e) => report.failed.push(ClipFailure {
+                    offer_id: offer.id,
+                    error: e.to_string(),
+                }),
+            }
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::Mutex;
+
+    struct FakePlatform {
+        offers: Vec<PlatformOffer>,
+        clipped: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl ClipAdapter for FakePlatform {
+        fn platform(&self) -> &str {
+            "Grocer"
+        }
+
+        async fn offers(&self, token: &AccessToken) -> Result<Vec<PlatformOffer>, ClipError> {
+            match token.expose() {
+                "good" => Ok(self.offers.clone()),
+                _ => Err(ClipError::Unauthorized),
+            }
+        }
+
+        async fn clip(&self, _token: &AccessToken, offer_id: &str) -> Result<(), ClipError> {
+            if offer_id == "expired" {
+                return Err(ClipError::Upstream("410 Gone".to_string()));
+            }
+            self.clipped.lock().await.push(offer_id.to_string());
+            Ok(())
+        }
+    }
+
+    fn offer(id: &str, clipped: bool) -> PlatformOffer {
+        PlatformOffer {
+            id: id.to_string(),
+            title: String::new(),
+            clipped,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_clips_only_unclipped_offers_with_consent() {
+        let platform = Arc::new(FakePlatform {
+            offers: vec![offer("milk", false), offer("eggs", true), offer("expired", false), offer("bread", false)],
+            clipped: Mutex::new(Vec::new()),
+        });
+        let service = ClippingService::new().with_adapter(platform.clone());
+        let token = AccessToken::new("good");
+
+        let no_consent = ClipRequest::default();
+        assert_eq!(service.clip("grocer", &token, &no_consent).await, Err(ClipError::ConsentRequired));
+        assert!(platform.clipped.lock().await.is_empty());
+
+        let request = ClipRequest {
+            consent: true,
+            offer_ids: Some(vec!["milk".into(), "eggs".into(), "expired".into(), "caviar".into()]),
+        };
+        let report = service.clip("grocer", &token, &request).await.unwrap();
+        assert_eq!(report.attached, vec![offer("milk", true)]);
+        assert_eq!(report.already_clipped, vec!["eggs"]);
+        assert_eq!(report.not_found, vec!["caviar"]);
+        assert_eq!(report.failed[0].offer_id, "expired");
+        assert_eq!(*platform.clipped.lock().await, vec!["milk"]);
+
+        let revoked = service.clip("grocer", &AccessToken::new("bad"), &request).await;
+        assert_eq!(revoked, Err(ClipError::Unauthorized));
+        assert_eq!(format!("{:?}", AccessToken::new("secret")), "AccessToken([redacted])");
+    }
+}
@@ -0,0 +1,97 @@
+//! Time source for expiry, rotation and cooldown logic
+//!
+//! Components that compare against "now" or wait for an interval take an
+//! `Arc<dyn Clock>` (via `with_clock`) instead of calling `Utc::now`, `Instant::now`
+//! or `tokio::time::sleep` directly, so tests can move time forward with
+//! [`MockClock`] rather than sleeping.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+
+#[async_trait]
+pub trait Clock: Send + Sync {
+    /// Wall-clock time, for timestamps and expiry dates
+    fn now(&self) -> DateTime<Utc>;
+    /// Monotonic time, for intervals and cooldowns
+    fn instant(&self) -> Instant;
+    async fn sleep(&self, duration: Duration);
+}
+
+/// The real clock
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+#[async_trait]
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        tokio::time::sleep(duration).await
+    }
+}
+
+pub fn system() -> Arc<dyn Clock> {
+    Arc::new(SystemClock)
+}
+
+/// A clock that only moves when told to; `sleep` advances it instead of waiting
+#[derive(Debug)]
+pub struct MockClock {
+    wall_start: DateTime<Utc>,
+    instant_start: Instant,
+    elapsed: Mutex<Duration>,
+}
+
+impl MockClock {
+    pub fn new() -> Self {
+        Self::at(Utc::now())
+    }
+
+    pub fn at(start: DateTime<Utc>) -> Self {
+        Self {
+            wall_start: start,
+            instant_start: Instant::now(),
+            elapsed: Mutex::new(Duration::ZERO),
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.elapsed.lock().unwrap() += by;
+    }
+
+    fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap()
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl Clock for MockClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.wall_start + chrono::Duration::from_std(self.elapsed()).unwrap_or(chrono::Duration::MAX)
+    }
+
+    fn instant(&self) -> Instant {
+        self.instant_start + self.elapsed()
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+        // Still give other tasks a turn, as a real sleep would
+        tokio::task::yield_now().await;
+    }
+}
@@ -0,0 +1,334 @@
+//! Deployment roles, leader election and merchant sharding
+//!
+//! One binary serves every role: `api` instances answer HTTP requests, `worker`
+//! instances run scrape jobs from the shared queue, and `all` (the default) does
+//! both. Tasks that must run on exactly one instance, such as the scrape job
+//! sweeper, hold a short Redis lease that the leader keeps renewing; without Redis
+//! every instance considers itself the leader.
+//!
+//! Workers also divide merchants between themselves (see [`Shards`]), so each
+//! merchant is scraped, and rate limited, by one worker at a time.
+
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use uuid::Uuid;
+
+use crate::models::domain::MerchantDomain;
+
+/// Leases outlive this many missed renewals before another instance takes over
+const LEASE_PERIODS: u32 = 3;
+
+/// Sorted set of live workers, scored by when their heartbeat expires (ms since the epoch)
+const WORKERS_KEY: &str = "workers";
+
+/// Take the lease when it is free, or renew it when this instance already holds it
+const LEASE_SCRIPT: &str = r"
+local holder = redis.call('GET', KEYS[1])
+if holder == ARGV[1] then
+    redis.call('PEXPIRE', KEYS[1], ARGV[2])
+    return 1
+end
+if not holder then
+    redis.call('SET', KEYS[1], ARGV[1], 'PX', ARGV[2])
+    return 1
+end
+return 0
+";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Role {
+    Api,
+    Worker,
+    #[default]
+    All,
+}
+
+impl Role {
+    /// Role from `--role <role>` or `--role=<role>`, then `SERVICE_ROLE`, defaulting to `all`
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if arg == "--role" {
+                return args.next().ok_or("--role needs a value: api, worker or all")?.parse();
+            }
+            if let Some(value) = arg.strip_prefix("--role=") {
+                return value.parse();
+            }
+        }
+
+        match std::env::var("SERVICE_ROLE") {
+            Ok(value) => value.parse(),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    pub fn serves_api(self) -> bool {
+        matches!(self, Self::Api | Self::All)
+    }
+
+    pub fn runs_workers(self) -> bool {
+        matches!(self, Self::Worker | Self::All)
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "api" => Ok(Self::Api),
+            "worker" => Ok(Self::Worker),
+            "all" => Ok(Self::All),
+            other => Err(format!("unknown role '{}': expected api, worker or all", other)),
+        }
+    }
+}
+
+/// Redis-lease leader election for singleton tasks
+pub struct LeaderElection {
+    redis_client: Option<redis::Client>,
+    instance_id: String,
+}
+
+impl LeaderElection {
+    pub fn new(redis_url: Option<&str>) -> Self {
+        let redis_client = redis_url.and_then(|url| redis::Client::open(url).ok());
+
+        Self {
+            redis_client,
+            instance_id: Uuid::new_v4().to_string(),
+        }
+    }
+
+    /// Elect through `REDIS_URL` when set
+    pub fn from_env() -> Self {
+        Self::new(std::env::var("REDIS_URL").ok().as_deref())
+    }
+
+    pub fn instance_id(&self) -> &str {
+        &self.instance_id
+    }
+
+    /// Whether this instance leads `task`, taking or renewing a lease of `lease`.
+    ///
+    /// Returns false when Redis is configured but unreachable, so a partitioned
+    /// instance never runs a singleton alongside the real leader.
+    pub fn try_lead(&self, task: &str, lease: Duration) -> bool {
+        let Some(client) = &self.redis_client else {
+            return true;
+        };
+
+        let result = client.get_connection().and_then(|mut con| {
+            redis::Script::new(LEASE_SCRIPT)
+                .key(format!("leader:{}", task))
+                .arg(&self.instance_id)
+                .arg(lease.as_millis() as u64)
+                .invoke::<i32>(&mut con)
+        });
+
+        match result {
+            Ok(led) => led == 1,
+            Err(e) => {
+                tracing::warn!(task, error = %e, "Leader election failed");
+                false
+            }
+        }
+    }
+
+    /// Run `tick` every `period` on whichever instance currently leads `task`
+    pub async fn run_singleton<F, Fut>(self: Arc<Self>, task: &'static str, period: Duration, mut tick: F)
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let mut ticker = tokio::time::interval(period);
+        let mut leading = false;
+        loop {
+            ticker.tick().await;
+
+            let led = self.try_lead(task, period * LEASE_PERIODS);
+            if led != leading {
+                match led {
+                    true => tracing::info!(instance = %self.instance_id, task, "Took leadership"),
+                    false => tracing::info!(instance = %self.instance_id, task, "Lost leadership"),
+                }
+                leading = led;
+            }
+            if led {
+                tick().await;
+            }
+        }
+    }
+}
+
+/// Merchants divided between the live worker instances.
+///
+/// Workers heartbeat into Redis. A merchant belongs to the live worker with the
+/// highest hash of (worker, merchant domain), so when a worker joins or leaves, only
+/// the merchants it gains or loses change hands. Without Redis the only worker owns
+/// every merchant.
+pub struct Shards {
+    redis_client: Option<redis::Client>,
+    instance_id: String,
+    /// Live workers as of the last heartbeat, sorted
+    workers: std::sync::RwLock<Vec<String>>,
+}
+
+impl Shards {
+    pub fn new(redis_url: Option<&str>, instance_id: &str) -> Self {
+        Self {
+            redis_client: redis_url.and_then(|url| redis::Client::open(url).ok()),
+            instance_id: instance_id.to_string(),
+            workers: std::sync::RwLock::new(vec![instance_id.to_string()]),
+        }
+    }
+
+    /// Shard through `REDIS_URL` when set
+    pub fn from_env(instance_id: &str) -> Self {
+        Self::new(std::env::var("REDIS_URL").ok().as_deref(), instance_id)
+    }
+
+    pub fn instance_id(&self) -> &str {
+        &self.instance_id
+    }
+
+    pub fn workers(&self) -> Vec<String> {
+        self.workers.read().unwrap().clone()
+    }
+
+    /// Which of `workers` owns `domain`
+    pub fn owner<'a>(workers: &'a [String], domain: &MerchantDomain) -> Option<&'a str> {
+        workers
+            .iter()
+            .max_by_key(|worker| (rendezvous_hash(worker, domain.as_str()), *worker))
+            .map(String::as_str)
+    }
+
+    /// Whether this instance should scrape `domain`
+    pub fn owns(&self, domain: &MerchantDomain) -> bool {
+        Self::owner(&self.workers.read().unwrap(), domain) == Some(self.instance_id.as_str())
+    }
+
+    /// Replace the live workers, returning whether they changed
+    pub(crate) fn set_workers(&self, mut workers: Vec<String>) -> bool {
+        workers.sort();
+        workers.dedup();
+        let mut current = self.workers.write().unwrap();
+        if *current == workers {
+            return false;
+        }
+        tracing::info!(instance = %self.instance_id, workers = workers.len(), "Live workers changed, rebalancing merchants");
+        *current = workers;
+        true
+    }
+
+    /// Announce this worker for `ttl` and refresh the live workers, returning whether
+    /// they changed.
+    ///
+    /// When Redis is configured but unreachable, this instance stops owning merchants
+    /// until it can reach Redis again, because the others will soon stop counting it.
+    pub fn heartbeat(&self, ttl: Duration) -> bool {
+        let Some(client) = &self.redis_client else {
+            return false;
+        };
+
+        let now = chrono::Utc::now().timestamp_millis();
+        let result = client.get_connection().and_then(|mut con| {
+            redis::pipe()
+                .atomic()
+                .zadd(WORKERS_KEY, &self.instance_id, now + ttl.as_millis() as i64)
+                .ignore()
+                .zrembyscore(WORKERS_KEY, "-inf", now)
+                .ignore()
+                .zrange(WORKERS_KEY, 0, -1)
+                .query::<(Vec<String>,)>(&mut con)
+        });
+
+        match result {
+            Ok((workers,)) => self.set_workers(workers),
+            Err(e) => {
+                tracing::warn!(error = %e, "Worker heartbeat failed");
+                self.set_workers(Vec::new())
+            }
+        }
+    }
+
+    /// Heartbeat every `period` until the process exits
+    pub async fn run_heartbeat(self: Arc<Self>, period: Duration) {
+        let mut ticker = tokio::time::interval(period);
+        loop {
+            ticker.tick().await;
+            self.heartbeat(period * LEASE_PERIODS);
+        }
+    }
+}
+
+/// FNV-1a of `worker` and `domain`, finished with a 64-bit mixer so nearby inputs
+/// spread over the whole range
+fn rendezvous_hash(worker: &str, domain: &str) -> u64 {
+    let fnv = worker
+        .bytes()
+        .chain(std::iter::once(0xff))
+        .chain(domain.bytes())
+        .fold(0xcbf29ce484222325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3));
+    let mut z = fnv.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn test_role_from_args() {
+        assert_eq!(Role::from_args(args(&["--role", "worker"])), Ok(Role::Worker));
+        assert_eq!(Role::from_args(args(&["--role=API"])), Ok(Role::Api));
+        assert!(Role::from_args(args(&["--role", "cron"])).is_err());
+        assert!(Role::from_args(args(&["--role"])).is_err());
+        assert!(Role::Api.serves_api() && !Role::Api.runs_workers());
+        assert!(Role::All.serves_api() && Role::All.runs_workers());
+    }
+
+    #[test]
+    fn test_single_instance_always_leads() {
+        let election = LeaderElection::new(None);
+
+        assert!(election.try_lead("scrape-job-sweeper", Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn test_each_merchant_has_one_owner_and_only_the_leavers_merchants_move() {
+        let workers: Vec<String> = ["w1", "w2", "w3"].iter().map(|w| w.to_string()).collect();
+        let domains: Vec<MerchantDomain> = (0..300)
+            .map(|i| MerchantDomain::parse(&format!("shop{}.example.com", i)).unwrap())
+            .collect();
+        let owners: Vec<&str> = domains.iter().map(|d| Shards::owner(&workers, d).unwrap()).collect();
+        for worker in &workers {
+            let owned = owners.iter().filter(|owner| *owner == worker).count();
+            assert!((60..=140).contains(&owned), "{} owns {} of 300", worker, owned);
+        }
+
+        let remaining = &workers[..2];
+        for (domain, before) in domains.iter().zip(&owners) {
+            let after = Shards::owner(remaining, domain).unwrap();
+            if *before != "w3" {
+                assert_eq!(after, *before);
+            }
+        }
+
+        let shards = Shards::new(None, "w1");
+        assert!(domains.iter().all(|d| shards.owns(d)));
+        assert!(shards.set_workers(workers.clone()));
+        assert!(!shards.set_workers(workers.clone()));
+        assert_eq!(domains.iter().filter(|d| shards.owns(d)).count(), owners.iter().filter(|o| **o == "w1").count());
+    }
+}
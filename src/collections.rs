@@ -0,0 +1,314 @@
+//! Curated deal collections for the homepage
+//!
+//! Editors put together named lists such as "Back to school under $50". A
+//! collection pins deals by id, picks them from the live catalogue with a rule, or
+//! both: a rule is search text, interpreted like `/deals/search` ("backpacks under
+//! $50 at target"), and evaluated against the ranked catalogue every time the
+//! collection is served, so deals join and leave as the catalogue changes. Pinned
+//! deals come first in the editor's order, then rule matches in the collection's
+//! ranking order; deals that are no longer active are left out.
+//!
+//! A collection is shown from `publish_at` until `unpublish_at`; without a
+//! `publish_at` it is a draft. With `REDIS_URL` set, collections are shared and
+//! re-read every [`REFRESH_INTERVAL`]; otherwise they are persisted to
+//! `COLLECTIONS_PATH` (default `data/collections.json`).
+
+use std::collections::{BTreeMap, HashSet};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+use utoipa::ToSchema;
+
+use crate::clock::{self, Clock};
+use crate::experiments::RankingStrategy;
+use crate::models::deal::{Deal, DealStatus};
+use crate::search::DealSearch;
+use crate::storage::persisted::{PersistedStore, StoreError};
+
+const REDIS_KEY: &str = "deal_collections";
+const STORE_NAME: &str = "deal collections";
+/// How often shared collections are re-read
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Deals shown per collection unless it sets `limit`
+pub const DEFAULT_LIMIT: usize = 20;
+pub const MAX_LIMIT: usize = 100;
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Collection {
+    /// Lowercase letters, digits and dashes; the collection's URL segment
+    #[serde(default)]
+    pub slug: String,
+    pub title: String,
+    #[serde(default)]
+    pub description: String,
+    /// Deals shown first, in this order
+    #[serde(default)]
+    pub deal_ids: Vec<String>,
+    /// Search text whose matching deals are members, e.g. `school supplies under $50`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rule: Option<String>,
+    /// Order of the rule's matches
+    #[serde(default)]
+    pub ranking: RankingStrategy,
+    #[serde(default = "default_limit")]
+    pub limit: usize,
+    /// Homepage position, lowest first
+    #[serde(default)]
+    pub position: i32,
+    /// Unset for a draft
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub publish_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unpublish_at: Option<DateTime<Utc>>,
+    #[serde(default = "Utc::now")]
+    pub updated_at: DateTime<Utc>,
+}
+
+fn default_limit() -> usize {
+    DEFAULT_LIMIT
+}
+
+impl Collection {
+    fn validate(&self) -> Result<(), String> {
+        if self.slug.is_empty() || !self.slug.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-') {
+            return Err("slug must be lowercase letters, digits and dashes".to_string());
+        }
+        if self.title.trim().is_empty() {
+            return Err("title must not be empty".to_string());
+        }
+        if self.deal_ids.is_empty() && self.rule.as_deref().is_none_or(|rule| rule.trim().is_empty()) {
+            return Err("a collection needs deal_ids, a rule, or both".to_string());
+        }
+        if !(1..=MAX_LIMIT).contains(&self.limit) {
+            return Err(format!("limit must be between 1 and {}", MAX_LIMIT));
+        }
+        if let (Some(publish_at), Some(unpublish_at)) = (self.publish_at, self.unpublish_at) {
+            if unpublish_at <= publish_at {
+                return Err("unpublish_at must be after publish_at".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the collection is shown at `at`
+    pub fn published(&self, at: DateTime<Utc>) -> bool {
+        self.publish_at.is_some_and(|publish_at| publish_at <= at) && self.unpublish_at.is_none_or(|until| until > at)
+    }
+
+    /// The collection's deals out of `ranked`, the catalogue in `self.ranking` order
+    pub fn members(&self, ranked: &[Deal], search: &DealSearch) -> Vec<Deal> {
+        let active = |deal: &&Deal| deal.status == DealStatus::Active;
+        let mut seen = HashSet::new();
+        let mut members: Vec<Deal> = self
+            .deal_ids
+            .iter()
+            .filter_map(|id| ranked.iter().find(|deal| &deal.id == id))
+            .filter(active)
+            .filter(|deal| seen.insert(deal.id.clone()))
+            .cloned()
+            .collect();
+
+        if let Some(rule) = self.rule.as_deref().filter(|rule| !rule.trim().is_empty()) {
+            let (_, hits) = search.search(rule, ranked.to_vec());
+            let matched: HashSet<String> = hits.into_iter().map(|hit| hit.deal.id).collect();
+            members.extend(
+                ranked
+                    .iter()
+                    .filter(active)
+                    .filter(|deal| matched.contains(&deal.id) && seen.insert(deal.id.clone()))
+                    .cloned(),
+            );
+        }
+
+        members.truncate(self.limit);
+        members
+    }
+}
+
+/// A collection with its current deals
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CollectionView {
+    #[serde(flatten)]
+    pub collection: Collection,
+    pub deals: Vec<Deal>,
+}
+
+pub struct CollectionService {
+    collections: Arc<RwLock<BTreeMap<String, Collection>>>,
+    /// Every collection in the file, one field of the Redis hash each
+    store: PersistedStore<BTreeMap<String, Collection>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl CollectionService {
+    /// Collections persisted to `path`, or kept in memory only
+    pub fn new(path: Option<PathBuf>) -> Self {
+        Self::with_store(PersistedStore::new(STORE_NAME, REDIS_KEY, path))
+    }
+
+    /// Collections shared through Redis
+    pub fn shared(redis_url: &str) -> Result<Self, StoreError> {
+        Ok(Self::with_store(PersistedStore::shared(STORE_NAME, REDIS_KEY, redis_url)?))
+    }
+
+    fn with_store(store: PersistedStore<BTreeMap<String, Collection>>) -> Self {
+        Self {
+            collections: Arc::new(RwLock::new(BTreeMap::new())),
+            store,
+            clock: clock::system(),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Share collections through `REDIS_URL` when set, otherwise load them from
+    /// `COLLECTIONS_PATH` (default `data/collections.json`)
+    pub async fn from_env() -> Self {
+        let service = Self::with_store(PersistedStore::from_env(STORE_NAME, REDIS_KEY, "COLLECTIONS_PATH", "data/collections.json"));
+        if let Err(e) = service.reload().await {
+            tracing::warn!(error = %e, "Starting without deal collections");
+        }
+        service
+    }
+
+    /// Replace the local copy with every stored collection
+    pub async fn reload(&self) -> Result<(), StoreError> {
+        if let Some(stored) = self.store.load_map(REDIS_KEY).await? {
+            *self.collections.write().await = stored;
+        }
+        Ok(())
+    }
+
+    /// Re-read shared collections every [`REFRESH_INTERVAL`], picking up changes
+    /// made through other instances
+    pub async fn start_background_tasks(self: Arc<Self>) {
+        self.store.refresh_every(self.clock.as_ref(), REFRESH_INTERVAL, || self.reload()).await
+    }
+
+    /// Every collection, drafts and scheduled ones included, by slug
+    pub async fn list(&self) -> Vec<Collection> {
+        self.collections.read().await.values().cloned().collect()
+    }
+
+    pub async fn get(&self, slug: &str) -> Option<Collection> {
+        self.collections.read().await.get(slug).cloned()
+    }
+
+    /// Create or replace the collection at `slug`
+    pub async fn put(&self, slug: &str, mut collection: Collection) -> Result<Collection, String> {
+        collection.slug = slug.to_string();
+        collection.deal_ids.retain(|id| !id.trim().is_empty());
+        collection.updated_at = self.clock.now();
+        collection.validate()?;
+
+        let mut collections = self.collections.write().await;
+        collections.insert(collection.slug.clone(), collection.clone());
+        self.store.persist_field(REDIS_KEY, slug, Some(&collection), || collections.clone()).await;
+        Ok(collection)
+    }
+
+    /// Returns false if there was no such collection
+    pub async fn delete(&self, slug: &str) -> bool {
+        let mut collections = self.collections.write().await;
+        let removed = collections.remove(slug).is_some();
+        if removed {
+            self.store.persist_field(REDIS_KEY, slug, None::<&Collection>, || collections.clone()).await;
+        }
+        removed
+    }
+
+    /// Collections shown now, in homepage order
+    pub async fn published(&self) -> Vec<Collection> {
+        let now = self.clock.now();
+        let mut published: Vec<Collection> =
+            self.collections.read().await.values().filter(|collection| collection.published(now)).cloned().collect();
+        published.sort_by_key(|collection| collection.position);
+        published
+    }
+
+    /// A collection if it is shown now
+    pub async fn get_published(&self, slug: &str) -> Option<Collection> {
+        let now = self.clock.now();
+        self.get(slug).await.filter(|collection| collection.published(now))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::Decimal;
+
+    use crate::clock::MockClock;
+    use crate::storage::deal_store::DealStore;
+
+    fn collection(deal_ids: &[&str], rule: Option<&str>) -> Collection {
+        Collection {
+            slug: String::new(),
+            title: "Tech under $500".to_string(),
+            description: String::new(),
+            deal_ids: deal_ids.iter().map(|id| id.to_string()).collect(),
+            rule: rule.map(str::to_string),
+            ranking: RankingStrategy::Newest,
+            limit: DEFAULT_LIMIT,
+            position: 0,
+            publish_at: None,
+            unpublish_at: None,
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_collections_pin_deals_follow_rules_and_keep_their_schedule() {
+        let clock = Arc::new(MockClock::new());
+        let now = clock.now();
+        let hours = |h: i64| now + chrono::Duration::hours(h);
+        let service = CollectionService::new(None).with_clock(clock.clone());
+
+        assert!(service.put("Tech", collection(&["deal_1"], None)).await.is_err());
+        assert!(service.put("tech", collection(&[], Some(" "))).await.is_err());
+        let mut backwards = collection(&["deal_1"], None);
+        (backwards.publish_at, backwards.unpublish_at) = (Some(hours(2)), Some(hours(1)));
+        assert!(service.put("tech", backwards).await.is_err());
+
+        // Pinned first, then rule matches newest first; inactive deals are left out
+        let mut deals = DealStore::with_sample_data().list().await;
+        deals.sort_by_key(|deal| std::cmp::Reverse(deal.posted_at));
+        let mut tech = collection(&["deal_2", "deal_1", "missing"], Some("electronics under $500"));
+        (tech.publish_at, tech.unpublish_at) = (Some(hours(1)), Some(hours(3)));
+        let tech = service.put("tech", tech).await.unwrap();
+        let members = tech.members(&deals, &DealSearch::new());
+        let expected: Vec<&String> = deals
+            .iter()
+            .filter(|deal| deal.category == "electronics" && deal.price.amount <= Decimal::from(500) && deal.id != "deal_1")
+            .map(|deal| &deal.id)
+            .collect();
+        assert!(!expected.is_empty());
+        assert_eq!((members[0].id.as_str(), members[1].id.as_str()), ("deal_2", "deal_1"));
+        assert_eq!(members[2..].iter().map(|deal| &deal.id).collect::<Vec<_>>(), expected);
+
+        let dead = deals.iter_mut().find(|deal| deal.id == "deal_1").unwrap();
+        dead.status = DealStatus::Dead;
+        assert!(tech.members(&deals, &DealSearch::new()).iter().all(|deal| deal.id != "deal_1"));
+
+        // Shown only between publish_at and unpublish_at; drafts never
+        service.put("draft", collection(&["deal_3"], None)).await.unwrap();
+        assert!(service.published().await.is_empty());
+        clock.advance(std::time::Duration::from_secs(3600));
+        assert_eq!(service.published().await.len(), 1);
+        assert!(service.get_published("tech").await.is_some());
+        assert!(service.get_published("draft").await.is_none());
+        clock.advance(std::time::Duration::from_secs(2 * 3600));
+        assert!(service.get_published("tech").await.is_none());
+        assert_eq!(service.list().await.len(), 2);
+        assert!(service.delete("draft").await);
+        assert!(!service.delete("draft").await);
+    }
+}
@@ -0,0 +1,226 @@
+//! Deal-community comment ingestion
+//!
+//! Comments pushed from community threads are analysed for sentiment and stock
+//! signals ("dead", "OOS", "price went up"), and each affected deal's status and
+//! status confidence are recomputed from its recent, recency-weighted comments.
+
+pub mod sentiment;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use utoipa::ToSchema;
+
+use crate::models::comment::CommunityComment;
+use crate::models::deal::DealStatus;
+use crate::storage::deal_store::DealStore;
+use sentiment::{analyze_comment, CommentSignal};
+
+/// Comments older than this no longer influence a deal's status
+const SIGNAL_WINDOW_HOURS: i64 = 72;
+/// A report's weight halves every this many hours
+const HALF_LIFE_HOURS: f64 = 12.0;
+/// Weighted reports needed before a deal is marked dead/OOS/price-increased
+const MIN_EVIDENCE: f64 = 0.8;
+
+struct AnalyzedComment {
+    posted_at: DateTime<Utc>,
+    sentiment: f64,
+    signals: Vec<CommentSignal>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CommunitySummary {
+    pub deal_id: String,
+    pub comment_count: usize,
+    pub average_sentiment: f64,
+    pub signal_counts: HashMap<CommentSignal, usize>,
+    pub status: DealStatus,
+    pub confidence: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct IngestReport {
+    pub ingested: usize,
+    pub unknown_deals: Vec<String>,
+    pub updated: Vec<CommunitySummary>,
+}
+
+pub struct CommunityService {
+    comments: Arc<Mutex<HashMap<String, Vec<AnalyzedComment>>>>,
+}
+
+impl CommunityService {
+    pub fn new() -> Self {
+        Self {
+            comments: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Analyse a batch of comments and re-annotate every deal they mention
+    pub async fn ingest(&self, store: &DealStore, batch: Vec<CommunityComment>) -> IngestReport {
+        let mut report = IngestReport {
+            ingested: 0,
+            unknown_deals: Vec::new(),
+            updated: Vec::new(),
+        };
+
+        let mut touched = Vec::new();
+        {
+            let mut comments = self.comments.lock().await;
+            for comment in batch {
+                if store.get(&comment.deal_id).await.is_none() {
+                    if !report.unknown_deals.contains(&comment.deal_id) {
+                        report.unknown_deals.push(comment.deal_id);
+                    }
+                    continue;
+                }
+
+                let analysis = analyze_comment(&comment.body);
+                comments.entry(comment.deal_id.clone()).or_default().push(AnalyzedComment {
+                    posted_at: comment.posted_at,
+                    sentiment: analysis.sentiment,
+                    signals: analysis.signals,
+                });
+                report.ingested += 1;
+
+                if !touched.contains(&comment.deal_id) {
+                    touched.push(comment.deal_id);
+                }
+            }
+        }
+
+        for deal_id in touched {
+            if let Some(summary) = self.summary(&deal_id).await {
+                store.set_status(&deal_id, summary.status, summary.confidence).await;
+                report.updated.push(summary);
+            }
+        }
+
+        report
+    }
+
+    pub async fn summary(&self, deal_id: &str) -> Option<CommunitySummary> {
+        let comments = self.comments.lock().await;
+        let deal_comments = comments.get(deal_id)?;
+        let (status, confidence) = derive_status(deal_comments, Utc::now());
+
+        let mut signal_counts = HashMap::new();
+        for signal in deal_comments.iter().flat_map(|c| &c.signals) {
+            *signal_counts.entry(*signal).or_insert(0) += 1;
+        }
+
+        Some(CommunitySummary {
+            deal_id: deal_id.to_string(),
+            comment_count: deal_comments.len(),
+            average_sentiment: deal_comments.iter().map(|c| c.sentiment).sum::<f64>() / deal_comments.len() as f64,
+            signal_counts,
+            status,
+            confidence,
+        })
+    }
+}
+
+impl Default for CommunityService {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn derive_status(comments: &[AnalyzedComment], now: DateTime<Utc>) -> (DealStatus, f64) {
+    const PRIOR: f64 = 1.0;
+
+    let mut weights: HashMap<CommentSignal, f64> = HashMap::new();
+    for comment in comments {
+        let age = now - comment.posted_at;
+        if age > Duration::hours(SIGNAL_WINDOW_HOURS) {
+            continue;
+        }
+
+        let weight = 0.5f64.powf(age.num_minutes().max(0) as f64 / 60.0 / HALF_LIFE_HOURS);
+        for signal in &comment.signals {
+            *weights.entry(*signal).or_insert(0.0) += weight;
+        }
+    }
+
+    let confirmed = weights.get(&CommentSignal::Confirmed).copied().unwrap_or(0.0);
+    let total: f64 = weights.values().sum();
+    let strongest = [
+        (CommentSignal::Dead, DealStatus::Dead),
+        (CommentSignal::OutOfStock, DealStatus::OutOfStock),
+        (CommentSignal::PriceIncreased, DealStatus::PriceIncreased),
+    ]
+    .into_iter()
+    .map(|(signal, status)| (status, weights.get(&signal).copied().unwrap_or(0.0)))
+    .max_by(|a, b| a.1.total_cmp(&b.1));
+
+    match strongest {
+        Some((status, weight)) if weight >= MIN_EVIDENCE && weight > confirmed => {
+            (status, round(weight / (total + PRIOR)))
+        }
+        _ => (DealStatus::Active, round((confirmed + PRIOR) / (total + PRIOR))),
+    }
+}
+
+fn round(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn comment(body: &str, hours_ago: i64) -> CommunityComment {
+        CommunityComment {
+            deal_id: "deal_1".to_string(),
+            source: "slickdeals".to_string(),
+            thread_url: None,
+            author: None,
+            body: body.to_string(),
+            posted_at: Utc::now() - Duration::hours(hours_ago),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_recent_dead_reports_mark_deal_dead() {
+        let store = DealStore::with_sample_data();
+        let service = CommunityService::new();
+
+        let report = service
+            .ingest(
+                &store,
+                vec![
+                    comment("Just bought one, thanks!", 30),
+                    comment("Dead now", 1),
+                    comment("yep expired, code not working", 0),
+                ],
+            )
+            .await;
+
+        assert_eq!(report.ingested, 3);
+        assert_eq!(report.updated[0].status, DealStatus::Dead);
+        assert_eq!(store.get("deal_1").await.unwrap().status, DealStatus::Dead);
+    }
+
+    #[tokio::test]
+    async fn test_confirmations_keep_deal_active() {
+        let store = DealStore::with_sample_data();
+        let service = CommunityService::new();
+
+        service
+            .ingest(
+                &store,
+                vec![
+                    comment("OOS for me", 10),
+                    comment("still in stock here, just ordered", 1),
+                    comment("worked for me", 0),
+                ],
+            )
+            .await;
+
+        assert_eq!(store.get("deal_1").await.unwrap().status, DealStatus::Active);
+    }
+}
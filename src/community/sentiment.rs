@@ -0,0 +1,122 @@
+//! Lightweight lexicon-based sentiment and stock-status extraction for deal comments
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+lazy_static! {
+    static ref DEAD_PATTERN: Regex = Regex::new(
+        r"(?i)\b(?:dead|expired|ended|no longer (?:working|available|valid)|(?:code|coupon|promo) (?:doesn'?t|does not|didn'?t|won'?t) work|not working)\b"
+    ).unwrap();
+    static ref OUT_OF_STOCK_PATTERN: Regex = Regex::new(
+        r"(?i)\b(?:oos|out of stock|sold out|unavailable|no stock|back ?order(?:ed)?)\b"
+    ).unwrap();
+    static ref PRICE_UP_PATTERN: Regex = Regex::new(
+        r"(?i)\b(?:price (?:went|gone|is|jumped) (?:up|back up)|back to (?:full|regular|normal) price|now \$?\d+(?:\.\d{2})? (?:again|at checkout)|price increased?)\b"
+    ).unwrap();
+    static ref CONFIRMED_PATTERN: Regex = Regex::new(
+        r"(?i)\b(?:still (?:works|working|live|available|in stock)|just (?:bought|ordered|got)|worked for me|in stock|ordered (?:one|two|mine)|got (?:one|mine)|confirmed)\b"
+    ).unwrap();
+}
+
+const POSITIVE_WORDS: &[&str] = &[
+    "great", "awesome", "love", "amazing", "excellent", "thanks", "thank", "solid", "bought", "worth", "nice", "good",
+    "steal", "best",
+];
+const NEGATIVE_WORDS: &[&str] = &[
+    "bad", "terrible", "scam", "junk", "overpriced", "awful", "broke", "broken", "refund", "worst", "meh", "avoid",
+    "fake", "returned",
+];
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum CommentSignal {
+    Dead,
+    OutOfStock,
+    PriceIncreased,
+    /// Someone reports the deal still works / they bought it
+    Confirmed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CommentAnalysis {
+    /// -1.0 (negative) to 1.0 (positive)
+    pub sentiment: f64,
+    pub signals: Vec<CommentSignal>,
+}
+
+pub fn analyze_comment(body: &str) -> CommentAnalysis {
+    let mut signals = Vec::new();
+    if DEAD_PATTERN.is_match(body) {
+        signals.push(CommentSignal::Dead);
+    }
+    if OUT_OF_STOCK_PATTERN.is_match(body) && !body.to_lowercase().contains("back in stock") {
+        signals.push(CommentSignal::OutOfStock);
+    }
+    if PRICE_UP_PATTERN.is_match(body) {
+        signals.push(CommentSignal::PriceIncreased);
+    }
+    if CONFIRMED_PATTERN.is_match(body) && signals.is_empty() {
+        signals.push(CommentSignal::Confirmed);
+    }
+
+    let mut positive = 0i32;
+    let mut negative = 0i32;
+    let mut negated = false;
+    for word in body.to_lowercase().split(|c: char| !c.is_alphanumeric() && c != '\'') {
+        if word.is_empty() {
+            continue;
+        }
+        if matches!(word, "not" | "no" | "never" | "isn't" | "wasn't" | "don't" | "doesn't") {
+            negated = true;
+            continue;
+        }
+
+        let (pos, neg) = (POSITIVE_WORDS.contains(&word), NEGATIVE_WORDS.contains(&word));
+        match (pos, neg, negated) {
+            (true, _, false) | (_, true, true) => positive += 1,
+            (true, _, true) | (_, true, false) => negative += 1,
+            _ => {}
+        }
+        negated = false;
+    }
+
+    // Status problems are strong negative evidence on their own
+    let status_penalty = signals
+        .iter()
+        .filter(|s| **s != CommentSignal::Confirmed)
+        .count() as i32;
+    negative += status_penalty;
+
+    let total = positive + negative;
+    let sentiment = if total == 0 {
+        0.0
+    } else {
+        (positive - negative) as f64 / total as f64
+    };
+
+    CommentAnalysis { sentiment, signals }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extracts_status_signals() {
+        assert_eq!(analyze_comment("Dead. Code doesn't work anymore").signals, vec![CommentSignal::Dead]);
+        assert_eq!(analyze_comment("OOS in my area :(").signals, vec![CommentSignal::OutOfStock]);
+        assert_eq!(
+            analyze_comment("price went up to $129 at checkout").signals,
+            vec![CommentSignal::PriceIncreased]
+        );
+        assert_eq!(analyze_comment("Just bought one, thanks OP!").signals, vec![CommentSignal::Confirmed]);
+    }
+
+    #[test]
+    fn test_sentiment_polarity() {
+        assert!(analyze_comment("Great deal, thanks!").sentiment > 0.0);
+        assert!(analyze_comment("Total junk, returned it").sentiment < 0.0);
+        assert!(analyze_comment("not bad at all").sentiment > 0.0);
+    }
+}
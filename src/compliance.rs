@@ -0,0 +1,188 @@
+//! Per-country compliance rules
+//!
+//! Some offers may not be shown in some countries (alcohol or tobacco deals, for
+//! instance), and some countries require a disclosure next to every advertised
+//! price. A [`JurisdictionProfile`] states both for one country; the API applies
+//! the profile of the request's country to every JSON response, as the handlers
+//! wrote it, before it is translated and shaped for the tenant (see [`crate::api`]).
+//!
+//! A request's country is its `X-Country` header (set by the edge from the
+//! client's IP, or by the client), else its tenant's `country`, else the rules'
+//! `default_country`. Countries without a profile are served unfiltered.
+//!
+//! Profiles are read from the JSON file at `COMPLIANCE_RULES_PATH`:
+//!
+//! ```json
+//! {
+//!   "default_country": "US",
+//!   "countries": {
+//!     "IN": {"excluded_categories": ["Alcohol", "Tobacco"], "excluded_keywords": ["whisky"]},
+//!     "DE": {"price_disclosure": "Alle Preise inkl. MwSt."}
+//!   }
+//! }
+//! ```
+
+use std::collections::{BTreeSet, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+pub const COUNTRY_HEADER: &str = "x-country";
+
+/// An ISO 3166-1 alpha-2 code, upper-cased
+pub fn parse_country(code: &str) -> Option<String> {
+    let code = code.trim();
+    (code.len() == 2 && code.chars().all(|c| c.is_ascii_alphabetic())).then(|| code.to_ascii_uppercase())
+}
+
+/// What one country requires of the offers served there
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JurisdictionProfile {
+    /// Deal categories that are not shown, compared case-insensitively
+    pub excluded_categories: BTreeSet<String>,
+    /// Words or phrases whose offers are not shown, matched as whole words in the
+    /// title and description; for coupons, which have no category
+    pub excluded_keywords: Vec<String>,
+    /// Shown as `price_disclosure` on every offer with a price
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub price_disclosure: Option<String>,
+}
+
+impl JurisdictionProfile {
+    /// Whether an offer in `category` (if it has one) described by `texts` is excluded
+    pub fn excludes(&self, category: Option<&str>, texts: &[&str]) -> bool {
+        let category = category.map(|c| c.trim().to_lowercase());
+        if category.is_some_and(|category| self.excluded_categories.iter().any(|c| c.trim().to_lowercase() == category)) {
+            return true;
+        }
+        if self.excluded_keywords.is_empty() {
+            return false;
+        }
+
+        let text = format!(" {} ", texts.iter().map(|text| words(text)).collect::<Vec<_>>().join(" "));
+        self.excluded_keywords
+            .iter()
+            .map(|keyword| words(keyword))
+            .any(|keyword| !keyword.is_empty() && text.contains(&format!(" {} ", keyword)))
+    }
+}
+
+/// `text` lower-cased, as single-space separated words
+fn words(text: &str) -> String {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+struct RulesConfig {
+    #[serde(default)]
+    default_country: Option<String>,
+    #[serde(default)]
+    countries: HashMap<String, JurisdictionProfile>,
+}
+
+#[derive(Debug, Default)]
+pub struct ComplianceRules {
+    default_country: Option<String>,
+    countries: HashMap<String, JurisdictionProfile>,
+}
+
+impl ComplianceRules {
+    pub fn new(countries: HashMap<String, JurisdictionProfile>) -> Self {
+        let countries = countries
+            .into_iter()
+            .filter_map(|(code, profile)| match parse_country(&code) {
+                Some(code) => Some((code, profile)),
+                None => {
+                    tracing::warn!(country = %code, "Ignoring compliance rules: not a two-letter country code");
+                    None
+                }
+            })
+            .collect();
+        Self {
+            default_country: None,
+            countries,
+        }
+    }
+
+    /// The country of requests that name none, and whose tenant has none
+    pub fn with_default_country(mut self, code: &str) -> Self {
+        self.default_country = parse_country(code);
+        self
+    }
+
+    /// Rules from `COMPLIANCE_RULES_PATH`, or none
+    pub fn from_env() -> Self {
+        let Ok(path) = std::env::var("COMPLIANCE_RULES_PATH") else {
+            return Self::default();
+        };
+
+        match Self::load_config(&path) {
+            Ok(config) => {
+                let rules = Self::new(config.countries);
+                match config.default_country {
+                    Some(code) => rules.with_default_country(&code),
+                    None => rules,
+                }
+            }
+            Err(e) => {
+                tracing::warn!(path = %path, error = %e, "Failed to load compliance rules");
+                Self::default()
+            }
+        }
+    }
+
+    fn load_config(path: &str) -> Result<RulesConfig, Box<dyn std::error::Error + Send + Sync>> {
+        let content = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.countries.is_empty()
+    }
+
+    /// The request's country: the header's, else the tenant's, else the default
+    pub fn resolve_country(&self, header: Option<&str>, tenant_country: Option<&str>) -> Option<String> {
+        header
+            .and_then(parse_country)
+            .or_else(|| tenant_country.and_then(parse_country))
+            .or_else(|| self.default_country.clone())
+    }
+
+    pub fn profile(&self, country: &str) -> Option<&JurisdictionProfile> {
+        self.countries.get(country)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolves_country_and_matches_whole_words() {
+        let profile = JurisdictionProfile {
+            excluded_categories: BTreeSet::from(["Alcohol".to_string()]),
+            excluded_keywords: vec!["craft beer".to_string(), "cigar".to_string()],
+            price_disclosure: None,
+        };
+        let rules = ComplianceRules::new(HashMap::from([
+            ("in".to_string(), profile.clone()),
+            ("India".to_string(), profile.clone()),
+        ]))
+        .with_default_country("us");
+        assert_eq!(rules.profile("IN"), Some(&profile));
+        assert!(rules.profile("INDIA").is_none());
+
+        assert_eq!(rules.resolve_country(Some(" de "), Some("FR")).as_deref(), Some("DE"));
+        assert_eq!(rules.resolve_country(Some("Germany"), Some("fr")).as_deref(), Some("FR"));
+        assert_eq!(rules.resolve_country(None, None).as_deref(), Some("US"));
+
+        assert!(profile.excludes(Some(" alcohol"), &[]));
+        assert!(profile.excludes(None, &["20% off Craft-Beer packs"]));
+        assert!(profile.excludes(None, &["Gift sets", "Cigar humidors included"]));
+        assert!(!profile.excludes(Some("Home"), &["Cigarette lighter adapter", "Craft supplies"]));
+    }
+}
@@ -0,0 +1,504 @@
+//! Startup configuration checks
+//!
+//! Most settings are read from the environment by the component that uses them,
+//! and a bad value there only falls back to a default with a log line. Before
+//! building anything, the binary checks the environment as a whole for values that
+//! do not parse, files that do not exist and settings that contradict each other,
+//! and prints the findings as a report. In production (`APP_ENV=production`) it
+//! refuses to start while any finding is fatal; elsewhere it starts anyway.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+use crate::cluster::Role;
+use crate::coupon_engine::parser::ParserVersion;
+use crate::coupon_engine::profiles::DomainProfile;
+use crate::cors::{CorsConfig, CorsPolicy};
+use crate::localization::Locale;
+use crate::server::ServerConfig;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Environment {
+    #[default]
+    Development,
+    Production,
+}
+
+impl Environment {
+    fn parse(value: Option<&str>) -> Result<Self, String> {
+        match value.map(|v| v.trim().to_ascii_lowercase()).as_deref() {
+            None | Some("") | Some("development") | Some("dev") | Some("test") => Ok(Self::Development),
+            Some("production") | Some("prod") => Ok(Self::Production),
+            Some(other) => Err(format!("unknown environment '{}', expected development or production", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Works, but probably not as intended
+    Warning,
+    /// The service would run with a setting silently ignored or a feature broken
+    Fatal,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Environment variable the finding is about
+    pub setting: &'static str,
+    pub message: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct ConfigReport {
+    pub environment: Environment,
+    pub role: Role,
+    /// Fatal findings first
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl ConfigReport {
+    /// Check the process environment for `role`
+    pub fn from_env(role: Role) -> Self {
+        Self::check(&std::env::vars().collect(), role)
+    }
+
+    pub fn check(env: &HashMap<String, String>, role: Role) -> Self {
+        let mut checks = Checks { env, diagnostics: Vec::new() };
+
+        let environment = match Environment::parse(checks.get("APP_ENV")) {
+            Ok(environment) => environment,
+            Err(e) => {
+                checks.fatal("APP_ENV", e);
+                Environment::Development
+            }
+        };
+
+        checks.redis(role);
+        checks.proxies();
+        checks.parsers();
+        checks.numbers();
+        checks.files();
+        checks.domain_profiles();
+        checks.alert_llm();
+        checks.alert_webhooks();
+        checks.translation();
+        checks.pii_ner();
+        checks.jwt();
+        checks.server(role);
+        checks.cors(role);
+
+        let mut diagnostics = checks.diagnostics;
+        diagnostics.sort_by_key(|d| std::cmp::Reverse(d.severity));
+        Self {
+            environment,
+            role,
+            diagnostics,
+        }
+    }
+
+    pub fn has_fatal(&self) -> bool {
+        self.diagnostics.iter().any(|d| d.severity == Severity::Fatal)
+    }
+
+    /// Whether startup must stop: fatal findings only block production
+    pub fn refuses_start(&self) -> bool {
+        self.environment == Environment::Production && self.has_fatal()
+    }
+}
+
+impl fmt::Display for ConfigReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fatal = self.diagnostics.iter().filter(|d| d.severity == Severity::Fatal).count();
+        writeln!(
+            f,
+            "Configuration check ({:?}, {:?} role): {} fatal, {} warning(s)",
+            self.environment,
+            self.role,
+            fatal,
+            self.diagnostics.len() - fatal
+        )?;
+        for diagnostic in &self.diagnostics {
+            let label = match diagnostic.severity {
+                Severity::Fatal => "fatal",
+                Severity::Warning => "warning",
+            };
+            writeln!(f, "  [{}] {}: {}", label, diagnostic.setting, diagnostic.message)?;
+        }
+        Ok(())
+    }
+}
+
+struct Checks<'a> {
+    env: &'a HashMap<String, String>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Checks<'_> {
+    fn get(&self, name: &str) -> Option<&str> {
+        self.env.get(name).map(String::as_str)
+    }
+
+    fn push(&mut self, severity: Severity, setting: &'static str, message: String) {
+        self.diagnostics.push(Diagnostic {
+            severity,
+            setting,
+            message,
+        });
+    }
+
+    fn fatal(&mut self, setting: &'static str, message: String) {
+        self.push(Severity::Fatal, setting, message);
+    }
+
+    fn warning(&mut self, setting: &'static str, message: String) {
+        self.push(Severity::Warning, setting, message);
+    }
+
+    fn redis(&mut self, role: Role) {
+        match self.get("REDIS_URL") {
+            Some(url) => {
+                if let Err(e) = redis::Client::open(url) {
+                    self.fatal("REDIS_URL", format!("not a valid Redis URL: {}", e));
+                }
+            }
+            // Split roles meet only through the shared queue
+            None if role != Role::All => self.fatal(
+                "REDIS_URL",
+                format!(
+                    "the {:?} role needs a shared scrape job queue, but without Redis each instance keeps its own",
+                    role
+                ),
+            ),
+            None => {}
+        }
+    }
+
+    fn proxies(&mut self) {
+        let rotation = match self.get("PROXY_ROTATION_ENABLED").map(parse_bool) {
+            Some(Ok(enabled)) => enabled,
+            Some(Err(e)) => {
+                self.fatal("PROXY_ROTATION_ENABLED", e);
+                return;
+            }
+            None => true,
+        };
+
+        match self.get("PROXY_LIST_PATH") {
+            None if rotation => self.fatal(
+                "PROXY_ROTATION_ENABLED",
+                "proxy rotation is on but no proxies are configured (set PROXY_LIST_PATH or PROXY_ROTATION_ENABLED=false); every fetch would go direct".to_string(),
+            ),
+            Some(_) if !rotation => self.warning(
+                "PROXY_LIST_PATH",
+                "proxies are configured but rotation is off, so they are never used".to_string(),
+            ),
+            _ => {}
+        }
+    }
+
+    fn parsers(&mut self) {
+        let mut version = |name: &'static str| match self.get(name).map(str::parse::<ParserVersion>) {
+            Some(Ok(version)) => Some(version),
+            Some(Err(e)) => {
+                self.fatal(name, e);
+                None
+            }
+            None => None,
+        };
+        let current = version("PARSER_VERSION").unwrap_or(ParserVersion::CURRENT);
+        if version("PARSER_SHADOW_VERSION") == Some(current) {
+            self.warning(
+                "PARSER_SHADOW_VERSION",
+                format!("same as the current parser ({}), so nothing is shadowed", current.as_str()),
+            );
+        }
+    }
+
+    fn numbers(&mut self) {
+        for name in [
+            "API_WORKER_THREADS",
+            "COUPON_HISTORY_RETENTION_DAYS",
+            "COUPON_WRITE_BATCH_SIZE",
+            "COUPON_WRITE_FLUSH_MS",
+            "COUPON_WRITE_QUEUE_CAPACITY",
+            "FETCH_QUOTA_PER_MINUTE",
+            "IMPORT_MAX_BYTES",
+            "IMPORT_MAX_LINE_BYTES",
+            "SANDBOX_SEED",
+            "SCRAPE_DAILY_BUDGET",
+            "SCRAPE_FRONTIER_CAPACITY",
+            "SCRAPE_FRONTIER_ROTATE_SECS",
+            "SCRAPE_MEMORY_CAP_MB",
+            "SCRAPE_WORKER_THREADS",
+            "SLA_P95_BUDGET_SECS",
+            "SLA_WINDOW_SECS",
+            "STREAM_RETENTION_SECS",
+            "TOP_COUPONS_LIMIT",
+        ] {
+            if let Some(value) = self.get(name) {
+                if value.trim().parse::<u64>().is_err() {
+                    self.fatal(name, format!("'{}' is not a whole number", value));
+                }
+            }
+        }
+
+        if let Some(value) = self.get("SCRAPE_FRONTIER_FP_RATE") {
+            if !value.trim().parse::<f64>().is_ok_and(|rate| rate > 0.0 && rate < 1.0) {
+                self.fatal("SCRAPE_FRONTIER_FP_RATE", format!("'{}' is not a rate between 0 and 1", value));
+            }
+        }
+
+        if let Some(quotas) = self.get("FETCH_CALLER_QUOTAS") {
+            let bad: Vec<String> = quotas
+                .split(',')
+                .filter(|entry| !entry.trim().is_empty())
+                .filter(|entry| entry.split_once('=').is_none_or(|(_, quota)| quota.trim().parse::<u32>().is_err()))
+                .map(|entry| entry.trim().to_string())
+                .collect();
+            if !bad.is_empty() {
+                self.fatal("FETCH_CALLER_QUOTAS", format!("expected caller=quota entries, got {}", bad.join(", ")));
+            }
+        }
+    }
+
+    fn files(&mut self) {
+        for name in [
+            "COUPON_MODEL_PATH",
+            "SCORING_MODEL_PATH",
+            "EVENTS_CONFIG_PATH",
+            "EXPERIMENTS_CONFIG_PATH",
+            "PROXY_LIST_PATH",
+            "STACKING_RULES_PATH",
+            "REWARDS_CONFIG_PATH",
+            "CLIPPING_PLATFORMS_PATH",
+            "TENANTS_CONFIG_PATH",
+            "COMPLIANCE_RULES_PATH",
+            "SEED_BUNDLES_PATH",
+        ] {
+            if let Some(path) = self.get(name) {
+                if !Path::new(path).is_file() {
+                    self.fatal(name, format!("{} does not exist", path));
+                }
+            }
+        }
+
+        if self.get("SCORING_ONNX_MODEL").is_some() && cfg!(not(feature = "onnx")) {
+            self.warning(
+                "SCORING_ONNX_MODEL",
+                "ignored: the binary was built without the onnx feature".to_string(),
+            );
+        }
+        if let Some(dir) = self.get("SNAPSHOT_ARCHIVE_DIR") {
+            if Path::new(dir).is_file() {
+                self.fatal("SNAPSHOT_ARCHIVE_DIR", format!("{} is a file, not a directory", dir));
+            }
+        }
+    }
+
+    /// Profiles read from a file are checked now, so a bad selector stops startup
+    /// instead of silently dropping its merchant's profile; ones in Redis are checked
+    /// as they load
+    fn domain_profiles(&mut self) {
+        let path = match (self.get("DOMAIN_PROFILES_PATH"), self.get("REDIS_URL")) {
+            (Some(path), _) => path.to_string(),
+            (None, None) => "data/domain_profiles.json".to_string(),
+            (None, Some(_)) => return,
+        };
+        let Ok(contents) = std::fs::read_to_string(&path) else {
+            return;
+        };
+        match serde_json::from_str::<Vec<DomainProfile>>(&contents) {
+            Ok(profiles) => {
+                for profile in profiles {
+                    if let Err(e) = profile.settings.validate() {
+                        self.fatal("DOMAIN_PROFILES_PATH", format!("profile for {} in {}: {}", profile.domain, path, e));
+                    }
+                }
+            }
+            Err(e) => self.fatal("DOMAIN_PROFILES_PATH", format!("{} is not a list of domain profiles: {}", path, e)),
+        }
+    }
+
+    fn alert_llm(&mut self) {
+        match (self.get("ALERT_LLM_API_URL"), self.get("ALERT_LLM_API_KEY")) {
+            (None, Some(_)) => self.warning(
+                "ALERT_LLM_API_KEY",
+                "set without ALERT_LLM_API_URL, so alerts use the rule-based parser".to_string(),
+            ),
+            (Some(url), _) if url::Url::parse(url).is_err() => {
+                self.fatal("ALERT_LLM_API_URL", format!("'{}' is not a valid URL", url));
+            }
+            _ => {}
+        }
+    }
+
+    fn translation(&mut self) {
+        match (self.get("TRANSLATION_API_URL"), self.get("TRANSLATION_API_KEY")) {
+            (None, Some(_)) => self.warning(
+                "TRANSLATION_API_KEY",
+                "set without TRANSLATION_API_URL, so coupons are not translated".to_string(),
+            ),
+            (Some(url), _) if url::Url::parse(url).is_err() => {
+                self.fatal("TRANSLATION_API_URL", format!("'{}' is not a valid URL", url));
+            }
+            _ => {}
+        }
+        if let Some(tag) = self.get("DEFAULT_CONTENT_LOCALE") {
+            if Locale::parse(tag).is_none() {
+                self.fatal("DEFAULT_CONTENT_LOCALE", format!("'{}' is not a language tag", tag));
+            }
+        }
+    }
+
+    fn alert_webhooks(&mut self) {
+        for name in ["SLA_ALERT_WEBHOOK_URL", "CANARY_ALERT_WEBHOOK_URL"] {
+            if let Some(url) = self.get(name) {
+                if url::Url::parse(url).is_err() {
+                    self.fatal(name, format!("'{}' is not a valid URL", url));
+                }
+            }
+        }
+    }
+
+    fn pii_ner(&mut self) {
+        match (self.get("PII_NER_URL"), self.get("PII_NER_API_KEY")) {
+            (None, Some(_)) => self.warning(
+                "PII_NER_API_KEY",
+                "set without PII_NER_URL, so PII is scrubbed by pattern only".to_string(),
+            ),
+            (Some(url), _) if url::Url::parse(url).is_err() => {
+                self.fatal("PII_NER_URL", format!("'{}' is not a valid URL", url));
+            }
+            _ => {}
+        }
+    }
+
+    fn jwt(&mut self) {
+        match self.get("JWT_JWKS_URL") {
+            None => {
+                for name in ["JWT_ISSUER", "JWT_AUDIENCE"] {
+                    if self.get(name).is_some() {
+                        self.warning(name, "set without JWT_JWKS_URL, so user endpoints stay open".to_string());
+                    }
+                }
+            }
+            Some(url) if url::Url::parse(url).is_err() => {
+                self.fatal("JWT_JWKS_URL", format!("'{}' is not a valid URL", url));
+            }
+            Some(_) if self.get("JWT_ISSUER").is_none() => {
+                self.warning("JWT_ISSUER", "not set, so tokens from any issuer using the JWKS are accepted".to_string());
+            }
+            Some(_) => {}
+        }
+    }
+
+    fn server(&mut self, role: Role) {
+        let config = match ServerConfig::load(self.env) {
+            Ok(config) => config,
+            Err(e) => {
+                self.fatal(e.setting, e.message);
+                return;
+            }
+        };
+        // Workers never bind the listener
+        let Some(tls) = config.tls.filter(|_| role.serves_api()) else {
+            return;
+        };
+        for (setting, path) in [("SERVER_TLS_CERT_PATH", &tls.cert_path), ("SERVER_TLS_KEY_PATH", &tls.key_path)] {
+            if !path.is_file() {
+                self.fatal(setting, format!("{} does not exist", path.display()));
+            }
+        }
+    }
+
+    fn cors(&mut self, role: Role) {
+        if !role.serves_api() {
+            return;
+        }
+        let config = match CorsConfig::load(self.env) {
+            Ok(config) => config,
+            Err(e) => {
+                self.fatal(e.setting, e.message);
+                return;
+            }
+        };
+        if let Err(e) = CorsPolicy::new(&config) {
+            self.fatal(e.setting, e.message);
+            return;
+        }
+        if config.rule_for("/admin").origins.is_none_or(|origins| origins.iter().any(|origin| origin == "*")) {
+            self.warning("CORS_CONFIG_PATH", "pages on any origin may call the /admin routes".to_string());
+        }
+    }
+}
+
+pub(crate) fn parse_bool(value: &str) -> Result<bool, String> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Ok(true),
+        "0" | "false" | "no" | "off" => Ok(false),
+        other => Err(format!("'{}' is not true or false", other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    fn settings(report: &ConfigReport, severity: Severity) -> Vec<&'static str> {
+        report.diagnostics.iter().filter(|d| d.severity == severity).map(|d| d.setting).collect()
+    }
+
+    #[test]
+    fn test_reports_contradictions_and_bad_values() {
+        let report = ConfigReport::check(
+            &env(&[
+                ("APP_ENV", "production"),
+                ("FETCH_CALLER_QUOTAS", "pricing=600,search"),
+                ("PARSER_SHADOW_VERSION", "v1"),
+                ("ALERT_LLM_API_KEY", "secret"),
+            ]),
+            Role::Worker,
+        );
+
+        assert_eq!(settings(&report, Severity::Fatal), vec!["REDIS_URL", "PROXY_ROTATION_ENABLED", "FETCH_CALLER_QUOTAS"]);
+        assert_eq!(settings(&report, Severity::Warning), vec!["PARSER_SHADOW_VERSION", "ALERT_LLM_API_KEY"]);
+        assert!(report.refuses_start());
+        assert!(report.to_string().contains("[fatal] FETCH_CALLER_QUOTAS: expected caller=quota entries, got search"));
+    }
+
+    #[test]
+    fn test_names_invalid_selectors_in_the_domain_profiles_file() {
+        let path = std::env::temp_dir().join(format!("domain_profiles_{}.json", uuid::Uuid::new_v4()));
+        std::fs::write(
+            &path,
+            r#"[{"domain": "shop.com", "selectors": [".code", "div[["], "updated_at": "2026-01-01T00:00:00Z"}]"#,
+        )
+        .unwrap();
+
+        let report = ConfigReport::check(
+            &env(&[("DOMAIN_PROFILES_PATH", path.to_str().unwrap()), ("PROXY_ROTATION_ENABLED", "false")]),
+            Role::All,
+        );
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(settings(&report, Severity::Fatal), vec!["DOMAIN_PROFILES_PATH"]);
+        assert!(report.diagnostics[0].message.contains("shop.com") && report.diagnostics[0].message.contains("div[["));
+    }
+
+    #[test]
+    fn test_fatal_findings_only_block_production() {
+        let clean = ConfigReport::check(&env(&[("APP_ENV", "production"), ("PROXY_ROTATION_ENABLED", "false")]), Role::All);
+        assert!(clean.diagnostics.is_empty());
+        assert!(!clean.refuses_start());
+
+        let development = ConfigReport::check(&env(&[("PARSER_VERSION", "v9")]), Role::All);
+        assert!(development.has_fatal());
+        assert!(!development.refuses_start());
+    }
+}
@@ -0,0 +1,344 @@
+//! Cross-origin access to the API
+//!
+//! Browsers only let a page call the API from another origin when the response
+//! allows it. The [`CorsConfig`] names the origins, methods and request headers
+//! allowed by default, and routes that differ, matched by path prefix (the longest
+//! prefix wins, with or without the `/api/v1` prefix). A route override only needs
+//! the fields it changes; the rest come from the default.
+//!
+//! Without a configuration any origin may call the API with the usual methods and
+//! headers, except the `/admin` routes, which only same-origin pages may call. A
+//! JSON file at `CORS_CONFIG_PATH` replaces that, and `CORS_ALLOWED_ORIGINS`,
+//! `CORS_ALLOWED_METHODS` and `CORS_ALLOWED_HEADERS` (comma-separated) override
+//! its default rule.
+//!
+//! An origin is `*` for any, an exact origin such as `https://dealmate.app`, or a
+//! pattern with one `*`, such as `https://*.dealmate.app` or `chrome-extension://*`
+//! for every installation of a browser extension.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use axum::http::{HeaderName, HeaderValue, Method};
+use serde::Deserialize;
+use tower_http::cors::{AllowHeaders, AllowMethods, AllowOrigin, Any, CorsLayer};
+
+use crate::compliance::COUNTRY_HEADER;
+use crate::tenant::API_KEY_HEADER;
+
+const DEFAULT_METHODS: &[&str] = &["GET", "POST", "PUT", "DELETE"];
+const DEFAULT_HEADERS: &[&str] = &["accept", "accept-language", "authorization", "content-type", API_KEY_HEADER, COUNTRY_HEADER];
+
+/// Origins, methods and request headers a route allows; unset fields are inherited
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct CorsRule {
+    pub origins: Option<Vec<String>>,
+    /// `*` for any
+    pub methods: Option<Vec<String>>,
+    /// `*` for any
+    pub headers: Option<Vec<String>>,
+}
+
+impl CorsRule {
+    /// This rule, with the fields it leaves unset taken from `base`
+    fn or(&self, base: &CorsRule) -> CorsRule {
+        CorsRule {
+            origins: self.origins.clone().or_else(|| base.origins.clone()),
+            methods: self.methods.clone().or_else(|| base.methods.clone()),
+            headers: self.headers.clone().or_else(|| base.headers.clone()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct RouteCors {
+    /// e.g. `/admin` or `/coupons/for-url`
+    pub prefix: String,
+    #[serde(flatten)]
+    pub rule: CorsRule,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct CorsConfig {
+    #[serde(default)]
+    pub default: CorsRule,
+    #[serde(default)]
+    pub routes: Vec<RouteCors>,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            default: CorsRule::default(),
+            routes: vec![RouteCors {
+                prefix: "/admin".to_string(),
+                rule: CorsRule {
+                    origins: Some(Vec::new()),
+                    ..CorsRule::default()
+                },
+            }],
+        }
+    }
+}
+
+/// A CORS setting that does not parse, named so startup can point at it
+#[derive(Debug, Clone, PartialEq)]
+pub struct CorsConfigError {
+    pub setting: &'static str,
+    pub message: String,
+}
+
+impl fmt::Display for CorsConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.setting, self.message)
+    }
+}
+
+impl std::error::Error for CorsConfigError {}
+
+impl CorsConfig {
+    /// The file at `CORS_CONFIG_PATH`, if set, with its default rule overridden by the `CORS_ALLOWED_*` variables
+    pub fn load(env: &HashMap<String, String>) -> Result<Self, CorsConfigError> {
+        let get = |name: &str| env.get(name).map(|value| value.trim()).filter(|value| !value.is_empty());
+        let list = |value: &str| value.split(',').map(|item| item.trim().to_string()).filter(|item| !item.is_empty()).collect();
+
+        let mut config = match get("CORS_CONFIG_PATH") {
+            Some(path) => {
+                let error = |message: String| CorsConfigError {
+                    setting: "CORS_CONFIG_PATH",
+                    message,
+                };
+                let contents = std::fs::read_to_string(path).map_err(|e| error(format!("{}: {}", path, e)))?;
+                serde_json::from_str(&contents).map_err(|e| error(format!("{} is not a CORS config: {}", path, e)))?
+            }
+            None => Self::default(),
+        };
+        if let Some(origins) = get("CORS_ALLOWED_ORIGINS") {
+            config.default.origins = Some(list(origins));
+        }
+        if let Some(methods) = get("CORS_ALLOWED_METHODS") {
+            config.default.methods = Some(list(methods));
+        }
+        if let Some(headers) = get("CORS_ALLOWED_HEADERS") {
+            config.default.headers = Some(list(headers));
+        }
+        Ok(config)
+    }
+
+    /// The rule that applies to `path`, after inheriting from the default
+    pub fn rule_for(&self, path: &str) -> CorsRule {
+        let path = crate::api::unversioned(path);
+        self.routes
+            .iter()
+            .filter(|route| matches_prefix(path, &route.prefix))
+            .max_by_key(|route| route.prefix.len())
+            .map_or_else(|| self.default.clone(), |route| route.rule.or(&self.default))
+    }
+}
+
+/// Whether `prefix` is `path` or a parent of it, so `/admin` does not match `/administer`
+fn matches_prefix(path: &str, prefix: &str) -> bool {
+    let prefix = prefix.trim_end_matches('/');
+    path.strip_prefix(prefix).is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// An allowed origin: exact, or with one `*` standing for anything
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct OriginPattern {
+    prefix: String,
+    /// `None` for an exact origin
+    suffix: Option<String>,
+}
+
+impl OriginPattern {
+    fn parse(origin: &str) -> Result<Self, String> {
+        let origin = origin.trim().trim_end_matches('/').to_ascii_lowercase();
+        if !origin.contains("://") {
+            return Err(format!("'{}' is not an origin like https://example.com", origin));
+        }
+        match origin.split_once('*') {
+            None => Ok(Self { prefix: origin, suffix: None }),
+            Some((_, suffix)) if suffix.contains('*') => Err(format!("'{}' has more than one *", origin)),
+            Some((prefix, suffix)) => Ok(Self {
+                prefix: prefix.to_string(),
+                suffix: Some(suffix.to_string()),
+            }),
+        }
+    }
+
+    fn matches(&self, origin: &str) -> bool {
+        let origin = origin.to_ascii_lowercase();
+        match &self.suffix {
+            None => origin == self.prefix,
+            Some(suffix) => {
+                origin.len() > self.prefix.len() + suffix.len() && origin.starts_with(&self.prefix) && origin.ends_with(suffix.as_str())
+            }
+        }
+    }
+}
+
+/// The [`CorsConfig`], checked and compiled into a layer per route
+#[derive(Clone)]
+pub struct CorsPolicy {
+    default: CorsLayer,
+    /// Longest prefix first
+    routes: Vec<(String, CorsLayer)>,
+}
+
+impl CorsPolicy {
+    pub fn new(config: &CorsConfig) -> Result<Self, CorsConfigError> {
+        // The default rule may come from the `CORS_ALLOWED_*` variables, route rules only from the file
+        let default = layer(&config.default).map_err(|(setting, message)| CorsConfigError { setting, message })?;
+        let mut routes = config
+            .routes
+            .iter()
+            .map(|route| {
+                let layer = layer(&route.rule.or(&config.default)).map_err(|(_, e)| CorsConfigError {
+                    setting: "CORS_CONFIG_PATH",
+                    message: format!("{}: {}", route.prefix, e),
+                })?;
+                Ok((route.prefix.clone(), layer))
+            })
+            .collect::<Result<Vec<_>, CorsConfigError>>()?;
+        routes.sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+
+        Ok(Self { default, routes })
+    }
+
+    /// The policy configured in the environment; the built-in one when it is invalid
+    pub fn from_env() -> Self {
+        let config = CorsConfig::load(&std::env::vars().collect()).unwrap_or_else(|e| {
+            tracing::warn!(error = %e, "Invalid CORS configuration, using the default");
+            CorsConfig::default()
+        });
+        Self::new(&config).unwrap_or_else(|e| {
+            tracing::warn!(error = %e, "Invalid CORS configuration, using the default");
+            Self::default()
+        })
+    }
+
+    /// The layer answering preflights and tagging responses of requests to `path`
+    pub fn layer_for(&self, path: &str) -> CorsLayer {
+        let path = crate::api::unversioned(path);
+        self.routes
+            .iter()
+            .find(|(prefix, _)| matches_prefix(path, prefix))
+            .map_or(&self.default, |(_, layer)| layer)
+            .clone()
+    }
+}
+
+impl Default for CorsPolicy {
+    fn default() -> Self {
+        Self::new(&CorsConfig::default()).expect("the built-in CORS config is valid")
+    }
+}
+
+/// The layer for `rule`, or the variable of the field that does not parse and why
+fn layer(rule: &CorsRule) -> Result<CorsLayer, (&'static str, String)> {
+    let origins = rule.origins.clone().unwrap_or_else(|| vec!["*".to_string()]);
+    let allow_origin = match origins.iter().any(|origin| origin == "*") {
+        true => AllowOrigin::any(),
+        false => {
+            let patterns = origins
+                .iter()
+                .map(|origin| OriginPattern::parse(origin))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| ("CORS_ALLOWED_ORIGINS", e))?;
+            AllowOrigin::predicate(move |origin: &HeaderValue, _| {
+                origin.to_str().is_ok_and(|origin| patterns.iter().any(|pattern| pattern.matches(origin)))
+            })
+        }
+    };
+
+    let methods = rule.methods.clone().unwrap_or_else(|| DEFAULT_METHODS.iter().map(|m| m.to_string()).collect());
+    let allow_methods = match methods.iter().any(|method| method == "*") {
+        true => AllowMethods::any(),
+        false => AllowMethods::list(
+            methods
+                .iter()
+                .map(|method| Method::from_bytes(method.trim().to_ascii_uppercase().as_bytes()))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| ("CORS_ALLOWED_METHODS", format!("{:?} are not all HTTP methods", methods)))?,
+        ),
+    };
+
+    let headers = rule.headers.clone().unwrap_or_else(|| DEFAULT_HEADERS.iter().map(|h| h.to_string()).collect());
+    let allow_headers = match headers.iter().any(|header| header == "*") {
+        true => AllowHeaders::any(),
+        false => AllowHeaders::list(
+            headers
+                .iter()
+                .map(|header| HeaderName::try_from(header.trim()))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| ("CORS_ALLOWED_HEADERS", format!("{:?} are not all header names", headers)))?,
+        ),
+    };
+
+    Ok(CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods(allow_methods)
+        .allow_headers(allow_headers)
+        .expose_headers(Any))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(vars: &[(&str, &str)]) -> HashMap<String, String> {
+        vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn test_origin_patterns() {
+        let extension = OriginPattern::parse("chrome-extension://*").unwrap();
+        assert!(extension.matches("chrome-extension://abcdefghijklmnop"));
+        assert!(!extension.matches("chrome-extension://"));
+        assert!(!extension.matches("https://evil.example"));
+
+        let subdomains = OriginPattern::parse("https://*.dealmate.app").unwrap();
+        assert!(subdomains.matches("https://admin.dealmate.app"));
+        assert!(!subdomains.matches("https://dealmate.app"));
+        assert!(!subdomains.matches("https://admin.dealmate.app.evil.example"));
+
+        assert!(OriginPattern::parse("https://DealMate.app/").unwrap().matches("https://dealmate.app"));
+        assert!(OriginPattern::parse("dealmate.app").is_err());
+        assert!(OriginPattern::parse("https://*.*.dealmate.app").is_err());
+    }
+
+    #[test]
+    fn test_routes_inherit_from_the_default_and_longest_prefix_wins() {
+        let config: CorsConfig = serde_json::from_value(serde_json::json!({
+            "default": {"origins": ["https://dealmate.app"], "methods": ["GET"]},
+            "routes": [
+                {"prefix": "/coupons", "origins": ["https://dealmate.app", "chrome-extension://*"]},
+                {"prefix": "/coupons/validate", "methods": ["POST"]},
+                {"prefix": "/admin", "origins": ["https://admin.dealmate.app"], "methods": ["*"]}
+            ]
+        }))
+        .unwrap();
+
+        assert_eq!(config.rule_for("/deals").origins, Some(vec!["https://dealmate.app".to_string()]));
+        let coupons = config.rule_for("/api/v1/coupons/for-url");
+        assert_eq!(coupons.origins.unwrap().len(), 2);
+        assert_eq!(coupons.methods, Some(vec!["GET".to_string()]));
+        let validate = config.rule_for("/coupons/validate");
+        assert_eq!((validate.origins.unwrap().len(), validate.methods), (1, Some(vec!["POST".to_string()])));
+        assert_eq!(config.rule_for("/administer").methods, Some(vec!["GET".to_string()]));
+        assert!(CorsPolicy::new(&config).is_ok());
+    }
+
+    #[test]
+    fn test_environment_overrides_the_default_rule() {
+        let config = CorsConfig::load(&env(&[("CORS_ALLOWED_ORIGINS", "https://a.example, https://b.example"), ("CORS_ALLOWED_METHODS", "GET,POST")])).unwrap();
+        assert_eq!(config.default.origins.as_ref().unwrap().len(), 2);
+        // The built-in admin override still applies
+        assert_eq!(config.rule_for("/admin/roles").origins, Some(Vec::new()));
+        assert_eq!(config.rule_for("/admin/roles").methods, Some(vec!["GET".to_string(), "POST".to_string()]));
+
+        let bad = CorsConfig::load(&env(&[("CORS_ALLOWED_METHODS", "GET,NOT A METHOD")])).unwrap();
+        assert_eq!(CorsPolicy::new(&bad).err().unwrap().setting, "CORS_ALLOWED_METHODS");
+        assert_eq!(CorsConfig::load(&env(&[("CORS_CONFIG_PATH", "/nonexistent/cors.json")])).unwrap_err().setting, "CORS_CONFIG_PATH");
+    }
+}
@@ -49,14 +49,14 @@ impl CouponAggregator {
         ];
 
         for (network_name, _api_url) in networks {
-            println!("Aggregating coupons from {}", network_name);
+            tracing::info!(network = %network_name, "Aggregating coupons");
             
             // For demo purposes, create sample coupons
             let sample_coupons = self.generate_sample_coupons(network_name);
             
             for coupon_data in sample_coupons {
                 if let Err(e) = self.store_coupon(coupon_data, network_name).await {
-                    eprintln!("Error storing coupon: {}", e);
+                    tracing::error!(error = %e, "Error storing a coupon");
                 }
             }
             
@@ -74,14 +74,14 @@ impl CouponAggregator {
         ];
 
         for (domain, _url) in merchants {
-            println!("Scraping coupons from {}", domain);
+            tracing::info!(merchant = %domain, "Scraping coupons");
             
             // For demo purposes, generate sample scraped coupons
             let scraped_coupons = self.generate_sample_scraped_coupons(domain);
             
             for coupon_data in scraped_coupons {
                 if let Err(e) = self.store_coupon(coupon_data, "scraping").await {
-                    eprintln!("Error storing scraped coupon: {}", e);
+                    tracing::error!(error = %e, "Error storing a scraped coupon");
                 }
             }
         }
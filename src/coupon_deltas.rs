@@ -0,0 +1,554 @@
+//! Coupon corpus delta alerts for partners
+//!
+//! A partner subscribes to a list of merchants and is told when a merchant's
+//! available coupons change in a way that matters to shoppers: a new best code, or
+//! the best code expiring (or disappearing). After each ingest run into the coupon
+//! store, [`CouponDeltas`] takes a snapshot of every merchant's active codes and
+//! diffs it against the previous one. With licenses set, only coupons that may be
+//! redistributed are snapshotted (see [`crate::licensing`]); partners are never
+//! told about restricted codes.
+//!
+//! Subscriptions deliver to a webhook either as soon as a run finds changes
+//! (`webhook`) or batched once a day (`digest`). They are shared through Redis when
+//! `REDIS_URL` is set, otherwise persisted to `COUPON_ALERTS_PATH` (default
+//! `data/coupon_alerts.json`), together with the last snapshots, so a restart does
+//! not re-announce the current best codes. With
+//! several instances, only the one holding the leader lease notifies partners.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::{Mutex, MutexGuard};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::clock::{self, Clock};
+use crate::cluster::LeaderElection;
+use crate::licensing::SourceLicenses;
+use crate::models::coupon_listing::{CouponListing, License};
+use crate::models::domain::{CouponCode, MerchantDomain};
+use crate::storage::coupon_store::CouponStore;
+use crate::storage::persisted::{PersistedStore, StoreError};
+use crate::top_coupons::TopCoupons;
+
+const REDIS_KEY: &str = "coupon_alerts";
+const STORE_NAME: &str = "coupon alert subscriptions";
+/// Merchants one subscription may watch
+pub const MAX_MERCHANTS: usize = 100;
+/// How often digest subscriptions are delivered
+pub const DIGEST_INTERVAL: TimeDelta = TimeDelta::days(1);
+/// Quiet time after a coupon store change before the corpus is diffed, so one
+/// ingest run is diffed once
+const SETTLE_DELAY: Duration = Duration::from_secs(5);
+/// How long the instance that last notified keeps the alerts between changes
+const LEAD_LEASE: Duration = Duration::from_secs(15 * 60);
+/// How often due digests are looked for
+const DIGEST_TICK: Duration = Duration::from_secs(3600);
+/// Changes a digest subscription holds before the oldest are dropped
+const MAX_PENDING: usize = 500;
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Delivery {
+    /// Posted after every ingest run that changed a watched merchant
+    #[default]
+    Webhook,
+    /// Posted once a day
+    Digest,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SubscriptionRequest {
+    pub merchants: Vec<MerchantDomain>,
+    pub webhook_url: String,
+    #[serde(default)]
+    pub delivery: Delivery,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Subscription {
+    pub id: Uuid,
+    pub tenant: String,
+    pub merchants: BTreeSet<MerchantDomain>,
+    pub webhook_url: String,
+    pub delivery: Delivery,
+    pub created_at: DateTime<Utc>,
+    /// Changes waiting for the next digest
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pending: Vec<CorpusDelta>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_delivered_at: Option<DateTime<Utc>>,
+}
+
+/// A merchant's best active code
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct BestCode {
+    pub code: CouponCode,
+    pub title: String,
+    pub discount_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub discount_value: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DeltaKind {
+    /// A better code than the previous best is available
+    NewBestCode,
+    /// The previous best code expired or is gone
+    TopCodeExpired,
+}
+
+/// A material change to one merchant's coupons
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct CorpusDelta {
+    pub merchant: MerchantDomain,
+    pub kind: DeltaKind,
+    pub previous: Option<BestCode>,
+    pub current: Option<BestCode>,
+    /// Active codes that were not active in the previous snapshot
+    #[serde(default)]
+    pub added: Vec<String>,
+    /// Codes that were active in the previous snapshot but no longer are
+    #[serde(default)]
+    pub removed: Vec<String>,
+    pub detected_at: DateTime<Utc>,
+}
+
+/// The canonical view of one merchant's corpus
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct MerchantSnapshot {
+    /// Active codes, upper-cased
+    codes: BTreeSet<String>,
+    best: Option<BestCode>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct DeltaState {
+    subscriptions: BTreeMap<Uuid, Subscription>,
+    snapshots: BTreeMap<MerchantDomain, MerchantSnapshot>,
+    /// The first snapshot only sets the baseline; nothing is announced for it
+    baselined: bool,
+}
+
+pub struct CouponDeltas {
+    state: Mutex<DeltaState>,
+    store: PersistedStore<DeltaState>,
+    client: reqwest::Client,
+    clock: Arc<dyn Clock>,
+    top_coupons: Option<Arc<TopCoupons>>,
+    licenses: Option<Arc<SourceLicenses>>,
+}
+
+impl CouponDeltas {
+    /// Subscriptions persisted to `path`, or kept in memory only
+    pub fn new(path: Option<PathBuf>) -> Self {
+        Self::with_store(PersistedStore::new(STORE_NAME, REDIS_KEY, path))
+    }
+
+    /// Subscriptions shared through Redis
+    pub fn shared(redis_url: &str) -> Result<Self, StoreError> {
+        Ok(Self::with_store(PersistedStore::shared(STORE_NAME, REDIS_KEY, redis_url)?))
+    }
+
+    fn with_store(store: PersistedStore<DeltaState>) -> Self {
+        Self {
+            state: Mutex::new(DeltaState::default()),
+            store,
+            client: reqwest::Client::builder()
+                .timeout(WEBHOOK_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+            clock: clock::system(),
+            top_coupons: None,
+            licenses: None,
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Drop a merchant's cached top coupons when one of its codes expires or disappears
+    pub fn with_top_coupons(mut self, top_coupons: Arc<TopCoupons>) -> Self {
+        self.top_coupons = Some(top_coupons);
+        self
+    }
+
+    /// Leave coupons that may not be redistributed out of the snapshots
+    pub fn with_licenses(mut self, licenses: Arc<SourceLicenses>) -> Self {
+        self.licenses = Some(licenses);
+        self
+    }
+
+    /// Share subscriptions through `REDIS_URL` when set, otherwise load them from
+    /// `COUPON_ALERTS_PATH` (default `data/coupon_alerts.json`)
+    pub async fn from_env() -> Self {
+        let deltas = Self::with_store(PersistedStore::from_env(STORE_NAME, REDIS_KEY, "COUPON_ALERTS_PATH", "data/coupon_alerts.json"));
+        if let Err(e) = deltas.reload(&mut *deltas.state.lock().await).await {
+            tracing::warn!(error = %e, "Starting without coupon alert subscriptions");
+        }
+        deltas
+    }
+
+    async fn reload(&self, state: &mut DeltaState) -> Result<(), StoreError> {
+        if let Some(stored) = self.store.load().await? {
+            *state = stored;
+        }
+        Ok(())
+    }
+
+    /// The subscriptions and snapshots, re-read when shared since any instance may
+    /// have changed the subscriptions
+    async fn lock(&self) -> MutexGuard<'_, DeltaState> {
+        let mut state = self.state.lock().await;
+        if self.store.is_shared() {
+            if let Err(e) = self.reload(&mut state).await {
+                tracing::warn!(error = %e, "Failed to re-read coupon alert subscriptions; using the local copy");
+            }
+        }
+        state
+    }
+
+    pub async fn subscribe(&self, tenant: &str, request: SubscriptionRequest) -> Result<Subscription, String> {
+        let merchants: BTreeSet<MerchantDomain> = request.merchants.into_iter().collect();
+        if merchants.is_empty() {
+            return Err("at least one merchant is required".to_string());
+        }
+        if merchants.len() > MAX_MERCHANTS {
+            return Err(format!("at most {} merchants per subscription", MAX_MERCHANTS));
+        }
+        if !url::Url::parse(&request.webhook_url).is_ok_and(|u| matches!(u.scheme(), "http" | "https")) {
+            return Err(format!("'{}' is not an http(s) URL", request.webhook_url));
+        }
+
+        let subscription = Subscription {
+            id: Uuid::new_v4(),
+            tenant: tenant.to_string(),
+            merchants,
+            webhook_url: request.webhook_url,
+            delivery: request.delivery,
+            created_at: self.clock.now(),
+            pending: Vec::new(),
+            last_delivered_at: None,
+        };
+        let mut state = self.lock().await;
+        state.subscriptions.insert(subscription.id, subscription.clone());
+        self.store.persist(&state).await;
+        Ok(subscription)
+    }
+
+    /// A tenant's subscriptions
+    pub async fn subscriptions(&self, tenant: &str) -> Vec<Subscription> {
+        self.lock()
+            .await
+            .subscriptions
+            .values()
+            .filter(|s| s.tenant == tenant)
+            .cloned()
+            .collect()
+    }
+
+    /// Remove a tenant's subscription; false when it has none with `id`
+    pub async fn unsubscribe(&self, tenant: &str, id: Uuid) -> bool {
+        let mut state = self.lock().await;
+        if state.subscriptions.get(&id).is_none_or(|s| s.tenant != tenant) {
+            return false;
+        }
+        state.subscriptions.remove(&id);
+        self.store.persist(&state).await;
+        true
+    }
+
+    /// Snapshot the corpus, diff it against the previous snapshot and notify the
+    /// subscribers of every merchant that materially changed
+    pub async fn run(&self, store: &CouponStore) -> Vec<CorpusDelta> {
+        self.diff_corpus(store, true).await
+    }
+
+    /// Snapshot and diff the corpus like [`CouponDeltas::run`] without notifying or
+    /// persisting anything, so an instance that does not lead the alerts keeps its
+    /// snapshots and top coupons current
+    pub async fn track(&self, store: &CouponStore) -> Vec<CorpusDelta> {
+        self.diff_corpus(store, false).await
+    }
+
+    async fn diff_corpus(&self, store: &CouponStore, notify: bool) -> Vec<CorpusDelta> {
+        let now = self.clock.now();
+        let mut listed = store.list().await;
+        if let Some(licenses) = &self.licenses {
+            licenses.retain_permitted(&mut listed, Some(&BTreeSet::from(License::REDISTRIBUTABLE))).await;
+        }
+        let snapshots = snapshot(&listed, now);
+
+        let mut state = match notify {
+            true => self.lock().await,
+            false => self.state.lock().await,
+        };
+        let mut deltas = Vec::new();
+        if state.baselined {
+            let merchants: BTreeSet<&MerchantDomain> = state.snapshots.keys().chain(snapshots.keys()).collect();
+            for merchant in merchants {
+                let empty = MerchantSnapshot::default();
+                let previous = state.snapshots.get(merchant).unwrap_or(&empty);
+                let current = snapshots.get(merchant).unwrap_or(&empty);
+                if let Some(delta) = diff(merchant, previous, current, now) {
+                    deltas.push(delta);
+                }
+            }
+        }
+        state.snapshots = snapshots;
+        state.baselined = true;
+        if let Some(top_coupons) = &self.top_coupons {
+            for delta in deltas.iter().filter(|delta| !delta.removed.is_empty()) {
+                top_coupons.invalidate(&delta.merchant);
+            }
+        }
+        if !notify {
+            return deltas;
+        }
+
+        let mut webhooks = Vec::new();
+        for subscription in state.subscriptions.values_mut() {
+            let matched: Vec<CorpusDelta> = deltas
+                .iter()
+                .filter(|delta| subscription.merchants.contains(&delta.merchant))
+                .cloned()
+                .collect();
+            if matched.is_empty() {
+                continue;
+            }
+            match subscription.delivery {
+                Delivery::Webhook => {
+                    subscription.last_delivered_at = Some(now);
+                    webhooks.push((subscription.id, subscription.webhook_url.clone(), matched));
+                }
+                Delivery::Digest => {
+                    subscription.pending.extend(matched);
+                    let overflow = subscription.pending.len().saturating_sub(MAX_PENDING);
+                    subscription.pending.drain(..overflow);
+                }
+            }
+        }
+        self.store.persist(&state).await;
+        drop(state);
+
+        for (id, url, matched) in webhooks {
+            self.deliver(id, url, "coupon_delta", matched);
+        }
+        deltas
+    }
+
+    /// Deliver the digests that are due
+    pub async fn flush_digests(&self) {
+        let now = self.clock.now();
+        let mut state = self.lock().await;
+        let mut due = Vec::new();
+        for subscription in state.subscriptions.values_mut() {
+            let is_due = subscription
+                .last_delivered_at
+                .is_none_or(|at| now - at >= DIGEST_INTERVAL);
+            if subscription.delivery == Delivery::Digest && is_due && !subscription.pending.is_empty() {
+                subscription.last_delivered_at = Some(now);
+                due.push((subscription.id, subscription.webhook_url.clone(), std::mem::take(&mut subscription.pending)));
+            }
+        }
+        if due.is_empty() {
+            return;
+        }
+        self.store.persist(&state).await;
+        drop(state);
+
+        for (id, url, pending) in due {
+            self.deliver(id, url, "coupon_delta_digest", pending);
+        }
+    }
+
+    fn deliver(&self, subscription: Uuid, url: String, event: &str, deltas: Vec<CorpusDelta>) {
+        let request = self
+            .client
+            .post(&url)
+            .json(&json!({"event": event, "subscription": subscription, "deltas": deltas}));
+        tokio::spawn(async move {
+            if let Err(e) = request.send().await.and_then(|r| r.error_for_status()) {
+                tracing::warn!(url = %url, error = %e, "Failed to deliver a coupon alert");
+            }
+        });
+    }
+
+    /// Diff the corpus after each burst of coupon store changes, and deliver digests
+    /// hourly when due. Only the instance leading `coupon-deltas` notifies and only
+    /// the one leading `coupon-delta-digests` delivers; the others just track the
+    /// corpus. Runs until the process exits.
+    pub async fn start_background_tasks(self: Arc<Self>, store: Arc<CouponStore>, leader: Arc<LeaderElection>) {
+        let digests = self.clone();
+        tokio::spawn(leader.clone().run_singleton("coupon-delta-digests", DIGEST_TICK, move || {
+            let digests = digests.clone();
+            async move {
+                digests.flush_digests().await;
+            }
+        }));
+
+        // The starting corpus is the baseline
+        loop {
+            match leader.try_lead("coupon-deltas", LEAD_LEASE) {
+                true => self.run(&store).await,
+                false => self.track(&store).await,
+            };
+            store.changed().await;
+            self.clock.sleep(SETTLE_DELAY).await;
+        }
+    }
+}
+
+/// Active codes and the best of them, per merchant
+fn snapshot(listings: &[CouponListing], now: DateTime<Utc>) -> BTreeMap<MerchantDomain, MerchantSnapshot> {
+    let mut by_merchant: BTreeMap<MerchantDomain, Vec<&CouponListing>> = BTreeMap::new();
+    for listing in listings.iter().filter(|l| l.valid_until.is_none_or(|until| until > now)) {
+        by_merchant.entry(listing.merchant_domain.clone()).or_default().push(listing);
+    }
+
+    by_merchant
+        .into_iter()
+        .map(|(merchant, listings)| {
+            let best = listings
+                .iter()
+                .max_by(|a, b| offer_rank(a).partial_cmp(&offer_rank(b)).unwrap_or(std::cmp::Ordering::Equal))
+                .map(|best| BestCode {
+                    code: best.code.clone(),
+                    title: best.title.clone(),
+                    discount_type: best.discount_type.clone(),
+                    discount_value: best.discount_value,
+                });
+            let codes = listings.iter().map(|l| l.code.as_str().to_ascii_uppercase()).collect();
+            (merchant, MerchantSnapshot { codes, best })
+        })
+        .collect()
+}
+
+/// How good an offer is: percentage discounts first, then fixed amounts, then free
+/// shipping, then anything else; within a kind the larger value, then the more
+/// certain extraction
+fn offer_rank(listing: &CouponListing) -> (u8, f64, f64) {
+    let kind = match listing.discount_type.as_str() {
+        "percentage" => 3,
+        "fixed" => 2,
+        "free_shipping" => 1,
+        _ => 0,
+    };
+    (kind, listing.discount_value.unwrap_or(0.0), listing.extraction_confidence)
+}
+
+fn diff(merchant: &MerchantDomain, previous: &MerchantSnapshot, current: &MerchantSnapshot, now: DateTime<Utc>) -> Option<CorpusDelta> {
+    let previous_best = previous.best.as_ref();
+    let current_best = current.best.as_ref();
+    let kind = match previous_best {
+        _ if previous_best.map(|b| &b.code) == current_best.map(|b| &b.code) => return None,
+        Some(best) if !current.codes.contains(&best.code.as_str().to_ascii_uppercase()) => DeltaKind::TopCodeExpired,
+        _ => DeltaKind::NewBestCode,
+    };
+
+    Some(CorpusDelta {
+        merchant: merchant.clone(),
+        kind,
+        previous: previous.best.clone(),
+        current: current.best.clone(),
+        added: current.codes.difference(&previous.codes).cloned().collect(),
+        removed: previous.codes.difference(&current.codes).cloned().collect(),
+        detected_at: now,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::models::coupon_listing::CouponSource;
+
+    fn listing(code: &str, percent: f64, valid_until: Option<DateTime<Utc>>) -> CouponListing {
+        CouponListing {
+            code: CouponCode::parse(code).unwrap(),
+            title: format!("{}% off", percent),
+            description: None,
+            locale: None,
+            merchant_domain: MerchantDomain::parse("shop.com").unwrap(),
+            discount_type: "percentage".to_string(),
+            discount_value: Some(percent),
+            minimum_order: None,
+            source: CouponSource::WebScraping,
+            license: None,
+            extraction_confidence: 0.9,
+            scraped_at: Utc::now(),
+            valid_until,
+            predicted_success: None,
+            tags: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_announces_new_best_and_expired_top_codes_to_digests() {
+        let clock = Arc::new(MockClock::new());
+        let deltas = CouponDeltas::new(None).with_clock(clock.clone());
+        let request = SubscriptionRequest {
+            merchants: vec![MerchantDomain::parse("shop.com").unwrap()],
+            webhook_url: "https://partner.example.com/hooks/coupons".to_string(),
+            delivery: Delivery::Digest,
+        };
+        let subscription = deltas.subscribe("acme", request).await.unwrap();
+        let store = CouponStore::with_coupons(vec![listing("SAVE10", 10.0, None)]);
+
+        // The first run is the baseline
+        assert!(deltas.run(&store).await.is_empty());
+
+        store.upsert(listing("SAVE25", 25.0, Some(clock.now() + TimeDelta::hours(1)))).await;
+        store.upsert(listing("SAVE5", 5.0, None)).await;
+        let found = deltas.run(&store).await;
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].kind, DeltaKind::NewBestCode);
+        assert_eq!(found[0].current.as_ref().unwrap().code.as_str(), "SAVE25");
+        assert_eq!(found[0].added, vec!["SAVE25", "SAVE5"]);
+
+        // Nothing material: a weaker code comes and goes
+        store.upsert(listing("SAVE3", 3.0, None)).await;
+        assert!(deltas.run(&store).await.is_empty());
+
+        clock.advance(Duration::from_secs(2 * 3600));
+        let found = deltas.run(&store).await;
+        assert_eq!(found[0].kind, DeltaKind::TopCodeExpired);
+        assert_eq!(found[0].current.as_ref().unwrap().code.as_str(), "SAVE10");
+        assert_eq!(found[0].removed, vec!["SAVE25"]);
+
+        let pending = &deltas.subscriptions("acme").await[0].pending;
+        assert_eq!(pending.len(), 2);
+        assert!(deltas.subscriptions("other").await.is_empty());
+        assert!(!deltas.unsubscribe("other", subscription.id).await);
+        assert!(deltas.unsubscribe("acme", subscription.id).await);
+    }
+
+    #[tokio::test]
+    async fn test_tracking_finds_deltas_without_queueing_them() {
+        let deltas = CouponDeltas::new(None);
+        let request = SubscriptionRequest {
+            merchants: vec![MerchantDomain::parse("shop.com").unwrap()],
+            webhook_url: "https://partner.example.com/hooks/coupons".to_string(),
+            delivery: Delivery::Digest,
+        };
+        deltas.subscribe("acme", request).await.unwrap();
+        let store = CouponStore::with_coupons(vec![listing("SAVE10", 10.0, None)]);
+        deltas.track(&store).await;
+
+        store.upsert(listing("SAVE25", 25.0, None)).await;
+        assert_eq!(deltas.track(&store).await.len(), 1);
+        assert!(deltas.subscriptions("acme").await[0].pending.is_empty());
+
+        // A new leader diffs from the tracked snapshots, so nothing is announced twice
+        assert!(deltas.run(&store).await.is_empty());
+    }
+}
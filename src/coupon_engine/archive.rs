@@ -0,0 +1,187 @@
+//! Raw content archive
+//!
+//! Fetched pages are kept as snapshots so the coupon corpus can be rebuilt after an
+//! extraction improvement (see [`crate::reprocess`]) without scraping every merchant
+//! again. Each snapshot is one JSON file named `<fetched at>-<merchant>-<hash>.json`,
+//! so listings can be filtered by time and merchant without opening the files. A
+//! page whose content has not changed since its previous snapshot is not stored again.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::Mutex;
+use utoipa::ToSchema;
+
+use crate::clock::{self, Clock};
+use crate::models::domain::MerchantDomain;
+
+const TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%S%.9fZ";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub url: String,
+    pub fetched_at: DateTime<Utc>,
+    pub content: String,
+}
+
+/// An archived snapshot, as listed
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SnapshotRef {
+    pub fetched_at: DateTime<Utc>,
+    pub merchant: String,
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, ToSchema)]
+pub struct SnapshotFilter {
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub merchant: Option<MerchantDomain>,
+}
+
+impl SnapshotFilter {
+    fn matches(&self, snapshot: &SnapshotRef) -> bool {
+        self.since.is_none_or(|since| snapshot.fetched_at >= since)
+            && self.until.is_none_or(|until| snapshot.fetched_at < until)
+            && self.merchant.as_ref().is_none_or(|merchant| merchant.as_str() == snapshot.merchant)
+    }
+}
+
+pub struct SnapshotArchive {
+    dir: PathBuf,
+    /// Content hash of the latest snapshot per URL
+    latest: Mutex<HashMap<String, String>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl SnapshotArchive {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            latest: Mutex::new(HashMap::new()),
+            clock: clock::system(),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Archive into `SNAPSHOT_ARCHIVE_DIR`; archiving is off when it is unset
+    pub fn from_env() -> Option<Self> {
+        std::env::var("SNAPSHOT_ARCHIVE_DIR").ok().map(|dir| Self::new(PathBuf::from(dir)))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub async fn store(&self, url: &str, content: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let hash = format!("{:x}", Sha256::digest(content.as_bytes()));
+        {
+            let mut latest = self.latest.lock().await;
+            if latest.get(url) == Some(&hash) {
+                return Ok(());
+            }
+            latest.insert(url.to_string(), hash.clone());
+        }
+
+        let fetched_at = self.clock.now();
+        let merchant = MerchantDomain::parse(url).map_or_else(|_| "unknown".to_string(), |domain| domain.to_string());
+        let path = self.dir.join(format!("{}-{}-{}.json", fetched_at.format(TIMESTAMP_FORMAT), merchant, &hash[..16]));
+        let snapshot = Snapshot {
+            url: url.to_string(),
+            fetched_at,
+            content: content.to_string(),
+        };
+
+        tokio::fs::create_dir_all(&self.dir).await?;
+        tokio::fs::write(path, serde_json::to_string(&snapshot)?).await?;
+        Ok(())
+    }
+
+    /// Matching snapshots, oldest first
+    pub async fn list(&self, filter: &SnapshotFilter) -> Result<Vec<SnapshotRef>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut snapshots = Vec::new();
+        let mut entries = match tokio::fs::read_dir(&self.dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(snapshots),
+            Err(e) => return Err(e.into()),
+        };
+
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if let Some(snapshot) = parse_file_name(&path).filter(|snapshot| filter.matches(snapshot)) {
+                snapshots.push(snapshot);
+            }
+        }
+        snapshots.sort();
+        Ok(snapshots)
+    }
+
+    pub async fn load(&self, snapshot: &SnapshotRef) -> Result<Snapshot, Box<dyn std::error::Error + Send + Sync>> {
+        let content = tokio::fs::read_to_string(&snapshot.path).await?;
+        Ok(serde_json::from_str(&content)?)
+    }
+}
+
+impl SnapshotRef {
+    /// The snapshot at `path`, if it is named the way the archive names snapshots
+    pub fn from_path(path: &Path) -> Option<Self> {
+        parse_file_name(path)
+    }
+}
+
+fn parse_file_name(path: &Path) -> Option<SnapshotRef> {
+    let stem = path.file_name()?.to_str()?.strip_suffix(".json")?;
+    let (timestamp, rest) = stem.split_once('-')?;
+    let (merchant, _hash) = rest.rsplit_once('-')?;
+    let fetched_at = NaiveDateTime::parse_from_str(timestamp, TIMESTAMP_FORMAT).ok()?.and_utc();
+
+    Some(SnapshotRef {
+        fetched_at,
+        merchant: merchant.to_string(),
+        path: path.to_path_buf(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_stores_changed_content_and_filters_listing() {
+        let dir = std::env::temp_dir().join(format!("snapshots_{}", uuid::Uuid::new_v4()));
+        let clock = Arc::new(MockClock::new());
+        let archive = SnapshotArchive::new(dir.clone()).with_clock(clock.clone());
+
+        archive.store("https://shop.example.com/deals", "v1").await.unwrap();
+        clock.advance(Duration::from_secs(60));
+        archive.store("https://shop.example.com/deals", "v1").await.unwrap();
+        let changed_at = clock.now();
+        archive.store("https://shop.example.com/deals", "v2").await.unwrap();
+        archive.store("https://www.other-shop.co.uk/", "other").await.unwrap();
+
+        let all = archive.list(&SnapshotFilter::default()).await.unwrap();
+        assert_eq!(all.len(), 3);
+
+        let filter = SnapshotFilter {
+            since: Some(changed_at),
+            merchant: Some(MerchantDomain::parse("shop.example.com").unwrap()),
+            ..SnapshotFilter::default()
+        };
+        let recent = archive.list(&filter).await.unwrap();
+        assert_eq!(recent.len(), 1);
+        assert_eq!(archive.load(&recent[0]).await.unwrap().content, "v2");
+        assert_eq!(all.iter().filter(|s| s.merchant == "other-shop.co.uk").count(), 1);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
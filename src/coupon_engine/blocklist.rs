@@ -0,0 +1,521 @@
+//! Rules that drop known extraction noise
+//!
+//! Some pages carry boilerplate that parses as a coupon everywhere it appears, such
+//! as an aggregator's `SIGNUP10` newsletter code in every page footer. Operators
+//! add rules for them through `/admin/blocklist` instead of shipping a parser change:
+//!
+//! - `url` rules match page URLs, whose pages the parser skips entirely;
+//! - `merchant` rules match merchant domains: the parser skips their pages and the
+//!   validator drops coupons listed for them on other sites;
+//! - `code` rules match codes, which the validator drops.
+//!
+//! Patterns are globs (`*` and `?`, matching the whole value) or regexes (matching
+//! anywhere), both case-insensitive. A rule may be scoped to one merchant's pages.
+//!
+//! Each rule counts its hits, counting only coupons that passed every other check,
+//! so rules that never fire can be retired. With `REDIS_URL` set the rules and hit
+//! counts are shared and re-read every [`REFRESH_INTERVAL`]; otherwise rules are
+//! persisted to `EXTRACTION_BLOCKLIST_PATH` (default `data/extraction_blocklist.json`)
+//! and hits are counted since the instance started.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::clock::{self, Clock};
+use crate::coupon_engine::RawCoupon;
+use crate::models::domain::MerchantDomain;
+use crate::storage::persisted::{PersistedStore, StoreError};
+
+const REDIS_KEY: &str = "extraction_blocklist";
+const REDIS_HITS_KEY: &str = "extraction_blocklist_hits";
+const REDIS_LAST_HIT_KEY: &str = "extraction_blocklist_last_hit";
+const STORE_NAME: &str = "extraction blocklist";
+/// How often shared rules are re-read and local hits added to the shared counts
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+/// Longest pattern accepted, to keep matching cheap
+const MAX_PATTERN_LEN: usize = 500;
+
+/// What a rule's pattern is matched against
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleTarget {
+    Code,
+    Merchant,
+    Url,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PatternSyntax {
+    /// `*` and `?` wildcards, matching the whole value
+    #[default]
+    Glob,
+    /// Matching anywhere in the value
+    Regex,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct BlockRule {
+    pub id: String,
+    pub target: RuleTarget,
+    pub pattern: String,
+    #[serde(default)]
+    pub syntax: PatternSyntax,
+    /// Only on this merchant's pages, e.g. the aggregator a boilerplate code appears on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub merchant: Option<MerchantDomain>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct BlockRuleRequest {
+    pub target: RuleTarget,
+    pub pattern: String,
+    #[serde(default)]
+    pub syntax: PatternSyntax,
+    #[serde(default)]
+    pub merchant: Option<MerchantDomain>,
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+impl BlockRuleRequest {
+    /// Whether the pattern is usable
+    pub fn validate(&self) -> Result<(), String> {
+        self.compile().map(|_| ())
+    }
+
+    /// The pattern, compiled, if it is usable
+    fn compile(&self) -> Result<Regex, String> {
+        let pattern = self.pattern.trim();
+        if pattern.is_empty() || pattern.len() > MAX_PATTERN_LEN {
+            return Err(format!("pattern must be 1 to {} characters", MAX_PATTERN_LEN));
+        }
+        if self.syntax == PatternSyntax::Glob && pattern.chars().all(|c| c == '*') {
+            return Err("pattern must not match everything".to_string());
+        }
+        compile(pattern, self.syntax)
+    }
+}
+
+/// A rule with how often it fired
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct RuleUsage {
+    #[serde(flatten)]
+    pub rule: BlockRule,
+    pub hits: u64,
+    pub last_hit_at: Option<DateTime<Utc>>,
+}
+
+fn compile(pattern: &str, syntax: PatternSyntax) -> Result<Regex, String> {
+    let source = match syntax {
+        PatternSyntax::Regex => pattern.to_string(),
+        PatternSyntax::Glob => {
+            let mut source = String::from("^");
+            for c in pattern.chars() {
+                match c {
+                    '*' => source.push_str(".*"),
+                    '?' => source.push('.'),
+                    c => source.push_str(&regex::escape(&c.to_string())),
+                }
+            }
+            source.push('$');
+            source
+        }
+    };
+    RegexBuilder::new(&source)
+        .case_insensitive(true)
+        .size_limit(1 << 20)
+        .build()
+        .map_err(|e| format!("invalid pattern: {}", e))
+}
+
+struct LoadedRule {
+    rule: BlockRule,
+    matcher: Regex,
+}
+
+impl LoadedRule {
+    fn load(rule: BlockRule) -> Result<Self, String> {
+        let matcher = compile(&rule.pattern, rule.syntax)?;
+        Ok(Self { rule, matcher })
+    }
+
+    /// Whether the rule applies on `page`'s merchant
+    fn scoped_to(&self, page: Option<&MerchantDomain>) -> bool {
+        self.rule
+            .merchant
+            .as_ref()
+            .is_none_or(|merchant| page.is_some_and(|page| covers(merchant, page)))
+    }
+}
+
+/// Whether `domain` is `merchant` or one of its subdomains
+fn covers(merchant: &MerchantDomain, domain: &MerchantDomain) -> bool {
+    let (merchant, domain) = (merchant.as_str(), domain.as_str());
+    domain == merchant || domain.strip_suffix(merchant).is_some_and(|sub| sub.ends_with('.'))
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Hits {
+    count: u64,
+    last_at: Option<DateTime<Utc>>,
+}
+
+pub struct ExtractionBlocklist {
+    rules: RwLock<BTreeMap<String, LoadedRule>>,
+    /// Every rule in the file, one field of the Redis hash each
+    store: PersistedStore<Vec<BlockRule>>,
+    /// Counts as of the last reload, plus this instance's since then
+    hits: Mutex<HashMap<String, Hits>>,
+    /// This instance's hits not yet added to the shared counts
+    unflushed: Mutex<HashMap<String, Hits>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl ExtractionBlocklist {
+    /// Rules persisted to `path`, or kept in memory only
+    pub fn new(path: Option<PathBuf>) -> Self {
+        Self::with_store(PersistedStore::new(STORE_NAME, REDIS_KEY, path))
+    }
+
+    /// Rules and hit counts shared through Redis
+    pub fn shared(redis_url: &str) -> Result<Self, StoreError> {
+        Ok(Self::with_store(PersistedStore::shared(STORE_NAME, REDIS_KEY, redis_url)?))
+    }
+
+    fn with_store(store: PersistedStore<Vec<BlockRule>>) -> Self {
+        Self {
+            rules: RwLock::new(BTreeMap::new()),
+            store: store.pretty(),
+            hits: Mutex::new(HashMap::new()),
+            unflushed: Mutex::new(HashMap::new()),
+            clock: clock::system(),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Share rules through `REDIS_URL` when set, otherwise load them from
+    /// `EXTRACTION_BLOCKLIST_PATH` (default `data/extraction_blocklist.json`)
+    pub async fn from_env() -> Self {
+        let store = PersistedStore::from_env(STORE_NAME, REDIS_KEY, "EXTRACTION_BLOCKLIST_PATH", "data/extraction_blocklist.json");
+        let blocklist = Self::with_store(store);
+        if let Err(e) = blocklist.reload().await {
+            tracing::warn!(error = %e, "Starting with an empty extraction blocklist");
+        }
+        blocklist
+    }
+
+    /// Replace the local copy with every stored rule and, when shared, the hit counts
+    pub async fn reload(&self) -> Result<(), StoreError> {
+        let stored = match self.store.redis() {
+            None => match self.store.load().await? {
+                Some(stored) => stored,
+                None => return Ok(()),
+            },
+            Some(client) => {
+                let mut con = client.get_connection()?;
+                let counts: HashMap<String, u64> = redis::cmd("HGETALL").arg(REDIS_HITS_KEY).query(&mut con)?;
+                let last: HashMap<String, String> = redis::cmd("HGETALL").arg(REDIS_LAST_HIT_KEY).query(&mut con)?;
+                let unflushed = self.unflushed.lock().unwrap();
+                let hits = counts
+                    .into_iter()
+                    .map(|(id, count)| {
+                        let pending = unflushed.get(&id).copied().unwrap_or_default();
+                        let last_at = last.get(&id).and_then(|at| at.parse().ok()).max(pending.last_at);
+                        (id, Hits { count: count + pending.count, last_at })
+                    })
+                    .collect();
+                *self.hits.lock().unwrap() = hits;
+                self.store.hash_values(REDIS_KEY)?.unwrap_or_default()
+            }
+        };
+
+        let mut rules = BTreeMap::new();
+        for rule in stored {
+            match LoadedRule::load(rule) {
+                Ok(loaded) => {
+                    rules.insert(loaded.rule.id.clone(), loaded);
+                }
+                Err(e) => tracing::warn!(error = %e, "Skipping an extraction blocklist rule"),
+            }
+        }
+        *self.rules.write().unwrap() = rules;
+        Ok(())
+    }
+
+    /// Every [`REFRESH_INTERVAL`], add this instance's hits to the shared counts and
+    /// re-read shared rules, picking up rules changed through other instances
+    pub async fn start_background_tasks(self: Arc<Self>) {
+        let refresh = || async {
+            if let Err(e) = self.flush_hits() {
+                tracing::warn!(error = %e, "Failed to share extraction blocklist hits");
+            }
+            self.reload().await
+        };
+        self.store.refresh_every(self.clock.as_ref(), REFRESH_INTERVAL, refresh).await
+    }
+
+    fn flush_hits(&self) -> Result<(), StoreError> {
+        let Some(client) = self.store.redis() else {
+            return Ok(());
+        };
+        let pending = std::mem::take(&mut *self.unflushed.lock().unwrap());
+        if pending.is_empty() {
+            return Ok(());
+        }
+        let mut pipe = redis::pipe();
+        for (id, hits) in &pending {
+            pipe.cmd("HINCRBY").arg(REDIS_HITS_KEY).arg(id).arg(hits.count).ignore();
+            if let Some(at) = hits.last_at {
+                pipe.cmd("HSET").arg(REDIS_LAST_HIT_KEY).arg(id).arg(at.to_rfc3339()).ignore();
+            }
+        }
+        let result = client.get_connection().and_then(|mut con| pipe.query::<()>(&mut con));
+        if result.is_err() {
+            // Keep them for the next flush
+            let mut unflushed = self.unflushed.lock().unwrap();
+            for (id, hits) in pending {
+                let entry = unflushed.entry(id).or_default();
+                entry.count += hits.count;
+                entry.last_at = entry.last_at.max(hits.last_at);
+            }
+        }
+        Ok(result?)
+    }
+
+    async fn write(&self, id: &str, rule: Option<&BlockRule>) -> Result<(), StoreError> {
+        let Some(client) = self.store.redis().filter(|_| rule.is_none()) else {
+            return self
+                .store
+                .write_field(REDIS_KEY, id, rule, || {
+                    let mut all: Vec<BlockRule> = self
+                        .rules
+                        .read()
+                        .unwrap()
+                        .values()
+                        .map(|loaded| loaded.rule.clone())
+                        .filter(|existing| existing.id != id)
+                        .collect();
+                    all.extend(rule.cloned());
+                    all.sort_by(|a, b| a.created_at.cmp(&b.created_at).then_with(|| a.id.cmp(&b.id)));
+                    all
+                })
+                .await;
+        };
+
+        // A deleted rule's shared hits go with it
+        let mut con = client.get_connection()?;
+        redis::pipe()
+            .atomic()
+            .cmd("HDEL")
+            .arg(REDIS_KEY)
+            .arg(id)
+            .cmd("HDEL")
+            .arg(REDIS_HITS_KEY)
+            .arg(id)
+            .cmd("HDEL")
+            .arg(REDIS_LAST_HIT_KEY)
+            .arg(id)
+            .query::<()>(&mut con)?;
+        Ok(())
+    }
+
+    /// Every rule with its hits, oldest first
+    pub fn list(&self) -> Vec<RuleUsage> {
+        let hits = self.hits.lock().unwrap();
+        let mut rules: Vec<RuleUsage> = self
+            .rules
+            .read()
+            .unwrap()
+            .values()
+            .map(|loaded| {
+                let rule_hits = hits.get(&loaded.rule.id).copied().unwrap_or_default();
+                RuleUsage {
+                    rule: loaded.rule.clone(),
+                    hits: rule_hits.count,
+                    last_hit_at: rule_hits.last_at,
+                }
+            })
+            .collect();
+        rules.sort_by(|a, b| a.rule.created_at.cmp(&b.rule.created_at).then_with(|| a.rule.id.cmp(&b.rule.id)));
+        rules
+    }
+
+    /// Add a rule; extraction consults it from the next page on
+    pub async fn add(&self, request: BlockRuleRequest) -> Result<BlockRule, String> {
+        let matcher = request.compile()?;
+        let rule = BlockRule {
+            id: uuid::Uuid::new_v4().to_string(),
+            target: request.target,
+            pattern: request.pattern.trim().to_string(),
+            syntax: request.syntax,
+            merchant: request.merchant,
+            reason: request.reason.map(|reason| reason.trim().to_string()).filter(|reason| !reason.is_empty()),
+            created_at: self.clock.now(),
+        };
+
+        let _write = self.store.write_lock().await;
+        self.write(&rule.id, Some(&rule))
+            .await
+            .map_err(|e| format!("failed to store the rule: {}", e))?;
+        let loaded = LoadedRule { rule: rule.clone(), matcher };
+        self.rules.write().unwrap().insert(rule.id.clone(), loaded);
+        Ok(rule)
+    }
+
+    /// Delete a rule and its hits; false when there is no such rule
+    pub async fn remove(&self, id: &str) -> Result<bool, String> {
+        let _write = self.store.write_lock().await;
+        if !self.rules.read().unwrap().contains_key(id) {
+            return Ok(false);
+        }
+        self.write(id, None)
+            .await
+            .map_err(|e| format!("failed to delete the rule: {}", e))?;
+        self.rules.write().unwrap().remove(id);
+        self.hits.lock().unwrap().remove(id);
+        self.unflushed.lock().unwrap().remove(id);
+        Ok(true)
+    }
+
+    /// Whether the page at `url` is skipped, by a `url` rule or a `merchant` rule
+    /// matching its domain
+    pub fn blocks_page(&self, url: &str) -> bool {
+        let domain = MerchantDomain::parse(url).ok();
+        self.first_match(domain.as_ref(), |rule| match rule.target {
+            RuleTarget::Url => Some(url),
+            RuleTarget::Merchant => domain.as_ref().map(MerchantDomain::as_str),
+            RuleTarget::Code => None,
+        })
+    }
+
+    /// Whether `coupon` is dropped, by a `code` rule or a `merchant` rule matching
+    /// the merchant it is listed for
+    pub fn blocks_coupon(&self, coupon: &RawCoupon) -> bool {
+        let page = MerchantDomain::parse(&coupon.source_url).ok();
+        self.first_match(page.as_ref(), |rule| match rule.target {
+            RuleTarget::Code => Some(coupon.code.as_str()),
+            RuleTarget::Merchant => Some(coupon.merchant_domain.as_str()),
+            RuleTarget::Url => None,
+        })
+    }
+
+    /// Whether a rule in scope on `page` matches its `value`, counting the hit
+    fn first_match<'a>(&self, page: Option<&MerchantDomain>, value: impl Fn(&BlockRule) -> Option<&'a str>) -> bool {
+        let rules = self.rules.read().unwrap();
+        let Some(hit) = rules.values().find(|loaded| {
+            loaded.scoped_to(page) && value(&loaded.rule).is_some_and(|value| loaded.matcher.is_match(value))
+        }) else {
+            return false;
+        };
+
+        let now = self.clock.now();
+        for counts in [&self.hits, &self.unflushed] {
+            let mut counts = counts.lock().unwrap();
+            let entry = counts.entry(hit.rule.id.clone()).or_default();
+            entry.count += 1;
+            entry.last_at = Some(now);
+        }
+        true
+    }
+}
+
+impl Default for ExtractionBlocklist {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coupon_engine::validator::Validator;
+    use crate::coupon_engine::{DiscountType, SourceType};
+    use crate::models::domain::CouponCode;
+
+    fn request(target: RuleTarget, pattern: &str, syntax: PatternSyntax, merchant: Option<&str>) -> BlockRuleRequest {
+        BlockRuleRequest {
+            target,
+            pattern: pattern.to_string(),
+            syntax,
+            merchant: merchant.map(|merchant| MerchantDomain::parse(merchant).unwrap()),
+            reason: Some("footer boilerplate".to_string()),
+        }
+    }
+
+    fn coupon(code: &str, merchant: &str, source_url: &str) -> RawCoupon {
+        RawCoupon {
+            code: CouponCode::parse(code).unwrap(),
+            title: "20% off".to_string(),
+            description: None,
+            discount_type: DiscountType::Percentage,
+            discount_value: Some(20.0),
+            minimum_order: None,
+            maximum_discount: None,
+            valid_from: None,
+            valid_until: None,
+            merchant_name: merchant.to_string(),
+            merchant_domain: MerchantDomain::parse(merchant).unwrap(),
+            source_url: source_url.to_string(),
+            source_type: SourceType::WebScraping,
+            metadata: serde_json::Value::Null,
+            scraped_at: Utc::now(),
+            parser_version: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rules_drop_matching_pages_and_coupons_and_count_hits() {
+        let path = std::env::temp_dir().join(format!("extraction_blocklist_{}.json", uuid::Uuid::new_v4()));
+        let blocklist = Arc::new(ExtractionBlocklist::new(Some(path.clone())));
+        assert!(blocklist.add(request(RuleTarget::Code, "*", PatternSyntax::Glob, None)).await.is_err());
+        assert!(blocklist.add(request(RuleTarget::Code, "(", PatternSyntax::Regex, None)).await.is_err());
+
+        let signup = blocklist
+            .add(request(RuleTarget::Code, "signup??", PatternSyntax::Glob, Some("deals.example.com")))
+            .await
+            .unwrap();
+        let tracking = blocklist.add(request(RuleTarget::Url, r"[?&]utm_", PatternSyntax::Regex, None)).await.unwrap();
+        blocklist.add(request(RuleTarget::Merchant, "*.giftcards.example", PatternSyntax::Glob, None)).await.unwrap();
+
+        let validator = Validator::new().with_blocklist(blocklist.clone());
+        let aggregator = "https://deals.example.com/shop-com";
+        assert!(!validator.is_valid(&coupon("SIGNUP10", "shop.com", aggregator)).await);
+        assert!(!validator.is_valid(&coupon("SIGNUP25", "shop.com", "https://www.deals.example.com/x")).await);
+        assert!(validator.is_valid(&coupon("SIGNUP10", "shop.com", "https://shop.com/")).await);
+        assert!(validator.is_valid(&coupon("SIGNUP100", "shop.com", aggregator)).await);
+        assert!(!validator.is_valid(&coupon("SAVE20", "eu.giftcards.example", aggregator)).await);
+
+        assert!(blocklist.blocks_page("https://shop.com/sale?utm_source=mail"));
+        assert!(blocklist.blocks_page("https://us.giftcards.example/"));
+        assert!(!blocklist.blocks_page("https://shop.com/sale"));
+
+        let hits: Vec<(RuleTarget, u64)> = blocklist.list().iter().map(|usage| (usage.rule.target, usage.hits)).collect();
+        assert_eq!(hits, vec![(RuleTarget::Code, 2), (RuleTarget::Url, 1), (RuleTarget::Merchant, 2)]);
+
+        let restarted = ExtractionBlocklist::new(Some(path.clone()));
+        restarted.reload().await.unwrap();
+        let rules: Vec<BlockRule> = restarted.list().into_iter().map(|usage| usage.rule).collect();
+        assert_eq!(rules.len(), 3);
+        assert_eq!(rules[0], signup);
+
+        assert!(blocklist.remove(&tracking.id).await.unwrap());
+        assert!(!blocklist.remove(&tracking.id).await.unwrap());
+        assert!(!blocklist.blocks_page("https://shop.com/sale?utm_source=mail"));
+        let _ = std::fs::remove_file(path);
+    }
+}
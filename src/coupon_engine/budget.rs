@@ -0,0 +1,327 @@
+//! Per-merchant daily request budgets
+//!
+//! However many jobs are queued, a merchant gets a bounded number of scrape requests
+//! per UTC day. Its budget is the `daily_request_budget` of its domain profile, or the
+//! default budget (`SCRAPE_DAILY_BUDGET`, default 500) scaled by the profile's `size`.
+//! The scrape job queue takes budget for a job's URLs before running it and defers
+//! the URLs over budget to the next day (see [`crate::jobs`]).
+//!
+//! Usage lives in Redis when `REDIS_URL` is set, so every worker draws on the same
+//! budget; otherwise it is persisted to `SCRAPE_BUDGET_PATH` (default
+//! `data/scrape_budget.json`).
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use utoipa::ToSchema;
+
+use crate::clock::{self, Clock};
+use crate::coupon_engine::profiles::DomainProfiles;
+use crate::models::domain::MerchantDomain;
+use crate::storage::persisted::{PersistedStore, StoreError};
+
+pub const DEFAULT_DAILY_BUDGET: u32 = 500;
+const REDIS_PREFIX: &str = "scrape_budget";
+const STORE_NAME: &str = "scrape budgets";
+/// Redis usage counters outlive their day by this much
+const REDIS_TTL_SECS: i64 = 2 * 24 * 3600;
+
+/// How much traffic a merchant's site can take, relative to the default budget
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MerchantSize {
+    Small,
+    #[default]
+    Medium,
+    Large,
+}
+
+impl MerchantSize {
+    fn scale(self, default_budget: u32) -> u32 {
+        match self {
+            MerchantSize::Small => (default_budget / 5).max(1),
+            MerchantSize::Medium => default_budget,
+            MerchantSize::Large => default_budget.saturating_mul(5),
+        }
+    }
+}
+
+/// A merchant's budget for the current day
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct BudgetUsage {
+    pub day: NaiveDate,
+    pub limit: u32,
+    pub used: u32,
+    pub remaining: u32,
+    pub resets_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+struct DailyUsage {
+    day: NaiveDate,
+    used: u32,
+}
+
+pub struct ScrapeBudgets {
+    /// Shared usage is kept in per-day counters rather than the stored value
+    store: PersistedStore<HashMap<MerchantDomain, DailyUsage>>,
+    /// The local usage; also the fallback while Redis is unavailable
+    usage: Mutex<HashMap<MerchantDomain, DailyUsage>>,
+    default_budget: u32,
+    profiles: Option<Arc<DomainProfiles>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl ScrapeBudgets {
+    /// Usage persisted to `path`, or kept in memory only
+    pub fn new(path: Option<PathBuf>) -> Self {
+        Self::with_store(PersistedStore::new(STORE_NAME, REDIS_PREFIX, path))
+    }
+
+    /// Usage shared through Redis, so every worker draws on the same budgets
+    pub fn shared(redis_url: &str) -> Result<Self, StoreError> {
+        Ok(Self::with_store(PersistedStore::shared(STORE_NAME, REDIS_PREFIX, redis_url)?))
+    }
+
+    fn with_store(store: PersistedStore<HashMap<MerchantDomain, DailyUsage>>) -> Self {
+        Self {
+            store,
+            usage: Mutex::new(HashMap::new()),
+            default_budget: DEFAULT_DAILY_BUDGET,
+            profiles: None,
+            clock: clock::system(),
+        }
+    }
+
+    /// Take per-merchant budgets and sizes from `profiles`
+    pub fn with_profiles(mut self, profiles: Arc<DomainProfiles>) -> Self {
+        self.profiles = Some(profiles);
+        self
+    }
+
+    /// Budget of a medium merchant without its own
+    pub fn with_default_budget(mut self, budget: u32) -> Self {
+        self.default_budget = budget.max(1);
+        self
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Share usage through `REDIS_URL` when set, otherwise load it from
+    /// `SCRAPE_BUDGET_PATH`; the default budget is `SCRAPE_DAILY_BUDGET`
+    pub async fn from_env(profiles: Arc<DomainProfiles>) -> Self {
+        let budgets = Self::with_store(PersistedStore::from_env(STORE_NAME, REDIS_PREFIX, "SCRAPE_BUDGET_PATH", "data/scrape_budget.json"));
+        if let Err(e) = budgets.load().await {
+            tracing::warn!(error = %e, "Starting with unused scrape budgets");
+        }
+
+        let default_budget = std::env::var("SCRAPE_DAILY_BUDGET")
+            .ok()
+            .and_then(|v| v.trim().parse().ok())
+            .unwrap_or(DEFAULT_DAILY_BUDGET);
+        budgets.with_profiles(profiles).with_default_budget(default_budget)
+    }
+
+    async fn load(&self) -> Result<(), StoreError> {
+        if self.store.is_shared() {
+            return Ok(());
+        }
+        if let Some(usage) = self.store.load().await? {
+            *self.usage.lock().await = usage;
+        }
+        Ok(())
+    }
+
+    /// Requests `domain` may receive per day
+    pub fn limit(&self, domain: &MerchantDomain) -> u32 {
+        let settings = self.profiles.as_ref().and_then(|profiles| profiles.get(domain)).map(|p| p.settings);
+        match settings {
+            Some(settings) => settings
+                .daily_request_budget
+                .unwrap_or_else(|| settings.size.scale(self.default_budget)),
+            None => self.default_budget,
+        }
+    }
+
+    /// When today's budgets reset
+    pub fn resets_at(&self) -> DateTime<Utc> {
+        next_reset(self.clock.now())
+    }
+
+    /// Take budget for `urls`, in order: the URLs that may be fetched today and the
+    /// ones that must wait. URLs without a merchant domain are not budgeted.
+    pub async fn take(&self, urls: &[String]) -> (Vec<String>, Vec<String>) {
+        let mut requested: Vec<(MerchantDomain, u32)> = Vec::new();
+        for domain in urls.iter().filter_map(|url| MerchantDomain::parse(url).ok()) {
+            match requested.iter_mut().find(|(d, _)| *d == domain) {
+                Some((_, count)) => *count += 1,
+                None => requested.push((domain, 1)),
+            }
+        }
+
+        let mut granted: HashMap<MerchantDomain, u32> = HashMap::new();
+        for (domain, count) in requested {
+            let allowed = self.reserve(&domain, count).await;
+            granted.insert(domain, allowed);
+        }
+
+        urls.iter().cloned().partition(|url| match MerchantDomain::parse(url) {
+            Ok(domain) => match granted.get_mut(&domain) {
+                Some(left) if *left > 0 => {
+                    *left -= 1;
+                    true
+                }
+                _ => false,
+            },
+            Err(_) => true,
+        })
+    }
+
+    /// Reserve up to `count` requests for `domain` today and return how many were granted
+    async fn reserve(&self, domain: &MerchantDomain, count: u32) -> u32 {
+        let limit = self.limit(domain);
+        let today = self.clock.now().date_naive();
+        if let Some(client) = self.store.redis() {
+            match reserve_shared(client, domain, today, count, limit) {
+                Ok(granted) => return granted,
+                Err(e) => tracing::warn!(error = %e, "Shared scrape budgets unavailable, budgeting locally"),
+            }
+        }
+
+        let mut usage = self.usage.lock().await;
+        let entry = usage.entry(domain.clone()).or_default();
+        if entry.day != today {
+            *entry = DailyUsage { day: today, used: 0 };
+        }
+        let granted = count.min(limit.saturating_sub(entry.used));
+        entry.used += granted;
+        // Yesterday's counters no longer matter
+        usage.retain(|_, u| u.day == today);
+        if !self.store.is_shared() {
+            self.store.persist(&usage).await;
+        }
+        granted
+    }
+
+    /// `domain`'s budget and usage today
+    pub async fn usage(&self, domain: &MerchantDomain) -> BudgetUsage {
+        let limit = self.limit(domain);
+        let today = self.clock.now().date_naive();
+        let mut used = None;
+        if let Some(client) = self.store.redis() {
+            match used_shared(client, domain, today) {
+                Ok(shared) => used = Some(shared),
+                Err(e) => tracing::warn!(error = %e, "Shared scrape budgets unavailable, reading local usage"),
+            }
+        }
+        let used = match used {
+            Some(used) => used,
+            None => self
+                .usage
+                .lock()
+                .await
+                .get(domain)
+                .filter(|u| u.day == today)
+                .map_or(0, |u| u.used),
+        };
+
+        BudgetUsage {
+            day: today,
+            limit,
+            used,
+            remaining: limit.saturating_sub(used),
+            resets_at: self.resets_at(),
+        }
+    }
+}
+
+impl Default for ScrapeBudgets {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+/// The UTC midnight after `now`, when daily budgets reset
+pub fn next_reset(now: DateTime<Utc>) -> DateTime<Utc> {
+    let tomorrow = now.date_naive() + TimeDelta::days(1);
+    tomorrow.and_hms_opt(0, 0, 0).expect("midnight exists").and_utc()
+}
+
+fn redis_key(domain: &MerchantDomain, day: NaiveDate) -> String {
+    format!("{}:{}:{}", REDIS_PREFIX, domain, day)
+}
+
+fn reserve_shared(client: &redis::Client, domain: &MerchantDomain, day: NaiveDate, count: u32, limit: u32) -> redis::RedisResult<u32> {
+    let mut con = client.get_connection()?;
+    let key = redis_key(domain, day);
+    let used: u64 = redis::cmd("INCRBY").arg(&key).arg(count).query(&mut con)?;
+    redis::cmd("EXPIRE").arg(&key).arg(REDIS_TTL_SECS).query::<()>(&mut con)?;
+
+    // Give back what went over the limit; a concurrent worker's share is unaffected
+    let over = used.saturating_sub(u64::from(limit)).min(u64::from(count));
+    if over > 0 {
+        redis::cmd("DECRBY").arg(&key).arg(over).query::<()>(&mut con)?;
+    }
+    Ok(count - over as u32)
+}
+
+fn used_shared(client: &redis::Client, domain: &MerchantDomain, day: NaiveDate) -> redis::RedisResult<u32> {
+    let mut con = client.get_connection()?;
+    let used: Option<u32> = redis::cmd("GET").arg(redis_key(domain, day)).query(&mut con)?;
+    Ok(used.unwrap_or(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::coupon_engine::profiles::ProfileSettings;
+    use chrono::TimeZone;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_budgets_scale_by_size_and_reset_daily() {
+        let clock = Arc::new(MockClock::at(Utc.with_ymd_and_hms(2024, 5, 1, 22, 0, 0).unwrap()));
+        let profiles = Arc::new(DomainProfiles::new(None));
+        let big = MerchantDomain::parse("big.example.com").unwrap();
+        let tiny = MerchantDomain::parse("tiny.example.com").unwrap();
+        let settings = |size, budget| ProfileSettings {
+            size,
+            daily_request_budget: budget,
+            ..ProfileSettings::default()
+        };
+        profiles.put(big.clone(), settings(MerchantSize::Large, None)).await.unwrap();
+        profiles.put(tiny.clone(), settings(MerchantSize::Small, Some(2))).await.unwrap();
+
+        let path = std::env::temp_dir().join(format!("scrape_budget_{}.json", uuid::Uuid::new_v4()));
+        let budgets = ScrapeBudgets::new(Some(path.clone()))
+            .with_profiles(profiles)
+            .with_default_budget(10)
+            .with_clock(clock.clone());
+        assert_eq!(budgets.limit(&big), 50);
+        assert_eq!(budgets.limit(&MerchantDomain::parse("other.example.com").unwrap()), 10);
+
+        let urls: Vec<String> = ["a", "b", "c"].iter().map(|p| format!("https://www.tiny.example.com/{}", p)).collect();
+        let (allowed, deferred) = budgets.take(&urls).await;
+        assert_eq!((allowed, deferred), (urls[..2].to_vec(), urls[2..].to_vec()));
+
+        // Usage survives a restart, and the budget is back the next day
+        let restarted = ScrapeBudgets::new(Some(path.clone())).with_clock(clock.clone());
+        restarted.load().await.unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(restarted.usage(&tiny).await.used, 2);
+        assert_eq!(budgets.resets_at(), Utc.with_ymd_and_hms(2024, 5, 2, 0, 0, 0).unwrap());
+
+        clock.advance(Duration::from_secs(2 * 3600));
+        let usage = budgets.usage(&tiny).await;
+        assert_eq!((usage.used, usage.remaining), (0, 2));
+        assert_eq!(budgets.take(&urls[2..]).await.0, urls[2..].to_vec());
+    }
+}
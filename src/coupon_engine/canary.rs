@@ -0,0 +1,446 @@
+//! Canary scrapes for layout-change detection
+//!
+//! A merchant's domain profile may list a few reference pages (`canary_urls`). Once a
+//! day they are fetched and fingerprinted: how many elements each coupon selector
+//! matches, and a hash of the page's tag structure. The first fingerprint of a page is
+//! its baseline. When the selectors stop matching what they matched in the baseline,
+//! the merchant's layout has most likely changed: a "layout changed" alert is logged
+//! and, when `CANARY_ALERT_WEBHOOK_URL` is set, posted there, and the scrape job queue
+//! holds back the merchant's URLs instead of spending its budget on empty pages.
+//!
+//! Once the parser is fixed (e.g. new profile selectors), `POST
+//! /admin/canaries/:domain/accept` takes the latest fingerprints as the new baselines
+//! and clears the alert. Baselines and results are shared through Redis when
+//! `REDIS_URL` is set, so every worker holds back the same merchants; otherwise they
+//! are persisted to `CANARY_BASELINES_PATH` (default `data/canary_baselines.json`).
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use scraper::{ElementRef, Html, Selector};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use tokio::sync::{Mutex, MutexGuard};
+use utoipa::ToSchema;
+
+use crate::clock::{self, Clock};
+use crate::coupon_engine::opt_out::{BlockedAt, OptOutRegistry};
+use crate::coupon_engine::profiles::DomainProfiles;
+use crate::coupon_engine::scraper::Fetcher;
+use crate::models::domain::MerchantDomain;
+use crate::storage::persisted::{PersistedStore, StoreError};
+
+/// How often each merchant's canary pages are checked
+pub const CANARY_INTERVAL: TimeDelta = TimeDelta::days(1);
+/// Reference pages a profile may list
+pub const MAX_CANARY_URLS: usize = 5;
+/// Selectors of the built-in HTML parsers, used for merchants without profile selectors
+const BUILTIN_SELECTORS: &[&str] = &[
+    "[class*='coupon-code']",
+    "[data-coupon-code]",
+    ".promo-code, .discount-code",
+    "[data-clipboard-text]",
+    ".coupon-item",
+];
+/// Depth of the tag structure that goes into the shape hash
+const SHAPE_DEPTH: usize = 8;
+/// Elements whose contents say nothing about the layout
+const OPAQUE_TAGS: &[&str] = &["script", "style", "noscript", "svg", "template"];
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+const REDIS_KEY: &str = "scrape_canaries";
+const STORE_NAME: &str = "scrape canaries";
+
+/// The structural fingerprint of one page
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct PageFingerprint {
+    /// Elements matched per selector
+    pub selector_hits: BTreeMap<String, u32>,
+    pub shape_hash: String,
+    pub taken_at: DateTime<Utc>,
+}
+
+impl PageFingerprint {
+    fn total_hits(&self) -> u32 {
+        self.selector_hits.values().sum()
+    }
+}
+
+/// One canary page compared against its baseline
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PageCheck {
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<PageFingerprint>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The tag structure differs from the baseline
+    pub shape_changed: bool,
+    /// Selectors that matched in the baseline match nothing now
+    pub selectors_lost: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CanaryResult {
+    pub domain: MerchantDomain,
+    pub checked_at: DateTime<Utc>,
+    pub pages: Vec<PageCheck>,
+    pub layout_changed: bool,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct CanaryState {
+    /// Per canary URL
+    baselines: HashMap<String, PageFingerprint>,
+    results: BTreeMap<MerchantDomain, CanaryResult>,
+}
+
+pub struct CanaryMonitor {
+    fetcher: Arc<dyn Fetcher>,
+    profiles: Arc<DomainProfiles>,
+    state: Mutex<CanaryState>,
+    store: PersistedStore<CanaryState>,
+    alert_webhook: Option<String>,
+    opt_outs: Option<Arc<OptOutRegistry>>,
+    client: reqwest::Client,
+    clock: Arc<dyn Clock>,
+}
+
+impl CanaryMonitor {
+    /// Canaries for the merchants in `profiles`, persisted to `path` or kept in memory only
+    pub fn new(fetcher: Arc<dyn Fetcher>, profiles: Arc<DomainProfiles>, path: Option<PathBuf>) -> Self {
+        Self::with_store(fetcher, profiles, PersistedStore::new(STORE_NAME, REDIS_KEY, path))
+    }
+
+    /// Canaries shared through Redis
+    pub fn shared(
+        fetcher: Arc<dyn Fetcher>,
+        profiles: Arc<DomainProfiles>,
+        redis_url: &str,
+    ) -> Result<Self, StoreError> {
+        Ok(Self::with_store(fetcher, profiles, PersistedStore::shared(STORE_NAME, REDIS_KEY, redis_url)?))
+    }
+
+    fn with_store(fetcher: Arc<dyn Fetcher>, profiles: Arc<DomainProfiles>, store: PersistedStore<CanaryState>) -> Self {
+        Self {
+            fetcher,
+            profiles,
+            state: Mutex::new(CanaryState::default()),
+            store,
+            alert_webhook: None,
+            opt_outs: None,
+            client: reqwest::Client::builder()
+                .timeout(WEBHOOK_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+            clock: clock::system(),
+        }
+    }
+
+    pub fn with_alert_webhook(mut self, url: String) -> Self {
+        self.alert_webhook = Some(url);
+        self
+    }
+
+    /// Skip the canary pages of merchants that opted out of scraping
+    pub fn with_opt_outs(mut self, opt_outs: Arc<OptOutRegistry>) -> Self {
+        self.opt_outs = Some(opt_outs);
+        self
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Share canaries through `REDIS_URL` when set, otherwise load them from
+    /// `CANARY_BASELINES_PATH`; alerts go to `CANARY_ALERT_WEBHOOK_URL`
+    pub async fn from_env(fetcher: Arc<dyn Fetcher>, profiles: Arc<DomainProfiles>) -> Self {
+        let store = PersistedStore::from_env(STORE_NAME, REDIS_KEY, "CANARY_BASELINES_PATH", "data/canary_baselines.json");
+        let mut monitor = Self::with_store(fetcher, profiles, store);
+        if let Ok(url) = std::env::var("CANARY_ALERT_WEBHOOK_URL") {
+            monitor = monitor.with_alert_webhook(url);
+        }
+        if let Err(e) = monitor.load().await {
+            tracing::warn!(error = %e, "Starting without canary baselines");
+        }
+        monitor
+    }
+
+    async fn load(&self) -> Result<(), StoreError> {
+        if let Some(loaded) = self.store.load().await? {
+            *self.state.lock().await = loaded;
+        }
+        Ok(())
+    }
+
+    /// The state, re-read from Redis for a shared monitor
+    async fn lock(&self) -> MutexGuard<'_, CanaryState> {
+        let mut state = self.state.lock().await;
+        if self.store.is_shared() {
+            match self.store.load().await {
+                Ok(Some(loaded)) => *state = loaded,
+                Ok(None) => {}
+                Err(e) => tracing::warn!(error = %e, "Shared scrape canaries unavailable, reading the local copy"),
+            }
+        }
+        state
+    }
+
+    /// Check every merchant whose canaries have not run within [`CANARY_INTERVAL`]
+    pub async fn run_due(&self) {
+        let now = self.clock.now();
+        for profile in self.profiles.list() {
+            if profile.settings.canary_urls.is_empty() {
+                continue;
+            }
+            let due = self
+                .lock()
+                .await
+                .results
+                .get(&profile.domain)
+                .is_none_or(|result| now - result.checked_at >= CANARY_INTERVAL);
+            if due {
+                self.check(&profile.domain).await;
+            }
+        }
+    }
+
+    /// Fetch `domain`'s canary pages now and compare them against their baselines
+    pub async fn check(&self, domain: &MerchantDomain) -> Option<CanaryResult> {
+        let settings = self.profiles.get(domain)?.settings;
+        let selectors = match settings.selectors.is_empty() {
+            true => BUILTIN_SELECTORS.iter().map(|s| s.to_string()).collect(),
+            false => settings.selectors.clone(),
+        };
+
+        let mut fingerprints = Vec::new();
+        for url in &settings.canary_urls {
+            if self.opt_outs.as_ref().is_some_and(|opt_outs| opt_outs.blocks(url, BlockedAt::Canary)) {
+                continue;
+            }
+            let fingerprint = match self.fetcher.fetch(url, None).await {
+                Ok(page) => Ok(fingerprint(&page, &selectors, self.clock.now())),
+                Err(e) => Err(e.to_string()),
+            };
+            fingerprints.push((url.clone(), fingerprint));
+        }
+
+        let mut state = self.lock().await;
+        let pages: Vec<PageCheck> = fingerprints
+            .into_iter()
+            .map(|(url, fingerprint)| match fingerprint {
+                Ok(fingerprint) => {
+                    let baseline = state.baselines.entry(url.clone()).or_insert_with(|| fingerprint.clone());
+                    compare(url, baseline, fingerprint)
+                }
+                Err(error) => PageCheck {
+                    url,
+                    fingerprint: None,
+                    error: Some(error),
+                    shape_changed: false,
+                    selectors_lost: Vec::new(),
+                },
+            })
+            .collect();
+
+        let result = CanaryResult {
+            domain: domain.clone(),
+            checked_at: self.clock.now(),
+            layout_changed: pages.iter().any(|page| !page.selectors_lost.is_empty()),
+            pages,
+        };
+        let was_changed = state.results.get(domain).is_some_and(|previous| previous.layout_changed);
+        state.results.insert(domain.clone(), result.clone());
+        self.store.persist(&state).await;
+        drop(state);
+
+        if result.layout_changed && !was_changed {
+            self.alert(&result);
+        }
+        Some(result)
+    }
+
+    fn alert(&self, result: &CanaryResult) {
+        let lost: HashSet<&str> = result.pages.iter().flat_map(|p| p.selectors_lost.iter().map(String::as_str)).collect();
+        tracing::warn!(
+            merchant = %result.domain,
+            lost = %lost.into_iter().collect::<Vec<_>>().join(", "),
+            "Layout changed: canary pages no longer match; holding back the merchant's scrapes"
+        );
+
+        if let Some(url) = &self.alert_webhook {
+            let request = self.client.post(url).json(&json!({"event": "layout_changed", "canary": result}));
+            tokio::spawn(async move {
+                if let Err(e) = request.send().await.and_then(|r| r.error_for_status()) {
+                    tracing::warn!(error = %e, "Failed to deliver a canary alert");
+                }
+            });
+        }
+    }
+
+    /// Take `domain`'s latest fingerprints as its baselines and clear its alert;
+    /// false when its canaries have not run
+    pub async fn accept(&self, domain: &MerchantDomain) -> bool {
+        let mut state = self.lock().await;
+        let Some(result) = state.results.get_mut(domain) else {
+            return false;
+        };
+
+        let accepted: Vec<(String, PageFingerprint)> = result
+            .pages
+            .iter_mut()
+            .filter_map(|page| {
+                page.shape_changed = false;
+                page.selectors_lost.clear();
+                Some((page.url.clone(), page.fingerprint.clone()?))
+            })
+            .collect();
+        result.layout_changed = false;
+        state.baselines.extend(accepted);
+        self.store.persist(&state).await;
+        true
+    }
+
+    /// The latest canary result of every merchant
+    pub async fn report(&self) -> Vec<CanaryResult> {
+        self.lock().await.results.values().cloned().collect()
+    }
+
+    /// Merchants whose layout changed and has not been accepted yet
+    pub async fn changed_layouts(&self) -> HashSet<MerchantDomain> {
+        self.lock()
+            .await
+            .results
+            .values()
+            .filter(|result| result.layout_changed)
+            .map(|result| result.domain.clone())
+            .collect()
+    }
+}
+
+fn compare(url: String, baseline: &PageFingerprint, fingerprint: PageFingerprint) -> PageCheck {
+    let selectors_lost = match fingerprint.total_hits() {
+        // Some selectors going quiet is normal (fewer coupons today); all of them is not
+        0 => baseline
+            .selector_hits
+            .iter()
+            .filter(|(_, hits)| **hits > 0)
+            .map(|(selector, _)| selector.clone())
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    PageCheck {
+        url,
+        shape_changed: fingerprint.shape_hash != baseline.shape_hash,
+        selectors_lost,
+        fingerprint: Some(fingerprint),
+        error: None,
+    }
+}
+
+/// Selector hit counts and the shape hash of `page`
+pub fn fingerprint(page: &str, selectors: &[String], taken_at: DateTime<Utc>) -> PageFingerprint {
+    let document = Html::parse_document(page);
+    let selector_hits = selectors
+        .iter()
+        .filter_map(|source| {
+            let selector = Selector::parse(source).ok()?;
+            Some((source.clone(), document.select(&selector).count() as u32))
+        })
+        .collect();
+
+    let mut shape = String::new();
+    write_shape(document.root_element(), 0, &mut shape);
+    let digest = Sha256::digest(shape.as_bytes());
+
+    PageFingerprint {
+        selector_hits,
+        shape_hash: digest.iter().take(8).map(|b| format!("{:02x}", b)).collect(),
+        taken_at,
+    }
+}
+
+/// Tag names down to [`SHAPE_DEPTH`]. Runs of same-tag siblings count once, so a list
+/// with more items today has the same shape.
+fn write_shape(element: ElementRef, depth: usize, out: &mut String) {
+    let name = element.value().name();
+    out.push_str(name);
+    if depth >= SHAPE_DEPTH || OPAQUE_TAGS.contains(&name) {
+        return;
+    }
+
+    out.push('(');
+    let mut previous: Option<&str> = None;
+    for child in element.children().filter_map(ElementRef::wrap) {
+        let child_name = child.value().name();
+        if previous == Some(child_name) {
+            continue;
+        }
+        previous = Some(child_name);
+        write_shape(child, depth + 1, out);
+        out.push(',');
+    }
+    out.push(')');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::coupon_engine::profiles::ProfileSettings;
+    use crate::coupon_engine::proxy_manager::ProxyConfig;
+    use axum::async_trait;
+
+    struct Site(std::sync::Mutex<&'static str>);
+
+    #[async_trait]
+    impl Fetcher for Site {
+        async fn fetch(&self, _url: &str, _proxy: Option<&ProxyConfig>) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+            Ok(self.0.lock().unwrap().to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_detects_layout_change_until_accepted() {
+        let old = "<html><body><ul><li class='promo-code'>SAVE10</li><li class='promo-code'>TAKE5</li></ul></body></html>";
+        let fewer = "<html><body><ul><li class='promo-code'>SAVE10</li></ul></body></html>";
+        let redesigned = "<html><body><div><div><span class='voucher'>SAVE10</span></div></div></body></html>";
+        let site = Arc::new(Site(std::sync::Mutex::new(old)));
+        let clock = Arc::new(MockClock::new());
+
+        let profiles = Arc::new(DomainProfiles::new(None));
+        let shop = MerchantDomain::parse("shop.example.com").unwrap();
+        let settings = ProfileSettings {
+            canary_urls: vec!["https://shop.example.com/coupons".to_string()],
+            ..ProfileSettings::default()
+        };
+        profiles.put(shop.clone(), settings).await.unwrap();
+        let canaries = CanaryMonitor::new(site.clone(), profiles, None).with_clock(clock.clone());
+
+        canaries.run_due().await;
+        assert!(!canaries.report().await[0].layout_changed);
+
+        // Fewer coupons is not a layout change, nor is running again within the day
+        *site.0.lock().unwrap() = fewer;
+        canaries.run_due().await;
+        clock.advance(Duration::from_secs(24 * 3600));
+        canaries.run_due().await;
+        let report = canaries.report().await;
+        assert!(!report[0].layout_changed && !report[0].pages[0].shape_changed);
+
+        *site.0.lock().unwrap() = redesigned;
+        let result = canaries.check(&shop).await.unwrap();
+        assert!(result.layout_changed && result.pages[0].shape_changed);
+        assert_eq!(result.pages[0].selectors_lost, vec![".promo-code, .discount-code"]);
+        assert!(canaries.changed_layouts().await.contains(&shop));
+
+        assert!(canaries.accept(&shop).await);
+        assert!(canaries.changed_layouts().await.is_empty());
+        assert!(!canaries.check(&shop).await.unwrap().pages[0].shape_changed);
+    }
+}
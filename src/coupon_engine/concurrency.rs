@@ -0,0 +1,271 @@
+//! Adaptive fetch concurrency
+//!
+//! How many pages the engine should fetch at once depends on the sites being
+//! scraped and on the proxies in front of them, so a fixed number is either too
+//! timid for fast sites or overloads slow ones. [`AdaptiveLimit`] adjusts the limit
+//! with a gradient algorithm, after Netflix's concurrency-limits (Gradient2):
+//!
+//! - A long-term average of fetch latency is the baseline a healthy pipeline sees,
+//!   and a short-term average is what it sees now.
+//! - While the short-term latency stays within [`TOLERANCE`] of the baseline, the
+//!   limit grows by about its square root per sample. As latency rises past that,
+//!   the limit shrinks in proportion, down to half per sample.
+//! - A failed fetch cuts the limit by [`BACKOFF`], at most once per short-term
+//!   latency, so one dead site failing a burst of URLs costs one cut.
+//! - The limit only grows while at least half of it is in use; otherwise it is not
+//!   what holds throughput back.
+//!
+//! The limit stays between [`MIN_LIMIT`] and the engine's `max_concurrent_requests`,
+//! starting at [`INITIAL_LIMIT`]. Both are capped at the maximum, which an operator
+//! can change while the engine runs (see [`AdaptiveLimit::set_max_limit`]).
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tokio::sync::Notify;
+use utoipa::ToSchema;
+
+pub const INITIAL_LIMIT: usize = 20;
+pub const MIN_LIMIT: usize = 4;
+/// Short-term latency may exceed the baseline by this factor before the limit shrinks
+pub const TOLERANCE: f64 = 1.5;
+/// Limit kept after a failed fetch
+pub const BACKOFF: f64 = 0.9;
+/// Samples the long-term average spans
+const LONG_WINDOW: f64 = 600.0;
+/// Samples the short-term average spans
+const SHORT_WINDOW: f64 = 10.0;
+/// Weight of each new limit estimate
+const SMOOTHING: f64 = 0.2;
+
+/// Current state of an [`AdaptiveLimit`]
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ConcurrencySnapshot {
+    pub limit: usize,
+    pub in_flight: usize,
+    pub min_limit: usize,
+    pub max_limit: usize,
+    /// Long-term average fetch latency
+    pub baseline_ms: Option<f64>,
+    /// Short-term average fetch latency
+    pub recent_ms: Option<f64>,
+}
+
+#[derive(Debug)]
+struct State {
+    limit: f64,
+    in_flight: usize,
+    long_rtt: Option<f64>,
+    short_rtt: Option<f64>,
+    samples: u64,
+    last_backoff: Option<Instant>,
+    min_limit: usize,
+    max_limit: usize,
+}
+
+pub struct AdaptiveLimit {
+    state: Mutex<State>,
+    released: Notify,
+}
+
+impl AdaptiveLimit {
+    /// Adapt between [`MIN_LIMIT`] and `max_limit`, starting at [`INITIAL_LIMIT`]
+    pub fn new(max_limit: usize) -> Self {
+        let max_limit = max_limit.max(1);
+        Self {
+            state: Mutex::new(State {
+                limit: INITIAL_LIMIT.min(max_limit) as f64,
+                in_flight: 0,
+                long_rtt: None,
+                short_rtt: None,
+                samples: 0,
+                last_backoff: None,
+                min_limit: MIN_LIMIT.min(max_limit),
+                max_limit,
+            }),
+            released: Notify::new(),
+        }
+    }
+
+    /// Adapt up to `max_limit` from now on; a limit above it drops to it at once,
+    /// while fetches already in flight finish
+    pub fn set_max_limit(&self, max_limit: usize) {
+        let max_limit = max_limit.max(1);
+        let mut state = self.state.lock().unwrap();
+        state.max_limit = max_limit;
+        state.min_limit = MIN_LIMIT.min(max_limit);
+        state.limit = state.limit.clamp(state.min_limit as f64, max_limit as f64);
+    }
+
+    /// Wait until fewer fetches than the limit are in flight
+    pub async fn acquire(self: &Arc<Self>) -> FetchPermit {
+        loop {
+            let released = self.released.notified();
+            tokio::pin!(released);
+            // Registered before checking, so a release in between is not missed
+            released.as_mut().enable();
+            {
+                let mut state = self.state.lock().unwrap();
+                if state.in_flight < state.limit as usize {
+                    state.in_flight += 1;
+                    return FetchPermit { limit: self.clone() };
+                }
+            }
+            released.await;
+        }
+    }
+
+    pub fn snapshot(&self) -> ConcurrencySnapshot {
+        let state = self.state.lock().unwrap();
+        ConcurrencySnapshot {
+            limit: state.limit as usize,
+            in_flight: state.in_flight,
+            min_limit: state.min_limit,
+            max_limit: state.max_limit,
+            baseline_ms: state.long_rtt.map(|rtt| (rtt * 1000.0).round() / 1000.0),
+            recent_ms: state.short_rtt.map(|rtt| (rtt * 1000.0).round() / 1000.0),
+        }
+    }
+
+    fn record(&self, latency: Duration, ok: bool, now: Instant) {
+        let grew = {
+            let mut state = self.state.lock().unwrap();
+            let before = state.limit as usize;
+            self.update(&mut state, latency.as_secs_f64() * 1000.0, ok, now);
+            state.limit as usize > before
+        };
+        if grew {
+            self.released.notify_waiters();
+        }
+    }
+
+    fn update(&self, state: &mut State, rtt: f64, ok: bool, now: Instant) {
+        let short = ema(state.short_rtt, rtt, SHORT_WINDOW, state.samples);
+        let mut long = ema(state.long_rtt, rtt, LONG_WINDOW, state.samples);
+        state.samples += 1;
+        // The baseline has drifted above what the pipeline now sees, e.g. after a
+        // slow site stopped being scraped; let it come down quickly
+        if long > short * 2.0 {
+            long *= 0.95;
+        }
+        state.short_rtt = Some(short);
+        state.long_rtt = Some(long);
+
+        if !ok {
+            let due = state
+                .last_backoff
+                .is_none_or(|at| now.duration_since(at).as_secs_f64() * 1000.0 >= short);
+            if due {
+                state.last_backoff = Some(now);
+                state.limit = (state.limit * BACKOFF).max(state.min_limit as f64);
+            }
+            return;
+        }
+
+        // Not using the limit, so latency says nothing about raising it
+        if (state.in_flight as f64) < state.limit / 2.0 {
+            return;
+        }
+        let gradient = (TOLERANCE * long / short).clamp(0.5, 1.0);
+        let estimate = state.limit * gradient + state.limit.sqrt();
+        state.limit = (state.limit * (1.0 - SMOOTHING) + estimate * SMOOTHING).clamp(state.min_limit as f64, state.max_limit as f64);
+    }
+
+    fn release(&self) {
+        self.state.lock().unwrap().in_flight -= 1;
+        self.released.notify_one();
+    }
+}
+
+/// A fetch slot; released when dropped
+pub struct FetchPermit {
+    limit: Arc<AdaptiveLimit>,
+}
+
+impl FetchPermit {
+    /// Report how long the fetch took and whether it succeeded
+    pub fn record(&self, latency: Duration, ok: bool) {
+        self.limit.record(latency, ok, Instant::now());
+    }
+}
+
+impl Drop for FetchPermit {
+    fn drop(&mut self) {
+        self.limit.release();
+    }
+}
+
+/// Exponential moving average over about `window` samples; a plain mean until the
+/// window has filled once
+fn ema(average: Option<f64>, sample: f64, window: f64, samples: u64) -> f64 {
+    match average {
+        None => sample,
+        Some(average) => {
+            let weight = (2.0 / (window + 1.0)).max(1.0 / (samples + 1) as f64);
+            average + (sample - average) * weight
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn busy(limit: &AdaptiveLimit) {
+        let mut state = limit.state.lock().unwrap();
+        state.in_flight = state.limit as usize;
+    }
+
+    #[tokio::test]
+    async fn test_limit_grows_while_healthy_and_backs_off_on_latency_and_errors() {
+        let limit = Arc::new(AdaptiveLimit::new(100));
+        let now = Instant::now();
+        assert_eq!(limit.snapshot().limit, INITIAL_LIMIT);
+
+        // Idle capacity: healthy latency does not raise the limit
+        limit.record(Duration::from_millis(100), true, now);
+        assert_eq!(limit.snapshot().limit, INITIAL_LIMIT);
+
+        for _ in 0..200 {
+            busy(&limit);
+            limit.record(Duration::from_millis(100), true, now);
+        }
+        assert_eq!(limit.snapshot().limit, 100);
+
+        // Latency well past the baseline shrinks it
+        for _ in 0..20 {
+            busy(&limit);
+            limit.record(Duration::from_millis(600), true, now);
+        }
+        let slowed = limit.snapshot().limit;
+        assert!(slowed < 50, "limit {}", slowed);
+
+        // A burst of failures costs one cut per short-term latency
+        for _ in 0..10 {
+            limit.record(Duration::from_millis(600), false, now);
+        }
+        let cut = limit.snapshot().limit;
+        assert!(cut < slowed && cut as f64 >= (slowed as f64 * BACKOFF).floor() - 1.0, "{} -> {}", slowed, cut);
+        for i in 0..100 {
+            limit.record(Duration::from_millis(600), false, now + Duration::from_secs(i));
+        }
+        assert_eq!(limit.snapshot().limit, MIN_LIMIT);
+
+        // Permits respect the limit and wake waiters as they are released
+        limit.state.lock().unwrap().in_flight = 0;
+        let permits: Vec<FetchPermit> = futures_util::future::join_all((0..MIN_LIMIT).map(|_| limit.acquire())).await;
+        assert_eq!(limit.snapshot().in_flight, MIN_LIMIT);
+        let waiting = tokio::spawn({
+            let limit = limit.clone();
+            async move { limit.acquire().await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!waiting.is_finished());
+        drop(permits);
+        let permit = waiting.await.unwrap();
+        assert_eq!(limit.snapshot().in_flight, 1);
+        drop(permit);
+        assert_eq!(limit.snapshot().in_flight, 0);
+    }
+}
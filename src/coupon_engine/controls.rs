@@ -0,0 +1,288 @@
+//! Operator controls of the scraping engine
+//!
+//! `/admin/scraper` pauses and resumes merchants and retunes the engine while it
+//! runs, without a deploy:
+//!
+//! - A paused merchant's URLs wait in the job queue, re-checked every
+//!   [`PAUSE_RECHECK`], until it is resumed (see [`crate::jobs`]). Fetches already
+//!   in flight finish.
+//! - [`EngineSettings`] override the [`EngineConfig`] fields the engine can change
+//!   between batches: the fetch concurrency ceiling, the retry attempts and the
+//!   memory cap. The others are fixed when the engine is built.
+//!
+//! With `REDIS_URL` set the controls are shared, so API instances steer the
+//! workers, and re-read every [`REFRESH_INTERVAL`]; otherwise they are persisted
+//! to `SCRAPER_CONTROLS_PATH` (default `data/scraper_controls.json`).
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::EngineConfig;
+use crate::clock::{self, Clock};
+use crate::models::domain::MerchantDomain;
+use crate::storage::persisted::{PersistedStore, StoreError};
+
+const REDIS_KEY: &str = "scraper_controls";
+const STORE_NAME: &str = "scraper controls";
+/// How often shared controls are re-read
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+/// How long a paused merchant's URLs wait before the queue checks again
+pub const PAUSE_RECHECK: Duration = Duration::from_secs(60);
+/// Largest `max_concurrent_requests` an operator may set
+pub const MAX_CONCURRENCY: usize = 1000;
+pub const MAX_RETRY_ATTEMPTS: u32 = 10;
+/// Smallest `memory_cap_bytes` an operator may set
+pub const MIN_MEMORY_CAP_BYTES: usize = 16 * 1024 * 1024;
+
+/// Overrides of the engine's [`EngineConfig`]; unset fields keep its value
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct EngineSettings {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_concurrent_requests: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_attempts: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_cap_bytes: Option<usize>,
+}
+
+impl EngineSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_concurrent_requests.is_some_and(|limit| !(1..=MAX_CONCURRENCY).contains(&limit)) {
+            return Err(format!("max_concurrent_requests must be between 1 and {}", MAX_CONCURRENCY));
+        }
+        if self.retry_attempts.is_some_and(|attempts| !(1..=MAX_RETRY_ATTEMPTS).contains(&attempts)) {
+            return Err(format!("retry_attempts must be between 1 and {}", MAX_RETRY_ATTEMPTS));
+        }
+        if self.memory_cap_bytes.is_some_and(|cap| cap < MIN_MEMORY_CAP_BYTES) {
+            return Err(format!("memory_cap_bytes must be at least {}", MIN_MEMORY_CAP_BYTES));
+        }
+        Ok(())
+    }
+
+    /// `config` with these overrides
+    pub fn apply(&self, config: &EngineConfig) -> EngineConfig {
+        EngineConfig {
+            max_concurrent_requests: self.max_concurrent_requests.unwrap_or(config.max_concurrent_requests),
+            retry_attempts: self.retry_attempts.unwrap_or(config.retry_attempts),
+            memory_cap_bytes: self.memory_cap_bytes.unwrap_or(config.memory_cap_bytes),
+            ..config.clone()
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Pause {
+    pub domain: MerchantDomain,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    pub paused_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, ToSchema)]
+pub struct PauseRequest {
+    /// Why, for the other operators, e.g. `merchant is migrating its storefront`
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct ControlState {
+    #[serde(default)]
+    paused: BTreeMap<MerchantDomain, Pause>,
+    #[serde(default)]
+    settings: EngineSettings,
+}
+
+pub struct ScraperControls {
+    state: RwLock<ControlState>,
+    store: PersistedStore<ControlState>,
+    clock: Arc<dyn Clock>,
+}
+
+impl ScraperControls {
+    /// Controls persisted to `path`, or kept in memory only
+    pub fn new(path: Option<PathBuf>) -> Self {
+        Self::with_store(PersistedStore::new(STORE_NAME, REDIS_KEY, path))
+    }
+
+    /// Controls shared through Redis
+    pub fn shared(redis_url: &str) -> Result<Self, StoreError> {
+        Ok(Self::with_store(PersistedStore::shared(STORE_NAME, REDIS_KEY, redis_url)?))
+    }
+
+    fn with_store(store: PersistedStore<ControlState>) -> Self {
+        Self {
+            state: RwLock::new(ControlState::default()),
+            store: store.pretty(),
+            clock: clock::system(),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Share controls through `REDIS_URL` when set, otherwise load them from
+    /// `SCRAPER_CONTROLS_PATH` (default `data/scraper_controls.json`)
+    pub async fn from_env() -> Self {
+        let controls = Self::with_store(PersistedStore::from_env(STORE_NAME, REDIS_KEY, "SCRAPER_CONTROLS_PATH", "data/scraper_controls.json"));
+        if let Err(e) = controls.reload().await {
+            tracing::warn!(error = %e, "Starting with no scraper controls");
+        }
+        controls
+    }
+
+    /// Replace the local copy with the stored controls
+    pub async fn reload(&self) -> Result<(), StoreError> {
+        if let Some(stored) = self.store.load().await? {
+            *self.state.write().unwrap() = stored;
+        }
+        Ok(())
+    }
+
+    /// Re-read shared controls every [`REFRESH_INTERVAL`], picking up changes made
+    /// through other instances
+    pub async fn start_background_tasks(self: Arc<Self>) {
+        self.store.refresh_every(self.clock.as_ref(), REFRESH_INTERVAL, || self.reload()).await
+    }
+
+    /// Apply `change` to the stored controls and keep the result; `change` returns
+    /// false to leave them as they were
+    async fn update<T>(&self, change: impl FnOnce(&mut ControlState) -> (bool, T)) -> Result<T, String> {
+        let _write = self.store.write_lock().await;
+        // Another instance may have changed shared controls since the last refresh
+        let mut state = match self.store.load().await {
+            Ok(Some(stored)) => stored,
+            Ok(None) => self.state.read().unwrap().clone(),
+            Err(e) => return Err(format!("failed to read the scraper controls: {}", e)),
+        };
+        let (changed, result) = change(&mut state);
+        if changed {
+            self.store
+                .save(&state)
+                .await
+                .map_err(|e| format!("failed to store the scraper controls: {}", e))?;
+        }
+        *self.state.write().unwrap() = state;
+        Ok(result)
+    }
+
+    /// Paused merchants, by domain
+    pub fn paused(&self) -> Vec<Pause> {
+        self.state.read().unwrap().paused.values().cloned().collect()
+    }
+
+    /// Whether `url` belongs to a paused merchant
+    pub fn is_paused(&self, url: &str) -> bool {
+        MerchantDomain::parse(url).is_ok_and(|domain| self.state.read().unwrap().paused.contains_key(&domain))
+    }
+
+    /// Stop scraping `domain` until it is resumed; pausing it again keeps the
+    /// original pause
+    pub async fn pause(&self, domain: MerchantDomain, request: PauseRequest) -> Result<Pause, String> {
+        let pause = Pause {
+            domain: domain.clone(),
+            reason: request.reason.map(|reason| reason.trim().to_string()).filter(|reason| !reason.is_empty()),
+            paused_at: self.clock.now(),
+        };
+        let pause = self
+            .update(|state| match state.paused.get(&domain) {
+                Some(existing) => (false, existing.clone()),
+                None => {
+                    state.paused.insert(domain.clone(), pause.clone());
+                    (true, pause)
+                }
+            })
+            .await?;
+        tracing::info!(merchant = %domain, "Scraping paused");
+        Ok(pause)
+    }
+
+    /// Scrape `domain` again; false when it was not paused
+    pub async fn resume(&self, domain: &MerchantDomain) -> Result<bool, String> {
+        let resumed = self
+            .update(|state| {
+                let resumed = state.paused.remove(domain).is_some();
+                (resumed, resumed)
+            })
+            .await?;
+        if resumed {
+            tracing::info!(merchant = %domain, "Scraping resumed");
+        }
+        Ok(resumed)
+    }
+
+    pub fn settings(&self) -> EngineSettings {
+        self.state.read().unwrap().settings.clone()
+    }
+
+    /// Replace the engine overrides; engines apply them from their next batch
+    pub async fn set_settings(&self, settings: EngineSettings) -> Result<EngineSettings, String> {
+        settings.validate()?;
+        self.update(|state| {
+            let changed = state.settings != settings;
+            state.settings = settings.clone();
+            (changed, settings)
+        })
+        .await
+    }
+}
+
+impl Default for ScraperControls {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_pauses_and_overrides_persist() {
+        let path = std::env::temp_dir().join(format!("scraper_controls_{}.json", uuid::Uuid::new_v4()));
+        let controls = ScraperControls::new(Some(path.clone()));
+        let shop = MerchantDomain::parse("shop.example.com").unwrap();
+
+        let pause = controls
+            .pause(shop.clone(), PauseRequest { reason: Some(" storefront migration ".to_string()) })
+            .await
+            .unwrap();
+        assert_eq!(pause.reason.as_deref(), Some("storefront migration"));
+        assert!(controls.is_paused("https://shop.example.com/coupons"));
+        assert!(!controls.is_paused("https://other.example.com/coupons"));
+        // Pausing again keeps the first pause
+        assert_eq!(controls.pause(shop.clone(), PauseRequest::default()).await.unwrap(), pause);
+
+        let invalid = EngineSettings {
+            retry_attempts: Some(0),
+            ..Default::default()
+        };
+        assert!(controls.set_settings(invalid).await.is_err());
+        let settings = EngineSettings {
+            max_concurrent_requests: Some(8),
+            ..Default::default()
+        };
+        controls.set_settings(settings.clone()).await.unwrap();
+        let config = controls.settings().apply(&EngineConfig::default());
+        assert_eq!(config.max_concurrent_requests, 8);
+        assert_eq!(config.retry_attempts, EngineConfig::default().retry_attempts);
+
+        let restarted = ScraperControls::new(Some(path.clone()));
+        restarted.reload().await.unwrap();
+        assert_eq!(restarted.paused(), vec![pause]);
+        assert_eq!(restarted.settings(), settings);
+
+        assert!(restarted.resume(&shop).await.unwrap());
+        assert!(!restarted.resume(&shop).await.unwrap());
+        assert!(!restarted.is_paused("https://shop.example.com/coupons"));
+        let _ = std::fs::remove_file(path);
+    }
+}
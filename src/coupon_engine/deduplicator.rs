@@ -1,9 +1,17 @@
 //! Efficient coupon deduplication using multiple strategies
 
+use axum::async_trait;
 use crate::coupon_engine::RawCoupon;
+use crate::models::domain::{CouponCode, MerchantDomain};
 use std::collections::{HashMap, HashSet};
 use sha2::{Sha256, Digest};
 
+/// Collapses coupons that describe the same offer
+#[async_trait]
+pub trait CouponDeduplicator: Send + Sync {
+    async fn deduplicate(&self, coupons: Vec<RawCoupon>) -> Result<Vec<RawCoupon>, Box<dyn std::error::Error + Send + Sync>>;
+}
+
 pub struct Deduplicator {
     strategy: DeduplicationStrategy,
 }
@@ -20,6 +28,12 @@ pub enum DeduplicationStrategy {
     Combined,
 }
 
+impl Default for Deduplicator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Deduplicator {
     pub fn new() -> Self {
         Self {
@@ -49,7 +63,7 @@ impl Deduplicator {
     }
 
     fn deduplicate_by_code_and_merchant(&self, coupons: Vec<RawCoupon>) -> Vec<RawCoupon> {
-        let mut seen: HashSet<(String, String)> = HashSet::new();
+        let mut seen: HashSet<(CouponCode, MerchantDomain)> = HashSet::new();
         let mut unique_coupons = Vec::new();
 
         for coupon in coupons {
@@ -97,17 +111,18 @@ impl Deduplicator {
         let coupons = self.deduplicate_by_code_and_merchant(coupons);
         
         // Second pass: fuzzy matching within same merchant
-        let mut merchant_groups: HashMap<String, Vec<RawCoupon>> = HashMap::new();
+        let mut merchant_groups: HashMap<MerchantDomain, Vec<RawCoupon>> = HashMap::new();
         for coupon in coupons {
             merchant_groups
                 .entry(coupon.merchant_domain.clone())
-                .or_insert_with(Vec::new)
+                .or_default()
                 .push(coupon);
         }
 
         let mut final_coupons = Vec::new();
         for (_, group) in merchant_groups {
-            let deduped_group = self.deduplicate_fuzzy(group, 0.85);
+            // Strict: codes one character apart with identical terms (SAVE10/SAVE20) score ~0.91
+            let deduped_group = self.deduplicate_fuzzy(group, 0.95);
             final_coupons.extend(deduped_group);
         }
 
@@ -119,9 +134,10 @@ impl Deduplicator {
         let mut hasher = Sha256::new();
         
         // Include key fields in hash
-        hasher.update(&coupon.code);
-        hasher.update(&coupon.merchant_domain);
-        hasher.update(&coupon.discount_type.to_string());
+        // Codes are case-insensitive, so hash them in one case
+        hasher.update(coupon.code.as_str().to_uppercase());
+        hasher.update(coupon.merchant_domain.as_str());
+        hasher.update(coupon.discount_type.as_str());
         
         if let Some(value) = coupon.discount_value {
             hasher.update(value.to_string());
@@ -135,7 +151,7 @@ impl Deduplicator {
         let mut weight_total = 0.0;
 
         // Code similarity (highest weight)
-        let code_similarity = self.levenshtein_similarity(&coupon1.code, &coupon2.code);
+        let code_similarity = self.levenshtein_similarity(coupon1.code.as_str(), coupon2.code.as_str());
         score += code_similarity * 0.4;
         weight_total += 0.4;
 
@@ -161,7 +177,7 @@ impl Deduplicator {
 
     fn levenshtein_similarity(&self, s1: &str, s2: &str) -> f64 {
         let distance = self.levenshtein_distance(s1, s2);
-        let max_len = s1.len().max(s2.len()) as f64;
+        let max_len = s1.chars().count().max(s2.chars().count()) as f64;
         
         if max_len == 0.0 {
             1.0
@@ -170,21 +186,22 @@ impl Deduplicator {
         }
     }
 
-    fn levenshtein_distance(&self, s1: &str, s2: &str) -> usize {
-        let len1 = s1.len();
-        let len2 = s2.len();
-        let mut matrix = vec![vec![0; len2 + 1]; len1 + 1];
+    /// Edit distance counted in characters, not bytes
+    pub fn levenshtein_distance(&self, s1: &str, s2: &str) -> usize {
+        let s1: Vec<char> = s1.chars().collect();
+        let s2: Vec<char> = s2.chars().collect();
+        let mut matrix = vec![vec![0; s2.len() + 1]; s1.len() + 1];
 
-        for i in 0..=len1 {
-            matrix[i][0] = i;
+        for (i, row) in matrix.iter_mut().enumerate() {
+            row[0] = i;
         }
 
-        for j in 0..=len2 {
-            matrix[0][j] = j;
+        for (j, cell) in matrix[0].iter_mut().enumerate() {
+            *cell = j;
         }
 
-        for (i, c1) in s1.chars().enumerate() {
-            for (j, c2) in s2.chars().enumerate() {
+        for (i, c1) in s1.iter().enumerate() {
+            for (j, c2) in s2.iter().enumerate() {
                 let cost = if c1 == c2 { 0 } else { 1 };
                 matrix[i + 1][j + 1] = std::cmp::min(
                     matrix[i][j] + cost,
@@ -196,7 +213,7 @@ impl Deduplicator {
             }
         }
 
-        matrix[len1][len2]
+        matrix[s1.len()][s2.len()]
     }
 
     /// Get statistics about deduplication
@@ -227,7 +244,8 @@ impl Deduplicator {
 }
 
 impl DiscountType {
-    fn to_string(&self) -> String {
+    /// Same spelling as the serialized form, e.g. `free_shipping`
+    pub fn as_str(&self) -> &'static str {
         match self {
             DiscountType::Percentage => "percentage",
             DiscountType::Fixed => "fixed",
@@ -236,7 +254,14 @@ impl DiscountType {
             DiscountType::CashBack => "cash_back",
             DiscountType::Points => "points",
             DiscountType::Unknown => "unknown",
-        }.to_string()
+        }
+    }
+}
+
+#[async_trait]
+impl CouponDeduplicator for Deduplicator {
+    async fn deduplicate(&self, coupons: Vec<RawCoupon>) -> Result<Vec<RawCoupon>, Box<dyn std::error::Error + Send + Sync>> {
+        Deduplicator::deduplicate(self, coupons).await
     }
 }
 
@@ -246,8 +271,8 @@ pub struct DeduplicationStats {
     pub deduplicated_count: usize,
     pub removed_count: usize,
     pub deduplication_rate: f64,
-    pub merchant_stats: HashMap<String, usize>,
-    pub deduplicated_merchant_stats: HashMap<String, usize>,
+    pub merchant_stats: HashMap<MerchantDomain, usize>,
+    pub deduplicated_merchant_stats: HashMap<MerchantDomain, usize>,
 }
 
 use crate::coupon_engine::DiscountType;
@@ -257,10 +282,11 @@ mod tests {
     use super::*;
     use crate::coupon_engine::SourceType;
     use chrono::Utc;
+    use proptest::prelude::*;
 
     fn create_test_coupon(code: &str, merchant: &str) -> RawCoupon {
         RawCoupon {
-            code: code.to_string(),
+            code: CouponCode::parse(code).unwrap(),
             title: format!("{} Discount", code),
             description: None,
             discount_type: DiscountType::Percentage,
@@ -270,11 +296,12 @@ mod tests {
             valid_from: None,
             valid_until: None,
             merchant_name: merchant.to_string(),
-            merchant_domain: format!("{}.com", merchant.to_lowercase()),
+            merchant_domain: MerchantDomain::parse(&format!("{}.com", merchant)).unwrap(),
             source_url: format!("https://{}.com", merchant.to_lowercase()),
             source_type: SourceType::WebScraping,
             metadata: serde_json::json!({}),
             scraped_at: Utc::now(),
+            parser_version: None,
         }
     }
 
@@ -304,4 +331,26 @@ mod tests {
         let result = deduplicator.deduplicate(coupons).await.unwrap();
         assert_eq!(result.len(), 2); // SAVE10 and SAVE1O should be considered similar
     }
+
+    proptest! {
+        #[test]
+        fn prop_levenshtein_is_a_metric(a in "\\PC{0,12}", b in "\\PC{0,12}", c in "\\PC{0,12}") {
+            let dedup = Deduplicator::new();
+            let distance = |x: &str, y: &str| dedup.levenshtein_distance(x, y);
+
+            prop_assert_eq!(distance(&a, &b), distance(&b, &a));
+            prop_assert_eq!(distance(&a, &b) == 0, a == b);
+            prop_assert!(distance(&a, &b) <= a.chars().count().max(b.chars().count()));
+            prop_assert!(distance(&a, &c) <= distance(&a, &b) + distance(&b, &c));
+        }
+
+        #[test]
+        fn prop_similarity_is_symmetric_and_bounded(a in "\\PC{0,12}", b in "\\PC{0,12}") {
+            let dedup = Deduplicator::new();
+            let similarity = dedup.levenshtein_similarity(&a, &b);
+
+            prop_assert_eq!(similarity, dedup.levenshtein_similarity(&b, &a));
+            prop_assert!((0.0..=1.0).contains(&similarity));
+        }
+    }
 }
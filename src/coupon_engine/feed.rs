@@ -0,0 +1,147 @@
+//! Streaming JSON feed reader
+//!
+//! Affiliate feeds can run to hundreds of megabytes. Deserializing one into a
+//! `serde_json::Value` costs several times its size, so feeds are instead walked with
+//! a visitor that hands each record to the caller as soon as it is read and skips
+//! everything else. Only one record is in memory at a time.
+//!
+//! A feed is either an array of records or an object with record arrays under any of
+//! [`FEED_KEYS`].
+
+use std::fmt;
+
+use serde::de::{DeserializeSeed, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde_json::Value;
+
+/// Keys of a feed object whose arrays hold records, in the order their records are
+/// reported
+pub const FEED_KEYS: [&str; 6] = ["coupons", "deals", "offers", "promotions", "data", "results"];
+
+/// Read the feed in `json` and call `each` with every record and the index in
+/// [`FEED_KEYS`] of the key it was listed under (0 for a top-level array)
+pub fn for_each_record<'de, R: serde_json::de::Read<'de>>(json: R, each: impl FnMut(usize, Value)) -> serde_json::Result<()> {
+    let mut deserializer = serde_json::Deserializer::new(json);
+    Feed(each).deserialize(&mut deserializer)?;
+    deserializer.end()
+}
+
+/// The whole document
+struct Feed<F>(F);
+
+impl<'de, F: FnMut(usize, Value)> DeserializeSeed<'de> for Feed<F> {
+    type Value = ();
+
+    fn deserialize<D: serde::Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de, F: FnMut(usize, Value)> Visitor<'de> for Feed<F> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a JSON array or object of coupon records")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(mut self, seq: A) -> Result<(), A::Error> {
+        Records { key: 0, each: &mut self.0 }.visit_seq(seq)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(mut self, mut map: A) -> Result<(), A::Error> {
+        while let Some(key) = map.next_key::<String>()? {
+            match FEED_KEYS.iter().position(|k| *k == key) {
+                Some(key) => map.next_value_seed(Records { key, each: &mut self.0 })?,
+                None => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The value under one of [`FEED_KEYS`]; anything but an array is skipped
+struct Records<'f, F> {
+    key: usize,
+    each: &'f mut F,
+}
+
+impl<'de, F: FnMut(usize, Value)> DeserializeSeed<'de> for Records<'_, F> {
+    type Value = ();
+
+    fn deserialize<D: serde::Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de, F: FnMut(usize, Value)> Visitor<'de> for Records<'_, F> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an array of coupon records")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while let Some(record) = seq.next_element::<Value>()? {
+            (self.each)(self.key, record);
+        }
+        Ok(())
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        while map.next_entry::<IgnoredAny, IgnoredAny>()?.is_some() {}
+        Ok(())
+    }
+
+    fn visit_bool<E>(self, _: bool) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_i64<E>(self, _: i64) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_u64<E>(self, _: u64) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_f64<E>(self, _: f64) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_str<E>(self, _: &str) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_unit<E>(self) -> Result<(), E> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::de::{IoRead, StrRead};
+    use serde_json::json;
+
+    fn records(feed: &str) -> serde_json::Result<Vec<(usize, Value)>> {
+        let mut found = Vec::new();
+        for_each_record(StrRead::new(feed), |key, record| found.push((key, record)))?;
+        Ok(found)
+    }
+
+    #[test]
+    fn test_streams_records_under_feed_keys_and_skips_the_rest() {
+        let feed = r#"{"meta": {"coupons": [1]}, "offers": [{"code": "B"}], "data": {"x": 1}, "coupons": [{"code": "A"}, 2], "results": null}"#;
+        let found = records(feed).unwrap();
+        assert_eq!(found, vec![(2, json!({"code": "B"})), (0, json!({"code": "A"})), (0, json!(2))]);
+
+        assert_eq!(records(r#"[{"code": "A"}]"#).unwrap(), vec![(0, json!({"code": "A"}))]);
+        assert!(records(r#"[{"code": "A"}] trailing"#).is_err());
+        assert!(records(r#"{"coupons": [{"code": "A"}"#).is_err());
+
+        let mut from_reader = Vec::new();
+        for_each_record(IoRead::new(feed.as_bytes()), |key, record| from_reader.push((key, record))).unwrap();
+        assert_eq!(from_reader, found);
+    }
+}
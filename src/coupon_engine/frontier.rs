@@ -0,0 +1,284 @@
+//! Crawl frontier filter for unchanged pages
+//!
+//! Many scheduled URLs serve the same page day after day. [`ScrapeFrontier`] keeps a
+//! bloom filter of the (URL, content hash) pairs scraped recently. When a page comes
+//! back with a hash already in the filter it is unchanged: the engine skips parsing
+//! it, and the URL itself is marked so the next batches do not fetch it at all.
+//!
+//! The filter is split into two generations. Every `rotate_every` (or once the
+//! current generation holds `capacity` entries) the older generation is dropped, so
+//! an unchanged page is skipped for at most two rotation periods before it is
+//! scraped again. A false positive only ever skips a page early; the rate is set by
+//! `SCRAPE_FRONTIER_FP_RATE`. Each instance keeps its own filter, persisted to
+//! `SCRAPE_FRONTIER_PATH` (default `data/scrape_frontier.json`).
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::Mutex;
+
+use crate::clock::{self, Clock};
+use crate::storage::persisted::{PersistedStore, StoreError};
+
+const STORE_KEY: &str = "scrape_frontier";
+const STORE_NAME: &str = "scrape frontier";
+/// How often the filter is written to disk at most
+const PERSIST_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone)]
+pub struct FrontierConfig {
+    /// Entries per generation the false-positive rate is sized for
+    pub capacity: usize,
+    pub false_positive_rate: f64,
+    pub rotate_every: Duration,
+}
+
+impl FrontierConfig {
+    /// Read `SCRAPE_FRONTIER_CAPACITY` (default 1,000,000), `SCRAPE_FRONTIER_FP_RATE`
+    /// (default 0.01) and `SCRAPE_FRONTIER_ROTATE_SECS` (default 43200)
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let var = |name: &str| std::env::var(name).ok().map(|value| value.trim().to_string());
+        Self {
+            capacity: var("SCRAPE_FRONTIER_CAPACITY")
+                .and_then(|v| v.parse().ok())
+                .filter(|capacity| *capacity > 0)
+                .unwrap_or(defaults.capacity),
+            false_positive_rate: var("SCRAPE_FRONTIER_FP_RATE")
+                .and_then(|v| v.parse().ok())
+                .filter(|rate| *rate > 0.0 && *rate < 1.0)
+                .unwrap_or(defaults.false_positive_rate),
+            rotate_every: var("SCRAPE_FRONTIER_ROTATE_SECS")
+                .and_then(|v| v.parse().ok())
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.rotate_every),
+        }
+    }
+}
+
+impl Default for FrontierConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 1_000_000,
+            false_positive_rate: 0.01,
+            rotate_every: Duration::from_secs(12 * 3600),
+        }
+    }
+}
+
+/// A fixed-size bloom filter over string keys
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    hashes: u32,
+    items: usize,
+}
+
+impl BloomFilter {
+    /// Sized so `capacity` entries give about `false_positive_rate` false positives
+    pub fn new(capacity: usize, false_positive_rate: f64) -> Self {
+        let ln2 = std::f64::consts::LN_2;
+        let bits = (-(capacity.max(1) as f64) * false_positive_rate.ln() / (ln2 * ln2)).ceil().max(64.0) as usize;
+        let hashes = ((bits as f64 / capacity.max(1) as f64) * ln2).round().max(1.0) as u32;
+        Self {
+            bits: vec![0; bits.div_ceil(64)],
+            hashes,
+            items: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.items
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items == 0
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.positions(key).all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    pub fn insert(&mut self, key: &str) {
+        let positions: Vec<usize> = self.positions(key).collect();
+        for bit in positions {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+        self.items += 1;
+    }
+
+    /// Double hashing over two halves of the key's SHA-256
+    fn positions(&self, key: &str) -> impl Iterator<Item = usize> {
+        let digest = Sha256::digest(key.as_bytes());
+        let h1 = u64::from_le_bytes(digest[..8].try_into().expect("8 bytes"));
+        let h2 = u64::from_le_bytes(digest[8..16].try_into().expect("8 bytes")) | 1;
+        let size = (self.bits.len() * 64) as u64;
+        (0..u64::from(self.hashes)).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % size) as usize)
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Generations {
+    current: BloomFilter,
+    previous: BloomFilter,
+    rotated_at: DateTime<Utc>,
+}
+
+struct FrontierState {
+    generations: Generations,
+    persisted_at: Option<DateTime<Utc>>,
+}
+
+pub struct ScrapeFrontier {
+    config: FrontierConfig,
+    state: Mutex<FrontierState>,
+    store: PersistedStore<Generations>,
+    clock: Arc<dyn Clock>,
+}
+
+impl ScrapeFrontier {
+    /// Filter persisted to `path`, or kept in memory only
+    pub fn new(config: FrontierConfig, path: Option<PathBuf>) -> Self {
+        let clock = clock::system();
+        Self {
+            state: Mutex::new(FrontierState {
+                generations: Generations {
+                    current: BloomFilter::new(config.capacity, config.false_positive_rate),
+                    previous: BloomFilter::new(config.capacity, config.false_positive_rate),
+                    rotated_at: clock.now(),
+                },
+                persisted_at: None,
+            }),
+            config,
+            store: PersistedStore::new(STORE_NAME, STORE_KEY, path),
+            clock,
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.state.get_mut().generations.rotated_at = clock.now();
+        self.clock = clock;
+        self
+    }
+
+    /// Configured by [`FrontierConfig::from_env`], loaded from `SCRAPE_FRONTIER_PATH`
+    pub async fn from_env() -> Self {
+        let path = std::env::var("SCRAPE_FRONTIER_PATH").unwrap_or_else(|_| "data/scrape_frontier.json".to_string());
+        let frontier = Self::new(FrontierConfig::from_env(), Some(PathBuf::from(path)));
+        if let Err(e) = frontier.load().await {
+            tracing::warn!(error = %e, "Starting with an empty scrape frontier");
+        }
+        frontier
+    }
+
+    async fn load(&self) -> Result<(), StoreError> {
+        let Some(generations) = self.store.load().await? else {
+            return Ok(());
+        };
+        // A filter sized for other settings is rebuilt rather than misread
+        let fresh = BloomFilter::new(self.config.capacity, self.config.false_positive_rate);
+        if generations.current.bits.len() != fresh.bits.len() || generations.current.hashes != fresh.hashes {
+            return Err("filter was sized for a different capacity or false-positive rate".into());
+        }
+        self.state.lock().await.generations = generations;
+        Ok(())
+    }
+
+    /// Whether `url` served an unchanged page recently, so scheduling it can be skipped
+    pub async fn is_unchanged(&self, url: &str) -> bool {
+        let mut state = self.state.lock().await;
+        self.rotate_if_due(&mut state.generations);
+        state.generations.contains(&unchanged_key(url))
+    }
+
+    /// Record that `url` served `content`; true when the same content was recorded
+    /// recently, in which case the URL is marked unchanged
+    pub async fn record(&self, url: &str, content: &str) -> bool {
+        let key = format!("{}#{:x}", url, Sha256::digest(content.as_bytes()));
+        let mut state = self.state.lock().await;
+        self.rotate_if_due(&mut state.generations);
+
+        let generations = &mut state.generations;
+        let unchanged = generations.contains(&key);
+        if !generations.current.contains(&key) {
+            generations.current.insert(&key);
+        }
+        if unchanged && !generations.current.contains(&unchanged_key(url)) {
+            generations.current.insert(&unchanged_key(url));
+        }
+        unchanged
+    }
+
+    fn rotate_if_due(&self, generations: &mut Generations) {
+        let now = self.clock.now();
+        let age = (now - generations.rotated_at).to_std().unwrap_or_default();
+        if age < self.config.rotate_every && generations.current.len() < self.config.capacity {
+            return;
+        }
+        let fresh = BloomFilter::new(self.config.capacity, self.config.false_positive_rate);
+        generations.previous = std::mem::replace(&mut generations.current, fresh);
+        generations.rotated_at = now;
+    }
+
+    /// Write the filter to disk, at most once per [`PERSIST_INTERVAL`]
+    pub async fn persist(&self) {
+        let now = self.clock.now();
+        let generations = {
+            let mut state = self.state.lock().await;
+            if state
+                .persisted_at
+                .is_some_and(|at| (now - at).to_std().unwrap_or_default() < PERSIST_INTERVAL)
+            {
+                return;
+            }
+            state.persisted_at = Some(now);
+            state.generations.clone()
+        };
+        self.store.persist(&generations).await;
+    }
+}
+
+impl Generations {
+    fn contains(&self, key: &str) -> bool {
+        self.current.contains(key) || self.previous.contains(key)
+    }
+}
+
+fn unchanged_key(url: &str) -> String {
+    format!("unchanged#{}", url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    #[tokio::test]
+    async fn test_skips_unchanged_pages_until_rotated_out() {
+        let clock = Arc::new(MockClock::new());
+        let config = FrontierConfig {
+            capacity: 1_000,
+            false_positive_rate: 0.001,
+            rotate_every: Duration::from_secs(3600),
+        };
+        let frontier = ScrapeFrontier::new(config, None).with_clock(clock.clone());
+        let url = "https://shop.example.com/coupons";
+
+        assert!(!frontier.record(url, "<p>SAVE10</p>").await);
+        assert!(!frontier.record(url, "<p>SAVE20</p>").await);
+        assert!(!frontier.is_unchanged(url).await);
+        assert!(frontier.record(url, "<p>SAVE20</p>").await);
+        assert!(frontier.is_unchanged(url).await);
+        assert!(!frontier.is_unchanged("https://shop.example.com/sale").await);
+
+        // Still known one rotation later, forgotten after the second
+        clock.advance(Duration::from_secs(3600));
+        assert!(frontier.is_unchanged(url).await);
+        clock.advance(Duration::from_secs(3600));
+        assert!(!frontier.is_unchanged(url).await);
+    }
+}
@@ -0,0 +1,188 @@
+//! Golden-output checks for the parser
+//!
+//! The corpus lives in `tests/fixtures/parsers/<domain>/<page>.<html|json|csv|txt>`.
+//! Each page sits next to a `<page>.golden.json` with the coupons the parser
+//! extracted when it was last blessed, and is parsed as if fetched from
+//! `https://<domain>/<page>`. `scraped_at` is left out since it changes every run.
+//!
+//! `deal-service parser bless` rewrites the goldens after an intended parser change.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde_json::Value;
+
+use crate::coupon_engine::parser::Parser;
+
+/// Corpus location relative to the crate root
+pub const DEFAULT_CORPUS: &str = "tests/fixtures/parsers";
+
+const PAGE_EXTENSIONS: &[&str] = &["html", "json", "csv", "txt"];
+const GOLDEN_SUFFIX: &str = ".golden.json";
+
+#[derive(Debug, Clone)]
+pub struct Fixture {
+    pub page: PathBuf,
+    pub golden: PathBuf,
+    pub source_url: String,
+}
+
+/// A page whose parser output no longer matches its golden
+#[derive(Debug)]
+pub struct Mismatch {
+    pub fixture: Fixture,
+    /// `None` when the page has no golden yet
+    pub expected: Option<Value>,
+    pub actual: Value,
+}
+
+/// Every page in the corpus, sorted by path
+pub fn fixtures(corpus: &Path) -> Result<Vec<Fixture>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut fixtures = Vec::new();
+    for domain_dir in std::fs::read_dir(corpus)? {
+        let domain_dir = domain_dir?.path();
+        if !domain_dir.is_dir() {
+            continue;
+        }
+        let domain = domain_dir.file_name().and_then(|name| name.to_str()).unwrap_or_default().to_string();
+
+        for entry in std::fs::read_dir(&domain_dir)? {
+            let page = entry?.path();
+            let file_name = page.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+            let is_page = page.extension().and_then(|ext| ext.to_str()).is_some_and(|ext| PAGE_EXTENSIONS.contains(&ext));
+            if !is_page || file_name.ends_with(GOLDEN_SUFFIX) {
+                continue;
+            }
+
+            let stem = page.file_stem().and_then(|stem| stem.to_str()).unwrap_or_default().to_string();
+            fixtures.push(Fixture {
+                golden: domain_dir.join(format!("{}{}", stem, GOLDEN_SUFFIX)),
+                source_url: format!("https://{}/{}", domain, stem),
+                page,
+            });
+        }
+    }
+
+    fixtures.sort_by(|a, b| a.page.cmp(&b.page));
+    Ok(fixtures)
+}
+
+/// Parser output for a fixture, in golden form
+pub async fn extract(parser: &Parser, fixture: &Fixture) -> Result<Value, Box<dyn std::error::Error + Send + Sync>> {
+    let content = tokio::fs::read_to_string(&fixture.page).await?;
+    let coupons = parser.extract_coupons(&content, &fixture.source_url).await?;
+
+    let mut output = serde_json::to_value(coupons)?;
+    if let Value::Array(coupons) = &mut output {
+        for coupon in coupons {
+            if let Value::Object(fields) = coupon {
+                fields.remove("scraped_at");
+            }
+        }
+    }
+    Ok(output)
+}
+
+fn read_golden(fixture: &Fixture) -> Result<Option<Value>, Box<dyn std::error::Error + Send + Sync>> {
+    match std::fs::read_to_string(&fixture.golden) {
+        Ok(content) => Ok(Some(serde_json::from_str(&content)?)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Parse every page in `corpus` and report those that differ from their golden
+pub async fn check(corpus: &Path) -> Result<Vec<Mismatch>, Box<dyn std::error::Error + Send + Sync>> {
+    let parser = Parser::new();
+    let mut mismatches = Vec::new();
+
+    for fixture in fixtures(corpus)? {
+        let actual = extract(&parser, &fixture).await?;
+        let expected = read_golden(&fixture)?;
+        if expected.as_ref() != Some(&actual) {
+            mismatches.push(Mismatch {
+                fixture,
+                expected,
+                actual,
+            });
+        }
+    }
+    Ok(mismatches)
+}
+
+/// Rewrite the goldens that differ from the current parser output, returning the pages updated
+pub async fn bless(corpus: &Path) -> Result<Vec<PathBuf>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut updated = Vec::new();
+    for mismatch in check(corpus).await? {
+        let mut content = serde_json::to_string_pretty(&mismatch.actual)?;
+        content.push('\n');
+        tokio::fs::write(&mismatch.fixture.golden, content).await?;
+        updated.push(mismatch.fixture.page);
+    }
+    Ok(updated)
+}
+
+fn coupon_code(coupon: &Value) -> &str {
+    coupon["code"].as_str().unwrap_or("?")
+}
+
+impl fmt::Display for Mismatch {
+    /// Which codes appeared or disappeared, and which fields changed on the rest
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let page = self.fixture.page.display();
+        let Some(expected) = &self.expected else {
+            return write!(f, "{}: no golden ({} coupons extracted)", page, self.actual.as_array().map_or(0, Vec::len));
+        };
+
+        let empty = Vec::new();
+        let expected = expected.as_array().unwrap_or(&empty);
+        let actual = self.actual.as_array().unwrap_or(&empty);
+        writeln!(f, "{}: expected {} coupons, extracted {}", page, expected.len(), actual.len())?;
+
+        for coupon in expected {
+            if !actual.iter().any(|c| coupon_code(c) == coupon_code(coupon)) {
+                writeln!(f, "  - missing {}", coupon_code(coupon))?;
+            }
+        }
+        for coupon in actual {
+            let Some(before) = expected.iter().find(|c| coupon_code(c) == coupon_code(coupon)) else {
+                writeln!(f, "  + new {}", coupon_code(coupon))?;
+                continue;
+            };
+            let (Some(before), Some(after)) = (before.as_object(), coupon.as_object()) else {
+                continue;
+            };
+            for (field, value) in after {
+                let old = before.get(field).unwrap_or(&Value::Null);
+                if old != value {
+                    writeln!(f, "  ~ {} {}: {} -> {}", coupon_code(coupon), field, old, value)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_mismatch_lists_changed_codes_and_fields() {
+        let mismatch = Mismatch {
+            fixture: Fixture {
+                page: PathBuf::from("shop.example.com/sale.html"),
+                golden: PathBuf::from("shop.example.com/sale.golden.json"),
+                source_url: "https://shop.example.com/sale".to_string(),
+            },
+            expected: Some(json!([{"code": "SAVE10", "discount_value": 10.0}, {"code": "GONE5"}])),
+            actual: json!([{"code": "SAVE10", "discount_value": 15.0}, {"code": "NEW20"}]),
+        };
+
+        let report = mismatch.to_string();
+        assert!(report.contains("- missing GONE5"));
+        assert!(report.contains("+ new NEW20"));
+        assert!(report.contains("~ SAVE10 discount_value: 10.0 -> 15.0"));
+    }
+}
@@ -0,0 +1,544 @@
+//! Merchant liveness checks
+//!
+//! Once a day every merchant in the coupon store is probed: does its domain still
+//! resolve, does a `HEAD` of its home page answer, and does the landing page still
+//! look like a storefront rather than a parking page. The landing page is
+//! fingerprinted (title, tag structure, the storefront and parking markers found) so
+//! a report shows what the check saw.
+//!
+//! A failed check is not enough to give up on a merchant, since sites go down for
+//! maintenance. After [`RETIRE_AFTER`] failed checks in a row the merchant is
+//! retired: its unexpired coupons are expired now, so they stop being offered. A
+//! merchant that comes back is marked live again and its codes return with the
+//! next scrape. Sites that refuse the probe (401, 403, 429) are `unverified` and
+//! neither pass nor fail.
+//!
+//! Results are shared through Redis when `REDIS_URL` is set, so API instances see
+//! what the worker running the checks found; otherwise they are persisted to
+//! `MERCHANT_LIVENESS_PATH` (default `data/merchant_liveness.json`).
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::async_trait;
+use chrono::{DateTime, TimeDelta, Utc};
+use deal_service_macros::selector;
+use futures_util::{stream, StreamExt};
+use scraper::Html;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, MutexGuard};
+use utoipa::ToSchema;
+
+use crate::clock::{self, Clock};
+use crate::coupon_engine::canary;
+use crate::models::domain::MerchantDomain;
+use crate::storage::coupon_store::CouponStore;
+use crate::storage::persisted::{PersistedStore, StoreError};
+use crate::top_coupons::TopCoupons;
+
+/// How often each merchant is checked
+pub const LIVENESS_INTERVAL: TimeDelta = TimeDelta::days(1);
+/// Failed checks in a row before a merchant's coupons are retired
+pub const RETIRE_AFTER: u32 = 3;
+/// Merchants checked per run; the rest wait for the next one
+const MAX_CHECKS_PER_RUN: usize = 200;
+const CHECK_CONCURRENCY: usize = 8;
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Landing page bytes read for the fingerprint
+const MAX_LANDING_BYTES: usize = 512 * 1024;
+const REDIS_KEY: &str = "merchant_liveness";
+const STORE_NAME: &str = "merchant liveness";
+
+/// Hosts parked domains are redirected to
+const PARKING_HOSTS: &[&str] = &[
+    "sedoparking.com",
+    "sedo.com",
+    "bodis.com",
+    "parkingcrew.net",
+    "above.com",
+    "parklogic.com",
+    "hugedomains.com",
+    "dan.com",
+    "afternic.com",
+    "undeveloped.com",
+];
+/// Text of parking and for-sale pages
+const PARKING_MARKERS: &[&str] = &[
+    "this domain is for sale",
+    "this domain may be for sale",
+    "buy this domain",
+    "domain is parked",
+    "parked free",
+    "domain parking",
+    "sedoparking",
+    "parkingcrew",
+];
+/// Markup only a shop's pages carry
+const STOREFRONT_MARKERS: &[&str] = &[
+    "add to cart",
+    "add-to-cart",
+    "addtocart",
+    "add to basket",
+    "/cart",
+    "/basket",
+    "checkout",
+    "/products/",
+    "schema.org/product",
+    "\"@type\":\"product\"",
+    "og:type\" content=\"product",
+    "cdn.shopify.com",
+    "woocommerce",
+    "shop now",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MerchantStatus {
+    Live,
+    /// The domain no longer resolves
+    Unresolved,
+    /// The home page did not answer, or answered with an error
+    Unreachable,
+    /// The domain shows a parking or for-sale page
+    Parked,
+    /// The site is up but nothing on it looks like a shop
+    NoStorefront,
+    /// The site refused the probe; says nothing either way
+    Unverified,
+}
+
+impl MerchantStatus {
+    fn is_failure(self) -> bool {
+        !matches!(self, MerchantStatus::Live | MerchantStatus::Unverified)
+    }
+}
+
+/// What a merchant's landing page looked like
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct LandingFingerprint {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    pub shape_hash: String,
+    pub storefront_markers: Vec<String>,
+    pub parking_markers: Vec<String>,
+}
+
+/// What probing a domain found, before it is judged
+#[derive(Debug, Clone, Default)]
+pub struct Probe {
+    pub resolved: bool,
+    /// Status of the home page's `HEAD`
+    pub status: Option<u16>,
+    /// Host the home page ended on after redirects
+    pub final_host: Option<String>,
+    /// The landing page, when it could be read
+    pub page: Option<String>,
+    pub error: Option<String>,
+}
+
+#[async_trait]
+pub trait SiteProbe: Send + Sync {
+    async fn probe(&self, domain: &MerchantDomain) -> Probe;
+}
+
+/// Resolves the domain, `HEAD`s its home page and reads the start of its landing page
+pub struct HttpProbe {
+    client: reqwest::Client,
+}
+
+impl Default for HttpProbe {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HttpProbe {
+    pub fn new() -> Self {
+        Self {
+            client: reqwest::Client::builder()
+                .timeout(PROBE_TIMEOUT)
+                .redirect(reqwest::redirect::Policy::limited(5))
+                .build()
+                .unwrap_or_default(),
+        }
+    }
+
+    async fn landing_page(&self, url: &str) -> Result<String, reqwest::Error> {
+        let mut response = self.client.get(url).send().await?.error_for_status()?;
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            body.extend_from_slice(&chunk);
+            if body.len() >= MAX_LANDING_BYTES {
+                break;
+            }
+        }
+        Ok(String::from_utf8_lossy(&body).into_owned())
+    }
+}
+
+#[async_trait]
+impl SiteProbe for HttpProbe {
+    async fn probe(&self, domain: &MerchantDomain) -> Probe {
+        match tokio::net::lookup_host((domain.as_str(), 443)).await.map(|mut addresses| addresses.next()) {
+            Ok(Some(_)) => {}
+            Ok(None) => return Probe::default(),
+            Err(e) => {
+                return Probe {
+                    error: Some(e.to_string()),
+                    ..Probe::default()
+                }
+            }
+        }
+
+        let url = format!("https://{}/", domain);
+        let head = match self.client.head(&url).send().await {
+            Ok(head) => head,
+            Err(e) => {
+                return Probe {
+                    resolved: true,
+                    error: Some(e.to_string()),
+                    ..Probe::default()
+                }
+            }
+        };
+        let final_url = head.url().to_string();
+        let mut probe = Probe {
+            resolved: true,
+            status: Some(head.status().as_u16()),
+            final_host: head.url().host_str().map(str::to_string),
+            ..Probe::default()
+        };
+        // Some shops answer HEAD with 405; the page itself decides
+        if head.status().is_success() || head.status() == reqwest::StatusCode::METHOD_NOT_ALLOWED {
+            match self.landing_page(&final_url).await {
+                Ok(page) => probe.page = Some(page),
+                Err(e) => probe.error = Some(e.to_string()),
+            }
+        }
+        probe
+    }
+}
+
+/// Judge a probe, returning the status, the landing page's fingerprint and why
+pub fn classify(probe: &Probe) -> (MerchantStatus, Option<LandingFingerprint>, Option<String>) {
+    if !probe.resolved {
+        let detail = probe.error.clone().unwrap_or_else(|| "no addresses".to_string());
+        return (MerchantStatus::Unresolved, None, Some(detail));
+    }
+    let Some(status) = probe.status else {
+        return (MerchantStatus::Unreachable, None, probe.error.clone());
+    };
+    if matches!(status, 401 | 403 | 429) {
+        return (MerchantStatus::Unverified, None, Some(format!("home page answered {}", status)));
+    }
+    if let Some(host) = probe.final_host.as_deref().filter(|host| is_parking_host(host)) {
+        return (MerchantStatus::Parked, None, Some(format!("redirects to {}", host)));
+    }
+    let Some(page) = &probe.page else {
+        let detail = probe.error.clone().unwrap_or_else(|| format!("home page answered {}", status));
+        return (MerchantStatus::Unreachable, None, Some(detail));
+    };
+
+    let fingerprint = fingerprint(page);
+    let (status, detail) = if !fingerprint.parking_markers.is_empty() {
+        (MerchantStatus::Parked, Some("landing page is a parking page".to_string()))
+    } else if fingerprint.storefront_markers.is_empty() {
+        (MerchantStatus::NoStorefront, Some("no cart, checkout or product markup on the landing page".to_string()))
+    } else {
+        (MerchantStatus::Live, None)
+    };
+    (status, Some(fingerprint), detail)
+}
+
+fn is_parking_host(host: &str) -> bool {
+    PARKING_HOSTS
+        .iter()
+        .any(|parking| host == *parking || host.ends_with(&format!(".{}", parking)))
+}
+
+/// The title, structure and markers of a landing page
+pub fn fingerprint(page: &str) -> LandingFingerprint {
+    let document = Html::parse_document(page);
+    let title = document
+        .select(selector!("title"))
+        .next()
+        .map(|title| title.text().collect::<String>().trim().to_string())
+        .filter(|title| !title.is_empty());
+    // Markers are matched on the raw page, so attribute values and scripts count too
+    let lowered = page.to_lowercase();
+    let found = |markers: &[&str]| markers.iter().filter(|marker| lowered.contains(*marker)).map(|marker| marker.to_string()).collect();
+
+    LandingFingerprint {
+        title,
+        shape_hash: canary::fingerprint(page, &[], Utc::now()).shape_hash,
+        storefront_markers: found(STOREFRONT_MARKERS),
+        parking_markers: found(PARKING_MARKERS),
+    }
+}
+
+/// A merchant's latest check
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct MerchantLiveness {
+    pub domain: MerchantDomain,
+    pub status: MerchantStatus,
+    pub checked_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<LandingFingerprint>,
+    /// Failed checks in a row
+    pub consecutive_failures: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_live_at: Option<DateTime<Utc>>,
+    /// When the merchant's coupons were retired; cleared once it is live again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retired_at: Option<DateTime<Utc>>,
+}
+
+pub struct LivenessMonitor {
+    probe: Arc<dyn SiteProbe>,
+    coupons: Arc<CouponStore>,
+    top_coupons: Option<Arc<TopCoupons>>,
+    results: Mutex<BTreeMap<MerchantDomain, MerchantLiveness>>,
+    store: PersistedStore<BTreeMap<MerchantDomain, MerchantLiveness>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl LivenessMonitor {
+    /// Checks of the coupon store's merchants, persisted to `path` or kept in memory only
+    pub fn new(probe: Arc<dyn SiteProbe>, coupons: Arc<CouponStore>, path: Option<PathBuf>) -> Self {
+        Self::with_store(probe, coupons, PersistedStore::new(STORE_NAME, REDIS_KEY, path))
+    }
+
+    /// Checks shared through Redis
+    pub fn shared(
+        probe: Arc<dyn SiteProbe>,
+        coupons: Arc<CouponStore>,
+        redis_url: &str,
+    ) -> Result<Self, StoreError> {
+        Ok(Self::with_store(probe, coupons, PersistedStore::shared(STORE_NAME, REDIS_KEY, redis_url)?))
+    }
+
+    fn with_store(
+        probe: Arc<dyn SiteProbe>,
+        coupons: Arc<CouponStore>,
+        store: PersistedStore<BTreeMap<MerchantDomain, MerchantLiveness>>,
+    ) -> Self {
+        Self {
+            probe,
+            coupons,
+            top_coupons: None,
+            results: Mutex::new(BTreeMap::new()),
+            store,
+            clock: clock::system(),
+        }
+    }
+
+    /// Drop a retired merchant's cached top coupons
+    pub fn with_top_coupons(mut self, top_coupons: Arc<TopCoupons>) -> Self {
+        self.top_coupons = Some(top_coupons);
+        self
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Share results through `REDIS_URL` when set, otherwise load them from
+    /// `MERCHANT_LIVENESS_PATH`
+    pub async fn from_env(probe: Arc<dyn SiteProbe>, coupons: Arc<CouponStore>) -> Self {
+        let store = PersistedStore::from_env(STORE_NAME, REDIS_KEY, "MERCHANT_LIVENESS_PATH", "data/merchant_liveness.json");
+        let monitor = Self::with_store(probe, coupons, store);
+        if let Err(e) = monitor.load().await {
+            tracing::warn!(error = %e, "Starting without merchant liveness results");
+        }
+        monitor
+    }
+
+    async fn load(&self) -> Result<(), StoreError> {
+        if let Some(loaded) = self.store.load().await? {
+            *self.results.lock().await = loaded;
+        }
+        Ok(())
+    }
+
+    /// The results, re-read from Redis when shared
+    async fn lock(&self) -> MutexGuard<'_, BTreeMap<MerchantDomain, MerchantLiveness>> {
+        let mut results = self.results.lock().await;
+        if self.store.is_shared() {
+            match self.store.load().await {
+                Ok(Some(loaded)) => *results = loaded,
+                Ok(None) => {}
+                Err(e) => tracing::warn!(error = %e, "Shared merchant liveness unavailable, reading the local copy"),
+            }
+        }
+        results
+    }
+
+    /// Check the coupon store's merchants not checked within [`LIVENESS_INTERVAL`],
+    /// least recently checked first, returning how many were checked
+    pub async fn run_due(&self) -> usize {
+        let now = self.clock.now();
+        let merchants: BTreeSet<MerchantDomain> = self.coupons.list().await.into_iter().map(|c| c.merchant_domain).collect();
+        let results = self.lock().await;
+        let mut due: Vec<(Option<DateTime<Utc>>, MerchantDomain)> = merchants
+            .into_iter()
+            .map(|domain| (results.get(&domain).map(|result| result.checked_at), domain))
+            .filter(|(checked_at, _)| checked_at.is_none_or(|at| now - at >= LIVENESS_INTERVAL))
+            .collect();
+        drop(results);
+        due.sort();
+        due.truncate(MAX_CHECKS_PER_RUN);
+
+        stream::iter(due)
+            .map(|(_, domain)| async move { self.check(&domain).await })
+            .buffer_unordered(CHECK_CONCURRENCY)
+            .count()
+            .await
+    }
+
+    /// Probe `domain` now, retiring its coupons when it has failed too often
+    pub async fn check(&self, domain: &MerchantDomain) -> MerchantLiveness {
+        let probe = self.probe.probe(domain).await;
+        let (status, fingerprint, detail) = classify(&probe);
+        let now = self.clock.now();
+
+        let mut results = self.lock().await;
+        let previous = results.get(domain);
+        let consecutive_failures = match status {
+            MerchantStatus::Live => 0,
+            MerchantStatus::Unverified => previous.map_or(0, |p| p.consecutive_failures),
+            _ => previous.map_or(0, |p| p.consecutive_failures) + 1,
+        };
+        let already_retired = previous.and_then(|p| p.retired_at);
+        let retire = already_retired.is_none() && status.is_failure() && consecutive_failures >= RETIRE_AFTER;
+        let result = MerchantLiveness {
+            domain: domain.clone(),
+            status,
+            checked_at: now,
+            detail,
+            fingerprint,
+            consecutive_failures,
+            last_live_at: match status {
+                MerchantStatus::Live => Some(now),
+                _ => previous.and_then(|p| p.last_live_at),
+            },
+            retired_at: match status {
+                MerchantStatus::Live => None,
+                _ if retire => Some(now),
+                _ => already_retired,
+            },
+        };
+        results.insert(domain.clone(), result.clone());
+        self.store.persist(&results).await;
+        drop(results);
+
+        if retire {
+            let retired = self.retire(domain, now).await;
+            tracing::warn!(merchant = %domain, retired, status = ?status, consecutive_failures, "Retired the coupons of an unreachable merchant");
+        }
+        result
+    }
+
+    /// Expire every unexpired coupon of `domain` at `now`
+    async fn retire(&self, domain: &MerchantDomain, now: DateTime<Utc>) -> usize {
+        let mut retired = 0;
+        for mut coupon in self.coupons.for_merchant(domain).await {
+            if coupon.valid_until.is_none_or(|until| until > now) {
+                coupon.valid_until = Some(now);
+                self.coupons.upsert(coupon).await;
+                retired += 1;
+            }
+        }
+        if let Some(top_coupons) = &self.top_coupons {
+            top_coupons.invalidate(domain);
+        }
+        retired
+    }
+
+    pub async fn get(&self, domain: &MerchantDomain) -> Option<MerchantLiveness> {
+        self.lock().await.get(domain).cloned()
+    }
+
+    pub async fn report(&self) -> Vec<MerchantLiveness> {
+        self.lock().await.values().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    const SHOP: &str = "<html><head><title>Shop</title></head><body><a href='/cart'>Cart</a><button>Add to cart</button></body></html>";
+    const PARKED: &str = "<html><head><title>shop.example</title></head><body>This domain is for sale!</body></html>";
+
+    struct Site(std::sync::Mutex<Probe>);
+
+    #[async_trait]
+    impl SiteProbe for Site {
+        async fn probe(&self, _domain: &MerchantDomain) -> Probe {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    fn up(page: &str) -> Probe {
+        Probe {
+            resolved: true,
+            status: Some(200),
+            final_host: Some("shop.example".to_string()),
+            page: Some(page.to_string()),
+            error: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retires_coupons_after_repeated_failures() {
+        assert_eq!(classify(&up(SHOP)).0, MerchantStatus::Live);
+        assert_eq!(classify(&up(PARKED)).0, MerchantStatus::Parked);
+        assert_eq!(classify(&up("<html><body>Hello</body></html>")).0, MerchantStatus::NoStorefront);
+        assert_eq!(classify(&Probe::default()).0, MerchantStatus::Unresolved);
+        let mut redirected = up(SHOP);
+        redirected.final_host = Some("www.sedoparking.com".to_string());
+        assert_eq!(classify(&redirected).0, MerchantStatus::Parked);
+        let mut blocked = up(SHOP);
+        blocked.status = Some(403);
+        assert_eq!(classify(&blocked).0, MerchantStatus::Unverified);
+
+        let coupons = Arc::new(CouponStore::with_sample_data());
+        let domain = MerchantDomain::parse("techstore.com").unwrap();
+        let site = Arc::new(Site(std::sync::Mutex::new(up(SHOP))));
+        let clock = Arc::new(MockClock::new());
+        let monitor = LivenessMonitor::new(site.clone(), coupons.clone(), None).with_clock(clock.clone());
+
+        assert_eq!(monitor.run_due().await, 5);
+        let live = monitor.get(&domain).await.unwrap();
+        assert_eq!(live.status, MerchantStatus::Live);
+        assert_eq!(live.fingerprint.unwrap().title.as_deref(), Some("Shop"));
+        // Nothing is due again until a day has passed
+        assert_eq!(monitor.run_due().await, 0);
+
+        *site.0.lock().unwrap() = up(PARKED);
+        for _ in 1..RETIRE_AFTER {
+            clock.advance(Duration::from_secs(24 * 3600));
+            monitor.check(&domain).await;
+        }
+        // A refused probe does not count either way
+        *site.0.lock().unwrap() = blocked;
+        assert_eq!(monitor.check(&domain).await.consecutive_failures, RETIRE_AFTER - 1);
+        assert!(coupons.for_merchant(&domain).await.iter().all(|c| c.valid_until.is_none()));
+
+        *site.0.lock().unwrap() = up(PARKED);
+        let retired = monitor.check(&domain).await;
+        assert_eq!(retired.status, MerchantStatus::Parked);
+        assert_eq!(retired.retired_at, Some(clock.now()));
+        assert!(coupons.for_merchant(&domain).await.iter().all(|c| c.valid_until == Some(clock.now())));
+
+        *site.0.lock().unwrap() = up(SHOP);
+        let back = monitor.check(&domain).await;
+        assert_eq!((back.status, back.retired_at, back.consecutive_failures), (MerchantStatus::Live, None, 0));
+    }
+}
@@ -0,0 +1,353 @@
+//! Memory held by in-flight batches
+//!
+//! A batch holds each fetched page from the fetch until its parse finishes, and
+//! every valid coupon until the batch returns, so a 100k-URL backfill could grow
+//! without bound. [`MemoryBudget`] accounts for both, per batch and across the
+//! engine, and the fetch stage waits while the total is at the cap
+//! (`SCRAPE_MEMORY_CAP_MB`, default [`DEFAULT_CAP_BYTES`]):
+//!
+//! - A page's size is only known once it arrives, so each fetch reserves the
+//!   average page size so far (at first [`INITIAL_PAGE_ESTIMATE`]) and the real size
+//!   replaces it on arrival. Pages larger than the average can take the total past
+//!   the cap.
+//! - At least one fetch may always proceed. A batch whose own coupons exceed the
+//!   cap slows to one page at a time instead of waiting forever for memory that
+//!   only its completion would free.
+//!
+//! Sizes are estimates: page bytes, and the fixed size of a coupon plus its text
+//! and metadata. The cap can be changed while the engine runs (see
+//! [`MemoryBudget::set_cap`]).
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use tokio::sync::Notify;
+use utoipa::ToSchema;
+
+use super::RawCoupon;
+
+pub const DEFAULT_CAP_BYTES: usize = 512 * 1024 * 1024;
+/// Reserved per fetch until pages have been seen
+pub const INITIAL_PAGE_ESTIMATE: usize = 256 * 1024;
+/// Pages the average page size spans
+const ESTIMATE_WINDOW: f64 = 100.0;
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct BatchMemorySnapshot {
+    pub id: u64,
+    pub urls: usize,
+    pub page_bytes: usize,
+    pub coupon_bytes: usize,
+    pub started_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct MemorySnapshot {
+    pub cap_bytes: usize,
+    pub held_bytes: usize,
+    pub page_bytes: usize,
+    pub coupon_bytes: usize,
+    /// Reserved for each fetch until its page arrives
+    pub page_estimate_bytes: usize,
+    /// Highest `held_bytes` since start
+    pub peak_bytes: usize,
+    /// Fetches that had to wait for memory to be released
+    pub fetches_delayed: u64,
+    /// Batches in flight, largest first
+    pub batches: Vec<BatchMemorySnapshot>,
+}
+
+#[derive(Debug)]
+struct BatchState {
+    urls: usize,
+    pages: usize,
+    coupons: usize,
+    started_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    pages: usize,
+    coupons: usize,
+    /// Fetches let through whose page has not been parsed yet
+    in_flight: usize,
+    page_estimate: f64,
+    peak: usize,
+    delayed: u64,
+    batches: HashMap<u64, BatchState>,
+}
+
+impl State {
+    fn add(&mut self, batch: u64, pages: isize, coupons: isize) {
+        self.pages = self.pages.saturating_add_signed(pages);
+        self.coupons = self.coupons.saturating_add_signed(coupons);
+        self.peak = self.peak.max(self.pages + self.coupons);
+        if let Some(batch) = self.batches.get_mut(&batch) {
+            batch.pages = batch.pages.saturating_add_signed(pages);
+            batch.coupons = batch.coupons.saturating_add_signed(coupons);
+        }
+    }
+}
+
+pub struct MemoryBudget {
+    cap: AtomicUsize,
+    state: Mutex<State>,
+    released: Notify,
+    next_batch: AtomicU64,
+}
+
+impl MemoryBudget {
+    pub fn new(cap_bytes: usize) -> Self {
+        Self {
+            cap: AtomicUsize::new(cap_bytes),
+            state: Mutex::new(State {
+                page_estimate: INITIAL_PAGE_ESTIMATE as f64,
+                ..State::default()
+            }),
+            released: Notify::new(),
+            next_batch: AtomicU64::new(1),
+        }
+    }
+
+    /// Hold in-flight batches to `cap_bytes` from now on; fetches waiting for room
+    /// re-check against it
+    pub fn set_cap(&self, cap_bytes: usize) {
+        if self.cap.swap(cap_bytes, Ordering::Relaxed) != cap_bytes {
+            self.released.notify_waiters();
+        }
+    }
+
+    /// Start accounting for a batch of `urls`; it stops when the [`BatchMemory`] drops
+    pub fn batch(self: &Arc<Self>, urls: usize) -> BatchMemory {
+        let id = self.next_batch.fetch_add(1, Ordering::Relaxed);
+        self.state.lock().unwrap().batches.insert(
+            id,
+            BatchState {
+                urls,
+                pages: 0,
+                coupons: 0,
+                started_at: Utc::now(),
+            },
+        );
+        BatchMemory { budget: self.clone(), id }
+    }
+
+    pub fn snapshot(&self) -> MemorySnapshot {
+        let state = self.state.lock().unwrap();
+        let mut batches: Vec<BatchMemorySnapshot> = state
+            .batches
+            .iter()
+            .map(|(id, batch)| BatchMemorySnapshot {
+                id: *id,
+                urls: batch.urls,
+                page_bytes: batch.pages,
+                coupon_bytes: batch.coupons,
+                started_at: batch.started_at,
+            })
+            .collect();
+        batches.sort_by_key(|batch| std::cmp::Reverse(batch.page_bytes + batch.coupon_bytes));
+        MemorySnapshot {
+            cap_bytes: self.cap.load(Ordering::Relaxed),
+            held_bytes: state.pages + state.coupons,
+            page_bytes: state.pages,
+            coupon_bytes: state.coupons,
+            page_estimate_bytes: state.page_estimate as usize,
+            peak_bytes: state.peak,
+            fetches_delayed: state.delayed,
+            batches,
+        }
+    }
+}
+
+/// One batch's share of a [`MemoryBudget`]
+pub struct BatchMemory {
+    budget: Arc<MemoryBudget>,
+    id: u64,
+}
+
+impl BatchMemory {
+    /// Wait until the engine holds less than the cap, or nothing is being fetched
+    /// or parsed, then reserve room for one page
+    pub async fn room(&self) -> MemoryHold {
+        let mut waited = false;
+        loop {
+            let released = self.budget.released.notified();
+            tokio::pin!(released);
+            // Registered before checking, so a release in between is not missed
+            released.as_mut().enable();
+            {
+                let mut state = self.budget.state.lock().unwrap();
+                if state.pages + state.coupons < self.budget.cap.load(Ordering::Relaxed) || state.in_flight == 0 {
+                    let reserved = state.page_estimate as usize;
+                    state.in_flight += 1;
+                    state.delayed += u64::from(waited);
+                    state.add(self.id, reserved as isize, 0);
+                    return MemoryHold {
+                        budget: self.budget.clone(),
+                        batch: self.id,
+                        page: reserved,
+                        coupons: 0,
+                        in_flight: true,
+                    };
+                }
+            }
+            waited = true;
+            released.await;
+        }
+    }
+}
+
+impl Drop for BatchMemory {
+    fn drop(&mut self) {
+        self.budget.state.lock().unwrap().batches.remove(&self.id);
+    }
+}
+
+/// Memory one URL holds; released when dropped
+pub struct MemoryHold {
+    budget: Arc<MemoryBudget>,
+    batch: u64,
+    page: usize,
+    coupons: usize,
+    in_flight: bool,
+}
+
+impl MemoryHold {
+    /// The fetched page, in place of the reservation, is held until
+    /// [`MemoryHold::keep_coupons`]
+    pub fn page(&mut self, bytes: usize) {
+        {
+            let mut state = self.budget.state.lock().unwrap();
+            state.add(self.batch, bytes as isize - self.page as isize, 0);
+            state.page_estimate += (bytes as f64 - state.page_estimate) / ESTIMATE_WINDOW;
+        }
+        self.page = bytes;
+        self.budget.released.notify_waiters();
+    }
+
+    /// The page is parsed: release it and hold what its coupons take instead
+    pub fn keep_coupons(&mut self, coupons: &[RawCoupon]) {
+        let bytes: usize = coupons.iter().map(coupon_bytes).sum();
+        {
+            let mut state = self.budget.state.lock().unwrap();
+            state.add(self.batch, -(self.page as isize), bytes as isize - self.coupons as isize);
+            if std::mem::take(&mut self.in_flight) {
+                state.in_flight -= 1;
+            }
+        }
+        self.page = 0;
+        self.coupons = bytes;
+        self.budget.released.notify_waiters();
+    }
+}
+
+impl Drop for MemoryHold {
+    fn drop(&mut self) {
+        {
+            let mut state = self.budget.state.lock().unwrap();
+            state.add(self.batch, -(self.page as isize), -(self.coupons as isize));
+            if self.in_flight {
+                state.in_flight -= 1;
+            }
+        }
+        self.budget.released.notify_waiters();
+    }
+}
+
+/// Estimated heap and inline size of a coupon
+pub fn coupon_bytes(coupon: &RawCoupon) -> usize {
+    std::mem::size_of::<RawCoupon>()
+        + coupon.code.as_str().len()
+        + coupon.title.len()
+        + coupon.description.as_ref().map_or(0, String::len)
+        + coupon.merchant_name.len()
+        + coupon.merchant_domain.as_str().len()
+        + coupon.source_url.len()
+        + coupon.parser_version.as_ref().map_or(0, String::len)
+        + json_bytes(&coupon.metadata)
+}
+
+fn json_bytes(value: &Value) -> usize {
+    std::mem::size_of::<Value>()
+        + match value {
+            Value::String(text) => text.len(),
+            Value::Array(items) => items.iter().map(json_bytes).sum(),
+            Value::Object(fields) => fields.iter().map(|(key, value)| key.len() + json_bytes(value)).sum(),
+            _ => 0,
+        }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coupon_engine::{DiscountType, SourceType};
+    use crate::models::domain::{CouponCode, MerchantDomain};
+
+    fn coupon() -> RawCoupon {
+        RawCoupon {
+            code: CouponCode::parse("SAVE20").unwrap(),
+            title: "20% off".to_string(),
+            description: None,
+            discount_type: DiscountType::Percentage,
+            discount_value: Some(20.0),
+            minimum_order: None,
+            maximum_discount: None,
+            valid_from: None,
+            valid_until: None,
+            merchant_name: "Shop".to_string(),
+            merchant_domain: MerchantDomain::parse("shop.com").unwrap(),
+            source_url: "https://shop.com/deals".to_string(),
+            source_type: SourceType::WebScraping,
+            metadata: serde_json::json!({"feed": {"id": "abc"}}),
+            scraped_at: Utc::now(),
+            parser_version: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fetches_wait_for_room_but_one_always_proceeds() {
+        let estimate = INITIAL_PAGE_ESTIMATE;
+        let budget = Arc::new(MemoryBudget::new(2 * estimate));
+        let batch = budget.batch(3);
+
+        // Each fetch reserves the page estimate until its page arrives
+        let mut first = batch.room().await;
+        let second = batch.room().await;
+        assert_eq!(budget.snapshot().page_bytes, 2 * estimate);
+
+        // At the cap: the next fetch waits until memory is released
+        let third = tokio::spawn(async move { batch.room().await });
+        tokio::task::yield_now().await;
+        assert!(!third.is_finished());
+        first.page(estimate / 2);
+        let third = third.await.unwrap();
+        let held = budget.snapshot();
+        assert!(held.page_estimate_bytes < estimate);
+        assert_eq!(held.fetches_delayed, 1);
+        assert_eq!(held.page_bytes, estimate / 2 + estimate + held.page_estimate_bytes);
+        first.keep_coupons(&[]);
+        drop((second, third));
+
+        // Coupons alone past the cap still let one fetch through at a time
+        let budget = Arc::new(MemoryBudget::new(100));
+        let batch = budget.batch(2);
+        let mut retained = batch.room().await;
+        retained.page(10);
+        retained.keep_coupons(&[coupon(), coupon()]);
+        let coupons = budget.snapshot().coupon_bytes;
+        assert!(coupons >= 2 * std::mem::size_of::<RawCoupon>());
+        let held = budget.snapshot();
+        assert_eq!((held.page_bytes, held.batches.len(), held.batches[0].coupon_bytes), (0, 1, coupons));
+        let hold = tokio::time::timeout(std::time::Duration::from_secs(1), batch.room()).await;
+        assert!(hold.is_ok());
+
+        drop((hold, retained, batch));
+        let idle = budget.snapshot();
+        assert_eq!((idle.held_bytes, idle.batches.len()), (0, 0));
+        assert!(idle.peak_bytes >= estimate);
+    }
+}
@@ -3,38 +3,252 @@
 //! This module provides the core Rust components for efficient coupon aggregation,
 //! including concurrent HTTP requests, HTML/JSON parsing, rate limiting, and data validation.
 
+pub mod archive;
+pub mod blocklist;
+pub mod budget;
+pub mod canary;
+pub mod concurrency;
+pub mod controls;
+pub mod feed;
+pub mod frontier;
+pub mod liveness;
+pub mod memory;
+pub mod normalize;
+pub mod opt_out;
 pub mod scraper;
 pub mod parser;
 pub mod validator;
 pub mod deduplicator;
 pub mod rate_limiter;
+pub mod redirects;
 pub mod proxy_manager;
+pub mod golden;
+pub mod profiles;
+pub mod shadow;
+pub mod stages;
+pub mod yield_stats;
 
 use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tracing::Instrument;
+use utoipa::ToSchema;
+
+use crate::models::domain::{CouponCode, MerchantDomain};
+use crate::models::url::CanonicalUrls;
+use crate::telemetry::TraceContext;
+use archive::SnapshotArchive;
+use concurrency::{AdaptiveLimit, ConcurrencySnapshot};
+use controls::ScraperControls;
+use profiles::DomainProfiles;
+use deduplicator::CouponDeduplicator;
+use frontier::ScrapeFrontier;
+use memory::{MemoryBudget, MemoryHold, MemorySnapshot};
+use normalize::{EmojiPolicy, TextNormalizer};
+use blocklist::ExtractionBlocklist;
+use opt_out::{BlockedAt, OptOutRegistry};
+use parser::CouponParser;
+use proxy_manager::ProxySource;
+use rate_limiter::Limiter;
+use redirects::{FlaggedChain, RedirectAudit, RedirectAuditor};
+use scraper::{Fetcher, ResponseHeaders};
+use shadow::{PageResult, ShadowParser, ShadowReport};
+use stages::{Stage, StageHistograms, StageTimings};
+use validator::ValidationPolicy;
+use yield_stats::{YieldCounts, YieldStats};
+
+/// Error of the URLs refused because their merchant opted out of scraping
+pub const OPTED_OUT: &str = "merchant opted out of scraping";
+/// Error of interactive requests for a paused merchant's pages that are not cached
+pub const PAUSED: &str = "merchant is paused by an operator";
+/// Longest [`CouponEngine::process_url`] waits for a page
+pub const INTERACTIVE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Pages kept for interactive requests; the least recently fetched go first
+const MAX_CACHED_PAGES: usize = 500;
+/// Feed coupons parsed ahead of [`FeedCoupons::next`]
+const FEED_BUFFER: usize = 64;
+
+/// What one URL of a batch produced
+struct UrlOutcome {
+    url: String,
+    domain: Option<MerchantDomain>,
+    fetched: bool,
+    unchanged: bool,
+    extracted: usize,
+    valid: Vec<RawCoupon>,
+    error: Option<String>,
+    headers: ResponseHeaders,
+    redirects: Option<RedirectAudit>,
+    timings: StageTimings,
+}
+
+impl UrlOutcome {
+    fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            domain: MerchantDomain::parse(url).ok(),
+            fetched: false,
+            unchanged: false,
+            extracted: 0,
+            valid: Vec::new(),
+            error: None,
+            headers: ResponseHeaders::default(),
+            redirects: None,
+            timings: StageTimings::default(),
+        }
+    }
+
+    /// The outcome as reported, and its valid coupons
+    fn into_result(self) -> (UrlResult, Vec<RawCoupon>) {
+        let result = UrlResult {
+            url: self.url,
+            fetched: self.fetched,
+            unchanged: self.unchanged,
+            coupons_extracted: self.extracted,
+            coupons_valid: self.valid.len(),
+            error: self.error,
+            headers: self.headers,
+            redirects: self.redirects,
+            timings: self.timings,
+        };
+        (result, self.valid)
+    }
+
+    /// Skipped because its page has not changed; not counted towards yield
+    fn unchanged(url: &str, fetched: bool, headers: ResponseHeaders, timings: StageTimings) -> Self {
+        Self {
+            domain: None,
+            fetched,
+            unchanged: true,
+            headers,
+            timings,
+            ..Self::new(url)
+        }
+    }
+}
+
+/// One URL of a batch, as reported in [`BatchResult::urls`]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct UrlResult {
+    /// The canonical URL the coupons were keyed by
+    pub url: String,
+    pub fetched: bool,
+    /// Not parsed because its page was unchanged, see [`ScrapeFrontier`]
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub unchanged: bool,
+    pub coupons_extracted: usize,
+    pub coupons_valid: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "ResponseHeaders::is_empty")]
+    #[schema(value_type = Object)]
+    pub headers: ResponseHeaders,
+    /// Present when the fetch followed redirects
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    pub redirects: Option<RedirectAudit>,
+    /// Time spent in each pipeline stage, see [`stages`]
+    #[serde(default, skip_serializing_if = "StageTimings::is_empty")]
+    #[schema(value_type = Object)]
+    pub timings: StageTimings,
+}
+
+/// What [`CouponEngine::process_url`] found on one page
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct PageCoupons {
+    #[serde(flatten)]
+    pub result: UrlResult,
+    /// The page's valid coupons, deduplicated
+    pub coupons: Vec<RawCoupon>,
+    /// Parsed from a copy fetched within `cache_duration_secs`
+    pub cached: bool,
+}
+
+/// A page [`CouponEngine::process_url`] fetched, kept for `cache_duration_secs`
+#[derive(Clone)]
+struct CachedPage {
+    /// The page's canonical URL
+    url: String,
+    content: String,
+    headers: ResponseHeaders,
+    redirects: Option<RedirectAudit>,
+    fetched_at: Instant,
+}
+
+/// Deduplicated coupons of a batch and what each of its URLs produced
+#[derive(Debug, Clone, Default)]
+pub struct BatchResult {
+    pub coupons: Vec<RawCoupon>,
+    pub urls: Vec<UrlResult>,
+}
+
+/// Valid coupons of a feed being read by [`CouponEngine::process_feed`], as the
+/// parser reaches them
+pub struct FeedCoupons {
+    parsed: tokio::sync::mpsc::Receiver<RawCoupon>,
+    parsing: tokio::task::JoinHandle<Result<(), Box<dyn std::error::Error + Send + Sync>>>,
+    validator: Arc<dyn ValidationPolicy>,
+    normalizer: TextNormalizer,
+    parser_version: String,
+    extracted: usize,
+}
+
+impl FeedCoupons {
+    /// The next valid coupon, normalized; `None` once the feed is read or has failed
+    pub async fn next(&mut self) -> Option<RawCoupon> {
+        while let Some(mut coupon) = self.parsed.recv().await {
+            self.extracted += 1;
+            self.normalizer.normalize(&mut coupon);
+            if self.validator.is_valid(&coupon).await {
+                coupon.parser_version = Some(self.parser_version.clone());
+                return Some(coupon);
+            }
+        }
+        None
+    }
+
+    /// How many coupons the feed held, valid or not, or why it could not be read.
+    /// Coupons not yet taken with [`next`](Self::next) are dropped.
+    pub async fn finish(mut self) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+        self.parsed.close();
+        while self.parsed.recv().await.is_some() {
+            self.extracted += 1;
+        }
+        self.parsing.await??;
+        Ok(self.extracted)
+    }
+}
 
 /// Core coupon data structure
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct RawCoupon {
-    pub code: String,
+    pub code: CouponCode,
     pub title: String,
     pub description: Option<String>,
     pub discount_type: DiscountType,
+    /// Percentage, amount or points depending on `discount_type`
     pub discount_value: Option<f64>,
-    pub minimum_order: Option<f64>,
-    pub maximum_discount: Option<f64>,
+    /// In the merchant's currency
+    pub minimum_order: Option<Decimal>,
+    pub maximum_discount: Option<Decimal>,
     pub valid_from: Option<DateTime<Utc>>,
     pub valid_until: Option<DateTime<Utc>>,
     pub merchant_name: String,
-    pub merchant_domain: String,
+    pub merchant_domain: MerchantDomain,
     pub source_url: String,
     pub source_type: SourceType,
     pub metadata: serde_json::Value,
     pub scraped_at: DateTime<Utc>,
+    /// Version of the parser that extracted it, see [`CouponParser::version`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parser_version: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, ToSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum DiscountType {
     Percentage,
@@ -43,10 +257,11 @@ pub enum DiscountType {
     Bogo,
     CashBack,
     Points,
+    #[default]
     Unknown,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum SourceType {
     AffiliateApi,
@@ -56,8 +271,9 @@ pub enum SourceType {
 }
 
 /// Configuration for the coupon engine
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct EngineConfig {
+    /// Ceiling for the adaptive fetch concurrency, see [`concurrency`]
     pub max_concurrent_requests: usize,
     pub request_timeout_secs: u64,
     pub retry_attempts: u32,
@@ -65,6 +281,16 @@ pub struct EngineConfig {
     pub proxy_rotation_enabled: bool,
     pub user_agent_rotation: bool,
     pub cache_duration_secs: u64,
+    /// Page and coupon bytes in-flight batches may hold before fetches wait, see [`memory`]
+    #[serde(default = "default_memory_cap")]
+    pub memory_cap_bytes: usize,
+    /// Whether scraped titles and descriptions keep their emoji, see [`normalize`]
+    #[serde(default)]
+    pub emoji: EmojiPolicy,
+}
+
+fn default_memory_cap() -> usize {
+    memory::DEFAULT_CAP_BYTES
 }
 
 impl Default for EngineConfig {
@@ -77,105 +303,596 @@ impl Default for EngineConfig {
             proxy_rotation_enabled: true,
             user_agent_rotation: true,
             cache_duration_secs: 3600,
+            memory_cap_bytes: memory::DEFAULT_CAP_BYTES,
+            emoji: EmojiPolicy::default(),
+        }
+    }
+}
+
+impl EngineConfig {
+    /// Defaults, with proxy rotation switched by `PROXY_ROTATION_ENABLED`, the
+    /// memory cap set by `SCRAPE_MEMORY_CAP_MB` and emoji kept or stripped by
+    /// `COUPON_EMOJI` (`strip` or `keep`)
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+        if let Ok(value) = std::env::var("PROXY_ROTATION_ENABLED") {
+            match crate::config::parse_bool(&value) {
+                Ok(enabled) => config.proxy_rotation_enabled = enabled,
+                Err(e) => tracing::warn!(error = %e, "Ignoring PROXY_ROTATION_ENABLED"),
+            }
         }
+        if let Some(megabytes) = std::env::var("SCRAPE_MEMORY_CAP_MB").ok().and_then(|value| value.trim().parse::<usize>().ok()) {
+            config.memory_cap_bytes = megabytes.max(1) * 1024 * 1024;
+        }
+        if let Ok(value) = std::env::var("COUPON_EMOJI") {
+            match EmojiPolicy::parse(&value) {
+                Ok(emoji) => config.emoji = emoji,
+                Err(e) => tracing::warn!(error = %e, "Ignoring COUPON_EMOJI"),
+            }
+        }
+        config
+    }
+
+    /// How coupon text is cleaned up before validation
+    pub fn normalizer(&self) -> TextNormalizer {
+        TextNormalizer::new(self.emoji)
     }
 }
 
 /// Main coupon aggregation engine
+///
+/// Every stage is a trait object so callers can swap in their own components via
+/// [`CouponEngineBuilder`], e.g. fakes in tests or an offline, parse-only worker.
 pub struct CouponEngine {
     config: EngineConfig,
-    scraper: Arc<scraper::Scraper>,
-    parser: Arc<parser::Parser>,
-    validator: Arc<validator::Validator>,
-    deduplicator: Arc<deduplicator::Deduplicator>,
-    rate_limiter: Arc<rate_limiter::RateLimiter>,
-    _proxy_manager: Option<Arc<proxy_manager::ProxyManager>>,
+    fetcher: Arc<dyn Fetcher>,
+    parser: Arc<dyn CouponParser>,
+    validator: Arc<dyn ValidationPolicy>,
+    deduplicator: Arc<dyn CouponDeduplicator>,
+    rate_limiter: Arc<dyn Limiter>,
+    proxies: Option<Arc<dyn ProxySource>>,
+    yield_stats: Option<Arc<YieldStats>>,
+    shadow: Option<Arc<ShadowParser>>,
+    archive: Option<Arc<SnapshotArchive>>,
+    frontier: Option<Arc<ScrapeFrontier>>,
+    opt_outs: Option<Arc<OptOutRegistry>>,
+    controls: Option<Arc<ScraperControls>>,
+    canonical_urls: Arc<CanonicalUrls>,
+    redirects: Arc<RedirectAuditor>,
+    stages: StageHistograms,
+    concurrency: Arc<AdaptiveLimit>,
+    memory: Arc<MemoryBudget>,
+    /// Pages fetched by [`CouponEngine::process_url`], by the URL asked for
+    pages: Mutex<HashMap<String, CachedPage>>,
 }
 
 impl CouponEngine {
+    /// Engine with the default scraper, parser, validator, deduplicator and limiter
     pub fn new(config: EngineConfig) -> Self {
-        let proxy_manager = if config.proxy_rotation_enabled {
-            Some(Arc::new(proxy_manager::ProxyManager::new()))
-        } else {
-            None
-        };
+        Self::builder(config).build()
+    }
 
-        Self {
-            scraper: Arc::new(scraper::Scraper::new(config.clone())),
-            parser: Arc::new(parser::Parser::new()),
-            validator: Arc::new(validator::Validator::new()),
-            deduplicator: Arc::new(deduplicator::Deduplicator::new()),
-            rate_limiter: Arc::new(rate_limiter::RateLimiter::new(config.rate_limit_per_domain)),
-            _proxy_manager: proxy_manager,
-            config,
-        }
+    pub fn builder(config: EngineConfig) -> CouponEngineBuilder {
+        CouponEngineBuilder::new(config)
     }
 
     /// Process a batch of URLs for coupon extraction
+    ///
+    /// URLs are canonicalized first (see [`crate::models::url`]), so variants of one
+    /// page are fetched once; coupons and snapshots are keyed by the canonical URL.
+    /// With a [`ScrapeFrontier`], URLs that recently served an unchanged page are
+    /// skipped, and pages that come back unchanged are not parsed again. With an
+    /// [`OptOutRegistry`], URLs of opted-out merchants are not fetched, and pages that
+    /// redirect or point their canonical link to one are not parsed.
+    ///
+    /// Fetches wait while in-flight batches hold more than the memory cap in pages
+    /// and coupons, see [`memory`].
+    ///
+    /// With [`ScraperControls`], each batch starts with the operator's current
+    /// [`controls::EngineSettings`].
+    ///
+    /// Dropping the returned future aborts any fetches still in flight.
     pub async fn process_batch(&self, urls: Vec<String>) -> Result<Vec<RawCoupon>, Box<dyn std::error::Error + Send + Sync>> {
-        let mut all_coupons = Vec::new();
-        
-        // Process URLs concurrently with rate limiting
-        let semaphore = Arc::new(tokio::sync::Semaphore::new(self.config.max_concurrent_requests));
-        let mut tasks: Vec<tokio::task::JoinHandle<Result<Vec<RawCoupon>, Box<dyn std::error::Error + Send + Sync>>>> = Vec::new();
-
-        for url in urls {
-            let sem = semaphore.clone();
-            let scraper = self.scraper.clone();
+        Ok(self.process_batch_detailed(urls).await?.coupons)
+    }
+
+    /// [`CouponEngine::process_batch`], also reporting each URL's outcome and the
+    /// response headers it was served with
+    pub async fn process_batch_detailed(
+        &self,
+        urls: Vec<String>,
+    ) -> Result<BatchResult, Box<dyn std::error::Error + Send + Sync>> {
+        let config = self.config();
+        if self.controls.is_some() {
+            self.concurrency.set_max_limit(config.max_concurrent_requests);
+            self.memory.set_cap(config.memory_cap_bytes);
+        }
+        let mut seen = std::collections::HashSet::new();
+        let urls: Vec<String> = urls
+            .iter()
+            .map(|url| self.canonical_urls.resolve(url))
+            .filter(|url| seen.insert(url.clone()))
+            .collect();
+
+        // Process URLs concurrently, as many at once as the adaptive limit and the
+        // memory cap allow
+        let memory = Arc::new(self.memory.batch(urls.len()));
+        // Each URL's task logs and fetches for the request or job the batch runs for
+        let (trace, span) = (TraceContext::current(), tracing::Span::current());
+        let mut tasks: tokio::task::JoinSet<(usize, UrlOutcome, Option<MemoryHold>)> = tokio::task::JoinSet::new();
+        let mut outcomes = Vec::new();
+
+        for (index, url) in urls.into_iter().enumerate() {
+            if self.opt_outs.as_ref().is_some_and(|opt_outs| opt_outs.blocks(&url, BlockedAt::Fetch)) {
+                outcomes.push((index, UrlOutcome {
+                    error: Some(OPTED_OUT.to_string()),
+                    ..UrlOutcome::new(&url)
+                }));
+                continue;
+            }
+            if let Some(frontier) = &self.frontier {
+                if frontier.is_unchanged(&url).await {
+                    outcomes.push((index, UrlOutcome::unchanged(&url, false, ResponseHeaders::default(), StageTimings::default())));
+                    continue;
+                }
+            }
+            let concurrency = self.concurrency.clone();
+            let memory = memory.clone();
+            let fetcher = self.fetcher.clone();
             let parser = self.parser.clone();
             let validator = self.validator.clone();
             let rate_limiter = self.rate_limiter.clone();
+            let proxies = self.proxies.clone();
+            let shadow = self.shadow.clone();
+            let archive = self.archive.clone();
+            let canonical_urls = self.canonical_urls.clone();
+            let frontier = self.frontier.clone();
+            let redirects = self.redirects.clone();
+            let opt_outs = self.opt_outs.clone();
+            let retry_attempts = config.retry_attempts;
+            let normalizer = config.normalizer();
             
-            let task = tokio::spawn(async move {
-                let _permit = sem.acquire().await.unwrap();
+            tasks.spawn(TraceContext::scope(trace.clone(), async move {
+                let mut held = memory.room().await;
+                let permit = concurrency.acquire().await;
                 
                 // Apply rate limiting per domain
                 if let Ok(domain) = Self::extract_domain(&url) {
                     rate_limiter.wait_if_needed(&domain).await;
                 }
-                
-                // Scrape content
-                match scraper.fetch_content(&url).await {
-                    Ok(content) => {
-                        // Parse coupons from content
-                        match parser.extract_coupons(&content, &url).await {
-                            Ok(coupons) => {
-                                // Validate each coupon
-                                let mut valid_coupons = Vec::new();
-                                for coupon in coupons {
-                                    if validator.is_valid(&coupon).await {
-                                        valid_coupons.push(coupon);
-                                    }
-                                }
-                                Ok(valid_coupons)
+
+                let mut timings = StageTimings::default();
+                let started = std::time::Instant::now();
+                let fetched = Self::fetch_with_failover(fetcher.as_ref(), proxies.as_deref(), &url, retry_attempts).await;
+                timings.record(Stage::Fetch, started.elapsed());
+                permit.record(started.elapsed(), fetched.is_ok());
+
+                let outcome = match fetched {
+                    Ok((content, headers)) => {
+                        held.page(content.len());
+                        let audit = redirects.audit(&url, &headers).await;
+                        if let Some(blocked) = audit.as_ref().filter(|audit| audit.blocked) {
+                            let landed = blocked.chain.last().cloned().unwrap_or_default();
+                            return (index, UrlOutcome {
+                                fetched: true,
+                                error: Some(format!("redirected off the merchant to {}", landed)),
+                                headers,
+                                redirects: audit,
+                                timings,
+                                ..UrlOutcome::new(&url)
+                            }, None);
+                        }
+                        let canonical = canonical_urls.learn(&url, &content);
+                        // Where the page says it lives, through its redirects or canonical link
+                        let discovered = [headers.final_url.as_deref(), Some(canonical.as_str())]
+                            .into_iter()
+                            .flatten()
+                            .filter(|discovered| *discovered != url)
+                            .find(|discovered| opt_outs.as_ref().is_some_and(|opt_outs| opt_outs.blocks(discovered, BlockedAt::Discovery)));
+                        if discovered.is_some() {
+                            return (index, UrlOutcome {
+                                fetched: true,
+                                error: Some(OPTED_OUT.to_string()),
+                                headers,
+                                redirects: audit,
+                                timings,
+                                ..UrlOutcome::new(&url)
+                            }, None);
+                        }
+                        let url = canonical;
+                        let started = std::time::Instant::now();
+                        if let Some(frontier) = &frontier {
+                            if frontier.record(&url, &content).await {
+                                timings.record(Stage::Persist, started.elapsed());
+                                // Its coupons came out of the same page last time
+                                return (index, UrlOutcome::unchanged(&url, true, headers, timings), None);
                             }
-                            Err(e) => {
-                                eprintln!("Failed to parse {}: {}", url, e);
-                                Ok(Vec::new())
+                        }
+                        if let Some(archive) = &archive {
+                            if let Err(e) = archive.store(&url, &content).await {
+                                tracing::warn!(url = %url, error = %e, "Failed to archive a page");
                             }
                         }
+                        if frontier.is_some() || archive.is_some() {
+                            timings.record(Stage::Persist, started.elapsed());
+                        }
+                        let mut outcome = Self::extract_valid(parser.as_ref(), validator.as_ref(), normalizer, &content, &url, headers).await;
+                        if let Some(shadow) = &shadow {
+                            Self::shadow_compare(shadow, validator.as_ref(), normalizer, &content, &url, &outcome).await;
+                        }
+                        held.keep_coupons(&outcome.valid);
+                        outcome.redirects = audit;
+                        outcome.timings.fetch_ms = timings.fetch_ms;
+                        outcome.timings.persist_ms = timings.persist_ms;
+                        outcome
                     }
                     Err(e) => {
-                        eprintln!("Failed to fetch {}: {}", url, e);
-                        Ok(Vec::new())
+                        tracing::warn!(url = %url, error = %e, "Failed to fetch a page");
+                        UrlOutcome {
+                            error: Some(e.to_string()),
+                            timings,
+                            ..UrlOutcome::new(&url)
+                        }
                     }
+                };
+                (index, outcome, Some(held))
+            }).instrument(span.clone()));
+        }
+
+        // Collect results, holding their memory until the batch returns
+        let mut holds = Vec::new();
+        while let Some(result) = tasks.join_next().await {
+            if let Ok((index, outcome, held)) = result {
+                outcomes.push((index, outcome));
+                holds.extend(held);
+            }
+        }
+        outcomes.sort_by_key(|(index, _)| *index);
+
+        if let Some(frontier) = &self.frontier {
+            frontier.persist().await;
+        }
+        self.deduplicate_and_record(outcomes.into_iter().map(|(_, outcome)| outcome).collect())
+            .await
+    }
+
+    /// Coupons on one page, for a shopper waiting on them, e.g. the browser extension
+    ///
+    /// Unlike a batch of one, the page is parsed from the copy this method fetched
+    /// within `cache_duration_secs` when there is one. Otherwise it is fetched ahead of
+    /// batch fetches to the merchant (see [`Limiter::wait_interactive`]), without
+    /// waiting for the concurrency limit or the memory cap, and given up after
+    /// [`INTERACTIVE_TIMEOUT`]. Nothing is recorded: no yield, frontier, snapshot or
+    /// stage timings. Opt-outs apply as in batches; a paused merchant's pages are only
+    /// served from the cache.
+    pub async fn process_url(&self, url: &str) -> PageCoupons {
+        let url = self.canonical_urls.resolve(url);
+        let failed = |error: String, timings: StageTimings| PageCoupons {
+            result: UrlOutcome {
+                error: Some(error),
+                timings,
+                ..UrlOutcome::new(&url)
+            }
+            .into_result()
+            .0,
+            coupons: Vec::new(),
+            cached: false,
+        };
+        if self.opt_outs.as_ref().is_some_and(|opt_outs| opt_outs.blocks(&url, BlockedAt::Fetch)) {
+            return failed(OPTED_OUT.to_string(), StageTimings::default());
+        }
+
+        let config = self.config();
+        let ttl = Duration::from_secs(config.cache_duration_secs);
+        let cached = self.pages.lock().await.get(&url).filter(|page| page.fetched_at.elapsed() < ttl).cloned();
+        let mut timings = StageTimings::default();
+        let (page, cached) = match cached {
+            Some(page) => (page, true),
+            None if self.controls.as_ref().is_some_and(|controls| controls.is_paused(&url)) => {
+                return failed(PAUSED.to_string(), timings);
+            }
+            None => {
+                let started = Instant::now();
+                let fetch = async {
+                    if let Ok(domain) = Self::extract_domain(&url) {
+                        self.rate_limiter.wait_interactive(&domain).await;
+                    }
+                    Self::fetch_with_failover(self.fetcher.as_ref(), self.proxies.as_deref(), &url, config.retry_attempts).await
+                };
+                let fetched = tokio::time::timeout(INTERACTIVE_TIMEOUT, fetch).await;
+                timings.record(Stage::Fetch, started.elapsed());
+                let (content, headers) = match fetched {
+                    Ok(Ok(fetched)) => fetched,
+                    Ok(Err(e)) => return failed(e.to_string(), timings),
+                    Err(_) => return failed(format!("timed out after {}s", INTERACTIVE_TIMEOUT.as_secs()), timings),
+                };
+
+                let redirects = self.redirects.audit(&url, &headers).await;
+                if let Some(blocked) = redirects.as_ref().filter(|audit| audit.blocked) {
+                    let landed = blocked.chain.last().cloned().unwrap_or_default();
+                    return failed(format!("redirected off the merchant to {}", landed), timings);
                 }
-            });
-            
-            tasks.push(task);
+                let page = CachedPage {
+                    url: self.canonical_urls.learn(&url, &content),
+                    content,
+                    headers,
+                    redirects,
+                    fetched_at: Instant::now(),
+                };
+                let mut pages = self.pages.lock().await;
+                if pages.len() >= MAX_CACHED_PAGES && !pages.contains_key(&url) {
+                    if let Some(oldest) = pages.iter().min_by_key(|(_, page)| page.fetched_at).map(|(url, _)| url.clone()) {
+                        pages.remove(&oldest);
+                    }
+                }
+                pages.insert(url.clone(), page.clone());
+                (page, false)
+            }
+        };
+
+        // Where the page says it lives, through its redirects or canonical link
+        let discovered = [page.headers.final_url.as_deref(), Some(page.url.as_str())]
+            .into_iter()
+            .flatten()
+            .filter(|discovered| *discovered != url)
+            .any(|discovered| self.opt_outs.as_ref().is_some_and(|opt_outs| opt_outs.blocks(discovered, BlockedAt::Discovery)));
+        if discovered {
+            return failed(OPTED_OUT.to_string(), timings);
+        }
+
+        let mut outcome = Self::extract_valid(self.parser.as_ref(), self.validator.as_ref(), self.config.normalizer(), &page.content, &page.url, page.headers).await;
+        outcome.fetched = !cached;
+        outcome.redirects = page.redirects;
+        outcome.timings.fetch_ms = timings.fetch_ms;
+        let (mut result, valid) = outcome.into_result();
+        let started = Instant::now();
+        let coupons = match self.deduplicator.deduplicate(valid).await {
+            Ok(coupons) => coupons,
+            Err(e) => return failed(e.to_string(), result.timings),
+        };
+        result.timings.record(Stage::Dedupe, started.elapsed());
+        PageCoupons { result, coupons, cached }
+    }
+
+    /// Fetch `url` through the next proxy, moving on to another proxy (up to
+    /// `max_proxies` in total) when one fails. Without proxies the fetch goes direct.
+    async fn fetch_with_failover(
+        fetcher: &dyn Fetcher,
+        proxies: Option<&dyn ProxySource>,
+        url: &str,
+        max_proxies: u32,
+    ) -> Result<(String, ResponseHeaders), Box<dyn std::error::Error + Send + Sync>> {
+        let Some(proxies) = proxies else {
+            return fetcher.fetch_with_headers(url, None).await;
+        };
+
+        let mut last_error = None;
+        for _ in 0..max_proxies.max(1) {
+            let Some(proxy) = proxies.next_proxy().await else {
+                break;
+            };
+            let fetched = fetcher.fetch_with_headers(url, Some(&proxy)).await;
+            proxies.report(&proxy.url, fetched.is_ok()).await;
+            match fetched {
+                Ok(content) => return Ok(content),
+                Err(e) => {
+                    tracing::warn!(proxy = %proxy.url, url = %url, error = %e, "Proxy fetch failed");
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        match last_error {
+            Some(e) => Err(e),
+            None => fetcher.fetch_with_headers(url, None).await,
+        }
+    }
+
+    /// Parse, validate and deduplicate already-fetched `(source_url, content)` pairs
+    /// without touching the network
+    pub async fn process_documents(
+        &self,
+        documents: Vec<(String, String)>,
+    ) -> Result<Vec<RawCoupon>, Box<dyn std::error::Error + Send + Sync>> {
+        let normalizer = self.config.normalizer();
+        let mut outcomes = Vec::new();
+        for (url, content) in &documents {
+            let outcome = Self::extract_valid(self.parser.as_ref(), self.validator.as_ref(), normalizer, content, url, ResponseHeaders::default()).await;
+            if let Some(shadow) = &self.shadow {
+                Self::shadow_compare(shadow, self.validator.as_ref(), normalizer, content, url, &outcome).await;
+            }
+            outcomes.push(outcome);
+        }
+
+        Ok(self.deduplicate_and_record(outcomes).await?.coupons)
+    }
+
+    /// Read the JSON feed in `reader` a record at a time, without touching the
+    /// network or holding the feed or its coupons whole; take its coupons from the
+    /// returned [`FeedCoupons`]. `reader` is read on a blocking thread. Coupons are
+    /// validated like those of [`process_documents`](Self::process_documents), but
+    /// neither deduplicated nor shadow-parsed.
+    pub fn process_feed(&self, mut reader: impl std::io::Read + Send + 'static, source_url: &str) -> FeedCoupons {
+        let (send, parsed) = tokio::sync::mpsc::channel(FEED_BUFFER);
+        let parser = self.parser.clone();
+        let url = source_url.to_string();
+        let parsing = tokio::task::spawn_blocking(move || {
+            parser.extract_feed(&mut reader, &url, &mut |coupon| {
+                // Only fails once the reader of the coupons is gone
+                let _ = send.blocking_send(coupon);
+            })
+        });
+        FeedCoupons {
+            parsed,
+            parsing,
+            validator: self.validator.clone(),
+            normalizer: self.config.normalizer(),
+            parser_version: self.parser.version().to_string(),
+            extracted: 0,
+        }
+    }
+
+    async fn extract_valid(
+        parser: &dyn CouponParser,
+        validator: &dyn ValidationPolicy,
+        normalizer: TextNormalizer,
+        content: &str,
+        url: &str,
+        headers: ResponseHeaders,
+    ) -> UrlOutcome {
+        let mut outcome = UrlOutcome {
+            fetched: true,
+            headers,
+            ..UrlOutcome::new(url)
+        };
+
+        let started = std::time::Instant::now();
+        let parsed = parser.extract_coupons(content, url).await;
+        outcome.timings.record(Stage::Parse, started.elapsed());
+        match parsed {
+            Ok(coupons) => {
+                outcome.extracted = coupons.len();
+                let started = std::time::Instant::now();
+                for mut coupon in coupons {
+                    normalizer.normalize(&mut coupon);
+                    if validator.is_valid(&coupon).await {
+                        coupon.parser_version = Some(parser.version().to_string());
+                        attach_headers(&mut coupon, &outcome.headers);
+                        outcome.valid.push(coupon);
+                    }
+                }
+                outcome.timings.record(Stage::Validate, started.elapsed());
+            }
+            Err(e) => {
+                tracing::warn!(url = %url, error = %e, "Failed to parse a page");
+                outcome.error = Some(e.to_string());
+            }
+        }
+        outcome
+    }
+
+    /// Parse the page with the shadow parser as well and record how the two compare
+    async fn shadow_compare(
+        shadow: &ShadowParser,
+        validator: &dyn ValidationPolicy,
+        normalizer: TextNormalizer,
+        content: &str,
+        url: &str,
+        current: &UrlOutcome,
+    ) {
+        let Some(domain) = &current.domain else {
+            return;
+        };
+        let candidate = Self::extract_valid(shadow.parser(), validator, normalizer, content, url, ResponseHeaders::default()).await;
+        shadow
+            .record(
+                domain,
+                PageResult {
+                    extracted: current.extracted,
+                    valid: &current.valid,
+                },
+                PageResult {
+                    extracted: candidate.extracted,
+                    valid: &candidate.valid,
+                },
+            )
+            .await;
+    }
+
+    /// Suspicious redirect chains seen recently, newest first
+    pub async fn flagged_redirects(&self) -> Vec<FlaggedChain> {
+        self.redirects.flagged().await
+    }
+
+    /// The configuration batches run with: the engine's own, with the operator's
+    /// overrides when it has [`ScraperControls`]
+    pub fn config(&self) -> EngineConfig {
+        match &self.controls {
+            Some(controls) => controls.settings().apply(&self.config),
+            None => self.config.clone(),
+        }
+    }
+
+    /// How many fetches may run at once right now, and why
+    pub fn concurrency(&self) -> ConcurrencySnapshot {
+        self.concurrency.snapshot()
+    }
+
+    /// Page and coupon bytes held by the batches in flight
+    pub fn memory(&self) -> MemorySnapshot {
+        self.memory.snapshot()
+    }
+
+    /// Time each pipeline stage has taken across the batches processed so far
+    pub fn stages(&self) -> &StageHistograms {
+        &self.stages
+    }
+
+    pub fn parser_version(&self) -> &str {
+        self.parser.version()
+    }
+
+    /// How the shadow parser compares so far, if one is configured
+    pub async fn shadow_report(&self) -> Option<ShadowReport> {
+        match &self.shadow {
+            Some(shadow) => Some(shadow.report().await),
+            None => None,
         }
+    }
+
+    /// Restart the shadow comparison; false when there is no shadow parser
+    pub async fn reset_shadow(&self) -> bool {
+        match &self.shadow {
+            Some(shadow) => {
+                shadow.reset().await;
+                true
+            }
+            None => false,
+        }
+    }
 
-        // Collect results
-        for task in tasks {
-            if let Ok(Ok(coupons)) = task.await {
-                all_coupons.extend(coupons);
+    /// Deduplicate the valid coupons of a batch and record per-merchant yield
+    async fn deduplicate_and_record(
+        &self,
+        outcomes: Vec<UrlOutcome>,
+    ) -> Result<BatchResult, Box<dyn std::error::Error + Send + Sync>> {
+        let mut counts: HashMap<MerchantDomain, YieldCounts> = HashMap::new();
+        let mut all_coupons = Vec::new();
+        let mut urls = Vec::with_capacity(outcomes.len());
+        for mut outcome in outcomes {
+            self.stages.observe(&outcome.timings);
+            if let Some(domain) = outcome.domain.take() {
+                let merchant = counts.entry(domain).or_default();
+                merchant.urls_scraped += 1;
+                merchant.fetch_failures += u32::from(!outcome.fetched);
+                merchant.coupons_extracted += outcome.extracted as u32;
+                merchant.coupons_valid += outcome.valid.len() as u32;
             }
+            let (result, valid) = outcome.into_result();
+            urls.push(result);
+            all_coupons.extend(valid);
         }
 
-        // Deduplicate coupons
+        let started = std::time::Instant::now();
         let unique_coupons = self.deduplicator.deduplicate(all_coupons).await?;
-        
-        Ok(unique_coupons)
+        let deduped = started.elapsed();
+        self.stages.observe_stage(Stage::Dedupe, deduped);
+        for url in &mut urls {
+            url.timings.record(Stage::Dedupe, deduped);
+        }
+
+        if let Some(yield_stats) = &self.yield_stats {
+            for coupon in &unique_coupons {
+                if let Some(merchant) = counts.get_mut(&coupon.merchant_domain) {
+                    merchant.coupons_new += 1;
+                }
+            }
+            yield_stats.record(counts).await;
+        }
+        Ok(BatchResult {
+            coupons: unique_coupons,
+            urls,
+        })
     }
 
     /// Extract domain from URL
@@ -185,6 +902,232 @@ impl CouponEngine {
     }
 }
 
+/// Keep the response headers a coupon was scraped with under `metadata.response`
+fn attach_headers(coupon: &mut RawCoupon, headers: &ResponseHeaders) {
+    if headers.is_empty() {
+        return;
+    }
+    if coupon.metadata.is_null() {
+        coupon.metadata = serde_json::json!({});
+    }
+    if let (Some(metadata), Ok(headers)) = (coupon.metadata.as_object_mut(), serde_json::to_value(headers)) {
+        metadata.insert("response".to_string(), headers);
+    }
+}
+
+/// Builds a [`CouponEngine`], using the default component for anything not supplied
+pub struct CouponEngineBuilder {
+    config: EngineConfig,
+    fetcher: Option<Arc<dyn Fetcher>>,
+    parser: Option<Arc<dyn CouponParser>>,
+    validator: Option<Arc<dyn ValidationPolicy>>,
+    deduplicator: Option<Arc<dyn CouponDeduplicator>>,
+    rate_limiter: Option<Arc<dyn Limiter>>,
+    proxies: Option<Arc<dyn ProxySource>>,
+    yield_stats: Option<Arc<YieldStats>>,
+    shadow_parser: Option<Arc<dyn CouponParser>>,
+    /// Versions of the default parser, for when no parser is supplied
+    parser_version: parser::ParserVersion,
+    shadow_version: Option<parser::ParserVersion>,
+    profiles: Option<Arc<DomainProfiles>>,
+    archive: Option<Arc<SnapshotArchive>>,
+    frontier: Option<Arc<ScrapeFrontier>>,
+    opt_outs: Option<Arc<OptOutRegistry>>,
+    controls: Option<Arc<ScraperControls>>,
+    blocklist: Option<Arc<ExtractionBlocklist>>,
+}
+
+impl CouponEngineBuilder {
+    pub fn new(config: EngineConfig) -> Self {
+        Self {
+            config,
+            fetcher: None,
+            parser: None,
+            validator: None,
+            deduplicator: None,
+            rate_limiter: None,
+            proxies: None,
+            yield_stats: None,
+            shadow_parser: None,
+            parser_version: parser::ParserVersion::CURRENT,
+            shadow_version: None,
+            profiles: None,
+            archive: None,
+            frontier: None,
+            opt_outs: None,
+            controls: None,
+            blocklist: None,
+        }
+    }
+
+    pub fn fetcher(mut self, fetcher: Arc<dyn Fetcher>) -> Self {
+        self.fetcher = Some(fetcher);
+        self
+    }
+
+    /// Fail every fetch; combine with [`CouponEngine::process_documents`] for parse-only workers
+    pub fn offline(self) -> Self {
+        self.fetcher(Arc::new(scraper::OfflineFetcher))
+    }
+
+    pub fn parser(mut self, parser: Arc<dyn CouponParser>) -> Self {
+        self.parser = Some(parser);
+        self
+    }
+
+    /// Run `parser` alongside the main parser and compare, without using its coupons
+    pub fn shadow_parser(mut self, parser: Arc<dyn CouponParser>) -> Self {
+        self.shadow_parser = Some(parser);
+        self
+    }
+
+    /// Use the default parser at `PARSER_VERSION` (default [`ParserVersion::CURRENT`]),
+    /// shadowed by `PARSER_SHADOW_VERSION` when set
+    ///
+    /// [`ParserVersion::CURRENT`]: parser::ParserVersion::CURRENT
+    pub fn parsers_from_env(mut self) -> Self {
+        let version = parser::ParserVersion::from_env;
+        self.parser_version = version("PARSER_VERSION").unwrap_or(parser::ParserVersion::CURRENT);
+        self.shadow_version = version("PARSER_SHADOW_VERSION").filter(|candidate| *candidate != self.parser_version);
+        self
+    }
+
+    /// Give the default parsers the selectors from `profiles`
+    pub fn domain_profiles(mut self, profiles: Arc<DomainProfiles>) -> Self {
+        self.profiles = Some(profiles);
+        self
+    }
+
+    pub fn validator(mut self, validator: Arc<dyn ValidationPolicy>) -> Self {
+        self.validator = Some(validator);
+        self
+    }
+
+    pub fn deduplicator(mut self, deduplicator: Arc<dyn CouponDeduplicator>) -> Self {
+        self.deduplicator = Some(deduplicator);
+        self
+    }
+
+    /// Use the default deduplicator with a different strategy
+    pub fn dedup_strategy(self, strategy: deduplicator::DeduplicationStrategy) -> Self {
+        self.deduplicator(Arc::new(deduplicator::Deduplicator::with_strategy(strategy)))
+    }
+
+    pub fn rate_limiter(mut self, rate_limiter: Arc<dyn Limiter>) -> Self {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
+
+    /// Proxy source to use even when `proxy_rotation_enabled` is off
+    pub fn proxies(mut self, proxies: Arc<dyn ProxySource>) -> Self {
+        self.proxies = Some(proxies);
+        self
+    }
+
+    /// Record per-merchant yield for every batch
+    pub fn yield_stats(mut self, yield_stats: Arc<YieldStats>) -> Self {
+        self.yield_stats = Some(yield_stats);
+        self
+    }
+
+    /// Keep a snapshot of every fetched page for later reprocessing
+    pub fn archive(mut self, archive: Arc<SnapshotArchive>) -> Self {
+        self.archive = Some(archive);
+        self
+    }
+
+    /// Skip URLs whose pages came back unchanged recently
+    pub fn frontier(mut self, frontier: Arc<ScrapeFrontier>) -> Self {
+        self.frontier = Some(frontier);
+        self
+    }
+
+    /// Refuse the URLs of merchants that opted out of scraping
+    pub fn opt_outs(mut self, opt_outs: Arc<OptOutRegistry>) -> Self {
+        self.opt_outs = Some(opt_outs);
+        self
+    }
+
+    /// Run batches with the operator's engine settings
+    pub fn controls(mut self, controls: Arc<ScraperControls>) -> Self {
+        self.controls = Some(controls);
+        self
+    }
+
+    /// Have the default parsers and validator drop what `blocklist` matches
+    pub fn blocklist(mut self, blocklist: Arc<ExtractionBlocklist>) -> Self {
+        self.blocklist = Some(blocklist);
+        self
+    }
+
+    pub fn build(self) -> CouponEngine {
+        let config = self.config;
+        let proxies = self.proxies.or_else(|| {
+            config
+                .proxy_rotation_enabled
+                .then(|| Arc::new(proxy_manager::ProxyManager::new()) as Arc<dyn ProxySource>)
+        });
+
+        let profiles = self.profiles;
+        let redirects = match &profiles {
+            Some(profiles) => RedirectAuditor::new().with_profiles(profiles.clone()),
+            None => RedirectAuditor::new(),
+        };
+        let blocklist = self.blocklist;
+        let default_parser = |version| {
+            let parser = parser::Parser::with_version(version);
+            let parser = match &profiles {
+                Some(profiles) => parser.with_profiles(profiles.clone()),
+                None => parser,
+            };
+            let parser = match &blocklist {
+                Some(blocklist) => parser.with_blocklist(blocklist.clone()),
+                None => parser,
+            };
+            Arc::new(parser) as Arc<dyn CouponParser>
+        };
+        let parser = self.parser.unwrap_or_else(|| default_parser(self.parser_version));
+        let shadow = self
+            .shadow_parser
+            .or_else(|| self.shadow_version.map(default_parser))
+            .map(|candidate| Arc::new(ShadowParser::new(candidate, parser.version())));
+
+        CouponEngine {
+            fetcher: self
+                .fetcher
+                .unwrap_or_else(|| Arc::new(scraper::Scraper::new(config.clone()))),
+            parser,
+            validator: self.validator.unwrap_or_else(|| {
+                let validator = validator::Validator::new();
+                Arc::new(match &blocklist {
+                    Some(blocklist) => validator.with_blocklist(blocklist.clone()),
+                    None => validator,
+                })
+            }),
+            deduplicator: self
+                .deduplicator
+                .unwrap_or_else(|| Arc::new(deduplicator::Deduplicator::new())),
+            rate_limiter: self
+                .rate_limiter
+                .unwrap_or_else(|| Arc::new(rate_limiter::RateLimiter::new(config.rate_limit_per_domain))),
+            proxies,
+            yield_stats: self.yield_stats,
+            shadow,
+            archive: self.archive,
+            frontier: self.frontier,
+            opt_outs: self.opt_outs,
+            controls: self.controls,
+            canonical_urls: Arc::new(CanonicalUrls::new()),
+            redirects: Arc::new(redirects),
+            stages: StageHistograms::new(),
+            concurrency: Arc::new(AdaptiveLimit::new(config.max_concurrent_requests)),
+            memory: Arc::new(MemoryBudget::new(config.memory_cap_bytes)),
+            pages: Mutex::new(HashMap::new()),
+            config,
+        }
+    }
+}
+
 /// Python interop functions (currently disabled - add "python" feature in Cargo.toml to enable)
 #[cfg(feature = "python")]
 #[allow(dead_code)]
@@ -235,3 +1178,235 @@ pub mod python_bindings {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::async_trait;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    const PAGE: &str = "<html><body><p>Use code SAVE20 for 20% off your order</p></body></html>";
+
+    #[derive(Default)]
+    struct FakeFetcher {
+        calls: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl Fetcher for FakeFetcher {
+        async fn fetch(
+            &self,
+            _url: &str,
+            _proxy: Option<&proxy_manager::ProxyConfig>,
+        ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Ok(PAGE.to_string())
+        }
+    }
+
+    #[derive(Default)]
+    struct RecordingLimiter {
+        domains: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl Limiter for RecordingLimiter {
+        async fn wait_if_needed(&self, domain: &str) {
+            self.domains.lock().unwrap().push(domain.to_string());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_builder_uses_injected_components() {
+        let fetcher = Arc::new(FakeFetcher::default());
+        let limiter = Arc::new(RecordingLimiter::default());
+        let engine = CouponEngine::builder(EngineConfig::default())
+            .fetcher(fetcher.clone())
+            .rate_limiter(limiter.clone())
+            .build();
+
+        let coupons = engine
+            .process_batch(vec![
+                "https://shop.example.com/a".to_string(),
+                "https://shop.example.com/b".to_string(),
+            ])
+            .await
+            .unwrap();
+
+        assert_eq!(fetcher.calls.load(Ordering::SeqCst), 2);
+        assert_eq!(*limiter.domains.lock().unwrap(), vec!["shop.example.com"; 2]);
+        assert_eq!(coupons.len(), 1);
+        assert_eq!(coupons[0].code.as_str(), "SAVE20");
+    }
+
+    #[tokio::test]
+    async fn test_interactive_requests_reuse_fresh_pages() {
+        let fetcher = Arc::new(FakeFetcher::default());
+        let engine = CouponEngine::builder(EngineConfig::default())
+            .fetcher(fetcher.clone())
+            .rate_limiter(Arc::new(RecordingLimiter::default()))
+            .build();
+
+        let first = engine.process_url("https://shop.example.com/cart?utm_source=ext").await;
+        assert!(!first.cached && first.result.fetched && first.result.error.is_none());
+        assert_eq!(first.coupons.len(), 1);
+        assert_eq!(first.coupons[0].code.as_str(), "SAVE20");
+
+        let second = engine.process_url("https://shop.example.com/cart").await;
+        assert!(second.cached);
+        assert_eq!(second.coupons.len(), 1);
+        assert_eq!(fetcher.calls.load(Ordering::SeqCst), 1);
+        // Nothing is recorded for interactive requests
+        assert!(engine.stages().report().bottleneck.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_offline_engine_only_parses_documents() {
+        let engine = CouponEngine::builder(EngineConfig::default()).offline().build();
+
+        let fetched = engine.process_batch(vec!["https://shop.example.com/".to_string()]).await.unwrap();
+        let parsed = engine
+            .process_documents(vec![("https://shop.example.com/".to_string(), PAGE.to_string())])
+            .await
+            .unwrap();
+
+        assert!(fetched.is_empty());
+        assert_eq!(parsed.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_feeds_yield_valid_coupons_as_they_are_read() {
+        let engine = CouponEngine::builder(EngineConfig::default())
+            .offline()
+            .parser(Arc::new(parser::Parser::with_version(parser::ParserVersion::V2)))
+            .build();
+        let feed = r#"{"coupons": [
+            {"code": "save10", "type": "percentage", "value": 10},
+            {"code": "TINY", "type": "percentage", "value": 0.5},
+            {"code": "deal5", "type": "fixed", "value": 5}
+        ]}"#;
+
+        let mut coupons = engine.process_feed(feed.as_bytes(), "https://shop.example.com/");
+        let mut codes = Vec::new();
+        while let Some(coupon) = coupons.next().await {
+            assert_eq!(coupon.parser_version.as_deref(), Some(engine.parser_version()));
+            codes.push(coupon.code.as_str().to_string());
+        }
+        assert_eq!(codes, ["SAVE10", "DEAL5"]);
+        assert_eq!(coupons.finish().await.unwrap(), 3);
+
+        let broken = engine.process_feed(&b"{\"coupons\": [{\"code\": \"SAVE10\"}"[..], "https://shop.example.com/");
+        assert!(broken.finish().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_batches_record_yield_per_merchant() {
+        let yield_stats = Arc::new(yield_stats::YieldStats::new(None));
+        let engine = CouponEngine::builder(EngineConfig::default())
+            .offline()
+            .yield_stats(yield_stats.clone())
+            .build();
+
+        engine.process_batch(vec!["https://shop.example.com/sale".to_string()]).await.unwrap();
+        engine
+            .process_documents(vec![
+                ("https://shop.example.com/".to_string(), PAGE.to_string()),
+                ("https://shop.example.com/copy".to_string(), PAGE.to_string()),
+            ])
+            .await
+            .unwrap();
+
+        let domain = MerchantDomain::parse("shop.example.com").unwrap();
+        let since = yield_stats.now() - chrono::TimeDelta::days(1);
+        let runs = yield_stats.series(&domain, since, yield_stats::YieldInterval::Run).await;
+        let counts: Vec<_> = runs.iter().map(|point| point.counts.clone()).collect();
+        assert_eq!(
+            counts,
+            vec![
+                YieldCounts { urls_scraped: 1, fetch_failures: 1, ..YieldCounts::default() },
+                YieldCounts { urls_scraped: 2, fetch_failures: 0, coupons_extracted: 2, coupons_valid: 2, coupons_new: 1 },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_shadow_parser_is_compared_but_not_used() {
+        let engine = CouponEngine::builder(EngineConfig::default())
+            .offline()
+            .parser(Arc::new(parser::Parser::with_version(parser::ParserVersion::V1)))
+            .shadow_parser(Arc::new(parser::Parser::with_version(parser::ParserVersion::V2)))
+            .build();
+        let feed = r#"{"offers": [
+            {"code": "TENT15", "title": "15% off tents"},
+            {"code": "SHIPFREE", "title": "Shipping", "discountType": "free_shipping"}
+        ]}"#;
+
+        let coupons = engine
+            .process_documents(vec![
+                ("https://shop.example.com/feed".to_string(), feed.to_string()),
+                ("https://shop.example.com/".to_string(), PAGE.to_string()),
+            ])
+            .await
+            .unwrap();
+
+        // v1 leaves feed coupons without a discount, so only the page's code is valid
+        assert_eq!(coupons.len(), 1);
+        assert_eq!(coupons[0].parser_version.as_deref(), Some("v1"));
+
+        let report = engine.shadow_report().await.unwrap();
+        assert_eq!((report.current_version.as_str(), report.candidate_version.as_str()), ("v1", "v2"));
+        let shop = &report.merchants[0];
+        assert_eq!((shop.pages, shop.current_valid, shop.candidate_valid, shop.yield_delta), (2, 1, 3, 2));
+        assert_eq!(shop.only_in_candidate, vec!["SHIPFREE".to_string(), "TENT15".to_string()]);
+
+        assert!(engine.reset_shadow().await);
+        assert!(engine.shadow_report().await.unwrap().merchants.is_empty());
+    }
+    struct CdnSite;
+
+    #[async_trait]
+    impl Fetcher for CdnSite {
+        async fn fetch(
+            &self,
+            _url: &str,
+            _proxy: Option<&proxy_manager::ProxyConfig>,
+        ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+            Ok(PAGE.to_string())
+        }
+
+        async fn fetch_with_headers(
+            &self,
+            url: &str,
+            proxy: Option<&proxy_manager::ProxyConfig>,
+        ) -> Result<(String, ResponseHeaders), Box<dyn std::error::Error + Send + Sync>> {
+            let headers = ResponseHeaders {
+                final_url: Some("https://shop.example.com/uk/sale".to_string()),
+                cf_ray: Some("8a1b2c3d4e5f-LHR".to_string()),
+                x_cache: Some("HIT".to_string()),
+                ..ResponseHeaders::default()
+            };
+            Ok((self.fetch(url, proxy).await?, headers))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_response_headers_reach_coupons_and_url_results() {
+        let engine = CouponEngine::builder(EngineConfig::default())
+            .fetcher(Arc::new(CdnSite))
+            .rate_limiter(Arc::new(RecordingLimiter::default()))
+            .build();
+
+        let batch = engine
+            .process_batch_detailed(vec!["https://shop.example.com/sale?utm_source=mail".to_string()])
+            .await
+            .unwrap();
+
+        assert_eq!(batch.coupons[0].metadata["response"]["cf_ray"], "8a1b2c3d4e5f-LHR");
+        let result = &batch.urls[0];
+        assert_eq!(result.url, "https://shop.example.com/sale");
+        assert_eq!((result.fetched, result.coupons_valid), (true, 1));
+        assert_eq!(result.headers.final_url.as_deref(), Some("https://shop.example.com/uk/sale"));
+        assert_eq!(result.headers.x_cache.as_deref(), Some("HIT"));
+    }
+}
@@ -0,0 +1,274 @@
+//! Cleaning up scraped coupon titles and descriptions
+//!
+//! Pages dress offers up for people: `🔥🔥 50% OFF!!!`, `Save&nbsp;20&#37;`,
+//! zero-width joiners between letters. Before validation and deduplication every
+//! scraped title and description is, in order:
+//!
+//! - stripped of markup, with HTML entities decoded
+//! - put in Unicode NFC, so `é` and `e` + `◌́` compare equal
+//! - stripped of zero-width and control characters
+//! - stripped of emoji, unless the engine keeps them (see [`EmojiPolicy`])
+//! - cut down to one of each repeated `!`, `?` or `.`, with whitespace collapsed
+//! - recased to sentence case when it is shouting, keeping tokens with digits
+//!   and the coupon's own code as they were
+//!
+//! The text as scraped is kept under `metadata.original` for anything changed.
+
+use serde::{Deserialize, Serialize};
+use unicode_normalization::UnicodeNormalization;
+use utoipa::ToSchema;
+
+use super::RawCoupon;
+
+/// Share of cased letters that must be capitals for text to count as shouting
+const SHOUTING_RATIO: f64 = 0.8;
+/// Text with fewer capitals than this is left alone
+const SHOUTING_MIN_CAPITALS: usize = 3;
+
+/// What to do with emoji in coupon text
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum EmojiPolicy {
+    /// Drop pictographs, flags and their modifiers; `©`, `®` and `™` stay
+    #[default]
+    Strip,
+    Keep,
+}
+
+impl EmojiPolicy {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "strip" => Ok(Self::Strip),
+            "keep" => Ok(Self::Keep),
+            other => Err(format!("expected strip or keep, got {:?}", other)),
+        }
+    }
+}
+
+/// Normalizes coupon text, see the module docs
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TextNormalizer {
+    emoji: EmojiPolicy,
+}
+
+impl TextNormalizer {
+    pub fn new(emoji: EmojiPolicy) -> Self {
+        Self { emoji }
+    }
+
+    /// Normalize the title and description of `coupon` in place, recording what
+    /// they were under `metadata.original`. Returns whether anything changed.
+    pub fn normalize(&self, coupon: &mut RawCoupon) -> bool {
+        let code = coupon.code.as_str().to_string();
+        let mut original = serde_json::Map::new();
+
+        let title = self.normalize_text(&coupon.title, &code);
+        if title != coupon.title {
+            original.insert("title".to_string(), std::mem::replace(&mut coupon.title, title).into());
+        }
+        if let Some(description) = &coupon.description {
+            let normalized = self.normalize_text(description, &code);
+            if &normalized != description {
+                original.insert("description".to_string(), description.clone().into());
+                coupon.description = Some(normalized).filter(|normalized| !normalized.is_empty());
+            }
+        }
+
+        if original.is_empty() {
+            return false;
+        }
+        if coupon.metadata.is_null() {
+            coupon.metadata = serde_json::json!({});
+        }
+        if let Some(metadata) = coupon.metadata.as_object_mut() {
+            metadata.insert("original".to_string(), original.into());
+        }
+        true
+    }
+
+    /// `text` normalized, keeping the case of any token equal to `code`
+    pub fn normalize_text(&self, text: &str, code: &str) -> String {
+        let text = strip_markup(text);
+        let text: String = text
+            .nfc()
+            .filter(|c| !is_invisible(*c))
+            .map(|c| if c.is_control() { ' ' } else { c })
+            .filter(|c| self.emoji == EmojiPolicy::Keep || !is_emoji(*c))
+            .collect();
+        let text = collapse(&text);
+        if is_shouting(&text) {
+            sentence_case(&text, code)
+        } else {
+            text
+        }
+    }
+}
+
+/// Text of `text` as an HTML fragment, entities decoded and tags dropped
+fn strip_markup(text: &str) -> String {
+    if !text.contains(['<', '&']) {
+        return text.to_string();
+    }
+    ::scraper::Html::parse_fragment(text).root_element().text().collect()
+}
+
+/// Characters that take up no space: zero-width spaces and joiners, the byte
+/// order mark and soft hyphens
+fn is_invisible(c: char) -> bool {
+    matches!(c, '\u{00AD}' | '\u{180E}' | '\u{200B}'..='\u{200F}' | '\u{2060}'..='\u{2064}' | '\u{FEFF}')
+}
+
+fn is_emoji(c: char) -> bool {
+    matches!(
+        c as u32,
+        // Arrows, clocks and media buttons drawn as emoji
+        0x231A..=0x231B
+            | 0x23E9..=0x23F3
+            | 0x23F8..=0x23FA
+            // Miscellaneous symbols and dingbats
+            | 0x2600..=0x27BF
+            | 0x2B05..=0x2B07
+            | 0x2B1B..=0x2B1C
+            | 0x2B50
+            | 0x2B55
+            | 0x3030
+            | 0x303D
+            | 0x3297
+            | 0x3299
+            // Variation selectors and the keycap
+            | 0xFE0E..=0xFE0F
+            | 0x20E3
+            // Pictographs, emoticons, flags and skin tones
+            | 0x1F000..=0x1FAFF
+            // Tags, as in subdivision flags
+            | 0xE0020..=0xE007F
+    )
+}
+
+/// Repeated `!`, `?` and `.` cut to one (an ellipsis stays), whitespace
+/// collapsed and trimmed
+fn collapse(text: &str) -> String {
+    let mut collapsed = String::with_capacity(text.len());
+    let mut run: Option<(char, usize)> = None;
+    for word in text.split_whitespace() {
+        if !collapsed.is_empty() {
+            collapsed.push(' ');
+        }
+        for c in word.chars() {
+            run = match run {
+                Some((previous, count)) if previous == c => Some((c, count + 1)),
+                _ => Some((c, 1)),
+            };
+            let count = run.map_or(1, |(_, count)| count);
+            let repeated = match c {
+                '!' | '?' => count > 1,
+                '.' => count > 3,
+                _ => false,
+            };
+            if !repeated {
+                collapsed.push(c);
+            }
+        }
+        run = None;
+    }
+    collapsed
+}
+
+/// Whether `text` is mostly capitals; a single word is an acronym (`BOGO`) or a
+/// code, not shouting
+fn is_shouting(text: &str) -> bool {
+    let upper = text.chars().filter(|c| c.is_uppercase()).count();
+    let lower = text.chars().filter(|c| c.is_lowercase()).count();
+    upper >= SHOUTING_MIN_CAPITALS
+        && upper as f64 >= (upper + lower) as f64 * SHOUTING_RATIO
+        && text.split_whitespace().nth(1).is_some()
+}
+
+/// Lowercase, with the first letter of each sentence capitalized. Tokens with
+/// digits (`SAVE20`, `2X`) and the coupon's `code` keep their case.
+fn sentence_case(text: &str, code: &str) -> String {
+    let mut cased = String::with_capacity(text.len());
+    let mut sentence_start = true;
+    for (index, word) in text.split(' ').enumerate() {
+        if index > 0 {
+            cased.push(' ');
+        }
+        let bare = word.trim_matches(|c: char| !c.is_alphanumeric());
+        let keep = bare.chars().any(|c| c.is_ascii_digit()) || (!code.is_empty() && bare.eq_ignore_ascii_case(code));
+        if keep {
+            cased.push_str(word);
+        } else {
+            for c in word.chars() {
+                if sentence_start && c.is_alphabetic() {
+                    cased.extend(c.to_uppercase());
+                    sentence_start = false;
+                } else {
+                    cased.extend(c.to_lowercase());
+                }
+            }
+        }
+        if word.chars().any(char::is_alphanumeric) {
+            sentence_start = false;
+        }
+        if word.ends_with(['.', '!', '?']) {
+            sentence_start = true;
+        }
+    }
+    cased
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coupon_engine::{DiscountType, SourceType};
+    use crate::models::domain::{CouponCode, MerchantDomain};
+
+    fn clean(text: &str) -> String {
+        TextNormalizer::default().normalize_text(text, "SAVE")
+    }
+
+    #[test]
+    fn test_normalize_text() {
+        assert_eq!(clean("🔥🔥 50% OFF!!!"), "50% off!");
+        assert_eq!(clean("Save&nbsp;20&#37; on <b>shoes</b> &amp; bags"), "Save 20% on shoes & bags");
+        assert_eq!(clean("Fre\u{200B}e deli\u{200D}very\u{FEFF}"), "Free delivery");
+        assert_eq!(clean("Cafe\u{301} deals"), "Caf\u{e9} deals");
+        assert_eq!(clean("Wait for it... really?!?!"), "Wait for it... really?!?!");
+        assert_eq!(clean("USE CODE SAVE FOR 2X POINTS. ENDS SOON!!"), "Use code SAVE for 2X points. Ends soon!");
+        // Acronyms and ordinary capitals are not shouting
+        assert_eq!(clean("BOGO on FREE Shipping weekends"), "BOGO on FREE Shipping weekends");
+        assert_eq!(clean("Dealmate™ members 🇺🇸 ⭐"), "Dealmate™ members");
+        assert_eq!(TextNormalizer::new(EmojiPolicy::Keep).normalize_text("Hot deal 🔥", ""), "Hot deal 🔥");
+    }
+
+    #[test]
+    fn test_normalize_keeps_the_original_in_metadata() {
+        let mut coupon = RawCoupon {
+            code: CouponCode::parse("SAVE20").unwrap(),
+            title: "🔥 20% OFF EVERYTHING 🔥".to_string(),
+            description: Some("🎉🎉".to_string()),
+            discount_type: DiscountType::Percentage,
+            discount_value: Some(20.0),
+            minimum_order: None,
+            maximum_discount: None,
+            valid_from: None,
+            valid_until: None,
+            merchant_name: "Test Store".to_string(),
+            merchant_domain: MerchantDomain::parse("teststore.com").unwrap(),
+            source_url: "https://teststore.com".to_string(),
+            source_type: SourceType::WebScraping,
+            metadata: serde_json::Value::Null,
+            scraped_at: chrono::Utc::now(),
+            parser_version: None,
+        };
+
+        assert!(TextNormalizer::default().normalize(&mut coupon));
+        assert_eq!(coupon.title, "20% off everything");
+        assert_eq!(coupon.description, None);
+        assert_eq!(
+            coupon.metadata["original"],
+            serde_json::json!({"title": "🔥 20% OFF EVERYTHING 🔥", "description": "🎉🎉"})
+        );
+        assert!(!TextNormalizer::default().normalize(&mut coupon));
+    }
+}
@@ -0,0 +1,325 @@
+//! Merchants that opted out of scraping
+//!
+//! A merchant that asks not to be scraped (a takedown request, a lapsed partner
+//! agreement) is added to the [`OptOutRegistry`] through `/admin/opt-outs`, rather
+//! than by editing seeds or profiles. The block is hard and covers the merchant's
+//! subdomains:
+//!
+//! - the scrape job queue drops its URLs when a job is submitted, and again when a
+//!   queued job starts (see [`crate::jobs`]), without retrying them;
+//! - the engine refuses to fetch them, and drops pages whose redirects or canonical
+//!   link lead to an opted-out merchant, so URLs discovered while crawling are
+//!   covered too;
+//! - canary checks and `POST /fetch` skip them.
+//!
+//! Every refused URL is logged and kept, with the registry's changes, in an audit
+//! trail served at `GET /admin/opt-outs/audit`. With `REDIS_URL` set the registry
+//! is shared and re-read every [`REFRESH_INTERVAL`]; otherwise it is persisted to
+//! `SCRAPE_OPT_OUTS_PATH` (default `data/scrape_opt_outs.json`).
+
+use std::collections::{BTreeMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::clock::{self, Clock};
+use crate::models::domain::MerchantDomain;
+use crate::storage::persisted::{PersistedStore, StoreError};
+
+const REDIS_KEY: &str = "scrape_opt_outs";
+const STORE_NAME: &str = "scrape opt-outs";
+/// How often a shared registry is re-read
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+/// Audit entries kept, oldest dropped first
+const MAX_AUDIT: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct OptOut {
+    pub domain: MerchantDomain,
+    pub reason: String,
+    /// Where the request came from, e.g. the takedown ticket or the sender
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reference: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct OptOutRequest {
+    pub reason: String,
+    #[serde(default)]
+    pub reference: Option<String>,
+}
+
+impl OptOutRequest {
+    /// Every opt-out records why it was made
+    pub fn validate(&self) -> Result<(), String> {
+        match self.reason.trim().is_empty() {
+            true => Err("reason must not be empty".to_string()),
+            false => Ok(()),
+        }
+    }
+}
+
+/// Where an opted-out URL was stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockedAt {
+    /// Submitted as part of a scrape job
+    Submit,
+    /// A queued job started after the merchant opted out
+    Queue,
+    /// About to be fetched by the engine
+    Fetch,
+    /// Reached through a redirect or a canonical link
+    Discovery,
+    Canary,
+    /// Requested through `POST /fetch`
+    FetchService,
+    /// A deal's product page, loaded to verify its price
+    PriceCheck,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum OptOutEvent {
+    Added { reason: String },
+    Removed,
+    Blocked { url: String, at: BlockedAt },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct AuditEntry {
+    pub domain: MerchantDomain,
+    #[serde(flatten)]
+    pub event: OptOutEvent,
+    pub recorded_at: DateTime<Utc>,
+}
+
+pub struct OptOutRegistry {
+    opt_outs: RwLock<BTreeMap<MerchantDomain, OptOut>>,
+    /// Every opt-out in the file, one field of the Redis hash each
+    store: PersistedStore<Vec<OptOut>>,
+    audit: Mutex<VecDeque<AuditEntry>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl OptOutRegistry {
+    /// Opt-outs persisted to `path`, or kept in memory only
+    pub fn new(path: Option<PathBuf>) -> Self {
+        Self::with_store(PersistedStore::new(STORE_NAME, REDIS_KEY, path))
+    }
+
+    /// Opt-outs shared through Redis
+    pub fn shared(redis_url: &str) -> Result<Self, StoreError> {
+        Ok(Self::with_store(PersistedStore::shared(STORE_NAME, REDIS_KEY, redis_url)?))
+    }
+
+    fn with_store(store: PersistedStore<Vec<OptOut>>) -> Self {
+        Self {
+            opt_outs: RwLock::new(BTreeMap::new()),
+            store: store.pretty(),
+            audit: Mutex::new(VecDeque::new()),
+            clock: clock::system(),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Share opt-outs through `REDIS_URL` when set, otherwise load them from
+    /// `SCRAPE_OPT_OUTS_PATH` (default `data/scrape_opt_outs.json`)
+    pub async fn from_env() -> Self {
+        let registry = Self::with_store(PersistedStore::from_env(STORE_NAME, REDIS_KEY, "SCRAPE_OPT_OUTS_PATH", "data/scrape_opt_outs.json"));
+        if let Err(e) = registry.reload().await {
+            tracing::warn!(error = %e, "Starting with no scrape opt-outs");
+        }
+        registry
+    }
+
+    /// Replace the local copy with every stored opt-out
+    pub async fn reload(&self) -> Result<(), StoreError> {
+        let stored = match self.store.hash_values(REDIS_KEY)? {
+            Some(stored) => stored,
+            None => match self.store.load().await? {
+                Some(stored) => stored,
+                None => return Ok(()),
+            },
+        };
+        *self.opt_outs.write().unwrap() = stored.into_iter().map(|opt_out| (opt_out.domain.clone(), opt_out)).collect();
+        Ok(())
+    }
+
+    /// Re-read a shared registry every [`REFRESH_INTERVAL`], picking up opt-outs
+    /// added or removed through other instances
+    pub async fn start_background_tasks(self: Arc<Self>) {
+        self.store.refresh_every(self.clock.as_ref(), REFRESH_INTERVAL, || self.reload()).await
+    }
+
+    async fn write(&self, domain: &MerchantDomain, opt_out: Option<&OptOut>) -> Result<(), StoreError> {
+        self.store
+            .write_field(REDIS_KEY, domain.as_str(), opt_out, || {
+                let mut all: Vec<OptOut> = self.list().into_iter().filter(|existing| &existing.domain != domain).collect();
+                all.extend(opt_out.cloned());
+                all.sort_by(|a, b| a.domain.cmp(&b.domain));
+                all
+            })
+            .await
+    }
+
+    /// Every opt-out, by domain
+    pub fn list(&self) -> Vec<OptOut> {
+        self.opt_outs.read().unwrap().values().cloned().collect()
+    }
+
+    /// Opt `domain` out of scraping; an existing opt-out is replaced
+    pub async fn add(&self, domain: MerchantDomain, request: OptOutRequest) -> Result<OptOut, String> {
+        request.validate()?;
+        let reason = request.reason.trim();
+        let opt_out = OptOut {
+            domain: domain.clone(),
+            reason: reason.to_string(),
+            reference: request.reference.map(|r| r.trim().to_string()).filter(|r| !r.is_empty()),
+            created_at: self.clock.now(),
+        };
+
+        let _write = self.store.write_lock().await;
+        self.write(&domain, Some(&opt_out))
+            .await
+            .map_err(|e| format!("failed to store the opt-out: {}", e))?;
+        self.opt_outs.write().unwrap().insert(domain.clone(), opt_out.clone());
+        tracing::info!(merchant = %domain, reason = %opt_out.reason, "Merchant opted out of scraping");
+        self.record(domain, OptOutEvent::Added { reason: opt_out.reason.clone() });
+        Ok(opt_out)
+    }
+
+    /// Allow `domain` to be scraped again; false when it had not opted out
+    pub async fn remove(&self, domain: &MerchantDomain) -> Result<bool, String> {
+        let _write = self.store.write_lock().await;
+        if !self.opt_outs.read().unwrap().contains_key(domain) {
+            return Ok(false);
+        }
+        self.write(domain, None)
+            .await
+            .map_err(|e| format!("failed to delete the opt-out: {}", e))?;
+        self.opt_outs.write().unwrap().remove(domain);
+        tracing::info!(merchant = %domain, "Merchant may be scraped again");
+        self.record(domain.clone(), OptOutEvent::Removed);
+        Ok(true)
+    }
+
+    /// The opted-out merchant `url` belongs to, if any
+    pub fn covering(&self, url: &str) -> Option<MerchantDomain> {
+        let host = MerchantDomain::parse(url).ok()?;
+        let host = host.as_str();
+        self.opt_outs
+            .read()
+            .unwrap()
+            .keys()
+            .find(|domain| {
+                let domain = domain.as_str();
+                host == domain || host.strip_suffix(domain).is_some_and(|sub| sub.ends_with('.'))
+            })
+            .cloned()
+    }
+
+    /// Whether `url` must not be scraped; refusals are logged and audited
+    pub fn blocks(&self, url: &str, at: BlockedAt) -> bool {
+        let Some(domain) = self.covering(url) else {
+            return false;
+        };
+        tracing::warn!(url = %url, at = ?at, merchant = %domain, "Refused to scrape: the merchant opted out");
+        self.record(domain, OptOutEvent::Blocked { url: url.to_string(), at });
+        true
+    }
+
+    fn record(&self, domain: MerchantDomain, event: OptOutEvent) {
+        let mut audit = self.audit.lock().unwrap();
+        if audit.len() >= MAX_AUDIT {
+            audit.pop_front();
+        }
+        audit.push_back(AuditEntry {
+            domain,
+            event,
+            recorded_at: self.clock.now(),
+        });
+    }
+
+    /// This instance's audit trail, newest first, optionally only one merchant's
+    pub fn audit(&self, domain: Option<&MerchantDomain>) -> Vec<AuditEntry> {
+        self.audit
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .filter(|entry| domain.is_none_or(|domain| &entry.domain == domain))
+            .cloned()
+            .collect()
+    }
+}
+
+impl Default for OptOutRegistry {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(reason: &str) -> OptOutRequest {
+        OptOutRequest {
+            reason: reason.to_string(),
+            reference: Some(" TKT-1042 ".to_string()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_blocks_opted_out_merchants_and_their_subdomains() {
+        let path = std::env::temp_dir().join(format!("scrape_opt_outs_{}.json", uuid::Uuid::new_v4()));
+        let registry = OptOutRegistry::new(Some(path.clone()));
+        let shop = MerchantDomain::parse("shop.example.com").unwrap();
+        assert!(registry.add(shop.clone(), request(" ")).await.is_err());
+        let opt_out = registry.add(shop.clone(), request("takedown request")).await.unwrap();
+        assert_eq!(opt_out.reference.as_deref(), Some("TKT-1042"));
+
+        assert!(registry.blocks("https://www.shop.example.com/coupons", BlockedAt::Fetch));
+        assert!(registry.blocks("https://de.shop.example.com/", BlockedAt::Discovery));
+        assert!(!registry.blocks("https://myshop.example.com/", BlockedAt::Fetch));
+        assert!(!registry.blocks("https://example.com/", BlockedAt::Fetch));
+
+        let restarted = OptOutRegistry::new(Some(path.clone()));
+        restarted.reload().await.unwrap();
+        assert_eq!(restarted.list(), vec![opt_out]);
+
+        assert!(registry.remove(&shop).await.unwrap());
+        assert!(!registry.remove(&shop).await.unwrap());
+        assert!(!registry.blocks("https://shop.example.com/coupons", BlockedAt::Fetch));
+
+        let events: Vec<OptOutEvent> = registry.audit(Some(&shop)).into_iter().map(|entry| entry.event).collect();
+        assert_eq!(
+            events,
+            vec![
+                OptOutEvent::Removed,
+                OptOutEvent::Blocked {
+                    url: "https://de.shop.example.com/".to_string(),
+                    at: BlockedAt::Discovery,
+                },
+                OptOutEvent::Blocked {
+                    url: "https://www.shop.example.com/coupons".to_string(),
+                    at: BlockedAt::Fetch,
+                },
+                OptOutEvent::Added {
+                    reason: "takedown request".to_string(),
+                },
+            ]
+        );
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -1,73 +1,271 @@
 //! High-performance coupon parser for HTML, JSON, and CSV content
 
+use axum::async_trait;
+use crate::coupon_engine::feed::{self, FEED_KEYS};
+use crate::coupon_engine::blocklist::ExtractionBlocklist;
+use crate::coupon_engine::profiles::DomainProfiles;
 use crate::coupon_engine::{RawCoupon, DiscountType, SourceType};
+use crate::models::domain::{CouponCode, MerchantDomain};
 use chrono::{DateTime, Utc};
+use aho_corasick::AhoCorasick;
+use deal_service_macros::selector;
 use regex::Regex;
+use rust_decimal::Decimal;
 use scraper::{Html, Selector};
 use serde_json::Value;
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::sync::Arc;
 
+/// Turns fetched content into raw coupons
+#[async_trait]
+pub trait CouponParser: Send + Sync {
+    async fn extract_coupons(
+        &self,
+        content: &str,
+        source_url: &str,
+    ) -> Result<Vec<RawCoupon>, Box<dyn std::error::Error + Send + Sync>>;
+
+    /// Extract the coupons of the JSON feed in `reader`, handing each to `each` as
+    /// it is read. Blocks, so call it off the async runtime (e.g. in
+    /// `spawn_blocking`); this default reads the whole feed and parses it with
+    /// [`extract_coupons`](Self::extract_coupons) on the current runtime.
+    fn extract_feed(
+        &self,
+        reader: &mut dyn std::io::Read,
+        source_url: &str,
+        each: &mut dyn FnMut(RawCoupon),
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut content = String::new();
+        reader.read_to_string(&mut content)?;
+        let coupons = tokio::runtime::Handle::current().block_on(self.extract_coupons(&content, source_url))?;
+        coupons.into_iter().for_each(each);
+        Ok(())
+    }
+
+    /// Recorded on every coupon this parser produces
+    fn version(&self) -> &str {
+        "unversioned"
+    }
+}
+
+/// Revisions of the default parser's definitions. A new revision is added alongside
+/// the old one, shadow-run against live content, then made [`CURRENT`](Self::CURRENT).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ParserVersion {
+    #[default]
+    V1,
+    /// Fills in the discount of coupons found by selectors or in JSON feeds, from
+    /// the feed's discount fields or the coupon's title and description
+    V2,
+}
+
+impl ParserVersion {
+    pub const CURRENT: Self = Self::V1;
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::V1 => "v1",
+            Self::V2 => "v2",
+        }
+    }
+
+    /// Version named by environment variable `name`, ignoring invalid values
+    pub fn from_env(name: &str) -> Option<Self> {
+        let value = std::env::var(name).ok()?;
+        value.parse().map_err(|e| tracing::warn!(variable = name, error = %e, "Ignoring an invalid parser version")).ok()
+    }
+}
+
+impl std::str::FromStr for ParserVersion {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "v1" | "1" => Ok(Self::V1),
+            "v2" | "2" => Ok(Self::V2),
+            other => Err(format!("unknown parser version '{}', expected v1 or v2", other)),
+        }
+    }
+}
+
+/// Default parser: per-domain HTML/JSON parser registry with generic and regex fallbacks
 pub struct Parser {
+    version: ParserVersion,
     html_parsers: HashMap<String, HtmlParser>,
     json_parsers: HashMap<String, JsonParser>,
     regex_patterns: RegexPatterns,
+    profiles: Option<Arc<DomainProfiles>>,
+    blocklist: Option<Arc<ExtractionBlocklist>>,
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Parser {
+    /// The [`CURRENT`](ParserVersion::CURRENT) parser
     pub fn new() -> Self {
+        Self::with_version(ParserVersion::CURRENT)
+    }
+
+    pub fn with_version(version: ParserVersion) -> Self {
         Self {
+            version,
             html_parsers: Self::init_html_parsers(),
             json_parsers: Self::init_json_parsers(),
             regex_patterns: RegexPatterns::new(),
+            profiles: None,
+            blocklist: None,
         }
     }
 
+    /// Use the selectors of a domain's profile in place of its built-in ones
+    pub fn with_profiles(mut self, profiles: Arc<DomainProfiles>) -> Self {
+        self.profiles = Some(profiles);
+        self
+    }
+
+    /// Skip pages matching the blocklist's URL and merchant rules
+    pub fn with_blocklist(mut self, blocklist: Arc<ExtractionBlocklist>) -> Self {
+        self.blocklist = Some(blocklist);
+        self
+    }
+
     pub async fn extract_coupons(
         &self,
         content: &str,
         source_url: &str,
     ) -> Result<Vec<RawCoupon>, Box<dyn std::error::Error + Send + Sync>> {
+        if self.blocklist.as_ref().is_some_and(|blocklist| blocklist.blocks_page(source_url)) {
+            return Ok(Vec::new());
+        }
         let content_type = crate::coupon_engine::scraper::detect_content_type(content);
-        let domain = Self::extract_domain(source_url)?;
+        let page = Page {
+            source_url,
+            domain: Self::extract_domain(source_url)?,
+            scraped_at: Utc::now(),
+        };
 
-        match content_type {
+        let mut coupons = match content_type {
             crate::coupon_engine::scraper::ContentType::Html => {
-                self.parse_html(content, source_url, &domain).await
+                self.parse_html(content, &page).await
             }
             crate::coupon_engine::scraper::ContentType::Json => {
-                self.parse_json(content, source_url, &domain).await
+                self.parse_json(content, &page).await
             }
             crate::coupon_engine::scraper::ContentType::Csv => {
-                self.parse_csv(content, source_url, &domain).await
+                self.parse_csv(content, &page).await
             }
             _ => {
                 // Try to extract coupons using regex patterns
-                self.parse_with_regex(content, source_url, &domain).await
+                self.parse_with_regex(content, &page).await
+            }
+        }?;
+
+        if self.version >= ParserVersion::V2 {
+            for coupon in &mut coupons {
+                self.fill_discount(coupon);
             }
         }
+        Ok(coupons)
+    }
+
+    /// Extract the coupons of a JSON feed read from `reader` a record at a time, handing
+    /// each to `each` as it is read, so neither the feed nor its coupons are ever held
+    /// whole. Blocking; see [`CouponParser::extract_feed`].
+    pub fn extract_feed_coupons(
+        &self,
+        reader: impl std::io::Read,
+        source_url: &str,
+        mut each: impl FnMut(RawCoupon),
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if self.blocklist.as_ref().is_some_and(|blocklist| blocklist.blocks_page(source_url)) {
+            return Ok(());
+        }
+        let page = Page {
+            source_url,
+            domain: Self::extract_domain(source_url)?,
+            scraped_at: Utc::now(),
+        };
+        let json = serde_json::de::IoRead::new(std::io::BufReader::new(reader));
+        self.json_parser(&page).for_each(json, &page, |_, mut coupon| {
+            if self.version >= ParserVersion::V2 {
+                self.fill_discount(&mut coupon);
+            }
+            each(coupon);
+        })?;
+        Ok(())
+    }
+
+    /// Give a coupon with no known discount the one stated in its feed item, or failing
+    /// that, in its title and description
+    fn fill_discount(&self, coupon: &mut RawCoupon) {
+        if coupon.discount_type != DiscountType::Unknown {
+            return;
+        }
+
+        let field = |names: &[&str]| names.iter().find_map(|name| coupon.metadata.get(*name).filter(|v| !v.is_null()));
+        if let Some(kind) = field(&DISCOUNT_TYPE_FIELDS).and_then(|v| v.as_str()).and_then(parse_discount_type) {
+            coupon.discount_type = kind;
+            if coupon.discount_value.is_none() {
+                coupon.discount_value = field(&DISCOUNT_VALUE_FIELDS).and_then(json_number);
+            }
+            return;
+        }
+
+        let text = format!("{} {}", coupon.title, coupon.description.as_deref().unwrap_or_default()).to_lowercase();
+        let info = self.find_discount_info(&text, 0, 0);
+        if info.discount_type != DiscountType::Unknown {
+            coupon.discount_type = info.discount_type;
+            coupon.discount_value = info.discount_value;
+        } else if text.contains("free shipping") {
+            coupon.discount_type = DiscountType::FreeShipping;
+        }
+        if coupon.minimum_order.is_none() {
+            coupon.minimum_order = info.minimum_order;
+        }
     }
 
     async fn parse_html(
         &self,
         content: &str,
-        source_url: &str,
-        domain: &str,
+        page: &Page<'_>,
     ) -> Result<Vec<RawCoupon>, Box<dyn std::error::Error + Send + Sync>> {
         let mut coupons = Vec::new();
+        let profile_selectors = self.profiles.as_ref().and_then(|profiles| profiles.selectors(&page.domain));
+        let domain_parser = self.html_parsers.get(page.domain.as_str());
+        // Most product pages have nothing the generic selectors or the text patterns
+        // could match, and need not even be parsed unless the domain has selectors of
+        // its own
+        let may_have_codes = self.regex_patterns.may_have_codes(content);
+        if !may_have_codes && profile_selectors.is_none() && domain_parser.is_none() {
+            return Ok(coupons);
+        }
         let document = Html::parse_document(content);
 
-        // Try domain-specific parser first
-        if let Some(parser) = self.html_parsers.get(domain) {
-            coupons.extend(parser.parse(&document, source_url)?);
+        // Try domain-specific selectors first, from the domain's profile if it has any
+        if let Some(selectors) = profile_selectors {
+            let extractor = CouponExtractor::generic();
+            for selector in selectors.iter() {
+                coupons.extend(document.select(selector).filter_map(|element| extractor.extract(&element)).map(|draft| draft.into_raw(page)));
+            }
+        } else if let Some(parser) = domain_parser {
+            coupons.extend(parser.parse(&document, page)?);
+        }
+        if !may_have_codes {
+            return Ok(coupons);
         }
 
         // Generic coupon extraction
         let generic_parser = &self.html_parsers["generic"];
-        coupons.extend(generic_parser.parse(&document, source_url)?);
+        coupons.extend(generic_parser.parse(&document, page)?);
 
         // Extract using regex patterns on text content
         let text_content = document.root_element().text().collect::<String>();
-        coupons.extend(self.extract_from_text(&text_content, source_url, domain)?);
+        coupons.extend(self.extract_from_text(&text_content, page)?);
 
         Ok(coupons)
     }
@@ -75,33 +273,29 @@ impl Parser {
     async fn parse_json(
         &self,
         content: &str,
-        source_url: &str,
-        domain: &str,
+        page: &Page<'_>,
     ) -> Result<Vec<RawCoupon>, Box<dyn std::error::Error + Send + Sync>> {
-        let value: Value = serde_json::from_str(content)?;
-        
-        // Try domain-specific parser
-        if let Some(parser) = self.json_parsers.get(domain) {
-            return Ok(parser.parse(&value, source_url)?);
-        }
+        Ok(self.json_parser(page).parse(serde_json::de::StrRead::new(content), page)?)
+    }
 
-        // Generic JSON parsing
-        self.json_parsers["generic"].parse(&value, source_url)
+    /// The domain's JSON parser, else the generic one
+    fn json_parser(&self, page: &Page<'_>) -> &JsonParser {
+        self.json_parsers.get(page.domain.as_str()).unwrap_or(&self.json_parsers["generic"])
     }
 
     async fn parse_csv(
         &self,
         content: &str,
-        source_url: &str,
-        domain: &str,
+        page: &Page<'_>,
     ) -> Result<Vec<RawCoupon>, Box<dyn std::error::Error + Send + Sync>> {
         let mut coupons = Vec::new();
         let mut reader = csv::Reader::from_reader(content.as_bytes());
 
-        for result in reader.records() {
-            let record = result?;
-            if let Some(coupon) = self.parse_csv_record(&record, source_url, domain) {
-                coupons.push(coupon);
+        // One record buffer for the whole export
+        let mut record = csv::StringRecord::new();
+        while reader.read_record(&mut record)? {
+            if let Some(coupon) = self.parse_csv_record(&record, page) {
+                coupons.push(coupon.into_raw(page));
             }
         }
 
@@ -111,57 +305,63 @@ impl Parser {
     async fn parse_with_regex(
         &self,
         content: &str,
-        source_url: &str,
-        domain: &str,
+        page: &Page<'_>,
     ) -> Result<Vec<RawCoupon>, Box<dyn std::error::Error + Send + Sync>> {
-        self.extract_from_text(content, source_url, domain)
+        if !self.regex_patterns.may_have_codes(content) {
+            return Ok(Vec::new());
+        }
+        self.extract_from_text(content, page)
     }
 
     fn extract_from_text(
         &self,
         text: &str,
-        source_url: &str,
-        domain: &str,
+        page: &Page<'_>,
     ) -> Result<Vec<RawCoupon>, Box<dyn std::error::Error + Send + Sync>> {
         let mut coupons = Vec::new();
 
         // Extract coupon codes
         for cap in self.regex_patterns.code_pattern.captures_iter(text) {
             if let Some(code) = cap.get(1) {
-                let code_str = code.as_str().to_uppercase();
-                
+                let Ok(coupon_code) = CouponCode::parse(&uppercase(code.as_str())) else {
+                    continue;
+                };
+
+
                 // Find associated discount info
                 let discount_info = self.find_discount_info(text, code.start(), code.end());
                 
-                let coupon = RawCoupon {
-                    code: code_str.clone(),
-                    title: discount_info.title.unwrap_or_else(|| format!("Coupon Code: {}", code_str)),
-                    description: discount_info.description,
+                let coupon = CouponDraft {
+                    title: discount_info.title.map_or_else(|| Cow::Owned(format!("Coupon Code: {}", coupon_code)), Cow::Owned),
+                    code: coupon_code,
+                    description: discount_info.description.map(Cow::Borrowed),
                     discount_type: discount_info.discount_type,
                     discount_value: discount_info.discount_value,
                     minimum_order: discount_info.minimum_order,
-                    maximum_discount: None,
-                    valid_from: None,
                     valid_until: discount_info.expiry_date,
-                    merchant_name: domain.to_string(),
-                    merchant_domain: domain.to_string(),
-                    source_url: source_url.to_string(),
+                    merchant_name: Some(Cow::Borrowed(page.domain.as_str())),
                     source_type: SourceType::WebScraping,
                     metadata: serde_json::json!({}),
-                    scraped_at: Utc::now(),
                 };
                 
-                coupons.push(coupon);
+                coupons.push(coupon.into_raw(page));
             }
         }
 
         Ok(coupons)
     }
 
-    fn find_discount_info(&self, text: &str, code_start: usize, code_end: usize) -> DiscountInfo {
+    fn find_discount_info<'t>(&self, text: &'t str, code_start: usize, code_end: usize) -> DiscountInfo<'t> {
         let context_range = 200; // Look 200 chars before and after
-        let start = code_start.saturating_sub(context_range);
-        let end = (code_end + context_range).min(text.len());
+        let mut start = code_start.saturating_sub(context_range);
+        let mut end = (code_end + context_range).min(text.len());
+        // Don't split a multi-byte character
+        while !text.is_char_boundary(start) {
+            start -= 1;
+        }
+        while !text.is_char_boundary(end) {
+            end += 1;
+        }
         let context = &text[start..end];
 
         let mut info = DiscountInfo::default();
@@ -194,54 +394,43 @@ impl Parser {
         }
 
         // Extract description
-        info.description = Some(context.trim().to_string());
+        info.description = Some(context.trim());
 
         info
     }
 
-    fn parse_csv_record(
+    fn parse_csv_record<'r>(
         &self,
-        record: &csv::StringRecord,
-        source_url: &str,
-        domain: &str,
-    ) -> Option<RawCoupon> {
+        record: &'r csv::StringRecord,
+        page: &'r Page<'_>,
+    ) -> Option<CouponDraft<'r>> {
         // Assuming standard CSV format with columns: code, title, discount_type, discount_value, expiry
         if record.len() < 2 {
             return None;
         }
 
-        let code = record.get(0)?.trim().to_uppercase();
-        let title = record.get(1).map(|s| s.trim().to_string())
-            .unwrap_or_else(|| format!("Coupon: {}", code));
+        let code = CouponCode::parse(&uppercase(record.get(0)?)).ok()?;
+        let title = record.get(1).map(|s| Cow::Borrowed(s.trim()))
+            .unwrap_or_else(|| Cow::Owned(format!("Coupon: {}", code)));
 
         let discount_type = record.get(2)
-            .and_then(|s| match s.trim().to_lowercase().as_str() {
-                "percentage" | "percent" | "%" => Some(DiscountType::Percentage),
-                "fixed" | "amount" | "$" => Some(DiscountType::Fixed),
-                "free_shipping" | "shipping" => Some(DiscountType::FreeShipping),
-                _ => None,
-            })
+            .and_then(parse_discount_type)
             .unwrap_or(DiscountType::Unknown);
 
         let discount_value = record.get(3)
             .and_then(|s| s.trim().parse().ok());
 
-        Some(RawCoupon {
+        Some(CouponDraft {
             code,
             title,
             description: None,
             discount_type,
             discount_value,
             minimum_order: None,
-            maximum_discount: None,
-            valid_from: None,
             valid_until: None,
-            merchant_name: domain.to_string(),
-            merchant_domain: domain.to_string(),
-            source_url: source_url.to_string(),
+            merchant_name: Some(Cow::Borrowed(page.domain.as_str())),
             source_type: SourceType::WebScraping,
             metadata: serde_json::json!({}),
-            scraped_at: Utc::now(),
         })
     }
 
@@ -266,14 +455,111 @@ impl Parser {
         parsers
     }
 
-    fn extract_domain(url: &str) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    fn extract_domain(url: &str) -> Result<MerchantDomain, Box<dyn std::error::Error + Send + Sync>> {
         let parsed = url::Url::parse(url)?;
-        Ok(parsed.host_str().unwrap_or("").to_string())
+        Ok(MerchantDomain::parse(parsed.host_str().unwrap_or(""))?)
+    }
+}
+
+#[async_trait]
+impl CouponParser for Parser {
+    async fn extract_coupons(
+        &self,
+        content: &str,
+        source_url: &str,
+    ) -> Result<Vec<RawCoupon>, Box<dyn std::error::Error + Send + Sync>> {
+        Parser::extract_coupons(self, content, source_url).await
+    }
+
+    fn extract_feed(
+        &self,
+        reader: &mut dyn std::io::Read,
+        source_url: &str,
+        each: &mut dyn FnMut(RawCoupon),
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.extract_feed_coupons(reader, source_url, each)
+    }
+
+    fn version(&self) -> &str {
+        self.version.as_str()
+    }
+}
+
+/// What every coupon on one page shares, worked out once per page rather than per coupon
+struct Page<'a> {
+    source_url: &'a str,
+    domain: MerchantDomain,
+    scraped_at: DateTime<Utc>,
+}
+
+/// A coupon as extracted, borrowing its text from the page until it is kept
+struct CouponDraft<'a> {
+    code: CouponCode,
+    title: Cow<'a, str>,
+    description: Option<Cow<'a, str>>,
+    discount_type: DiscountType,
+    discount_value: Option<f64>,
+    minimum_order: Option<Decimal>,
+    valid_until: Option<DateTime<Utc>>,
+    /// `None` when the page doesn't say
+    merchant_name: Option<Cow<'a, str>>,
+    source_type: SourceType,
+    metadata: Value,
+}
+
+impl CouponDraft<'_> {
+    fn into_raw(self, page: &Page<'_>) -> RawCoupon {
+        RawCoupon {
+            code: self.code,
+            title: self.title.into_owned(),
+            description: self.description.map(Cow::into_owned),
+            discount_type: self.discount_type,
+            discount_value: self.discount_value,
+            minimum_order: self.minimum_order,
+            maximum_discount: None,
+            valid_from: None,
+            valid_until: self.valid_until,
+            merchant_name: self.merchant_name.map_or_else(|| "Unknown".to_string(), Cow::into_owned),
+            merchant_domain: page.domain.clone(),
+            source_url: page.source_url.to_string(),
+            source_type: self.source_type,
+            metadata: self.metadata,
+            scraped_at: page.scraped_at,
+            parser_version: None,
+        }
+    }
+}
+
+/// `code` in upper case, copied only if it has lower case letters
+fn uppercase(code: &str) -> Cow<'_, str> {
+    if code.chars().any(char::is_lowercase) {
+        Cow::Owned(code.to_uppercase())
+    } else {
+        Cow::Borrowed(code)
+    }
+}
+
+/// Discount type as written in feeds and CSV exports
+fn parse_discount_type(value: &str) -> Option<DiscountType> {
+    match value.trim().to_lowercase().as_str() {
+        "percentage" | "percent" | "%" => Some(DiscountType::Percentage),
+        "fixed" | "amount" | "$" => Some(DiscountType::Fixed),
+        "free_shipping" | "shipping" => Some(DiscountType::FreeShipping),
+        _ => None,
+    }
+}
+
+/// A number from a JSON number or numeric string
+fn json_number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(number) => number.as_f64(),
+        Value::String(text) => text.trim().trim_start_matches('$').trim_end_matches('%').parse().ok(),
+        _ => None,
     }
 }
 
 struct HtmlParser {
-    selectors: Vec<(Selector, CouponExtractor)>,
+    selectors: Vec<(&'static Selector, CouponExtractor)>,
 }
 
 impl HtmlParser {
@@ -281,15 +567,15 @@ impl HtmlParser {
         Self {
             selectors: vec![
                 (
-                    Selector::parse("[class*='coupon-code']").unwrap(),
+                    selector!("[class*='coupon-code']"),
                     CouponExtractor::generic(),
                 ),
                 (
-                    Selector::parse("[data-coupon-code]").unwrap(),
+                    selector!("[data-coupon-code]"),
                     CouponExtractor::data_attribute(),
                 ),
                 (
-                    Selector::parse(".promo-code, .discount-code").unwrap(),
+                    selector!(".promo-code, .discount-code"),
                     CouponExtractor::generic(),
                 ),
             ],
@@ -300,7 +586,7 @@ impl HtmlParser {
         Self {
             selectors: vec![
                 (
-                    Selector::parse("[data-clipboard-text]").unwrap(),
+                    selector!("[data-clipboard-text]"),
                     CouponExtractor::retailmenot(),
                 ),
             ],
@@ -311,20 +597,20 @@ impl HtmlParser {
         Self {
             selectors: vec![
                 (
-                    Selector::parse(".coupon-item").unwrap(),
+                    selector!(".coupon-item"),
                     CouponExtractor::coupons_com(),
                 ),
             ],
         }
     }
 
-    fn parse(&self, document: &Html, source_url: &str) -> Result<Vec<RawCoupon>, Box<dyn std::error::Error + Send + Sync>> {
+    fn parse(&self, document: &Html, page: &Page<'_>) -> Result<Vec<RawCoupon>, Box<dyn std::error::Error + Send + Sync>> {
         let mut coupons = Vec::new();
         
         for (selector, extractor) in &self.selectors {
             for element in document.select(selector) {
-                if let Some(coupon) = extractor.extract(&element, source_url) {
-                    coupons.push(coupon);
+                if let Some(coupon) = extractor.extract(&element) {
+                    coupons.push(coupon.into_raw(page));
                 }
             }
         }
@@ -340,40 +626,35 @@ impl JsonParser {
         Self
     }
 
-    fn parse(&self, value: &Value, source_url: &str) -> Result<Vec<RawCoupon>, Box<dyn std::error::Error + Send + Sync>> {
-        let mut coupons = Vec::new();
-        
-        // Try to find coupon arrays in common patterns
-        if let Some(arr) = value.as_array() {
-            for item in arr {
-                if let Some(coupon) = self.extract_coupon_from_json(item, source_url) {
-                    coupons.push(coupon);
-                }
-            }
-        } else if let Some(obj) = value.as_object() {
-            // Look for common keys that might contain coupons
-            for key in &["coupons", "deals", "offers", "promotions", "data", "results"] {
-                if let Some(Value::Array(arr)) = obj.get(*key) {
-                    for item in arr {
-                        if let Some(coupon) = self.extract_coupon_from_json(item, source_url) {
-                            coupons.push(coupon);
-                        }
-                    }
-                }
+    /// Streams the feed (see [`feed`]), so it is never held whole as a `Value`
+    fn parse<'de>(&self, json: impl serde_json::de::Read<'de>, page: &Page<'_>) -> serde_json::Result<Vec<RawCoupon>> {
+        // Coupons come out grouped by feed key, in `FEED_KEYS` order, whatever order the
+        // keys have in the document
+        let mut found: Vec<Vec<RawCoupon>> = FEED_KEYS.iter().map(|_| Vec::new()).collect();
+        self.for_each(json, page, |key, coupon| found[key].push(coupon))?;
+        Ok(found.into_iter().flatten().collect())
+    }
+
+    /// Hand `each` the coupon of every record as it is read, with the index of its
+    /// feed key, in document order
+    fn for_each<'de>(&self, json: impl serde_json::de::Read<'de>, page: &Page<'_>, mut each: impl FnMut(usize, RawCoupon)) -> serde_json::Result<()> {
+        feed::for_each_record(json, |key, item| {
+            if let Some(coupon) = self.extract_coupon_from_json(item) {
+                each(key, coupon.into_raw(page));
             }
-        }
-        
-        Ok(coupons)
+        })
     }
 
-    fn extract_coupon_from_json(&self, value: &Value, source_url: &str) -> Option<RawCoupon> {
-        let obj = value.as_object()?;
+    fn extract_coupon_from_json(&self, value: Value) -> Option<CouponDraft<'static>> {
+        let Value::Object(mut obj) = value else {
+            return None;
+        };
         
         let code = obj.get("code")
             .or(obj.get("couponCode"))
             .or(obj.get("promoCode"))
-            .and_then(|v| v.as_str())?
-            .to_uppercase();
+            .and_then(|v| v.as_str())
+            .and_then(|code| CouponCode::parse(&uppercase(code)).ok())?;
 
         let title = obj.get("title")
             .or(obj.get("name"))
@@ -382,26 +663,43 @@ impl JsonParser {
             .unwrap_or("Coupon")
             .to_string();
 
-        Some(RawCoupon {
+        let description = obj.get("description").and_then(|v| v.as_str()).map(|d| Cow::Owned(d.to_string()));
+        let discount_value = obj.get("discountValue").and_then(|v| v.as_f64());
+        let minimum_order = obj.get("minimumOrder").and_then(json_amount);
+
+        // Only the discount fields are kept, for `Parser::fill_discount`; the rest of
+        // the record is dropped here
+        obj.retain(|key, _| DISCOUNT_TYPE_FIELDS.contains(&key.as_str()) || DISCOUNT_VALUE_FIELDS.contains(&key.as_str()));
+        Some(CouponDraft {
             code,
-            title,
-            description: obj.get("description").and_then(|v| v.as_str()).map(String::from),
+            title: Cow::Owned(title),
+            description,
             discount_type: DiscountType::Unknown,
-            discount_value: obj.get("discountValue").and_then(|v| v.as_f64()),
-            minimum_order: obj.get("minimumOrder").and_then(|v| v.as_f64()),
-            maximum_discount: None,
-            valid_from: None,
+            discount_value,
+            minimum_order,
             valid_until: None,
-            merchant_name: "Unknown".to_string(),
-            merchant_domain: Parser::extract_domain(source_url).unwrap_or_default(),
-            source_url: source_url.to_string(),
+            merchant_name: None,
             source_type: SourceType::AffiliateApi,
-            metadata: value.clone(),
-            scraped_at: Utc::now(),
+            metadata: Value::Object(obj),
         })
     }
 }
 
+/// Feed fields `Parser::fill_discount` reads the discount type from, in order
+const DISCOUNT_TYPE_FIELDS: [&str; 3] = ["discountType", "discount_type", "type"];
+
+/// Feed fields `Parser::fill_discount` reads the discount value from, in order
+const DISCOUNT_VALUE_FIELDS: [&str; 3] = ["discountValue", "discount_value", "value"];
+
+/// Read a money amount from a JSON number or string without going through `f64`
+fn json_amount(value: &Value) -> Option<Decimal> {
+    match value {
+        Value::Number(number) => number.to_string().parse().ok(),
+        Value::String(text) => text.trim().trim_start_matches('$').parse().ok(),
+        _ => None,
+    }
+}
+
 struct CouponExtractor;
 
 impl CouponExtractor {
@@ -421,62 +719,79 @@ impl CouponExtractor {
         Self
     }
 
-    fn extract(&self, element: &scraper::ElementRef, source_url: &str) -> Option<RawCoupon> {
+    fn extract<'a>(&self, element: &scraper::ElementRef<'a>) -> Option<CouponDraft<'a>> {
         // Extract code from various attributes or text
-        let code = if let Some(attr_code) = element.value().attr("data-coupon-code")
+        let code = match element.value().attr("data-coupon-code")
             .or(element.value().attr("data-clipboard-text")) {
-            attr_code.to_uppercase()
-        } else {
-            let text = element.text().collect::<String>();
-            text.trim().split_whitespace().next()?.to_uppercase()
+            Some(attr_code) => uppercase(attr_code),
+            None => match first_word(element)? {
+                Cow::Borrowed(word) => uppercase(word),
+                Cow::Owned(word) => Cow::Owned(word.to_uppercase()),
+            },
         };
 
-        if code.len() < 3 || code.len() > 50 {
-            return None; // Invalid code length
+        if code.len() < 3 {
+            return None; // Too short to be a code
         }
+        let code = CouponCode::parse(&code).ok()?;
 
         let title = element.value().attr("data-title")
             .or(element.value().attr("title"))
-            .unwrap_or("Coupon Code")
-            .to_string();
+            .unwrap_or("Coupon Code");
 
-        Some(RawCoupon {
+        Some(CouponDraft {
             code,
-            title,
+            title: Cow::Borrowed(title),
             description: None,
             discount_type: DiscountType::Unknown,
             discount_value: None,
             minimum_order: None,
-            maximum_discount: None,
-            valid_from: None,
             valid_until: None,
-            merchant_name: "Unknown".to_string(),
-            merchant_domain: Parser::extract_domain(source_url).unwrap_or_default(),
-            source_url: source_url.to_string(),
+            merchant_name: None,
             source_type: SourceType::WebScraping,
             metadata: serde_json::json!({}),
-            scraped_at: Utc::now(),
         })
     }
 }
 
+/// First whitespace-separated word of an element's text, borrowed from the document
+/// unless it runs across text nodes
+fn first_word<'a>(element: &scraper::ElementRef<'a>) -> Option<Cow<'a, str>> {
+    let mut word: Option<Cow<'a, str>> = None;
+    for text in element.text() {
+        let rest = if word.is_none() { text.trim_start() } else { text };
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let piece = &rest[..end];
+        match &mut word {
+            None if piece.is_empty() => continue,
+            None => word = Some(Cow::Borrowed(piece)),
+            Some(word) if !piece.is_empty() => word.to_mut().push_str(piece),
+            Some(_) => {}
+        }
+        if end < rest.len() {
+            break;
+        }
+    }
+    word
+}
+
 #[derive(Default)]
-struct DiscountInfo {
+struct DiscountInfo<'a> {
     title: Option<String>,
-    description: Option<String>,
+    /// The text around the code
+    description: Option<&'a str>,
     discount_type: DiscountType,
     discount_value: Option<f64>,
-    minimum_order: Option<f64>,
+    minimum_order: Option<Decimal>,
     expiry_date: Option<DateTime<Utc>>,
 }
 
-impl Default for DiscountType {
-    fn default() -> Self {
-        DiscountType::Unknown
-    }
-}
+/// Every generic selector and `code_pattern` needs one of these, in any case
+const CODE_KEYWORDS: [&str; 3] = ["code", "coupon", "promo"];
 
 struct RegexPatterns {
+    /// Finds any of [`CODE_KEYWORDS`] in one pass, far cheaper than the patterns
+    code_keywords: AhoCorasick,
     code_pattern: Regex,
     percentage_pattern: Regex,
     fixed_pattern: Regex,
@@ -486,10 +801,78 @@ struct RegexPatterns {
 impl RegexPatterns {
     fn new() -> Self {
         Self {
+            code_keywords: AhoCorasick::builder()
+                .ascii_case_insensitive(true)
+                .build(CODE_KEYWORDS)
+                .unwrap(),
             code_pattern: Regex::new(r"(?i)(?:code|coupon|promo)[\s:]*([A-Z0-9]{3,20})").unwrap(),
             percentage_pattern: Regex::new(r"(\d+)\s*%\s*off").unwrap(),
             fixed_pattern: Regex::new(r"\$(\d+(?:\.\d{2})?)\s*off").unwrap(),
             minimum_pattern: Regex::new(r"(?i)minimum\s*(?:order|purchase)[\s:]*\$?(\d+(?:\.\d{2})?)").unwrap(),
         }
     }
+
+    /// False when the page cannot hold a code the generic extraction would find.
+    /// A keyword spelled with character references (`&#99;ode`) is not seen.
+    fn may_have_codes(&self, content: &str) -> bool {
+        self.code_keywords.is_match(content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_first_word_borrows_unless_it_spans_text_nodes() {
+        let document = Html::parse_fragment("<span class='a'>  save20 now</span><span class='b'>SA<b>VE</b>30 today</span>");
+        let word = |class: &str| {
+            let element = document.select(&Selector::parse(class).unwrap()).next().unwrap();
+            first_word(&element).unwrap()
+        };
+        assert!(matches!(word(".a"), Cow::Borrowed("save20")));
+        assert!(matches!(word(".b"), Cow::Owned(ref w) if w == "SAVE30"));
+    }
+
+    #[tokio::test]
+    async fn test_feed_items_keep_only_discount_fields_with_one_scrape_time() {
+        let feed = r#"{"coupons": [{"code": "save10", "title": "10% off", "type": "percentage", "value": 10, "image": "x.png"}, {"code": "FREESHIP"}]}"#;
+        let coupons = Parser::new().extract_coupons(feed, "https://feeds.example.com/x").await.unwrap();
+        assert_eq!(coupons.len(), 2);
+        assert_eq!(coupons[0].code.as_str(), "SAVE10");
+        assert_eq!(coupons[0].metadata, serde_json::json!({"type": "percentage", "value": 10}));
+        assert_eq!(coupons[1].title, "Coupon");
+        assert_eq!(coupons[0].scraped_at, coupons[1].scraped_at);
+    }
+
+    #[tokio::test]
+    async fn test_feed_read_from_a_reader_matches_the_text_path() {
+        let feed = r#"{"deals": [{"code": "DEAL5"}], "skip": {"big": [1, 2, 3]}, "coupons": [{"promoCode": "SAVE10"}]}"#;
+        let parser = Parser::new();
+        let url = "https://feeds.example.com/x";
+        let codes = |coupons: Vec<RawCoupon>| coupons.into_iter().map(|c| c.code.as_str().to_string()).collect::<Vec<_>>();
+        assert_eq!(codes(parser.extract_coupons(feed, url).await.unwrap()), ["SAVE10", "DEAL5"]);
+
+        // Streamed in document order, one record at a time
+        let mut streamed = Vec::new();
+        parser.extract_feed_coupons(feed.as_bytes(), url, |coupon| streamed.push(coupon)).unwrap();
+        assert_eq!(codes(streamed), ["DEAL5", "SAVE10"]);
+    }
+
+    #[tokio::test]
+    async fn test_pages_without_code_keywords_skip_generic_extraction_only() {
+        let parser = Parser::new();
+        let codes = |coupons: Vec<RawCoupon>| coupons.into_iter().map(|c| c.code.as_str().to_string()).collect::<Vec<_>>();
+        let product = "<html><body><h1>Headphones</h1><p>SAVE20 on everything</p></body></html>";
+        assert!(!parser.regex_patterns.may_have_codes(product));
+        assert!(parser.extract_coupons(product, "https://shop.example.com/p/1").await.unwrap().is_empty());
+
+        // Domain parsers do not rely on the keywords
+        let listing = "<html><body><button data-clipboard-text='save20'>Reveal</button></body></html>";
+        assert_eq!(codes(parser.extract_coupons(listing, "https://www.retailmenot.com/view/x").await.unwrap()), ["SAVE20"]);
+
+        let with_code = "<html><body><p>Use PROMO: SAVE20 today</p></body></html>";
+        assert!(parser.regex_patterns.may_have_codes(with_code));
+        assert_eq!(codes(parser.extract_coupons(with_code, "https://shop.example.com/p/1").await.unwrap()), ["SAVE20"]);
+    }
 }
@@ -0,0 +1,398 @@
+//! Per-merchant domain profiles
+//!
+//! A profile overrides how one merchant is scraped: the CSS selectors its codes sit
+//! in, its request rate and daily request budget, the proxy country to fetch from,
+//! whether its pages need JavaScript rendering, the reference pages its canary
+//! checks (see [`super::canary`]) and the hosts its pages may redirect to (see
+//! [`super::redirects`]). Profiles are managed through `/admin/domain-profiles` and
+//! take effect without a restart. With `REDIS_URL` set they live in Redis and each
+//! change is published on [`CHANGE_CHANNEL`], so every running instance picks it up
+//! within seconds; otherwise they are persisted to `DOMAIN_PROFILES_PATH` (default
+//! `data/domain_profiles.json`).
+//!
+//! The parser applies the selectors, the rate limiter the rates and the scrape job
+//! queue the budgets (see [`super::budget`]). Proxy country
+//! and rendering are carried for fetchers that support them; the built-in scraper
+//! does neither yet.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use scraper::Selector;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::coupon_engine::budget::MerchantSize;
+use crate::coupon_engine::canary::MAX_CANARY_URLS;
+use crate::coupon_engine::rate_limiter::RateLimiter;
+use crate::models::domain::MerchantDomain;
+use crate::storage::persisted::{PersistedStore, StoreError};
+
+const REDIS_KEY: &str = "domain_profiles";
+const STORE_NAME: &str = "domain profiles";
+/// Redis channel carrying the domain of every changed or deleted profile
+pub const CHANGE_CHANNEL: &str = "domain_profiles:changed";
+/// Wait before resubscribing after the change channel drops
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(5);
+
+/// The editable part of a profile
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct ProfileSettings {
+    /// CSS selectors of elements holding a code; they replace the built-in ones for the domain
+    #[serde(default)]
+    pub selectors: Vec<String>,
+    pub rate_limit_per_minute: Option<u32>,
+    /// Scrape requests per UTC day; the default budget scaled by `size` when unset
+    pub daily_request_budget: Option<u32>,
+    #[serde(default)]
+    pub size: MerchantSize,
+    /// ISO 3166-1 alpha-2 country, e.g. `US`
+    pub proxy_country: Option<String>,
+    #[serde(default)]
+    pub render_js: bool,
+    /// Reference pages fingerprinted daily to catch layout changes
+    #[serde(default)]
+    pub canary_urls: Vec<String>,
+    /// Other domains the merchant's pages may redirect to, e.g. regional stores
+    #[serde(default)]
+    pub redirect_hosts: Vec<String>,
+}
+
+impl ProfileSettings {
+    /// Check the settings and compile the selectors
+    pub fn validate(&self) -> Result<Vec<Selector>, String> {
+        if self.rate_limit_per_minute == Some(0) {
+            return Err("rate_limit_per_minute must be at least 1".to_string());
+        }
+        if self.daily_request_budget == Some(0) {
+            return Err("daily_request_budget must be at least 1".to_string());
+        }
+        if self.canary_urls.len() > MAX_CANARY_URLS {
+            return Err(format!("at most {} canary_urls", MAX_CANARY_URLS));
+        }
+        if let Some(bad) = self
+            .canary_urls
+            .iter()
+            .find(|u| !url::Url::parse(u).is_ok_and(|u| matches!(u.scheme(), "http" | "https")))
+        {
+            return Err(format!("canary URL '{}' is not an http(s) URL", bad));
+        }
+        if let Some(bad) = self.redirect_hosts.iter().find(|host| MerchantDomain::parse(host).is_err()) {
+            return Err(format!("redirect host '{}' is not a domain", bad));
+        }
+        if let Some(country) = &self.proxy_country {
+            if country.len() != 2 || !country.chars().all(|c| c.is_ascii_uppercase()) {
+                return Err(format!("proxy_country '{}' is not a two-letter country code", country));
+            }
+        }
+
+        self.selectors
+            .iter()
+            .map(|selector| Selector::parse(selector).map_err(|e| format!("invalid selector '{}': {}", selector, e)))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct DomainProfile {
+    pub domain: MerchantDomain,
+    #[serde(flatten)]
+    pub settings: ProfileSettings,
+    pub updated_at: DateTime<Utc>,
+}
+
+struct LoadedProfile {
+    profile: DomainProfile,
+    selectors: Arc<Vec<Selector>>,
+}
+
+pub struct DomainProfiles {
+    profiles: RwLock<HashMap<MerchantDomain, LoadedProfile>>,
+    /// Every profile in the file, one field of the Redis hash each
+    store: PersistedStore<Vec<DomainProfile>>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+}
+
+impl DomainProfiles {
+    /// Profiles persisted to `path`, or kept in memory only
+    pub fn new(path: Option<PathBuf>) -> Self {
+        Self::with_store(PersistedStore::new(STORE_NAME, REDIS_KEY, path))
+    }
+
+    /// Profiles shared through Redis, with changes announced on [`CHANGE_CHANNEL`]
+    pub fn shared(redis_url: &str) -> Result<Self, StoreError> {
+        Ok(Self::with_store(PersistedStore::shared(STORE_NAME, REDIS_KEY, redis_url)?))
+    }
+
+    fn with_store(store: PersistedStore<Vec<DomainProfile>>) -> Self {
+        Self {
+            profiles: RwLock::new(HashMap::new()),
+            store: store.with_change_channel(CHANGE_CHANNEL),
+            rate_limiter: None,
+        }
+    }
+
+    /// Apply profile rate limits to `rate_limiter`
+    pub fn with_rate_limiter(mut self, rate_limiter: Arc<RateLimiter>) -> Self {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
+
+    /// Share profiles through `REDIS_URL` when set, otherwise load them from
+    /// `DOMAIN_PROFILES_PATH` (default `data/domain_profiles.json`)
+    pub async fn from_env(rate_limiter: Arc<RateLimiter>) -> Self {
+        let store = PersistedStore::from_env(STORE_NAME, REDIS_KEY, "DOMAIN_PROFILES_PATH", "data/domain_profiles.json");
+        let profiles = Self::with_store(store).with_rate_limiter(rate_limiter);
+
+        if let Err(e) = profiles.reload().await {
+            tracing::warn!(error = %e, "Starting with no domain profiles");
+        }
+        profiles
+    }
+
+    /// Replace the local copy with every stored profile
+    async fn reload(&self) -> Result<(), StoreError> {
+        let stored = match self.store.hash_values(REDIS_KEY)? {
+            Some(stored) => stored,
+            None => match self.store.load().await? {
+                Some(stored) => stored,
+                None => return Ok(()),
+            },
+        };
+
+        let stale: Vec<MerchantDomain> = {
+            let profiles = self.profiles.read().unwrap();
+            profiles.keys().filter(|domain| !stored.iter().any(|p| &p.domain == *domain)).cloned().collect()
+        };
+        for domain in stale {
+            self.apply(&domain, None).await;
+        }
+        for profile in stored {
+            self.apply(&profile.domain.clone(), Some(profile)).await;
+        }
+        Ok(())
+    }
+
+    /// Re-read one profile from Redis after a change notification
+    async fn reload_one(&self, domain: &MerchantDomain) -> Result<(), StoreError> {
+        if !self.store.is_shared() {
+            return Ok(());
+        }
+        let profile = self.store.hash_get(REDIS_KEY, domain.as_str())?;
+        self.apply(domain, profile).await;
+        Ok(())
+    }
+
+    /// Update the local copy and the rate limiter
+    async fn apply(&self, domain: &MerchantDomain, profile: Option<DomainProfile>) {
+        let loaded = profile.and_then(|profile| match profile.settings.validate() {
+            Ok(selectors) => Some(LoadedProfile {
+                profile,
+                selectors: Arc::new(selectors),
+            }),
+            Err(e) => {
+                tracing::warn!(merchant = %domain, error = %e, "Ignoring an invalid domain profile");
+                None
+            }
+        });
+        let rate = loaded.as_ref().and_then(|loaded| loaded.profile.settings.rate_limit_per_minute);
+
+        {
+            let mut profiles = self.profiles.write().unwrap();
+            match loaded {
+                Some(loaded) => profiles.insert(domain.clone(), loaded),
+                None => profiles.remove(domain),
+            };
+        }
+
+        if let Some(rate_limiter) = &self.rate_limiter {
+            // The limiter is keyed by host, which may still carry the `www.`
+            for host in [domain.to_string(), format!("www.{}", domain)] {
+                match rate {
+                    Some(rate) => rate_limiter.set_domain_limit(&host, rate).await,
+                    None => rate_limiter.remove_domain_limit(&host).await,
+                }
+            }
+        }
+    }
+
+    async fn write(&self, domain: &MerchantDomain, profile: Option<&DomainProfile>) -> Result<(), StoreError> {
+        self.store
+            .write_field(REDIS_KEY, domain.as_str(), profile, || {
+                let mut all: Vec<DomainProfile> = self.list().into_iter().filter(|existing| &existing.domain != domain).collect();
+                all.extend(profile.cloned());
+                all
+            })
+            .await
+    }
+
+    /// All profiles, sorted by domain
+    pub fn list(&self) -> Vec<DomainProfile> {
+        let mut profiles: Vec<DomainProfile> = self.profiles.read().unwrap().values().map(|loaded| loaded.profile.clone()).collect();
+        profiles.sort_by(|a, b| a.domain.cmp(&b.domain));
+        profiles
+    }
+
+    pub fn get(&self, domain: &MerchantDomain) -> Option<DomainProfile> {
+        self.profiles.read().unwrap().get(domain).map(|loaded| loaded.profile.clone())
+    }
+
+    /// Compiled selectors for `domain`, if its profile sets any
+    pub fn selectors(&self, domain: &MerchantDomain) -> Option<Arc<Vec<Selector>>> {
+        self.profiles
+            .read()
+            .unwrap()
+            .get(domain)
+            .filter(|loaded| !loaded.selectors.is_empty())
+            .map(|loaded| loaded.selectors.clone())
+    }
+
+    /// Create or replace the profile for `domain`
+    pub async fn put(&self, domain: MerchantDomain, settings: ProfileSettings) -> Result<DomainProfile, String> {
+        settings.validate()?;
+        let profile = DomainProfile {
+            domain: domain.clone(),
+            settings,
+            updated_at: Utc::now(),
+        };
+
+        let _write = self.store.write_lock().await;
+        self.write(&domain, Some(&profile))
+            .await
+            .map_err(|e| format!("failed to store domain profile: {}", e))?;
+        self.apply(&domain, Some(profile.clone())).await;
+        Ok(profile)
+    }
+
+    /// Remove the profile for `domain`; false when there was none
+    pub async fn delete(&self, domain: &MerchantDomain) -> Result<bool, String> {
+        let _write = self.store.write_lock().await;
+        if self.get(domain).is_none() {
+            return Ok(false);
+        }
+        self.write(domain, None)
+            .await
+            .map_err(|e| format!("failed to delete domain profile: {}", e))?;
+        self.apply(domain, None).await;
+        Ok(true)
+    }
+
+    /// Follow changes made by other instances. Only shared profiles change elsewhere,
+    /// so this returns at once for local ones.
+    pub async fn start_background_tasks(self: Arc<Self>) {
+        let Some(client) = self.store.redis() else {
+            return;
+        };
+
+        loop {
+            let (changes, mut changed) = tokio::sync::mpsc::channel::<String>(64);
+            let subscriber = client.clone();
+            let subscription = tokio::task::spawn_blocking(move || -> redis::RedisResult<()> {
+                let mut con = subscriber.get_connection()?;
+                let mut pubsub = con.as_pubsub();
+                pubsub.subscribe(CHANGE_CHANNEL)?;
+                loop {
+                    let domain: String = pubsub.get_message()?.get_payload()?;
+                    if changes.blocking_send(domain).is_err() {
+                        return Ok(());
+                    }
+                }
+            });
+
+            // Catch up on anything missed while not subscribed
+            if let Err(e) = self.reload().await {
+                tracing::warn!(error = %e, "Failed to reload domain profiles");
+            }
+            while let Some(domain) = changed.recv().await {
+                let Ok(domain) = MerchantDomain::parse(&domain) else {
+                    continue;
+                };
+                if let Err(e) = self.reload_one(&domain).await {
+                    tracing::warn!(merchant = %domain, error = %e, "Failed to reload a domain profile");
+                }
+            }
+
+            match subscription.await {
+                Ok(Err(e)) => tracing::warn!(error = %e, "Domain profile change channel dropped"),
+                Err(e) => tracing::warn!(error = %e, "Domain profile subscriber failed"),
+                Ok(Ok(())) => {}
+            }
+            tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validation_rejects_bad_selectors_and_countries() {
+        let settings = |selectors: &[&str], country: Option<&str>| ProfileSettings {
+            selectors: selectors.iter().map(|s| s.to_string()).collect(),
+            proxy_country: country.map(String::from),
+            ..ProfileSettings::default()
+        };
+
+        assert_eq!(settings(&[".code", "[data-promo]"], Some("US")).validate().unwrap().len(), 2);
+        assert!(settings(&[".code", "div[["], None).validate().unwrap_err().contains("div[["));
+        assert!(settings(&[], Some("usa")).validate().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_changes_persist_and_apply_rate_limits() {
+        let path = std::env::temp_dir().join(format!("domain_profiles_{}.json", uuid::Uuid::new_v4()));
+        let limiter = Arc::new(RateLimiter::new(10));
+        let profiles = DomainProfiles::new(Some(path.clone())).with_rate_limiter(limiter.clone());
+        let shop = MerchantDomain::parse("shop.example.com").unwrap();
+
+        let settings = ProfileSettings {
+            selectors: vec![".voucher".to_string()],
+            rate_limit_per_minute: Some(1),
+            ..ProfileSettings::default()
+        };
+        profiles.put(shop.clone(), settings.clone()).await.unwrap();
+        assert_eq!(profiles.selectors(&shop).unwrap().len(), 1);
+
+        limiter.wait_if_needed("www.shop.example.com").await;
+        assert_eq!(limiter.get_current_rate("www.shop.example.com").await, Some(1));
+
+        let reloaded = DomainProfiles::new(Some(path.clone()));
+        reloaded.reload().await.unwrap();
+        assert_eq!(reloaded.get(&shop).unwrap().settings, settings);
+
+        assert!(profiles.delete(&shop).await.unwrap());
+        assert!(profiles.selectors(&shop).is_none());
+        assert_eq!(limiter.get_current_rate("shop.example.com").await, None);
+        reloaded.reload().await.unwrap();
+        assert!(reloaded.list().is_empty());
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn test_parser_uses_profile_selectors() {
+        use crate::coupon_engine::parser::Parser;
+
+        let profiles = Arc::new(DomainProfiles::new(None));
+        let parser = Parser::new().with_profiles(profiles.clone());
+        let page = "<html><body><span class='voucher'>TENT15</span></body></html>";
+        let codes = |coupons: Vec<crate::coupon_engine::RawCoupon>| -> Vec<String> {
+            coupons.into_iter().map(|c| c.code.to_string()).collect()
+        };
+
+        let before = parser.extract_coupons(page, "https://shop.example.com/").await.unwrap();
+        assert!(codes(before).is_empty());
+
+        let settings = ProfileSettings {
+            selectors: vec!["span.voucher".to_string()],
+            ..ProfileSettings::default()
+        };
+        profiles.put(MerchantDomain::parse("shop.example.com").unwrap(), settings).await.unwrap();
+        let after = parser.extract_coupons(page, "https://www.shop.example.com/").await.unwrap();
+        assert_eq!(codes(after), vec!["TENT15"]);
+    }
+}
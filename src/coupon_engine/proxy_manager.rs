@@ -1,5 +1,6 @@
 //! Proxy management module for rotating proxies and handling failures
 
+use axum::async_trait;
 use reqwest::Proxy;
 use std::collections::VecDeque;
 use std::sync::Arc;
@@ -7,6 +8,8 @@ use tokio::sync::Mutex;
 use serde::{Deserialize, Serialize};
 use std::time::{Duration, Instant};
 
+use crate::clock::{self, Clock};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProxyConfig {
     pub url: String,
@@ -22,10 +25,18 @@ pub enum ProxyType {
     Socks5,
 }
 
+/// Supplies proxies for outgoing fetches and learns from their outcomes
+#[async_trait]
+pub trait ProxySource: Send + Sync {
+    async fn next_proxy(&self) -> Option<ProxyConfig>;
+    async fn report(&self, proxy_url: &str, success: bool);
+}
+
 pub struct ProxyManager {
     proxies: Arc<Mutex<VecDeque<ProxyState>>>,
     failed_proxies: Arc<Mutex<Vec<FailedProxy>>>,
     config: ProxyManagerConfig,
+    clock: Arc<dyn Clock>,
 }
 
 struct ProxyState {
@@ -57,6 +68,12 @@ impl Default for ProxyManagerConfig {
     }
 }
 
+impl Default for ProxyManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ProxyManager {
     pub fn new() -> Self {
         Self::with_config(ProxyManagerConfig::default())
@@ -67,9 +84,15 @@ impl ProxyManager {
             proxies: Arc::new(Mutex::new(VecDeque::new())),
             failed_proxies: Arc::new(Mutex::new(Vec::new())),
             config,
+            clock: clock::system(),
         }
     }
 
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
     pub async fn add_proxy(&self, proxy_config: ProxyConfig) {
         let mut proxies = self.proxies.lock().await;
         proxies.push_back(ProxyState {
@@ -103,7 +126,7 @@ impl ProxyManager {
         }
 
         // Rotate to find a proxy that hasn't been used recently
-        let now = Instant::now();
+        let now = self.clock.instant();
         let mut rotations = 0;
         
         loop {
@@ -176,7 +199,7 @@ impl ProxyManager {
             if let Some(proxy_state) = proxies.remove(index) {
                 failed_proxies.push(FailedProxy {
                     config: proxy_state.config,
-                    failed_at: Instant::now(),
+                    failed_at: self.clock.instant(),
                     _reason: reason.to_string(),
                 });
             }
@@ -187,7 +210,7 @@ impl ProxyManager {
         let mut failed_proxies = self.failed_proxies.lock().await;
         let mut proxies = self.proxies.lock().await;
         
-        let now = Instant::now();
+        let now = self.clock.instant();
         let mut recovered = Vec::new();
         
         // Find proxies that can be retried
@@ -282,6 +305,21 @@ impl ProxyManager {
     }
 }
 
+#[async_trait]
+impl ProxySource for ProxyManager {
+    async fn next_proxy(&self) -> Option<ProxyConfig> {
+        self.get_next_proxy().await
+    }
+
+    async fn report(&self, proxy_url: &str, success: bool) {
+        if success {
+            self.mark_success(proxy_url).await;
+        } else {
+            self.mark_failure(proxy_url, "fetch failed").await;
+        }
+    }
+}
+
 #[derive(Debug, Serialize)]
 pub struct ProxyStats {
     pub active_proxies: usize,
@@ -333,6 +371,7 @@ impl ProxyValidator {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::clock::MockClock;
 
     #[tokio::test]
     async fn test_proxy_rotation() {
@@ -383,4 +422,31 @@ mod tests {
         assert_eq!(stats.active_proxies, 0);
         assert_eq!(stats.failed_proxies, 1);
     }
+
+    #[tokio::test]
+    async fn test_failed_proxy_returns_after_retry_delay() {
+        let clock = Arc::new(MockClock::new());
+        let manager = ProxyManager::with_config(ProxyManagerConfig {
+            rotation_interval: Duration::from_secs(1),
+            max_failures: 1,
+            retry_after: Duration::from_secs(300),
+        })
+        .with_clock(clock.clone());
+        let url = "http://test.proxy.com:8080";
+        manager.add_proxy(ProxyConfig {
+            url: url.to_string(),
+            username: None,
+            password: None,
+            proxy_type: ProxyType::Http,
+        }).await;
+
+        manager.mark_failure(url, "Connection refused").await;
+        assert!(manager.get_next_proxy().await.is_none());
+
+        clock.advance(Duration::from_secs(299));
+        assert!(manager.get_next_proxy().await.is_none());
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(manager.get_next_proxy().await.unwrap().url, url);
+    }
 }
@@ -1,13 +1,34 @@
 //! Rate limiting module for controlling request frequency per domain
 
+use axum::async_trait;
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::Mutex;
-use tokio::time::{Duration, Instant, sleep};
+use tokio::time::sleep;
+
+use crate::clock::{self, Clock};
+
+/// Paces requests to a domain; `wait_if_needed` returns once a request may be sent
+#[async_trait]
+pub trait Limiter: Send + Sync {
+    async fn wait_if_needed(&self, domain: &str);
+
+    /// [`Limiter::wait_if_needed`] for a shopper waiting on the response. Limiters
+    /// that can tell them apart let it through ahead of batch fetches.
+    async fn wait_interactive(&self, domain: &str) {
+        self.wait_if_needed(domain).await
+    }
+}
+
+/// Requests per window an interactive request may go over a domain's limit by, as a
+/// share of the limit (at least one); batch fetches then wait for the window to clear
+const INTERACTIVE_HEADROOM: f64 = 0.1;
 
 pub struct RateLimiter {
     limits: Arc<Mutex<HashMap<String, DomainLimit>>>,
     default_rate: u32,
+    clock: Arc<dyn Clock>,
 }
 
 struct DomainLimit {
@@ -21,48 +42,69 @@ impl RateLimiter {
         Self {
             limits: Arc::new(Mutex::new(HashMap::new())),
             default_rate: default_rate_per_minute,
+            clock: clock::system(),
         }
     }
 
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
     pub async fn wait_if_needed(&self, domain: &str) {
-        let mut limits = self.limits.lock().await;
-        
-        let limit = limits.entry(domain.to_string()).or_insert_with(|| {
-            DomainLimit {
-                max_requests: self.default_rate,
-                window_duration: Duration::from_secs(60),
-                request_times: Vec::new(),
-            }
-        });
+        self.wait(domain, false).await
+    }
 
-        // Clean up old request times
-        let now = Instant::now();
-        limit.request_times.retain(|&time| now.duration_since(time) < limit.window_duration);
+    /// Like [`RateLimiter::wait_if_needed`], but allowed [`INTERACTIVE_HEADROOM`]
+    /// over the domain's limit
+    pub async fn wait_interactive(&self, domain: &str) {
+        self.wait(domain, true).await
+    }
 
-        // Check if we need to wait
-        if limit.request_times.len() >= limit.max_requests as usize {
-            // Calculate wait time
-            if let Some(&oldest) = limit.request_times.first() {
-                let elapsed = now.duration_since(oldest);
-                if elapsed < limit.window_duration {
-                    let wait_time = limit.window_duration - elapsed + Duration::from_millis(100);
-                    drop(limits); // Release lock while waiting
-                    sleep(wait_time).await;
-                    
-                    // Re-acquire lock and clean up
-                    let mut limits = self.limits.lock().await;
-                    if let Some(limit) = limits.get_mut(domain) {
-                        let now = Instant::now();
-                        limit.request_times.retain(|&time| now.duration_since(time) < limit.window_duration);
-                    }
+    async fn wait(&self, domain: &str, interactive: bool) {
+        let wait_time = {
+            let mut limits = self.limits.lock().await;
+
+            let limit = limits.entry(domain.to_string()).or_insert_with(|| {
+                DomainLimit {
+                    max_requests: self.default_rate,
+                    window_duration: Duration::from_secs(60),
+                    request_times: Vec::new(),
                 }
+            });
+
+            // Clean up old request times
+            let now = self.clock.instant();
+            limit.request_times.retain(|&time| now.duration_since(time) < limit.window_duration);
+
+            // Check if we need to wait
+            let allowed = match interactive {
+                true => limit.max_requests + ((limit.max_requests as f64 * INTERACTIVE_HEADROOM) as u32).max(1),
+                false => limit.max_requests,
+            };
+            if limit.request_times.len() >= allowed as usize {
+                // Until enough of the window's requests have aged out
+                limit.request_times.get(limit.request_times.len() - allowed as usize).and_then(|&oldest| {
+                    let elapsed = now.duration_since(oldest);
+                    (elapsed < limit.window_duration)
+                        .then(|| limit.window_duration - elapsed + Duration::from_millis(100))
+                })
+            } else {
+                None
             }
+        };
+
+        // Sleep without holding the lock
+        if let Some(wait_time) = wait_time {
+            self.clock.sleep(wait_time).await;
         }
 
         // Record this request
         let mut limits = self.limits.lock().await;
         if let Some(limit) = limits.get_mut(domain) {
-            limit.request_times.push(Instant::now());
+            let now = self.clock.instant();
+            limit.request_times.retain(|&time| now.duration_since(time) < limit.window_duration);
+            limit.request_times.push(now);
         }
     }
 
@@ -78,10 +120,15 @@ impl RateLimiter {
         );
     }
 
+    /// Go back to the default rate for `domain`
+    pub async fn remove_domain_limit(&self, domain: &str) {
+        self.limits.lock().await.remove(domain);
+    }
+
     pub async fn get_current_rate(&self, domain: &str) -> Option<usize> {
         let limits = self.limits.lock().await;
         limits.get(domain).map(|limit| {
-            let now = Instant::now();
+            let now = self.clock.instant();
             limit.request_times.iter()
                 .filter(|&&time| now.duration_since(time) < limit.window_duration)
                 .count()
@@ -106,6 +153,7 @@ pub struct BurstRateLimiter {
     buckets: Arc<Mutex<HashMap<String, TokenBucket>>>,
     default_rate: u32,
     default_burst: u32,
+    clock: Arc<dyn Clock>,
 }
 
 struct TokenBucket {
@@ -120,24 +168,30 @@ impl BurstRateLimiter {
         Self {
             buckets: Arc::new(Mutex::new(HashMap::new())),
             default_rate: default_rate_per_minute,
-            default_burst: default_burst,
+            default_burst,
+            clock: clock::system(),
         }
     }
 
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
     pub async fn acquire(&self, domain: &str, tokens: f64) -> Result<(), RateLimitError> {
         let mut buckets = self.buckets.lock().await;
         
+        let now = self.clock.instant();
         let bucket = buckets.entry(domain.to_string()).or_insert_with(|| {
             TokenBucket {
                 capacity: self.default_burst as f64,
                 tokens: self.default_burst as f64,
                 refill_rate: self.default_rate as f64 / 60.0, // per second
-                last_refill: Instant::now(),
+                last_refill: now,
             }
         });
 
         // Refill tokens
-        let now = Instant::now();
         let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
         bucket.tokens = (bucket.tokens + elapsed * bucket.refill_rate).min(bucket.capacity);
         bucket.last_refill = now;
@@ -163,13 +217,31 @@ impl BurstRateLimiter {
             match self.acquire(domain, tokens).await {
                 Ok(()) => break,
                 Err(RateLimitError::InsufficientTokens { wait_time, .. }) => {
-                    sleep(wait_time + Duration::from_millis(10)).await;
+                    self.clock.sleep(wait_time + Duration::from_millis(10)).await;
                 }
             }
         }
     }
 }
 
+#[async_trait]
+impl Limiter for RateLimiter {
+    async fn wait_if_needed(&self, domain: &str) {
+        RateLimiter::wait_if_needed(self, domain).await
+    }
+
+    async fn wait_interactive(&self, domain: &str) {
+        RateLimiter::wait_interactive(self, domain).await
+    }
+}
+
+#[async_trait]
+impl Limiter for BurstRateLimiter {
+    async fn wait_if_needed(&self, domain: &str) {
+        self.acquire_or_wait(domain, 1.0).await
+    }
+}
+
 #[derive(Debug)]
 pub enum RateLimitError {
     InsufficientTokens {
@@ -226,9 +298,17 @@ impl DistributedRateLimiter {
     }
 }
 
+#[async_trait]
+impl Limiter for DistributedRateLimiter {
+    async fn wait_if_needed(&self, domain: &str) {
+        DistributedRateLimiter::wait_if_needed(self, domain).await
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::clock::MockClock;
 
     #[tokio::test]
     async fn test_basic_rate_limiting() {
@@ -258,4 +338,47 @@ mod tests {
         // Next request should fail
         assert!(limiter.acquire(domain, 1.0).await.is_err());
     }
+
+    #[tokio::test]
+    async fn test_waits_out_the_window_on_the_clock() {
+        let clock = Arc::new(MockClock::new());
+        let limiter = RateLimiter::new(2).with_clock(clock.clone());
+        let start = clock.instant();
+
+        for _ in 0..3 {
+            limiter.wait_if_needed("example.com").await;
+        }
+
+        // The third request waited for the first to leave the one-minute window
+        assert!(clock.instant() - start >= Duration::from_secs(60));
+        assert_eq!(limiter.get_current_rate("example.com").await, Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_interactive_requests_go_ahead_of_batch_fetches() {
+        let clock = Arc::new(MockClock::new());
+        let limiter = RateLimiter::new(2).with_clock(clock.clone());
+        let start = clock.instant();
+
+        limiter.wait_if_needed("example.com").await;
+        limiter.wait_if_needed("example.com").await;
+        limiter.wait_interactive("example.com").await;
+        assert_eq!(clock.instant(), start);
+
+        // The next batch fetch waits for two of the three to leave the window
+        limiter.wait_if_needed("example.com").await;
+        assert!(clock.instant() - start >= Duration::from_secs(60));
+    }
+
+    #[tokio::test]
+    async fn test_burst_bucket_refills_over_time() {
+        let clock = Arc::new(MockClock::new());
+        let limiter = BurstRateLimiter::new(60, 2).with_clock(clock.clone());
+
+        assert!(limiter.acquire("example.com", 2.0).await.is_ok());
+        assert!(limiter.acquire("example.com", 1.0).await.is_err());
+
+        clock.advance(Duration::from_secs(1));
+        assert!(limiter.acquire("example.com", 1.0).await.is_ok());
+    }
 }
@@ -0,0 +1,284 @@
+//! Redirect-chain auditing
+//!
+//! Merchant pages sometimes redirect somewhere else: a regional store, an affiliate
+//! network that rewrites the link, or a parking page once a domain has lapsed.
+//! Coupons parsed from such a page would be attributed to the merchant that was
+//! requested. [`RedirectAuditor`] checks every chain the scraper followed (see
+//! [`ResponseHeaders::redirects`]): hops through affiliate networks and landings on
+//! parking services are flagged, and a page that ends up off the requested
+//! merchant is not parsed unless the merchant's domain profile lists the landing
+//! host in `redirect_hosts`. Recently flagged chains are served at
+//! `GET /admin/redirects`.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use utoipa::ToSchema;
+
+use crate::clock::{self, Clock};
+use crate::coupon_engine::profiles::DomainProfiles;
+use crate::coupon_engine::scraper::ResponseHeaders;
+use crate::models::domain::MerchantDomain;
+
+/// Flagged chains kept for the admin report
+const MAX_FLAGGED: usize = 500;
+/// Click trackers of the big affiliate networks
+const AFFILIATE_HOSTS: &[&str] = &[
+    "awin1.com",
+    "shareasale.com",
+    "linksynergy.com",
+    "anrdoezrs.net",
+    "dpbolvw.net",
+    "jdoqocy.com",
+    "kqzyfj.com",
+    "tkqlhce.com",
+    "skimresources.com",
+    "viglink.com",
+    "sjv.io",
+    "pntra.com",
+    "avantlink.com",
+    "partnerize.com",
+    "prf.hn",
+    "tradedoubler.com",
+    "webgains.com",
+    "impactradius.com",
+];
+/// Domain parking and aftermarket services
+const PARKING_HOSTS: &[&str] = &[
+    "sedoparking.com",
+    "parkingcrew.net",
+    "bodis.com",
+    "above.com",
+    "dan.com",
+    "afternic.com",
+    "hugedomains.com",
+    "parklogic.com",
+    "domainmarket.com",
+    "undeveloped.com",
+];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum RedirectFinding {
+    /// The chain passes through an affiliate network's click tracker
+    AffiliateHop { host: String },
+    /// The chain ends on a domain parking service
+    ParkedDomain { host: String },
+    /// The chain ends outside the requested merchant
+    OffMerchant { expected: MerchantDomain, landed: String },
+}
+
+/// The redirect chain of one fetch and what was found in it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct RedirectAudit {
+    /// Every URL from the one requested to the one that answered
+    pub chain: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub findings: Vec<RedirectFinding>,
+    /// The page is not parsed: it ended up off the merchant
+    pub blocked: bool,
+}
+
+impl RedirectAudit {
+    pub fn is_suspicious(&self) -> bool {
+        !self.findings.is_empty()
+    }
+}
+
+/// A suspicious chain, as listed at `GET /admin/redirects`
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct FlaggedChain {
+    pub url: String,
+    #[serde(flatten)]
+    pub audit: RedirectAudit,
+    pub seen_at: DateTime<Utc>,
+}
+
+pub struct RedirectAuditor {
+    profiles: Option<Arc<DomainProfiles>>,
+    flagged: Mutex<VecDeque<FlaggedChain>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl RedirectAuditor {
+    pub fn new() -> Self {
+        Self {
+            profiles: None,
+            flagged: Mutex::new(VecDeque::new()),
+            clock: clock::system(),
+        }
+    }
+
+    /// Allow each merchant's `redirect_hosts` from `profiles`
+    pub fn with_profiles(mut self, profiles: Arc<DomainProfiles>) -> Self {
+        self.profiles = Some(profiles);
+        self
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Audit the redirects the fetch of `url` followed; `None` when it followed none
+    pub async fn audit(&self, url: &str, headers: &ResponseHeaders) -> Option<RedirectAudit> {
+        if headers.redirects.is_empty() {
+            return None;
+        }
+        let landed_url = headers.final_url.clone().unwrap_or_else(|| url.to_string());
+        let mut chain: Vec<String> = headers.redirects.iter().map(|hop| hop.url.clone()).collect();
+        chain.push(landed_url.clone());
+
+        let mut findings = Vec::new();
+        for host in chain.iter().filter_map(|hop| host_of(hop)) {
+            if let Some(network) = matching(&host, AFFILIATE_HOSTS) {
+                let finding = RedirectFinding::AffiliateHop { host: network.to_string() };
+                if !findings.contains(&finding) {
+                    findings.push(finding);
+                }
+            }
+        }
+        let landed = host_of(&landed_url).unwrap_or_default();
+        if let Some(parking) = matching(&landed, PARKING_HOSTS) {
+            findings.push(RedirectFinding::ParkedDomain { host: parking.to_string() });
+        }
+
+        let mut blocked = false;
+        if let Ok(expected) = MerchantDomain::parse(url) {
+            if !same_merchant(&expected, &landed) && !self.allowed(&expected, &landed) {
+                findings.push(RedirectFinding::OffMerchant {
+                    expected,
+                    landed: landed.clone(),
+                });
+                blocked = true;
+            }
+        }
+
+        let audit = RedirectAudit { chain, findings, blocked };
+        if audit.is_suspicious() {
+            tracing::warn!(
+                url = %url,
+                chain = %audit.chain.join(" -> "),
+                blocked = audit.blocked,
+                "Suspicious redirect chain"
+            );
+            let mut flagged = self.flagged.lock().await;
+            if flagged.len() >= MAX_FLAGGED {
+                flagged.pop_front();
+            }
+            flagged.push_back(FlaggedChain {
+                url: url.to_string(),
+                audit: audit.clone(),
+                seen_at: self.clock.now(),
+            });
+        }
+        Some(audit)
+    }
+
+    fn allowed(&self, merchant: &MerchantDomain, landed: &str) -> bool {
+        let Some(profile) = self.profiles.as_ref().and_then(|profiles| profiles.get(merchant)) else {
+            return false;
+        };
+        profile
+            .settings
+            .redirect_hosts
+            .iter()
+            .filter_map(|host| MerchantDomain::parse(host).ok())
+            .any(|allowed| same_merchant(&allowed, landed))
+    }
+
+    /// Suspicious chains seen recently, newest first
+    pub async fn flagged(&self) -> Vec<FlaggedChain> {
+        self.flagged.lock().await.iter().rev().cloned().collect()
+    }
+}
+
+impl Default for RedirectAuditor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn host_of(url: &str) -> Option<String> {
+    let url = url::Url::parse(url).ok()?;
+    Some(url.host_str()?.trim_end_matches('.').to_ascii_lowercase())
+}
+
+/// The entry of `list` that `host` is or is a subdomain of
+fn matching<'a>(host: &str, list: &[&'a str]) -> Option<&'a str> {
+    list.iter().copied().find(|entry| is_within(host, entry))
+}
+
+fn is_within(host: &str, domain: &str) -> bool {
+    host == domain || host.strip_suffix(domain).is_some_and(|rest| rest.ends_with('.'))
+}
+
+/// Whether `host` belongs to `merchant`: the same domain, a subdomain of it, or the
+/// parent it is a subdomain of (`www.` is already stripped from the merchant)
+fn same_merchant(merchant: &MerchantDomain, host: &str) -> bool {
+    let host = host.strip_prefix("www.").unwrap_or(host);
+    is_within(host, merchant.as_str()) || is_within(merchant.as_str(), host)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coupon_engine::profiles::ProfileSettings;
+    use crate::coupon_engine::scraper::RedirectHop;
+
+    fn chain(hops: &[&str], landed: &str) -> ResponseHeaders {
+        ResponseHeaders {
+            final_url: Some(landed.to_string()),
+            redirects: hops
+                .iter()
+                .map(|url| RedirectHop {
+                    url: url.to_string(),
+                    status: 302,
+                })
+                .collect(),
+            ..ResponseHeaders::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_flags_hijacks_and_blocks_off_merchant_landings() {
+        let profiles = Arc::new(DomainProfiles::new(None));
+        let shop = MerchantDomain::parse("shop.com").unwrap();
+        let settings = ProfileSettings {
+            redirect_hosts: vec!["shop.co.uk".to_string()],
+            ..ProfileSettings::default()
+        };
+        profiles.put(shop, settings).await.unwrap();
+        let auditor = RedirectAuditor::new().with_profiles(profiles);
+
+        let regional = auditor
+            .audit("https://www.shop.com/deals", &chain(&["https://www.shop.com/deals"], "https://uk.shop.co.uk/deals"))
+            .await
+            .unwrap();
+        assert!(!regional.blocked && !regional.is_suspicious());
+
+        let hijacked = auditor
+            .audit(
+                "https://shop.com/deals",
+                &chain(&["https://shop.com/deals", "https://click.linksynergy.com/x?id=1"], "https://rival.com/deals"),
+            )
+            .await
+            .unwrap();
+        assert!(hijacked.blocked);
+        assert_eq!(hijacked.chain.len(), 3);
+        assert_eq!(hijacked.findings[0], RedirectFinding::AffiliateHop { host: "linksynergy.com".to_string() });
+
+        let parked = auditor
+            .audit("https://oldshop.com/", &chain(&["https://oldshop.com/"], "https://www.sedoparking.com/oldshop.com"))
+            .await
+            .unwrap();
+        assert!(parked.blocked);
+        assert!(parked.findings.contains(&RedirectFinding::ParkedDomain { host: "sedoparking.com".to_string() }));
+
+        assert!(auditor.audit("https://shop.com/", &ResponseHeaders::default()).await.is_none());
+        assert_eq!(auditor.flagged().await[0].url, "https://oldshop.com/");
+    }
+}
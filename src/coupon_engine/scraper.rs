@@ -1,15 +1,40 @@
 //! High-performance web scraper with proxy support and error recovery
 
+use axum::async_trait;
 use reqwest::Client;
+use std::sync::Arc;
 use std::time::Duration;
-use tokio::time::sleep;
 use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use crate::clock::{self, Clock};
 use crate::coupon_engine::EngineConfig;
+use crate::coupon_engine::proxy_manager::ProxyConfig;
+use crate::telemetry::TraceContext;
+
+/// Redirects followed before a fetch gives up
+const MAX_REDIRECTS: usize = 10;
+
+/// Fetches the raw content behind a coupon source URL
+#[async_trait]
+pub trait Fetcher: Send + Sync {
+    async fn fetch(&self, url: &str, proxy: Option<&ProxyConfig>) -> Result<String, Box<dyn std::error::Error + Send + Sync>>;
+
+    /// Like [`Fetcher::fetch`], with the response headers worth keeping. Fetchers that
+    /// do not see headers return none.
+    async fn fetch_with_headers(
+        &self,
+        url: &str,
+        proxy: Option<&ProxyConfig>,
+    ) -> Result<(String, ResponseHeaders), Box<dyn std::error::Error + Send + Sync>> {
+        Ok((self.fetch(url, proxy).await?, ResponseHeaders::default()))
+    }
+}
 
 pub struct Scraper {
     config: EngineConfig,
     clients: Vec<Client>,
     user_agents: Vec<String>,
+    clock: Arc<dyn Clock>,
 }
 
 impl Scraper {
@@ -25,8 +50,10 @@ impl Scraper {
         
         // Create clients with different configurations
         for _ in 0..5 {
+            // Redirects are followed by hand so the chain can be recorded
             let mut client_builder = Client::builder()
                 .timeout(Duration::from_secs(config.request_timeout_secs))
+                .redirect(reqwest::redirect::Policy::none())
                 .gzip(true)
                 .deflate(true)
                 .brotli(true);
@@ -56,16 +83,47 @@ impl Scraper {
             config,
             clients,
             user_agents,
+            clock: clock::system(),
         }
     }
 
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
     pub async fn fetch_content(&self, url: &str) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        self.fetch_page(url, None, &CacheValidators::default()).await?.into_body()
+    }
+
+    /// Fetch `url`, sending `validators` as a conditional request. Direct fetches
+    /// are retried with backoff; a proxied fetch is tried once so the caller can
+    /// move on to another proxy.
+    pub async fn fetch_page(
+        &self,
+        url: &str,
+        proxy: Option<&ProxyConfig>,
+        validators: &CacheValidators,
+    ) -> Result<FetchedPage, Box<dyn std::error::Error + Send + Sync>> {
+        if let Some(proxy) = proxy {
+            let mut proxy_setting = reqwest::Proxy::all(&proxy.url)?;
+            if let (Some(username), Some(password)) = (&proxy.username, &proxy.password) {
+                proxy_setting = proxy_setting.basic_auth(username, password);
+            }
+            let client = Client::builder()
+                .timeout(Duration::from_secs(self.config.request_timeout_secs))
+                .redirect(reqwest::redirect::Policy::none())
+                .proxy(proxy_setting)
+                .build()?;
+            return self.fetch_with_client(&client, url, &self.user_agents[0], validators).await;
+        }
+
         let mut last_error = None;
         
         for attempt in 0..self.config.retry_attempts {
             if attempt > 0 {
                 // Exponential backoff
-                sleep(Duration::from_millis(1000 * 2_u64.pow(attempt))).await;
+                self.clock.sleep(Duration::from_millis(1000 * 2_u64.pow(attempt))).await;
             }
 
             // Select random client and user agent
@@ -76,11 +134,11 @@ impl Scraper {
                 self.user_agents[0].clone()
             };
 
-            match self.fetch_with_client(client, url, &user_agent).await {
-                Ok(content) => return Ok(content),
+            match self.fetch_with_client(client, url, &user_agent, validators).await {
+                Ok(page) => return Ok(page),
                 Err(e) => {
+                    tracing::warn!(attempt = attempt + 1, url = %url, error = %e, "Scrape attempt failed");
                     last_error = Some(e);
-                    eprintln!("Attempt {} failed for {}: {:?}", attempt + 1, url, last_error);
                 }
             }
         }
@@ -93,27 +151,172 @@ impl Scraper {
         client: &Client,
         url: &str,
         user_agent: &str,
-    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
-        let response = client
-            .get(url)
-            .header("User-Agent", user_agent)
-            .send()
-            .await?;
+        validators: &CacheValidators,
+    ) -> Result<FetchedPage, Box<dyn std::error::Error + Send + Sync>> {
+        let mut current = url::Url::parse(url)?;
+        let mut redirects = Vec::new();
+        // The request being served, if any, so the merchant's logs can be matched to ours
+        let trace = TraceContext::current();
+        let response = loop {
+            let mut request = client.get(current.clone()).header("User-Agent", user_agent);
+            for (name, value) in trace.iter().flat_map(TraceContext::headers) {
+                request = request.header(name, value);
+            }
+            if let Some(etag) = &validators.etag {
+                request = request.header("If-None-Match", etag);
+            }
+            if let Some(last_modified) = &validators.last_modified {
+                request = request.header("If-Modified-Since", last_modified);
+            }
+            let response = request.send().await?;
+
+            let status = response.status();
+            if !status.is_redirection() || status == reqwest::StatusCode::NOT_MODIFIED {
+                break response;
+            }
+            let location = response
+                .headers()
+                .get(reqwest::header::LOCATION)
+                .and_then(|v| v.to_str().ok())
+                .ok_or_else(|| format!("HTTP {} without a Location header", status))?;
+            if redirects.len() >= MAX_REDIRECTS {
+                return Err(format!("more than {} redirects", MAX_REDIRECTS).into());
+            }
+            let next = current.join(location)?;
+            redirects.push(RedirectHop {
+                url: current.to_string(),
+                status: status.as_u16(),
+            });
+            current = next;
+        };
 
         // Check status
+        if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+            return Ok(FetchedPage::NotModified);
+        }
         if !response.status().is_success() {
             return Err(format!("HTTP error: {}", response.status()).into());
         }
 
+        let header = |name: reqwest::header::HeaderName| {
+            response.headers().get(name).and_then(|v| v.to_str().ok()).map(str::to_string)
+        };
+        let content_type = header(reqwest::header::CONTENT_TYPE);
+        let validators = CacheValidators {
+            etag: header(reqwest::header::ETAG),
+            last_modified: header(reqwest::header::LAST_MODIFIED),
+        };
+        let headers = ResponseHeaders {
+            final_url: Some(response.url().to_string()).filter(|final_url| final_url != url),
+            last_modified: validators.last_modified.clone(),
+            cf_ray: header(reqwest::header::HeaderName::from_static("cf-ray")),
+            x_cache: header(reqwest::header::HeaderName::from_static("x-cache")),
+            redirects,
+        };
+
         // Read content
-        let content = response.text().await?;
+        let body = response.text().await?;
         
         // Basic validation
-        if content.is_empty() {
+        if body.is_empty() {
             return Err("Empty response content".into());
         }
 
-        Ok(content)
+        Ok(FetchedPage::Modified {
+            body,
+            content_type,
+            validators,
+            headers: Box::new(headers),
+        })
+    }
+}
+
+#[async_trait]
+impl Fetcher for Scraper {
+    async fn fetch(&self, url: &str, proxy: Option<&ProxyConfig>) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        self.fetch_page(url, proxy, &CacheValidators::default()).await?.into_body()
+    }
+
+    async fn fetch_with_headers(
+        &self,
+        url: &str,
+        proxy: Option<&ProxyConfig>,
+    ) -> Result<(String, ResponseHeaders), Box<dyn std::error::Error + Send + Sync>> {
+        match self.fetch_page(url, proxy, &CacheValidators::default()).await? {
+            FetchedPage::Modified { body, headers, .. } => Ok((body, *headers)),
+            FetchedPage::NotModified => Err("304 Not Modified for an unconditional request".into()),
+        }
+    }
+}
+
+/// `ETag` and `Last-Modified` of a cached copy, for conditional requests
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CacheValidators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+/// Response headers kept with what was scraped, for debugging geo-blocking and
+/// stale caches
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ResponseHeaders {
+    /// Where redirects ended up, when that is not the URL requested
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub final_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+    /// Cloudflare's request id, which also names the edge location that answered
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cf_ray: Option<String>,
+    /// Whether a CDN or proxy cache answered, e.g. `HIT`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub x_cache: Option<String>,
+    /// The redirects followed to reach `final_url`, in order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub redirects: Vec<RedirectHop>,
+}
+
+/// A URL that answered with a redirect
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RedirectHop {
+    pub url: String,
+    pub status: u16,
+}
+
+impl ResponseHeaders {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum FetchedPage {
+    /// 304: the cached copy is still current
+    NotModified,
+    Modified {
+        body: String,
+        content_type: Option<String>,
+        validators: CacheValidators,
+        headers: Box<ResponseHeaders>,
+    },
+}
+
+impl FetchedPage {
+    fn into_body(self) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        match self {
+            Self::Modified { body, .. } => Ok(body),
+            Self::NotModified => Err("304 Not Modified for an unconditional request".into()),
+        }
+    }
+}
+
+/// Fetcher for parse-only deployments with no network access; every fetch fails
+pub struct OfflineFetcher;
+
+#[async_trait]
+impl Fetcher for OfflineFetcher {
+    async fn fetch(&self, url: &str, _proxy: Option<&ProxyConfig>) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        Err(format!("network access is disabled, cannot fetch {}", url).into())
     }
 }
 
@@ -139,3 +342,22 @@ pub enum ContentType {
     Csv,
     Unknown,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn prop_detect_content_type_accepts_any_text(content in "\\PC*") {
+            detect_content_type(&content);
+        }
+
+        #[test]
+        fn prop_leading_brace_is_json(indent in "[ \t\r\n]{0,4}", rest in "\\PC{0,32}") {
+            let content = format!("{}{{{}", indent, rest);
+            prop_assert!(matches!(detect_content_type(&content), ContentType::Json));
+        }
+    }
+}
@@ -0,0 +1,154 @@
+//! Shadow runs of a candidate parser
+//!
+//! With a shadow parser configured, the engine parses every page a second time
+//! with the candidate and validates the result, but only ever returns the current
+//! parser's coupons. The comparison accumulates per merchant, so a parser change
+//! can be judged on live content — which merchants gain or lose valid coupons, and
+//! which codes — before it is promoted.
+
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::sync::Mutex;
+use utoipa::ToSchema;
+
+use crate::coupon_engine::parser::CouponParser;
+use crate::coupon_engine::RawCoupon;
+use crate::models::domain::MerchantDomain;
+
+/// Codes listed per merchant on each side of the diff
+const MAX_SAMPLE_CODES: usize = 20;
+
+#[derive(Debug, Default)]
+struct MerchantComparison {
+    pages: u32,
+    current_extracted: u32,
+    current_valid: u32,
+    candidate_extracted: u32,
+    candidate_valid: u32,
+    only_current: BTreeSet<String>,
+    only_candidate: BTreeSet<String>,
+}
+
+/// One parser's result for a page
+pub struct PageResult<'a> {
+    pub extracted: usize,
+    pub valid: &'a [RawCoupon],
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct MerchantShadowDiff {
+    pub domain: MerchantDomain,
+    pub pages: u32,
+    pub current_extracted: u32,
+    pub candidate_extracted: u32,
+    pub current_valid: u32,
+    pub candidate_valid: u32,
+    /// Valid coupons the candidate would gain (negative: lose)
+    pub yield_delta: i64,
+    pub only_in_current: Vec<String>,
+    pub only_in_candidate: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ShadowReport {
+    pub current_version: String,
+    pub candidate_version: String,
+    pub since: DateTime<Utc>,
+    pub pages: u32,
+    pub yield_delta: i64,
+    /// Largest change first
+    pub merchants: Vec<MerchantShadowDiff>,
+}
+
+pub struct ShadowParser {
+    parser: Arc<dyn CouponParser>,
+    current_version: String,
+    comparison: Mutex<(DateTime<Utc>, HashMap<MerchantDomain, MerchantComparison>)>,
+}
+
+impl ShadowParser {
+    pub fn new(parser: Arc<dyn CouponParser>, current_version: &str) -> Self {
+        Self {
+            parser,
+            current_version: current_version.to_string(),
+            comparison: Mutex::new((Utc::now(), HashMap::new())),
+        }
+    }
+
+    pub fn parser(&self) -> &dyn CouponParser {
+        self.parser.as_ref()
+    }
+
+    pub fn candidate_version(&self) -> &str {
+        self.parser.version()
+    }
+
+    /// Add one page's results from both parsers
+    pub async fn record(&self, domain: &MerchantDomain, current: PageResult<'_>, candidate: PageResult<'_>) {
+        let codes = |coupons: &[RawCoupon]| -> BTreeSet<String> {
+            coupons.iter().map(|c| c.code.as_str().to_uppercase()).collect()
+        };
+        let current_codes = codes(current.valid);
+        let candidate_codes = codes(candidate.valid);
+
+        let mut comparison = self.comparison.lock().await;
+        let merchant = comparison.1.entry(domain.clone()).or_default();
+        merchant.pages += 1;
+        merchant.current_extracted += current.extracted as u32;
+        merchant.current_valid += current.valid.len() as u32;
+        merchant.candidate_extracted += candidate.extracted as u32;
+        merchant.candidate_valid += candidate.valid.len() as u32;
+        for code in current_codes.difference(&candidate_codes) {
+            if merchant.only_current.len() < MAX_SAMPLE_CODES {
+                merchant.only_current.insert(code.clone());
+            }
+        }
+        for code in candidate_codes.difference(&current_codes) {
+            if merchant.only_candidate.len() < MAX_SAMPLE_CODES {
+                merchant.only_candidate.insert(code.clone());
+            }
+        }
+    }
+
+    pub async fn report(&self) -> ShadowReport {
+        let comparison = self.comparison.lock().await;
+        let mut merchants: Vec<MerchantShadowDiff> = comparison
+            .1
+            .iter()
+            .map(|(domain, m)| MerchantShadowDiff {
+                domain: domain.clone(),
+                pages: m.pages,
+                current_extracted: m.current_extracted,
+                candidate_extracted: m.candidate_extracted,
+                current_valid: m.current_valid,
+                candidate_valid: m.candidate_valid,
+                yield_delta: m.candidate_valid as i64 - m.current_valid as i64,
+                only_in_current: m.only_current.iter().cloned().collect(),
+                only_in_candidate: m.only_candidate.iter().cloned().collect(),
+            })
+            .collect();
+        merchants.sort_by(|a, b| {
+            b.yield_delta
+                .abs()
+                .cmp(&a.yield_delta.abs())
+                .then_with(|| a.domain.as_str().cmp(b.domain.as_str()))
+        });
+
+        ShadowReport {
+            current_version: self.current_version.clone(),
+            candidate_version: self.candidate_version().to_string(),
+            since: comparison.0,
+            pages: merchants.iter().map(|m| m.pages).sum(),
+            yield_delta: merchants.iter().map(|m| m.yield_delta).sum(),
+            merchants,
+        }
+    }
+
+    /// Start a fresh comparison window
+    pub async fn reset(&self) {
+        *self.comparison.lock().await = (Utc::now(), HashMap::new());
+    }
+}
@@ -0,0 +1,267 @@
+//! Per-stage latency of the scrape pipeline
+//!
+//! Each URL of a batch is timed through the pipeline's stages: fetching the page,
+//! persisting it (the snapshot archive and the scrape frontier), parsing it and
+//! validating what it yielded. Deduplication runs once per batch over every URL's
+//! coupons. Every [`UrlResult`](super::UrlResult) carries its [`StageTimings`], and
+//! [`StageHistograms`] aggregates them per stage into fixed buckets, served at
+//! `GET /admin/perf/stages`, so the stage that actually dominates is the one
+//! optimized.
+//!
+//! Fetch time excludes waiting for the per-domain rate limit and for a free
+//! connection slot; it covers every proxy tried.
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// Upper bounds of the histogram buckets, in milliseconds; slower samples fall into
+/// a final unbounded bucket
+pub const BUCKET_BOUNDS_MS: [f64; 16] = [
+    0.5, 1.0, 2.5, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1_000.0, 2_500.0, 5_000.0, 10_000.0, 30_000.0, 60_000.0,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    Fetch,
+    Parse,
+    Validate,
+    Dedupe,
+    Persist,
+}
+
+impl Stage {
+    /// In pipeline order
+    pub const ALL: [Stage; 5] = [Stage::Fetch, Stage::Parse, Stage::Validate, Stage::Dedupe, Stage::Persist];
+}
+
+/// Milliseconds one URL spent in each stage it reached
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct StageTimings {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fetch_ms: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parse_ms: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub validate_ms: Option<f64>,
+    /// The whole batch's deduplication, which every URL of the batch waited for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dedupe_ms: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub persist_ms: Option<f64>,
+}
+
+impl StageTimings {
+    pub fn is_empty(&self) -> bool {
+        Stage::ALL.iter().all(|stage| self.get(*stage).is_none())
+    }
+
+    pub fn get(&self, stage: Stage) -> Option<f64> {
+        *self.slot(stage)
+    }
+
+    /// Add `elapsed` to the stage, which may run more than once per URL
+    pub fn record(&mut self, stage: Stage, elapsed: Duration) {
+        let slot = self.slot_mut(stage);
+        *slot = Some(round(slot.unwrap_or(0.0) + elapsed.as_secs_f64() * 1000.0));
+    }
+
+    fn slot(&self, stage: Stage) -> &Option<f64> {
+        match stage {
+            Stage::Fetch => &self.fetch_ms,
+            Stage::Parse => &self.parse_ms,
+            Stage::Validate => &self.validate_ms,
+            Stage::Dedupe => &self.dedupe_ms,
+            Stage::Persist => &self.persist_ms,
+        }
+    }
+
+    fn slot_mut(&mut self, stage: Stage) -> &mut Option<f64> {
+        match stage {
+            Stage::Fetch => &mut self.fetch_ms,
+            Stage::Parse => &mut self.parse_ms,
+            Stage::Validate => &mut self.validate_ms,
+            Stage::Dedupe => &mut self.dedupe_ms,
+            Stage::Persist => &mut self.persist_ms,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Histogram {
+    /// One count per bound of [`BUCKET_BOUNDS_MS`], then the unbounded bucket
+    counts: [u64; BUCKET_BOUNDS_MS.len() + 1],
+    count: u64,
+    sum_ms: f64,
+    max_ms: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, ms: f64) {
+        let bucket = BUCKET_BOUNDS_MS.iter().position(|bound| ms <= *bound).unwrap_or(BUCKET_BOUNDS_MS.len());
+        self.counts[bucket] += 1;
+        self.count += 1;
+        self.sum_ms += ms;
+        self.max_ms = self.max_ms.max(ms);
+    }
+
+    /// Estimate of the `q` quantile, interpolated within its bucket
+    fn quantile(&self, q: f64) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        let rank = q * self.count as f64;
+        let mut below = 0;
+        for (bucket, count) in self.counts.iter().enumerate() {
+            if *count > 0 && (below + count) as f64 >= rank {
+                let lower = if bucket == 0 { 0.0 } else { BUCKET_BOUNDS_MS[bucket - 1] };
+                let upper = BUCKET_BOUNDS_MS.get(bucket).copied().unwrap_or(self.max_ms).min(self.max_ms);
+                let within = ((rank - below as f64) / *count as f64).clamp(0.0, 1.0);
+                return round(lower + (upper - lower).max(0.0) * within);
+            }
+            below += count;
+        }
+        self.max_ms
+    }
+}
+
+/// One histogram bucket; `le_ms` is absent for the unbounded last bucket
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Bucket {
+    pub le_ms: Option<f64>,
+    pub count: u64,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct StageStats {
+    pub stage: Stage,
+    pub count: u64,
+    pub total_ms: f64,
+    pub mean_ms: f64,
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+    /// Fraction of the time spent across all stages
+    pub share: f64,
+    pub buckets: Vec<Bucket>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct StageReport {
+    pub stages: Vec<StageStats>,
+    /// The stage with the most total time
+    pub bottleneck: Option<Stage>,
+}
+
+/// Stage latency aggregated over every URL since start (or the last reset)
+#[derive(Default)]
+pub struct StageHistograms {
+    histograms: Mutex<[Histogram; Stage::ALL.len()]>,
+}
+
+impl StageHistograms {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count one URL's timings. Its dedupe time is the batch's, so it is left to
+    /// [`StageHistograms::observe_stage`] once per batch.
+    pub fn observe(&self, timings: &StageTimings) {
+        let mut histograms = self.histograms.lock().unwrap();
+        for (i, stage) in Stage::ALL.iter().enumerate() {
+            if let Some(ms) = timings.get(*stage).filter(|_| *stage != Stage::Dedupe) {
+                histograms[i].observe(ms);
+            }
+        }
+    }
+
+    pub fn observe_stage(&self, stage: Stage, elapsed: Duration) {
+        let i = Stage::ALL.iter().position(|s| *s == stage).expect("every stage is listed");
+        self.histograms.lock().unwrap()[i].observe(elapsed.as_secs_f64() * 1000.0);
+    }
+
+    pub fn report(&self) -> StageReport {
+        let histograms = self.histograms.lock().unwrap().clone();
+        let total: f64 = histograms.iter().map(|h| h.sum_ms).sum();
+        let stages: Vec<StageStats> = Stage::ALL
+            .iter()
+            .zip(histograms.iter())
+            .map(|(stage, h)| StageStats {
+                stage: *stage,
+                count: h.count,
+                total_ms: round(h.sum_ms),
+                mean_ms: if h.count == 0 { 0.0 } else { round(h.sum_ms / h.count as f64) },
+                p50_ms: h.quantile(0.5),
+                p95_ms: h.quantile(0.95),
+                p99_ms: h.quantile(0.99),
+                max_ms: round(h.max_ms),
+                share: if total > 0.0 { (h.sum_ms / total * 1000.0).round() / 1000.0 } else { 0.0 },
+                buckets: h
+                    .counts
+                    .iter()
+                    .enumerate()
+                    .map(|(i, count)| Bucket {
+                        le_ms: BUCKET_BOUNDS_MS.get(i).copied(),
+                        count: *count,
+                    })
+                    .collect(),
+            })
+            .collect();
+        let bottleneck = stages
+            .iter()
+            .filter(|stats| stats.count > 0)
+            .max_by(|a, b| a.total_ms.total_cmp(&b.total_ms))
+            .map(|stats| stats.stage);
+        StageReport { stages, bottleneck }
+    }
+
+    pub fn reset(&self) {
+        *self.histograms.lock().unwrap() = Default::default();
+    }
+}
+
+/// To the microsecond
+fn round(ms: f64) -> f64 {
+    (ms * 1000.0).round() / 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aggregates_url_timings_into_stage_histograms() {
+        let histograms = StageHistograms::new();
+        for i in 0..100 {
+            let mut timings = StageTimings::default();
+            timings.record(Stage::Fetch, Duration::from_millis(200 + i));
+            timings.record(Stage::Parse, Duration::from_millis(3));
+            timings.record(Stage::Parse, Duration::from_millis(1));
+            timings.record(Stage::Dedupe, Duration::from_millis(999));
+            assert_eq!(timings.parse_ms, Some(4.0));
+            histograms.observe(&timings);
+        }
+        histograms.observe_stage(Stage::Dedupe, Duration::from_millis(7));
+
+        let report = histograms.report();
+        let stats = |stage| report.stages.iter().find(|s| s.stage == stage).unwrap();
+        assert_eq!(report.bottleneck, Some(Stage::Fetch));
+        assert_eq!(stats(Stage::Fetch).count, 100);
+        assert_eq!(stats(Stage::Fetch).max_ms, 299.0);
+        assert!((250.0..=299.0).contains(&stats(Stage::Fetch).p95_ms));
+        assert!(stats(Stage::Fetch).p50_ms <= stats(Stage::Fetch).p99_ms);
+        assert_eq!(stats(Stage::Parse).p50_ms, 3.25);
+        assert_eq!(stats(Stage::Dedupe).count, 1);
+        assert_eq!(stats(Stage::Validate).count, 0);
+        assert_eq!(stats(Stage::Fetch).buckets.iter().map(|b| b.count).sum::<u64>(), 100);
+        assert!(stats(Stage::Fetch).buckets.last().unwrap().le_ms.is_none());
+
+        histograms.reset();
+        assert_eq!(histograms.report().bottleneck, None);
+        assert!(StageTimings::default().is_empty());
+    }
+}
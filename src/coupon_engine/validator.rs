@@ -1,8 +1,16 @@
 //! Coupon validation module for verifying coupon data quality and validity
 
+use axum::async_trait;
+use crate::clock::{self, Clock};
+use crate::coupon_engine::blocklist::ExtractionBlocklist;
 use crate::coupon_engine::{RawCoupon, DiscountType};
-use chrono::Utc;
+use crate::models::coupon_listing::CouponListing;
+use crate::models::domain::CouponCode;
+use std::sync::Arc;
 use regex::Regex;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 use std::collections::HashSet;
 use lazy_static::lazy_static;
 
@@ -19,10 +27,61 @@ lazy_static! {
     };
 }
 
+/// Why a shopper's code was refused by `POST /coupons/validate`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureReason {
+    InvalidFormat,
+    InvalidMerchant,
+    /// No codes are known for the merchant at all
+    UnknownMerchant,
+    UnknownCode,
+    Expired,
+    BelowMinimumOrder,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ValidationFailure {
+    pub reason: FailureReason,
+    pub message: String,
+}
+
+impl ValidationFailure {
+    pub fn new(reason: FailureReason, message: impl Into<String>) -> Self {
+        Self { reason, message: message.into() }
+    }
+}
+
+/// The answer of `POST /coupons/validate`
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CouponValidation {
+    pub valid: bool,
+    /// Empty when `valid`
+    pub failures: Vec<ValidationFailure>,
+    /// The catalogue's listing, when the code is known
+    pub coupon: Option<CouponListing>,
+    /// What the code takes off the order, when valid and the order total was given
+    pub discount: Option<Decimal>,
+}
+
+/// Decides which parsed coupons are kept
+#[async_trait]
+pub trait ValidationPolicy: Send + Sync {
+    async fn is_valid(&self, coupon: &RawCoupon) -> bool;
+}
+
 pub struct Validator {
     min_discount_value: f64,
     max_discount_percentage: f64,
     max_future_days: i64,
+    blocklist: Option<Arc<ExtractionBlocklist>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for Validator {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Validator {
@@ -31,9 +90,22 @@ impl Validator {
             min_discount_value: 1.0,
             max_discount_percentage: 99.0,
             max_future_days: 365,
+            blocklist: None,
+            clock: clock::system(),
         }
     }
 
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Drop coupons matching the blocklist's code and merchant rules
+    pub fn with_blocklist(mut self, blocklist: Arc<ExtractionBlocklist>) -> Self {
+        self.blocklist = Some(blocklist);
+        self
+    }
+
     pub async fn is_valid(&self, coupon: &RawCoupon) -> bool {
         // Basic validation checks
         if !self.validate_code(&coupon.code) {
@@ -52,10 +124,18 @@ impl Validator {
             return false;
         }
 
+        // Last, so a rule's hits only count coupons it alone dropped
+        if self.blocklist.as_ref().is_some_and(|blocklist| blocklist.blocks_coupon(coupon)) {
+            return false;
+        }
+
         true
     }
 
-    fn validate_code(&self, code: &str) -> bool {
+    /// Whether a code looks real: uppercase alphanumerics, no spam words, no repeated pattern
+    pub fn validate_code(&self, code: &CouponCode) -> bool {
+        let code = code.as_str();
+
         // Check if code matches valid pattern
         if !VALID_CODE_PATTERN.is_match(code) {
             return false;
@@ -77,6 +157,44 @@ impl Validator {
         true
     }
 
+    /// Parse a code as typed by a shopper, who may not match the case it was issued in
+    pub fn check_format(&self, raw: &str) -> Result<CouponCode, ValidationFailure> {
+        let code = CouponCode::parse(&raw.to_uppercase()).map_err(|e| ValidationFailure::new(FailureReason::InvalidFormat, e))?;
+        if !self.validate_code(&code) {
+            return Err(ValidationFailure::new(
+                FailureReason::InvalidFormat,
+                format!("'{}' does not look like a coupon code", code),
+            ));
+        }
+        Ok(code)
+    }
+
+    /// Why a known code cannot be used now on an order of `order_total`. The minimum
+    /// order is only checked when the total is given.
+    pub fn check_listing(&self, listing: &CouponListing, order_total: Option<Decimal>) -> Vec<ValidationFailure> {
+        let mut failures = Vec::new();
+
+        if let Some(valid_until) = listing.valid_until {
+            if valid_until < self.clock.now() {
+                failures.push(ValidationFailure::new(
+                    FailureReason::Expired,
+                    format!("expired on {}", valid_until.format("%Y-%m-%d")),
+                ));
+            }
+        }
+
+        if let (Some(minimum), Some(total)) = (listing.minimum_order, order_total) {
+            if total < minimum {
+                failures.push(ValidationFailure::new(
+                    FailureReason::BelowMinimumOrder,
+                    format!("needs an order of at least {}, order is {}", minimum, total),
+                ));
+            }
+        }
+
+        failures
+    }
+
     fn validate_discount(&self, discount_type: &DiscountType, value: Option<f64>) -> bool {
         match discount_type {
             DiscountType::Percentage => {
@@ -103,7 +221,7 @@ impl Validator {
             }
             DiscountType::Points => {
                 if let Some(v) = value {
-                    v >= 1.0 && v <= 100000.0
+                    (1.0..=100000.0).contains(&v)
                 } else {
                     false
                 }
@@ -113,7 +231,7 @@ impl Validator {
     }
 
     fn validate_dates(&self, coupon: &RawCoupon) -> bool {
-        let now = Utc::now();
+        let now = self.clock.now();
 
         // Check if coupon has already expired
         if let Some(valid_until) = coupon.valid_until {
@@ -152,26 +270,11 @@ impl Validator {
             return false;
         }
 
-        // Check merchant domain
-        if coupon.merchant_domain.is_empty() || !self.is_valid_domain(&coupon.merchant_domain) {
-            return false;
-        }
-
+        // The domain itself was validated when the `MerchantDomain` was parsed
         true
     }
 
-    fn is_valid_domain(&self, domain: &str) -> bool {
-        // Basic domain validation
-        if domain.len() < 4 || domain.len() > 253 {
-            return false;
-        }
-
-        // Check for valid characters
-        let domain_pattern = Regex::new(r"^[a-zA-Z0-9][a-zA-Z0-9-]{0,61}[a-zA-Z0-9]?(\.[a-zA-Z0-9][a-zA-Z0-9-]{0,61}[a-zA-Z0-9]?)*$").unwrap();
-        domain_pattern.is_match(domain)
-    }
-
-    fn has_repetitive_pattern(&self, code: &str) -> bool {
+    pub fn has_repetitive_pattern(&self, code: &str) -> bool {
         // Check for patterns like AAAA, 1111, ABAB
         if code.len() < 4 {
             return false;
@@ -253,6 +356,13 @@ impl Validator {
     }
 }
 
+#[async_trait]
+impl ValidationPolicy for Validator {
+    async fn is_valid(&self, coupon: &RawCoupon) -> bool {
+        Validator::is_valid(self, coupon).await
+    }
+}
+
 #[derive(Debug)]
 pub struct ValidationResult {
     pub coupon: RawCoupon,
@@ -263,13 +373,17 @@ pub struct ValidationResult {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::clock::MockClock;
     use crate::coupon_engine::SourceType;
+    use crate::models::domain::MerchantDomain;
+    use chrono::Utc;
+    use proptest::prelude::*;
 
     #[tokio::test]
     async fn test_valid_coupon() {
         let validator = Validator::new();
         let coupon = RawCoupon {
-            code: "SAVE20".to_string(),
+            code: CouponCode::parse("SAVE20").unwrap(),
             title: "20% Off".to_string(),
             description: None,
             discount_type: DiscountType::Percentage,
@@ -279,11 +393,12 @@ mod tests {
             valid_from: None,
             valid_until: Some(Utc::now() + chrono::Duration::days(30)),
             merchant_name: "Test Store".to_string(),
-            merchant_domain: "teststore.com".to_string(),
+            merchant_domain: MerchantDomain::parse("teststore.com").unwrap(),
             source_url: "https://teststore.com".to_string(),
             source_type: SourceType::WebScraping,
             metadata: serde_json::json!({}),
             scraped_at: Utc::now(),
+            parser_version: None,
         };
 
         assert!(validator.is_valid(&coupon).await);
@@ -293,7 +408,7 @@ mod tests {
     async fn test_invalid_code_pattern() {
         let validator = Validator::new();
         let coupon = RawCoupon {
-            code: "AAAA".to_string(), // Repetitive pattern
+            code: CouponCode::parse("AAAA").unwrap(), // Repetitive pattern
             title: "Test".to_string(),
             description: None,
             discount_type: DiscountType::Percentage,
@@ -303,13 +418,99 @@ mod tests {
             valid_from: None,
             valid_until: Some(Utc::now() + chrono::Duration::days(30)),
             merchant_name: "Test Store".to_string(),
-            merchant_domain: "teststore.com".to_string(),
+            merchant_domain: MerchantDomain::parse("teststore.com").unwrap(),
             source_url: "https://teststore.com".to_string(),
             source_type: SourceType::WebScraping,
             metadata: serde_json::json!({}),
             scraped_at: Utc::now(),
+            parser_version: None,
         };
 
         assert!(!validator.is_valid(&coupon).await);
     }
+
+    #[tokio::test]
+    async fn test_coupon_expires_on_the_clock() {
+        let clock = Arc::new(MockClock::new());
+        let validator = Validator::new().with_clock(clock.clone());
+        let coupon = RawCoupon {
+            code: CouponCode::parse("SAVE20").unwrap(),
+            title: "20% Off".to_string(),
+            description: None,
+            discount_type: DiscountType::Percentage,
+            discount_value: Some(20.0),
+            minimum_order: None,
+            maximum_discount: None,
+            valid_from: None,
+            valid_until: Some(clock.now() + chrono::Duration::days(30)),
+            merchant_name: "Test Store".to_string(),
+            merchant_domain: MerchantDomain::parse("teststore.com").unwrap(),
+            source_url: "https://teststore.com".to_string(),
+            source_type: SourceType::WebScraping,
+            metadata: serde_json::json!({}),
+            scraped_at: clock.now(),
+            parser_version: None,
+        };
+        assert!(validator.is_valid(&coupon).await);
+
+        clock.advance(std::time::Duration::from_secs(31 * 24 * 3600));
+        assert!(!validator.is_valid(&coupon).await);
+    }
+
+    #[test]
+    fn test_check_reports_structured_failures() {
+        let clock = Arc::new(MockClock::new());
+        let validator = Validator::new().with_clock(clock.clone());
+
+        assert_eq!(validator.check_format(" save20 ").unwrap().as_str(), "SAVE20");
+        assert_eq!(validator.check_format("FAKE50").unwrap_err().reason, FailureReason::InvalidFormat);
+        assert_eq!(validator.check_format("save 20").unwrap_err().reason, FailureReason::InvalidFormat);
+
+        let listing = CouponListing {
+            code: CouponCode::parse("SAVE20").unwrap(),
+            title: "20% Off".to_string(),
+            description: None,
+            locale: None,
+            merchant_domain: MerchantDomain::parse("teststore.com").unwrap(),
+            discount_type: "percentage".to_string(),
+            discount_value: Some(20.0),
+            minimum_order: Some(Decimal::new(50, 0)),
+            source: crate::models::coupon_listing::CouponSource::AffiliateApi,
+            license: None,
+            extraction_confidence: 0.9,
+            scraped_at: clock.now(),
+            valid_until: Some(clock.now() + chrono::Duration::days(1)),
+            predicted_success: None,
+            tags: Vec::new(),
+        };
+        let reasons = |total| validator.check_listing(&listing, total).into_iter().map(|f| f.reason).collect::<Vec<_>>();
+        assert!(reasons(None).is_empty());
+        assert!(reasons(Some(Decimal::new(5000, 2))).is_empty());
+        assert_eq!(reasons(Some(Decimal::new(4999, 2))), vec![FailureReason::BelowMinimumOrder]);
+
+        clock.advance(std::time::Duration::from_secs(2 * 24 * 3600));
+        assert_eq!(reasons(Some(Decimal::new(10, 0))), vec![FailureReason::Expired, FailureReason::BelowMinimumOrder]);
+    }
+
+    // Scraped codes are arbitrary text, so mix well-formed codes with any printable input
+    const CODE_INPUT: &str = "[A-Za-z0-9]{0,12}|\\PC{0,24}";
+
+    proptest! {
+        #[test]
+        fn prop_code_checks_accept_any_text(raw in CODE_INPUT) {
+            let validator = Validator::new();
+            validator.has_repetitive_pattern(&raw);
+            if let Ok(code) = CouponCode::parse(&raw) {
+                if validator.validate_code(&code) {
+                    prop_assert!(code.as_str().chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit()));
+                    prop_assert!(!validator.has_repetitive_pattern(code.as_str()));
+                }
+            }
+        }
+
+        #[test]
+        fn prop_one_repeated_character_is_repetitive(c in any::<char>(), count in 4usize..40) {
+            prop_assert!(Validator::new().has_repetitive_pattern(&c.to_string().repeat(count)));
+        }
+    }
 }
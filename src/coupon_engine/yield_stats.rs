@@ -0,0 +1,319 @@
+//! Per-merchant scrape yield
+//!
+//! Every batch records, per merchant, how many URLs were scraped and how many
+//! coupons survived each stage. When a merchant changes its page layout the fetches
+//! keep succeeding while extraction or validation quietly drops to zero, which
+//! shows up here long before anyone notices the missing coupons. Runs are kept
+//! for [`RETENTION_DAYS`], shared through Redis when `REDIS_URL` is set and
+//! persisted to a JSON file otherwise.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, DurationRound, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use utoipa::ToSchema;
+
+use crate::clock::{self, Clock};
+use crate::models::domain::MerchantDomain;
+use crate::storage::persisted::{PersistedStore, StoreError};
+
+const REDIS_KEY: &str = "scrape_yield";
+const STORE_NAME: &str = "scrape yield";
+pub const RETENTION_DAYS: i64 = 90;
+/// How often shared runs are re-read
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Stage counts for one merchant, over one run or summed over several
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct YieldCounts {
+    pub urls_scraped: u32,
+    pub fetch_failures: u32,
+    pub coupons_extracted: u32,
+    pub coupons_valid: u32,
+    /// Valid coupons left after deduplication
+    pub coupons_new: u32,
+}
+
+impl YieldCounts {
+    fn add(&mut self, other: &YieldCounts) {
+        self.urls_scraped += other.urls_scraped;
+        self.fetch_failures += other.fetch_failures;
+        self.coupons_extracted += other.coupons_extracted;
+        self.coupons_valid += other.coupons_valid;
+        self.coupons_new += other.coupons_new;
+    }
+
+    /// Share of extracted coupons that passed validation
+    pub fn validation_pass_rate(&self) -> Option<f64> {
+        ratio(self.coupons_valid, self.coupons_extracted)
+    }
+
+    /// Share of valid coupons that were not duplicates
+    pub fn dedup_new_rate(&self) -> Option<f64> {
+        ratio(self.coupons_new, self.coupons_valid)
+    }
+}
+
+fn ratio(part: u32, whole: u32) -> Option<f64> {
+    (whole > 0).then(|| (part as f64 / whole as f64 * 1000.0).round() / 1000.0)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct YieldRun {
+    at: DateTime<Utc>,
+    #[serde(flatten)]
+    counts: YieldCounts,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum YieldInterval {
+    /// One point per batch
+    Run,
+    Hour,
+    #[default]
+    Day,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct YieldPoint {
+    /// Start of the bucket, or the run time for `run` intervals
+    pub at: DateTime<Utc>,
+    pub runs: u32,
+    #[serde(flatten)]
+    pub counts: YieldCounts,
+    pub validation_pass_rate: Option<f64>,
+    pub dedup_new_rate: Option<f64>,
+}
+
+/// How a merchant's pages have been fetching
+#[derive(Debug, Clone, PartialEq)]
+pub struct FetchSummary {
+    pub last_success_at: Option<DateTime<Utc>>,
+    pub success_rate: Option<f64>,
+}
+
+pub struct YieldStats {
+    runs: Arc<Mutex<HashMap<MerchantDomain, Vec<YieldRun>>>>,
+    /// Every merchant's runs in the file, one field of the Redis hash each
+    store: PersistedStore<HashMap<MerchantDomain, Vec<YieldRun>>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl YieldStats {
+    /// Runs persisted to `path`, or kept in memory only
+    pub fn new(path: Option<PathBuf>) -> Self {
+        Self::with_store(PersistedStore::new(STORE_NAME, REDIS_KEY, path))
+    }
+
+    /// Runs shared through Redis, so every worker's runs are counted
+    pub fn shared(redis_url: &str) -> Result<Self, StoreError> {
+        Ok(Self::with_store(PersistedStore::shared(STORE_NAME, REDIS_KEY, redis_url)?))
+    }
+
+    fn with_store(store: PersistedStore<HashMap<MerchantDomain, Vec<YieldRun>>>) -> Self {
+        Self {
+            runs: Arc::new(Mutex::new(HashMap::new())),
+            store,
+            clock: clock::system(),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Share runs through `REDIS_URL` when set, otherwise load them from
+    /// `SCRAPE_YIELD_PATH` (default `data/scrape_yield.json`)
+    pub async fn from_env() -> Self {
+        let stats = Self::with_store(PersistedStore::from_env(STORE_NAME, REDIS_KEY, "SCRAPE_YIELD_PATH", "data/scrape_yield.json"));
+        if let Err(e) = stats.reload().await {
+            tracing::warn!(error = %e, "Starting with an empty scrape yield history");
+        }
+        stats
+    }
+
+    /// Replace the local copy with every stored merchant's runs
+    pub async fn reload(&self) -> Result<(), StoreError> {
+        if let Some(stored) = self.store.load_map(REDIS_KEY).await? {
+            *self.runs.lock().await = stored;
+        }
+        Ok(())
+    }
+
+    /// Re-read shared runs every [`REFRESH_INTERVAL`], picking up the runs of other
+    /// instances
+    pub async fn start_background_tasks(self: Arc<Self>) {
+        self.store.refresh_every(self.clock.as_ref(), REFRESH_INTERVAL, || self.reload()).await
+    }
+
+    /// Record one run for each merchant in the batch, dropping runs past retention
+    pub async fn record(&self, batch: HashMap<MerchantDomain, YieldCounts>) {
+        if batch.is_empty() {
+            return;
+        }
+
+        let now = self.clock.now();
+        let cutoff = now - TimeDelta::days(RETENTION_DAYS);
+        let mut runs = self.runs.lock().await;
+        let mut changed = Vec::new();
+        for (domain, counts) in batch {
+            // Other workers record runs of the same merchant
+            match self.store.hash_get::<Vec<YieldRun>>(REDIS_KEY, domain.as_str()) {
+                Ok(Some(stored)) => {
+                    runs.insert(domain.clone(), stored);
+                }
+                Ok(None) => {}
+                Err(e) => tracing::warn!(merchant = %domain, error = %e, "Failed to read shared scrape yield"),
+            }
+            runs.entry(domain.clone()).or_default().push(YieldRun { at: now, counts });
+            changed.push(domain);
+        }
+        for (domain, merchant_runs) in runs.iter_mut() {
+            let before = merchant_runs.len();
+            merchant_runs.retain(|run| run.at >= cutoff);
+            if merchant_runs.len() != before && !changed.contains(domain) {
+                changed.push(domain.clone());
+            }
+        }
+        runs.retain(|_, merchant_runs| !merchant_runs.is_empty());
+
+        let fields: Vec<(&str, Option<&Vec<YieldRun>>)> = changed.iter().map(|domain| (domain.as_str(), runs.get(domain))).collect();
+        if let Err(e) = self.store.write_fields(REDIS_KEY, &fields, || runs.clone()).await {
+            tracing::warn!(error = %e, "Failed to persist scrape yield");
+        }
+    }
+
+    /// Yield for `domain` since `since`, oldest first
+    pub async fn series(&self, domain: &MerchantDomain, since: DateTime<Utc>, interval: YieldInterval) -> Vec<YieldPoint> {
+        let runs = self.runs.lock().await;
+        let mut points: Vec<YieldPoint> = Vec::new();
+
+        for run in runs.get(domain).into_iter().flatten().filter(|run| run.at >= since) {
+            let at = match interval {
+                YieldInterval::Run => run.at,
+                YieldInterval::Hour => run.at.duration_trunc(TimeDelta::hours(1)).unwrap_or(run.at),
+                YieldInterval::Day => run.at.duration_trunc(TimeDelta::days(1)).unwrap_or(run.at),
+            };
+
+            match points.last_mut() {
+                Some(point) if interval != YieldInterval::Run && point.at == at => {
+                    point.runs += 1;
+                    point.counts.add(&run.counts);
+                }
+                _ => points.push(YieldPoint {
+                    at,
+                    runs: 1,
+                    counts: run.counts.clone(),
+                    validation_pass_rate: None,
+                    dedup_new_rate: None,
+                }),
+            }
+        }
+
+        for point in &mut points {
+            point.validation_pass_rate = point.counts.validation_pass_rate();
+            point.dedup_new_rate = point.counts.dedup_new_rate();
+        }
+        points
+    }
+
+    /// Merchants with runs on record
+    pub async fn merchants(&self) -> Vec<MerchantDomain> {
+        self.runs.lock().await.keys().cloned().collect()
+    }
+
+    /// Per merchant, its runs since `since` summed into one count
+    pub async fn totals(&self, since: DateTime<Utc>) -> HashMap<MerchantDomain, YieldCounts> {
+        let runs = self.runs.lock().await;
+        let mut totals = HashMap::new();
+        for (domain, merchant_runs) in runs.iter() {
+            for run in merchant_runs.iter().filter(|run| run.at >= since) {
+                totals.entry(domain.clone()).or_insert_with(YieldCounts::default).add(&run.counts);
+            }
+        }
+        totals
+    }
+
+    /// Per merchant, the last run that fetched at least one page, and the share of
+    /// fetches that succeeded since `since`
+    pub async fn fetch_summary(&self, since: DateTime<Utc>) -> HashMap<MerchantDomain, FetchSummary> {
+        let runs = self.runs.lock().await;
+        runs.iter()
+            .map(|(domain, merchant_runs)| {
+                let last_success = merchant_runs
+                    .iter()
+                    .filter(|run| run.counts.urls_scraped > run.counts.fetch_failures)
+                    .map(|run| run.at)
+                    .max();
+                let mut recent = YieldCounts::default();
+                for run in merchant_runs.iter().filter(|run| run.at >= since) {
+                    recent.add(&run.counts);
+                }
+                let summary = FetchSummary {
+                    last_success_at: last_success,
+                    success_rate: ratio(recent.urls_scraped.saturating_sub(recent.fetch_failures), recent.urls_scraped),
+                };
+                (domain.clone(), summary)
+            })
+            .collect()
+    }
+
+    /// Current time on the stats clock, for relative queries
+    pub fn now(&self) -> DateTime<Utc> {
+        self.clock.now()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use chrono::TimeZone;
+    use std::time::Duration;
+
+    fn counts(urls: u32, failures: u32, extracted: u32, valid: u32, new: u32) -> YieldCounts {
+        YieldCounts {
+            urls_scraped: urls,
+            fetch_failures: failures,
+            coupons_extracted: extracted,
+            coupons_valid: valid,
+            coupons_new: new,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_series_buckets_runs_and_computes_rates() {
+        let clock = Arc::new(MockClock::at(Utc.with_ymd_and_hms(2024, 5, 1, 9, 0, 0).unwrap()));
+        let stats = YieldStats::new(None).with_clock(clock.clone());
+        let shop = MerchantDomain::parse("shop.example.com").unwrap();
+        let other = MerchantDomain::parse("other.example.com").unwrap();
+
+        stats
+            .record(HashMap::from([(shop.clone(), counts(2, 0, 10, 8, 6)), (other.clone(), counts(1, 1, 0, 0, 0))]))
+            .await;
+        clock.advance(Duration::from_secs(3 * 3600));
+        stats.record(HashMap::from([(shop.clone(), counts(2, 0, 10, 4, 2))])).await;
+        // The next day the layout changed: pages still fetch but nothing is extracted
+        clock.advance(Duration::from_secs(24 * 3600));
+        stats.record(HashMap::from([(shop.clone(), counts(2, 0, 0, 0, 0))])).await;
+
+        let since = Utc.with_ymd_and_hms(2024, 4, 1, 0, 0, 0).unwrap();
+        let days = stats.series(&shop, since, YieldInterval::Day).await;
+        assert_eq!(days.len(), 2);
+        assert_eq!(days[0].at, Utc.with_ymd_and_hms(2024, 5, 1, 0, 0, 0).unwrap());
+        assert_eq!((days[0].runs, days[0].counts.clone()), (2, counts(4, 0, 20, 12, 8)));
+        assert_eq!(days[0].validation_pass_rate, Some(0.6));
+        assert_eq!(days[0].dedup_new_rate, Some(0.667));
+        assert_eq!((days[1].validation_pass_rate, days[1].dedup_new_rate), (None, None));
+
+        assert_eq!(stats.series(&shop, since, YieldInterval::Run).await.len(), 3);
+        let recent = Utc.with_ymd_and_hms(2024, 5, 2, 0, 0, 0).unwrap();
+        assert_eq!(stats.series(&shop, recent, YieldInterval::Hour).await.len(), 1);
+    }
+}
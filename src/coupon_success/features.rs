@@ -0,0 +1,118 @@
+//! Features describing how likely a freshly scraped coupon code is to work
+
+use std::collections::HashSet;
+
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::models::coupon_listing::{CouponListing, CouponSource};
+
+lazy_static! {
+    /// What a real code usually looks like: a single token of letters, digits and dashes
+    static ref PLAUSIBLE_CODE: Regex = Regex::new(r"^[A-Za-z0-9][A-Za-z0-9\-_]{2,19}$").unwrap();
+}
+
+/// Hours after which a code's recency weight halves
+const RECENCY_HALF_LIFE_HOURS: f64 = 72.0;
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct CouponFeatures {
+    /// Prior reliability of the source the code came from (0.0 - 1.0)
+    pub source_reliability: f64,
+    /// How well the code matches known-working formats for the merchant (0.0 - 1.0)
+    pub format_match: f64,
+    /// Historical share of this merchant's codes that worked (0.0 - 1.0)
+    pub merchant_success_rate: f64,
+    /// Decays from 1.0 as the code gets older
+    pub recency: f64,
+    pub extraction_confidence: f64,
+}
+
+impl CouponFeatures {
+    pub const NAMES: [&'static str; 5] = [
+        "source_reliability",
+        "format_match",
+        "merchant_success_rate",
+        "recency",
+        "extraction_confidence",
+    ];
+
+    pub fn to_vec(&self) -> Vec<f64> {
+        vec![
+            self.source_reliability,
+            self.format_match,
+            self.merchant_success_rate,
+            self.recency,
+            self.extraction_confidence,
+        ]
+    }
+
+    pub fn extract(
+        coupon: &CouponListing,
+        merchant_success_rate: f64,
+        working_shapes: Option<&HashSet<String>>,
+        now: DateTime<Utc>,
+    ) -> Self {
+        let age_hours = (now - coupon.scraped_at).num_minutes().max(0) as f64 / 60.0;
+
+        Self {
+            source_reliability: source_reliability(coupon.source),
+            format_match: format_match(coupon.code.as_str(), working_shapes),
+            merchant_success_rate,
+            recency: 0.5f64.powf(age_hours / RECENCY_HALF_LIFE_HOURS),
+            extraction_confidence: coupon.extraction_confidence.clamp(0.0, 1.0),
+        }
+    }
+}
+
+fn source_reliability(source: CouponSource) -> f64 {
+    match source {
+        CouponSource::AffiliateApi => 0.9,
+        CouponSource::PartnerApi => 0.85,
+        CouponSource::WebScraping => 0.6,
+        CouponSource::UserSubmitted => 0.5,
+    }
+}
+
+/// Letters become `A`, digits `9`: "SAVE20" and "DEAL15" share the shape "AAAA99"
+pub fn code_shape(code: &str) -> String {
+    code.chars()
+        .map(|c| match c {
+            c if c.is_ascii_alphabetic() => 'A',
+            c if c.is_ascii_digit() => '9',
+            c => c,
+        })
+        .collect()
+}
+
+fn format_match(code: &str, working_shapes: Option<&HashSet<String>>) -> f64 {
+    if !PLAUSIBLE_CODE.is_match(code) {
+        return 0.0;
+    }
+    if working_shapes.is_some_and(|shapes| shapes.contains(&code_shape(&code.to_uppercase()))) {
+        return 1.0;
+    }
+    // All-lowercase words ("sitewide", "none") are usually scraped labels, not codes
+    if code.chars().all(|c| c.is_ascii_lowercase()) {
+        return 0.2;
+    }
+    0.6
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_match() {
+        let shapes: HashSet<String> = [code_shape("SAVE20")].into_iter().collect();
+
+        assert_eq!(format_match("DEAL15", Some(&shapes)), 1.0);
+        assert_eq!(format_match("WINTER2024", Some(&shapes)), 0.6);
+        assert_eq!(format_match("see site", None), 0.0);
+        assert_eq!(format_match("sitewide", None), 0.2);
+    }
+}
@@ -0,0 +1,337 @@
+//! Coupon success prediction
+//!
+//! A logistic model estimates the probability that a newly scraped code works,
+//! from source reliability, code format, merchant track record, recency and
+//! extraction confidence. It is attached to coupons as `predicted_success` so
+//! new codes can be ranked before any user feedback exists. Test outcomes are
+//! collected as labelled examples and the model is periodically refit on them.
+//!
+//! Outcomes are also counted per code. Once a code has results of its own, its
+//! prediction is blended with its observed success rate, the model's estimate
+//! counting as [`OBSERVED_PRIOR_WEIGHT`] outcomes, so codes that keep failing at
+//! checkout sink in the ranking without waiting for a refit.
+
+pub mod features;
+
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, RwLock};
+use utoipa::ToSchema;
+
+use crate::models::coupon_listing::CouponListing;
+use crate::models::domain::{CouponCode, MerchantDomain};
+use crate::reputation::ReputationService;
+use features::{code_shape, CouponFeatures};
+
+/// Success rate assumed for merchants without coupon test history
+const UNKNOWN_MERCHANT_RATE: f64 = 0.7;
+/// Labelled outcomes required before the first refit
+const MIN_TRAINING_SAMPLES: usize = 50;
+/// Refit after this many new outcomes
+const RETRAIN_EVERY: usize = 25;
+const EPOCHS: usize = 500;
+const LEARNING_RATE: f64 = 0.5;
+const L2_PENALTY: f64 = 0.001;
+/// Outcomes the model's prediction counts as when blended with a code's own results
+pub const OBSERVED_PRIOR_WEIGHT: f64 = 5.0;
+
+/// Logistic regression over [`CouponFeatures`]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CouponSuccessModel {
+    pub version: String,
+    pub bias: f64,
+    pub weights: Vec<f64>,
+}
+
+impl CouponSuccessModel {
+    pub fn from_file(path: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let content = std::fs::read_to_string(path)?;
+        let model: CouponSuccessModel = serde_json::from_str(&content)?;
+
+        if model.weights.len() != CouponFeatures::NAMES.len() {
+            return Err(format!(
+                "Model {} has {} weights, expected {}",
+                model.version,
+                model.weights.len(),
+                CouponFeatures::NAMES.len()
+            )
+            .into());
+        }
+
+        Ok(model)
+    }
+
+    pub fn predict(&self, features: &CouponFeatures) -> f64 {
+        let logit = self.bias
+            + features
+                .to_vec()
+                .iter()
+                .zip(&self.weights)
+                .map(|(x, w)| x * w)
+                .sum::<f64>();
+
+        1.0 / (1.0 + (-logit).exp())
+    }
+
+    /// Fit by batch gradient descent, starting from this model's weights
+    pub fn train(&self, samples: &[(CouponFeatures, bool)], version: String) -> Self {
+        let mut model = Self { version, ..self.clone() };
+        if samples.is_empty() {
+            return model;
+        }
+
+        let rows: Vec<(Vec<f64>, f64)> = samples
+            .iter()
+            .map(|(features, worked)| (features.to_vec(), if *worked { 1.0 } else { 0.0 }))
+            .collect();
+        let n = rows.len() as f64;
+
+        for _ in 0..EPOCHS {
+            let mut bias_gradient = 0.0;
+            let mut gradients = vec![0.0; model.weights.len()];
+
+            for (x, label) in &rows {
+                let logit = model.bias + x.iter().zip(&model.weights).map(|(x, w)| x * w).sum::<f64>();
+                let error = 1.0 / (1.0 + (-logit).exp()) - label;
+                bias_gradient += error;
+                for (gradient, value) in gradients.iter_mut().zip(x) {
+                    *gradient += error * value;
+                }
+            }
+
+            model.bias -= LEARNING_RATE * bias_gradient / n;
+            for (weight, gradient) in model.weights.iter_mut().zip(&gradients) {
+                *weight -= LEARNING_RATE * (gradient / n + L2_PENALTY * *weight);
+            }
+        }
+
+        model
+    }
+}
+
+impl Default for CouponSuccessModel {
+    /// Hand-tuned baseline used until enough outcomes are collected
+    fn default() -> Self {
+        Self {
+            version: "coupon-baseline-v1".to_string(),
+            bias: -3.0,
+            weights: vec![1.5, 1.5, 2.0, 1.0, 1.0],
+        }
+    }
+}
+
+/// Checkout results of one code
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CodeStats {
+    pub attempts: u32,
+    pub successes: u32,
+    pub last_worked_at: Option<DateTime<Utc>>,
+    pub last_failed_at: Option<DateTime<Utc>>,
+}
+
+/// A labelled outcome, as exported for offline training
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct TrainingRow {
+    pub merchant_domain: MerchantDomain,
+    pub code: CouponCode,
+    /// As they were when the code was tested
+    pub features: CouponFeatures,
+    pub worked: bool,
+    pub recorded_at: DateTime<Utc>,
+}
+
+pub struct CouponSuccessPredictor {
+    model: RwLock<CouponSuccessModel>,
+    outcomes: Mutex<Vec<TrainingRow>>,
+    /// Code shapes that have worked per merchant, for the format feature
+    working_shapes: RwLock<HashMap<MerchantDomain, HashSet<String>>>,
+    code_stats: RwLock<HashMap<(MerchantDomain, CouponCode), CodeStats>>,
+}
+
+impl CouponSuccessPredictor {
+    pub fn new(model: CouponSuccessModel) -> Self {
+        Self {
+            model: RwLock::new(model),
+            outcomes: Mutex::new(Vec::new()),
+            working_shapes: RwLock::new(HashMap::new()),
+            code_stats: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Load the model at `COUPON_MODEL_PATH`, falling back to the baseline weights
+    pub fn from_env() -> Self {
+        if let Ok(path) = std::env::var("COUPON_MODEL_PATH") {
+            match CouponSuccessModel::from_file(&path) {
+                Ok(model) => return Self::new(model),
+                Err(e) => tracing::warn!(path = %path, error = %e, "Failed to load the coupon success model"),
+            }
+        }
+
+        Self::new(CouponSuccessModel::default())
+    }
+
+    pub async fn model(&self) -> CouponSuccessModel {
+        self.model.read().await.clone()
+    }
+
+    pub async fn features(&self, coupon: &CouponListing, reputation: &ReputationService) -> CouponFeatures {
+        let merchant_rate = reputation
+            .reputation(&coupon.merchant_domain, &[])
+            .await
+            .coupon_success_rate
+            .unwrap_or(UNKNOWN_MERCHANT_RATE);
+        let shapes = self.working_shapes.read().await;
+
+        CouponFeatures::extract(coupon, merchant_rate, shapes.get(&coupon.merchant_domain), Utc::now())
+    }
+
+    /// Attach `predicted_success` to each coupon, blended with the code's own results
+    pub async fn annotate(&self, coupons: &mut [CouponListing], reputation: &ReputationService) {
+        for coupon in coupons.iter_mut() {
+            let features = self.features(coupon, reputation).await;
+            let mut probability = self.model.read().await.predict(&features);
+            let key = (coupon.merchant_domain.clone(), coupon.code.clone());
+            if let Some(stats) = self.code_stats.read().await.get(&key) {
+                probability = (probability * OBSERVED_PRIOR_WEIGHT + stats.successes as f64)
+                    / (OBSERVED_PRIOR_WEIGHT + stats.attempts as f64);
+            }
+            coupon.predicted_success = Some((probability * 1000.0).round() / 1000.0);
+        }
+    }
+
+    pub async fn code_stats(&self, merchant_domain: &MerchantDomain, code: &CouponCode) -> Option<CodeStats> {
+        self.code_stats
+            .read()
+            .await
+            .get(&(merchant_domain.clone(), code.clone()))
+            .cloned()
+    }
+
+    /// Every labelled outcome so far, oldest first
+    pub async fn training_data(&self) -> Vec<TrainingRow> {
+        self.outcomes.lock().await.clone()
+    }
+
+    /// Store a labelled outcome (features as they were when the code was tested) and
+    /// count it towards the code's stats, refitting when due
+    pub async fn record_outcome(&self, coupon: &CouponListing, features: CouponFeatures, worked: bool) {
+        let now = Utc::now();
+        {
+            let mut code_stats = self.code_stats.write().await;
+            let stats = code_stats
+                .entry((coupon.merchant_domain.clone(), coupon.code.clone()))
+                .or_default();
+            stats.attempts += 1;
+            match worked {
+                true => {
+                    stats.successes += 1;
+                    stats.last_worked_at = Some(now);
+                }
+                false => stats.last_failed_at = Some(now),
+            }
+        }
+
+        if worked {
+            self.working_shapes
+                .write()
+                .await
+                .entry(coupon.merchant_domain.clone())
+                .or_default()
+                .insert(code_shape(&coupon.code.as_str().to_uppercase()));
+        }
+
+        let samples = {
+            let mut outcomes = self.outcomes.lock().await;
+            outcomes.push(TrainingRow {
+                merchant_domain: coupon.merchant_domain.clone(),
+                code: coupon.code.clone(),
+                features,
+                worked,
+                recorded_at: now,
+            });
+            let due = outcomes.len() >= MIN_TRAINING_SAMPLES && outcomes.len() % RETRAIN_EVERY == 0;
+            due.then(|| outcomes.iter().map(|row| (row.features.clone(), row.worked)).collect::<Vec<_>>())
+        };
+
+        if let Some(samples) = samples {
+            let mut model = self.model.write().await;
+            *model = model.train(&samples, format!("coupon-trained-{}", samples.len()));
+            tracing::info!(outcomes = samples.len(), "Retrained the coupon success model");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn features(source_reliability: f64, format_match: f64) -> CouponFeatures {
+        CouponFeatures {
+            source_reliability,
+            format_match,
+            merchant_success_rate: 0.7,
+            recency: 1.0,
+            extraction_confidence: 0.8,
+        }
+    }
+
+    #[test]
+    fn test_training_learns_signal() {
+        // Codes with a good format work, the rest fail regardless of source
+        let samples: Vec<(CouponFeatures, bool)> = (0..100)
+            .map(|i| {
+                let good_format = i % 2 == 1;
+                let source = if i % 3 == 0 { 0.9 } else { 0.5 };
+                (features(source, if good_format { 1.0 } else { 0.0 }), good_format)
+            })
+            .collect();
+
+        let trained = CouponSuccessModel::default().train(&samples, "test".to_string());
+
+        assert!(trained.predict(&features(0.5, 1.0)) > 0.7);
+        assert!(trained.predict(&features(0.9, 0.0)) < 0.3);
+    }
+
+    #[tokio::test]
+    async fn test_affiliate_code_outranks_scraped_label() {
+        let store = crate::storage::coupon_store::CouponStore::with_sample_data();
+        let reputation = ReputationService::new(None);
+        let predictor = CouponSuccessPredictor::new(CouponSuccessModel::default());
+
+        let mut coupons = store.list().await;
+        predictor.annotate(&mut coupons, &reputation).await;
+
+        let predicted = |code: &str| coupons.iter().find(|c| c.code.as_str() == code).unwrap().predicted_success.unwrap();
+        assert!(predicted("SAVE20") > predicted("sitewide"));
+        assert!(predicted("SAVE20") > predicted("BOOKWORM10"));
+    }
+
+    #[tokio::test]
+    async fn test_codes_failing_at_checkout_sink_before_any_refit() {
+        let store = crate::storage::coupon_store::CouponStore::with_sample_data();
+        let reputation = ReputationService::new(None);
+        let predictor = CouponSuccessPredictor::new(CouponSuccessModel::default());
+        let mut coupons = store.list().await;
+        predictor.annotate(&mut coupons, &reputation).await;
+        let before = coupons.iter().find(|c| c.code.as_str() == "SAVE20").unwrap().clone();
+
+        for _ in 0..10 {
+            let features = predictor.features(&before, &reputation).await;
+            predictor.record_outcome(&before, features, false).await;
+        }
+        predictor.annotate(&mut coupons, &reputation).await;
+        let after = coupons.iter().find(|c| c.code.as_str() == "SAVE20").unwrap();
+
+        // One third model, two thirds observed: well below the sample's other codes
+        let expected = before.predicted_success.unwrap() * OBSERVED_PRIOR_WEIGHT / (OBSERVED_PRIOR_WEIGHT + 10.0);
+        assert!((after.predicted_success.unwrap() - expected).abs() < 0.002);
+        assert!(coupons.iter().filter(|c| c.code.as_str() != "SAVE20").all(|c| c.predicted_success > after.predicted_success));
+        let stats = predictor.code_stats(&before.merchant_domain, &before.code).await.unwrap();
+        assert_eq!((stats.attempts, stats.successes, stats.last_worked_at), (10, 0, None));
+        let rows = predictor.training_data().await;
+        assert_eq!(rows.len(), 10);
+        assert!(rows.iter().all(|row| !row.worked && row.code == before.code));
+    }
+}
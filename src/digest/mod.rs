@@ -0,0 +1,225 @@
+//! Daily deal digest
+//!
+//! The last day's deals are grouped into topics by embedding similarity
+//! ("laptop deals", "kitchen deals") and the resulting digest is cached for the
+//! `/digests/daily` endpoint and for notification delivery. Digests per user or
+//! channel on their own schedule are assembled by [`scheduled`].
+
+pub mod scheduled;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+use tokio::time::interval;
+use utoipa::ToSchema;
+
+use crate::experiments::RankingStrategy;
+use crate::models::deal::Deal;
+use crate::recommendations::embeddings::{cosine_similarity, Embedder, HashingEmbedder};
+use crate::services::ranking::RankingPipeline;
+use crate::tenant::DEFAULT_TENANT;
+
+/// Minimum similarity to a topic's centroid for a deal to join it
+const TOPIC_SIMILARITY: f32 = 0.35;
+/// Deals shown per topic
+const DEALS_PER_TOPIC: usize = 5;
+
+const LABEL_STOPWORDS: &[&str] = &[
+    "off", "buy", "get", "free", "the", "and", "for", "with", "inch", "deal", "deals", "sale", "new",
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct DigestTopic {
+    pub label: String,
+    pub category: String,
+    pub deal_count: usize,
+    /// Highest honest (or advertised) discount in the topic
+    pub best_discount: f64,
+    pub deals: Vec<Deal>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct DailyDigest {
+    pub date: NaiveDate,
+    pub generated_at: DateTime<Utc>,
+    pub total_deals: usize,
+    pub topics: Vec<DigestTopic>,
+}
+
+struct Topic {
+    centroid: Vec<f32>,
+    deals: Vec<Deal>,
+}
+
+pub struct DigestService {
+    embedder: Box<dyn Embedder>,
+    latest: RwLock<Option<DailyDigest>>,
+}
+
+impl DigestService {
+    pub fn new() -> Self {
+        Self {
+            embedder: Box::new(HashingEmbedder::default()),
+            latest: RwLock::new(None),
+        }
+    }
+
+    /// Group deals (best first) into topics, largest topics first
+    pub fn cluster(&self, deals: Vec<Deal>) -> Vec<DigestTopic> {
+        let mut topics: Vec<Topic> = Vec::new();
+
+        for deal in deals {
+            let embedding = self.embedder.embed(&deal);
+            let best = topics
+                .iter_mut()
+                .map(|topic| (cosine_similarity(&embedding, &topic.centroid), topic))
+                .filter(|(similarity, _)| *similarity >= TOPIC_SIMILARITY)
+                .max_by(|a, b| a.0.total_cmp(&b.0));
+
+            match best {
+                Some((_, topic)) => {
+                    let n = topic.deals.len() as f32;
+                    for (c, e) in topic.centroid.iter_mut().zip(&embedding) {
+                        *c = (*c * n + e) / (n + 1.0);
+                    }
+                    topic.deals.push(deal);
+                }
+                None => topics.push(Topic {
+                    centroid: embedding,
+                    deals: vec![deal],
+                }),
+            }
+        }
+
+        let mut digest_topics: Vec<DigestTopic> = topics.into_iter().map(summarize_topic).collect();
+        digest_topics.sort_by_key(|topic| std::cmp::Reverse(topic.deal_count));
+        digest_topics
+    }
+
+    /// Build the digest from deals posted in the 24 hours before `now`
+    pub fn build(&self, deals: Vec<Deal>, now: DateTime<Utc>) -> DailyDigest {
+        let recent: Vec<Deal> = deals
+            .into_iter()
+            .filter(|deal| deal.posted_at > now - Duration::hours(24) && deal.posted_at <= now)
+            .collect();
+
+        DailyDigest {
+            date: now.date_naive(),
+            generated_at: now,
+            total_deals: recent.len(),
+            topics: self.cluster(recent),
+        }
+    }
+
+    /// The cached digest for today, generating it if needed
+    pub async fn daily(&self, ranking: &RankingPipeline) -> DailyDigest {
+        let today = Utc::now().date_naive();
+        if let Some(digest) = self.latest.read().await.as_ref().filter(|d| d.date == today) {
+            return digest.clone();
+        }
+
+        let deals = ranking.ranked(DEFAULT_TENANT, RankingStrategy::ScoredWithoutEvents).await;
+        let digest = self.build(deals, Utc::now());
+        *self.latest.write().await = Some(digest.clone());
+        digest
+    }
+
+    /// Regenerate the digest once per day
+    pub async fn start_background_tasks(self: Arc<Self>, ranking: Arc<RankingPipeline>) {
+        let mut ticker = interval(std::time::Duration::from_secs(3600));
+        loop {
+            ticker.tick().await;
+            self.daily(&ranking).await;
+        }
+    }
+}
+
+impl Default for DigestService {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn summarize_topic(topic: Topic) -> DigestTopic {
+    let mut categories: HashMap<&str, usize> = HashMap::new();
+    let mut tokens: HashMap<String, usize> = HashMap::new();
+    for deal in &topic.deals {
+        *categories.entry(deal.category.as_str()).or_insert(0) += 1;
+
+        let mut seen = Vec::new();
+        for token in deal
+            .title
+            .to_lowercase()
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|t| t.len() > 2 && !t.chars().all(|c| c.is_ascii_digit()) && !LABEL_STOPWORDS.contains(t))
+        {
+            let token = token.trim_end_matches('s').to_string();
+            if !seen.contains(&token) {
+                seen.push(token.clone());
+                *tokens.entry(token).or_insert(0) += 1;
+            }
+        }
+    }
+
+    let category = categories
+        .into_iter()
+        .max_by(|a, b| a.1.cmp(&b.1).then_with(|| b.0.cmp(a.0)))
+        .map(|(c, _)| c.to_string())
+        .unwrap_or_default();
+
+    // Name the topic after words most of its deals share, else after the category
+    let min_shared = topic.deals.len().div_ceil(2).max(2);
+    let mut shared: Vec<(String, usize)> = tokens.into_iter().filter(|(_, n)| *n >= min_shared).collect();
+    shared.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    let label = if shared.is_empty() {
+        format!("{} deals", category.replace('_', " "))
+    } else {
+        let words: Vec<&str> = shared.iter().take(2).map(|(t, _)| t.as_str()).collect();
+        format!("{} deals", words.join(" "))
+    };
+
+    DigestTopic {
+        label,
+        category,
+        deal_count: topic.deals.len(),
+        best_discount: topic
+            .deals
+            .iter()
+            .map(|d| d.honest_discount.unwrap_or(d.discount))
+            .fold(0.0, f64::max),
+        deals: topic.deals.into_iter().take(DEALS_PER_TOPIC).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::deal_store::DealStore;
+
+    #[tokio::test]
+    async fn test_laptops_cluster_together() {
+        let deals = DealStore::with_sample_data().list().await;
+        let topics = DigestService::new().cluster(deals);
+
+        let laptops = topics
+            .iter()
+            .find(|t| t.deals.iter().any(|d| d.id == "deal_1"))
+            .unwrap();
+        assert!(laptops.deals.iter().any(|d| d.id == "deal_7"));
+        assert_eq!(laptops.label, "laptop deals");
+        assert!(topics.iter().any(|t| t.label == "books deals" && t.deal_count == 1));
+    }
+
+    #[tokio::test]
+    async fn test_digest_only_includes_last_day() {
+        let deals = DealStore::with_sample_data().list().await;
+        let digest = DigestService::new().build(deals, Utc::now());
+
+        // Sample deals are posted 5 hours apart; deals 6 and 7 are older than a day
+        assert_eq!(digest.total_deals, 5);
+        assert_eq!(digest.topics.iter().map(|t| t.deal_count).sum::<usize>(), 5);
+    }
+}
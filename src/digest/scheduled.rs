@@ -0,0 +1,646 @@
+//! Scheduled digests per user or channel
+//!
+//! A [`DigestSubscription`] asks for a daily or weekly digest for one user or one
+//! shared channel. Each digest holds the best ranked deals posted in the period in
+//! the subscription's categories, price changes of its watched products over the
+//! period, and its saved coupons that expire before the next digest. For users who
+//! did not pick categories, their notification preference categories are used.
+//!
+//! Digests are rendered with the `templates/digest.html` and `templates/digest.txt`
+//! templates and handed to the [`NotificationDispatcher`]: a user's digest is
+//! subject to the notification preferences of its user or channel (see
+//! [`Recipient::outbox`]), like every other notification. A digest
+//! held for quiet hours is retried when they end; empty digests are not sent.
+//! With `REDIS_URL` set, subscriptions are shared and re-read every
+//! [`REFRESH_INTERVAL`]; otherwise they are persisted to `DIGEST_SUBSCRIPTIONS_PATH`
+//! (default `data/digest_subscriptions.json`).
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use askama::Template;
+use chrono::{DateTime, TimeDelta, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::clock::{self, Clock};
+use crate::experiments::RankingStrategy;
+use crate::models::deal::Deal;
+use crate::models::domain::{CouponCode, MerchantDomain};
+use crate::notifications::{Channel, Delivery, Message, Notification, NotificationDispatcher};
+use crate::services::ranking::RankingPipeline;
+use crate::storage::coupon_store::CouponStore;
+use crate::storage::deal_store::DealStore;
+use crate::storage::persisted::{PersistedStore, StoreError};
+use crate::tenant::DEFAULT_TENANT;
+
+const REDIS_KEY: &str = "digest_subscriptions";
+const STORE_NAME: &str = "digest subscriptions";
+/// How often shared subscriptions are re-read
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+/// Deals listed per digest
+pub const TOP_DEALS: usize = 5;
+/// Watched products and saved coupons one subscription may hold
+pub const MAX_WATCHED: usize = 100;
+/// How often [`DigestScheduler::run_due`] should run
+pub const DIGEST_TICK: Duration = Duration::from_secs(15 * 60);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Frequency {
+    #[default]
+    Daily,
+    Weekly,
+}
+
+impl Frequency {
+    pub fn period(self) -> TimeDelta {
+        match self {
+            Frequency::Daily => TimeDelta::days(1),
+            Frequency::Weekly => TimeDelta::weeks(1),
+        }
+    }
+}
+
+/// Who a digest is for
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Recipient {
+    User { user_id: String },
+    /// A shared channel, such as a community group, with the preferences set for
+    /// `channel:<name>`
+    Channel { name: String },
+}
+
+impl Recipient {
+    /// Whose outbox the digest is queued in
+    pub fn outbox(&self) -> String {
+        match self {
+            Recipient::User { user_id } => user_id.clone(),
+            Recipient::Channel { name } => format!("channel:{}", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct SavedCoupon {
+    pub merchant_domain: MerchantDomain,
+    pub code: CouponCode,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct DigestSubscriptionRequest {
+    pub recipient: Recipient,
+    #[serde(default)]
+    pub frequency: Frequency,
+    #[serde(default = "default_channel")]
+    pub channel: Channel,
+    /// Deal categories to pick top deals from; empty for the user's notification
+    /// categories, or all
+    #[serde(default)]
+    pub categories: Vec<String>,
+    /// Product ids whose price changes are reported
+    #[serde(default)]
+    pub watched_products: Vec<String>,
+    #[serde(default)]
+    pub saved_coupons: Vec<SavedCoupon>,
+}
+
+fn default_channel() -> Channel {
+    Channel::Email
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct DigestSubscription {
+    pub id: Uuid,
+    pub tenant: String,
+    pub recipient: Recipient,
+    pub frequency: Frequency,
+    pub channel: Channel,
+    pub categories: Vec<String>,
+    pub watched_products: Vec<String>,
+    pub saved_coupons: Vec<SavedCoupon>,
+    pub created_at: DateTime<Utc>,
+    /// When the next digest is built
+    pub next_due: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_sent_at: Option<DateTime<Utc>>,
+}
+
+/// A watched product whose price moved over the period
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PriceChange {
+    pub product_id: String,
+    pub title: String,
+    pub previous: Decimal,
+    pub current: Decimal,
+    /// Negative for drops, rounded to one place
+    pub change_percent: Decimal,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ExpiringCoupon {
+    pub merchant_domain: MerchantDomain,
+    pub code: CouponCode,
+    pub title: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ScheduledDigest {
+    pub subscription_id: Uuid,
+    pub frequency: Frequency,
+    pub period_start: DateTime<Utc>,
+    pub generated_at: DateTime<Utc>,
+    pub deals: Vec<Deal>,
+    pub price_changes: Vec<PriceChange>,
+    pub expiring_coupons: Vec<ExpiringCoupon>,
+}
+
+/// Subject and bodies of a digest
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct RenderedDigest {
+    pub subject: String,
+    pub html: String,
+    pub text: String,
+}
+
+#[derive(Template)]
+#[template(path = "digest.html")]
+struct HtmlDigest<'a> {
+    subject: &'a str,
+    digest: &'a ScheduledDigest,
+}
+
+#[derive(Template)]
+#[template(path = "digest.txt")]
+struct TextDigest<'a> {
+    subject: &'a str,
+    digest: &'a ScheduledDigest,
+}
+
+impl ScheduledDigest {
+    pub fn is_empty(&self) -> bool {
+        self.deals.is_empty() && self.price_changes.is_empty() && self.expiring_coupons.is_empty()
+    }
+
+    pub fn subject(&self) -> String {
+        let mut parts = Vec::new();
+        let count = |n: usize, one: &str, many: &str| format!("{} {}", n, if n == 1 { one } else { many });
+        if !self.deals.is_empty() {
+            parts.push(count(self.deals.len(), "deal", "deals"));
+        }
+        if !self.price_changes.is_empty() {
+            parts.push(count(self.price_changes.len(), "price change", "price changes"));
+        }
+        if !self.expiring_coupons.is_empty() {
+            parts.push(count(self.expiring_coupons.len(), "expiring coupon", "expiring coupons"));
+        }
+        let frequency = match self.frequency {
+            Frequency::Daily => "daily",
+            Frequency::Weekly => "weekly",
+        };
+        match parts.is_empty() {
+            true => format!("Your {} DealMate digest", frequency),
+            false => format!("Your {} DealMate digest: {}", frequency, parts.join(", ")),
+        }
+    }
+
+    pub fn render(&self) -> Result<RenderedDigest, askama::Error> {
+        let subject = self.subject();
+        let html = HtmlDigest { subject: &subject, digest: self }.render()?;
+        let text = TextDigest { subject: &subject, digest: self }.render()?;
+        Ok(RenderedDigest { subject, html, text })
+    }
+}
+
+/// What one pass over the due subscriptions did
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DigestRun {
+    pub sent: usize,
+    /// Held for quiet hours, retried when they end
+    pub held: usize,
+    /// Refused by the recipient's preferences
+    pub dropped: usize,
+    /// Nothing to report this period
+    pub empty: usize,
+}
+
+pub struct DigestScheduler {
+    subscriptions: Mutex<HashMap<Uuid, DigestSubscription>>,
+    notifications: Arc<NotificationDispatcher>,
+    /// Every subscription in the file, one field of the Redis hash each
+    store: PersistedStore<HashMap<Uuid, DigestSubscription>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl DigestScheduler {
+    /// Subscriptions persisted to `path`, or kept in memory only
+    pub fn new(path: Option<PathBuf>, notifications: Arc<NotificationDispatcher>) -> Self {
+        Self::with_store(PersistedStore::new(STORE_NAME, REDIS_KEY, path), notifications)
+    }
+
+    /// Subscriptions shared through Redis
+    pub fn shared(redis_url: &str, notifications: Arc<NotificationDispatcher>) -> Result<Self, StoreError> {
+        Ok(Self::with_store(PersistedStore::shared(STORE_NAME, REDIS_KEY, redis_url)?, notifications))
+    }
+
+    fn with_store(store: PersistedStore<HashMap<Uuid, DigestSubscription>>, notifications: Arc<NotificationDispatcher>) -> Self {
+        Self {
+            subscriptions: Mutex::new(HashMap::new()),
+            notifications,
+            store,
+            clock: clock::system(),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Share subscriptions through `REDIS_URL` when set, otherwise load them from
+    /// `DIGEST_SUBSCRIPTIONS_PATH` (default `data/digest_subscriptions.json`)
+    pub async fn from_env(notifications: Arc<NotificationDispatcher>) -> Self {
+        let store = PersistedStore::from_env(STORE_NAME, REDIS_KEY, "DIGEST_SUBSCRIPTIONS_PATH", "data/digest_subscriptions.json");
+        let scheduler = Self::with_store(store, notifications);
+        if let Err(e) = scheduler.reload().await {
+            tracing::warn!(error = %e, "Starting without digest subscriptions");
+        }
+        scheduler
+    }
+
+    /// Replace the local copy with every stored subscription
+    pub async fn reload(&self) -> Result<(), StoreError> {
+        if let Some(stored) = self.store.load_map(REDIS_KEY).await? {
+            *self.subscriptions.lock().await = stored;
+        }
+        Ok(())
+    }
+
+    /// Re-read shared subscriptions every [`REFRESH_INTERVAL`], picking up changes
+    /// made through other instances
+    pub async fn start_background_tasks(self: Arc<Self>) {
+        self.store.refresh_every(self.clock.as_ref(), REFRESH_INTERVAL, || self.reload()).await
+    }
+
+    /// Subscribe; the first digest is due one period from now
+    pub async fn subscribe(&self, tenant: &str, request: DigestSubscriptionRequest) -> Result<DigestSubscription, String> {
+        let recipient = match request.recipient {
+            Recipient::User { user_id } => Recipient::User { user_id: user_id.trim().to_string() },
+            Recipient::Channel { name } => Recipient::Channel { name: name.trim().to_lowercase() },
+        };
+        if matches!(&recipient, Recipient::User { user_id: id } | Recipient::Channel { name: id } if id.is_empty()) {
+            return Err("recipient must not be empty".to_string());
+        }
+        if request.watched_products.len() > MAX_WATCHED || request.saved_coupons.len() > MAX_WATCHED {
+            return Err(format!("at most {} watched products and {} saved coupons", MAX_WATCHED, MAX_WATCHED));
+        }
+
+        let mut categories: Vec<String> = request
+            .categories
+            .iter()
+            .map(|category| category.trim().to_lowercase())
+            .filter(|category| !category.is_empty())
+            .collect();
+        categories.sort();
+        categories.dedup();
+
+        let now = self.clock.now();
+        let subscription = DigestSubscription {
+            id: Uuid::new_v4(),
+            tenant: tenant.to_string(),
+            recipient,
+            frequency: request.frequency,
+            channel: request.channel,
+            categories,
+            watched_products: request.watched_products,
+            saved_coupons: request.saved_coupons,
+            created_at: now,
+            next_due: now + request.frequency.period(),
+            last_sent_at: None,
+        };
+        let mut subscriptions = self.subscriptions.lock().await;
+        subscriptions.insert(subscription.id, subscription.clone());
+        self.store
+            .persist_field(REDIS_KEY, &subscription.id.to_string(), Some(&subscription), || subscriptions.clone())
+            .await;
+        Ok(subscription)
+    }
+
+    /// A tenant's subscriptions
+    pub async fn subscriptions(&self, tenant: &str) -> Vec<DigestSubscription> {
+        let mut subscriptions: Vec<DigestSubscription> = self
+            .subscriptions
+            .lock()
+            .await
+            .values()
+            .filter(|s| s.tenant == tenant)
+            .cloned()
+            .collect();
+        subscriptions.sort_by_key(|s| s.created_at);
+        subscriptions
+    }
+
+    /// Remove a tenant's subscription; false when it has none with `id`
+    pub async fn unsubscribe(&self, tenant: &str, id: Uuid) -> bool {
+        let mut subscriptions = self.subscriptions.lock().await;
+        if subscriptions.get(&id).is_none_or(|s| s.tenant != tenant) {
+            return false;
+        }
+        subscriptions.remove(&id);
+        self.store
+            .persist_field(REDIS_KEY, &id.to_string(), None::<&DigestSubscription>, || subscriptions.clone())
+            .await;
+        true
+    }
+
+    /// The digest a tenant's subscription would get now, without sending it
+    pub async fn preview(&self, tenant: &str, id: Uuid, ranked: &[Deal], deals: &DealStore, coupons: &CouponStore) -> Option<ScheduledDigest> {
+        let subscription = self.subscriptions.lock().await.get(&id).filter(|s| s.tenant == tenant).cloned()?;
+        Some(self.build(&subscription, ranked, deals, coupons).await)
+    }
+
+    /// Assemble `subscription`'s digest for the period ending now from `ranked`
+    /// deals, best first
+    pub async fn build(&self, subscription: &DigestSubscription, ranked: &[Deal], deals: &DealStore, coupons: &CouponStore) -> ScheduledDigest {
+        let now = self.clock.now();
+        let period_start = now - subscription.frequency.period();
+
+        let categories = match (&subscription.recipient, subscription.categories.is_empty()) {
+            (Recipient::User { user_id }, true) => self.notifications.preferences(user_id).await.categories,
+            _ => subscription.categories.clone(),
+        };
+        let top_deals = ranked
+            .iter()
+            .filter(|deal| deal.posted_at > period_start && deal.posted_at <= now)
+            .filter(|deal| categories.is_empty() || categories.contains(&deal.category.to_lowercase()))
+            .take(TOP_DEALS)
+            .cloned()
+            .collect();
+
+        let mut price_changes = Vec::new();
+        for product_id in &subscription.watched_products {
+            let history = deals.price_history(product_id).await;
+            let observed: Vec<_> = history.iter().filter(|point| point.observed_at <= now).collect();
+            let Some(current) = observed.last() else {
+                continue;
+            };
+            // The price as the period started, else the first one seen in it
+            let previous = observed.iter().rev().find(|point| point.observed_at <= period_start).or(observed.first());
+            let Some(previous) = previous.filter(|previous| previous.price != current.price && !previous.price.is_zero()) else {
+                continue;
+            };
+            let title = match deals.for_product(product_id).await.first() {
+                Some(deal) => deal.title.clone(),
+                None => product_id.clone(),
+            };
+            price_changes.push(PriceChange {
+                product_id: product_id.clone(),
+                title,
+                previous: previous.price,
+                current: current.price,
+                change_percent: ((current.price - previous.price) / previous.price * Decimal::ONE_HUNDRED).round_dp(1),
+            });
+        }
+
+        let next_digest = now + subscription.frequency.period();
+        let mut expiring_coupons = Vec::new();
+        for saved in &subscription.saved_coupons {
+            let Some(coupon) = coupons.find(&saved.merchant_domain, &saved.code).await else {
+                continue;
+            };
+            if let Some(expires_at) = coupon.valid_until.filter(|at| *at > now && *at <= next_digest) {
+                expiring_coupons.push(ExpiringCoupon {
+                    merchant_domain: coupon.merchant_domain,
+                    code: coupon.code,
+                    title: coupon.title,
+                    expires_at,
+                });
+            }
+        }
+        expiring_coupons.sort_by_key(|coupon| coupon.expires_at);
+
+        ScheduledDigest {
+            subscription_id: subscription.id,
+            frequency: subscription.frequency,
+            period_start,
+            generated_at: now,
+            deals: top_deals,
+            price_changes,
+            expiring_coupons,
+        }
+    }
+
+    /// Build, render and hand over every digest that is due
+    pub async fn run(&self, ranked: &[Deal], deals: &DealStore, coupons: &CouponStore) -> DigestRun {
+        let now = self.clock.now();
+        // Subscriptions may have been made or changed through other instances
+        if self.store.is_shared() {
+            if let Err(e) = self.reload().await {
+                tracing::warn!(error = %e, "Failed to re-read digest subscriptions; using the local copy");
+            }
+        }
+        let due: Vec<DigestSubscription> = self
+            .subscriptions
+            .lock()
+            .await
+            .values()
+            .filter(|s| s.next_due <= now)
+            .cloned()
+            .collect();
+
+        let mut run = DigestRun::default();
+        let mut outcomes = Vec::new();
+        for subscription in due {
+            let digest = self.build(&subscription, ranked, deals, coupons).await;
+            let next_due = match digest.is_empty() {
+                true => {
+                    run.empty += 1;
+                    None
+                }
+                false => match self.dispatch(&subscription, &digest).await {
+                    Some(Delivery::Hold { until }) => {
+                        run.held += 1;
+                        Some(until)
+                    }
+                    Some(Delivery::Drop { .. }) | None => {
+                        run.dropped += 1;
+                        None
+                    }
+                    Some(Delivery::Send) => {
+                        run.sent += 1;
+                        outcomes.push((subscription.id, Some(now), None));
+                        continue;
+                    }
+                },
+            };
+            outcomes.push((subscription.id, None, next_due));
+        }
+
+        let mut subscriptions = self.subscriptions.lock().await;
+        let mut updated = Vec::new();
+        for (id, sent_at, retry_at) in outcomes {
+            // Unsubscribed while the digest was built
+            let Some(subscription) = subscriptions.get_mut(&id) else {
+                continue;
+            };
+            if sent_at.is_some() {
+                subscription.last_sent_at = sent_at;
+            }
+            subscription.next_due = match retry_at {
+                Some(at) => at,
+                None => next_period(subscription.next_due, subscription.frequency, now),
+            };
+            updated.push((id.to_string(), subscription.clone()));
+        }
+        let fields: Vec<(&str, Option<&DigestSubscription>)> = updated.iter().map(|(id, s)| (id.as_str(), Some(s))).collect();
+        if let Err(e) = self.store.write_fields(REDIS_KEY, &fields, || subscriptions.clone()).await {
+            tracing::warn!(error = %e, "Failed to persist digest subscriptions");
+        }
+        run
+    }
+
+    /// `None` when the digest could not be rendered
+    async fn dispatch(&self, subscription: &DigestSubscription, digest: &ScheduledDigest) -> Option<Delivery> {
+        let rendered = match digest.render() {
+            Ok(rendered) => rendered,
+            Err(e) => {
+                tracing::error!(subscription = %subscription.id, error = %e, "Failed to render a digest");
+                return None;
+            }
+        };
+        let message = Message {
+            notification: Notification { channel: subscription.channel, category: None },
+            subject: rendered.subject,
+            html: Some(rendered.html),
+            text: rendered.text,
+            queued_at: digest.generated_at,
+        };
+        Some(self.notifications.send(&subscription.recipient.outbox(), message).await)
+    }
+
+    /// Rank the deals and send the digests that are due
+    pub async fn run_due(&self, ranking: &RankingPipeline, deals: &DealStore, coupons: &CouponStore) -> DigestRun {
+        let ranked = ranking.ranked(DEFAULT_TENANT, RankingStrategy::ScoredWithoutEvents).await;
+        let run = self.run(&ranked, deals, coupons).await;
+        if run != DigestRun::default() {
+            tracing::info!(sent = run.sent, held = run.held, dropped = run.dropped, empty = run.empty, "Sent due digests");
+        }
+        run
+    }
+}
+
+/// The first due time after `now` on the subscription's schedule
+fn next_period(due: DateTime<Utc>, frequency: Frequency, now: DateTime<Utc>) -> DateTime<Utc> {
+    let mut next = due + frequency.period();
+    while next <= now {
+        next += frequency.period();
+    }
+    next
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::models::coupon_listing::{CouponListing, CouponSource};
+    use crate::notifications::NotificationPreferences;
+
+    fn coupon(code: &str, valid_until: DateTime<Utc>) -> CouponListing {
+        CouponListing {
+            code: CouponCode::parse(code).unwrap(),
+            title: format!("{} off", code),
+            description: None,
+            locale: None,
+            merchant_domain: MerchantDomain::parse("bestbuy.com").unwrap(),
+            discount_type: "percentage".to_string(),
+            discount_value: Some(10.0),
+            minimum_order: None,
+            source: CouponSource::AffiliateApi,
+            license: None,
+            extraction_confidence: 0.9,
+            scraped_at: valid_until - TimeDelta::days(30),
+            valid_until: Some(valid_until),
+            predicted_success: None,
+            tags: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_builds_renders_and_schedules_digests() {
+        let deals = DealStore::with_sample_data();
+        let clock = Arc::new(MockClock::new());
+        let now = clock.now();
+        let notifications = Arc::new(NotificationDispatcher::new(None).with_clock(clock.clone()));
+        let scheduler = DigestScheduler::new(None, notifications.clone()).with_clock(clock.clone());
+        let coupons = CouponStore::new();
+        coupons.upsert(coupon("SAVE10", now + TimeDelta::days(3))).await;
+        coupons.upsert(coupon("SAVE15", now + TimeDelta::days(10))).await;
+        coupons.upsert(coupon("LATER20", now + TimeDelta::days(30))).await;
+
+        let preferences = NotificationPreferences {
+            categories: vec!["electronics".to_string()],
+            ..Default::default()
+        };
+        notifications.set_preferences("ana", preferences).await.unwrap();
+        let saved = |code: &str| SavedCoupon {
+            merchant_domain: MerchantDomain::parse("bestbuy.com").unwrap(),
+            code: CouponCode::parse(code).unwrap(),
+        };
+        let ana = scheduler
+            .subscribe(
+                "acme",
+                DigestSubscriptionRequest {
+                    recipient: Recipient::User { user_id: "ana".to_string() },
+                    frequency: Frequency::Weekly,
+                    channel: Channel::Email,
+                    categories: Vec::new(),
+                    watched_products: vec!["prod_1".to_string(), "no_such_product".to_string()],
+                    saved_coupons: vec![saved("SAVE10"), saved("SAVE15"), saved("LATER20")],
+                },
+            )
+            .await
+            .unwrap();
+        let channel = serde_json::from_value(serde_json::json!({"recipient": {"type": "channel", "name": "Kitchen"}, "categories": ["Kitchen"]}));
+        scheduler.subscribe("acme", channel.unwrap()).await.unwrap();
+
+        // Nothing is due before the first period ends; previews are built on demand
+        let ranked = deals.list().await;
+        assert_eq!(scheduler.run(&ranked, &deals, &coupons).await, DigestRun::default());
+        let digest = scheduler.preview("acme", ana.id, &ranked, &deals, &coupons).await.unwrap();
+        assert!(scheduler.preview("globex", ana.id, &ranked, &deals, &coupons).await.is_none());
+        assert!(!digest.deals.is_empty() && digest.deals.iter().all(|deal| deal.category == "electronics"));
+        assert_eq!(digest.price_changes.len(), 1);
+        assert_eq!(digest.price_changes[0].current, deals.get("deal_1").await.unwrap().price.amount);
+        assert_eq!(digest.expiring_coupons.len(), 1);
+        assert_eq!(digest.expiring_coupons[0].code.as_str(), "SAVE10");
+
+        let rendered = digest.render().unwrap();
+        assert!(rendered.subject.starts_with("Your weekly DealMate digest: "));
+        assert!(rendered.html.contains("<code>SAVE10</code>"));
+        assert!(rendered.text.contains("- SAVE10 at bestbuy.com"));
+        assert!(!rendered.text.contains('<'));
+
+        // A week on only SAVE15 is worth a mention; the kitchen channel has no new deals
+        clock.advance(Duration::from_secs(7 * 24 * 3600));
+        let run = scheduler.run(&ranked, &deals, &coupons).await;
+        assert_eq!((run.sent, run.empty), (1, 1));
+        let outbox = notifications.outbox("ana").await;
+        assert_eq!(outbox.len(), 1);
+        assert_eq!(outbox[0].subject, "Your weekly DealMate digest: 1 expiring coupon");
+        assert!(outbox[0].text.contains("SAVE15") && !outbox[0].text.contains("SAVE10"));
+        assert!(notifications.outbox("channel:kitchen").await.is_empty());
+
+        let subscriptions = scheduler.subscriptions("acme").await;
+        assert!(subscriptions.iter().all(|s| s.next_due == now + TimeDelta::weeks(2) || s.next_due == now + TimeDelta::days(8)));
+        assert_eq!(scheduler.run(&ranked, &deals, &coupons).await, DigestRun::default());
+        assert!(scheduler.unsubscribe("acme", ana.id).await);
+        assert!(!scheduler.unsubscribe("acme", ana.id).await);
+    }
+}
@@ -0,0 +1,359 @@
+//! Shopping-event calendar
+//!
+//! Black Friday, Prime Day, regional festivals and similar sale windows. While an
+//! event is running, deals in its categories are tagged with the event and get a
+//! ranking boost. Tenants can add their own events or replace the defaults via the
+//! JSON file at `EVENTS_CONFIG_PATH`.
+
+use std::collections::HashMap;
+
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::models::deal::Deal;
+
+/// Regions served when a tenant does not configure its own
+const DEFAULT_REGIONS: &[&str] = &["us"];
+
+/// When an event takes place; end dates are inclusive
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EventWindow {
+    /// One-off window, e.g. a festival whose date moves every year
+    Fixed { start: NaiveDate, end: NaiveDate },
+    /// Same calendar dates every year
+    Annual { month: u32, day: u32, days: u32 },
+    /// Starts on the nth weekday of a month, e.g. the 4th Friday of November
+    NthWeekday { month: u32, weekday: Weekday, n: u8, days: u32 },
+}
+
+impl EventWindow {
+    fn occurrence(&self, year: i32) -> Option<(NaiveDate, NaiveDate)> {
+        match self {
+            EventWindow::Fixed { start, end } => (start.year() == year).then_some((*start, *end)),
+            EventWindow::Annual { month, day, days } => {
+                let start = NaiveDate::from_ymd_opt(year, *month, *day)?;
+                Some((start, start + Duration::days(*days as i64 - 1)))
+            }
+            EventWindow::NthWeekday { month, weekday, n, days } => {
+                let start = NaiveDate::from_weekday_of_month_opt(year, *month, *weekday, *n)?;
+                Some((start, start + Duration::days(*days as i64 - 1)))
+            }
+        }
+    }
+
+    /// The running occurrence, or the next one to start
+    fn next_occurrence(&self, today: NaiveDate) -> Option<(NaiveDate, NaiveDate)> {
+        (today.year() - 1..=today.year() + 1)
+            .filter_map(|year| self.occurrence(year))
+            .find(|(_, end)| *end >= today)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct EventDefinition {
+    pub id: String,
+    pub name: String,
+    pub window: EventWindow,
+    pub categories: Vec<String>,
+    /// Relative score boost for matching deals while the event runs
+    #[serde(default = "default_boost")]
+    pub boost: f64,
+    /// Regions the event applies to; empty means everywhere
+    #[serde(default)]
+    pub regions: Vec<String>,
+}
+
+fn default_boost() -> f64 {
+    0.2
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TenantEvents {
+    #[serde(default)]
+    pub regions: Vec<String>,
+    /// Drop the built-in and global events for this tenant
+    #[serde(default)]
+    pub replace_defaults: bool,
+    #[serde(default)]
+    pub events: Vec<EventDefinition>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct CalendarConfig {
+    #[serde(default)]
+    events: Vec<EventDefinition>,
+    #[serde(default)]
+    tenants: HashMap<String, TenantEvents>,
+}
+
+/// A dated occurrence of an event, as served by `/events`
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct EventOccurrence {
+    pub id: String,
+    pub name: String,
+    pub starts_on: NaiveDate,
+    pub ends_on: NaiveDate,
+    pub categories: Vec<String>,
+    pub boost: f64,
+    pub active: bool,
+}
+
+pub struct EventCalendar {
+    events: Vec<EventDefinition>,
+    tenants: HashMap<String, TenantEvents>,
+}
+
+impl EventCalendar {
+    pub fn new(events: Vec<EventDefinition>, tenants: HashMap<String, TenantEvents>) -> Self {
+        Self { events, tenants }
+    }
+
+    /// Built-in events plus anything configured in `EVENTS_CONFIG_PATH`
+    pub fn from_env() -> Self {
+        let mut events = default_events();
+
+        let Ok(path) = std::env::var("EVENTS_CONFIG_PATH") else {
+            return Self::new(events, HashMap::new());
+        };
+
+        match Self::load_config(&path) {
+            Ok(config) => {
+                events.extend(config.events);
+                Self::new(events, config.tenants)
+            }
+            Err(e) => {
+                tracing::warn!(path = %path, error = %e, "Failed to load the events config");
+                Self::new(events, HashMap::new())
+            }
+        }
+    }
+
+    fn load_config(path: &str) -> Result<CalendarConfig, Box<dyn std::error::Error + Send + Sync>> {
+        let content = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    fn events_for(&self, tenant: &str) -> Vec<&EventDefinition> {
+        let config = self.tenants.get(tenant);
+        let regions: Vec<&str> = match config {
+            Some(config) if !config.regions.is_empty() => config.regions.iter().map(String::as_str).collect(),
+            _ => DEFAULT_REGIONS.to_vec(),
+        };
+
+        let mut events: Vec<&EventDefinition> = Vec::new();
+        if !config.is_some_and(|c| c.replace_defaults) {
+            events.extend(
+                self.events
+                    .iter()
+                    .filter(|e| e.regions.is_empty() || e.regions.iter().any(|r| regions.contains(&r.as_str()))),
+            );
+        }
+        if let Some(config) = config {
+            // Tenant events override global events with the same id
+            events.retain(|e| !config.events.iter().any(|t| t.id == e.id));
+            events.extend(&config.events);
+        }
+        events
+    }
+
+    /// Events running today or starting within `days`, soonest first
+    pub fn upcoming(&self, tenant: &str, today: NaiveDate, days: i64) -> Vec<EventOccurrence> {
+        let horizon = today + Duration::days(days);
+        let mut upcoming: Vec<EventOccurrence> = self
+            .events_for(tenant)
+            .into_iter()
+            .filter_map(|event| occurrence(event, today))
+            .filter(|o| o.starts_on <= horizon)
+            .collect();
+
+        upcoming.sort_by(|a, b| a.starts_on.cmp(&b.starts_on).then_with(|| a.id.cmp(&b.id)));
+        upcoming
+    }
+
+    /// The current or next occurrence of an event
+    pub fn find(&self, tenant: &str, id: &str, today: NaiveDate) -> Option<EventOccurrence> {
+        self.events_for(tenant)
+            .into_iter()
+            .find(|event| event.id == id)
+            .and_then(|event| occurrence(event, today))
+    }
+
+    /// Tag deals with the events running today and boost their scores, then re-rank
+    pub fn apply(&self, tenant: &str, today: NaiveDate, deals: &mut [Deal]) {
+        let active: Vec<EventOccurrence> = self.upcoming(tenant, today, 0).into_iter().filter(|o| o.active).collect();
+        if active.is_empty() {
+            return;
+        }
+
+        for deal in deals.iter_mut() {
+            let matching: Vec<&EventOccurrence> = active.iter().filter(|o| o.matches(deal)).collect();
+            let Some(boost) = matching.iter().map(|o| o.boost).reduce(f64::max) else {
+                continue;
+            };
+
+            deal.events = matching.iter().map(|o| o.id.clone()).collect();
+            deal.score = deal.score.map(|s| ((s * (1.0 + boost)).min(1.0) * 1000.0).round() / 1000.0);
+        }
+
+        deals.sort_by(|a, b| b.score.unwrap_or(0.0).total_cmp(&a.score.unwrap_or(0.0)));
+    }
+}
+
+impl EventOccurrence {
+    pub fn matches(&self, deal: &Deal) -> bool {
+        self.categories.iter().any(|c| c.eq_ignore_ascii_case(&deal.category))
+    }
+}
+
+fn occurrence(event: &EventDefinition, today: NaiveDate) -> Option<EventOccurrence> {
+    let (starts_on, ends_on) = event.window.next_occurrence(today)?;
+    Some(EventOccurrence {
+        id: event.id.clone(),
+        name: event.name.clone(),
+        starts_on,
+        ends_on,
+        categories: event.categories.clone(),
+        boost: event.boost,
+        active: starts_on <= today && today <= ends_on,
+    })
+}
+
+fn event(id: &str, name: &str, window: EventWindow, categories: &[&str], boost: f64, regions: &[&str]) -> EventDefinition {
+    EventDefinition {
+        id: id.to_string(),
+        name: name.to_string(),
+        window,
+        categories: categories.iter().map(|c| c.to_string()).collect(),
+        boost,
+        regions: regions.iter().map(|r| r.to_string()).collect(),
+    }
+}
+
+fn default_events() -> Vec<EventDefinition> {
+    vec![
+        event(
+            "black_friday",
+            "Black Friday & Cyber Monday",
+            EventWindow::NthWeekday { month: 11, weekday: Weekday::Fri, n: 4, days: 4 },
+            &["electronics", "toys", "kitchen", "home", "fashion"],
+            0.3,
+            &[],
+        ),
+        // Amazon announces the dates each year; tenants pin them with a fixed window
+        event(
+            "prime_day",
+            "Prime Day",
+            EventWindow::Annual { month: 7, day: 8, days: 4 },
+            &["electronics", "home", "kitchen"],
+            0.2,
+            &[],
+        ),
+        event(
+            "back_to_school",
+            "Back to School",
+            EventWindow::Annual { month: 7, day: 15, days: 52 },
+            &["back_to_school", "books", "electronics"],
+            0.2,
+            &["us", "ca"],
+        ),
+        event(
+            "holiday_gifting",
+            "Holiday Gifting",
+            EventWindow::Annual { month: 12, day: 1, days: 24 },
+            &["toys", "electronics", "fashion", "beauty"],
+            0.15,
+            &[],
+        ),
+        event(
+            "boxing_day",
+            "Boxing Day",
+            EventWindow::Annual { month: 12, day: 26, days: 2 },
+            &["electronics", "fashion", "home"],
+            0.2,
+            &["uk", "ca", "au"],
+        ),
+        event(
+            "singles_day",
+            "Singles' Day",
+            EventWindow::Annual { month: 11, day: 11, days: 1 },
+            &["electronics", "fashion", "beauty"],
+            0.25,
+            &["cn", "sg", "my"],
+        ),
+        event(
+            "diwali_2026",
+            "Diwali Festive Sale",
+            EventWindow::Fixed {
+                start: NaiveDate::from_ymd_opt(2026, 10, 28).unwrap(),
+                end: NaiveDate::from_ymd_opt(2026, 11, 8).unwrap(),
+            },
+            &["electronics", "home", "kitchen", "fashion"],
+            0.3,
+            &["in"],
+        ),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::deal_store::DealStore;
+    use crate::tenant::DEFAULT_TENANT;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[test]
+    fn test_black_friday_window() {
+        let calendar = EventCalendar::new(default_events(), HashMap::new());
+        let black_friday = calendar.find(DEFAULT_TENANT, "black_friday", date(2026, 10, 1)).unwrap();
+
+        assert_eq!(black_friday.starts_on, date(2026, 11, 27));
+        assert_eq!(black_friday.ends_on, date(2026, 11, 30));
+        assert!(!black_friday.active);
+
+        // After this year's event the next occurrence is returned
+        let next = calendar.find(DEFAULT_TENANT, "black_friday", date(2026, 12, 5)).unwrap();
+        assert_eq!(next.starts_on, date(2027, 11, 26));
+    }
+
+    #[test]
+    fn test_regional_events_follow_tenant() {
+        let mut tenants = HashMap::new();
+        tenants.insert(
+            "dealmate-in".to_string(),
+            TenantEvents {
+                regions: vec!["in".to_string()],
+                ..Default::default()
+            },
+        );
+        let calendar = EventCalendar::new(default_events(), tenants);
+        let today = date(2026, 10, 20);
+
+        let ids = |tenant: &str| -> Vec<String> { calendar.upcoming(tenant, today, 30).into_iter().map(|o| o.id).collect() };
+        assert!(ids("dealmate-in").contains(&"diwali_2026".to_string()));
+        assert!(!ids(DEFAULT_TENANT).contains(&"diwali_2026".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_active_event_boosts_matching_deals() {
+        let calendar = EventCalendar::new(default_events(), HashMap::new());
+        let mut deals = DealStore::with_sample_data().list().await;
+        for deal in deals.iter_mut() {
+            deal.score = Some(0.5);
+        }
+
+        calendar.apply(DEFAULT_TENANT, date(2026, 8, 1), &mut deals);
+
+        assert_eq!(deals[0].events, vec!["back_to_school".to_string()]);
+        assert_eq!(deals[0].score, Some(0.6));
+        let books = deals.iter().find(|d| d.category == "books").unwrap();
+        assert!(books.events.contains(&"back_to_school".to_string()));
+        let kitchen = deals.iter().find(|d| d.category == "kitchen").unwrap();
+        assert!(kitchen.events.is_empty());
+        assert_eq!(kitchen.score, Some(0.5));
+    }
+}
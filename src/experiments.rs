@@ -0,0 +1,367 @@
+//! A/B experiments for deal ranking
+//!
+//! Each request is bucketed into a variant of the active ranking experiment by
+//! hashing the experiment id with the caller's user id, API key or session id, so
+//! the same caller always sees the same variant. Responses and interaction
+//! events carry the assignment, and per-variant exposure and engagement counts
+//! are kept for readouts.
+
+use std::collections::HashMap;
+
+use axum::{async_trait, extract::FromRequestParts, http::request::Parts};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, RwLock};
+use utoipa::ToSchema;
+
+use crate::models::experiment::ExperimentAssignment;
+use crate::models::interaction::InteractionKind;
+
+/// How a variant orders the deal list
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, Hash, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RankingStrategy {
+    /// Model score with shopping-event boosts (production ranking)
+    #[default]
+    Scored,
+    /// Model score only
+    ScoredWithoutEvents,
+    /// Largest honest discount first
+    HonestDiscount,
+    /// Most recently posted first
+    Newest,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Variant {
+    pub name: String,
+    /// Relative share of traffic
+    pub weight: u32,
+    pub ranking: RankingStrategy,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Experiment {
+    pub id: String,
+    #[serde(default)]
+    pub description: String,
+    pub variants: Vec<Variant>,
+    #[serde(default = "default_active")]
+    pub active: bool,
+}
+
+fn default_active() -> bool {
+    true
+}
+
+impl Experiment {
+    fn validate(&self) -> Result<(), String> {
+        if self.id.trim().is_empty() {
+            return Err("experiment id must not be empty".to_string());
+        }
+        if self.variants.is_empty() {
+            return Err("experiment needs at least one variant".to_string());
+        }
+        if self.variants.iter().all(|v| v.weight == 0) {
+            return Err("at least one variant needs a positive weight".to_string());
+        }
+        for (i, variant) in self.variants.iter().enumerate() {
+            if self.variants[..i].iter().any(|v| v.name == variant.name) {
+                return Err(format!("duplicate variant '{}'", variant.name));
+            }
+        }
+        Ok(())
+    }
+
+    /// Deterministically pick a variant for a subject
+    fn assign(&self, subject: &str) -> &Variant {
+        let total: u64 = self.variants.iter().map(|v| v.weight as u64).sum();
+        let mut bucket = fnv1a(&format!("{}:{}", self.id, subject)) % total;
+
+        for variant in &self.variants {
+            if bucket < variant.weight as u64 {
+                return variant;
+            }
+            bucket -= variant.weight as u64;
+        }
+        &self.variants[0]
+    }
+}
+
+/// FNV-1a; unlike `DefaultHasher` it is stable across releases, so assignments survive upgrades
+fn fnv1a(value: &str) -> u64 {
+    value.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[derive(Debug, Clone, Default)]
+struct VariantMetrics {
+    requests: u64,
+    impressions: u64,
+    views: u64,
+    clicks: u64,
+    add_to_carts: u64,
+    purchases: u64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct VariantReadout {
+    pub variant: String,
+    pub requests: u64,
+    pub impressions: u64,
+    pub views: u64,
+    pub clicks: u64,
+    pub add_to_carts: u64,
+    pub purchases: u64,
+    /// Clicks per impression
+    pub click_through_rate: Option<f64>,
+    /// Purchases per impression
+    pub conversion_rate: Option<f64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ExperimentReadout {
+    pub experiment: Experiment,
+    pub variants: Vec<VariantReadout>,
+}
+
+pub struct ExperimentService {
+    experiments: RwLock<Vec<Experiment>>,
+    metrics: Mutex<HashMap<(String, String), VariantMetrics>>,
+}
+
+impl ExperimentService {
+    pub fn new(experiments: Vec<Experiment>) -> Self {
+        Self {
+            experiments: RwLock::new(experiments),
+            metrics: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Load experiment definitions from the JSON file at `EXPERIMENTS_CONFIG_PATH`
+    pub fn from_env() -> Self {
+        let Ok(path) = std::env::var("EXPERIMENTS_CONFIG_PATH") else {
+            return Self::new(Vec::new());
+        };
+
+        match Self::load_config(&path) {
+            Ok(experiments) => Self::new(experiments),
+            Err(e) => {
+                tracing::warn!(path = %path, error = %e, "Failed to load experiments");
+                Self::new(Vec::new())
+            }
+        }
+    }
+
+    fn load_config(path: &str) -> Result<Vec<Experiment>, Box<dyn std::error::Error + Send + Sync>> {
+        let content = std::fs::read_to_string(path)?;
+        let experiments: Vec<Experiment> = serde_json::from_str(&content)?;
+        for experiment in &experiments {
+            experiment.validate()?;
+        }
+        Ok(experiments)
+    }
+
+    pub async fn list(&self) -> Vec<Experiment> {
+        self.experiments.read().await.clone()
+    }
+
+    /// Create or replace an experiment
+    pub async fn upsert(&self, experiment: Experiment) -> Result<(), String> {
+        experiment.validate()?;
+
+        let mut experiments = self.experiments.write().await;
+        match experiments.iter_mut().find(|e| e.id == experiment.id) {
+            Some(existing) => *existing = experiment,
+            None => experiments.push(experiment),
+        }
+        Ok(())
+    }
+
+    /// Ranking for a request; callers that cannot be identified get production ranking untagged
+    pub async fn assign(&self, subject: &ExperimentSubject) -> (RankingStrategy, Option<ExperimentAssignment>) {
+        let Some(subject) = &subject.0 else {
+            return (RankingStrategy::default(), None);
+        };
+
+        let experiments = self.experiments.read().await;
+        let Some(experiment) = experiments.iter().find(|e| e.active) else {
+            return (RankingStrategy::default(), None);
+        };
+
+        let variant = experiment.assign(subject);
+        (
+            variant.ranking,
+            Some(ExperimentAssignment {
+                experiment_id: experiment.id.clone(),
+                variant: variant.name.clone(),
+            }),
+        )
+    }
+
+    /// Count a served ranked list of `shown` deals
+    pub async fn record_exposure(&self, assignment: &ExperimentAssignment, shown: usize) {
+        let mut metrics = self.metrics.lock().await;
+        let entry = metrics
+            .entry((assignment.experiment_id.clone(), assignment.variant.clone()))
+            .or_default();
+        entry.requests += 1;
+        entry.impressions += shown as u64;
+    }
+
+    pub async fn record_interaction(&self, assignment: &ExperimentAssignment, kind: InteractionKind) {
+        let mut metrics = self.metrics.lock().await;
+        let entry = metrics
+            .entry((assignment.experiment_id.clone(), assignment.variant.clone()))
+            .or_default();
+        match kind {
+            InteractionKind::View => entry.views += 1,
+            InteractionKind::Click => entry.clicks += 1,
+            InteractionKind::AddToCart => entry.add_to_carts += 1,
+            InteractionKind::Purchase => entry.purchases += 1,
+        }
+    }
+
+    pub async fn readout(&self, experiment_id: &str) -> Option<ExperimentReadout> {
+        let experiment = self
+            .experiments
+            .read()
+            .await
+            .iter()
+            .find(|e| e.id == experiment_id)
+            .cloned()?;
+        let metrics = self.metrics.lock().await;
+
+        let variants = experiment
+            .variants
+            .iter()
+            .map(|variant| {
+                let m = metrics
+                    .get(&(experiment.id.clone(), variant.name.clone()))
+                    .cloned()
+                    .unwrap_or_default();
+                let per_impression = |count: u64| (m.impressions > 0).then(|| round(count as f64 / m.impressions as f64));
+
+                VariantReadout {
+                    variant: variant.name.clone(),
+                    requests: m.requests,
+                    impressions: m.impressions,
+                    views: m.views,
+                    clicks: m.clicks,
+                    add_to_carts: m.add_to_carts,
+                    purchases: m.purchases,
+                    click_through_rate: per_impression(m.clicks),
+                    conversion_rate: per_impression(m.purchases),
+                }
+            })
+            .collect();
+
+        Some(ExperimentReadout { experiment, variants })
+    }
+}
+
+fn round(value: f64) -> f64 {
+    (value * 10000.0).round() / 10000.0
+}
+
+/// Identity used for bucketing: `X-User-Id`, then `X-Api-Key`, then `X-Session-Id`
+#[derive(Debug, Clone)]
+pub struct ExperimentSubject(pub Option<String>);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ExperimentSubject {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let subject = ["x-user-id", "x-api-key", "x-session-id"].iter().find_map(|name| {
+            parts
+                .headers
+                .get(*name)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.trim())
+                .filter(|v| !v.is_empty())
+                .map(|v| format!("{}:{}", name, v))
+        });
+
+        Ok(ExperimentSubject(subject))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn experiment() -> Experiment {
+        Experiment {
+            id: "ranking-v2".to_string(),
+            description: String::new(),
+            variants: vec![
+                Variant {
+                    name: "control".to_string(),
+                    weight: 50,
+                    ranking: RankingStrategy::Scored,
+                },
+                Variant {
+                    name: "newest".to_string(),
+                    weight: 50,
+                    ranking: RankingStrategy::Newest,
+                },
+            ],
+            active: true,
+        }
+    }
+
+    fn subject(id: &str) -> ExperimentSubject {
+        ExperimentSubject(Some(format!("x-user-id:{}", id)))
+    }
+
+    #[tokio::test]
+    async fn test_assignment_is_deterministic_and_split() {
+        let service = ExperimentService::new(vec![experiment()]);
+
+        let (_, first) = service.assign(&subject("user-42")).await;
+        let (_, second) = service.assign(&subject("user-42")).await;
+        assert_eq!(first, second);
+
+        let mut newest = 0;
+        for i in 0..1000 {
+            if service.assign(&subject(&format!("user-{}", i))).await.0 == RankingStrategy::Newest {
+                newest += 1;
+            }
+        }
+        assert!((400..600).contains(&newest), "unbalanced split: {}", newest);
+
+        let (strategy, assignment) = service.assign(&ExperimentSubject(None)).await;
+        assert_eq!(strategy, RankingStrategy::Scored);
+        assert!(assignment.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_readout_rates() {
+        let service = ExperimentService::new(vec![experiment()]);
+        let assignment = ExperimentAssignment {
+            experiment_id: "ranking-v2".to_string(),
+            variant: "newest".to_string(),
+        };
+
+        service.record_exposure(&assignment, 10).await;
+        service.record_interaction(&assignment, InteractionKind::Click).await;
+        service.record_interaction(&assignment, InteractionKind::Purchase).await;
+
+        let readout = service.readout("ranking-v2").await.unwrap();
+        let newest = readout.variants.iter().find(|v| v.variant == "newest").unwrap();
+        assert_eq!(newest.click_through_rate, Some(0.1));
+        assert_eq!(newest.conversion_rate, Some(0.1));
+        assert_eq!(readout.variants[0].click_through_rate, None);
+    }
+
+    #[tokio::test]
+    async fn test_rejects_invalid_experiment() {
+        let service = ExperimentService::new(Vec::new());
+        let mut invalid = experiment();
+        invalid.variants[1].name = "control".to_string();
+
+        assert!(service.upsert(invalid).await.is_err());
+        assert!(service.upsert(experiment()).await.is_ok());
+    }
+}
@@ -0,0 +1,385 @@
+//! Shared fetch service for sister services
+//!
+//! `POST /fetch` lets other DealMate services fetch merchant pages through the
+//! coupon scraper instead of hitting merchants directly, so every request to a
+//! merchant shares one per-domain rate limit and the proxy pool. Pages are cached
+//! for `cache_duration_secs` and then revalidated with `If-None-Match` /
+//! `If-Modified-Since`, so a popular page costs the merchant one conditional request
+//! per cache period. Each caller (the `X-Caller-Id` header) gets a per-minute
+//! request quota, `FETCH_QUOTA_PER_MINUTE` by default, overridden per caller with
+//! `FETCH_CALLER_QUOTAS=pricing=600,search=120`. Merchants that opted out of
+//! scraping are refused (see [`crate::coupon_engine::opt_out`]).
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{async_trait, extract::FromRequestParts, http::request::Parts};
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::Serialize;
+use tokio::sync::Mutex;
+
+use crate::clock::{self, Clock};
+use crate::coupon_engine::opt_out::{BlockedAt, OptOutRegistry};
+use crate::coupon_engine::proxy_manager::ProxySource;
+use crate::coupon_engine::rate_limiter::Limiter;
+use crate::coupon_engine::scraper::{CacheValidators, FetchedPage, Scraper};
+use crate::models::url::normalize;
+
+pub const ANONYMOUS_CALLER: &str = "anonymous";
+const DEFAULT_QUOTA_PER_MINUTE: u32 = 60;
+/// Pages kept in the cache; the least recently fetched go first
+const MAX_CACHED_PAGES: usize = 1000;
+
+/// Service a fetch is made for, taken from the `X-Caller-Id` header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallerId(pub String);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for CallerId {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let caller = parts
+            .headers
+            .get("x-caller-id")
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.trim().to_lowercase())
+            .filter(|v| !v.is_empty())
+            .unwrap_or_else(|| ANONYMOUS_CALLER.to_string());
+
+        Ok(CallerId(caller))
+    }
+}
+
+/// Requests per minute for each caller
+#[derive(Debug, Clone)]
+pub struct FetchQuotas {
+    pub default_per_minute: u32,
+    pub per_caller: HashMap<String, u32>,
+}
+
+impl Default for FetchQuotas {
+    fn default() -> Self {
+        Self {
+            default_per_minute: DEFAULT_QUOTA_PER_MINUTE,
+            per_caller: HashMap::new(),
+        }
+    }
+}
+
+impl FetchQuotas {
+    pub fn from_env() -> Self {
+        let default_per_minute = std::env::var("FETCH_QUOTA_PER_MINUTE")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_QUOTA_PER_MINUTE);
+        let per_caller = std::env::var("FETCH_CALLER_QUOTAS")
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|entry| {
+                let (caller, quota) = entry.split_once('=')?;
+                Some((caller.trim().to_lowercase(), quota.trim().parse().ok()?))
+            })
+            .collect();
+
+        Self {
+            default_per_minute,
+            per_caller,
+        }
+    }
+
+    fn for_caller(&self, caller: &str) -> u32 {
+        self.per_caller.get(caller).copied().unwrap_or(self.default_per_minute)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheStatus {
+    /// Served from the cache without contacting the merchant
+    Hit,
+    /// The merchant confirmed the cached copy with a 304
+    Revalidated,
+    Miss,
+}
+
+impl CacheStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Hit => "hit",
+            Self::Revalidated => "revalidated",
+            Self::Miss => "miss",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FetchResponse {
+    pub body: String,
+    /// The merchant's `Content-Type`, passed through unchanged
+    pub content_type: Option<String>,
+    pub etag: Option<String>,
+    pub cache: CacheStatus,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum FetchError {
+    InvalidUrl(String),
+    QuotaExceeded { retry_after: Duration },
+    /// The merchant opted out of scraping
+    OptedOut,
+    Upstream(String),
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidUrl(e) => write!(f, "invalid url: {}", e),
+            Self::QuotaExceeded { retry_after } => write!(f, "fetch quota exceeded, retry in {}s", retry_after.as_secs()),
+            Self::OptedOut => write!(f, "the merchant opted out of scraping"),
+            Self::Upstream(e) => write!(f, "fetch failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for FetchError {}
+
+#[derive(Debug, Clone)]
+struct CachedPage {
+    body: String,
+    content_type: Option<String>,
+    validators: CacheValidators,
+    fetched_at: DateTime<Utc>,
+}
+
+pub struct FetchService {
+    scraper: Arc<Scraper>,
+    rate_limiter: Arc<dyn Limiter>,
+    proxies: Option<Arc<dyn ProxySource>>,
+    /// Proxies tried per fetch before giving up
+    max_proxies: u32,
+    cache_ttl: TimeDelta,
+    cache: Mutex<HashMap<String, CachedPage>>,
+    quotas: FetchQuotas,
+    opt_outs: Option<Arc<OptOutRegistry>>,
+    /// Start of the current one-minute window and requests made in it, per caller
+    usage: Mutex<HashMap<String, (DateTime<Utc>, u32)>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl FetchService {
+    pub fn new(scraper: Arc<Scraper>, rate_limiter: Arc<dyn Limiter>, cache_ttl: Duration) -> Self {
+        Self {
+            scraper,
+            rate_limiter,
+            proxies: None,
+            max_proxies: 3,
+            cache_ttl: TimeDelta::from_std(cache_ttl).unwrap_or(TimeDelta::MAX),
+            cache: Mutex::new(HashMap::new()),
+            quotas: FetchQuotas::default(),
+            opt_outs: None,
+            usage: Mutex::new(HashMap::new()),
+            clock: clock::system(),
+        }
+    }
+
+    /// Fetch through `proxies`, moving on to another one (up to `max_proxies`) when one fails
+    pub fn with_proxies(mut self, proxies: Arc<dyn ProxySource>, max_proxies: u32) -> Self {
+        self.proxies = Some(proxies);
+        self.max_proxies = max_proxies.max(1);
+        self
+    }
+
+    pub fn with_quotas(mut self, quotas: FetchQuotas) -> Self {
+        self.quotas = quotas;
+        self
+    }
+
+    /// Refuse pages of merchants that opted out of scraping
+    pub fn with_opt_outs(mut self, opt_outs: Arc<OptOutRegistry>) -> Self {
+        self.opt_outs = Some(opt_outs);
+        self
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub async fn fetch(&self, caller: &str, url: &str) -> Result<FetchResponse, FetchError> {
+        let parsed = url::Url::parse(url).map_err(|e| FetchError::InvalidUrl(e.to_string()))?;
+        if !matches!(parsed.scheme(), "http" | "https") {
+            return Err(FetchError::InvalidUrl(format!("unsupported scheme '{}'", parsed.scheme())));
+        }
+        let host = parsed.host_str().unwrap_or_default().to_string();
+        // Variants of one page share a cache entry
+        let url = &normalize(url).map_err(FetchError::InvalidUrl)?;
+        if self.opt_outs.as_ref().is_some_and(|opt_outs| opt_outs.blocks(url, BlockedAt::FetchService)) {
+            return Err(FetchError::OptedOut);
+        }
+        self.take_quota(caller).await?;
+
+        let now = self.clock.now();
+        let cached = self.cache.lock().await.get(url).cloned();
+        if let Some(page) = &cached {
+            if now - page.fetched_at < self.cache_ttl {
+                return Ok(response(page, CacheStatus::Hit));
+            }
+        }
+
+        self.rate_limiter.wait_if_needed(&host).await;
+        let validators = cached.as_ref().map(|page| page.validators.clone()).unwrap_or_default();
+        let fetched = self
+            .fetch_with_failover(url, &validators)
+            .await
+            .map_err(|e| FetchError::Upstream(e.to_string()))?;
+
+        let fetched_at = self.clock.now();
+        let (page, status) = match (fetched, cached) {
+            (FetchedPage::NotModified, Some(page)) => (CachedPage { fetched_at, ..page }, CacheStatus::Revalidated),
+            (FetchedPage::NotModified, None) => {
+                return Err(FetchError::Upstream("304 Not Modified for an unconditional request".to_string()));
+            }
+            (FetchedPage::Modified { body, content_type, validators, .. }, _) => (
+                CachedPage {
+                    body,
+                    content_type,
+                    validators,
+                    fetched_at,
+                },
+                CacheStatus::Miss,
+            ),
+        };
+
+        let mut cache = self.cache.lock().await;
+        if cache.len() >= MAX_CACHED_PAGES && !cache.contains_key(url) {
+            if let Some(oldest) = cache.iter().min_by_key(|(_, page)| page.fetched_at).map(|(url, _)| url.clone()) {
+                cache.remove(&oldest);
+            }
+        }
+        cache.insert(url.to_string(), page.clone());
+        Ok(response(&page, status))
+    }
+
+    async fn fetch_with_failover(
+        &self,
+        url: &str,
+        validators: &CacheValidators,
+    ) -> Result<FetchedPage, Box<dyn std::error::Error + Send + Sync>> {
+        let Some(proxies) = &self.proxies else {
+            return self.scraper.fetch_page(url, None, validators).await;
+        };
+
+        let mut last_error = None;
+        for _ in 0..self.max_proxies {
+            let Some(proxy) = proxies.next_proxy().await else {
+                break;
+            };
+            let fetched = self.scraper.fetch_page(url, Some(&proxy), validators).await;
+            proxies.report(&proxy.url, fetched.is_ok()).await;
+            match fetched {
+                Ok(page) => return Ok(page),
+                Err(e) => {
+                    tracing::warn!(proxy = %proxy.url, url = %url, error = %e, "Proxy fetch failed");
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        match last_error {
+            Some(e) => Err(e),
+            None => self.scraper.fetch_page(url, None, validators).await,
+        }
+    }
+
+    async fn take_quota(&self, caller: &str) -> Result<(), FetchError> {
+        let quota = self.quotas.for_caller(caller);
+        let now = self.clock.now();
+        let mut usage = self.usage.lock().await;
+        let (window_start, used) = usage.entry(caller.to_string()).or_insert((now, 0));
+        if now - *window_start >= TimeDelta::minutes(1) {
+            *window_start = now;
+            *used = 0;
+        }
+        if *used >= quota {
+            let retry_after = (*window_start + TimeDelta::minutes(1) - now).to_std().unwrap_or_default();
+            return Err(FetchError::QuotaExceeded { retry_after });
+        }
+        *used += 1;
+        Ok(())
+    }
+}
+
+fn response(page: &CachedPage, cache: CacheStatus) -> FetchResponse {
+    FetchResponse {
+        body: page.body.clone(),
+        content_type: page.content_type.clone(),
+        etag: page.validators.etag.clone(),
+        cache,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::coupon_engine::rate_limiter::RateLimiter;
+    use crate::coupon_engine::EngineConfig;
+    use axum::http::{header, HeaderMap, StatusCode};
+    use axum::response::IntoResponse;
+    use axum::routing::get;
+    use axum::Router;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    async fn feed(headers: HeaderMap, hits: Arc<AtomicUsize>) -> axum::response::Response {
+        hits.fetch_add(1, Ordering::SeqCst);
+        if headers.get(header::IF_NONE_MATCH).is_some_and(|etag| etag == "\"v1\"") {
+            return StatusCode::NOT_MODIFIED.into_response();
+        }
+        ([(header::CONTENT_TYPE, "application/json"), (header::ETAG, "\"v1\"")], r#"{"offers": []}"#).into_response()
+    }
+
+    #[tokio::test]
+    async fn test_caches_revalidates_and_enforces_quota() {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let handler_hits = hits.clone();
+        let app = Router::new().route("/feed.json", get(move |headers| feed(headers, handler_hits.clone())));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/feed.json", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let clock = Arc::new(MockClock::new());
+        let config = EngineConfig {
+            retry_attempts: 1,
+            ..EngineConfig::default()
+        };
+        let service = FetchService::new(
+            Arc::new(Scraper::new(config)),
+            Arc::new(RateLimiter::new(100).with_clock(clock.clone())),
+            Duration::from_secs(300),
+        )
+        .with_quotas(FetchQuotas {
+            default_per_minute: 3,
+            per_caller: HashMap::new(),
+        })
+        .with_clock(clock.clone());
+
+        let first = service.fetch("pricing", &url).await.unwrap();
+        assert_eq!(first.cache, CacheStatus::Miss);
+        assert_eq!(first.content_type.as_deref(), Some("application/json"));
+        assert_eq!(service.fetch("pricing", &url).await.unwrap().cache, CacheStatus::Hit);
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+
+        clock.advance(Duration::from_secs(600));
+        let revalidated = service.fetch("pricing", &url).await.unwrap();
+        assert_eq!((revalidated.cache, revalidated.body.as_str()), (CacheStatus::Revalidated, r#"{"offers": []}"#));
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+
+        service.fetch("pricing", &url).await.unwrap();
+        service.fetch("pricing", &url).await.unwrap();
+        assert!(matches!(service.fetch("pricing", &url).await, Err(FetchError::QuotaExceeded { .. })));
+        assert!(service.fetch("search", &url).await.is_ok());
+    }
+}
@@ -0,0 +1,238 @@
+//! Price forecasting for "should I wait for a sale" recommendations
+//!
+//! Each registered model is backtested on the most recent window of the price
+//! history; the one with the lowest error produces the forecast, and its
+//! residual spread drives the drop probability.
+
+pub mod models;
+
+use chrono::{DateTime, Duration, Utc};
+use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::models::deal::PricePoint;
+use models::{ExponentialSmoothing, ForecastModel, SeasonalNaive};
+
+/// A drop smaller than this fraction of the current price is not worth waiting for
+const MIN_MEANINGFUL_DROP: f64 = 0.02;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Recommendation {
+    BuyNow,
+    Wait,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ForecastPoint {
+    pub date: DateTime<Utc>,
+    pub price: Decimal,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct PriceForecast {
+    pub model: String,
+    pub current_price: Decimal,
+    pub horizon_days: usize,
+    pub forecast: Vec<ForecastPoint>,
+    pub predicted_low: Decimal,
+    pub expected_drop_percentage: f64,
+    pub drop_probability: f64,
+    pub recommendation: Recommendation,
+    pub confidence: f64,
+}
+
+pub struct PriceForecaster {
+    models: Vec<Box<dyn ForecastModel>>,
+}
+
+impl PriceForecaster {
+    pub fn new() -> Self {
+        Self::with_models(vec![
+            Box::new(SeasonalNaive::new(7)),
+            Box::new(ExponentialSmoothing::default()),
+        ])
+    }
+
+    pub fn with_models(models: Vec<Box<dyn ForecastModel>>) -> Self {
+        Self { models }
+    }
+
+    pub fn model_names(&self) -> Vec<&'static str> {
+        self.models.iter().map(|m| m.name()).collect()
+    }
+
+    /// Forecast the next `horizon_days` of prices.
+    ///
+    /// When `model` is given only that model is used, otherwise the best backtesting
+    /// model is picked. Returns `None` when there is no usable history.
+    pub fn forecast(&self, history: &[PricePoint], horizon_days: usize, model: Option<&str>) -> Option<PriceForecast> {
+        let series = daily_series(history);
+        let current_price = *series.last()?;
+        let last_observed = history.iter().map(|p| p.observed_at).max()?;
+        let horizon_days = horizon_days.max(1);
+
+        let (model, error) = self
+            .models
+            .iter()
+            .filter(|m| model.is_none_or(|name| m.name() == name))
+            .map(|m| (m, backtest_error(m.as_ref(), &series, horizon_days)))
+            .min_by(|a, b| a.1.total_cmp(&b.1))?;
+
+        let predictions = model.forecast(&series, horizon_days);
+        let predicted_low = predictions.iter().copied().fold(current_price, f64::min);
+
+        // Probability that the price falls at least MIN_MEANINGFUL_DROP below today's,
+        // treating backtest error as the forecast's standard deviation
+        let threshold = current_price * (1.0 - MIN_MEANINGFUL_DROP);
+        let sigma = error.max(current_price * 0.005).max(f64::MIN_POSITIVE);
+        let drop_probability = normal_cdf((threshold - predicted_low) / sigma);
+
+        let recommendation = if drop_probability > 0.5 {
+            Recommendation::Wait
+        } else {
+            Recommendation::BuyNow
+        };
+
+        Some(PriceForecast {
+            model: model.name().to_string(),
+            current_price: to_cents(current_price),
+            horizon_days,
+            forecast: predictions
+                .iter()
+                .enumerate()
+                .map(|(day, &price)| ForecastPoint {
+                    date: last_observed + Duration::days(day as i64 + 1),
+                    price: to_cents(price),
+                })
+                .collect(),
+            predicted_low: to_cents(predicted_low),
+            // A free or unpriced listing has nothing to drop from
+            expected_drop_percentage: match current_price > 0.0 {
+                true => round_cents((current_price - predicted_low) / current_price * 100.0),
+                false => 0.0,
+            },
+            drop_probability: round_cents(drop_probability),
+            recommendation,
+            confidence: round_cents(drop_probability.max(1.0 - drop_probability)),
+        })
+    }
+}
+
+impl Default for PriceForecaster {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Collapse price points into one price per day (last observation wins), oldest first.
+/// The models work in `f64`; prices go back to `Decimal` only for the response.
+fn daily_series(history: &[PricePoint]) -> Vec<f64> {
+    let mut points: Vec<&PricePoint> = history.iter().collect();
+    points.sort_by_key(|p| p.observed_at);
+
+    let mut series: Vec<f64> = Vec::with_capacity(points.len());
+    let mut last_day = None;
+    for point in points {
+        let day = point.observed_at.date_naive();
+        if last_day == Some(day) {
+            if let Some(last) = series.last_mut() {
+                *last = point.price.to_f64().unwrap_or(*last);
+            }
+        } else {
+            series.push(point.price.to_f64().unwrap_or(0.0));
+            last_day = Some(day);
+        }
+    }
+    series
+}
+
+/// Root mean squared error of forecasting the final `horizon` points from the rest
+fn backtest_error(model: &dyn ForecastModel, series: &[f64], horizon: usize) -> f64 {
+    let horizon = horizon.min(series.len() / 2);
+    if horizon == 0 {
+        return f64::MAX;
+    }
+
+    let (train, test) = series.split_at(series.len() - horizon);
+    let predictions = model.forecast(train, horizon);
+    let squared: f64 = predictions
+        .iter()
+        .zip(test)
+        .map(|(p, actual)| (p - actual).powi(2))
+        .sum();
+
+    (squared / horizon as f64).sqrt()
+}
+
+/// Standard normal CDF (Abramowitz-Stegun approximation)
+fn normal_cdf(z: f64) -> f64 {
+    let t = 1.0 / (1.0 + 0.2316419 * z.abs());
+    let density = (-z * z / 2.0).exp() / (2.0 * std::f64::consts::PI).sqrt();
+    let tail = density * t * (0.319381530 + t * (-0.356563782 + t * (1.781477937 + t * (-1.821255978 + t * 1.330274429))));
+    if z >= 0.0 {
+        1.0 - tail
+    } else {
+        tail
+    }
+}
+
+fn round_cents(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+fn to_cents(price: f64) -> Decimal {
+    Decimal::from_f64(price).unwrap_or_default().round_dp(2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(prices: &[f64]) -> Vec<PricePoint> {
+        let start = Utc::now() - Duration::days(prices.len() as i64);
+        prices
+            .iter()
+            .enumerate()
+            .map(|(i, &price)| PricePoint {
+                price: Decimal::from_f64(price).unwrap(),
+                observed_at: start + Duration::days(i as i64),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_falling_price_recommends_wait() {
+        let prices: Vec<f64> = (0..60).map(|i| 500.0 - i as f64 * 2.0).collect();
+        let forecast = PriceForecaster::new().forecast(&history(&prices), 14, None).unwrap();
+
+        assert_eq!(forecast.recommendation, Recommendation::Wait);
+        assert!(forecast.predicted_low < forecast.current_price);
+    }
+
+    #[test]
+    fn test_flat_price_recommends_buy_now() {
+        let forecast = PriceForecaster::new().forecast(&history(&[99.0; 30]), 7, None).unwrap();
+
+        assert_eq!(forecast.recommendation, Recommendation::BuyNow);
+        assert_eq!(forecast.forecast.len(), 7);
+    }
+
+    #[test]
+    fn test_unknown_model_and_empty_history() {
+        let forecaster = PriceForecaster::new();
+        assert!(forecaster.forecast(&history(&[10.0; 10]), 7, Some("arima")).is_none());
+        assert!(forecaster.forecast(&[], 7, None).is_none());
+    }
+
+    #[test]
+    fn test_zero_price_has_no_expected_drop() {
+        let forecast = PriceForecaster::new().forecast(&history(&[0.0; 14]), 7, None).unwrap();
+
+        assert_eq!(forecast.expected_drop_percentage, 0.0);
+        assert!(forecast.drop_probability.is_finite());
+        assert!(serde_json::to_value(&forecast).unwrap()["expected_drop_percentage"].is_number());
+    }
+}
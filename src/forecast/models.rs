@@ -0,0 +1,112 @@
+//! Time-series models used for price forecasting
+
+pub trait ForecastModel: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Fit on a daily price series (oldest first) and predict the next `horizon` days
+    fn forecast(&self, series: &[f64], horizon: usize) -> Vec<f64>;
+}
+
+/// Repeats the value observed one season earlier
+pub struct SeasonalNaive {
+    season_length: usize,
+}
+
+impl SeasonalNaive {
+    pub fn new(season_length: usize) -> Self {
+        Self {
+            season_length: season_length.max(1),
+        }
+    }
+}
+
+impl ForecastModel for SeasonalNaive {
+    fn name(&self) -> &'static str {
+        "seasonal_naive"
+    }
+
+    fn forecast(&self, series: &[f64], horizon: usize) -> Vec<f64> {
+        let Some(&last) = series.last() else {
+            return Vec::new();
+        };
+
+        if series.len() < self.season_length {
+            return vec![last; horizon];
+        }
+
+        let last_season = &series[series.len() - self.season_length..];
+        (0..horizon)
+            .map(|h| last_season[h % self.season_length])
+            .collect()
+    }
+}
+
+/// Holt's linear exponential smoothing (level + trend)
+pub struct ExponentialSmoothing {
+    alpha: f64,
+    beta: f64,
+}
+
+impl ExponentialSmoothing {
+    pub fn new(alpha: f64, beta: f64) -> Self {
+        Self {
+            alpha: alpha.clamp(0.01, 1.0),
+            beta: beta.clamp(0.0, 1.0),
+        }
+    }
+}
+
+impl Default for ExponentialSmoothing {
+    fn default() -> Self {
+        Self::new(0.3, 0.1)
+    }
+}
+
+impl ForecastModel for ExponentialSmoothing {
+    fn name(&self) -> &'static str {
+        "exponential_smoothing"
+    }
+
+    fn forecast(&self, series: &[f64], horizon: usize) -> Vec<f64> {
+        let (level, trend) = match series {
+            [] => return Vec::new(),
+            [only] => (*only, 0.0),
+            [first, second, rest @ ..] => {
+                let mut level = *second;
+                let mut trend = second - first;
+                for &value in rest {
+                    let previous_level = level;
+                    level = self.alpha * value + (1.0 - self.alpha) * (level + trend);
+                    trend = self.beta * (level - previous_level) + (1.0 - self.beta) * trend;
+                }
+                (level, trend)
+            }
+        };
+
+        (1..=horizon)
+            .map(|h| (level + trend * h as f64).max(0.0))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seasonal_naive_repeats_last_season() {
+        let model = SeasonalNaive::new(3);
+        let forecast = model.forecast(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0], 4);
+        assert_eq!(forecast, vec![4.0, 5.0, 6.0, 4.0]);
+    }
+
+    #[test]
+    fn test_exponential_smoothing_follows_trend() {
+        let model = ExponentialSmoothing::new(0.8, 0.5);
+        let series: Vec<f64> = (0..20).map(|i| 100.0 - i as f64).collect();
+        let forecast = model.forecast(&series, 3);
+
+        assert!(forecast[0] < 82.0);
+        assert!(forecast[2] < forecast[0]);
+    }
+}
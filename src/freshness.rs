@@ -0,0 +1,169 @@
+//! Data freshness status
+//!
+//! API consumers cache deals and coupons and need to know whether our copy of a
+//! merchant is current before trusting it. [`report`] summarizes, per merchant and
+//! per platform (the store name deals are listed under, or the merchant domain for
+//! coupon-only merchants), when data was last ingested and how much of it there is.
+//! It is served publicly at `GET /status/freshness`.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::coupon_engine::yield_stats::YieldStats;
+use crate::models::domain::MerchantDomain;
+use crate::storage::coupon_store::CouponStore;
+use crate::storage::deal_store::DealStore;
+
+/// Age past which a merchant counts as stale
+pub const STALE_AFTER: TimeDelta = TimeDelta::hours(24);
+/// Window the fetch success rate is computed over
+const FETCH_WINDOW: TimeDelta = TimeDelta::hours(24);
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct MerchantFreshness {
+    pub merchant: MerchantDomain,
+    pub platform: String,
+    /// Latest of the deal, coupon and scrape timestamps below
+    pub last_ingested_at: Option<DateTime<Utc>>,
+    pub age_seconds: Option<i64>,
+    pub stale: bool,
+    pub last_deal_ingest: Option<DateTime<Utc>>,
+    pub last_coupon_ingest: Option<DateTime<Utc>>,
+    /// Last scrape of the merchant that fetched at least one page
+    pub last_successful_scrape: Option<DateTime<Utc>>,
+    pub deals: usize,
+    /// Coupons not known to have expired
+    pub active_coupons: usize,
+    /// Share of the merchant's page fetches that succeeded over the last 24 hours
+    pub fetch_success_rate: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PlatformFreshness {
+    pub platform: String,
+    pub merchants: usize,
+    pub stale_merchants: usize,
+    /// Share of the platform's merchants that are not stale
+    pub coverage: f64,
+    pub last_ingested_at: Option<DateTime<Utc>>,
+    pub deals: usize,
+    pub active_coupons: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct FreshnessReport {
+    pub generated_at: DateTime<Utc>,
+    pub stale_after_seconds: i64,
+    pub merchants: Vec<MerchantFreshness>,
+    pub platforms: Vec<PlatformFreshness>,
+}
+
+/// Freshness of every merchant the stores or the scraper know, as of `now`
+pub async fn report(deals: &DealStore, coupons: &CouponStore, yields: &YieldStats, now: DateTime<Utc>) -> FreshnessReport {
+    let deal_ingests = deals.merchant_ingests().await;
+    let fetches = yields.fetch_summary(now - FETCH_WINDOW).await;
+    let mut coupon_ingests: BTreeMap<MerchantDomain, (Option<DateTime<Utc>>, usize)> = BTreeMap::new();
+    for listing in coupons.list().await {
+        let entry = coupon_ingests.entry(listing.merchant_domain.clone()).or_default();
+        entry.0 = entry.0.max(Some(listing.scraped_at));
+        if listing.valid_until.is_none_or(|until| until > now) {
+            entry.1 += 1;
+        }
+    }
+
+    let known: BTreeSet<&MerchantDomain> = deal_ingests.keys().chain(coupon_ingests.keys()).chain(fetches.keys()).collect();
+    let merchants: Vec<MerchantFreshness> = known
+        .into_iter()
+        .map(|merchant| {
+            let deal = deal_ingests.get(merchant);
+            let (last_coupon_ingest, active_coupons) = coupon_ingests.get(merchant).cloned().unwrap_or_default();
+            let fetch = fetches.get(merchant);
+            let last_deal_ingest = deal.and_then(|d| d.last_ingested_at);
+            let last_successful_scrape = fetch.and_then(|f| f.last_success_at);
+            let last_ingested_at = last_deal_ingest.max(last_coupon_ingest).max(last_successful_scrape);
+            let age = last_ingested_at.map(|at| now - at);
+            MerchantFreshness {
+                merchant: merchant.clone(),
+                platform: deal.map(|d| d.store.clone()).unwrap_or_else(|| merchant.as_str().to_string()),
+                last_ingested_at,
+                age_seconds: age.map(|age| age.num_seconds().max(0)),
+                stale: age.is_none_or(|age| age > STALE_AFTER),
+                last_deal_ingest,
+                last_coupon_ingest,
+                last_successful_scrape,
+                deals: deal.map_or(0, |d| d.deals),
+                active_coupons,
+                fetch_success_rate: fetch.and_then(|f| f.success_rate),
+            }
+        })
+        .collect();
+
+    let mut platforms: BTreeMap<&str, PlatformFreshness> = BTreeMap::new();
+    for merchant in &merchants {
+        let platform = platforms.entry(&merchant.platform).or_insert_with(|| PlatformFreshness {
+            platform: merchant.platform.clone(),
+            merchants: 0,
+            stale_merchants: 0,
+            coverage: 0.0,
+            last_ingested_at: None,
+            deals: 0,
+            active_coupons: 0,
+        });
+        platform.merchants += 1;
+        platform.stale_merchants += usize::from(merchant.stale);
+        platform.last_ingested_at = platform.last_ingested_at.max(merchant.last_ingested_at);
+        platform.deals += merchant.deals;
+        platform.active_coupons += merchant.active_coupons;
+    }
+    let platforms = platforms
+        .into_values()
+        .map(|mut platform| {
+            let fresh = platform.merchants - platform.stale_merchants;
+            platform.coverage = (fresh as f64 / platform.merchants as f64 * 1000.0).round() / 1000.0;
+            platform
+        })
+        .collect();
+
+    FreshnessReport {
+        generated_at: now,
+        stale_after_seconds: STALE_AFTER.num_seconds(),
+        merchants,
+        platforms,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coupon_engine::yield_stats::YieldCounts;
+    use std::collections::HashMap;
+
+    #[tokio::test]
+    async fn test_reports_merchants_and_platforms_stale_after_a_day() {
+        let deals = DealStore::with_sample_data();
+        let coupons = CouponStore::new();
+        let yields = YieldStats::new(None);
+        let scraped = MerchantDomain::parse("coupons-only.example.com").unwrap();
+        let counts = YieldCounts {
+            urls_scraped: 4,
+            fetch_failures: 1,
+            ..YieldCounts::default()
+        };
+        yields.record(HashMap::from([(scraped.clone(), counts)])).await;
+
+        let fresh = report(&deals, &coupons, &yields, Utc::now()).await;
+        assert!(fresh.merchants.iter().all(|m| !m.stale));
+        let coupon_only = fresh.merchants.iter().find(|m| m.merchant == scraped).unwrap();
+        assert_eq!(coupon_only.platform, "coupons-only.example.com");
+        assert_eq!(coupon_only.fetch_success_rate, Some(0.75));
+        let amazon = fresh.platforms.iter().find(|p| p.platform == "Amazon").unwrap();
+        assert!(amazon.deals > 0 && amazon.coverage == 1.0);
+
+        let later = report(&deals, &coupons, &yields, Utc::now() + TimeDelta::hours(25)).await;
+        assert!(later.merchants.iter().all(|m| m.stale));
+        assert!(later.platforms.iter().all(|p| p.coverage == 0.0));
+    }
+}
@@ -0,0 +1,240 @@
+//! Dependency probes and background task liveness behind `GET /health`
+//!
+//! [`HealthMonitor::check`] pings Redis (when `REDIS_URL` is set) and, with the
+//! `postgres` feature, Postgres (when `DATABASE_URL` is set). Both are critical:
+//! when either is down the instance reports `unhealthy` and `/health` answers 503,
+//! so load balancers stop routing to it.
+//!
+//! Background tasks started by [`Services::spawn_tasks_for`](crate::app::Services::spawn_tasks_for)
+//! are [`watch`](HealthMonitor::watch)ed. Tasks that return are fine (most stores only
+//! loop while shared through Redis), but one that panicked leaves the instance
+//! `degraded`: it still serves, with stale in-memory state, so it stays in rotation.
+//! `GET /health/live` probes nothing, for restarts on deadlock only.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tokio::task::JoinHandle;
+use utoipa::ToSchema;
+
+/// How long a probe may take before its dependency counts as down
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    Healthy,
+    /// Serving, with a non-critical part down
+    Degraded,
+    /// A critical dependency is down
+    Unhealthy,
+}
+
+/// One dependency or background task
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DependencyHealth {
+    /// `up`, `down`, or for tasks also `running` and `finished`
+    pub status: String,
+    /// Whether the instance is unhealthy while this is down
+    pub critical: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl DependencyHealth {
+    fn probed(critical: bool, started: Instant, result: Result<(), String>) -> Self {
+        let latency_ms = Some(started.elapsed().as_millis() as u64);
+        match result {
+            Ok(()) => Self { status: "up".to_string(), critical, latency_ms, error: None },
+            Err(e) => Self { status: "down".to_string(), critical, latency_ms, error: Some(e) },
+        }
+    }
+
+    fn is_down(&self) -> bool {
+        self.status == "down"
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct HealthReport {
+    pub status: HealthStatus,
+    /// By dependency (`redis`, `postgres`) or `task:` and the task's name
+    pub checks: BTreeMap<String, DependencyHealth>,
+}
+
+/// How a watched task ended, if it has
+#[derive(Debug, Clone)]
+enum TaskState {
+    Running,
+    Finished,
+    Failed(String),
+}
+
+pub struct HealthMonitor {
+    redis: Option<redis::Client>,
+    #[cfg(feature = "postgres")]
+    database_url: Option<String>,
+    tasks: Arc<Mutex<BTreeMap<&'static str, TaskState>>>,
+}
+
+impl HealthMonitor {
+    /// Probe Redis at `redis_url`, when given; Postgres is not probed
+    pub fn new(redis_url: Option<&str>) -> Self {
+        Self {
+            redis: redis_url.and_then(|url| redis::Client::open(url).ok()),
+            #[cfg(feature = "postgres")]
+            database_url: None,
+            tasks: Arc::new(Mutex::new(BTreeMap::new())),
+        }
+    }
+
+    /// Also probe the Postgres database at `url`
+    #[cfg(feature = "postgres")]
+    pub fn with_database(mut self, url: &str) -> Self {
+        self.database_url = Some(url.to_string());
+        self
+    }
+
+    /// Probe `REDIS_URL` and, with the `postgres` feature, `DATABASE_URL` when set
+    pub fn from_env() -> Self {
+        let monitor = Self::new(std::env::var("REDIS_URL").ok().as_deref());
+        #[cfg(feature = "postgres")]
+        if let Ok(url) = std::env::var("DATABASE_URL") {
+            return monitor.with_database(&url);
+        }
+        monitor
+    }
+
+    /// Track how the task behind `handle` ends. Must be called from within a Tokio runtime.
+    pub fn watch(&self, task: &'static str, handle: JoinHandle<()>) {
+        self.tasks.lock().unwrap().insert(task, TaskState::Running);
+        let tasks = self.tasks.clone();
+        tokio::spawn(async move {
+            let state = match handle.await {
+                Ok(()) => TaskState::Finished,
+                Err(e) if e.is_panic() => TaskState::Failed("panicked".to_string()),
+                Err(_) => TaskState::Failed("cancelled".to_string()),
+            };
+            if let TaskState::Failed(reason) = &state {
+                tracing::error!(task, reason = %reason, "Background task stopped");
+            }
+            tasks.lock().unwrap().insert(task, state);
+        });
+    }
+
+    /// Probe every dependency, each within [`PROBE_TIMEOUT`], and collect the task states
+    pub async fn check(&self) -> HealthReport {
+        let mut checks = BTreeMap::new();
+        if let Some(client) = &self.redis {
+            let started = Instant::now();
+            checks.insert("redis".to_string(), DependencyHealth::probed(true, started, ping_redis(client).await));
+        }
+        #[cfg(feature = "postgres")]
+        if let Some(url) = &self.database_url {
+            let started = Instant::now();
+            checks.insert("postgres".to_string(), DependencyHealth::probed(true, started, ping_postgres(url).await));
+        }
+        for (task, state) in self.tasks.lock().unwrap().iter() {
+            let (status, error) = match state {
+                TaskState::Running => ("running", None),
+                TaskState::Finished => ("finished", None),
+                TaskState::Failed(reason) => ("down", Some(reason.clone())),
+            };
+            let health = DependencyHealth { status: status.to_string(), critical: false, latency_ms: None, error };
+            checks.insert(format!("task:{}", task), health);
+        }
+
+        let status = match checks.values().filter(|check| check.is_down()).map(|check| check.critical).max() {
+            None => HealthStatus::Healthy,
+            Some(false) => HealthStatus::Degraded,
+            Some(true) => HealthStatus::Unhealthy,
+        };
+        HealthReport { status, checks }
+    }
+}
+
+/// PING on a blocking thread, so a slow Redis holds up neither the runtime nor,
+/// past [`PROBE_TIMEOUT`] in all, the report
+async fn ping_redis(client: &redis::Client) -> Result<(), String> {
+    let client = client.clone();
+    let probe = tokio::task::spawn_blocking(move || {
+        // Bounds the thread too, once the report has given up on it
+        let mut con = client.get_connection_with_timeout(PROBE_TIMEOUT)?;
+        con.set_read_timeout(Some(PROBE_TIMEOUT))?;
+        redis::cmd("PING").query::<String>(&mut con).map(|_| ())
+    });
+    match tokio::time::timeout(PROBE_TIMEOUT, probe).await {
+        Ok(Ok(result)) => result.map_err(|e| e.to_string()),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err(format!("no answer within {}s", PROBE_TIMEOUT.as_secs())),
+    }
+}
+
+#[cfg(feature = "postgres")]
+async fn ping_postgres(url: &str) -> Result<(), String> {
+    let probe = async {
+        let (client, connection) = tokio_postgres::connect(url, tokio_postgres::NoTls).await?;
+        tokio::spawn(connection);
+        client.simple_query("SELECT 1").await.map(|_| ())
+    };
+    match tokio::time::timeout(PROBE_TIMEOUT, probe).await {
+        Ok(result) => result.map_err(|e| e.to_string()),
+        Err(_) => Err(format!("no answer within {}s", PROBE_TIMEOUT.as_secs())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_critical_dependencies_decide_the_status() {
+        let monitor = HealthMonitor::new(None);
+        assert_eq!(monitor.check().await.status, HealthStatus::Healthy);
+
+        monitor.watch("returns", tokio::spawn(async {}));
+        monitor.watch("panics", tokio::spawn(async { panic!("boom") }));
+        monitor.watch("loops", tokio::spawn(std::future::pending()));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let report = monitor.check().await;
+        assert_eq!(report.status, HealthStatus::Degraded);
+        let statuses: Vec<(&str, &str)> = report.checks.iter().map(|(name, check)| (name.as_str(), check.status.as_str())).collect();
+        assert_eq!(statuses, vec![("task:loops", "running"), ("task:panics", "down"), ("task:returns", "finished")]);
+
+        // Nothing listens on port 1
+        let monitor = HealthMonitor::new(Some("redis://127.0.0.1:1/"));
+        let report = monitor.check().await;
+        assert_eq!(report.status, HealthStatus::Unhealthy);
+        assert!(report.checks["redis"].error.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_silent_redis_is_down_within_the_timeout_without_blocking() {
+        // Accepts connections and never answers
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let monitor = HealthMonitor::new(Some(&format!("redis://{}/", listener.local_addr().unwrap())));
+        let ticks = Arc::new(std::sync::atomic::AtomicU32::new(0));
+        let ticker = tokio::spawn({
+            let ticks = ticks.clone();
+            async move {
+                loop {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    ticks.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                }
+            }
+        });
+
+        let started = Instant::now();
+        let report = monitor.check().await;
+        ticker.abort();
+        assert!(started.elapsed() < PROBE_TIMEOUT + Duration::from_millis(500));
+        assert_eq!(report.checks["redis"].status, "down");
+        // The single-threaded test runtime kept running other tasks meanwhile
+        assert!(ticks.load(std::sync::atomic::Ordering::Relaxed) > 10);
+        drop(listener);
+    }
+}
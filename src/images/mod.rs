@@ -0,0 +1,75 @@
+//! Deal image pipeline
+//!
+//! Downloads each deal's product image once and records its perceptual hash on
+//! the deal, so the deduplicator can match re-listed deals by picture.
+
+pub mod phash;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::time::interval;
+
+use crate::storage::deal_store::DealStore;
+
+/// Images larger than this are skipped rather than decoded
+const MAX_IMAGE_BYTES: usize = 10 * 1024 * 1024;
+
+pub struct ImagePipeline {
+    client: reqwest::Client,
+}
+
+impl ImagePipeline {
+    pub fn new() -> Self {
+        Self {
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(15))
+                .build()
+                .unwrap_or_default(),
+        }
+    }
+
+    pub async fn hash_url(&self, url: &str) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+        let response = self.client.get(url).send().await?.error_for_status()?;
+        let bytes = response.bytes().await?;
+        if bytes.len() > MAX_IMAGE_BYTES {
+            return Err(format!("image is {} bytes, limit is {}", bytes.len(), MAX_IMAGE_BYTES).into());
+        }
+
+        let image = image::load_from_memory(&bytes)?;
+        Ok(phash::phash(&image))
+    }
+
+    /// Hash the images of deals that have an image URL but no hash yet
+    pub async fn process(&self, store: &DealStore) -> usize {
+        let mut hashed = 0;
+        for deal in store.list().await {
+            let (Some(url), None) = (&deal.image_url, &deal.image_hash) else {
+                continue;
+            };
+
+            match self.hash_url(url).await {
+                Ok(hash) => {
+                    store.set_image_hash(&deal.id, phash::to_hex(hash)).await;
+                    hashed += 1;
+                }
+                Err(e) => tracing::warn!(deal = %deal.id, error = %e, "Failed to hash a deal image"),
+            }
+        }
+        hashed
+    }
+
+    pub async fn start_background_tasks(self: Arc<Self>, store: Arc<DealStore>) {
+        let mut ticker = interval(Duration::from_secs(300));
+        loop {
+            ticker.tick().await;
+            self.process(&store).await;
+        }
+    }
+}
+
+impl Default for ImagePipeline {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,87 @@
+//! DCT-based perceptual hash (pHash)
+//!
+//! The image is reduced to 32x32 greyscale, transformed with a 2D DCT and the
+//! lowest 8x8 frequencies (minus the DC term) are compared against their median.
+//! Re-encoded, resized or lightly retouched copies of a photo end up a few bits
+//! apart; unrelated images differ in roughly half of the 64 bits.
+
+use std::f64::consts::PI;
+
+use image::imageops::FilterType;
+use image::DynamicImage;
+
+const SAMPLE_SIZE: u32 = 32;
+const HASH_SIZE: usize = 8;
+
+pub fn phash(image: &DynamicImage) -> u64 {
+    let pixels = image
+        .resize_exact(SAMPLE_SIZE, SAMPLE_SIZE, FilterType::Triangle)
+        .to_luma8();
+    let n = SAMPLE_SIZE as usize;
+
+    let mut coefficients = [0.0f64; HASH_SIZE * HASH_SIZE];
+    for u in 0..HASH_SIZE {
+        for v in 0..HASH_SIZE {
+            let mut sum = 0.0;
+            for (x, y, pixel) in pixels.enumerate_pixels() {
+                sum += pixel.0[0] as f64
+                    * ((2 * x as usize + 1) as f64 * u as f64 * PI / (2 * n) as f64).cos()
+                    * ((2 * y as usize + 1) as f64 * v as f64 * PI / (2 * n) as f64).cos();
+            }
+            coefficients[u * HASH_SIZE + v] = sum;
+        }
+    }
+
+    // The DC term only reflects overall brightness
+    let mut ac: Vec<f64> = coefficients[1..].to_vec();
+    ac.sort_by(f64::total_cmp);
+    let median = ac[ac.len() / 2];
+
+    coefficients
+        .iter()
+        .enumerate()
+        .filter(|(i, c)| *i > 0 && **c > median)
+        .fold(0u64, |hash, (i, _)| hash | (1 << i))
+}
+
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+pub fn to_hex(hash: u64) -> String {
+    format!("{:016x}", hash)
+}
+
+pub fn from_hex(hex: &str) -> Option<u64> {
+    u64::from_str_radix(hex, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GrayImage, Luma};
+
+    /// A blocky "product photo" drawn from a seed; identical seeds give the same picture
+    fn picture(width: u32, height: u32, seed: u64, brightness: i16) -> DynamicImage {
+        DynamicImage::ImageLuma8(GrayImage::from_fn(width, height, |x, y| {
+            let cell = (x * 8 / width + (y * 8 / height) * 8) as u64;
+            let value = (seed.wrapping_mul(6364136223846793005).wrapping_add(cell.wrapping_mul(1442695040888963407)) >> 56) as i16;
+            Luma([(value / 2 + 40 + brightness).clamp(0, 255) as u8])
+        }))
+    }
+
+    #[test]
+    fn test_resized_copy_hashes_close() {
+        let original = phash(&picture(400, 300, 7, 0));
+        let copy = phash(&picture(200, 150, 7, 25));
+        let other = phash(&picture(400, 300, 99, 0));
+
+        assert!(hamming_distance(original, copy) <= 6);
+        assert!(hamming_distance(original, other) > 16);
+    }
+
+    #[test]
+    fn test_hex_round_trip() {
+        assert_eq!(from_hex(&to_hex(0xdead_beef_0042)), Some(0xdead_beef_0042));
+    }
+}
@@ -0,0 +1,1110 @@
+//! Scrape job queue
+//!
+//! Coupon scrapes are submitted as jobs instead of being spawned directly, so
+//! interactive requests jump ahead of scheduled crawls and backfills, one tenant's
+//! backlog cannot starve the others, and jobs can be cancelled. The queue is
+//! shared through Redis when `REDIS_URL` is set, so API instances can enqueue jobs
+//! for dedicated workers; otherwise it is persisted to a JSON file and jobs that
+//! were running when the service stopped are queued again on startup.
+//!
+//! With [`ScrapeBudgets`], a job takes its merchants' daily request budgets when it
+//! starts. URLs over budget are moved to a follow-up job that waits for the next day,
+//! as are the URLs of merchants whose canary reports a layout change (see
+//! [`CanaryMonitor`]).
+//!
+//! With [`ScraperControls`], the URLs of paused merchants move to a follow-up job
+//! that checks again after [`PAUSE_RECHECK`].
+//!
+//! With [`Shards`], a worker only runs the URLs of merchants it owns. A claimed job's
+//! other URLs are handed off to follow-up jobs, one per owning worker, so each
+//! merchant is fetched under a single worker's rate limits.
+//!
+//! With an [`OptOutRegistry`], the URLs of merchants that opted out of scraping are
+//! dropped when a job is submitted and when it starts; they are never retried.
+//!
+//! URLs whose fetch failed, or every URL of a job that failed outright, are retried
+//! by a follow-up job after [`RETRY_DELAY`], doubled for each retry. After
+//! [`MAX_RETRIES`] they move to a dead-letter list kept with the queue, from which
+//! an operator can queue them again once the merchant is back.
+//!
+//! [Backfills](crate::backfill) keep their plan and checkpoints with the queue, so
+//! they are shared and survive restarts the same way as jobs.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, Notify};
+use tracing::Instrument;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::backfill::StoredBackfill;
+use crate::clock::{self, Clock};
+use crate::cluster::Shards;
+use crate::coupon_engine::budget::{self, ScrapeBudgets};
+use crate::coupon_engine::canary::CanaryMonitor;
+use crate::coupon_engine::controls::{ScraperControls, PAUSE_RECHECK};
+use crate::coupon_engine::opt_out::{BlockedAt, OptOutRegistry};
+use crate::coupon_engine::{BatchResult, CouponEngine, RawCoupon, UrlResult, OPTED_OUT};
+use crate::models::domain::MerchantDomain;
+use crate::models::url::normalize;
+use crate::storage::persisted::{PersistedStore, StoreError};
+use crate::telemetry::TraceContext;
+
+/// Concurrent jobs per service instance
+const WORKERS: usize = 2;
+/// Finished jobs kept for status lookups; older ones are dropped first
+const MAX_FINISHED_JOBS: usize = 500;
+pub const MAX_URLS_PER_JOB: usize = 100;
+/// URLs an operator may queue at once with [`ScrapeQueue::submit_batch`]
+pub const MAX_URLS_PER_BATCH: usize = 10_000;
+/// How long an idle worker waits before checking the queue again
+const IDLE_POLL: Duration = Duration::from_secs(5);
+/// How often a running job checks whether it was cancelled on another instance
+const CANCEL_POLL: Duration = Duration::from_secs(5);
+const REDIS_KEY: &str = "scrape_jobs";
+const STORE_NAME: &str = "scrape job queue";
+/// Retries of a failed URL before it is dead-lettered
+pub const MAX_RETRIES: u32 = 3;
+/// Wait before the first retry; each later retry waits twice as long as the last
+pub const RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+/// Dead letters kept; the oldest are dropped first
+const MAX_DEAD_LETTERS: usize = 5_000;
+
+/// Ordered from most to least urgent
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum JobPriority {
+    /// A user is waiting on the result
+    Interactive,
+    #[default]
+    Scheduled,
+    Backfill,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    pub fn is_finished(self) -> bool {
+        matches!(self, Self::Completed | Self::Failed | Self::Cancelled)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ScrapeJob {
+    pub id: Uuid,
+    pub tenant: String,
+    pub urls: Vec<String>,
+    pub priority: JobPriority,
+    pub status: JobStatus,
+    /// The request that queued the job; its fetches send the IDs on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace: Option<TraceContext>,
+    /// Not started before this time, e.g. when deferred to the next day's budget
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_before: Option<DateTime<Utc>>,
+    /// URLs over their merchant's daily budget, held back by its canary or of a
+    /// paused merchant, moved to `deferred_to`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deferred_urls: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deferred_to: Option<Uuid>,
+    /// URLs of merchants that opted out of scraping, dropped
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub blocked_urls: Vec<String>,
+    /// URLs of merchants owned by other workers, moved to `handed_off_to`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub handed_off_urls: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub handed_off_to: Vec<Uuid>,
+    /// Earlier attempts at these URLs, see [`MAX_RETRIES`]
+    #[serde(default)]
+    pub retries: u32,
+    /// URLs whose fetch failed, moved to `retried_to`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub retried_urls: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retried_to: Option<Uuid>,
+    /// URLs that failed their last retry, see [`ScrapeQueue::dead_letters`]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dead_lettered_urls: Vec<String>,
+    /// Coupons found, once the job has completed
+    #[serde(default)]
+    pub coupons: Vec<RawCoupon>,
+    /// What each URL produced and the response headers it was served with, once
+    /// the job has completed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub url_results: Vec<UrlResult>,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+impl ScrapeJob {
+    fn new(tenant: String, urls: Vec<String>, priority: JobPriority, now: DateTime<Utc>) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            tenant,
+            urls,
+            priority,
+            status: JobStatus::Queued,
+            trace: None,
+            not_before: None,
+            deferred_urls: Vec::new(),
+            deferred_to: None,
+            blocked_urls: Vec::new(),
+            handed_off_urls: Vec::new(),
+            handed_off_to: Vec::new(),
+            retries: 0,
+            retried_urls: Vec::new(),
+            retried_to: None,
+            dead_lettered_urls: Vec::new(),
+            coupons: Vec::new(),
+            url_results: Vec::new(),
+            error: None,
+            created_at: now,
+            started_at: None,
+            finished_at: None,
+        }
+    }
+
+    /// A queued job for `urls`, with this job's tenant, priority, trace and retry count
+    fn follow_up(&self, urls: Vec<String>, not_before: Option<DateTime<Utc>>, now: DateTime<Utc>) -> Self {
+        Self {
+            not_before,
+            retries: self.retries,
+            trace: self.trace.clone(),
+            ..Self::new(self.tenant.clone(), urls, self.priority, now)
+        }
+    }
+}
+
+/// A URL that failed on every attempt
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct DeadLetter {
+    pub id: Uuid,
+    pub tenant: String,
+    pub url: String,
+    pub priority: JobPriority,
+    /// Including the first
+    pub attempts: u32,
+    /// Why the last attempt failed
+    pub error: String,
+    /// The job that made the last attempt
+    pub job_id: Uuid,
+    pub failed_at: DateTime<Utc>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CancelError {
+    NotFound,
+    AlreadyFinished(JobStatus),
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct QueueState {
+    jobs: HashMap<Uuid, ScrapeJob>,
+    /// Dispatch sequence number of each tenant's most recently started job
+    last_served: HashMap<String, u64>,
+    dispatched: u64,
+    /// Oldest first
+    #[serde(default)]
+    dead_letters: Vec<DeadLetter>,
+    /// Kept with the jobs they queue, see [`crate::backfill`]
+    #[serde(default)]
+    backfills: HashMap<Uuid, StoredBackfill>,
+    /// Signalled to stop a running job
+    #[serde(skip)]
+    running: HashMap<Uuid, Arc<Notify>>,
+}
+
+impl QueueState {
+    /// Most urgent queued job that may start at `now` and has a URL `mine` accepts,
+    /// taking the tenant served longest ago first within a priority
+    fn next_queued(&self, now: DateTime<Utc>, mine: impl Fn(&str) -> bool) -> Option<Uuid> {
+        self.jobs
+            .values()
+            .filter(|job| job.status == JobStatus::Queued && job.not_before.is_none_or(|at| at <= now))
+            .filter(|job| job.urls.iter().any(|url| mine(url)))
+            .min_by_key(|job| {
+                let last_served = self.last_served.get(&job.tenant).copied().unwrap_or(0);
+                (job.priority, last_served, job.created_at)
+            })
+            .map(|job| job.id)
+    }
+
+    /// Queue a retry of the `(url, error)` pairs that failed in `id`, or dead-letter
+    /// them once it was the last retry
+    fn retry_failed(&mut self, id: Uuid, failed: Vec<(String, String)>, now: DateTime<Utc>) {
+        let Some(job) = self.jobs.get(&id).filter(|_| !failed.is_empty()) else {
+            return;
+        };
+        let urls: Vec<String> = failed.iter().map(|(url, _)| url.clone()).collect();
+        if job.retries >= MAX_RETRIES {
+            let (tenant, priority, attempts) = (job.tenant.clone(), job.priority, job.retries + 1);
+            self.dead_letters.extend(failed.into_iter().map(|(url, error)| DeadLetter {
+                id: Uuid::new_v4(),
+                tenant: tenant.clone(),
+                url,
+                priority,
+                attempts,
+                error,
+                job_id: id,
+                failed_at: now,
+            }));
+            let over = self.dead_letters.len().saturating_sub(MAX_DEAD_LETTERS);
+            self.dead_letters.drain(..over);
+            if let Some(job) = self.jobs.get_mut(&id) {
+                job.dead_lettered_urls = urls;
+            }
+            return;
+        }
+
+        let delay = RETRY_DELAY * 2u32.pow(job.retries);
+        let retry = ScrapeJob {
+            retries: job.retries + 1,
+            ..job.follow_up(urls.clone(), Some(now + chrono::Duration::from_std(delay).unwrap_or(chrono::Duration::MAX)), now)
+        };
+        if let Some(job) = self.jobs.get_mut(&id) {
+            job.retried_urls = urls;
+            job.retried_to = Some(retry.id);
+        }
+        self.jobs.insert(retry.id, retry);
+    }
+
+    fn prune_finished(&mut self) {
+        let mut finished: Vec<(DateTime<Utc>, Uuid)> = self
+            .jobs
+            .values()
+            .filter(|job| job.status.is_finished())
+            .map(|job| (job.finished_at.unwrap_or(job.created_at), job.id))
+            .collect();
+        if finished.len() <= MAX_FINISHED_JOBS {
+            return;
+        }
+
+        finished.sort();
+        for (_, id) in &finished[..finished.len() - MAX_FINISHED_JOBS] {
+            self.jobs.remove(id);
+        }
+    }
+}
+
+pub struct ScrapeQueue {
+    state: Mutex<QueueState>,
+    wakeup: Notify,
+    store: PersistedStore<QueueState>,
+    budgets: Option<Arc<ScrapeBudgets>>,
+    canaries: Option<Arc<CanaryMonitor>>,
+    opt_outs: Option<Arc<OptOutRegistry>>,
+    controls: Option<Arc<ScraperControls>>,
+    shards: Option<Arc<Shards>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl ScrapeQueue {
+    /// Queue persisted to `path`, or kept in memory only
+    pub fn new(path: Option<PathBuf>) -> Self {
+        Self::with_store(PersistedStore::new(STORE_NAME, REDIS_KEY, path))
+    }
+
+    /// Queue shared through Redis, so API and worker instances see the same jobs
+    pub fn shared(redis_url: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(Self::with_store(PersistedStore::shared(STORE_NAME, REDIS_KEY, redis_url)?))
+    }
+
+    fn with_store(store: PersistedStore<QueueState>) -> Self {
+        Self {
+            state: Mutex::new(QueueState::default()),
+            wakeup: Notify::new(),
+            store,
+            budgets: None,
+            canaries: None,
+            opt_outs: None,
+            controls: None,
+            shards: None,
+            clock: clock::system(),
+        }
+    }
+
+    /// Hold jobs to their merchants' daily request budgets
+    pub fn with_budgets(mut self, budgets: Arc<ScrapeBudgets>) -> Self {
+        self.budgets = Some(budgets);
+        self
+    }
+
+    /// Hold back the URLs of merchants whose layout changed
+    pub fn with_canaries(mut self, canaries: Arc<CanaryMonitor>) -> Self {
+        self.canaries = Some(canaries);
+        self
+    }
+
+    /// Drop the URLs of merchants that opted out of scraping
+    pub fn with_opt_outs(mut self, opt_outs: Arc<OptOutRegistry>) -> Self {
+        self.opt_outs = Some(opt_outs);
+        self
+    }
+
+    /// Hold back the URLs of paused merchants
+    pub fn with_controls(mut self, controls: Arc<ScraperControls>) -> Self {
+        self.controls = Some(controls);
+        self
+    }
+
+    /// Run only the URLs of merchants this worker owns
+    pub fn with_shards(mut self, shards: Arc<Shards>) -> Self {
+        self.shards = Some(shards);
+        self
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Share the queue through `REDIS_URL` when set, otherwise load it from `JOB_QUEUE_PATH`
+    /// (default `data/scrape_jobs.json`)
+    pub async fn from_env() -> Self {
+        let queue = Self::with_store(PersistedStore::from_env(STORE_NAME, REDIS_KEY, "JOB_QUEUE_PATH", "data/scrape_jobs.json"));
+        if let Err(e) = queue.load().await {
+            tracing::warn!(error = %e, "Starting with an empty scrape job queue");
+        }
+        queue
+    }
+
+    /// Load a file-backed queue, requeueing jobs that were running when the process stopped.
+    ///
+    /// A shared queue is left alone: other instances may still be running those jobs, and
+    /// [`sweep`](Self::sweep) requeues the ones whose worker died.
+    pub(crate) async fn load(&self) -> Result<(), StoreError> {
+        if self.store.is_shared() {
+            return Ok(());
+        }
+        let Some(mut loaded) = self.store.load().await? else {
+            return Ok(());
+        };
+        for job in loaded.jobs.values_mut() {
+            if job.status == JobStatus::Running {
+                job.status = JobStatus::Queued;
+                job.started_at = None;
+            }
+        }
+        *self.state.lock().await = loaded;
+        Ok(())
+    }
+
+    /// Apply `change` to the latest queue state and persist the result.
+    ///
+    /// A shared queue is re-read inside a Redis `WATCH` transaction, so `change` runs
+    /// again if another instance modified the queue in the meantime.
+    async fn update<T>(&self, mut change: impl FnMut(&mut QueueState) -> T) -> T {
+        let mut state = self.state.lock().await;
+        let Some(client) = self.store.redis() else {
+            let result = change(&mut state);
+            self.store.persist(&state).await;
+            return result;
+        };
+        match update_shared(client, &mut state, &mut change) {
+            Ok(result) => result,
+            Err(e) => {
+                tracing::warn!(error = %e, "Shared scrape job queue unavailable, updating the local copy");
+                change(&mut state)
+            }
+        }
+    }
+
+    async fn read<T>(&self, view: impl FnOnce(&QueueState) -> T) -> T {
+        let mut state = self.state.lock().await;
+        if let Some(client) = self.store.redis() {
+            if let Err(e) = refresh_shared(client, &mut state) {
+                tracing::warn!(error = %e, "Shared scrape job queue unavailable, reading the local copy");
+            }
+        }
+        view(&state)
+    }
+
+    pub async fn submit(&self, tenant: &str, urls: Vec<String>, priority: JobPriority) -> Result<ScrapeJob, String> {
+        if urls.is_empty() {
+            return Err("at least one URL is required".to_string());
+        }
+        if urls.len() > MAX_URLS_PER_JOB {
+            return Err(format!("at most {} URLs per job", MAX_URLS_PER_JOB));
+        }
+        // Variants of one page are scraped once
+        let mut urls = urls.iter().map(|u| normalize(u)).collect::<Result<Vec<_>, _>>()?;
+        let mut seen = HashSet::new();
+        urls.retain(|u| seen.insert(u.clone()));
+        let (blocked, urls) = self.partition_blocked(urls, BlockedAt::Submit);
+        if urls.is_empty() {
+            return Err("every URL belongs to a merchant that opted out of scraping".to_string());
+        }
+
+        let mut job = ScrapeJob::new(tenant.to_string(), urls, priority, self.clock.now());
+        job.blocked_urls = blocked;
+        job.trace = TraceContext::current();
+
+        self.update(|state| {
+            state.jobs.insert(job.id, job.clone());
+        })
+        .await;
+
+        self.wakeup.notify_one();
+        Ok(job)
+    }
+
+    /// Queue a large batch of `urls`, e.g. an operator's backfill, as jobs of at most
+    /// [`MAX_URLS_PER_JOB`] URLs each. Nothing is queued when any URL is invalid.
+    pub async fn submit_batch(&self, tenant: &str, urls: Vec<String>, priority: JobPriority) -> Result<Vec<ScrapeJob>, String> {
+        if urls.is_empty() {
+            return Err("at least one URL is required".to_string());
+        }
+        if urls.len() > MAX_URLS_PER_BATCH {
+            return Err(format!("at most {} URLs per batch", MAX_URLS_PER_BATCH));
+        }
+        let mut urls = urls.iter().map(|u| normalize(u)).collect::<Result<Vec<_>, _>>()?;
+        let mut seen = HashSet::new();
+        urls.retain(|u| seen.insert(u.clone()));
+        let (_, urls) = self.partition_blocked(urls, BlockedAt::Submit);
+        if urls.is_empty() {
+            return Err("every URL belongs to a merchant that opted out of scraping".to_string());
+        }
+
+        let mut jobs = Vec::with_capacity(urls.len().div_ceil(MAX_URLS_PER_JOB));
+        for chunk in urls.chunks(MAX_URLS_PER_JOB) {
+            jobs.push(self.submit(tenant, chunk.to_vec(), priority).await?);
+        }
+        Ok(jobs)
+    }
+
+    /// A tenant's job; other tenants' jobs are reported as missing
+    pub async fn get(&self, tenant: &str, id: Uuid) -> Option<ScrapeJob> {
+        self.read(|state| state.jobs.get(&id).filter(|job| job.tenant == tenant).cloned())
+            .await
+    }
+
+    /// Cancel a queued or running job
+    pub async fn cancel(&self, tenant: &str, id: Uuid) -> Result<ScrapeJob, CancelError> {
+        let job = self
+            .update(|state| {
+                let job = state
+                    .jobs
+                    .get_mut(&id)
+                    .filter(|job| job.tenant == tenant)
+                    .ok_or(CancelError::NotFound)?;
+                if job.status.is_finished() {
+                    return Err(CancelError::AlreadyFinished(job.status));
+                }
+
+                job.status = JobStatus::Cancelled;
+                job.finished_at = Some(self.clock.now());
+                Ok(job.clone())
+            })
+            .await?;
+
+        // Jobs running on other instances notice the status on their next poll
+        if let Some(stop) = self.state.lock().await.running.remove(&id) {
+            stop.notify_one();
+        }
+        Ok(job)
+    }
+
+    /// Mark the next job with URLs this worker owns as running, handing its other URLs
+    /// off, and return it with its cancellation signal
+    async fn claim_next(&self) -> Option<(ScrapeJob, Arc<Notify>)> {
+        let shards = self.shards.as_ref().map(|shards| (shards.workers(), shards.instance_id()));
+        // Out of touch with the other workers, so owning nothing
+        if shards.as_ref().is_some_and(|(workers, _)| workers.is_empty()) {
+            return None;
+        }
+        let owner = |url: &str| match &shards {
+            Some((workers, _)) => url_owner(workers, url),
+            None => None,
+        };
+        let mine = |url: &str| owner(url).is_none_or(|owner| shards.as_ref().is_some_and(|(_, me)| owner == *me));
+
+        let job = self
+            .update(|state| {
+                let id = state.next_queued(self.clock.now(), mine)?;
+                state.dispatched += 1;
+                let sequence = state.dispatched;
+                let job = state.jobs.get_mut(&id)?;
+
+                let (urls, others): (Vec<String>, Vec<String>) = job.urls.iter().cloned().partition(|url| mine(url));
+                let mut by_owner: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+                for url in &others {
+                    by_owner.entry(owner(url).unwrap_or_default()).or_default().push(url.clone());
+                }
+                let hand_offs: Vec<ScrapeJob> = by_owner
+                    .into_values()
+                    .map(|urls| ScrapeJob {
+                        id: Uuid::new_v4(),
+                        urls,
+                        ..job.clone()
+                    })
+                    .collect();
+                job.urls = urls;
+                job.handed_off_urls = others;
+                job.handed_off_to = hand_offs.iter().map(|hand_off| hand_off.id).collect();
+
+                job.status = JobStatus::Running;
+                job.started_at = Some(self.clock.now());
+                let job = job.clone();
+                state.last_served.insert(job.tenant.clone(), sequence);
+                for hand_off in hand_offs {
+                    state.jobs.insert(hand_off.id, hand_off);
+                }
+                Some(job)
+            })
+            .await?;
+
+        let stop = Arc::new(Notify::new());
+        self.state.lock().await.running.insert(job.id, stop.clone());
+        Some((job, stop))
+    }
+
+    /// Decide which of a claimed job's URLs run now. URLs held back by a canary or over
+    /// budget move to a job queued for when the budgets reset, and those of paused
+    /// merchants to one queued for [`PAUSE_RECHECK`] later; a job with no URL left
+    /// waits itself and `None` is returned.
+    async fn admit(&self, mut job: ScrapeJob) -> Option<ScrapeJob> {
+        let (blocked, urls) = self.partition_blocked(job.urls.clone(), BlockedAt::Queue);
+        if !blocked.is_empty() {
+            job.urls = urls;
+            job.blocked_urls.extend(blocked);
+            self.update(|state| {
+                if let Some(stored) = state.jobs.get_mut(&job.id) {
+                    stored.urls = job.urls.clone();
+                    stored.blocked_urls = job.blocked_urls.clone();
+                }
+            })
+            .await;
+            if job.urls.is_empty() {
+                self.finish(job.id, Ok(BatchResult::default())).await;
+                return None;
+            }
+        }
+
+        let (mut allowed, mut deferred) = (job.urls.clone(), Vec::new());
+        if let Some(canaries) = &self.canaries {
+            let changed = canaries.changed_layouts().await;
+            if !changed.is_empty() {
+                (deferred, allowed) = allowed
+                    .into_iter()
+                    .partition(|url| MerchantDomain::parse(url).is_ok_and(|domain| changed.contains(&domain)));
+            }
+        }
+        let mut paused = Vec::new();
+        if let Some(controls) = &self.controls {
+            (paused, allowed) = allowed.into_iter().partition(|url| controls.is_paused(url));
+        }
+        if let Some(budgets) = &self.budgets {
+            let over;
+            (allowed, over) = budgets.take(&allowed).await;
+            deferred.extend(over);
+        }
+        if deferred.is_empty() && paused.is_empty() {
+            return Some(job);
+        }
+
+        let now = self.clock.now();
+        let resume_at = match deferred.is_empty() {
+            true => now + chrono::Duration::from_std(PAUSE_RECHECK).unwrap_or(chrono::Duration::MAX),
+            false => budget::next_reset(now),
+        };
+        deferred.extend(paused);
+        if allowed.is_empty() {
+            self.update(|state| {
+                if let Some(stored) = state.jobs.get_mut(&job.id).filter(|stored| stored.status == JobStatus::Running) {
+                    stored.status = JobStatus::Queued;
+                    stored.started_at = None;
+                    stored.not_before = Some(resume_at);
+                }
+            })
+            .await;
+            self.state.lock().await.running.remove(&job.id);
+            tracing::info!(job = %job.id, %resume_at, "Scrape job held back, paused or over budget; deferred");
+            return None;
+        }
+
+        let follow_up = job.follow_up(deferred.clone(), Some(resume_at), self.clock.now());
+        job.urls = allowed;
+        job.deferred_urls = deferred;
+        job.deferred_to = Some(follow_up.id);
+        self.update(|state| {
+            state.jobs.insert(follow_up.id, follow_up.clone());
+            if let Some(stored) = state.jobs.get_mut(&job.id) {
+                stored.deferred_urls = job.deferred_urls.clone();
+                stored.deferred_to = job.deferred_to;
+            }
+        })
+        .await;
+        Some(job)
+    }
+
+    /// `(blocked, allowed)`: `urls` split by whether their merchant opted out
+    fn partition_blocked(&self, urls: Vec<String>, at: BlockedAt) -> (Vec<String>, Vec<String>) {
+        match &self.opt_outs {
+            Some(opt_outs) => urls.into_iter().partition(|url| opt_outs.blocks(url, at)),
+            None => (Vec::new(), urls),
+        }
+    }
+
+    async fn finish(&self, id: Uuid, result: Result<BatchResult, String>) {
+        let now = self.clock.now();
+        self.update(|state| {
+            let Some(job) = state.jobs.get_mut(&id) else {
+                return;
+            };
+            // A cancelled job keeps its status even if the scrape finished first
+            if job.status != JobStatus::Running {
+                return;
+            }
+
+            let failed: Vec<(String, String)> = match &result {
+                Ok(batch) => {
+                    job.status = JobStatus::Completed;
+                    job.coupons = batch.coupons.clone();
+                    job.url_results = batch.urls.clone();
+                    // Pages fetched but rejected, e.g. redirected off the merchant, fail
+                    // the same way again, as do opted-out merchants
+                    batch
+                        .urls
+                        .iter()
+                        .filter(|url| !url.fetched && url.error.as_deref() != Some(OPTED_OUT))
+                        .filter_map(|url| Some((url.url.clone(), url.error.clone()?)))
+                        .collect()
+                }
+                Err(e) => {
+                    job.status = JobStatus::Failed;
+                    job.error = Some(e.clone());
+                    job.urls.iter().map(|url| (url.clone(), e.clone())).collect()
+                }
+            };
+            job.finished_at = Some(now);
+            state.retry_failed(id, failed, now);
+            state.prune_finished();
+        })
+        .await;
+        self.state.lock().await.running.remove(&id);
+    }
+
+    /// Dead-lettered URLs, newest first, optionally only a tenant's or a merchant's
+    pub async fn dead_letters(&self, tenant: Option<&str>, merchant: Option<&MerchantDomain>) -> Vec<DeadLetter> {
+        self.read(|state| {
+            state
+                .dead_letters
+                .iter()
+                .rev()
+                .filter(|letter| tenant.is_none_or(|tenant| letter.tenant == tenant))
+                .filter(|letter| merchant.is_none_or(|merchant| MerchantDomain::parse(&letter.url).is_ok_and(|domain| domain == *merchant)))
+                .cloned()
+                .collect()
+        })
+        .await
+    }
+
+    /// Queue a dead-lettered URL again with a fresh set of retries
+    pub async fn retry_dead_letter(&self, id: Uuid) -> Option<ScrapeJob> {
+        let now = self.clock.now();
+        let job = self
+            .update(|state| {
+                let index = state.dead_letters.iter().position(|letter| letter.id == id)?;
+                let letter = state.dead_letters.remove(index);
+                let job = ScrapeJob::new(letter.tenant, vec![letter.url], letter.priority, now);
+                state.jobs.insert(job.id, job.clone());
+                Some(job)
+            })
+            .await?;
+
+        self.wakeup.notify_one();
+        Some(job)
+    }
+
+    pub(crate) async fn backfills(&self) -> Vec<StoredBackfill> {
+        self.read(|state| state.backfills.values().cloned().collect()).await
+    }
+
+    pub(crate) async fn backfill(&self, id: Uuid) -> Option<StoredBackfill> {
+        self.read(|state| state.backfills.get(&id).cloned()).await
+    }
+
+    /// Store a new backfill, dropping the oldest finished ones over `keep_finished`
+    pub(crate) async fn insert_backfill(&self, backfill: StoredBackfill, keep_finished: usize) {
+        self.update(|state| {
+            let mut finished: Vec<(DateTime<Utc>, Uuid)> = state
+                .backfills
+                .values()
+                .filter(|stored| stored.backfill.status.is_finished())
+                .map(|stored| (stored.backfill.created_at, stored.backfill.id))
+                .collect();
+            finished.sort();
+            for (_, id) in &finished[..finished.len().saturating_sub(keep_finished)] {
+                state.backfills.remove(id);
+            }
+            state.backfills.insert(backfill.backfill.id, backfill.clone());
+        })
+        .await
+    }
+
+    /// Apply `change` to backfill `id`, `None` when there is no such backfill
+    pub(crate) async fn update_backfill<T>(&self, id: Uuid, mut change: impl FnMut(&mut StoredBackfill) -> T) -> Option<T> {
+        self.update(|state| state.backfills.get_mut(&id).map(&mut change)).await
+    }
+
+    /// Requeue jobs that have been running longer than `timeout`, e.g. because their
+    /// worker was stopped, and return how many were requeued.
+    ///
+    /// Should run on a single instance; see [`LeaderElection`](crate::cluster::LeaderElection).
+    pub async fn sweep(&self, timeout: Duration) -> usize {
+        let cutoff = self.clock.now() - chrono::Duration::from_std(timeout).unwrap_or(chrono::Duration::MAX);
+        let requeued = self
+            .update(|state| {
+                let mut requeued = 0;
+                for job in state.jobs.values_mut() {
+                    if job.status == JobStatus::Running && job.started_at.is_none_or(|at| at < cutoff) {
+                        job.status = JobStatus::Queued;
+                        job.started_at = None;
+                        requeued += 1;
+                    }
+                }
+                requeued
+            })
+            .await;
+
+        if requeued > 0 {
+            tracing::info!(requeued, "Requeued stalled scrape jobs");
+            self.wakeup.notify_waiters();
+        }
+        requeued
+    }
+
+    /// Resolves once job `id` is cancelled, here or on another instance
+    async fn cancelled(&self, id: Uuid, stop: &Notify) {
+        loop {
+            tokio::select! {
+                _ = stop.notified() => return,
+                _ = tokio::time::sleep(CANCEL_POLL) => {
+                    let status = self.read(|state| state.jobs.get(&id).map(|job| job.status)).await;
+                    if status != Some(JobStatus::Running) {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Run queued jobs through the coupon engine until the process exits
+    pub async fn start_background_tasks(self: Arc<Self>, engine: Arc<CouponEngine>) {
+        let mut workers = tokio::task::JoinSet::new();
+        for _ in 0..WORKERS {
+            workers.spawn(self.clone().run_worker(engine.clone()));
+        }
+        while workers.join_next().await.is_some() {}
+    }
+
+    async fn run_worker(self: Arc<Self>, engine: Arc<CouponEngine>) {
+        loop {
+            let Some((job, stop)) = self.claim_next().await else {
+                let _ = tokio::time::timeout(IDLE_POLL, self.wakeup.notified()).await;
+                continue;
+            };
+            let Some(job) = self.admit(job).await else {
+                continue;
+            };
+
+            let span = tracing::info_span!(
+                "scrape_job",
+                job_id = %job.id,
+                request_id = job.trace.as_ref().map(|trace| trace.request_id.as_str()),
+            );
+            let batch = TraceContext::scope(job.trace.clone(), engine.process_batch_detailed(job.urls.clone())).instrument(span);
+            tokio::select! {
+                result = batch => {
+                    self.finish(job.id, result.map_err(|e| e.to_string())).await;
+                }
+                _ = self.cancelled(job.id, &stop) => {
+                    self.state.lock().await.running.remove(&job.id);
+                    tracing::info!(job = %job.id, "Cancelled a scrape job");
+                }
+            }
+        }
+    }
+}
+
+/// Worker that should scrape `url`, or `None` when anyone may
+fn url_owner<'a>(workers: &'a [String], url: &str) -> Option<&'a str> {
+    MerchantDomain::parse(url).ok().and_then(|domain| Shards::owner(workers, &domain))
+}
+
+/// Replace the local copy with the state stored in Redis, keeping local cancellation signals
+fn load_shared(state: &mut QueueState, stored: Option<String>) -> redis::RedisResult<()> {
+    let loaded = match stored {
+        Some(content) => serde_json::from_str(&content).map_err(|e| {
+            redis::RedisError::from((redis::ErrorKind::TypeError, "invalid scrape job queue", e.to_string()))
+        })?,
+        None => QueueState::default(),
+    };
+    let running = std::mem::take(&mut state.running);
+    *state = loaded;
+    state.running = running;
+    Ok(())
+}
+
+fn refresh_shared(client: &redis::Client, state: &mut QueueState) -> redis::RedisResult<()> {
+    let mut con = client.get_connection()?;
+    let stored: Option<String> = redis::cmd("GET").arg(REDIS_KEY).query(&mut con)?;
+    load_shared(state, stored)
+}
+
+fn update_shared<T>(
+    client: &redis::Client,
+    state: &mut QueueState,
+    change: &mut impl FnMut(&mut QueueState) -> T,
+) -> redis::RedisResult<T> {
+    let mut con = client.get_connection()?;
+    let mut result = None;
+    redis::transaction(&mut con, &[REDIS_KEY], |con, pipe| {
+        let stored: Option<String> = redis::cmd("GET").arg(REDIS_KEY).query(con)?;
+        load_shared(state, stored)?;
+        result = Some(change(state));
+
+        let content = serde_json::to_string(&*state).map_err(|e| {
+            redis::RedisError::from((redis::ErrorKind::TypeError, "unserializable scrape job queue", e.to_string()))
+        })?;
+        pipe.set(REDIS_KEY, content).ignore().query::<Option<()>>(con)
+    })?;
+
+    Ok(result.expect("transaction applied the change"))
+}
+
+impl Default for ScrapeQueue {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    fn urls() -> Vec<String> {
+        vec!["https://shop.example.com/".to_string()]
+    }
+
+    #[tokio::test]
+    async fn test_priority_then_tenant_fairness() {
+        let queue = ScrapeQueue::default();
+        let backfill = queue.submit("a", urls(), JobPriority::Backfill).await.unwrap();
+        let first_a = queue.submit("a", urls(), JobPriority::Scheduled).await.unwrap();
+        let second_a = queue.submit("a", urls(), JobPriority::Scheduled).await.unwrap();
+        let only_b = queue.submit("b", urls(), JobPriority::Scheduled).await.unwrap();
+        let interactive = queue.submit("a", urls(), JobPriority::Interactive).await.unwrap();
+
+        let mut order = Vec::new();
+        while let Some((job, _)) = queue.claim_next().await {
+            order.push(job.id);
+        }
+
+        // Tenant "a" was just served by the interactive job, so "b" goes before its scheduled backlog
+        assert_eq!(order, vec![interactive.id, only_b.id, first_a.id, second_a.id, backfill.id]);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_running_job_discards_result() {
+        let queue = ScrapeQueue::default();
+        let job = queue.submit("a", urls(), JobPriority::Interactive).await.unwrap();
+        let (_, stop) = queue.claim_next().await.unwrap();
+
+        assert!(queue.cancel("b", job.id).await.is_err());
+        assert_eq!(queue.cancel("a", job.id).await.unwrap().status, JobStatus::Cancelled);
+        // The worker sees the stored permit even though it was not waiting yet
+        stop.notified().await;
+
+        queue.finish(job.id, Ok(BatchResult::default())).await;
+        assert_eq!(queue.get("a", job.id).await.unwrap().status, JobStatus::Cancelled);
+        assert_eq!(
+            queue.cancel("a", job.id).await.unwrap_err(),
+            CancelError::AlreadyFinished(JobStatus::Cancelled)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_running_jobs_resume_after_restart() {
+        let path = std::env::temp_dir().join(format!("scrape_jobs_{}.json", Uuid::new_v4()));
+        let queue = ScrapeQueue::new(Some(path.clone()));
+        let trace = TraceContext {
+            request_id: "batch-7".to_string(),
+            traceparent: "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01".to_string(),
+        };
+        let job = TraceContext::scope(Some(trace.clone()), queue.submit("a", urls(), JobPriority::Scheduled)).await.unwrap();
+        queue.claim_next().await.unwrap();
+
+        let restarted = ScrapeQueue::new(Some(path.clone()));
+        restarted.load().await.unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        let stored = restarted.get("a", job.id).await.unwrap();
+        assert_eq!((stored.status, stored.trace), (JobStatus::Queued, Some(trace)));
+        assert_eq!(restarted.claim_next().await.unwrap().0.id, job.id);
+    }
+
+    #[tokio::test]
+    async fn test_urls_over_budget_wait_for_next_day() {
+        let clock = Arc::new(MockClock::new());
+        let budgets = Arc::new(ScrapeBudgets::default().with_default_budget(2).with_clock(clock.clone()));
+        let queue = ScrapeQueue::default().with_budgets(budgets).with_clock(clock.clone());
+        let urls: Vec<String> = (1..=3).map(|i| format!("https://shop.example.com/{}", i)).collect();
+        let job = queue.submit("a", urls.clone(), JobPriority::Interactive).await.unwrap();
+
+        let (claimed, _) = queue.claim_next().await.unwrap();
+        let running = queue.admit(claimed).await.unwrap();
+        assert_eq!(running.urls, urls[..2].to_vec());
+        let stored = queue.get("a", job.id).await.unwrap();
+        assert_eq!(stored.deferred_urls, urls[2..].to_vec());
+
+        // The follow-up is held back until the budgets reset
+        assert!(queue.claim_next().await.is_none());
+        clock.advance(Duration::from_secs(24 * 3600));
+        let (follow_up, _) = queue.claim_next().await.unwrap();
+        assert_eq!(Some(follow_up.id), stored.deferred_to);
+        assert_eq!(queue.admit(follow_up).await.unwrap().urls, urls[2..].to_vec());
+    }
+
+    #[tokio::test]
+    async fn test_paused_merchants_urls_wait_until_resumed() {
+        let clock = Arc::new(MockClock::new());
+        let controls = Arc::new(ScraperControls::new(None).with_clock(clock.clone()));
+        let queue = ScrapeQueue::default().with_controls(controls.clone()).with_clock(clock.clone());
+        let paused = "https://paused.example.com/sale".to_string();
+        let running = "https://shop.example.com/sale".to_string();
+        controls.pause(MerchantDomain::parse("paused.example.com").unwrap(), Default::default()).await.unwrap();
+        let job = queue.submit("a", vec![paused.clone(), running.clone()], JobPriority::Scheduled).await.unwrap();
+
+        let (claimed, _) = queue.claim_next().await.unwrap();
+        assert_eq!(queue.admit(claimed).await.unwrap().urls, vec![running]);
+        assert_eq!(queue.get("a", job.id).await.unwrap().deferred_urls, vec![paused.clone()]);
+
+        controls.resume(&MerchantDomain::parse("paused.example.com").unwrap()).await.unwrap();
+        assert!(queue.claim_next().await.is_none());
+        clock.advance(PAUSE_RECHECK);
+        let (follow_up, _) = queue.claim_next().await.unwrap();
+        assert_eq!(queue.admit(follow_up).await.unwrap().urls, vec![paused]);
+    }
+
+    #[tokio::test]
+    async fn test_workers_run_only_their_merchants_and_pick_up_leavers_merchants() {
+        let workers = vec!["w1".to_string(), "w2".to_string()];
+        let shards = Arc::new(Shards::new(None, "w1"));
+        shards.set_workers(workers.clone());
+        let queue = ScrapeQueue::default().with_shards(shards.clone());
+        let urls: Vec<String> = (0..20).map(|i| format!("https://shop{}.example.com/", i)).collect();
+        let job = queue.submit("a", urls.clone(), JobPriority::Scheduled).await.unwrap();
+
+        let (claimed, _) = queue.claim_next().await.unwrap();
+        let (mine, theirs): (Vec<String>, Vec<String>) =
+            urls.into_iter().partition(|url| url_owner(&workers, url) == Some("w1"));
+        assert!(!mine.is_empty() && !theirs.is_empty());
+        assert_eq!(claimed.urls, mine);
+        let stored = queue.get("a", job.id).await.unwrap();
+        assert_eq!(stored.handed_off_urls, theirs);
+        assert_eq!(stored.handed_off_to.len(), 1);
+
+        // w2's merchants wait for w2, until it leaves
+        assert!(queue.claim_next().await.is_none());
+        shards.set_workers(vec!["w1".to_string()]);
+        let (hand_off, _) = queue.claim_next().await.unwrap();
+        assert_eq!(hand_off.id, stored.handed_off_to[0]);
+        assert_eq!(hand_off.urls, theirs);
+        assert!(hand_off.handed_off_to.is_empty());
+    }
+
+    fn fetch_failed(url: &str) -> UrlResult {
+        UrlResult {
+            url: url.to_string(),
+            fetched: false,
+            unchanged: false,
+            coupons_extracted: 0,
+            coupons_valid: 0,
+            error: Some("connection refused".to_string()),
+            headers: Default::default(),
+            redirects: None,
+            timings: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failed_urls_are_retried_with_backoff_then_dead_lettered() {
+        let clock = Arc::new(MockClock::new());
+        let queue = ScrapeQueue::default().with_clock(clock.clone());
+        let (up, down) = ("https://up.example.com/".to_string(), "https://down.example.com/".to_string());
+        let job = queue.submit("a", vec![up.clone(), down.clone()], JobPriority::Scheduled).await.unwrap();
+
+        let (claimed, _) = queue.claim_next().await.unwrap();
+        let batch = BatchResult {
+            coupons: Vec::new(),
+            urls: vec![UrlResult { fetched: true, error: None, ..fetch_failed(&up) }, fetch_failed(&down)],
+        };
+        queue.finish(claimed.id, Ok(batch)).await;
+        let stored = queue.get("a", job.id).await.unwrap();
+        assert_eq!((stored.status, stored.retried_urls.clone()), (JobStatus::Completed, vec![down.clone()]));
+
+        // Each retry waits twice as long as the one before
+        let mut retry_id = stored.retried_to.unwrap();
+        for retry in 1..=MAX_RETRIES {
+            let delay = RETRY_DELAY * 2u32.pow(retry - 1);
+            clock.advance(delay - Duration::from_secs(1));
+            assert!(queue.claim_next().await.is_none());
+            clock.advance(Duration::from_secs(1));
+            let (claimed, _) = queue.claim_next().await.unwrap();
+            assert_eq!((claimed.id, claimed.retries, claimed.urls.clone()), (retry_id, retry, vec![down.clone()]));
+            queue.finish(claimed.id, Err("dedupe failed".to_string())).await;
+            retry_id = queue.get("a", claimed.id).await.unwrap().retried_to.unwrap_or_default();
+        }
+
+        // The last retry failed, so the URL is dead-lettered instead
+        assert_eq!(retry_id, Uuid::nil());
+        clock.advance(Duration::from_secs(24 * 3600));
+        assert!(queue.claim_next().await.is_none());
+        let letters = queue.dead_letters(Some("a"), None).await;
+        assert_eq!(letters.len(), 1);
+        assert_eq!((letters[0].url.clone(), letters[0].attempts), (down.clone(), MAX_RETRIES + 1));
+        assert_eq!(letters[0].error, "dedupe failed");
+        let up_domain = MerchantDomain::parse(&up).unwrap();
+        assert!(queue.dead_letters(None, Some(&up_domain)).await.is_empty());
+        assert!(queue.dead_letters(Some("b"), None).await.is_empty());
+
+        let requeued = queue.retry_dead_letter(letters[0].id).await.unwrap();
+        assert_eq!((requeued.urls, requeued.retries), (vec![down], 0));
+        assert!(queue.dead_letters(None, None).await.is_empty());
+        assert!(queue.retry_dead_letter(letters[0].id).await.is_none());
+        assert_eq!(queue.claim_next().await.unwrap().0.id, requeued.id);
+    }
+
+    #[tokio::test]
+    async fn test_sweep_requeues_stalled_jobs() {
+        let clock = Arc::new(MockClock::new());
+        let queue = ScrapeQueue::default().with_clock(clock.clone());
+        let job = queue.submit("a", urls(), JobPriority::Scheduled).await.unwrap();
+        queue.claim_next().await.unwrap();
+
+        clock.advance(Duration::from_secs(59));
+        assert_eq!(queue.sweep(Duration::from_secs(60)).await, 0);
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(queue.sweep(Duration::from_secs(60)).await, 1);
+        assert_eq!(queue.get("a", job.id).await.unwrap().status, JobStatus::Queued);
+    }
+}
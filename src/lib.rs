@@ -0,0 +1,82 @@
+//! DealMate deal service
+//!
+//! Deal ranking, coupon aggregation and the supporting models behind the
+//! DealMate HTTP API. The `deal-service` binary is a thin wrapper around [`cli`],
+//! which serves [`api::router`] over [`app::Services`]; the same building blocks
+//! are available to the browser-extension backend and batch workers.
+//!
+//! # Stability
+//!
+//! The crate follows semver. Items re-exported at the crate root, the
+//! [`coupon_engine`], [`stacksmart`] and [`storage`] modules, and the
+//! [`app`]/[`api`] entry points are the supported public API; breaking changes to
+//! them bump the minor version while the crate is pre-1.0 and are listed in
+//! `CHANGELOG.md`. Other modules are public so workers can reuse them but may
+//! change between minor releases.
+
+pub mod alerts;
+pub mod analytics;
+pub mod api;
+pub mod api_keys;
+pub mod app;
+pub mod auth;
+pub mod backfill;
+pub mod cli;
+pub mod clipping;
+#[cfg(feature = "client")]
+pub mod client;
+pub mod clock;
+pub mod cluster;
+pub mod collections;
+pub mod community;
+pub mod compliance;
+pub mod config;
+pub mod cors;
+pub mod coupon_deltas;
+pub mod coupon_engine;
+pub mod coupon_success;
+pub mod digest;
+pub mod events;
+pub mod experiments;
+pub mod fetch_service;
+pub mod forecast;
+pub mod freshness;
+pub mod health;
+pub mod images;
+pub mod jobs;
+pub mod licensing;
+pub mod localization;
+pub mod models;
+pub mod notifications;
+pub mod onboarding;
+pub mod pricing;
+pub mod privacy;
+pub mod rbac;
+pub mod recommendations;
+pub mod reprocess;
+pub mod reputation;
+pub mod runtimes;
+pub mod sandbox;
+pub mod savings;
+pub mod scoring;
+pub mod search;
+pub mod seeding;
+pub mod server;
+pub mod services;
+pub mod sharing;
+pub mod sla;
+pub mod stacksmart;
+pub mod storage;
+pub mod stream;
+pub mod tagging;
+pub mod telemetry;
+pub mod tenant;
+pub mod top_coupons;
+pub mod widget;
+
+pub use app::{Services, ServicesBuilder};
+pub use coupon_engine::{CouponEngine, CouponEngineBuilder, EngineConfig, RawCoupon};
+pub use models::domain::{CouponCode, Currency, MerchantDomain, Money};
+pub use stacksmart::StackSmartEngine;
+pub use storage::coupon_store::CouponStore;
+pub use storage::deal_store::DealStore;
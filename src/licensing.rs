@@ -0,0 +1,349 @@
+//! Redistribution terms of coupon data
+//!
+//! Coupons come under the terms of the source they were found through: an affiliate
+//! network's T&Cs, a partner agreement, or none for codes scraped from public pages
+//! or submitted by shoppers. A [`SourceRecord`] holds a source's [`License`], the
+//! attribution it requires and where the terms come from, for all of the source's
+//! coupons or one merchant's. Coupons are tagged with their terms when they are
+//! ingested, and responses to API keys leave out coupons whose license the key's
+//! tenant may not receive (see [`crate::tenant::TenantRecord::licenses`]). Requests
+//! without a key are our own apps and receive everything.
+//!
+//! Sources without a record fall back to [`default_terms`]: affiliate and partner
+//! coupons are restricted, scraped and shopper-submitted ones are open. Records are
+//! managed through `/admin/licenses`. With `REDIS_URL` set they are shared and
+//! re-read every [`REFRESH_INTERVAL`]; otherwise they are persisted to
+//! `COUPON_LICENSES_PATH` (default `data/coupon_licenses.json`).
+
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+use utoipa::ToSchema;
+
+use crate::clock::{self, Clock};
+use crate::models::coupon_listing::{CouponListing, CouponSource, License, LicenseTag};
+use crate::models::domain::MerchantDomain;
+use crate::storage::persisted::{PersistedStore, StoreError};
+
+const REDIS_KEY: &str = "coupon_licenses";
+const STORE_NAME: &str = "coupon licenses";
+/// How often shared records are re-read
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct SourceTerms {
+    pub license: License,
+    /// Shown with the source's coupons, e.g. "Coupons provided by Rakuten"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attribution: Option<String>,
+    /// Where the terms come from: the T&Cs URL or the agreement's reference
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agreement: Option<String>,
+}
+
+impl SourceTerms {
+    pub fn tag(&self) -> LicenseTag {
+        LicenseTag {
+            license: self.license,
+            attribution: self.attribution.clone(),
+        }
+    }
+}
+
+/// Terms a source's coupons fall under until a record says otherwise
+pub fn default_terms(source: CouponSource) -> SourceTerms {
+    let license = match source {
+        CouponSource::AffiliateApi | CouponSource::PartnerApi => License::Restricted,
+        CouponSource::WebScraping | CouponSource::UserSubmitted => License::Open,
+    };
+    SourceTerms {
+        license,
+        attribution: None,
+        agreement: None,
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SourceTermsRequest {
+    /// Only this merchant's coupons from the source; all of them when unset
+    #[serde(default)]
+    pub merchant_domain: Option<MerchantDomain>,
+    #[serde(flatten)]
+    pub terms: SourceTerms,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SourceRecord {
+    pub source: CouponSource,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub merchant_domain: Option<MerchantDomain>,
+    #[serde(flatten)]
+    pub terms: SourceTerms,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl SourceRecord {
+    fn covers(&self, source: CouponSource, merchant: Option<&MerchantDomain>) -> bool {
+        self.source == source && self.merchant_domain.as_ref() == merchant
+    }
+}
+
+pub struct SourceLicenses {
+    records: RwLock<Vec<SourceRecord>>,
+    store: PersistedStore<Vec<SourceRecord>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl SourceLicenses {
+    /// Records persisted to `path`, or kept in memory only
+    pub fn new(path: Option<PathBuf>) -> Self {
+        Self::with_store(PersistedStore::new(STORE_NAME, REDIS_KEY, path))
+    }
+
+    /// Records shared through Redis
+    pub fn shared(redis_url: &str) -> Result<Self, StoreError> {
+        Ok(Self::with_store(PersistedStore::shared(STORE_NAME, REDIS_KEY, redis_url)?))
+    }
+
+    fn with_store(store: PersistedStore<Vec<SourceRecord>>) -> Self {
+        Self {
+            records: RwLock::new(Vec::new()),
+            store: store.pretty(),
+            clock: clock::system(),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Share records through `REDIS_URL` when set, otherwise load them from
+    /// `COUPON_LICENSES_PATH` (default `data/coupon_licenses.json`)
+    pub async fn from_env() -> Self {
+        let licenses = Self::with_store(PersistedStore::from_env(STORE_NAME, REDIS_KEY, "COUPON_LICENSES_PATH", "data/coupon_licenses.json"));
+        if let Err(e) = licenses.reload().await {
+            tracing::warn!(error = %e, "Starting with the default coupon licenses");
+        }
+        licenses
+    }
+
+    /// Replace the local copy with the stored records
+    pub async fn reload(&self) -> Result<(), StoreError> {
+        if let Some(stored) = self.store.load().await? {
+            *self.records.write().await = stored;
+        }
+        Ok(())
+    }
+
+    /// Re-read shared records every [`REFRESH_INTERVAL`], picking up changes made
+    /// through other instances
+    pub async fn start_background_tasks(self: Arc<Self>) {
+        self.store.refresh_every(self.clock.as_ref(), REFRESH_INTERVAL, || self.reload()).await
+    }
+
+    /// Apply `change` to the stored records and keep the result; `change` returns
+    /// false to leave them as they were
+    async fn update(&self, change: impl FnOnce(&mut Vec<SourceRecord>) -> bool) -> bool {
+        let _write = self.store.write_lock().await;
+        // Another instance may have changed shared records since the last refresh
+        let mut records = match self.store.load().await {
+            Ok(Some(stored)) => stored,
+            Ok(None) => self.records.read().await.clone(),
+            Err(e) => {
+                tracing::warn!(error = %e, "Failed to read the coupon licenses; changing the local copy");
+                self.records.read().await.clone()
+            }
+        };
+        let changed = change(&mut records);
+        if changed {
+            self.store.persist(&records).await;
+        }
+        *self.records.write().await = records;
+        changed
+    }
+
+    pub async fn records(&self) -> Vec<SourceRecord> {
+        self.records.read().await.clone()
+    }
+
+    /// The terms of `source`'s coupons for `merchant`: the merchant's record, else
+    /// the source's, else the default
+    pub async fn terms(&self, source: CouponSource, merchant: &MerchantDomain) -> SourceTerms {
+        let records = self.records.read().await;
+        records
+            .iter()
+            .find(|record| record.covers(source, Some(merchant)))
+            .or_else(|| records.iter().find(|record| record.covers(source, None)))
+            .map(|record| record.terms.clone())
+            .unwrap_or_else(|| default_terms(source))
+    }
+
+    /// Tag `coupon` with its source's current terms unless it already has a tag
+    pub async fn tag(&self, coupon: &mut CouponListing) {
+        if coupon.license.is_none() {
+            coupon.license = Some(self.terms(coupon.source, &coupon.merchant_domain).await.tag());
+        }
+    }
+
+    /// Tag `coupon` and tell whether a caller that may receive `allowed` (`None`
+    /// for everything) may receive it
+    pub async fn permits(&self, coupon: &mut CouponListing, allowed: Option<&BTreeSet<License>>) -> bool {
+        self.tag(coupon).await;
+        match (allowed, &coupon.license) {
+            (None, _) => true,
+            (Some(allowed), Some(tag)) => allowed.contains(&tag.license),
+            (Some(_), None) => false,
+        }
+    }
+
+    /// Tag `coupons` and keep those a caller that may receive `allowed` may receive
+    pub async fn retain_permitted(&self, coupons: &mut Vec<CouponListing>, allowed: Option<&BTreeSet<License>>) {
+        let mut permitted = Vec::with_capacity(coupons.len());
+        for mut coupon in coupons.drain(..) {
+            if self.permits(&mut coupon, allowed).await {
+                permitted.push(coupon);
+            }
+        }
+        *coupons = permitted;
+    }
+
+    /// Record the terms of `source`'s coupons, or of one merchant's
+    pub async fn set(&self, source: CouponSource, request: SourceTermsRequest) -> Result<SourceRecord, String> {
+        let mut terms = request.terms;
+        terms.attribution = terms.attribution.map(|text| text.trim().to_string()).filter(|text| !text.is_empty());
+        if terms.license == License::Attribution && terms.attribution.is_none() {
+            return Err("attribution licenses need the attribution text".to_string());
+        }
+
+        let record = SourceRecord {
+            source,
+            merchant_domain: request.merchant_domain,
+            terms,
+            updated_at: self.clock.now(),
+        };
+        self.update(|records| {
+            records.retain(|existing| !existing.covers(source, record.merchant_domain.as_ref()));
+            records.push(record.clone());
+            true
+        })
+        .await;
+        Ok(record)
+    }
+
+    /// Drop a record so the coupons fall back to the source's or the default terms;
+    /// false when there is none
+    pub async fn remove(&self, source: CouponSource, merchant: Option<&MerchantDomain>) -> bool {
+        self.update(|records| {
+            let before = records.len();
+            records.retain(|record| !record.covers(source, merchant));
+            records.len() != before
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::domain::CouponCode;
+
+    fn coupon(source: CouponSource, domain: &str) -> CouponListing {
+        CouponListing {
+            code: CouponCode::parse("SAVE10").unwrap(),
+            title: "10% off".to_string(),
+            description: None,
+            locale: None,
+            merchant_domain: MerchantDomain::parse(domain).unwrap(),
+            discount_type: "percentage".to_string(),
+            discount_value: Some(10.0),
+            minimum_order: None,
+            source,
+            license: None,
+            extraction_confidence: 0.9,
+            scraped_at: Utc::now(),
+            valid_until: None,
+            predicted_success: None,
+            tags: Vec::new(),
+        }
+    }
+
+    fn terms(license: License, attribution: Option<&str>) -> SourceTermsRequest {
+        SourceTermsRequest {
+            merchant_domain: None,
+            terms: SourceTerms {
+                license,
+                attribution: attribution.map(str::to_string),
+                agreement: None,
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_coupons_are_tagged_and_filtered_by_their_sources_terms() {
+        let licenses = SourceLicenses::new(None);
+        assert!(licenses.set(CouponSource::AffiliateApi, terms(License::Attribution, Some("  "))).await.is_err());
+        licenses
+            .set(CouponSource::AffiliateApi, terms(License::Attribution, Some("Coupons by Rakuten")))
+            .await
+            .unwrap();
+        let mut shop = terms(License::Restricted, None);
+        shop.merchant_domain = Some(MerchantDomain::parse("shop.example").unwrap());
+        licenses.set(CouponSource::AffiliateApi, shop).await.unwrap();
+
+        let mut coupons = vec![
+            coupon(CouponSource::AffiliateApi, "store.example"),
+            coupon(CouponSource::AffiliateApi, "shop.example"),
+            coupon(CouponSource::PartnerApi, "store.example"),
+            coupon(CouponSource::WebScraping, "store.example"),
+        ];
+        let everything = coupons.clone();
+        licenses
+            .retain_permitted(&mut coupons, Some(&BTreeSet::from(License::REDISTRIBUTABLE)))
+            .await;
+        let kept: Vec<_> = coupons.iter().map(|c| (c.source, c.license.clone().unwrap())).collect();
+        assert_eq!(
+            kept,
+            vec![
+                (
+                    CouponSource::AffiliateApi,
+                    LicenseTag {
+                        license: License::Attribution,
+                        attribution: Some("Coupons by Rakuten".to_string()),
+                    }
+                ),
+                (
+                    CouponSource::WebScraping,
+                    LicenseTag {
+                        license: License::Open,
+                        attribution: None,
+                    }
+                ),
+            ]
+        );
+
+        // Callers without restrictions get every coupon, tagged
+        let mut unrestricted = everything.clone();
+        licenses.retain_permitted(&mut unrestricted, None).await;
+        assert_eq!(unrestricted.len(), 4);
+        assert!(unrestricted.iter().all(|c| c.license.is_some()));
+
+        // A coupon keeps the tag it was ingested under
+        let mut tagged = everything[3].clone();
+        tagged.license = Some(LicenseTag {
+            license: License::Restricted,
+            attribution: None,
+        });
+        assert!(!licenses.permits(&mut tagged, Some(&BTreeSet::from([License::Open]))).await);
+
+        assert!(licenses.remove(CouponSource::AffiliateApi, None).await);
+        assert!(!licenses.remove(CouponSource::AffiliateApi, None).await);
+        let store = MerchantDomain::parse("store.example").unwrap();
+        assert_eq!(licenses.terms(CouponSource::AffiliateApi, &store).await, default_terms(CouponSource::AffiliateApi));
+    }
+}
@@ -0,0 +1,66 @@
+//! Translation through an HTTP API
+//!
+//! Configured by `TRANSLATION_API_URL` and, optionally, `TRANSLATION_API_KEY` (sent as
+//! a bearer token). The service receives `{"texts": [...], "source": "en", "target":
+//! "de"}` and answers `{"translations": [...]}` in the same order.
+
+use std::time::Duration;
+
+use axum::async_trait;
+use serde::Deserialize;
+use serde_json::json;
+
+use super::{Locale, TranslationError, TranslationProvider};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct HttpTranslationProvider {
+    client: reqwest::Client,
+    api_url: String,
+    api_key: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TranslationResponse {
+    translations: Vec<String>,
+}
+
+impl HttpTranslationProvider {
+    pub fn new(api_url: String, api_key: Option<String>) -> Self {
+        Self {
+            client: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+            api_url,
+            api_key,
+        }
+    }
+
+    pub fn from_env() -> Option<Self> {
+        let api_url = std::env::var("TRANSLATION_API_URL").ok()?;
+        Some(Self::new(api_url, std::env::var("TRANSLATION_API_KEY").ok()))
+    }
+}
+
+#[async_trait]
+impl TranslationProvider for HttpTranslationProvider {
+    async fn translate(&self, texts: &[String], source: &Locale, target: &Locale) -> Result<Vec<String>, TranslationError> {
+        let mut request = self.client.post(&self.api_url).json(&json!({
+            "texts": texts,
+            "source": source.as_str(),
+            "target": target.as_str(),
+        }));
+        if let Some(key) = &self.api_key {
+            request = request.bearer_auth(key);
+        }
+
+        let response = request
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| TranslationError(e.to_string()))?;
+        let body: TranslationResponse = response.json().await.map_err(|e| TranslationError(e.to_string()))?;
+        Ok(body.translations)
+    }
+}
@@ -0,0 +1,265 @@
+//! Coupon localization
+//!
+//! Coupons carry the locale their text is written in ([`CouponListing::locale`]; the
+//! default locale when unset). When a request asks for another language through
+//! `Accept-Language`, coupon titles and descriptions are translated by a pluggable
+//! [`TranslationProvider`] before they are served. Translations are cached, so each
+//! distinct text is sent to the provider once per target language.
+//!
+//! [`CouponListing::locale`]: crate::models::coupon_listing::CouponListing::locale
+
+pub mod http;
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::Arc;
+
+use axum::async_trait;
+use tokio::sync::Mutex;
+
+/// Translations kept in the cache; the oldest are evicted first
+const MAX_CACHED: usize = 50_000;
+
+/// A normalized BCP 47 language tag, e.g. `de` or `pt-BR`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Locale(String);
+
+impl Locale {
+    pub fn parse(tag: &str) -> Option<Self> {
+        let mut subtags = tag.trim().split(['-', '_']);
+        let language = subtags.next()?;
+        if !(2..=3).contains(&language.len()) || !language.chars().all(|c| c.is_ascii_alphabetic()) {
+            return None;
+        }
+
+        let mut normalized = language.to_ascii_lowercase();
+        for subtag in subtags {
+            if !(2..=8).contains(&subtag.len()) || !subtag.chars().all(|c| c.is_ascii_alphanumeric()) {
+                return None;
+            }
+            normalized.push('-');
+            // Regions are upper case (`pt-BR`), scripts and variants are left as they are
+            match subtag.len() {
+                2 => normalized.push_str(&subtag.to_ascii_uppercase()),
+                _ => normalized.push_str(subtag),
+            }
+        }
+        Some(Self(normalized))
+    }
+
+    /// The highest-weighted usable locale in an `Accept-Language` header
+    pub fn from_accept_language(header: &str) -> Option<Self> {
+        let mut ranked: Vec<(f32, usize, Locale)> = header
+            .split(',')
+            .enumerate()
+            .filter_map(|(position, entry)| {
+                let mut parts = entry.split(';');
+                let locale = Locale::parse(parts.next()?)?;
+                let weight = parts
+                    .filter_map(|p| p.trim().strip_prefix("q="))
+                    .find_map(|q| q.trim().parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                (weight > 0.0).then_some((weight, position, locale))
+            })
+            .collect();
+        ranked.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)));
+        ranked.into_iter().next().map(|(_, _, locale)| locale)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The primary language subtag, e.g. `pt` for `pt-BR`
+    pub fn language(&self) -> &str {
+        self.0.split('-').next().unwrap_or(&self.0)
+    }
+}
+
+impl fmt::Display for Locale {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TranslationError(pub String);
+
+impl fmt::Display for TranslationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "translation failed: {}", self.0)
+    }
+}
+
+impl std::error::Error for TranslationError {}
+
+/// A machine translation service
+#[async_trait]
+pub trait TranslationProvider: Send + Sync {
+    /// Translate each of `texts` from `source` to `target`, in order
+    async fn translate(&self, texts: &[String], source: &Locale, target: &Locale) -> Result<Vec<String>, TranslationError>;
+}
+
+type CacheKey = (String, String, String);
+
+#[derive(Default)]
+struct TranslationCache {
+    entries: HashMap<CacheKey, String>,
+    order: VecDeque<CacheKey>,
+}
+
+impl TranslationCache {
+    fn insert(&mut self, key: CacheKey, translation: String) {
+        if self.entries.insert(key.clone(), translation).is_none() {
+            self.order.push_back(key);
+        }
+        while self.order.len() > MAX_CACHED {
+            if let Some(oldest) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+    }
+}
+
+pub struct Translator {
+    provider: Option<Arc<dyn TranslationProvider>>,
+    default_locale: Locale,
+    cache: Mutex<TranslationCache>,
+}
+
+impl Translator {
+    /// No provider: text is always served as written
+    pub fn new(default_locale: Locale) -> Self {
+        Self {
+            provider: None,
+            default_locale,
+            cache: Mutex::new(TranslationCache::default()),
+        }
+    }
+
+    pub fn with_provider(mut self, provider: Arc<dyn TranslationProvider>) -> Self {
+        self.provider = Some(provider);
+        self
+    }
+
+    /// Default locale from `DEFAULT_CONTENT_LOCALE` (default `en`); the HTTP provider
+    /// when `TRANSLATION_API_URL` is set
+    pub fn from_env() -> Self {
+        let default_locale = std::env::var("DEFAULT_CONTENT_LOCALE")
+            .ok()
+            .and_then(|tag| Locale::parse(&tag))
+            .unwrap_or_else(|| Locale("en".to_string()));
+
+        let translator = Self::new(default_locale);
+        match http::HttpTranslationProvider::from_env() {
+            Some(provider) => translator.with_provider(Arc::new(provider)),
+            None => translator,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.provider.is_some()
+    }
+
+    /// Locale of text whose locale is not recorded
+    pub fn default_locale(&self) -> &Locale {
+        &self.default_locale
+    }
+
+    /// Whether text in `source` has to be translated for a reader of `target`
+    pub fn needs_translation(&self, source: &Locale, target: &Locale) -> bool {
+        self.is_enabled() && source.language() != target.language()
+    }
+
+    /// `texts` translated from `source` to `target`, in order; cached translations are reused
+    pub async fn translate(&self, texts: &[String], source: &Locale, target: &Locale) -> Result<Vec<String>, TranslationError> {
+        let Some(provider) = &self.provider else {
+            return Ok(texts.to_vec());
+        };
+        if !self.needs_translation(source, target) {
+            return Ok(texts.to_vec());
+        }
+
+        let key = |text: &str| (source.0.clone(), target.0.clone(), text.to_string());
+        let mut translated: Vec<Option<String>> = {
+            let cache = self.cache.lock().await;
+            texts.iter().map(|text| cache.entries.get(&key(text)).cloned()).collect()
+        };
+
+        let mut missing: Vec<String> = texts
+            .iter()
+            .zip(&translated)
+            .filter(|(_, cached)| cached.is_none())
+            .map(|(text, _)| text.clone())
+            .collect();
+        missing.sort();
+        missing.dedup();
+        if !missing.is_empty() {
+            let fresh = provider.translate(&missing, source, target).await?;
+            if fresh.len() != missing.len() {
+                return Err(TranslationError(format!("asked for {} translations, got {}", missing.len(), fresh.len())));
+            }
+
+            let fresh: HashMap<String, String> = missing.into_iter().zip(fresh).collect();
+            let mut cache = self.cache.lock().await;
+            for (text, translation) in &fresh {
+                cache.insert(key(text), translation.clone());
+            }
+            for (text, slot) in texts.iter().zip(translated.iter_mut()) {
+                if slot.is_none() {
+                    *slot = fresh.get(text).cloned();
+                }
+            }
+        }
+
+        Ok(translated.into_iter().zip(texts).map(|(t, text)| t.unwrap_or_else(|| text.clone())).collect())
+    }
+}
+
+impl Default for Translator {
+    fn default() -> Self {
+        Self::new(Locale("en".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct Shouting {
+        calls: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl TranslationProvider for Shouting {
+        async fn translate(&self, texts: &[String], _source: &Locale, target: &Locale) -> Result<Vec<String>, TranslationError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Ok(texts.iter().map(|t| format!("[{}] {}", target, t.to_uppercase())).collect())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_negotiates_locale_and_caches_translations() {
+        let accepted = Locale::from_accept_language("en;q=0.5, pt_br, de;q=0.9, *;q=0.1").unwrap();
+        assert_eq!(accepted.as_str(), "pt-BR");
+        assert_eq!(accepted.language(), "pt");
+        assert_eq!(Locale::from_accept_language("fr;q=0, x"), None);
+
+        let provider = Arc::new(Shouting {
+            calls: AtomicUsize::new(0),
+        });
+        let translator = Translator::default().with_provider(provider.clone());
+        let english = translator.default_locale().clone();
+        let texts = vec!["20% off".to_string(), "Free shipping".to_string(), "20% off".to_string()];
+
+        let first = translator.translate(&texts, &english, &accepted).await.unwrap();
+        assert_eq!(first, vec!["[pt-BR] 20% OFF", "[pt-BR] FREE SHIPPING", "[pt-BR] 20% OFF"]);
+        translator.translate(&texts[..2], &english, &accepted).await.unwrap();
+        assert_eq!(provider.calls.load(Ordering::SeqCst), 1);
+
+        // Same language, other region: served as written
+        let british = Locale::parse("en-gb").unwrap();
+        assert_eq!(translator.translate(&texts, &english, &british).await.unwrap(), texts);
+    }
+}
@@ -0,0 +1,31 @@
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertType {
+    /// Fire when the price falls to or below `target_price`
+    TargetPrice,
+    /// Fire when the discount reaches `min_discount`
+    DiscountThreshold,
+    /// Fire on any new deal for the product
+    AnyDeal,
+}
+
+/// A user's standing request to be notified about a product's deals
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct DealAlert {
+    pub id: Uuid,
+    pub user_id: String,
+    pub product_name: String,
+    /// In whatever currency the user wrote it in
+    pub target_price: Option<Decimal>,
+    pub min_discount: Option<f64>,
+    pub platforms: Vec<String>,
+    pub alert_type: AlertType,
+    pub created_at: DateTime<Utc>,
+    pub last_triggered: Option<DateTime<Utc>>,
+}
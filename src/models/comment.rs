@@ -0,0 +1,15 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// A comment from a deal-community thread (e.g. Slickdeals) about one of our deals
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CommunityComment {
+    pub deal_id: String,
+    pub source: String,
+    pub thread_url: Option<String>,
+    pub author: Option<String>,
+    pub body: String,
+    #[serde(default = "Utc::now")]
+    pub posted_at: DateTime<Utc>,
+}
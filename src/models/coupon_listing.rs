@@ -0,0 +1,84 @@
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::domain::{CouponCode, MerchantDomain};
+
+/// Where a coupon code was found
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CouponSource {
+    AffiliateApi,
+    PartnerApi,
+    WebScraping,
+    UserSubmitted,
+}
+
+/// What may be done with a coupon under its source's terms (see [`crate::licensing`])
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum License {
+    /// May be passed on freely
+    Open,
+    /// May be passed on if the source's attribution is shown with it
+    Attribution,
+    /// Only for our own apps and the tenants whose agreements cover it
+    Restricted,
+}
+
+impl License {
+    /// What API keys receive when their tenant's record names no licenses
+    pub const REDISTRIBUTABLE: [License; 2] = [License::Open, License::Attribution];
+}
+
+/// The license a coupon was ingested under
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct LicenseTag {
+    pub license: License,
+    /// To be shown wherever the coupon is
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attribution: Option<String>,
+}
+
+/// A coupon code as served by the `/coupons` endpoints
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CouponListing {
+    pub code: CouponCode,
+    pub title: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// BCP 47 tag of the language `title` and `description` are written in; the
+    /// default content locale when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
+    pub merchant_domain: MerchantDomain,
+    /// `percentage`, `fixed`, `free_shipping`, ...
+    pub discount_type: String,
+    pub discount_value: Option<f64>,
+    /// Smallest order total the code applies to, in the merchant's currency
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub minimum_order: Option<Decimal>,
+    pub source: CouponSource,
+    /// The source's terms when the coupon was ingested; untagged coupons fall under
+    /// the source's current terms
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub license: Option<LicenseTag>,
+    /// How sure the extractor was that `code` is really a code (0.0 - 1.0)
+    #[serde(default = "default_extraction_confidence")]
+    pub extraction_confidence: f64,
+    pub scraped_at: DateTime<Utc>,
+    /// When the code stops working, if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub valid_until: Option<DateTime<Utc>>,
+    /// Probability that the code works, from the coupon success model
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub predicted_success: Option<f64>,
+    /// Curation tags, from editors and the tag vocabulary's keywords (see [`crate::tagging`])
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+fn default_extraction_confidence() -> f64 {
+    0.7
+}
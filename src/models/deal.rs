@@ -0,0 +1,78 @@
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::domain::{MerchantDomain, Money};
+
+/// A product deal as served by the `/deals` endpoints
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Deal {
+    pub id: String,
+    pub product_id: String,
+    pub title: String,
+    pub store: String,
+    pub merchant_domain: MerchantDomain,
+    pub category: String,
+    pub brand: Option<String>,
+    pub price: Money,
+    pub original_price: Money,
+    /// Advertised discount percentage
+    pub discount: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_url: Option<String>,
+    /// The product page, loaded to verify the price of high-value deals
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub product_url: Option<String>,
+    /// Perceptual hash of the product image (hex), set by the image pipeline
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_hash: Option<String>,
+    #[serde(default)]
+    pub free_shipping: bool,
+    pub posted_at: DateTime<Utc>,
+    /// Engagement score assigned by the scoring pipeline (0.0 - 1.0)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub score: Option<f64>,
+    /// Discount percentage against the product's typical selling price
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub honest_discount: Option<f64>,
+    /// Set when the claimed original price is not supported by price history
+    #[serde(default)]
+    pub discount_inflated: bool,
+    /// When the product page was last checked for the advertised price and discount
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verified_at: Option<DateTime<Utc>>,
+    /// Set when the product page did not show the advertised price or discount
+    #[serde(default)]
+    pub price_mismatch: bool,
+    #[serde(default)]
+    pub status: DealStatus,
+    /// Confidence in `status`, derived from community signals
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status_confidence: Option<f64>,
+    /// Shopping events (Black Friday, Prime Day, ...) currently featuring this deal
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub events: Vec<String>,
+    /// Curation tags, from editors and the tag vocabulary's keywords (see [`crate::tagging`])
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DealStatus {
+    #[default]
+    Active,
+    /// Deal ended or the price is no longer available
+    Dead,
+    OutOfStock,
+    PriceIncreased,
+}
+
+/// A single observed price for a product
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PricePoint {
+    /// In the currency of the product's deals
+    pub price: Decimal,
+    pub observed_at: DateTime<Utc>,
+}
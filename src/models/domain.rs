@@ -0,0 +1,263 @@
+//! Validated value types shared by deals, coupons and the API
+//!
+//! Each type normalizes its input once, when it is constructed or deserialized, so
+//! the rest of the crate can compare and key on it without re-trimming or
+//! lowercasing. Money is a decimal amount tagged with its currency instead of a
+//! bare `f64`.
+
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// A coupon code as entered at checkout.
+///
+/// The code keeps its original case (lowercase often means a scraped label rather
+/// than a real code), but equality and hashing ignore case because merchants do.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(try_from = "String", into = "String")]
+#[schema(value_type = String, example = "SAVE20")]
+pub struct CouponCode(String);
+
+impl CouponCode {
+    pub const MAX_LEN: usize = 50;
+
+    /// Trim and check a code: non-empty, at most `MAX_LEN` characters, no whitespace
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        let code = raw.trim();
+        if code.is_empty() {
+            return Err("coupon code must not be empty".to_string());
+        }
+        if code.chars().count() > Self::MAX_LEN {
+            return Err(format!("coupon code longer than {} characters", Self::MAX_LEN));
+        }
+        if code.chars().any(|c| c.is_whitespace() || c.is_control()) {
+            return Err(format!("coupon code '{}' contains whitespace", code));
+        }
+        Ok(Self(code.to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq for CouponCode {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.eq_ignore_ascii_case(&other.0)
+    }
+}
+
+impl Eq for CouponCode {}
+
+impl Hash for CouponCode {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.to_ascii_uppercase().hash(state);
+    }
+}
+
+impl fmt::Display for CouponCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl TryFrom<String> for CouponCode {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::parse(&value)
+    }
+}
+
+impl From<CouponCode> for String {
+    fn from(code: CouponCode) -> Self {
+        code.0
+    }
+}
+
+/// A merchant's registrable host name, e.g. `amazon.com`.
+///
+/// Parsing lowercases the input and strips a scheme, `www.`, port, path and
+/// trailing dot, so `https://www.Amazon.com/deals` and `amazon.com` are the same
+/// merchant.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize, ToSchema)]
+#[serde(try_from = "String", into = "String")]
+#[schema(value_type = String, example = "amazon.com")]
+pub struct MerchantDomain(String);
+
+impl MerchantDomain {
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        let mut host = raw.trim().to_ascii_lowercase();
+        if let Some((_, rest)) = host.split_once("://") {
+            host = rest.to_string();
+        }
+        if let Some(end) = host.find(['/', '?', '#']) {
+            host.truncate(end);
+        }
+        if let Some(end) = host.find(':') {
+            host.truncate(end);
+        }
+        let host = host.trim_end_matches('.');
+        let host = host.strip_prefix("www.").unwrap_or(host);
+
+        let valid_label = |label: &str| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        };
+        if host.len() > 253 || !host.contains('.') || !host.split('.').all(valid_label) {
+            return Err(format!("'{}' is not a valid merchant domain", raw.trim()));
+        }
+        Ok(Self(host.to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for MerchantDomain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl TryFrom<String> for MerchantDomain {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::parse(&value)
+    }
+}
+
+impl From<MerchantDomain> for String {
+    fn from(domain: MerchantDomain) -> Self {
+        domain.0
+    }
+}
+
+/// ISO 4217 currency code
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(try_from = "String", into = "String")]
+#[schema(value_type = String, example = "USD")]
+pub struct Currency([u8; 3]);
+
+impl Currency {
+    pub const USD: Currency = Currency(*b"USD");
+
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        let code = raw.trim().to_ascii_uppercase();
+        match <[u8; 3]>::try_from(code.as_bytes()) {
+            Ok(bytes) if bytes.iter().all(u8::is_ascii_uppercase) => Ok(Self(bytes)),
+            _ => Err(format!("'{}' is not an ISO 4217 currency code", raw.trim())),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Only ASCII letters get past `parse`
+        std::str::from_utf8(&self.0).unwrap_or("???")
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl TryFrom<String> for Currency {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::parse(&value)
+    }
+}
+
+impl From<Currency> for String {
+    fn from(currency: Currency) -> Self {
+        currency.as_str().to_string()
+    }
+}
+
+/// An amount of money; serialized as `{"amount": "499.99", "currency": "USD"}`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+pub struct Money {
+    pub amount: Decimal,
+    pub currency: Currency,
+}
+
+impl Money {
+    pub fn new(amount: Decimal, currency: Currency) -> Self {
+        Self { amount, currency }
+    }
+
+    pub fn usd(amount: Decimal) -> Self {
+        Self::new(amount, Currency::USD)
+    }
+
+    /// Percentage by which `self` is below `original`, rounded to two places.
+    ///
+    /// `None` when the currencies differ or `original` is not positive.
+    pub fn percent_off(self, original: Money) -> Option<Decimal> {
+        if self.currency != original.currency || original.amount <= Decimal::ZERO {
+            return None;
+        }
+        Some(((original.amount - self.amount) / original.amount * Decimal::ONE_HUNDRED).round_dp(2))
+    }
+
+    /// Lossy conversion for statistics and model features; never use it for arithmetic on prices
+    pub fn to_f64(self) -> f64 {
+        self.amount.to_f64().unwrap_or(0.0)
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {:.2}", self.currency, self.amount)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_merchant_domain_normalizes() {
+        let domain = MerchantDomain::parse(" https://www.Amazon.com:443/deals?id=1 ").unwrap();
+
+        assert_eq!(domain.as_str(), "amazon.com");
+        assert_eq!(domain, MerchantDomain::parse("AMAZON.COM.").unwrap());
+        assert!(MerchantDomain::parse("localhost").is_err());
+        assert!(MerchantDomain::parse("-bad.com").is_err());
+    }
+
+    #[test]
+    fn test_coupon_code_compares_case_insensitively() {
+        let code = CouponCode::parse(" save20 ").unwrap();
+
+        assert_eq!(code.as_str(), "save20");
+        assert_eq!(code, CouponCode::parse("SAVE20").unwrap());
+        assert!(CouponCode::parse("see site").is_err());
+        assert!(CouponCode::parse("").is_err());
+    }
+
+    #[test]
+    fn test_money_percent_off_is_exact() {
+        let price = Money::usd(dec!(0.70));
+        let original = Money::usd(dec!(1.00));
+
+        assert_eq!(price.percent_off(original), Some(dec!(30)));
+        assert_eq!(price.percent_off(Money::new(dec!(1.00), Currency::parse("eur").unwrap())), None);
+        assert_eq!(
+            serde_json::to_value(price).unwrap(),
+            serde_json::json!({"amount": "0.70", "currency": "USD"})
+        );
+    }
+}
@@ -0,0 +1,9 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// The experiment variant a request was served with
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
+pub struct ExperimentAssignment {
+    pub experiment_id: String,
+    pub variant: String,
+}
@@ -0,0 +1,27 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::experiment::ExperimentAssignment;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum InteractionKind {
+    View,
+    Click,
+    AddToCart,
+    Purchase,
+}
+
+/// A user/session engaging with a deal
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Interaction {
+    pub session_id: String,
+    pub deal_id: String,
+    pub kind: InteractionKind,
+    #[serde(default = "Utc::now")]
+    pub occurred_at: DateTime<Utc>,
+    /// Ranking variant the deal was shown under; echoed back from the deals response
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub experiment: Option<ExperimentAssignment>,
+}
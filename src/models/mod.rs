@@ -0,0 +1,8 @@
+pub mod alert;
+pub mod comment;
+pub mod coupon_listing;
+pub mod deal;
+pub mod domain;
+pub mod experiment;
+pub mod interaction;
+pub mod url;
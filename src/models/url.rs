@@ -0,0 +1,166 @@
+//! Page URL canonicalization
+//!
+//! The same merchant page is linked under many spellings: `?utm_source=…` and other
+//! tracking parameters, parameters in a different order, an upper-case host, a
+//! `#fragment`, an explicit default port. [`normalize`] maps them all to one string,
+//! which the crawler, the scrape job queue, the fetch cache and the snapshot archive
+//! use as the page's key.
+//!
+//! Pages may also name their preferred URL with `<link rel="canonical">`;
+//! [`CanonicalUrls`] remembers those so the next crawl of a variant goes straight to
+//! the canonical page.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use deal_service_macros::selector;
+use scraper::Html;
+use url::Url;
+
+use crate::models::domain::MerchantDomain;
+
+/// Query parameters that only identify the campaign or click, never the page
+const TRACKING_PARAMS: &[&str] = &[
+    "gclid", "gbraid", "wbraid", "dclid", "fbclid", "msclkid", "yclid", "igshid", "twclid", "ttclid", "mc_cid",
+    "mc_eid", "_ga", "_gl", "_hsenc", "_hsmi", "mkt_tok", "ref_src", "srsltid", "spm",
+];
+/// Prefixes of tracking parameter families such as `utm_source`
+const TRACKING_PREFIXES: &[&str] = &["utm_", "pk_", "hsa_"];
+/// Variant to canonical URLs remembered before the map starts over
+const MAX_ALIASES: usize = 50_000;
+
+/// The canonical form of an absolute http(s) URL: lower-case scheme and host, no
+/// default port, fragment or tracking parameters, remaining parameters sorted by name
+pub fn normalize(raw: &str) -> Result<String, String> {
+    let url = Url::parse(raw.trim()).map_err(|e| format!("invalid URL '{}': {}", raw.trim(), e))?;
+    canonicalize(url)
+}
+
+/// Resolve `href` (absolute or relative) against the page at `base` and normalize it
+pub fn resolve(base: &str, href: &str) -> Result<String, String> {
+    let base = Url::parse(base.trim()).map_err(|e| format!("invalid URL '{}': {}", base.trim(), e))?;
+    let url = base.join(href.trim()).map_err(|e| format!("invalid URL '{}': {}", href.trim(), e))?;
+    canonicalize(url)
+}
+
+fn canonicalize(mut url: Url) -> Result<String, String> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(format!("'{}' is not an http(s) URL", url));
+    }
+    if let Some(host) = url.host_str().filter(|host| host.ends_with('.')) {
+        let host = host.trim_end_matches('.').to_string();
+        url.set_host(Some(&host)).map_err(|e| format!("invalid host '{}': {}", host, e))?;
+    }
+    url.set_fragment(None);
+
+    let mut params: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(name, _)| !is_tracking(name))
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .collect();
+    // Stable, so repeated parameters keep their relative order
+    params.sort_by(|a, b| a.0.cmp(&b.0));
+    match params.is_empty() {
+        true => url.set_query(None),
+        false => {
+            url.query_pairs_mut().clear().extend_pairs(params);
+        }
+    }
+    Ok(url.into())
+}
+
+fn is_tracking(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    TRACKING_PARAMS.contains(&name.as_str()) || TRACKING_PREFIXES.iter().any(|prefix| name.starts_with(prefix))
+}
+
+/// The normalized `<link rel="canonical">` of the page at `page_url`, when it names
+/// a page of the same merchant
+pub fn canonical_link(page: &str, page_url: &str) -> Option<String> {
+    let document = Html::parse_document(page);
+    let href = document
+        .select(selector!("link[rel][href]"))
+        .find(|link| {
+            link.value()
+                .attr("rel")
+                .is_some_and(|rel| rel.split_ascii_whitespace().any(|r| r.eq_ignore_ascii_case("canonical")))
+        })?
+        .value()
+        .attr("href")?;
+
+    let canonical = resolve(page_url, href).ok()?;
+    // A page cannot move its coupons to another merchant
+    let same_merchant = MerchantDomain::parse(&canonical).ok()? == MerchantDomain::parse(page_url).ok()?;
+    same_merchant.then_some(canonical)
+}
+
+/// Canonical URLs learned from the pages crawled so far
+#[derive(Default)]
+pub struct CanonicalUrls {
+    /// Normalized variant to the canonical URL its page named
+    aliases: Mutex<HashMap<String, String>>,
+}
+
+impl CanonicalUrls {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The URL to crawl for `raw`: normalized, then replaced by its page's canonical
+    /// URL when one has been seen. URLs that do not parse are returned as they are.
+    pub fn resolve(&self, raw: &str) -> String {
+        let Ok(url) = normalize(raw) else {
+            return raw.to_string();
+        };
+        let aliases = self.aliases.lock().unwrap();
+        aliases.get(&url).cloned().unwrap_or(url)
+    }
+
+    /// The key of the page fetched from `url`: its canonical link when it has one,
+    /// which is remembered for `url`, otherwise `url` itself
+    pub fn learn(&self, url: &str, page: &str) -> String {
+        let Some(canonical) = canonical_link(page, url).filter(|canonical| canonical != url) else {
+            return url.to_string();
+        };
+        let mut aliases = self.aliases.lock().unwrap();
+        if aliases.len() >= MAX_ALIASES {
+            aliases.clear();
+        }
+        aliases.insert(url.to_string(), canonical.clone());
+        canonical
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_variants_normalize_to_one_url() {
+        let variants = [
+            "https://Shop.Example.com/deals?b=2&a=1",
+            "HTTPS://shop.example.com:443/deals?a=1&b=2#top",
+            "https://shop.example.com./deals?utm_source=mail&a=1&gclid=xyz&b=2",
+            "https://shop.example.com/deals?a=1&UTM_Campaign=spring&b=2&fbclid=1",
+        ];
+        for variant in variants {
+            assert_eq!(normalize(variant).unwrap(), "https://shop.example.com/deals?a=1&b=2");
+        }
+        assert_eq!(normalize("https://shop.example.com?utm_medium=x").unwrap(), "https://shop.example.com/");
+        assert_eq!(resolve("https://shop.example.com/deals/shoes?x=1", "../coupons?utm_source=a").unwrap(), "https://shop.example.com/coupons");
+        assert!(normalize("ftp://shop.example.com/deals").is_err());
+    }
+
+    #[test]
+    fn test_learns_canonical_links_of_the_same_merchant() {
+        let canonicals = CanonicalUrls::new();
+        let variant = canonicals.resolve("https://www.shop.example.com/deals?sort=new&utm_source=mail");
+        let page = "<html><head><link rel='canonical' href='/deals'></head></html>";
+
+        assert_eq!(canonicals.learn(&variant, page), "https://www.shop.example.com/deals");
+        assert_eq!(canonicals.resolve("https://WWW.shop.example.com/deals?utm_source=feed&sort=new"), "https://www.shop.example.com/deals");
+
+        let elsewhere = "<html><head><link rel='canonical' href='https://other.example.net/deals'></head></html>";
+        assert_eq!(canonicals.learn("https://shop.example.com/sale", elsewhere), "https://shop.example.com/sale");
+    }
+}
@@ -0,0 +1,432 @@
+//! Per-user notification preferences and the dispatch check that enforces them
+//!
+//! Each user picks the channels they can be reached on, quiet hours, a cap on
+//! notifications per day and the deal categories they want to hear about. Before
+//! sending anything, a sender asks [`NotificationDispatcher::admit`], which
+//! answers with a [`Delivery`]: send now, hold until quiet hours end, or drop.
+//! Only sends count towards the daily cap. Days and quiet hours are in the user's
+//! UTC offset.
+//!
+//! Users who never set preferences get [`NotificationPreferences::default`], which
+//! already caps them at [`DEFAULT_MAX_PER_DAY`]. With `REDIS_URL` set,
+//! preferences are shared and re-read every [`REFRESH_INTERVAL`]; otherwise they
+//! are persisted to a JSON file. The daily counts are then kept in Redis too, so a
+//! user's cap holds across instances; without it they are kept in memory.
+//!
+//! Every sender hands its rendered [`Message`]s to [`NotificationDispatcher::send`],
+//! which checks them the same way and, when admitted, queues them in the
+//! recipient's outbox for the channel senders. Shared channels are recipients like
+//! users, with preferences of their own. The last [`OUTBOX_CAPACITY`] messages per
+//! recipient are kept in memory.
+
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, NaiveDate, NaiveTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, RwLock};
+use utoipa::ToSchema;
+
+use crate::clock::{self, Clock};
+use crate::storage::persisted::{PersistedStore, StoreError};
+
+const REDIS_KEY: &str = "notification_preferences";
+const STORE_NAME: &str = "notification preferences";
+/// Prefix of the Redis counters of sends per user and local day
+const SENT_KEY: &str = "notifications_sent";
+/// How long a day's counter is kept; longer than any day in any UTC offset
+const SENT_TTL_SECONDS: i64 = 2 * 24 * 3600;
+/// How often shared preferences are re-read
+pub const REFRESH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Notifications a day for users who have not set `max_per_day`
+pub const DEFAULT_MAX_PER_DAY: u32 = 10;
+const MAX_UTC_OFFSET_MINUTES: i32 = 14 * 60;
+/// Messages kept per recipient before the oldest are dropped
+pub const OUTBOX_CAPACITY: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Channel {
+    Push,
+    Email,
+    Sms,
+    InApp,
+}
+
+/// Local times between which nothing is sent; `start` after `end` spans midnight
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct QuietHours {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl QuietHours {
+    fn contains(&self, local: NaiveTime) -> bool {
+        match self.start < self.end {
+            true => self.start <= local && local < self.end,
+            false => local >= self.start || local < self.end,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct NotificationPreferences {
+    #[serde(default = "default_channels")]
+    pub channels: Vec<Channel>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quiet_hours: Option<QuietHours>,
+    /// Offset of the user's local time, for quiet hours and where a day starts
+    #[serde(default)]
+    pub utc_offset_minutes: i32,
+    #[serde(default = "default_max_per_day")]
+    pub max_per_day: u32,
+    /// Deal categories to be notified about; empty for all
+    #[serde(default)]
+    pub categories: Vec<String>,
+    /// Unset until the user first saves preferences
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+fn default_channels() -> Vec<Channel> {
+    vec![Channel::Push, Channel::Email]
+}
+
+fn default_max_per_day() -> u32 {
+    DEFAULT_MAX_PER_DAY
+}
+
+impl Default for NotificationPreferences {
+    fn default() -> Self {
+        Self {
+            channels: default_channels(),
+            quiet_hours: None,
+            utc_offset_minutes: 0,
+            max_per_day: DEFAULT_MAX_PER_DAY,
+            categories: Vec::new(),
+            updated_at: None,
+        }
+    }
+}
+
+impl NotificationPreferences {
+    fn validate(&self) -> Result<(), String> {
+        if self.utc_offset_minutes.abs() > MAX_UTC_OFFSET_MINUTES {
+            return Err("utc_offset_minutes must be within 14 hours of UTC".to_string());
+        }
+        if self.quiet_hours.is_some_and(|quiet| quiet.start == quiet.end) {
+            return Err("quiet hours must not start and end at the same time".to_string());
+        }
+        Ok(())
+    }
+
+    fn local(&self, at: DateTime<Utc>) -> chrono::NaiveDateTime {
+        at.naive_utc() + Duration::minutes(i64::from(self.utc_offset_minutes))
+    }
+
+    /// When the quiet hours around `at` end, if `at` is in them
+    pub fn quiet_until(&self, at: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let quiet = self.quiet_hours?;
+        let local = self.local(at);
+        if !quiet.contains(local.time()) {
+            return None;
+        }
+        let mut end = local.date().and_time(quiet.end);
+        if end <= local {
+            end += Duration::days(1);
+        }
+        Some(at + (end - local))
+    }
+}
+
+/// What a sender wants to deliver
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Notification {
+    pub channel: Channel,
+    /// Deal category the notification is about, if any
+    #[serde(default)]
+    pub category: Option<String>,
+}
+
+/// A rendered notification, ready for its channel's sender
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Message {
+    #[serde(flatten)]
+    pub notification: Notification,
+    pub subject: String,
+    /// For channels that render markup, such as email
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub html: Option<String>,
+    pub text: String,
+    pub queued_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DropReason {
+    ChannelOff,
+    CategoryOff,
+    DailyLimit,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "delivery", rename_all = "snake_case")]
+pub enum Delivery {
+    Send,
+    /// In quiet hours; ask again at `until`
+    Hold { until: DateTime<Utc> },
+    Drop { reason: DropReason },
+}
+
+pub struct NotificationDispatcher {
+    preferences: Arc<RwLock<HashMap<String, NotificationPreferences>>>,
+    /// Sends per user on their current local day, when not counted in Redis
+    sent: Mutex<HashMap<String, (NaiveDate, u32)>>,
+    outbox: Mutex<HashMap<String, VecDeque<Message>>>,
+    /// Every user's preferences in the file, one field of the Redis hash each
+    store: PersistedStore<HashMap<String, NotificationPreferences>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl NotificationDispatcher {
+    /// Preferences persisted to `path`, or kept in memory only
+    pub fn new(path: Option<PathBuf>) -> Self {
+        Self::with_store(PersistedStore::new(STORE_NAME, REDIS_KEY, path))
+    }
+
+    /// Preferences shared through Redis
+    pub fn shared(redis_url: &str) -> Result<Self, StoreError> {
+        Ok(Self::with_store(PersistedStore::shared(STORE_NAME, REDIS_KEY, redis_url)?))
+    }
+
+    fn with_store(store: PersistedStore<HashMap<String, NotificationPreferences>>) -> Self {
+        Self {
+            preferences: Arc::new(RwLock::new(HashMap::new())),
+            sent: Mutex::new(HashMap::new()),
+            outbox: Mutex::new(HashMap::new()),
+            store,
+            clock: clock::system(),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Share preferences through `REDIS_URL` when set, otherwise load them from
+    /// `NOTIFICATION_PREFERENCES_PATH` (default `data/notification_preferences.json`)
+    pub async fn from_env() -> Self {
+        let store = PersistedStore::from_env(STORE_NAME, REDIS_KEY, "NOTIFICATION_PREFERENCES_PATH", "data/notification_preferences.json");
+        let dispatcher = Self::with_store(store);
+        if let Err(e) = dispatcher.reload().await {
+            tracing::warn!(error = %e, "Starting without notification preferences");
+        }
+        dispatcher
+    }
+
+    /// Replace the local copy with every stored user's preferences
+    pub async fn reload(&self) -> Result<(), StoreError> {
+        if let Some(stored) = self.store.load_map(REDIS_KEY).await? {
+            *self.preferences.write().await = stored;
+        }
+        Ok(())
+    }
+
+    /// Re-read shared preferences every [`REFRESH_INTERVAL`], picking up changes
+    /// made through other instances
+    pub async fn start_background_tasks(self: Arc<Self>) {
+        self.store.refresh_every(self.clock.as_ref(), REFRESH_INTERVAL, || self.reload()).await
+    }
+
+    /// `user_id`'s preferences, or the defaults if they never set any
+    pub async fn preferences(&self, user_id: &str) -> NotificationPreferences {
+        self.preferences.read().await.get(user_id).cloned().unwrap_or_default()
+    }
+
+    pub async fn set_preferences(
+        &self,
+        user_id: &str,
+        mut preferences: NotificationPreferences,
+    ) -> Result<NotificationPreferences, String> {
+        if user_id.trim().is_empty() {
+            return Err("user id must not be empty".to_string());
+        }
+        preferences.validate()?;
+        preferences.categories = preferences
+            .categories
+            .iter()
+            .map(|category| category.trim().to_lowercase())
+            .filter(|category| !category.is_empty())
+            .collect();
+        preferences.categories.sort();
+        preferences.categories.dedup();
+        preferences.updated_at = Some(self.clock.now());
+
+        let mut stored = self.preferences.write().await;
+        stored.insert(user_id.to_string(), preferences.clone());
+        self.store.persist_field(REDIS_KEY, user_id, Some(&preferences), || stored.clone()).await;
+        Ok(preferences)
+    }
+
+    /// Whether `notification` may go to `user_id` now; a [`Delivery::Send`] counts
+    /// towards their daily cap
+    pub async fn admit(&self, user_id: &str, notification: &Notification) -> Delivery {
+        let preferences = self.preferences(user_id).await;
+        if !preferences.channels.contains(&notification.channel) {
+            return Delivery::Drop { reason: DropReason::ChannelOff };
+        }
+        let category = notification.category.as_deref().map(|category| category.trim().to_lowercase());
+        if !preferences.categories.is_empty() && category.is_some_and(|category| !preferences.categories.contains(&category)) {
+            return Delivery::Drop { reason: DropReason::CategoryOff };
+        }
+
+        let now = self.clock.now();
+        if let Some(until) = preferences.quiet_until(now) {
+            return Delivery::Hold { until };
+        }
+
+        let today = preferences.local(now).date();
+        if let Some(admitted) = self.count_shared(user_id, today, preferences.max_per_day) {
+            return match admitted {
+                true => Delivery::Send,
+                false => Delivery::Drop { reason: DropReason::DailyLimit },
+            };
+        }
+        let mut sent = self.sent.lock().await;
+        let count = sent.entry(user_id.to_string()).or_insert((today, 0));
+        if count.0 != today {
+            *count = (today, 0);
+        }
+        if count.1 >= preferences.max_per_day {
+            return Delivery::Drop { reason: DropReason::DailyLimit };
+        }
+        count.1 += 1;
+        Delivery::Send
+    }
+
+    /// Count a send towards `user_id`'s cap for `day` in Redis; whether it is within
+    /// `max_per_day`, or `None` when the counts are not shared or Redis failed
+    fn count_shared(&self, user_id: &str, day: NaiveDate, max_per_day: u32) -> Option<bool> {
+        let client = self.store.redis()?;
+        let key = format!("{}:{}:{}", SENT_KEY, user_id, day);
+        let counted = (|| {
+            let mut con = client.get_connection()?;
+            let (count,): (u32,) = redis::pipe().incr(&key, 1).expire(&key, SENT_TTL_SECONDS).ignore().query(&mut con)?;
+            if count > max_per_day {
+                redis::cmd("DECR").arg(&key).query::<()>(&mut con)?;
+            }
+            Ok::<_, redis::RedisError>(count <= max_per_day)
+        })();
+        match counted {
+            Ok(admitted) => Some(admitted),
+            Err(e) => {
+                tracing::warn!(user_id, error = %e, "Failed to count the send in Redis; counting it locally");
+                None
+            }
+        }
+    }
+
+    /// Queue `message` for `recipient`, a user or shared channel, if their
+    /// preferences [`admit`](Self::admit) it
+    pub async fn send(&self, recipient: &str, mut message: Message) -> Delivery {
+        let delivery = self.admit(recipient, &message.notification).await;
+        if delivery == Delivery::Send {
+            message.queued_at = self.clock.now();
+            self.queue(recipient, message).await;
+        }
+        delivery
+    }
+
+    async fn queue(&self, recipient: &str, message: Message) {
+        let mut outbox = self.outbox.lock().await;
+        let queue = outbox.entry(recipient.to_string()).or_default();
+        queue.push_back(message);
+        while queue.len() > OUTBOX_CAPACITY {
+            queue.pop_front();
+        }
+    }
+
+    /// Messages queued for `recipient`, oldest first
+    pub async fn outbox(&self, recipient: &str) -> Vec<Message> {
+        self.outbox
+            .lock()
+            .await
+            .get(recipient)
+            .map(|queue| queue.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    fn email(category: Option<&str>) -> Notification {
+        Notification {
+            channel: Channel::Email,
+            category: category.map(str::to_string),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dispatcher_enforces_channels_categories_quiet_hours_and_daily_cap() {
+        let clock = Arc::new(MockClock::new());
+        let dispatcher = NotificationDispatcher::new(None).with_clock(clock.clone());
+        assert_eq!(dispatcher.preferences("ana").await.max_per_day, DEFAULT_MAX_PER_DAY);
+
+        // Quiet 22:00-07:00 in UTC-5, starting 04:00 local
+        let local = clock.now().naive_utc() - Duration::hours(5);
+        let mut four = local.date().and_hms_opt(4, 0, 0).unwrap();
+        if four <= local {
+            four += Duration::days(1);
+        }
+        clock.advance((four - local).to_std().unwrap());
+        let preferences: NotificationPreferences = serde_json::from_value(serde_json::json!({
+            "channels": ["email"],
+            "quiet_hours": {"start": "22:00:00", "end": "07:00:00"},
+            "utc_offset_minutes": -300,
+            "max_per_day": 2,
+            "categories": [" Electronics", "home", "home"]
+        }))
+        .unwrap();
+        let mut invalid = preferences.clone();
+        invalid.utc_offset_minutes = 15 * 60;
+        assert!(dispatcher.set_preferences("ana", invalid).await.is_err());
+        let saved = dispatcher.set_preferences("ana", preferences).await.unwrap();
+        assert_eq!(saved.categories, vec!["electronics", "home"]);
+
+        let push = Notification { channel: Channel::Push, category: None };
+        assert_eq!(dispatcher.admit("ana", &push).await, Delivery::Drop { reason: DropReason::ChannelOff });
+        assert_eq!(dispatcher.admit("ana", &email(Some("toys"))).await, Delivery::Drop { reason: DropReason::CategoryOff });
+        let until = clock.now() + Duration::hours(3);
+        assert_eq!(dispatcher.admit("ana", &email(Some("home"))).await, Delivery::Hold { until });
+
+        // After quiet hours: two sends, then the cap until the local day turns
+        clock.advance(std::time::Duration::from_secs(3 * 3600));
+        assert_eq!(dispatcher.admit("ana", &email(Some("Electronics"))).await, Delivery::Send);
+        assert_eq!(dispatcher.admit("ana", &email(None)).await, Delivery::Send);
+        assert_eq!(dispatcher.admit("ana", &email(None)).await, Delivery::Drop { reason: DropReason::DailyLimit });
+        clock.advance(std::time::Duration::from_secs(24 * 3600));
+        assert_eq!(dispatcher.admit("ana", &email(None)).await, Delivery::Send);
+        assert_eq!(dispatcher.admit("ben", &push).await, Delivery::Send);
+
+        // Shared channels are capped by their own preferences too
+        let channel = NotificationPreferences { max_per_day: 1, ..Default::default() };
+        dispatcher.set_preferences("channel:kitchen", channel).await.unwrap();
+        let message = |subject: &str| Message {
+            notification: email(None),
+            subject: subject.to_string(),
+            html: None,
+            text: String::new(),
+            queued_at: clock.now(),
+        };
+        assert_eq!(dispatcher.send("channel:kitchen", message("first")).await, Delivery::Send);
+        assert_eq!(dispatcher.send("channel:kitchen", message("second")).await, Delivery::Drop { reason: DropReason::DailyLimit });
+        let outbox = dispatcher.outbox("channel:kitchen").await;
+        assert_eq!(outbox.iter().map(|m| m.subject.as_str()).collect::<Vec<_>>(), vec!["first"]);
+    }
+}
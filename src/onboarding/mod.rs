@@ -0,0 +1,640 @@
+//! Merchant self-service onboarding
+//!
+//! A merchant registers its domain, proves ownership (see [`verification`]) and
+//! receives an API key for pushing its own coupon feed. Feed coupons are
+//! first-party, so they skip the extraction-confidence discount applied to scraped
+//! codes, but still pass through moderation: the coupon validator rejects malformed
+//! or expired codes, and unusually large discounts wait for an admin review before
+//! they are published. Accounts and feed submissions are shared through Redis when
+//! `REDIS_URL` is set, otherwise persisted to `MERCHANT_ACCOUNTS_PATH` (default
+//! `data/merchant_accounts.json`).
+
+pub mod verification;
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::{Mutex, MutexGuard};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::coupon_engine::validator::Validator;
+use crate::coupon_engine::{DiscountType, RawCoupon, SourceType};
+use crate::licensing::SourceLicenses;
+use crate::localization::Locale;
+use crate::models::coupon_listing::{CouponListing, CouponSource};
+use crate::models::domain::{CouponCode, MerchantDomain};
+use crate::storage::coupon_store::CouponStore;
+use crate::storage::persisted::{PersistedStore, StoreError};
+use crate::top_coupons::TopCoupons;
+use verification::{DomainVerifier, VerificationMethod, META_TAG_NAME, TXT_RECORD_PREFIX};
+
+const REDIS_KEY: &str = "merchant_accounts";
+const STORE_NAME: &str = "merchant accounts";
+const MAX_FEED_COUPONS: usize = 5000;
+/// Percentage discounts at or above this wait for an admin review
+const REVIEW_PERCENTAGE: f64 = 70.0;
+/// Fixed discounts at or above this (in the merchant's currency) wait for an admin review
+const REVIEW_FIXED_AMOUNT: f64 = 500.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountStatus {
+    PendingVerification,
+    Verified,
+    Suspended,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct MerchantAccount {
+    pub id: Uuid,
+    pub domain: MerchantDomain,
+    pub name: String,
+    pub contact_email: String,
+    pub status: AccountStatus,
+    /// Published by the merchant in DNS or a meta tag to prove ownership
+    pub verification_token: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verification_method: Option<VerificationMethod>,
+    pub created_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verified_at: Option<DateTime<Utc>>,
+}
+
+impl MerchantAccount {
+    /// How to publish the verification token, shown to the merchant after registering
+    pub fn verification_instructions(&self) -> VerificationInstructions {
+        VerificationInstructions {
+            dns_txt: TxtRecord {
+                name: self.domain.clone(),
+                value: format!("{}{}", TXT_RECORD_PREFIX, self.verification_token),
+            },
+            meta_tag: format!("<meta name=\"{}\" content=\"{}\">", META_TAG_NAME, self.verification_token),
+        }
+    }
+}
+
+/// Either way of publishing a merchant's verification token
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct VerificationInstructions {
+    pub dns_txt: TxtRecord,
+    /// For the `<head>` of the merchant's home page
+    pub meta_tag: String,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct TxtRecord {
+    pub name: MerchantDomain,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StoredAccount {
+    account: MerchantAccount,
+    /// SHA-256 of the current API key; the key itself is only shown once
+    api_key_hash: Option<String>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct Registration {
+    pub domain: MerchantDomain,
+    pub name: String,
+    pub contact_email: String,
+}
+
+/// A coupon in a merchant's first-party feed
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct FeedCoupon {
+    pub code: CouponCode,
+    pub title: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Language of `title` and `description`, e.g. `de-DE`
+    #[serde(default)]
+    pub locale: Option<String>,
+    pub discount_type: DiscountType,
+    #[serde(default)]
+    pub discount_value: Option<f64>,
+    #[serde(default)]
+    pub minimum_order: Option<Decimal>,
+    #[serde(default)]
+    pub valid_from: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub valid_until: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ModerationStatus {
+    PendingReview,
+    Published,
+    Rejected,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct FeedItem {
+    pub coupon: FeedCoupon,
+    pub status: ModerationStatus,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reasons: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct FeedSubmission {
+    pub id: Uuid,
+    pub merchant_id: Uuid,
+    pub domain: MerchantDomain,
+    pub submitted_at: DateTime<Utc>,
+    pub items: Vec<FeedItem>,
+}
+
+impl FeedSubmission {
+    pub fn count(&self, status: ModerationStatus) -> usize {
+        self.items.iter().filter(|item| item.status == status).count()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum OnboardingError {
+    Invalid(String),
+    /// The domain is already verified by another account
+    Conflict(String),
+    NotFound,
+    /// Missing, unknown or not yet verified API key
+    Unauthorized,
+    VerificationFailed(String),
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct OnboardingState {
+    accounts: HashMap<Uuid, StoredAccount>,
+    submissions: HashMap<Uuid, FeedSubmission>,
+}
+
+pub struct OnboardingService {
+    state: Mutex<OnboardingState>,
+    store: PersistedStore<OnboardingState>,
+    verifier: DomainVerifier,
+    validator: Validator,
+    coupons: Arc<CouponStore>,
+    top_coupons: Option<Arc<TopCoupons>>,
+    licenses: Option<Arc<SourceLicenses>>,
+}
+
+impl OnboardingService {
+    /// Accounts persisted to `path`, or kept in memory only
+    pub fn new(path: Option<PathBuf>, verifier: DomainVerifier, coupons: Arc<CouponStore>) -> Self {
+        Self::with_store(PersistedStore::new(STORE_NAME, REDIS_KEY, path), verifier, coupons)
+    }
+
+    /// Accounts shared through Redis
+    pub fn shared(redis_url: &str, verifier: DomainVerifier, coupons: Arc<CouponStore>) -> Result<Self, StoreError> {
+        Ok(Self::with_store(PersistedStore::shared(STORE_NAME, REDIS_KEY, redis_url)?, verifier, coupons))
+    }
+
+    fn with_store(store: PersistedStore<OnboardingState>, verifier: DomainVerifier, coupons: Arc<CouponStore>) -> Self {
+        Self {
+            state: Mutex::new(OnboardingState::default()),
+            store: store.pretty(),
+            verifier,
+            validator: Validator::new(),
+            coupons,
+            top_coupons: None,
+            licenses: None,
+        }
+    }
+
+    /// Drop a merchant's cached top coupons whenever a review decides on one of its codes
+    pub fn with_top_coupons(mut self, top_coupons: Arc<TopCoupons>) -> Self {
+        self.top_coupons = Some(top_coupons);
+        self
+    }
+
+    /// Tag published coupons with the partner agreement's license
+    pub fn with_licenses(mut self, licenses: Arc<SourceLicenses>) -> Self {
+        self.licenses = Some(licenses);
+        self
+    }
+
+    /// Share accounts through `REDIS_URL` when set, otherwise load them from
+    /// `MERCHANT_ACCOUNTS_PATH` (default `data/merchant_accounts.json`)
+    pub async fn from_env(verifier: DomainVerifier, coupons: Arc<CouponStore>) -> Self {
+        let store = PersistedStore::from_env(STORE_NAME, REDIS_KEY, "MERCHANT_ACCOUNTS_PATH", "data/merchant_accounts.json");
+        let service = Self::with_store(store, verifier, coupons);
+        if let Err(e) = service.reload(&mut *service.state.lock().await).await {
+            tracing::warn!(error = %e, "Starting with no merchant accounts");
+        }
+        service
+    }
+
+    async fn reload(&self, state: &mut OnboardingState) -> Result<(), StoreError> {
+        if let Some(stored) = self.store.load().await? {
+            *state = stored;
+        }
+        Ok(())
+    }
+
+    /// The accounts and submissions, re-read when shared since any instance may
+    /// have changed them
+    async fn lock(&self) -> MutexGuard<'_, OnboardingState> {
+        let mut state = self.state.lock().await;
+        if self.store.is_shared() {
+            if let Err(e) = self.reload(&mut state).await {
+                tracing::warn!(error = %e, "Failed to re-read the merchant accounts; using the local copy");
+            }
+        }
+        state
+    }
+
+    pub async fn register(&self, registration: Registration) -> Result<MerchantAccount, OnboardingError> {
+        let name = registration.name.trim();
+        if name.is_empty() || name.len() > 100 {
+            return Err(OnboardingError::Invalid("name must be 1-100 characters".to_string()));
+        }
+        let contact_email = registration.contact_email.trim();
+        if !contact_email.split_once('@').is_some_and(|(user, host)| !user.is_empty() && host.contains('.')) {
+            return Err(OnboardingError::Invalid(format!("'{}' is not an email address", contact_email)));
+        }
+
+        let mut state = self.lock().await;
+        let verified_elsewhere = state
+            .accounts
+            .values()
+            .any(|stored| stored.account.domain == registration.domain && stored.account.status != AccountStatus::PendingVerification);
+        if verified_elsewhere {
+            return Err(OnboardingError::Conflict(format!("{} is already registered", registration.domain)));
+        }
+
+        let account = MerchantAccount {
+            id: Uuid::new_v4(),
+            domain: registration.domain,
+            name: name.to_string(),
+            contact_email: contact_email.to_string(),
+            status: AccountStatus::PendingVerification,
+            verification_token: random_hex(16),
+            verification_method: None,
+            created_at: Utc::now(),
+            verified_at: None,
+        };
+        state.accounts.insert(
+            account.id,
+            StoredAccount {
+                account: account.clone(),
+                api_key_hash: None,
+            },
+        );
+        self.store.persist(&state).await;
+        Ok(account)
+    }
+
+    pub async fn account(&self, id: Uuid) -> Option<MerchantAccount> {
+        self.lock().await.accounts.get(&id).map(|stored| stored.account.clone())
+    }
+
+    /// Check domain ownership and issue a new API key, returned only this once.
+    ///
+    /// Verifying an already verified account rotates its key.
+    pub async fn verify(&self, id: Uuid, method: VerificationMethod) -> Result<(MerchantAccount, String), OnboardingError> {
+        let account = self.account(id).await.ok_or(OnboardingError::NotFound)?;
+        if account.status == AccountStatus::Suspended {
+            return Err(OnboardingError::Unauthorized);
+        }
+
+        // Network checks run without holding the lock
+        self.verifier
+            .verify(&account.domain, &account.verification_token, method)
+            .await
+            .map_err(OnboardingError::VerificationFailed)?;
+
+        let mut state = self.lock().await;
+        if state.accounts.values().any(|stored| {
+            stored.account.id != id
+                && stored.account.domain == account.domain
+                && stored.account.status == AccountStatus::Verified
+        }) {
+            return Err(OnboardingError::Conflict(format!("{} is already registered", account.domain)));
+        }
+
+        let api_key = format!("dm_{}", random_hex(32));
+        let stored = state.accounts.get_mut(&id).ok_or(OnboardingError::NotFound)?;
+        stored.account.status = AccountStatus::Verified;
+        stored.account.verification_method = Some(method);
+        stored.account.verified_at = Some(Utc::now());
+        stored.api_key_hash = Some(hash_key(&api_key));
+        let account = stored.account.clone();
+
+        // Drop stale pending registrations for the same domain
+        state
+            .accounts
+            .retain(|_, other| other.account.domain != account.domain || other.account.id == id);
+        self.store.persist(&state).await;
+        Ok((account, api_key))
+    }
+
+    fn authenticate(state: &OnboardingState, api_key: &str) -> Result<MerchantAccount, OnboardingError> {
+        let hash = hash_key(api_key);
+        state
+            .accounts
+            .values()
+            .find(|stored| stored.api_key_hash.as_deref() == Some(hash.as_str()))
+            .filter(|stored| stored.account.status == AccountStatus::Verified)
+            .map(|stored| stored.account.clone())
+            .ok_or(OnboardingError::Unauthorized)
+    }
+
+    /// Moderate a merchant's feed and publish the coupons that pass
+    pub async fn submit_feed(&self, api_key: &str, coupons: Vec<FeedCoupon>) -> Result<FeedSubmission, OnboardingError> {
+        let account = Self::authenticate(&*self.lock().await, api_key)?;
+        if coupons.is_empty() || coupons.len() > MAX_FEED_COUPONS {
+            return Err(OnboardingError::Invalid(format!("a feed holds 1-{} coupons", MAX_FEED_COUPONS)));
+        }
+
+        let raw: Vec<RawCoupon> = coupons.iter().map(|coupon| to_raw_coupon(&account, coupon)).collect();
+        let mut items = Vec::with_capacity(coupons.len());
+        for (coupon, result) in coupons.into_iter().zip(self.validator.validate_batch(raw).await) {
+            let bad_locale = coupon.locale.as_deref().filter(|tag| Locale::parse(tag).is_none());
+            let (status, reasons) = if !result.is_valid {
+                (ModerationStatus::Rejected, result.validation_errors)
+            } else if let Some(tag) = bad_locale {
+                (ModerationStatus::Rejected, vec![format!("Locale '{}' is not a language tag", tag)])
+            } else if needs_review(&coupon) {
+                (ModerationStatus::PendingReview, vec!["Unusually large discount".to_string()])
+            } else {
+                self.publish(&account.domain, &coupon).await;
+                (ModerationStatus::Published, Vec::new())
+            };
+            items.push(FeedItem { coupon, status, reasons });
+        }
+
+        let submission = FeedSubmission {
+            id: Uuid::new_v4(),
+            merchant_id: account.id,
+            domain: account.domain,
+            submitted_at: Utc::now(),
+            items,
+        };
+
+        let mut state = self.lock().await;
+        state.submissions.insert(submission.id, submission.clone());
+        self.store.persist(&state).await;
+        Ok(submission)
+    }
+
+    /// A submission, visible only to the merchant that pushed it
+    pub async fn submission(&self, api_key: &str, id: Uuid) -> Result<FeedSubmission, OnboardingError> {
+        let state = self.lock().await;
+        let account = Self::authenticate(&state, api_key)?;
+        state
+            .submissions
+            .get(&id)
+            .filter(|submission| submission.merchant_id == account.id)
+            .cloned()
+            .ok_or(OnboardingError::NotFound)
+    }
+
+    /// Submissions with coupons waiting for an admin review, oldest first
+    pub async fn pending_reviews(&self) -> Vec<FeedSubmission> {
+        let state = self.lock().await;
+        let mut pending: Vec<FeedSubmission> = state
+            .submissions
+            .values()
+            .filter(|submission| submission.count(ModerationStatus::PendingReview) > 0)
+            .cloned()
+            .collect();
+        pending.sort_by_key(|submission| submission.submitted_at);
+        pending
+    }
+
+    /// Publish or reject a coupon held for review
+    pub async fn review(
+        &self,
+        submission_id: Uuid,
+        code: &CouponCode,
+        approve: bool,
+        reason: Option<String>,
+    ) -> Result<FeedSubmission, OnboardingError> {
+        let mut state = self.lock().await;
+        let submission = state.submissions.get_mut(&submission_id).ok_or(OnboardingError::NotFound)?;
+        let item = submission
+            .items
+            .iter_mut()
+            .find(|item| &item.coupon.code == code && item.status == ModerationStatus::PendingReview)
+            .ok_or(OnboardingError::NotFound)?;
+
+        if approve {
+            item.status = ModerationStatus::Published;
+            item.reasons.clear();
+            self.publish(&submission.domain, &item.coupon).await;
+        } else {
+            item.status = ModerationStatus::Rejected;
+            item.reasons = vec![reason.unwrap_or_else(|| "Rejected by moderator".to_string())];
+        }
+        if let Some(top_coupons) = &self.top_coupons {
+            top_coupons.invalidate(&submission.domain);
+        }
+        let submission = submission.clone();
+        self.store.persist(&state).await;
+        Ok(submission)
+    }
+
+    async fn publish(&self, domain: &MerchantDomain, coupon: &FeedCoupon) {
+        let license = match &self.licenses {
+            Some(licenses) => Some(licenses.terms(CouponSource::PartnerApi, domain).await.tag()),
+            None => None,
+        };
+        self.coupons
+            .upsert(CouponListing {
+                code: coupon.code.clone(),
+                title: coupon.title.clone(),
+                description: coupon.description.clone(),
+                locale: coupon.locale.as_deref().and_then(Locale::parse).map(|l| l.to_string()),
+                merchant_domain: domain.clone(),
+                discount_type: coupon.discount_type.as_str().to_string(),
+                discount_value: coupon.discount_value,
+                minimum_order: coupon.minimum_order,
+                source: CouponSource::PartnerApi,
+                // First-party codes are exactly what the merchant issued
+                license,
+                extraction_confidence: 1.0,
+                scraped_at: Utc::now(),
+                valid_until: coupon.valid_until,
+                predicted_success: None,
+                tags: Vec::new(),
+            })
+            .await;
+    }
+}
+
+fn to_raw_coupon(account: &MerchantAccount, coupon: &FeedCoupon) -> RawCoupon {
+    RawCoupon {
+        code: coupon.code.clone(),
+        title: coupon.title.clone(),
+        description: coupon.description.clone(),
+        discount_type: coupon.discount_type.clone(),
+        discount_value: coupon.discount_value,
+        minimum_order: coupon.minimum_order,
+        maximum_discount: None,
+        valid_from: coupon.valid_from,
+        valid_until: coupon.valid_until,
+        merchant_name: account.name.clone(),
+        merchant_domain: account.domain.clone(),
+        source_url: format!("partner:{}", account.id),
+        source_type: SourceType::PartnerApi,
+        metadata: serde_json::json!({}),
+        scraped_at: Utc::now(),
+        parser_version: None,
+    }
+}
+
+fn needs_review(coupon: &FeedCoupon) -> bool {
+    let value = coupon.discount_value.unwrap_or(0.0);
+    match coupon.discount_type {
+        DiscountType::Percentage => value >= REVIEW_PERCENTAGE,
+        DiscountType::Fixed => value >= REVIEW_FIXED_AMOUNT,
+        _ => false,
+    }
+}
+
+fn random_hex(bytes: usize) -> String {
+    let mut rng = rand::thread_rng();
+    (0..bytes).map(|_| format!("{:02x}", rng.gen::<u8>())).collect()
+}
+
+fn hash_key(api_key: &str) -> String {
+    Sha256::digest(api_key.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coupon_engine::proxy_manager::ProxyConfig;
+    use crate::coupon_engine::scraper::Fetcher;
+    use axum::async_trait;
+    use verification::TxtResolver;
+
+    /// Serves whatever TXT record and home page the test sets up
+    #[derive(Default)]
+    struct FakeSite {
+        txt: std::sync::Mutex<Vec<String>>,
+        home_page: std::sync::Mutex<String>,
+    }
+
+    #[async_trait]
+    impl TxtResolver for FakeSite {
+        async fn txt_records(&self, _name: &str) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
+            Ok(self.txt.lock().unwrap().clone())
+        }
+    }
+
+    #[async_trait]
+    impl Fetcher for FakeSite {
+        async fn fetch(
+            &self,
+            _url: &str,
+            _proxy: Option<&ProxyConfig>,
+        ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+            Ok(self.home_page.lock().unwrap().clone())
+        }
+    }
+
+    fn service(site: &Arc<FakeSite>) -> (OnboardingService, Arc<CouponStore>) {
+        let coupons = Arc::new(CouponStore::new());
+        let verifier = DomainVerifier::new(site.clone(), site.clone());
+        (OnboardingService::new(None, verifier, coupons.clone()), coupons)
+    }
+
+    fn registration() -> Registration {
+        Registration {
+            domain: MerchantDomain::parse("shop.example.com").unwrap(),
+            name: "Example Shop".to_string(),
+            contact_email: "deals@example.com".to_string(),
+        }
+    }
+
+    fn feed_coupon(code: &str, discount_type: DiscountType, value: Option<f64>) -> FeedCoupon {
+        FeedCoupon {
+            code: CouponCode::parse(code).unwrap(),
+            title: format!("{} offer", code),
+            description: None,
+            locale: None,
+            discount_type,
+            discount_value: value,
+            minimum_order: None,
+            valid_from: None,
+            valid_until: Some(Utc::now() + chrono::Duration::days(30)),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_verification_requires_published_token() {
+        let site = Arc::new(FakeSite::default());
+        let (service, _) = service(&site);
+        let account = service.register(registration()).await.unwrap();
+
+        assert!(matches!(
+            service.verify(account.id, VerificationMethod::DnsTxt).await,
+            Err(OnboardingError::VerificationFailed(_))
+        ));
+
+        *site.home_page.lock().unwrap() = format!(
+            "<html><head><meta name=\"{}\" content=\"{}\"></head></html>",
+            META_TAG_NAME, account.verification_token
+        );
+        let (verified, api_key) = service.verify(account.id, VerificationMethod::MetaTag).await.unwrap();
+
+        assert_eq!(verified.status, AccountStatus::Verified);
+        assert!(api_key.starts_with("dm_"));
+        assert_eq!(
+            service.register(registration()).await.unwrap_err(),
+            OnboardingError::Conflict("shop.example.com is already registered".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_feed_is_moderated_before_publishing() {
+        let site = Arc::new(FakeSite::default());
+        let (service, coupons) = service(&site);
+        let account = service.register(registration()).await.unwrap();
+        *site.txt.lock().unwrap() = vec![format!("{}{}", TXT_RECORD_PREFIX, account.verification_token)];
+        let (_, api_key) = service.verify(account.id, VerificationMethod::DnsTxt).await.unwrap();
+
+        assert_eq!(
+            service.submit_feed("dm_wrong", vec![]).await.unwrap_err(),
+            OnboardingError::Unauthorized
+        );
+        let submission = service
+            .submit_feed(
+                &api_key,
+                vec![
+                    feed_coupon("SPRING15", DiscountType::Percentage, Some(15.0)),
+                    feed_coupon("TESTCODE", DiscountType::Percentage, Some(15.0)),
+                    feed_coupon("HALFOFF80", DiscountType::Percentage, Some(80.0)),
+                ],
+            )
+            .await
+            .unwrap();
+
+        let statuses: Vec<_> = submission.items.iter().map(|item| item.status).collect();
+        assert_eq!(
+            statuses,
+            vec![ModerationStatus::Published, ModerationStatus::Rejected, ModerationStatus::PendingReview]
+        );
+        let published = coupons.list().await;
+        assert_eq!(published.len(), 1);
+        assert_eq!((published[0].source, published[0].extraction_confidence), (CouponSource::PartnerApi, 1.0));
+
+        let reviewed = service
+            .review(submission.id, &CouponCode::parse("HALFOFF80").unwrap(), true, None)
+            .await
+            .unwrap();
+        assert_eq!(reviewed.count(ModerationStatus::PendingReview), 0);
+        assert_eq!(coupons.list().await.len(), 2);
+        assert_eq!(service.submission(&api_key, submission.id).await.unwrap().count(ModerationStatus::Published), 2);
+    }
+}
@@ -0,0 +1,166 @@
+//! Domain ownership checks
+//!
+//! A merchant proves it controls its domain by publishing its verification token
+//! either as a DNS TXT record on the domain or as a `<meta>` tag on the home page.
+//! TXT records are looked up over DNS-over-HTTPS; the home page is fetched with the
+//! coupon engine's fetcher so it goes through the same user agents and proxies as
+//! scraping.
+
+use std::sync::Arc;
+
+use axum::async_trait;
+use deal_service_macros::selector;
+use scraper::Html;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::coupon_engine::scraper::Fetcher;
+use crate::models::domain::MerchantDomain;
+
+pub const META_TAG_NAME: &str = "dealmate-site-verification";
+/// TXT records carry `dealmate-site-verification=<token>`
+pub const TXT_RECORD_PREFIX: &str = "dealmate-site-verification=";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum VerificationMethod {
+    DnsTxt,
+    MetaTag,
+}
+
+#[async_trait]
+pub trait TxtResolver: Send + Sync {
+    async fn txt_records(&self, name: &str) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>>;
+}
+
+/// Resolves TXT records through a DNS-over-HTTPS JSON endpoint
+pub struct DohResolver {
+    client: reqwest::Client,
+    endpoint: String,
+}
+
+#[derive(Deserialize)]
+struct DohResponse {
+    #[serde(rename = "Answer", default)]
+    answer: Vec<DohAnswer>,
+}
+
+#[derive(Deserialize)]
+struct DohAnswer {
+    #[serde(rename = "type")]
+    record_type: u16,
+    data: String,
+}
+
+const TXT_RECORD_TYPE: u16 = 16;
+
+impl DohResolver {
+    pub fn new(endpoint: String) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            endpoint,
+        }
+    }
+
+    /// Use `DNS_OVER_HTTPS_URL` (default Cloudflare's resolver)
+    pub fn from_env() -> Self {
+        Self::new(std::env::var("DNS_OVER_HTTPS_URL").unwrap_or_else(|_| "https://cloudflare-dns.com/dns-query".to_string()))
+    }
+}
+
+#[async_trait]
+impl TxtResolver for DohResolver {
+    async fn txt_records(&self, name: &str) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
+        let response: DohResponse = self
+            .client
+            .get(&self.endpoint)
+            .query(&[("name", name), ("type", "TXT")])
+            .header("accept", "application/dns-json")
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(response
+            .answer
+            .into_iter()
+            .filter(|answer| answer.record_type == TXT_RECORD_TYPE)
+            .map(|answer| join_txt_strings(&answer.data))
+            .collect())
+    }
+}
+
+/// A TXT record arrives as one or more quoted strings, e.g. `"abc" "def"`
+fn join_txt_strings(data: &str) -> String {
+    if !data.starts_with('"') {
+        return data.to_string();
+    }
+    data.split('"').skip(1).step_by(2).collect()
+}
+
+fn meta_tag_tokens(html: &str) -> Vec<String> {
+    let document = Html::parse_document(html);
+    document
+        .select(selector!("meta[name][content]"))
+        .filter(|tag| tag.value().attr("name") == Some(META_TAG_NAME))
+        .filter_map(|tag| tag.value().attr("content"))
+        .map(|content| content.trim().to_string())
+        .collect()
+}
+
+pub struct DomainVerifier {
+    resolver: Arc<dyn TxtResolver>,
+    fetcher: Arc<dyn Fetcher>,
+}
+
+impl DomainVerifier {
+    pub fn new(resolver: Arc<dyn TxtResolver>, fetcher: Arc<dyn Fetcher>) -> Self {
+        Self { resolver, fetcher }
+    }
+
+    /// Check that `token` is published on `domain`, returning why not otherwise
+    pub async fn verify(&self, domain: &MerchantDomain, token: &str, method: VerificationMethod) -> Result<(), String> {
+        let found = match method {
+            VerificationMethod::DnsTxt => self
+                .resolver
+                .txt_records(domain.as_str())
+                .await
+                .map_err(|e| format!("DNS lookup for {} failed: {}", domain, e))?
+                .iter()
+                .any(|record| record.strip_prefix(TXT_RECORD_PREFIX) == Some(token)),
+            VerificationMethod::MetaTag => {
+                let url = format!("https://{}/", domain);
+                let html = self
+                    .fetcher
+                    .fetch(&url, None)
+                    .await
+                    .map_err(|e| format!("Could not fetch {}: {}", url, e))?;
+                meta_tag_tokens(&html).iter().any(|found| found == token)
+            }
+        };
+
+        if found {
+            Ok(())
+        } else {
+            Err(match method {
+                VerificationMethod::DnsTxt => format!("No TXT record '{}{}' on {}", TXT_RECORD_PREFIX, token, domain),
+                VerificationMethod::MetaTag => format!("No '{}' meta tag with the token on https://{}/", META_TAG_NAME, domain),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parses_txt_strings_and_meta_tags() {
+        assert_eq!(join_txt_strings("\"dealmate-site-\" \"verification=abc\""), "dealmate-site-verification=abc");
+        assert_eq!(join_txt_strings("plain"), "plain");
+
+        let html = r#"<html><head><meta name="dealmate-site-verification" content=" abc "></head></html>"#;
+        assert_eq!(meta_tag_tokens(html), vec!["abc".to_string()]);
+    }
+}
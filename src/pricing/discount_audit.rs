@@ -0,0 +1,180 @@
+//! Fake-discount detection
+//!
+//! Compares a deal's claimed original price (MRP) against the distribution of
+//! prices the product has actually sold at, and derives the "honest" discount
+//! relative to the typical selling price.
+
+use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use serde::Serialize;
+
+use crate::models::deal::{Deal, PricePoint};
+use crate::storage::deal_store::DealStore;
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct DiscountAudit {
+    /// Discount percentage relative to the median observed price
+    pub honest_discount: f64,
+    /// Median observed price, in the deal's currency
+    pub reference_price: Decimal,
+    /// Share of observed prices below the claimed original price (0.0 - 1.0)
+    pub claimed_price_percentile: f64,
+    pub inflated: bool,
+}
+
+pub struct DiscountAuditor {
+    /// Minimum number of price observations before a verdict is given
+    min_history_points: usize,
+    /// Percentage points the advertised discount may exceed the honest one by
+    tolerance: f64,
+    /// Claimed prices this far above the 95th percentile count as never charged
+    mrp_margin: Decimal,
+}
+
+impl DiscountAuditor {
+    pub fn new() -> Self {
+        Self {
+            min_history_points: 14,
+            tolerance: 15.0,
+            mrp_margin: dec!(0.05),
+        }
+    }
+
+    pub fn audit(&self, deal: &Deal, history: &[PricePoint]) -> Option<DiscountAudit> {
+        let claimed = deal.original_price.amount;
+        if history.len() < self.min_history_points || claimed <= Decimal::ZERO {
+            return None;
+        }
+
+        let mut prices: Vec<Decimal> = history.iter().map(|p| p.price).collect();
+        prices.sort();
+
+        let reference_price = percentile(&prices, 0.5);
+        let p95 = percentile(&prices, 0.95);
+        let below_claimed = prices.iter().filter(|&&p| p < claimed).count();
+        let claimed_price_percentile = below_claimed as f64 / prices.len() as f64;
+
+        let honest_discount = if reference_price > Decimal::ZERO {
+            ((reference_price - deal.price.amount) / reference_price * Decimal::ONE_HUNDRED)
+                .max(Decimal::ZERO)
+                .round_dp(1)
+                .to_f64()
+                .unwrap_or(0.0)
+        } else {
+            0.0
+        };
+
+        let inflated = claimed > p95 * (Decimal::ONE + self.mrp_margin)
+            && deal.discount - honest_discount > self.tolerance;
+
+        Some(DiscountAudit {
+            honest_discount,
+            reference_price,
+            claimed_price_percentile,
+            inflated,
+        })
+    }
+
+    /// Fill in `honest_discount` and `discount_inflated` on each deal
+    pub async fn annotate(&self, store: &DealStore, deals: &mut [Deal]) {
+        for deal in deals.iter_mut() {
+            let history = store.price_history(&deal.product_id).await;
+            if let Some(audit) = self.audit(deal, &history) {
+                deal.honest_discount = Some(audit.honest_discount);
+                deal.discount_inflated = audit.inflated;
+            }
+        }
+    }
+}
+
+impl Default for DiscountAuditor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Linear-interpolated percentile of an ascending slice
+fn percentile(sorted: &[Decimal], q: f64) -> Decimal {
+    if sorted.is_empty() {
+        return Decimal::ZERO;
+    }
+
+    let rank = q.clamp(0.0, 1.0) * (sorted.len() - 1) as f64;
+    let lower = rank.floor() as usize;
+    let upper = rank.ceil() as usize;
+    let weight = Decimal::from_f64(rank - lower as f64).unwrap_or(Decimal::ZERO);
+    (sorted[lower] + (sorted[upper] - sorted[lower]) * weight).round_dp(2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::deal::DealStatus;
+    use crate::models::domain::{MerchantDomain, Money};
+    use chrono::{Duration, Utc};
+
+    fn deal(price: Decimal, original_price: Decimal) -> Deal {
+        Deal {
+            id: "deal_test".to_string(),
+            product_id: "prod_test".to_string(),
+            title: "Test".to_string(),
+            store: "Test Store".to_string(),
+            merchant_domain: MerchantDomain::parse("teststore.com").unwrap(),
+            category: "electronics".to_string(),
+            brand: None,
+            price: Money::usd(price),
+            original_price: Money::usd(original_price),
+            discount: ((original_price - price) / original_price * Decimal::ONE_HUNDRED)
+                .round()
+                .to_f64()
+                .unwrap(),
+            image_url: None,
+            product_url: None,
+            image_hash: None,
+            free_shipping: false,
+            posted_at: Utc::now(),
+            score: None,
+            honest_discount: None,
+            discount_inflated: false,
+            verified_at: None,
+            price_mismatch: false,
+            status: DealStatus::Active,
+            status_confidence: None,
+            events: Vec::new(),
+            tags: Vec::new(),
+        }
+    }
+
+    fn flat_history(price: Decimal) -> Vec<PricePoint> {
+        (0..30)
+            .map(|i| PricePoint {
+                price,
+                observed_at: Utc::now() - Duration::days(i),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_inflated_mrp_is_flagged() {
+        // Always sold at $100, now "$90, was $200 (55% off)"
+        let audit = DiscountAuditor::new().audit(&deal(dec!(90), dec!(200)), &flat_history(dec!(100))).unwrap();
+
+        assert_eq!(audit.honest_discount, 10.0);
+        assert!(audit.inflated);
+    }
+
+    #[test]
+    fn test_genuine_discount_is_not_flagged() {
+        let audit = DiscountAuditor::new().audit(&deal(dec!(75), dec!(100)), &flat_history(dec!(100))).unwrap();
+
+        assert_eq!(audit.honest_discount, 25.0);
+        assert!(!audit.inflated);
+    }
+
+    #[test]
+    fn test_short_history_gives_no_verdict() {
+        let history = flat_history(dec!(100))[..5].to_vec();
+        assert!(DiscountAuditor::new().audit(&deal(dec!(90), dec!(200)), &history).is_none());
+    }
+}
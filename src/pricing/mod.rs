@@ -0,0 +1,6 @@
+//! Price analysis shared by the deal endpoints
+
+pub mod discount_audit;
+pub mod rewards;
+pub mod shipping;
+pub mod verification;
@@ -0,0 +1,244 @@
+//! Gift card and loyalty point valuation
+//!
+//! The cheapest way to pay is often not a code at all: a merchant gift card bought
+//! at a discount, or loyalty points earned on the order, lower what it really costs.
+//! [`RewardsValuator`] turns a checkout price into an effective price using the gift
+//! card offers and loyalty programs configured in the JSON file at
+//! `REWARDS_CONFIG_PATH`.
+
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::models::domain::{Currency, MerchantDomain, Money};
+
+/// A merchant gift card sold below face value
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GiftCardOffer {
+    pub merchant: MerchantDomain,
+    /// Where the card is sold, e.g. a gift card marketplace
+    pub seller: String,
+    pub currency: Currency,
+    /// Percentage below face value the card sells for
+    pub discount_percent: Decimal,
+    /// Largest face value on offer, if limited
+    #[serde(default)]
+    pub max_face_value: Option<Decimal>,
+}
+
+/// Earn and burn values of a points program
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoyaltyProgram {
+    pub id: String,
+    pub merchants: Vec<MerchantDomain>,
+    pub currency: Currency,
+    /// Points earned per unit of currency paid
+    pub earn_rate: Decimal,
+    /// What one point is worth when redeemed
+    pub point_value: Decimal,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RewardsConfig {
+    #[serde(default)]
+    pub gift_cards: Vec<GiftCardOffer>,
+    #[serde(default)]
+    pub loyalty_programs: Vec<LoyaltyProgram>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct GiftCardPurchase {
+    pub seller: String,
+    pub face_value: Money,
+    pub cost: Money,
+    pub savings: Money,
+}
+
+/// What an order costs once gift cards and points are accounted for
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct EffectivePrice {
+    pub checkout_price: Money,
+    pub loyalty_program: Option<String>,
+    pub points_redeemed: u64,
+    /// Part of the checkout price paid with points
+    pub redemption_value: Money,
+    /// Gift card to buy before checking out
+    pub gift_card: Option<GiftCardPurchase>,
+    pub points_earned: u64,
+    pub points_earned_value: Money,
+    /// Money actually paid: the checkout price less points and gift card savings
+    pub cash_price: Money,
+    /// Cash paid plus the value of points burned, less the value of points earned
+    pub effective_price: Money,
+}
+
+pub struct RewardsValuator {
+    config: RewardsConfig,
+}
+
+impl Default for RewardsValuator {
+    fn default() -> Self {
+        Self::new(RewardsConfig::default())
+    }
+}
+
+impl RewardsValuator {
+    pub fn new(config: RewardsConfig) -> Self {
+        Self { config }
+    }
+
+    /// Offers and programs from `REWARDS_CONFIG_PATH`, or none
+    pub fn from_env() -> Self {
+        let Ok(path) = std::env::var("REWARDS_CONFIG_PATH") else {
+            return Self::default();
+        };
+
+        match Self::load_config(&path) {
+            Ok(config) => Self::new(config),
+            Err(e) => {
+                tracing::warn!(path = %path, error = %e, "Failed to load the rewards config");
+                Self::default()
+            }
+        }
+    }
+
+    fn load_config(path: &str) -> Result<RewardsConfig, Box<dyn std::error::Error + Send + Sync>> {
+        let content = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    pub fn program_for(&self, merchant: &MerchantDomain, currency: Currency) -> Option<&LoyaltyProgram> {
+        self.config
+            .loyalty_programs
+            .iter()
+            .find(|p| p.currency == currency && p.merchants.contains(merchant))
+    }
+
+    /// Gift card purchase that saves the most on `amount`, given each offer's limit
+    pub fn best_gift_card(&self, merchant: &MerchantDomain, amount: Money) -> Option<GiftCardPurchase> {
+        let money = |value: Decimal| Money::new(value.round_dp(2), amount.currency);
+        self.config
+            .gift_cards
+            .iter()
+            .filter(|o| &o.merchant == merchant && o.currency == amount.currency && o.discount_percent > Decimal::ZERO)
+            .filter_map(|offer| {
+                let face = offer.max_face_value.map_or(amount.amount, |max| amount.amount.min(max));
+                let savings = (face * offer.discount_percent / Decimal::ONE_HUNDRED).round_dp(2);
+                (face > Decimal::ZERO).then(|| GiftCardPurchase {
+                    seller: offer.seller.clone(),
+                    face_value: money(face),
+                    cost: money(face - savings),
+                    savings: money(savings),
+                })
+            })
+            .max_by_key(|purchase| purchase.savings.amount)
+    }
+
+    /// Value a `price` checkout at `merchant`, burning up to `redeem_points` first.
+    ///
+    /// Points are earned on the part not paid with points; paying with a gift card
+    /// still earns them.
+    pub fn effective_price(&self, merchant: &MerchantDomain, price: Money, redeem_points: u64) -> EffectivePrice {
+        let currency = price.currency;
+        let money = |amount: Decimal| Money::new(amount.round_dp(2), currency);
+        let program = self.program_for(merchant, currency);
+
+        let (points_redeemed, redemption) = match program {
+            Some(p) if redeem_points > 0 && p.point_value > Decimal::ZERO => {
+                let needed = (price.amount / p.point_value).ceil().to_u64().unwrap_or(u64::MAX);
+                let points = redeem_points.min(needed);
+                (points, (Decimal::from(points) * p.point_value).min(price.amount))
+            }
+            _ => (0, Decimal::ZERO),
+        };
+        let to_pay = price.amount - redemption;
+
+        let gift_card = self.best_gift_card(merchant, Money::new(to_pay, currency));
+        let gift_card_savings = gift_card.as_ref().map_or(Decimal::ZERO, |g| g.savings.amount);
+
+        let (points_earned, earned_value) = program.map_or((0, Decimal::ZERO), |p| {
+            let points = (to_pay * p.earn_rate).floor().to_u64().unwrap_or(0);
+            (points, Decimal::from(points) * p.point_value)
+        });
+        let cash = to_pay - gift_card_savings;
+
+        EffectivePrice {
+            checkout_price: price,
+            loyalty_program: program.map(|p| p.id.clone()),
+            points_redeemed,
+            redemption_value: money(redemption),
+            gift_card,
+            points_earned,
+            points_earned_value: money(earned_value),
+            cash_price: money(cash),
+            effective_price: money(cash + redemption - earned_value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn valuator() -> RewardsValuator {
+        let shop = MerchantDomain::parse("shop.example.com").unwrap();
+        RewardsValuator::new(RewardsConfig {
+            gift_cards: vec![
+                GiftCardOffer {
+                    merchant: shop.clone(),
+                    seller: "cards-a".to_string(),
+                    currency: Currency::USD,
+                    discount_percent: dec!(5),
+                    max_face_value: None,
+                },
+                GiftCardOffer {
+                    merchant: shop.clone(),
+                    seller: "cards-b".to_string(),
+                    currency: Currency::USD,
+                    discount_percent: dec!(10),
+                    max_face_value: Some(dec!(50)),
+                },
+            ],
+            loyalty_programs: vec![LoyaltyProgram {
+                id: "shop-rewards".to_string(),
+                merchants: vec![shop],
+                currency: Currency::USD,
+                earn_rate: dec!(2),
+                point_value: dec!(0.01),
+            }],
+        })
+    }
+
+    #[test]
+    fn test_effective_price_counts_gift_card_and_points() {
+        let shop = MerchantDomain::parse("shop.example.com").unwrap();
+        let price = valuator().effective_price(&shop, Money::usd(dec!(40.00)), 0);
+
+        let gift_card = price.gift_card.unwrap();
+        assert_eq!(gift_card.seller, "cards-b");
+        assert_eq!(gift_card.cost, Money::usd(dec!(36.00)));
+        assert_eq!(price.points_earned, 80);
+        assert_eq!(price.cash_price, Money::usd(dec!(36.00)));
+        assert_eq!(price.effective_price, Money::usd(dec!(35.20)));
+    }
+
+    #[test]
+    fn test_redeemed_points_are_a_cost_and_earn_nothing() {
+        let shop = MerchantDomain::parse("shop.example.com").unwrap();
+        let price = valuator().effective_price(&shop, Money::usd(dec!(100.00)), 1_000);
+
+        assert_eq!(price.redemption_value, Money::usd(dec!(10.00)));
+        // 10% on the first $50 of the remaining $90 beats 5% on all of it
+        assert_eq!(price.gift_card.unwrap().savings, Money::usd(dec!(5.00)));
+        assert_eq!(price.points_earned, 180);
+        assert_eq!(price.cash_price, Money::usd(dec!(85.00)));
+        assert_eq!(price.effective_price, Money::usd(dec!(93.20)));
+
+        let elsewhere = MerchantDomain::parse("other.example.com").unwrap();
+        let plain = valuator().effective_price(&elsewhere, Money::usd(dec!(100.00)), 1_000);
+        assert_eq!((plain.points_redeemed, plain.gift_card), (0, None));
+        assert_eq!(plain.effective_price, Money::usd(dec!(100.00)));
+    }
+}
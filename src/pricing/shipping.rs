@@ -0,0 +1,208 @@
+//! Delivered-price estimation
+//!
+//! A lower sticker price is no bargain if the merchant charges for delivery. The
+//! merchant's [`ShippingRule`] decides whether an order ships free (over a price
+//! threshold, or with a membership the shopper holds) and adds any surcharge for the
+//! shopper's region. Deals are then compared on sticker price plus shipping.
+
+use std::collections::BTreeMap;
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::models::deal::Deal;
+use crate::models::domain::{MerchantDomain, Money};
+use crate::storage::shipping_rules::ShippingRule;
+
+/// Who is buying: where it ships and which memberships they hold
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct ShippingContext {
+    /// Region code matched against the rules' surcharges, e.g. `HI`
+    #[serde(default)]
+    pub region: Option<String>,
+    #[serde(default)]
+    pub memberships: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FreeShipping {
+    /// The listing itself says it ships free
+    Listing,
+    Threshold,
+    Membership,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct ShippingEstimate {
+    pub cost: Money,
+    /// Why the base cost was waived, if it was
+    pub free: Option<FreeShipping>,
+    pub surcharge: Money,
+    /// False when the merchant has no rule and the listing does not ship free
+    pub known: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct DeliveredPrice {
+    pub deal: Deal,
+    pub shipping: ShippingEstimate,
+    pub delivered_price: Money,
+}
+
+/// Shipping for `deal` under its merchant's `rule`.
+///
+/// A rule in another currency than the deal is ignored.
+pub fn estimate(deal: &Deal, rule: Option<&ShippingRule>, context: &ShippingContext) -> ShippingEstimate {
+    let currency = deal.price.currency;
+    let rule = rule.filter(|rule| rule.currency == currency);
+
+    let surcharge = match (rule, &context.region) {
+        (Some(rule), Some(region)) => rule
+            .regional_surcharges
+            .get(&region.trim().to_ascii_uppercase())
+            .copied()
+            .unwrap_or(Decimal::ZERO),
+        _ => Decimal::ZERO,
+    };
+    let surcharge = Money::new(surcharge, currency);
+
+    let free = if deal.free_shipping {
+        Some(FreeShipping::Listing)
+    } else if rule.and_then(|r| r.free_over).is_some_and(|over| deal.price.amount >= over) {
+        Some(FreeShipping::Threshold)
+    } else if rule
+        .and_then(|r| r.free_with_membership.as_deref())
+        .is_some_and(|needed| context.memberships.iter().any(|m| m.trim().eq_ignore_ascii_case(needed)))
+    {
+        Some(FreeShipping::Membership)
+    } else {
+        None
+    };
+
+    let base = match (free, rule) {
+        (Some(_), _) | (None, None) => Decimal::ZERO,
+        (None, Some(rule)) => rule.base_cost,
+    };
+    ShippingEstimate {
+        cost: Money::new(base + surcharge.amount, currency),
+        free,
+        surcharge,
+        known: rule.is_some() || deal.free_shipping,
+    }
+}
+
+/// Cheapest delivered price first; at the same price, deals with known shipping go first.
+///
+/// Amounts are compared as they are, so the deals should share a currency.
+pub fn rank_by_delivered_price(
+    deals: Vec<Deal>,
+    rules: &BTreeMap<MerchantDomain, ShippingRule>,
+    context: &ShippingContext,
+) -> Vec<DeliveredPrice> {
+    let mut ranked: Vec<DeliveredPrice> = deals
+        .into_iter()
+        .map(|deal| {
+            let shipping = estimate(&deal, rules.get(&deal.merchant_domain), context);
+            let delivered_price = Money::new(deal.price.amount + shipping.cost.amount, deal.price.currency);
+            DeliveredPrice {
+                deal,
+                shipping,
+                delivered_price,
+            }
+        })
+        .collect();
+    ranked.sort_by(|a, b| {
+        a.delivered_price
+            .amount
+            .cmp(&b.delivered_price.amount)
+            .then_with(|| b.shipping.known.cmp(&a.shipping.known))
+    });
+    ranked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::deal::DealStatus;
+    use crate::models::domain::Currency;
+    use chrono::Utc;
+    use rust_decimal_macros::dec;
+
+    fn deal(id: &str, domain: &str, price: Decimal) -> Deal {
+        Deal {
+            id: id.to_string(),
+            product_id: "prod_test".to_string(),
+            title: "Test".to_string(),
+            store: domain.to_string(),
+            merchant_domain: MerchantDomain::parse(domain).unwrap(),
+            category: "electronics".to_string(),
+            brand: None,
+            price: Money::usd(price),
+            original_price: Money::usd(price),
+            discount: 0.0,
+            image_url: None,
+            product_url: None,
+            image_hash: None,
+            free_shipping: false,
+            posted_at: Utc::now(),
+            score: None,
+            honest_discount: None,
+            discount_inflated: false,
+            verified_at: None,
+            price_mismatch: false,
+            status: DealStatus::Active,
+            status_confidence: None,
+            events: Vec::new(),
+            tags: Vec::new(),
+        }
+    }
+
+    fn rule(base_cost: Decimal, free_over: Option<Decimal>, membership: Option<&str>) -> ShippingRule {
+        ShippingRule {
+            currency: Currency::USD,
+            base_cost,
+            free_over,
+            free_with_membership: membership.map(String::from),
+            regional_surcharges: BTreeMap::from([("HI".to_string(), dec!(15))]),
+        }
+    }
+
+    #[test]
+    fn test_estimate_waives_base_cost_but_keeps_surcharge() {
+        let context = ShippingContext {
+            region: Some("hi".to_string()),
+            memberships: vec!["Prime".to_string()],
+        };
+        let shipping_rule = rule(dec!(5.99), Some(dec!(35)), Some("prime"));
+
+        let cheap = estimate(&deal("a", "shop.com", dec!(20)), Some(&shipping_rule), &context);
+        assert_eq!(cheap.free, Some(FreeShipping::Membership));
+        assert_eq!(cheap.cost, Money::usd(dec!(15)));
+
+        let over = estimate(&deal("b", "shop.com", dec!(40)), Some(&shipping_rule), &ShippingContext::default());
+        assert_eq!((over.free, over.cost), (Some(FreeShipping::Threshold), Money::usd(dec!(0))));
+
+        let unknown = estimate(&deal("c", "shop.com", dec!(20)), None, &context);
+        assert!(!unknown.known);
+        assert_eq!(unknown.cost, Money::usd(dec!(0)));
+    }
+
+    #[test]
+    fn test_ranks_by_delivered_price() {
+        let rules = BTreeMap::from([
+            (MerchantDomain::parse("cheap.com").unwrap(), rule(dec!(12), None, None)),
+            (MerchantDomain::parse("free.com").unwrap(), rule(dec!(8), Some(dec!(25)), None)),
+        ]);
+        let deals = vec![
+            deal("sticker", "cheap.com", dec!(24)),
+            deal("delivered", "free.com", dec!(30)),
+            deal("unknown", "other.com", dec!(30)),
+        ];
+
+        let ranked = rank_by_delivered_price(deals, &rules, &ShippingContext::default());
+        let order: Vec<(&str, Decimal)> = ranked.iter().map(|r| (r.deal.id.as_str(), r.delivered_price.amount)).collect();
+        assert_eq!(order, vec![("delivered", dec!(30)), ("unknown", dec!(30)), ("sticker", dec!(36))]);
+    }
+}
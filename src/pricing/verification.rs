@@ -0,0 +1,274 @@
+//! Product page checks for high-value deals
+//!
+//! A partner feed can advertise a price the merchant never charged. When
+//! `PRICE_VERIFICATION_MIN_PRICE` is set, deals at or above that price, and deals
+//! whose discount looks too good to be true, have their `product_url` loaded and
+//! searched for the advertised price and discount. The outcome is kept on the deal
+//! as `verified_at` and `price_mismatch`.
+//!
+//! Too-good deals that could not be verified, or did not match, are still stored but
+//! are held back from the deal stream unless `PRICE_VERIFICATION_HOLD` is `false`.
+
+use std::sync::Arc;
+
+use chrono::TimeDelta;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+
+use crate::clock::{self, Clock};
+use crate::coupon_engine::opt_out::{BlockedAt, OptOutRegistry};
+use crate::coupon_engine::scraper::Fetcher;
+use crate::models::deal::Deal;
+
+/// How long a check holds for a deal whose prices have not changed
+pub const VERIFIED_FOR: TimeDelta = TimeDelta::hours(24);
+
+#[derive(Debug, Clone)]
+pub struct VerificationPolicy {
+    /// Deals at or above this price, in their own currency, are verified
+    pub min_price: Decimal,
+    /// Advertised discounts (percent) from which a deal is too good to stream unverified
+    pub too_good_discount: f64,
+    /// Keep too-good deals off the stream until their page confirms them
+    pub hold_unverified: bool,
+}
+
+impl VerificationPolicy {
+    /// Read `PRICE_VERIFICATION_MIN_PRICE`, `PRICE_VERIFICATION_TOO_GOOD_DISCOUNT`
+    /// (default 70) and `PRICE_VERIFICATION_HOLD` (default true); none without a
+    /// minimum price
+    pub fn from_env() -> Option<Self> {
+        let var = |name: &str| std::env::var(name).ok().map(|value| value.trim().to_string());
+        Some(Self {
+            min_price: var("PRICE_VERIFICATION_MIN_PRICE")?.parse().ok()?,
+            too_good_discount: var("PRICE_VERIFICATION_TOO_GOOD_DISCOUNT")
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(70.0),
+            hold_unverified: var("PRICE_VERIFICATION_HOLD").is_none_or(|value| value != "false"),
+        })
+    }
+}
+
+pub struct PriceVerifier {
+    fetcher: Arc<dyn Fetcher>,
+    policy: Option<VerificationPolicy>,
+    opt_outs: Option<Arc<OptOutRegistry>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl PriceVerifier {
+    /// Load product pages through `fetcher`, or verify nothing without a `policy`
+    pub fn new(fetcher: Arc<dyn Fetcher>, policy: Option<VerificationPolicy>) -> Self {
+        Self {
+            fetcher,
+            policy,
+            opt_outs: None,
+            clock: clock::system(),
+        }
+    }
+
+    pub fn disabled() -> Self {
+        Self::new(Arc::new(crate::coupon_engine::scraper::OfflineFetcher), None)
+    }
+
+    /// Skip the pages of merchants that opted out of scraping
+    pub fn with_opt_outs(mut self, opt_outs: Arc<OptOutRegistry>) -> Self {
+        self.opt_outs = Some(opt_outs);
+        self
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.policy.is_some()
+    }
+
+    /// Whether `deal` stays off the stream: too good to be true and not confirmed
+    pub fn holds(&self, deal: &Deal) -> bool {
+        self.policy.as_ref().is_some_and(|policy| {
+            policy.hold_unverified
+                && deal.discount >= policy.too_good_discount
+                && (deal.verified_at.is_none() || deal.price_mismatch)
+        })
+    }
+
+    /// Check `deal`'s product page when the policy covers it. A check of `previous`
+    /// within [`VERIFIED_FOR`] and for the same prices is reused instead.
+    pub async fn verify(&self, previous: Option<&Deal>, deal: &mut Deal) {
+        let Some(policy) = &self.policy else {
+            return;
+        };
+        // Feeds do not get to vouch for their own prices
+        deal.verified_at = None;
+        deal.price_mismatch = false;
+        if deal.price.amount < policy.min_price && deal.discount < policy.too_good_discount {
+            return;
+        }
+
+        let now = self.clock.now();
+        let checked = previous.filter(|previous| {
+            previous.price == deal.price
+                && previous.original_price == deal.original_price
+                && previous.verified_at.is_some_and(|at| now - at < VERIFIED_FOR)
+        });
+        if let Some(previous) = checked {
+            deal.verified_at = previous.verified_at;
+            deal.price_mismatch = previous.price_mismatch;
+            return;
+        }
+
+        let Some(url) = deal.product_url.clone() else {
+            return;
+        };
+        if self.opt_outs.as_ref().is_some_and(|opt_outs| opt_outs.blocks(&url, BlockedAt::PriceCheck)) {
+            return;
+        }
+        match self.fetcher.fetch(&url, None).await {
+            Ok(page) => {
+                deal.verified_at = Some(now);
+                deal.price_mismatch = !shows_prices(&page_text(&page), deal);
+            }
+            Err(e) => tracing::warn!(url = %url, deal = %deal.id, error = %e, "Could not load a deal page to verify it"),
+        }
+    }
+}
+
+/// The text of an HTML page, script contents (e.g. JSON-LD offers) included
+fn page_text(page: &str) -> String {
+    scraper::Html::parse_document(page).root_element().text().collect::<Vec<_>>().join(" ")
+}
+
+/// Whether `text` shows `deal`'s price and, for a discounted deal, its original
+/// price or discount percentage
+fn shows_prices(text: &str, deal: &Deal) -> bool {
+    let shows = |amount: Decimal| price_forms(amount).iter().any(|form| appears(text, form));
+    let percent = deal.discount.round();
+    shows(deal.price.amount)
+        && (deal.original_price.amount <= deal.price.amount
+            || shows(deal.original_price.amount)
+            || [format!("{}%", percent), format!("{} %", percent)].iter().any(|form| appears(text, form)))
+}
+
+/// `amount` as shops write it: `1299.99`, `1,299.99`, `1.299,99`, `1 299,99`, and
+/// without the cents when there are none
+fn price_forms(amount: Decimal) -> Vec<String> {
+    let amount = amount.round_dp(2);
+    let whole = amount.trunc().to_string();
+    let cents = ((amount - amount.trunc()) * Decimal::ONE_HUNDRED).to_u32().unwrap_or(0);
+
+    let mut forms = Vec::new();
+    for (thousands, decimal) in [("", "."), (",", "."), ("", ","), (".", ","), (" ", ","), ("\u{a0}", ",")] {
+        let whole = grouped(&whole, thousands);
+        forms.push(format!("{}{}{:02}", whole, decimal, cents));
+        if cents == 0 {
+            forms.push(whole);
+        }
+    }
+    forms.sort();
+    forms.dedup();
+    forms
+}
+
+fn grouped(digits: &str, separator: &str) -> String {
+    let mut out = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            out.push_str(separator);
+        }
+        out.push(digit);
+    }
+    out
+}
+
+/// Whether `number` occurs in `text` as a whole number, not part of a longer one
+fn appears(text: &str, number: &str) -> bool {
+    text.match_indices(number).any(|(at, _)| {
+        !continues_number(text[..at].chars().rev()) && !continues_number(text[at + number.len()..].chars())
+    })
+}
+
+fn continues_number(mut chars: impl Iterator<Item = char>) -> bool {
+    match chars.next() {
+        Some(c) if c.is_ascii_digit() => true,
+        Some('.' | ',') => chars.next().is_some_and(|c| c.is_ascii_digit()),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coupon_engine::proxy_manager::ProxyConfig;
+    use crate::models::domain::Money;
+    use crate::storage::deal_store::DealStore;
+    use axum::async_trait;
+    use rust_decimal_macros::dec;
+
+    struct Page(&'static str);
+
+    #[async_trait]
+    impl Fetcher for Page {
+        async fn fetch(&self, _url: &str, _proxy: Option<&ProxyConfig>) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+            Ok(self.0.to_string())
+        }
+    }
+
+    fn verifier(page: &'static str) -> PriceVerifier {
+        let policy = VerificationPolicy {
+            min_price: dec!(500),
+            too_good_discount: 70.0,
+            hold_unverified: true,
+        };
+        PriceVerifier::new(Arc::new(Page(page)), Some(policy))
+    }
+
+    async fn deal(price: Decimal, original_price: Decimal, discount: f64) -> Deal {
+        let mut deal = DealStore::with_sample_data().list().await.remove(0);
+        deal.price = Money::usd(price);
+        deal.original_price = Money::usd(original_price);
+        deal.discount = discount;
+        deal.product_url = Some("https://shop.example.com/p/1".to_string());
+        deal
+    }
+
+    #[tokio::test]
+    async fn test_checks_the_page_for_the_advertised_prices() {
+        let page = r#"<html><body><span class="price">$1,299.99</span> <s>$1,999.00</s>
+            <script type="application/ld+json">{"offers": {"price": "1299.99"}}</script></body></html>"#;
+
+        let mut shown = deal(dec!(1299.99), dec!(1999), 35.0).await;
+        verifier(page).verify(None, &mut shown).await;
+        assert!(shown.verified_at.is_some());
+        assert!(!shown.price_mismatch);
+
+        // The page shows a different price; the deal is too good to stream
+        let mut wrong = deal(dec!(299.99), dec!(1999), 85.0).await;
+        let checker = verifier(page);
+        checker.verify(None, &mut wrong).await;
+        assert!(wrong.verified_at.is_some() && wrong.price_mismatch);
+        assert!(checker.holds(&wrong));
+
+        // European formatting, and a discount shown only as a percentage
+        let mut euro = deal(dec!(1299), dec!(2000), 35.0).await;
+        verifier("<p>1.299,00 € (-35 %)</p>").verify(None, &mut euro).await;
+        assert!(!euro.price_mismatch);
+
+        // Cheap, plausible deals are not loaded, whatever the feed says
+        let mut cheap = deal(dec!(19.99), dec!(24.99), 20.0).await;
+        cheap.verified_at = Some(chrono::Utc::now());
+        verifier(page).verify(None, &mut cheap).await;
+        assert_eq!((cheap.verified_at, cheap.price_mismatch), (None, false));
+        assert!(!verifier(page).holds(&cheap));
+
+        // A recent check of the same prices is reused
+        let mut again = deal(dec!(1299.99), dec!(1999), 35.0).await;
+        verifier("<p>gone</p>").verify(Some(&shown), &mut again).await;
+        assert_eq!((again.verified_at, again.price_mismatch), (shown.verified_at, false));
+
+        assert!(!appears("$11,299.99", "1,299.99"));
+        assert!(!appears("1299.995", "1299.99"));
+    }
+}
@@ -0,0 +1,338 @@
+//! PII scrubbing on ingestion
+//!
+//! User-submitted coupons and community comments sometimes carry email addresses,
+//! phone numbers or names. [`Scrubber`] removes them before the text is stored or
+//! analysed: emails and phone numbers are found by pattern, and when the tenant opts
+//! in (`ner`) names and addresses are found by a pluggable [`EntityRecognizer`].
+//!
+//! What happens to a match is set per tenant and per field by a [`PiiPolicy`] (see
+//! [`crate::tenant::TenantRecord::pii`]). Every scrubbed entity is counted per
+//! tenant, field and kind for the compliance audit at `GET /admin/pii`.
+
+pub mod ner;
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::Arc;
+
+use axum::async_trait;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use utoipa::ToSchema;
+
+use crate::tenant::TenantId;
+
+/// Fields scrubbed on ingestion, as named in [`PiiPolicy::fields`]
+pub const COUPON_TITLE: &str = "coupon.title";
+pub const COUPON_DESCRIPTION: &str = "coupon.description";
+pub const COMMENT_BODY: &str = "comment.body";
+pub const COMMENT_AUTHOR: &str = "comment.author";
+
+/// Digits a phone number has at least and at most (E.164)
+const PHONE_DIGITS: std::ops::RangeInclusive<usize> = 9..=15;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PiiKind {
+    Email,
+    Phone,
+    Person,
+    Address,
+}
+
+impl PiiKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PiiKind::Email => "email",
+            PiiKind::Phone => "phone",
+            PiiKind::Person => "person",
+            PiiKind::Address => "address",
+        }
+    }
+}
+
+/// What happens to PII found in a field
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldPolicy {
+    /// Replaced by a placeholder such as `[email]`
+    #[default]
+    Redact,
+    /// Partly hidden, e.g. `j***@example.com` or `*******4567`
+    Mask,
+    /// Left as it is
+    Keep,
+}
+
+/// A tenant's scrubbing configuration
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PiiPolicy {
+    /// Policy for fields not listed in `fields`
+    #[serde(default)]
+    pub default: FieldPolicy,
+    /// Per-field policies, e.g. `{"comment.author": "mask"}`
+    #[serde(default)]
+    pub fields: HashMap<String, FieldPolicy>,
+    /// Also look for names and addresses with the entity recognizer
+    #[serde(default)]
+    pub ner: bool,
+}
+
+impl PiiPolicy {
+    pub fn for_field(&self, field: &str) -> FieldPolicy {
+        self.fields.get(field).copied().unwrap_or(self.default)
+    }
+}
+
+/// An entity found by an [`EntityRecognizer`]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Entity {
+    pub text: String,
+    pub kind: PiiKind,
+}
+
+#[derive(Debug, Clone)]
+pub struct RecognizerError(pub String);
+
+impl fmt::Display for RecognizerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "entity recognition failed: {}", self.0)
+    }
+}
+
+impl std::error::Error for RecognizerError {}
+
+/// Named-entity recognition for the PII patterns cannot find
+#[async_trait]
+pub trait EntityRecognizer: Send + Sync {
+    /// The entities in each text, in the same order
+    async fn recognize(&self, texts: &[String]) -> Result<Vec<Vec<Entity>>, RecognizerError>;
+}
+
+/// Scrubbed entities for one tenant, field and kind
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct ScrubCount {
+    pub tenant: String,
+    pub field: String,
+    pub kind: PiiKind,
+    pub count: u64,
+}
+
+pub struct Scrubber {
+    email: Regex,
+    phone: Regex,
+    recognizer: Option<Arc<dyn EntityRecognizer>>,
+    /// (tenant, field, kind) to scrubbed entities
+    audit: Mutex<BTreeMap<(String, String, PiiKind), u64>>,
+}
+
+impl Scrubber {
+    pub fn new() -> Self {
+        Self {
+            email: Regex::new(r"[A-Za-z0-9._%+-]+@[A-Za-z0-9-]+(?:\.[A-Za-z0-9-]+)*\.[A-Za-z]{2,}").unwrap(),
+            phone: Regex::new(r"(?:\+\d|\(\d|\b\d)[\d\s().-]{7,}\d\b").unwrap(),
+            recognizer: None,
+            audit: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn with_recognizer(mut self, recognizer: Arc<dyn EntityRecognizer>) -> Self {
+        self.recognizer = Some(recognizer);
+        self
+    }
+
+    /// Pattern scrubbing, plus the HTTP recognizer when `PII_NER_URL` is set
+    pub fn from_env() -> Self {
+        match ner::HttpEntityRecognizer::from_env() {
+            Some(recognizer) => Self::new().with_recognizer(Arc::new(recognizer)),
+            None => Self::new(),
+        }
+    }
+
+    /// Scrub `fields` (name and text) in place under `tenant`'s `policy`
+    pub async fn scrub(&self, tenant: &TenantId, policy: &PiiPolicy, fields: Vec<(&str, &mut String)>) {
+        let fields: Vec<(&str, FieldPolicy, &mut String)> = fields
+            .into_iter()
+            .map(|(field, text)| (field, policy.for_field(field), text))
+            .filter(|(_, field_policy, text)| *field_policy != FieldPolicy::Keep && !text.is_empty())
+            .collect();
+        if fields.is_empty() {
+            return;
+        }
+
+        let mut entities = vec![Vec::new(); fields.len()];
+        if let (true, Some(recognizer)) = (policy.ner, &self.recognizer) {
+            let texts: Vec<String> = fields.iter().map(|(_, _, text)| text.to_string()).collect();
+            match recognizer.recognize(&texts).await {
+                Ok(found) if found.len() == texts.len() => entities = found,
+                Ok(_) => tracing::warn!("Entity recognizer answered for the wrong number of texts; scrubbing by pattern only"),
+                // Pattern scrubbing still applies; ingestion should not wait on the recognizer
+                Err(e) => tracing::warn!(error = %e, "Entity recognizer failed; scrubbing by pattern only"),
+            }
+        }
+
+        let mut counts: HashMap<(&str, PiiKind), u64> = HashMap::new();
+        for ((field, field_policy, text), entities) in fields.into_iter().zip(entities) {
+            let spans = self.find(text, &entities);
+            if spans.is_empty() {
+                continue;
+            }
+            let mut scrubbed = String::with_capacity(text.len());
+            let mut last = 0;
+            for (start, end, kind) in spans {
+                scrubbed.push_str(&text[last..start]);
+                scrubbed.push_str(&replacement(&text[start..end], kind, field_policy));
+                *counts.entry((field, kind)).or_default() += 1;
+                last = end;
+            }
+            scrubbed.push_str(&text[last..]);
+            *text = scrubbed;
+        }
+
+        if !counts.is_empty() {
+            let mut audit = self.audit.lock().await;
+            for ((field, kind), count) in counts {
+                *audit.entry((tenant.0.clone(), field.to_string(), kind)).or_default() += count;
+            }
+        }
+    }
+
+    /// Non-overlapping PII spans in `text`, in order
+    fn find(&self, text: &str, entities: &[Entity]) -> Vec<(usize, usize, PiiKind)> {
+        let mut spans: Vec<(usize, usize, PiiKind)> = self
+            .email
+            .find_iter(text)
+            .map(|m| (m.start(), m.end(), PiiKind::Email))
+            .collect();
+        spans.extend(
+            self.phone
+                .find_iter(text)
+                .filter(|m| PHONE_DIGITS.contains(&m.as_str().chars().filter(char::is_ascii_digit).count()))
+                .map(|m| (m.start(), m.end(), PiiKind::Phone)),
+        );
+        for entity in entities.iter().filter(|e| !e.text.trim().is_empty()) {
+            spans.extend(
+                text.match_indices(entity.text.as_str())
+                    .map(|(start, found)| (start, start + found.len(), entity.kind)),
+            );
+        }
+
+        // Earlier spans win; of two starting together, the longer one
+        spans.sort_by_key(|&(start, end, _)| (start, std::cmp::Reverse(end)));
+        let mut kept: Vec<(usize, usize, PiiKind)> = Vec::with_capacity(spans.len());
+        for span in spans {
+            if kept.last().is_none_or(|last| span.0 >= last.1) {
+                kept.push(span);
+            }
+        }
+        kept
+    }
+
+    /// Entities scrubbed so far, by tenant, field and kind
+    pub async fn audit(&self) -> Vec<ScrubCount> {
+        self.audit
+            .lock()
+            .await
+            .iter()
+            .map(|((tenant, field, kind), count)| ScrubCount {
+                tenant: tenant.clone(),
+                field: field.clone(),
+                kind: *kind,
+                count: *count,
+            })
+            .collect()
+    }
+}
+
+impl Default for Scrubber {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn replacement(found: &str, kind: PiiKind, policy: FieldPolicy) -> String {
+    match policy {
+        FieldPolicy::Keep => found.to_string(),
+        FieldPolicy::Redact => format!("[{}]", kind.as_str()),
+        FieldPolicy::Mask => match kind {
+            PiiKind::Email => {
+                let (local, domain) = found.split_once('@').unwrap_or((found, ""));
+                format!("{}***@{}", local.chars().next().unwrap_or('*'), domain)
+            }
+            // Every digit but the last four
+            PiiKind::Phone => {
+                let digits = found.chars().filter(char::is_ascii_digit).count();
+                let mut seen = 0;
+                found
+                    .chars()
+                    .map(|c| match c.is_ascii_digit() {
+                        true => {
+                            seen += 1;
+                            if seen + 4 > digits { c } else { '*' }
+                        }
+                        false => c,
+                    })
+                    .collect()
+            }
+            PiiKind::Person | PiiKind::Address => format!("{}***", found.chars().next().unwrap_or('*')),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Names;
+
+    #[async_trait]
+    impl EntityRecognizer for Names {
+        async fn recognize(&self, texts: &[String]) -> Result<Vec<Vec<Entity>>, RecognizerError> {
+            Ok(texts
+                .iter()
+                .map(|text| match text.contains("Jane Doe") {
+                    true => vec![Entity {
+                        text: "Jane Doe".to_string(),
+                        kind: PiiKind::Person,
+                    }],
+                    false => Vec::new(),
+                })
+                .collect())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_scrubs_per_field_policy_and_counts() {
+        let scrubber = Scrubber::new().with_recognizer(Arc::new(Names));
+        let tenant = TenantId("acme".to_string());
+        let policy: PiiPolicy = serde_json::from_value(serde_json::json!({
+            "fields": {"comment.author": "mask", "coupon.title": "keep"},
+            "ner": true
+        }))
+        .unwrap();
+
+        let mut body = "Ask Jane Doe at jane.doe@mail.example.com or call +1 (415) 555-0142. SAVE20 for $20 off, ends 2024-12-31".to_string();
+        let mut author = "+44 20 7946 0958".to_string();
+        let mut title = "Email deals@shop.com".to_string();
+        scrubber
+            .scrub(&tenant, &policy, vec![(COMMENT_BODY, &mut body), (COMMENT_AUTHOR, &mut author), (COUPON_TITLE, &mut title)])
+            .await;
+
+        assert_eq!(body, "Ask [person] at [email] or call [phone]. SAVE20 for $20 off, ends 2024-12-31");
+        assert_eq!(author, "+** ** **** 0958");
+        assert_eq!(title, "Email deals@shop.com");
+
+        let audit = scrubber.audit().await;
+        let counts: Vec<(&str, PiiKind, u64)> = audit.iter().map(|c| (c.field.as_str(), c.kind, c.count)).collect();
+        assert_eq!(
+            counts,
+            vec![
+                (COMMENT_AUTHOR, PiiKind::Phone, 1),
+                (COMMENT_BODY, PiiKind::Email, 1),
+                (COMMENT_BODY, PiiKind::Phone, 1),
+                (COMMENT_BODY, PiiKind::Person, 1),
+            ]
+        );
+    }
+}
@@ -0,0 +1,73 @@
+//! Entity recognition through an HTTP API
+//!
+//! Configured by `PII_NER_URL` and, optionally, `PII_NER_API_KEY` (sent as a bearer
+//! token). The service receives `{"texts": [...]}` and answers `{"entities": [[{"text":
+//! "Jane Doe", "kind": "person"}], ...]}`, one list per text in the same order. Kinds
+//! other than `person` and `address` are ignored.
+
+use std::time::Duration;
+
+use axum::async_trait;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::{Entity, EntityRecognizer, PiiKind, RecognizerError};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct HttpEntityRecognizer {
+    client: reqwest::Client,
+    api_url: String,
+    api_key: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RecognizerResponse {
+    entities: Vec<Vec<Value>>,
+}
+
+impl HttpEntityRecognizer {
+    pub fn new(api_url: String, api_key: Option<String>) -> Self {
+        Self {
+            client: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+            api_url,
+            api_key,
+        }
+    }
+
+    pub fn from_env() -> Option<Self> {
+        let api_url = std::env::var("PII_NER_URL").ok()?;
+        Some(Self::new(api_url, std::env::var("PII_NER_API_KEY").ok()))
+    }
+}
+
+#[async_trait]
+impl EntityRecognizer for HttpEntityRecognizer {
+    async fn recognize(&self, texts: &[String]) -> Result<Vec<Vec<Entity>>, RecognizerError> {
+        let mut request = self.client.post(&self.api_url).json(&json!({ "texts": texts }));
+        if let Some(key) = &self.api_key {
+            request = request.bearer_auth(key);
+        }
+
+        let response = request
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| RecognizerError(e.to_string()))?;
+        let body: RecognizerResponse = response.json().await.map_err(|e| RecognizerError(e.to_string()))?;
+        Ok(body
+            .entities
+            .into_iter()
+            .map(|found| {
+                found
+                    .into_iter()
+                    .filter_map(|entity| serde_json::from_value::<Entity>(entity).ok())
+                    .filter(|entity| matches!(entity.kind, PiiKind::Person | PiiKind::Address))
+                    .collect()
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,358 @@
+//! Role-based access to the admin endpoints
+//!
+//! Every `/admin/*` endpoint requires one [`Permission`], and each [`Role`] grants
+//! a fixed set of them. Roles are assigned to a [`Subject`]: an API key (by its
+//! SHA-256, like tenant keys) or a user named by a trusted header. Users are only
+//! recognized when `ADMIN_USER_HEADER` names the header an authenticating proxy
+//! sets; a request carrying such a user is judged by the user's roles, not the
+//! key's. Anyone without an assignment is denied.
+//!
+//! Assignments are persisted to `ADMIN_ROLES_PATH` (default
+//! `data/admin_roles.json`), which is also how the first admin is set up. With
+//! `REDIS_URL` set they are shared instead, one field of the `admin_roles` hash per
+//! subject, and a subject's roles are read from Redis on every check, so a
+//! revocation through one instance holds on all of them at once; the first admin is
+//! then set with `HSET admin_roles key:<sha256> '["admin"]'`. Admins change
+//! assignments through `/admin/roles`; the last admin cannot be removed.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::http::{HeaderMap, HeaderName};
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+use utoipa::ToSchema;
+
+use crate::clock::{self, Clock};
+use crate::storage::persisted::{PersistedStore, StoreError};
+use crate::tenant::Caller;
+
+const REDIS_KEY: &str = "admin_roles";
+const STORE_NAME: &str = "admin role assignments";
+/// How often shared assignments are re-read for the listing
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+type Assignments = BTreeMap<Subject, BTreeSet<Role>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Admin,
+    /// Runs the scraping sources, moderation and curated content
+    Editor,
+    /// Reads reports
+    Analyst,
+    /// Moderates partner submissions
+    PartnerSupport,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    /// Yield, SLA, canary, redirect, parser, stage and experiment reports
+    ViewReports,
+    /// Domain profiles, source licenses, canaries, liveness checks, dead letters,
+    /// reprocessing, seeding
+    ManageSources,
+    /// Partner coupon review
+    Moderate,
+    /// Collections and shipping rules
+    ManageContent,
+    ManageExperiments,
+    /// Exports of user data and the PII audit
+    ExportUserData,
+    ManageRoles,
+    /// Issuing and revoking partner API keys
+    ManageApiKeys,
+}
+
+impl Role {
+    pub fn permissions(self) -> &'static [Permission] {
+        use Permission::*;
+        match self {
+            Role::Admin => &[
+                ViewReports,
+                ManageSources,
+                Moderate,
+                ManageContent,
+                ManageExperiments,
+                ExportUserData,
+                ManageRoles,
+                ManageApiKeys,
+            ],
+            Role::Editor => &[ViewReports, ManageSources, Moderate, ManageContent, ManageExperiments],
+            Role::Analyst => &[ViewReports],
+            Role::PartnerSupport => &[ViewReports, Moderate],
+        }
+    }
+}
+
+/// Who roles are assigned to: `key:<sha256>` or `user:<id>`
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(try_from = "String", into = "String")]
+pub enum Subject {
+    /// SHA-256 (hex) of an API key
+    ApiKey(String),
+    User(String),
+}
+
+impl fmt::Display for Subject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Subject::ApiKey(hash) => write!(f, "key:{}", hash),
+            Subject::User(id) => write!(f, "user:{}", id),
+        }
+    }
+}
+
+impl Subject {
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        match raw.trim().split_once(':') {
+            Some(("key", hash)) if hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit()) => {
+                Ok(Subject::ApiKey(hash.to_ascii_lowercase()))
+            }
+            Some(("user", id)) if !id.trim().is_empty() => Ok(Subject::User(id.trim().to_string())),
+            _ => Err(format!("'{}' is not key:<sha256 hex> or user:<id>", raw)),
+        }
+    }
+}
+
+impl TryFrom<String> for Subject {
+    type Error = String;
+
+    fn try_from(raw: String) -> Result<Self, Self::Error> {
+        Subject::parse(&raw)
+    }
+}
+
+impl From<Subject> for String {
+    fn from(subject: Subject) -> Self {
+        subject.to_string()
+    }
+}
+
+/// Why a request was refused
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AccessDenied {
+    /// No API key or trusted user
+    Anonymous,
+    /// Known, but none of its roles grants the permission
+    Forbidden { subject: Subject, required: Option<Permission> },
+}
+
+pub struct AccessControl {
+    assignments: RwLock<Assignments>,
+    user_header: Option<HeaderName>,
+    store: PersistedStore<Assignments>,
+    clock: Arc<dyn Clock>,
+}
+
+impl AccessControl {
+    /// Assignments persisted to `path`, or kept in memory only
+    pub fn new(path: Option<PathBuf>) -> Self {
+        Self::with_store(PersistedStore::new(STORE_NAME, REDIS_KEY, path))
+    }
+
+    /// Assignments shared through Redis
+    pub fn shared(redis_url: &str) -> Result<Self, StoreError> {
+        Ok(Self::with_store(PersistedStore::shared(STORE_NAME, REDIS_KEY, redis_url)?))
+    }
+
+    fn with_store(store: PersistedStore<Assignments>) -> Self {
+        Self {
+            assignments: RwLock::new(BTreeMap::new()),
+            user_header: None,
+            store: store.pretty(),
+            clock: clock::system(),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Trust `header` to name the user an authenticating proxy signed in
+    pub fn with_user_header(mut self, header: HeaderName) -> Self {
+        self.user_header = Some(header);
+        self
+    }
+
+    /// Assignments shared through `REDIS_URL` when set, otherwise loaded from
+    /// `ADMIN_ROLES_PATH`; users from the `ADMIN_USER_HEADER` header
+    pub async fn from_env() -> Self {
+        let mut access = Self::with_store(PersistedStore::from_env(STORE_NAME, REDIS_KEY, "ADMIN_ROLES_PATH", "data/admin_roles.json"));
+        match std::env::var("ADMIN_USER_HEADER").map(|name| HeaderName::try_from(name.trim())) {
+            Ok(Ok(header)) => access = access.with_user_header(header),
+            Ok(Err(e)) => tracing::warn!(error = %e, "Ignoring ADMIN_USER_HEADER"),
+            Err(_) => {}
+        }
+        if let Err(e) = access.reload().await {
+            tracing::warn!(error = %e, "Starting without admin role assignments");
+        }
+        access
+    }
+
+    /// Replace the local copy with the stored assignments
+    pub async fn reload(&self) -> Result<(), StoreError> {
+        if let Some(stored) = self.store.load_map(REDIS_KEY).await? {
+            *self.assignments.write().await = stored;
+        }
+        Ok(())
+    }
+
+    /// Re-read shared assignments every [`REFRESH_INTERVAL`], so the listing shows
+    /// changes made through other instances
+    pub async fn start_background_tasks(self: Arc<Self>) {
+        self.store.refresh_every(self.clock.as_ref(), REFRESH_INTERVAL, || self.reload()).await
+    }
+
+    /// The subject a request acts as: its trusted user, else its API key
+    pub fn subject(&self, caller: Option<&Caller>, headers: &HeaderMap) -> Option<Subject> {
+        let user = self
+            .user_header
+            .as_ref()
+            .and_then(|header| headers.get(header))
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|user| !user.is_empty());
+        match user {
+            Some(user) => Some(Subject::User(user.to_string())),
+            None => caller.and_then(|caller| caller.api_key.clone()).map(Subject::ApiKey),
+        }
+    }
+
+    /// `subject`'s roles; read from Redis when shared, so changes made through other
+    /// instances apply at once
+    pub async fn roles(&self, subject: &Subject) -> BTreeSet<Role> {
+        if self.store.is_shared() {
+            match self.store.hash_get(REDIS_KEY, &subject.to_string()) {
+                Ok(roles) => return roles.unwrap_or_default(),
+                Err(e) => tracing::warn!(%subject, error = %e, "Failed to read admin roles; using the local copy"),
+            }
+        }
+        self.assignments.read().await.get(subject).cloned().unwrap_or_default()
+    }
+
+    /// Whether `subject` may act with `required`; `None` is required by no role,
+    /// so it is always refused
+    pub async fn authorize(&self, subject: Option<Subject>, required: Option<Permission>) -> Result<Subject, AccessDenied> {
+        let subject = subject.ok_or(AccessDenied::Anonymous)?;
+        let granted = match required {
+            Some(permission) => self.roles(&subject).await.iter().any(|role| role.permissions().contains(&permission)),
+            None => false,
+        };
+        match granted {
+            true => Ok(subject),
+            false => Err(AccessDenied::Forbidden { subject, required }),
+        }
+    }
+
+    pub async fn assignments(&self) -> BTreeMap<Subject, BTreeSet<Role>> {
+        self.assignments.read().await.clone()
+    }
+
+    /// Replace `subject`'s roles; no roles removes the assignment
+    pub async fn assign(&self, subject: Subject, roles: BTreeSet<Role>) -> Result<BTreeSet<Role>, String> {
+        let _write = self.store.write_lock().await;
+        // Another instance may have changed shared assignments since the last refresh
+        let assignments = match self.store.load_map(REDIS_KEY).await {
+            Ok(Some(stored)) => stored,
+            Ok(None) => self.assignments.read().await.clone(),
+            Err(e) => {
+                tracing::warn!(error = %e, "Failed to read admin role assignments; changing the local copy");
+                self.assignments.read().await.clone()
+            }
+        };
+        let mut updated = assignments.clone();
+        match roles.is_empty() {
+            true => updated.remove(&subject),
+            false => updated.insert(subject.clone(), roles.clone()),
+        };
+        let has_admin = |assignments: &Assignments| assignments.values().any(|roles| roles.contains(&Role::Admin));
+        if has_admin(&assignments) && !has_admin(&updated) {
+            return Err("the last admin cannot be removed".to_string());
+        }
+        let record = updated.get(&subject).cloned();
+        self.store.persist_field(REDIS_KEY, &subject.to_string(), record.as_ref(), || updated.clone()).await;
+        *self.assignments.write().await = updated;
+        Ok(roles)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tenant::TenantId;
+
+    fn caller(key: Option<&str>) -> Caller {
+        Caller {
+            tenant: TenantId("default".to_string()),
+            sandbox: false,
+            api_key: key.map(str::to_string),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_roles_grant_permissions_and_everyone_else_is_denied() {
+        let access = AccessControl::new(None).with_user_header(HeaderName::from_static("x-forwarded-user"));
+        let admin_key = "a".repeat(64);
+        let admin = Subject::parse(&format!("key:{}", admin_key.to_uppercase())).unwrap();
+        assert_eq!(admin, Subject::ApiKey(admin_key.clone()));
+        assert!(Subject::parse("key:abc").is_err());
+        assert!(Subject::parse("team:ops").is_err());
+        access.assign(admin.clone(), BTreeSet::from([Role::Admin])).await.unwrap();
+        access.assign(Subject::User("ana".to_string()), BTreeSet::from([Role::Analyst])).await.unwrap();
+
+        let mut headers = HeaderMap::new();
+        let subject = access.subject(Some(&caller(Some(&admin_key))), &headers);
+        assert_eq!(subject, Some(admin.clone()));
+        assert!(access.authorize(subject.clone(), Some(Permission::ManageRoles)).await.is_ok());
+        // Routes without a permission are refused even to admins
+        assert!(access.authorize(subject, None).await.is_err());
+        assert_eq!(access.authorize(None, Some(Permission::ViewReports)).await, Err(AccessDenied::Anonymous));
+
+        // A trusted user is judged by their own roles, whatever key they came with
+        headers.insert("x-forwarded-user", "ana".parse().unwrap());
+        let ana = access.subject(Some(&caller(Some(&admin_key))), &headers);
+        assert!(access.authorize(ana.clone(), Some(Permission::ViewReports)).await.is_ok());
+        assert_eq!(
+            access.authorize(ana, Some(Permission::Moderate)).await,
+            Err(AccessDenied::Forbidden {
+                subject: Subject::User("ana".to_string()),
+                required: Some(Permission::Moderate),
+            })
+        );
+        let unknown = access.subject(Some(&caller(Some(&"b".repeat(64)))), &HeaderMap::new());
+        assert!(access.authorize(unknown, Some(Permission::ViewReports)).await.is_err());
+
+        assert!(access.assign(admin.clone(), BTreeSet::new()).await.is_err());
+        assert_eq!(access.roles(&admin).await, BTreeSet::from([Role::Admin]));
+        let json = serde_json::to_value(access.assignments().await).unwrap();
+        assert_eq!(json["user:ana"], serde_json::json!(["analyst"]));
+    }
+
+    #[tokio::test]
+    async fn test_assignments_are_reloaded_from_the_store() {
+        let path = std::env::temp_dir().join(format!("admin_roles_{}.json", uuid::Uuid::new_v4()));
+        let first = AccessControl::new(Some(path.clone()));
+        let admin = Subject::ApiKey("a".repeat(64));
+        let ana = Subject::User("ana".to_string());
+        first.assign(admin.clone(), BTreeSet::from([Role::Admin])).await.unwrap();
+        first.assign(ana.clone(), BTreeSet::from([Role::Analyst])).await.unwrap();
+
+        let second = AccessControl::new(Some(path.clone()));
+        second.reload().await.unwrap();
+        assert_eq!(second.roles(&ana).await, BTreeSet::from([Role::Analyst]));
+
+        // A revocation is kept and reaches an instance once it reloads
+        first.assign(ana.clone(), BTreeSet::new()).await.unwrap();
+        second.reload().await.unwrap();
+        assert!(second.roles(&ana).await.is_empty());
+        assert_eq!(second.roles(&admin).await, BTreeSet::from([Role::Admin]));
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,87 @@
+//! Deal embeddings for item-item similarity
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use crate::models::deal::Deal;
+
+pub trait Embedder: Send + Sync {
+    /// L2-normalised embedding of the deal
+    fn embed(&self, deal: &Deal) -> Vec<f32>;
+    /// L2-normalised embedding of free text (e.g. a search query) in the same space
+    fn embed_text(&self, text: &str) -> Vec<f32>;
+}
+
+/// Feature-hashed bag of words over title, category, brand and store.
+///
+/// Cheap and dependency-free; good enough to group "65-inch OLED TV" with other
+/// TVs until a learned text encoder is plugged in.
+pub struct HashingEmbedder {
+    dimensions: usize,
+}
+
+impl HashingEmbedder {
+    pub fn new(dimensions: usize) -> Self {
+        Self {
+            dimensions: dimensions.max(8),
+        }
+    }
+
+    fn add_token(&self, vector: &mut [f32], token: &str, weight: f32) {
+        let mut hasher = DefaultHasher::new();
+        token.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        let index = (hash % self.dimensions as u64) as usize;
+        let sign = if (hash >> 63) == 0 { 1.0 } else { -1.0 };
+        vector[index] += sign * weight;
+    }
+
+    fn add_text(&self, vector: &mut [f32], text: &str) {
+        for token in text.to_lowercase().split(|c: char| !c.is_alphanumeric()).filter(|t| t.len() > 1) {
+            // Crude plural folding so "laptops" and "laptop" share a bucket
+            let token = if token.len() > 3 { token.trim_end_matches('s') } else { token };
+            self.add_token(vector, token, 1.0);
+        }
+    }
+}
+
+fn normalize(mut vector: Vec<f32>) -> Vec<f32> {
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|x| *x /= norm);
+    }
+    vector
+}
+
+impl Default for HashingEmbedder {
+    fn default() -> Self {
+        Self::new(128)
+    }
+}
+
+impl Embedder for HashingEmbedder {
+    fn embed(&self, deal: &Deal) -> Vec<f32> {
+        let mut vector = vec![0.0; self.dimensions];
+
+        self.add_text(&mut vector, &deal.title);
+        self.add_token(&mut vector, &format!("category:{}", deal.category), 1.5);
+        if let Some(brand) = &deal.brand {
+            self.add_token(&mut vector, &format!("brand:{}", brand.to_lowercase()), 1.0);
+        }
+        self.add_token(&mut vector, &format!("store:{}", deal.merchant_domain), 0.3);
+
+        normalize(vector)
+    }
+
+    fn embed_text(&self, text: &str) -> Vec<f32> {
+        let mut vector = vec![0.0; self.dimensions];
+        self.add_text(&mut vector, text);
+        normalize(vector)
+    }
+}
+
+/// Cosine similarity of two L2-normalised vectors
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
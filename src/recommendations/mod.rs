@@ -0,0 +1,279 @@
+//! "You may also like" recommendations
+//!
+//! Similar deals come from nearest neighbours over deal embeddings; complementary
+//! ("frequently bought with") deals come from co-interaction counts within a
+//! session. Both indexes are maintained incrementally by a background job;
+//! sessions idle for [`SESSION_IDLE`] are forgotten.
+
+pub mod embeddings;
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::Instant;
+
+use tokio::sync::{Mutex, RwLock};
+use tokio::time::{interval, Duration};
+
+use crate::clock::{self, Clock};
+use crate::models::interaction::{Interaction, InteractionKind};
+use crate::storage::deal_store::DealStore;
+use embeddings::{cosine_similarity, Embedder, HashingEmbedder};
+
+/// Items remembered per session when counting co-interactions
+const SESSION_HISTORY: usize = 50;
+/// Sessions without an interaction for this long are dropped on the next refresh
+pub const SESSION_IDLE: Duration = Duration::from_secs(30 * 60);
+/// Sessions remembered at once; the least recently active go first
+const MAX_SESSIONS: usize = 100_000;
+/// Interactions queued between refreshes; the oldest are dropped beyond this
+const MAX_PENDING_INTERACTIONS: usize = 100_000;
+
+struct Session {
+    items: VecDeque<String>,
+    last_active: Instant,
+}
+
+#[derive(Default)]
+struct RecommendationIndex {
+    embeddings: HashMap<String, Vec<f32>>,
+    /// Top-k most similar deals per deal, best first
+    neighbours: HashMap<String, Vec<(String, f32)>>,
+    /// Weighted co-interaction counts between deal pairs
+    co_interactions: HashMap<String, HashMap<String, f64>>,
+    sessions: HashMap<String, Session>,
+}
+
+pub struct RecommendationService {
+    embedder: Box<dyn Embedder>,
+    index: Arc<RwLock<RecommendationIndex>>,
+    pending_interactions: Arc<Mutex<VecDeque<Interaction>>>,
+    neighbours_per_item: usize,
+    clock: Arc<dyn Clock>,
+}
+
+impl RecommendationService {
+    pub fn new() -> Self {
+        Self::with_embedder(Box::new(HashingEmbedder::default()))
+    }
+
+    pub fn with_embedder(embedder: Box<dyn Embedder>) -> Self {
+        Self {
+            embedder,
+            index: Arc::new(RwLock::new(RecommendationIndex::default())),
+            pending_interactions: Arc::new(Mutex::new(VecDeque::new())),
+            neighbours_per_item: 20,
+            clock: clock::system(),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Queue an interaction; it is folded into the co-interaction index on the next refresh
+    pub async fn record_interaction(&self, interaction: Interaction) {
+        let mut pending = self.pending_interactions.lock().await;
+        if pending.len() >= MAX_PENDING_INTERACTIONS {
+            pending.pop_front();
+        }
+        pending.push_back(interaction);
+    }
+
+    pub async fn similar(&self, deal_id: &str, limit: usize) -> Vec<(String, f32)> {
+        let index = self.index.read().await;
+        index
+            .neighbours
+            .get(deal_id)
+            .map(|n| n.iter().take(limit).cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub async fn frequently_bought_with(&self, deal_id: &str, limit: usize) -> Vec<(String, f64)> {
+        let index = self.index.read().await;
+        let mut items: Vec<(String, f64)> = index
+            .co_interactions
+            .get(deal_id)
+            .map(|counts| counts.iter().map(|(id, count)| (id.clone(), *count)).collect())
+            .unwrap_or_default();
+
+        items.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        items.truncate(limit);
+        items
+    }
+
+    /// Embed deals not yet indexed, fold queued interactions into the
+    /// co-interaction counts and forget idle sessions
+    pub async fn refresh(&self, store: &DealStore) {
+        let deals = store.list().await;
+        let mut guard = self.index.write().await;
+        let index = &mut *guard;
+
+        for deal in &deals {
+            if index.embeddings.contains_key(&deal.id) {
+                continue;
+            }
+
+            let embedding = self.embedder.embed(deal);
+            let mut neighbours = Vec::new();
+
+            for (other_id, other_embedding) in index.embeddings.iter() {
+                let similarity = cosine_similarity(&embedding, other_embedding);
+                neighbours.push((other_id.clone(), similarity));
+            }
+
+            // Offer the new deal to every existing item's neighbour list
+            for (other_id, similarity) in &neighbours {
+                let list = index.neighbours.entry(other_id.clone()).or_default();
+                list.push((deal.id.clone(), *similarity));
+                sort_and_truncate(list, self.neighbours_per_item);
+            }
+
+            sort_and_truncate(&mut neighbours, self.neighbours_per_item);
+            index.neighbours.insert(deal.id.clone(), neighbours);
+            index.embeddings.insert(deal.id.clone(), embedding);
+        }
+
+        let pending: VecDeque<Interaction> = std::mem::take(&mut *self.pending_interactions.lock().await);
+        let now = self.clock.instant();
+        index.sessions.retain(|_, session| now.duration_since(session.last_active) < SESSION_IDLE);
+        for interaction in pending {
+            let weight = match interaction.kind {
+                InteractionKind::View => 0.1,
+                InteractionKind::Click => 0.25,
+                InteractionKind::AddToCart => 0.6,
+                InteractionKind::Purchase => 1.0,
+            };
+
+            let previous: Vec<String> = index
+                .sessions
+                .get(&interaction.session_id)
+                .map(|session| session.items.iter().filter(|id| **id != interaction.deal_id).cloned().collect())
+                .unwrap_or_default();
+
+            for other in previous {
+                *index
+                    .co_interactions
+                    .entry(interaction.deal_id.clone())
+                    .or_default()
+                    .entry(other.clone())
+                    .or_insert(0.0) += weight;
+                *index
+                    .co_interactions
+                    .entry(other)
+                    .or_default()
+                    .entry(interaction.deal_id.clone())
+                    .or_insert(0.0) += weight;
+            }
+
+            let session = index.sessions.entry(interaction.session_id).or_insert_with(|| Session {
+                items: VecDeque::new(),
+                last_active: now,
+            });
+            session.last_active = now;
+            session.items.retain(|id| *id != interaction.deal_id);
+            session.items.push_back(interaction.deal_id);
+            if session.items.len() > SESSION_HISTORY {
+                session.items.pop_front();
+            }
+        }
+
+        if index.sessions.len() > MAX_SESSIONS {
+            let mut by_activity: Vec<(Instant, String)> =
+                index.sessions.iter().map(|(id, session)| (session.last_active, id.clone())).collect();
+            by_activity.sort();
+            for (_, id) in by_activity.into_iter().take(index.sessions.len() - MAX_SESSIONS) {
+                index.sessions.remove(&id);
+            }
+        }
+    }
+
+    /// Periodically refresh the indexes from the deal store
+    pub async fn start_background_tasks(self: Arc<Self>, store: Arc<DealStore>) {
+        let mut ticker = interval(Duration::from_secs(30));
+        loop {
+            ticker.tick().await;
+            self.refresh(&store).await;
+        }
+    }
+}
+
+impl Default for RecommendationService {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn sort_and_truncate(list: &mut Vec<(String, f32)>, limit: usize) {
+    list.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    list.truncate(limit);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use chrono::Utc;
+
+    fn interaction(session: &str, deal: &str, kind: InteractionKind) -> Interaction {
+        Interaction {
+            session_id: session.to_string(),
+            deal_id: deal.to_string(),
+            kind,
+            occurred_at: Utc::now(),
+            experiment: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_similar_deals_share_category() {
+        let store = DealStore::with_sample_data();
+        let service = RecommendationService::new();
+        service.refresh(&store).await;
+
+        // Gaming laptop should sit closer to the other laptop than to the backpack bundle
+        let similar = service.similar("deal_7", 10).await;
+        let position = |id: &str| similar.iter().position(|(d, _)| d == id).unwrap();
+        assert!(position("deal_1") < position("deal_6"));
+    }
+
+    #[tokio::test]
+    async fn test_co_purchases_are_counted() {
+        let store = DealStore::with_sample_data();
+        let service = RecommendationService::new();
+
+        service.record_interaction(interaction("s1", "deal_3", InteractionKind::Purchase)).await;
+        service.record_interaction(interaction("s1", "deal_4", InteractionKind::Purchase)).await;
+        service.record_interaction(interaction("s2", "deal_3", InteractionKind::View)).await;
+        service.record_interaction(interaction("s2", "deal_5", InteractionKind::View)).await;
+        service.refresh(&store).await;
+
+        let items = service.frequently_bought_with("deal_3", 5).await;
+        assert_eq!(items[0].0, "deal_4");
+        assert_eq!(items.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_idle_sessions_are_forgotten() {
+        let store = DealStore::with_sample_data();
+        let clock = Arc::new(MockClock::new());
+        let service = RecommendationService::new().with_clock(clock.clone());
+
+        service.record_interaction(interaction("s1", "deal_3", InteractionKind::Purchase)).await;
+        service.record_interaction(interaction("s2", "deal_5", InteractionKind::View)).await;
+        service.refresh(&store).await;
+        clock.advance(SESSION_IDLE / 2);
+        service.record_interaction(interaction("s2", "deal_6", InteractionKind::View)).await;
+        service.refresh(&store).await;
+        clock.advance(SESSION_IDLE / 2);
+        service.record_interaction(interaction("s1", "deal_4", InteractionKind::Purchase)).await;
+        service.refresh(&store).await;
+
+        // s1 was idle for the whole window, so deal_4 starts a new session
+        assert!(service.frequently_bought_with("deal_4", 5).await.is_empty());
+        assert_eq!(service.frequently_bought_with("deal_6", 5).await[0].0, "deal_5");
+        let index = service.index.read().await;
+        assert_eq!(index.sessions["s1"].items, ["deal_4"]);
+        assert!(index.sessions.contains_key("s2"));
+    }
+}
@@ -0,0 +1,442 @@
+//! Coupon corpus reprocessing
+//!
+//! After an extraction improvement, a reprocess run replays the archived page
+//! snapshots (see [`SnapshotArchive`]) through the current parser, validator and
+//! deduplicator and rebuilds the coupon corpus from them, without fetching any
+//! merchant again. Snapshots are replayed oldest first, in batches, so the latest
+//! snapshot of a page wins and progress can be followed while the run goes. Listings
+//! are only added or updated; coupons missing from the snapshots are left alone. A
+//! dry run works out the same diff against the corpus without writing it.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::runtime::Handle;
+use tokio::sync::Mutex;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::coupon_engine::archive::{SnapshotArchive, SnapshotFilter, SnapshotRef};
+use crate::coupon_engine::parser::{Parser, ParserVersion};
+use crate::coupon_engine::{CouponEngine, EngineConfig, RawCoupon, SourceType};
+use crate::licensing::SourceLicenses;
+use crate::models::coupon_listing::{CouponListing, CouponSource};
+use crate::models::domain::{CouponCode, MerchantDomain};
+use crate::storage::coupon_store::CouponStore;
+
+const DEFAULT_BATCH_SIZE: usize = 50;
+/// Added and changed listings included in a run's diff
+const MAX_SAMPLE_CHANGES: usize = 50;
+/// Finished runs kept for status lookups; older ones are dropped first
+const MAX_FINISHED_RUNS: usize = 20;
+
+#[derive(Debug, Clone, Default, Deserialize, ToSchema)]
+pub struct ReprocessRequest {
+    /// Work out the diff without touching the corpus
+    #[serde(default)]
+    pub dry_run: bool,
+    #[serde(flatten)]
+    pub filter: SnapshotFilter,
+    /// Snapshots per batch (default 50)
+    pub batch_size: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RunStatus {
+    Running,
+    Completed,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct CorpusChange {
+    /// `None` for a listing the corpus does not have yet
+    pub before: Option<CouponListing>,
+    pub after: CouponListing,
+}
+
+#[derive(Debug, Clone, Default, Serialize, ToSchema)]
+pub struct CorpusDiff {
+    pub added: u32,
+    pub changed: u32,
+    pub unchanged: u32,
+    pub samples: Vec<CorpusChange>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ReprocessRun {
+    pub id: Uuid,
+    pub status: RunStatus,
+    pub dry_run: bool,
+    pub parser_version: String,
+    pub snapshots_total: u32,
+    pub snapshots_done: u32,
+    /// Snapshots that could not be read
+    pub snapshots_failed: u32,
+    pub batches_total: u32,
+    pub batches_done: u32,
+    /// Distinct valid coupons found so far
+    pub coupons_found: u32,
+    /// Set once the run has completed
+    pub diff: Option<CorpusDiff>,
+    pub error: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ReprocessError {
+    /// `SNAPSHOT_ARCHIVE_DIR` is not set, so there is nothing to replay
+    NoArchive,
+    AlreadyRunning(Uuid),
+}
+
+impl fmt::Display for ReprocessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoArchive => write!(f, "no snapshot archive configured (set SNAPSHOT_ARCHIVE_DIR)"),
+            Self::AlreadyRunning(id) => write!(f, "reprocess run {} is still running", id),
+        }
+    }
+}
+
+impl std::error::Error for ReprocessError {}
+
+pub struct Reprocessor {
+    archive: Option<Arc<SnapshotArchive>>,
+    store: Arc<CouponStore>,
+    engine: CouponEngine,
+    runs: Mutex<HashMap<Uuid, ReprocessRun>>,
+    runtime: Option<Handle>,
+    licenses: Option<Arc<SourceLicenses>>,
+}
+
+impl Reprocessor {
+    /// Replay with an offline engine using the parser at `PARSER_VERSION`
+    pub fn new(archive: Option<Arc<SnapshotArchive>>, store: Arc<CouponStore>) -> Self {
+        let version = ParserVersion::from_env("PARSER_VERSION").unwrap_or(ParserVersion::CURRENT);
+        let engine = CouponEngine::builder(EngineConfig::default())
+            .offline()
+            .parser(Arc::new(Parser::with_version(version)))
+            .build();
+
+        Self {
+            archive,
+            store,
+            engine,
+            runs: Mutex::new(HashMap::new()),
+            runtime: None,
+            licenses: None,
+        }
+    }
+
+    /// Run replays on `runtime` rather than the caller's
+    pub fn with_runtime(mut self, runtime: Handle) -> Self {
+        self.runtime = Some(runtime);
+        self
+    }
+
+    /// Tag rebuilt listings with their source's license
+    pub fn with_licenses(mut self, licenses: Arc<SourceLicenses>) -> Self {
+        self.licenses = Some(licenses);
+        self
+    }
+
+    /// Replay through `engine` instead; only [`CouponEngine::process_documents`] is used
+    pub fn with_engine(mut self, engine: CouponEngine) -> Self {
+        self.engine = engine;
+        self
+    }
+
+    /// Start a run in the background
+    pub async fn start(self: &Arc<Self>, request: ReprocessRequest) -> Result<ReprocessRun, ReprocessError> {
+        if self.archive.is_none() {
+            return Err(ReprocessError::NoArchive);
+        }
+
+        let run = {
+            let mut runs = self.runs.lock().await;
+            if let Some(running) = runs.values().find(|run| run.status == RunStatus::Running) {
+                return Err(ReprocessError::AlreadyRunning(running.id));
+            }
+
+            let mut finished: Vec<(DateTime<Utc>, Uuid)> = runs.values().map(|run| (run.started_at, run.id)).collect();
+            if finished.len() >= MAX_FINISHED_RUNS {
+                finished.sort();
+                for (_, id) in &finished[..=finished.len() - MAX_FINISHED_RUNS] {
+                    runs.remove(id);
+                }
+            }
+
+            let run = ReprocessRun {
+                id: Uuid::new_v4(),
+                status: RunStatus::Running,
+                dry_run: request.dry_run,
+                parser_version: self.engine.parser_version().to_string(),
+                snapshots_total: 0,
+                snapshots_done: 0,
+                snapshots_failed: 0,
+                batches_total: 0,
+                batches_done: 0,
+                coupons_found: 0,
+                diff: None,
+                error: None,
+                started_at: Utc::now(),
+                finished_at: None,
+            };
+            runs.insert(run.id, run.clone());
+            run
+        };
+
+        let reprocessor = self.clone();
+        let id = run.id;
+        let replay = async move {
+            let result = reprocessor.execute(id, &request).await;
+            reprocessor
+                .update(id, |run| {
+                    run.finished_at = Some(Utc::now());
+                    match result {
+                        Ok(diff) => {
+                            run.status = RunStatus::Completed;
+                            run.diff = Some(diff);
+                        }
+                        Err(e) => {
+                            run.status = RunStatus::Failed;
+                            run.error = Some(e.to_string());
+                        }
+                    }
+                })
+                .await;
+        };
+        match &self.runtime {
+            Some(runtime) => runtime.spawn(replay),
+            None => tokio::spawn(replay),
+        };
+        Ok(run)
+    }
+
+    pub async fn get(&self, id: Uuid) -> Option<ReprocessRun> {
+        self.runs.lock().await.get(&id).cloned()
+    }
+
+    async fn update(&self, id: Uuid, change: impl FnOnce(&mut ReprocessRun)) {
+        if let Some(run) = self.runs.lock().await.get_mut(&id) {
+            change(run);
+        }
+    }
+
+    async fn execute(&self, id: Uuid, request: &ReprocessRequest) -> Result<CorpusDiff, Box<dyn std::error::Error + Send + Sync>> {
+        let archive = self.archive.as_ref().ok_or(ReprocessError::NoArchive)?;
+        let snapshots = archive.list(&request.filter).await?;
+        let batch_size = request.batch_size.unwrap_or(DEFAULT_BATCH_SIZE).max(1);
+        self.update(id, |run| {
+            run.snapshots_total = snapshots.len() as u32;
+            run.batches_total = snapshots.len().div_ceil(batch_size) as u32;
+        })
+        .await;
+
+        let mut rebuilt: HashMap<(MerchantDomain, CouponCode), CouponListing> = HashMap::new();
+        for batch in snapshots.chunks(batch_size) {
+            let failed = self.replay_into(archive, batch, &mut rebuilt).await?;
+            self.update(id, |run| {
+                run.snapshots_done += batch.len() as u32;
+                run.snapshots_failed += failed;
+                run.batches_done += 1;
+                run.coupons_found = rebuilt.len() as u32;
+            })
+            .await;
+        }
+
+        let listings = self.licensed(rebuilt).await;
+        Ok(self.apply(listings, request.dry_run).await)
+    }
+
+    /// Replay `snapshots`, oldest first, and write the listings they rebuild, e.g. as
+    /// one chunk of a [backfill](crate::backfill). Returns the coupons found and the
+    /// snapshots that could not be read.
+    pub async fn replay(&self, snapshots: &[SnapshotRef]) -> Result<(u32, u32), Box<dyn std::error::Error + Send + Sync>> {
+        let archive = self.archive.as_ref().ok_or(ReprocessError::NoArchive)?;
+        let mut rebuilt = HashMap::new();
+        let failed = self.replay_into(archive, snapshots, &mut rebuilt).await?;
+        let listings = self.licensed(rebuilt).await;
+        let found = listings.len() as u32;
+        self.apply(listings, false).await;
+        Ok((found, failed))
+    }
+
+    /// Parse `snapshots` into `rebuilt`, later snapshots replacing earlier listings,
+    /// and return how many could not be read
+    async fn replay_into(
+        &self,
+        archive: &SnapshotArchive,
+        snapshots: &[SnapshotRef],
+        rebuilt: &mut HashMap<(MerchantDomain, CouponCode), CouponListing>,
+    ) -> Result<u32, Box<dyn std::error::Error + Send + Sync>> {
+        let mut failed = 0;
+        for snapshot in snapshots {
+            let loaded = match archive.load(snapshot).await {
+                Ok(loaded) => loaded,
+                Err(e) => {
+                    tracing::warn!(snapshot = %snapshot.path.display(), error = %e, "Skipping an unreadable snapshot");
+                    failed += 1;
+                    continue;
+                }
+            };
+            let coupons = self.engine.process_documents(vec![(loaded.url, loaded.content)]).await?;
+            for coupon in coupons {
+                let key = (coupon.merchant_domain.clone(), coupon.code.clone());
+                rebuilt.insert(key, listing(&coupon, loaded.fetched_at));
+            }
+        }
+        Ok(failed)
+    }
+
+    /// The rebuilt listings tagged with their licenses, by merchant and code
+    async fn licensed(&self, rebuilt: HashMap<(MerchantDomain, CouponCode), CouponListing>) -> Vec<CouponListing> {
+        let mut listings: Vec<CouponListing> = rebuilt.into_values().collect();
+        if let Some(licenses) = &self.licenses {
+            for listing in &mut listings {
+                licenses.tag(listing).await;
+            }
+        }
+        listings.sort_by(|a, b| {
+            (a.merchant_domain.as_str(), a.code.as_str()).cmp(&(b.merchant_domain.as_str(), b.code.as_str()))
+        });
+        listings
+    }
+
+    /// Diff the rebuilt listings against the corpus, writing them unless `dry_run`
+    async fn apply(&self, listings: Vec<CouponListing>, dry_run: bool) -> CorpusDiff {
+        let mut diff = CorpusDiff::default();
+        for listing in listings {
+            let before = self.store.find(&listing.merchant_domain, &listing.code).await;
+            match &before {
+                None => diff.added += 1,
+                Some(existing) if same_offer(existing, &listing) => {
+                    diff.unchanged += 1;
+                    continue;
+                }
+                Some(_) => diff.changed += 1,
+            }
+
+            if diff.samples.len() < MAX_SAMPLE_CHANGES {
+                diff.samples.push(CorpusChange {
+                    before,
+                    after: listing.clone(),
+                });
+            }
+            if !dry_run {
+                self.store.upsert(listing).await;
+            }
+        }
+        diff
+    }
+}
+
+pub(crate) fn same_offer(a: &CouponListing, b: &CouponListing) -> bool {
+    a.title == b.title && a.discount_type == b.discount_type && a.discount_value == b.discount_value && a.source == b.source
+}
+
+pub(crate) fn listing(coupon: &RawCoupon, fetched_at: DateTime<Utc>) -> CouponListing {
+    CouponListing {
+        code: coupon.code.clone(),
+        title: coupon.title.clone(),
+        description: coupon.description.clone(),
+        locale: None,
+        merchant_domain: coupon.merchant_domain.clone(),
+        discount_type: coupon.discount_type.as_str().to_string(),
+        discount_value: coupon.discount_value,
+        minimum_order: coupon.minimum_order,
+        source: match coupon.source_type {
+            SourceType::AffiliateApi => CouponSource::AffiliateApi,
+            SourceType::PartnerApi => CouponSource::PartnerApi,
+            SourceType::WebScraping => CouponSource::WebScraping,
+            SourceType::UserSubmitted => CouponSource::UserSubmitted,
+        },
+        license: None,
+        // The parser does not score its codes; same default as stored listings
+        extraction_confidence: 0.7,
+        scraped_at: fetched_at,
+        valid_until: coupon.valid_until,
+        predicted_success: None,
+        tags: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const PAGE_V1: &str = "<p>Use code SAVE20 for 20% off your order</p>";
+    const PAGE_V2: &str = "<p>Use code SAVE25 for 25% off your order</p><p>Use code SHIP10 for $10 off</p>";
+
+    async fn wait(reprocessor: &Reprocessor, id: Uuid) -> ReprocessRun {
+        tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                let run = reprocessor.get(id).await.unwrap();
+                if run.status != RunStatus::Running {
+                    return run;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("run finished")
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_diffs_and_real_run_rebuilds_corpus() {
+        let dir = std::env::temp_dir().join(format!("reprocess_{}", Uuid::new_v4()));
+        let archive = Arc::new(SnapshotArchive::new(dir.clone()));
+        archive.store("https://shop.example.com/deals", PAGE_V1).await.unwrap();
+        archive.store("https://shop.example.com/deals", PAGE_V2).await.unwrap();
+        archive.store("https://other.example.com/", PAGE_V1).await.unwrap();
+        let store = Arc::new(CouponStore::new());
+        let reprocessor = Arc::new(Reprocessor::new(Some(archive), store.clone()));
+
+        let request = ReprocessRequest {
+            dry_run: true,
+            batch_size: Some(2),
+            ..ReprocessRequest::default()
+        };
+        let started = reprocessor.start(request.clone()).await.unwrap();
+        let dry_run = wait(&reprocessor, started.id).await;
+        assert_eq!(dry_run.status, RunStatus::Completed);
+        assert_eq!((dry_run.snapshots_done, dry_run.batches_total, dry_run.batches_done), (3, 2, 2));
+        let diff = dry_run.diff.unwrap();
+        assert_eq!((diff.added, diff.changed, diff.unchanged), (4, 0, 0));
+        assert!(store.list().await.is_empty());
+
+        let started = reprocessor.start(ReprocessRequest { dry_run: false, ..request }).await.unwrap();
+        wait(&reprocessor, started.id).await;
+        let mut codes: Vec<String> = store.list().await.iter().map(|c| format!("{} {}", c.merchant_domain, c.code)).collect();
+        codes.sort();
+        assert_eq!(
+            codes,
+            vec![
+                "other.example.com SAVE20",
+                "shop.example.com SAVE20",
+                "shop.example.com SAVE25",
+                "shop.example.com SHIP10"
+            ]
+        );
+
+        let again = reprocessor.start(ReprocessRequest::default()).await.unwrap();
+        let diff = wait(&reprocessor, again.id).await.diff.unwrap();
+        assert_eq!((diff.added, diff.changed, diff.unchanged), (0, 0, 4));
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn test_requires_an_archive() {
+        let reprocessor = Arc::new(Reprocessor::new(None, Arc::new(CouponStore::new())));
+        let result = reprocessor.start(ReprocessRequest::default()).await;
+        assert_eq!(result.unwrap_err(), ReprocessError::NoArchive);
+    }
+}
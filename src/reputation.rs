@@ -0,0 +1,300 @@
+//! Merchant reputation scoring
+//!
+//! Combines coupon success rates, deal accuracy, scrape reliability and user
+//! feedback into a 0-100 score per merchant. Each component is smoothed towards a
+//! prior so merchants with little data are neither trusted nor punished too much.
+//! Raw signals are shared through Redis when `REDIS_URL` is set, otherwise
+//! persisted to a JSON file, so scores survive restarts.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use utoipa::ToSchema;
+
+use crate::clock::{self, Clock};
+use crate::models::deal::{Deal, DealStatus};
+use crate::models::domain::MerchantDomain;
+use crate::storage::persisted::{PersistedStore, StoreError};
+
+const REDIS_KEY: &str = "merchant_reputation";
+const STORE_NAME: &str = "merchant reputation";
+/// How often shared signals are re-read
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Prior success rate assumed for every component
+const PRIOR_RATE: f64 = 0.7;
+/// Number of pseudo-observations the prior is worth
+const PRIOR_WEIGHT: f64 = 5.0;
+
+const COUPON_WEIGHT: f64 = 0.35;
+const ACCURACY_WEIGHT: f64 = 0.25;
+const SCRAPE_WEIGHT: f64 = 0.2;
+const FEEDBACK_WEIGHT: f64 = 0.2;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct MerchantSignals {
+    coupon_successes: u32,
+    coupon_failures: u32,
+    scrape_successes: u32,
+    scrape_failures: u32,
+    feedback_total: f64,
+    feedback_count: u32,
+    updated_at: Option<DateTime<Utc>>,
+}
+
+/// Raw outcome counts reported by the coupon tester and scraper
+#[derive(Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct SignalUpdate {
+    #[serde(default)]
+    pub coupon_successes: u32,
+    #[serde(default)]
+    pub coupon_failures: u32,
+    #[serde(default)]
+    pub scrape_successes: u32,
+    #[serde(default)]
+    pub scrape_failures: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct MerchantReputation {
+    pub domain: MerchantDomain,
+    /// Overall reputation (0 - 100)
+    pub score: f64,
+    pub coupon_success_rate: Option<f64>,
+    pub deal_accuracy: Option<f64>,
+    pub scrape_reliability: Option<f64>,
+    /// Average user rating (1 - 5)
+    pub user_rating: Option<f64>,
+    /// Relative weight the crawl scheduler gives this merchant's sources (0.1 - 1.0)
+    pub crawl_weight: f64,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+pub struct ReputationService {
+    signals: Arc<Mutex<HashMap<MerchantDomain, MerchantSignals>>>,
+    /// Every merchant's signals in the file, one field of the Redis hash each
+    store: PersistedStore<HashMap<MerchantDomain, MerchantSignals>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl ReputationService {
+    /// Signals persisted to `path`, or kept in memory only
+    pub fn new(path: Option<PathBuf>) -> Self {
+        Self::with_store(PersistedStore::new(STORE_NAME, REDIS_KEY, path))
+    }
+
+    /// Signals shared through Redis
+    pub fn shared(redis_url: &str) -> Result<Self, StoreError> {
+        Ok(Self::with_store(PersistedStore::shared(STORE_NAME, REDIS_KEY, redis_url)?))
+    }
+
+    fn with_store(store: PersistedStore<HashMap<MerchantDomain, MerchantSignals>>) -> Self {
+        Self {
+            signals: Arc::new(Mutex::new(HashMap::new())),
+            store: store.pretty(),
+            clock: clock::system(),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Share signals through `REDIS_URL` when set, otherwise load them from
+    /// `REPUTATION_STORE_PATH` (default `data/merchant_reputation.json`)
+    pub async fn from_env() -> Self {
+        let store = PersistedStore::from_env(STORE_NAME, REDIS_KEY, "REPUTATION_STORE_PATH", "data/merchant_reputation.json");
+        let service = Self::with_store(store);
+        if let Err(e) = service.reload().await {
+            tracing::warn!(error = %e, "Starting with an empty merchant reputation store");
+        }
+        service
+    }
+
+    /// Replace the local copy with every stored merchant's signals
+    pub async fn reload(&self) -> Result<(), StoreError> {
+        if let Some(stored) = self.store.load_map(REDIS_KEY).await? {
+            *self.signals.lock().await = stored;
+        }
+        Ok(())
+    }
+
+    /// Re-read shared signals every [`REFRESH_INTERVAL`], picking up outcomes
+    /// recorded by other instances
+    pub async fn start_background_tasks(self: Arc<Self>) {
+        self.store.refresh_every(self.clock.as_ref(), REFRESH_INTERVAL, || self.reload()).await
+    }
+
+    /// Apply `change` to `domain`'s signals, as last stored when shared so counts
+    /// recorded by other instances are kept
+    async fn update(&self, domain: &MerchantDomain, change: impl FnOnce(&mut MerchantSignals)) {
+        let mut signals = self.signals.lock().await;
+        let stored = match self.store.hash_get(REDIS_KEY, domain.as_str()) {
+            Ok(stored) => stored,
+            Err(e) => {
+                tracing::warn!(merchant = %domain, error = %e, "Failed to read shared merchant signals; using the local copy");
+                None
+            }
+        };
+        let entry = signals.entry(domain.clone()).or_default();
+        if let Some(stored) = stored {
+            *entry = stored;
+        }
+        change(entry);
+        entry.updated_at = Some(self.clock.now());
+        let updated = entry.clone();
+        self.store.persist_field(REDIS_KEY, domain.as_str(), Some(&updated), || signals.clone()).await;
+    }
+
+    pub async fn record_signals(&self, domain: &MerchantDomain, update: SignalUpdate) {
+        self.update(domain, |entry| {
+            entry.coupon_successes += update.coupon_successes;
+            entry.coupon_failures += update.coupon_failures;
+            entry.scrape_successes += update.scrape_successes;
+            entry.scrape_failures += update.scrape_failures;
+        })
+        .await;
+    }
+
+    /// Record a 1-5 star user rating
+    pub async fn record_feedback(&self, domain: &MerchantDomain, rating: f64) {
+        self.update(domain, |entry| {
+            entry.feedback_total += rating.clamp(1.0, 5.0);
+            entry.feedback_count += 1;
+        })
+        .await;
+    }
+
+    /// Reputation for a merchant, judging deal accuracy from the (annotated) listed deals
+    pub async fn reputation(&self, domain: &MerchantDomain, deals: &[Deal]) -> MerchantReputation {
+        let signals = self.signals.lock().await.get(domain).cloned().unwrap_or_default();
+        compute_reputation(domain, &signals, deals)
+    }
+
+    /// All known merchants, highest reputation first (the scheduler's crawl order)
+    pub async fn ranked(&self, deals: &[Deal]) -> Vec<MerchantReputation> {
+        let signals = self.signals.lock().await.clone();
+
+        let mut domains: Vec<MerchantDomain> = signals.keys().cloned().collect();
+        for deal in deals {
+            if !domains.contains(&deal.merchant_domain) {
+                domains.push(deal.merchant_domain.clone());
+            }
+        }
+
+        let mut ranked: Vec<MerchantReputation> = domains
+            .iter()
+            .map(|domain| compute_reputation(domain, &signals.get(domain).cloned().unwrap_or_default(), deals))
+            .collect();
+
+        ranked.sort_by(|a, b| b.score.total_cmp(&a.score));
+        ranked
+    }
+}
+
+fn compute_reputation(domain: &MerchantDomain, signals: &MerchantSignals, deals: &[Deal]) -> MerchantReputation {
+    let merchant_deals: Vec<&Deal> = deals.iter().filter(|d| &d.merchant_domain == domain).collect();
+    let accurate = merchant_deals
+        .iter()
+        .filter(|d| !d.discount_inflated && !matches!(d.status, DealStatus::Dead | DealStatus::PriceIncreased))
+        .count();
+
+    let coupon = rate(signals.coupon_successes as f64, (signals.coupon_successes + signals.coupon_failures) as f64);
+    let accuracy = rate(accurate as f64, merchant_deals.len() as f64);
+    let scrape = rate(signals.scrape_successes as f64, (signals.scrape_successes + signals.scrape_failures) as f64);
+    // Map 1-5 stars onto 0-1 before smoothing
+    let feedback = rate(
+        (signals.feedback_total - signals.feedback_count as f64) / 4.0,
+        signals.feedback_count as f64,
+    );
+
+    let score = (coupon.smoothed * COUPON_WEIGHT
+        + accuracy.smoothed * ACCURACY_WEIGHT
+        + scrape.smoothed * SCRAPE_WEIGHT
+        + feedback.smoothed * FEEDBACK_WEIGHT)
+        * 100.0;
+
+    MerchantReputation {
+        domain: domain.clone(),
+        score: round(score),
+        coupon_success_rate: coupon.observed,
+        deal_accuracy: accuracy.observed,
+        scrape_reliability: scrape.observed,
+        user_rating: (signals.feedback_count > 0)
+            .then(|| round(signals.feedback_total / signals.feedback_count as f64)),
+        crawl_weight: round((score / 100.0).powi(2).max(0.1)),
+        updated_at: signals.updated_at,
+    }
+}
+
+struct Rate {
+    observed: Option<f64>,
+    smoothed: f64,
+}
+
+fn rate(successes: f64, total: f64) -> Rate {
+    Rate {
+        observed: (total > 0.0).then(|| round(successes / total)),
+        smoothed: (successes + PRIOR_RATE * PRIOR_WEIGHT) / (total + PRIOR_WEIGHT),
+    }
+}
+
+fn round(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn domain(name: &str) -> MerchantDomain {
+        MerchantDomain::parse(name).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_reliable_merchant_outranks_unreliable() {
+        let service = ReputationService::new(None);
+        service
+            .record_signals(
+                &domain("good.com"),
+                SignalUpdate {
+                    coupon_successes: 40,
+                    coupon_failures: 2,
+                    scrape_successes: 100,
+                    ..Default::default()
+                },
+            )
+            .await;
+        service
+            .record_signals(
+                &domain("flaky.com"),
+                SignalUpdate {
+                    coupon_successes: 3,
+                    coupon_failures: 30,
+                    scrape_successes: 10,
+                    scrape_failures: 40,
+                },
+            )
+            .await;
+        service.record_feedback(&domain("flaky.com"), 1.0).await;
+
+        let ranked = service.ranked(&[]).await;
+        assert_eq!(ranked[0].domain, domain("good.com"));
+        assert!(ranked[0].crawl_weight > ranked[1].crawl_weight);
+        assert_eq!(ranked[1].user_rating, Some(1.0));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_merchant_gets_prior_score() {
+        let reputation = ReputationService::new(None).reputation(&domain("new.com"), &[]).await;
+
+        assert_eq!(reputation.score, 70.0);
+        assert!(reputation.coupon_success_rate.is_none());
+    }
+}
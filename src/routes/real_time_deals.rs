@@ -93,7 +93,7 @@ async fn get_deals(
             Ok(Json(GetDealsResponse { deals, total }))
         }
         Err(e) => {
-            tracing::error!("Failed to get deals: {}", e);
+            tracing::error!(error = %e, "Failed to get deals");
             Err(StatusCode::INTERNAL_SERVER_ERROR)
         }
     }
@@ -118,7 +118,7 @@ async fn create_alert(
     match service.create_price_alert(alert.clone()).await {
         Ok(_) => Ok(Json(alert)),
         Err(e) => {
-            tracing::error!("Failed to create alert: {}", e);
+            tracing::error!(error = %e, "Failed to create an alert");
             Err(StatusCode::INTERNAL_SERVER_ERROR)
         }
     }
@@ -131,7 +131,7 @@ async fn get_price_history(
     match service.get_price_history(&params.platform, &params.product_name).await {
         Ok(history) => Ok(Json(history)),
         Err(e) => {
-            tracing::error!("Failed to get price history: {}", e);
+            tracing::error!(error = %e, "Failed to get price history");
             Err(StatusCode::INTERNAL_SERVER_ERROR)
         }
     }
@@ -158,7 +158,7 @@ async fn get_trending_deals(
             Ok(Json(GetDealsResponse { deals, total }))
         }
         Err(e) => {
-            tracing::error!("Failed to get trending deals: {}", e);
+            tracing::error!(error = %e, "Failed to get trending deals");
             Err(StatusCode::INTERNAL_SERVER_ERROR)
         }
     }
@@ -184,7 +184,7 @@ async fn get_flash_sales(
             Ok(Json(GetDealsResponse { deals, total }))
         }
         Err(e) => {
-            tracing::error!("Failed to get flash sales: {}", e);
+            tracing::error!(error = %e, "Failed to get flash sales");
             Err(StatusCode::INTERNAL_SERVER_ERROR)
         }
     }
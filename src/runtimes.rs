@@ -0,0 +1,213 @@
+//! Separate Tokio runtimes for serving and scraping
+//!
+//! Parsing a large page takes milliseconds of CPU without an `.await` in between,
+//! so a burst of scraping on the runtime that serves the API leaves requests queued
+//! behind it. The binary instead runs two multi-threaded runtimes: the API runtime
+//! that `main` blocks on, with `API_WORKER_THREADS` threads, and a scrape runtime
+//! with `SCRAPE_WORKER_THREADS` threads, where [`Services`](crate::Services) spawns
+//! the scrape job workers, canaries and reprocess runs. Each defaults to half of the
+//! available cores, and at least one thread.
+//!
+//! `deal-service runtime bench [--seconds N]` measures what the separation buys:
+//! request latency on the API runtime while the parser saturates the scrape pool,
+//! with both pools in one runtime and then separated.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::runtime::{Builder, Handle, Runtime};
+
+use crate::coupon_engine::parser::Parser;
+
+/// Worker threads per runtime
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RuntimeConfig {
+    pub api_threads: usize,
+    pub scrape_threads: usize,
+}
+
+impl Default for RuntimeConfig {
+    fn default() -> Self {
+        let cores = std::thread::available_parallelism().map_or(2, |cores| cores.get());
+        let half = (cores / 2).max(1);
+        Self {
+            api_threads: half,
+            scrape_threads: half,
+        }
+    }
+}
+
+impl RuntimeConfig {
+    /// `API_WORKER_THREADS` and `SCRAPE_WORKER_THREADS`, each falling back to the default
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        let var = |name: &str| std::env::var(name).ok().and_then(|value| value.trim().parse().ok()).filter(|value| *value > 0);
+        Self {
+            api_threads: var("API_WORKER_THREADS").unwrap_or(default.api_threads),
+            scrape_threads: var("SCRAPE_WORKER_THREADS").unwrap_or(default.scrape_threads),
+        }
+    }
+
+    /// The runtime `main` blocks on
+    pub fn api_runtime(&self) -> std::io::Result<Runtime> {
+        Builder::new_multi_thread()
+            .worker_threads(self.api_threads)
+            .thread_name("api-worker")
+            .enable_all()
+            .build()
+    }
+}
+
+/// The scrape runtime; shut down without waiting for its tasks when dropped, which
+/// is safe from inside another runtime
+pub struct ScrapeRuntime {
+    runtime: Option<Runtime>,
+}
+
+impl ScrapeRuntime {
+    pub fn new(threads: usize) -> std::io::Result<Self> {
+        let runtime = Builder::new_multi_thread()
+            .worker_threads(threads.max(1))
+            .thread_name("scrape-worker")
+            .enable_all()
+            .build()?;
+        Ok(Self { runtime: Some(runtime) })
+    }
+
+    pub fn handle(&self) -> Handle {
+        self.runtime.as_ref().expect("runtime is only taken on drop").handle().clone()
+    }
+}
+
+impl Drop for ScrapeRuntime {
+    fn drop(&mut self) {
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+/// Request latency over one run of [`isolation_bench`]
+#[derive(Debug, Clone)]
+pub struct LatencySummary {
+    pub mode: &'static str,
+    pub requests: usize,
+    pub p50: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl std::fmt::Display for LatencySummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:<9} {:>6} requests  p50 {:>9.3} ms  p99 {:>9.3} ms  max {:>9.3} ms",
+            self.mode,
+            self.requests,
+            self.p50.as_secs_f64() * 1000.0,
+            self.p99.as_secs_f64() * 1000.0,
+            self.max.as_secs_f64() * 1000.0
+        )
+    }
+}
+
+const LISTING_URL: &str = "https://aggregator.example.com/coupons";
+
+/// An HTML coupon listing with `items` coupons, for the scrape pool to parse
+fn coupon_listing(items: usize) -> String {
+    let mut html = String::from("<html><body><main>");
+    for i in 0..items {
+        let percent = i % 60 + 5;
+        html.push_str(&format!(
+            "<div class=\"coupon-item\"><h3>{percent}% off sitewide</h3>\
+             <span class=\"coupon-code\">SAVE{code}X{i}</span>\
+             <p>Use code SAVE{code}X{i} for {percent}% off orders over $50.</p></div>",
+            code = i % 90 + 10,
+        ));
+    }
+    html.push_str("</main></body></html>");
+    html
+}
+
+/// Issue a small request every 2 ms on the API runtime for `duration` while twice as
+/// many parser loops as scrape threads run on the scrape pool, first with the pools
+/// sharing one runtime and then on separate runtimes
+pub fn isolation_bench(config: RuntimeConfig, duration: Duration) -> std::io::Result<Vec<LatencySummary>> {
+    let mut summaries = Vec::new();
+    for (mode, separate) in [("shared", false), ("separate", true)] {
+        let shared_threads = RuntimeConfig {
+            api_threads: config.api_threads + config.scrape_threads,
+            ..config
+        };
+        let api = match separate {
+            true => config.api_runtime()?,
+            false => shared_threads.api_runtime()?,
+        };
+        let scrape = separate.then(|| ScrapeRuntime::new(config.scrape_threads)).transpose()?;
+        let scrape_handle = scrape.as_ref().map_or_else(|| api.handle().clone(), ScrapeRuntime::handle);
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let parser = Arc::new(Parser::new());
+        let page = Arc::new(coupon_listing(500));
+        for _ in 0..config.scrape_threads * 2 {
+            let (stop, parser, page) = (stop.clone(), parser.clone(), page.clone());
+            scrape_handle.spawn(async move {
+                while !stop.load(Ordering::Relaxed) {
+                    let _ = std::hint::black_box(parser.extract_coupons(&page, LISTING_URL).await);
+                    // Where a real scrape task would await its next fetch
+                    tokio::task::yield_now().await;
+                }
+            });
+        }
+
+        let mut latencies = api.block_on(async {
+            let mut latencies = Vec::new();
+            let started = Instant::now();
+            while started.elapsed() < duration {
+                // Paced off the runtime, whose timers a starved pool would delay
+                std::thread::sleep(Duration::from_millis(2));
+                let sent = Instant::now();
+                let response = tokio::spawn(async {
+                    serde_json::to_vec(&serde_json::json!({"coupons": ["SAVE20", "FREESHIP"], "service": "deal-service"}))
+                });
+                let _ = response.await;
+                latencies.push(sent.elapsed());
+            }
+            latencies
+        });
+        stop.store(true, Ordering::Relaxed);
+        drop(scrape);
+        api.shutdown_background();
+
+        latencies.sort_unstable();
+        let at = |q: f64| latencies[((latencies.len() as f64 * q) as usize).min(latencies.len() - 1)];
+        summaries.push(LatencySummary {
+            mode,
+            requests: latencies.len(),
+            p50: at(0.5),
+            p99: at(0.99),
+            max: latencies.last().copied().unwrap_or_default(),
+        });
+    }
+    Ok(summaries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_scrape_runtime_runs_tasks_on_its_own_threads_and_drops_inside_a_runtime() {
+        let scrape = ScrapeRuntime::new(1).unwrap();
+        let thread = scrape
+            .handle()
+            .spawn(async { std::thread::current().name().map(str::to_string) })
+            .await
+            .unwrap();
+        assert_eq!(thread.as_deref(), Some("scrape-worker"));
+
+        scrape.handle().spawn(std::future::pending::<()>());
+        drop(scrape);
+    }
+}
@@ -0,0 +1,210 @@
+//! Seedable synthetic catalogue
+//!
+//! [`Faker`] generates deals, price history and coupons from a seed. The same seed
+//! always yields the same data: timestamps count back from a fixed [`epoch`] rather
+//! than the clock, so partner test suites can assert on exact values.
+
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+
+use crate::models::coupon_listing::{CouponListing, CouponSource};
+use crate::models::deal::{Deal, DealStatus, PricePoint};
+use crate::models::domain::{CouponCode, MerchantDomain, Money};
+
+/// Days of price history generated per product
+const HISTORY_DAYS: i64 = 90;
+
+/// (store, domain)
+const STORES: &[(&str, &str)] = &[
+    ("Sandbox Electronics", "electronics.sandbox.test"),
+    ("Sandbox Outfitters", "outfitters.sandbox.test"),
+    ("Sandbox Home", "home.sandbox.test"),
+    ("Sandbox Books", "books.sandbox.test"),
+    ("Sandbox Market", "market.sandbox.test"),
+];
+
+/// (category, brands, products)
+const CATEGORIES: &[(&str, &[&str], &[&str])] = &[
+    ("electronics", &["Voltix", "Nimbus", "Arcwave"], &["Wireless Earbuds", "4K Monitor", "Mechanical Keyboard", "Smart Speaker"]),
+    ("fashion", &["Northline", "Kestrel"], &["Running Shoes", "Rain Jacket", "Wool Sweater"]),
+    ("home", &["Hearthly", "Brightnest"], &["Air Fryer", "Robot Vacuum", "Coffee Grinder"]),
+    ("books", &["Paperlane"], &["Cookbook", "Sci-Fi Box Set", "Travel Guide"]),
+];
+
+const CODE_PREFIXES: &[&str] = &["SAVE", "TAKE", "EXTRA", "DEAL", "SPRING"];
+
+/// Fixed point synthetic timestamps count back from
+pub fn epoch() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap()
+}
+
+/// Synthetic deals for `products` products, each offered by one to three stores
+pub struct SyntheticCatalogue {
+    pub deals: Vec<Deal>,
+    /// Per product id
+    pub price_history: HashMap<String, Vec<PricePoint>>,
+}
+
+pub struct Faker {
+    rng: StdRng,
+}
+
+impl Faker {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    pub fn catalogue(&mut self, products: usize) -> SyntheticCatalogue {
+        let mut deals = Vec::new();
+        let mut price_history = HashMap::new();
+
+        for p in 0..products {
+            let (category, brands, names) = CATEGORIES[self.rng.gen_range(0..CATEGORIES.len())];
+            let brand = brands[self.rng.gen_range(0..brands.len())];
+            let title = format!("{} {}", brand, names[self.rng.gen_range(0..names.len())]);
+            let product_id = format!("sbx_prod_{}", p + 1);
+            // Whole cents between $5 and $500
+            let typical = Decimal::new(self.rng.gen_range(500..50_000), 2);
+
+            let offers = self.rng.gen_range(1..=3);
+            let stores: Vec<_> = STORES.choose_multiple(&mut self.rng, offers).copied().collect();
+            for (store, domain) in stores {
+                let original = typical;
+                let discount_percent = Decimal::from(self.rng.gen_range(5..60u32));
+                let price = (original * (Decimal::ONE_HUNDRED - discount_percent) / Decimal::ONE_HUNDRED).round_dp(2);
+                let (price, original_price) = (Money::usd(price), Money::usd(original));
+                let discount = price
+                    .percent_off(original_price)
+                    .and_then(|percent| percent.round().to_f64())
+                    .unwrap_or(0.0);
+
+                deals.push(Deal {
+                    id: format!("sbx_deal_{}", deals.len() + 1),
+                    product_id: product_id.clone(),
+                    title: title.clone(),
+                    store: store.to_string(),
+                    merchant_domain: MerchantDomain::parse(domain).expect("sandbox domains are valid"),
+                    category: category.to_string(),
+                    brand: Some(brand.to_string()),
+                    price,
+                    original_price,
+                    discount,
+                    image_url: None,
+                    product_url: None,
+                    image_hash: None,
+                    free_shipping: self.rng.gen_bool(0.4),
+                    posted_at: epoch() - Duration::minutes(self.rng.gen_range(0..30 * 24 * 60)),
+                    score: None,
+                    honest_discount: None,
+                    discount_inflated: false,
+                    verified_at: None,
+                    price_mismatch: false,
+                    status: DealStatus::Active,
+                    status_confidence: None,
+                    events: Vec::new(),
+                    tags: Vec::new(),
+                });
+            }
+
+            price_history.insert(product_id, self.history(typical));
+        }
+
+        SyntheticCatalogue { deals, price_history }
+    }
+
+    /// Daily prices wandering up to 15% around `typical`
+    fn history(&mut self, typical: Decimal) -> Vec<PricePoint> {
+        (0..HISTORY_DAYS)
+            .rev()
+            .map(|days_ago| {
+                let swing = Decimal::new(self.rng.gen_range(-15..=15), 2);
+                PricePoint {
+                    price: (typical * (Decimal::ONE + swing)).round_dp(2),
+                    observed_at: epoch() - Duration::days(days_ago),
+                }
+            })
+            .collect()
+    }
+
+    /// Coupons spread over the sandbox stores, unique per store and code
+    pub fn coupons(&mut self, count: usize) -> Vec<CouponListing> {
+        let sources = [
+            CouponSource::AffiliateApi,
+            CouponSource::WebScraping,
+            CouponSource::PartnerApi,
+            CouponSource::UserSubmitted,
+        ];
+        let mut seen = HashSet::new();
+        let mut coupons = Vec::with_capacity(count);
+
+        while coupons.len() < count {
+            let (_, domain) = STORES[self.rng.gen_range(0..STORES.len())];
+            let prefix = CODE_PREFIXES[self.rng.gen_range(0..CODE_PREFIXES.len())];
+            let (discount_type, value, title) = match self.rng.gen_range(0..3) {
+                0 => {
+                    let percent = self.rng.gen_range(1..=8) * 5;
+                    ("percentage", Some(percent), format!("{}% off sitewide", percent))
+                }
+                1 => {
+                    let amount = self.rng.gen_range(1..=10) * 5;
+                    ("fixed", Some(amount), format!("${} off your order", amount))
+                }
+                _ => ("free_shipping", None, "Free shipping".to_string()),
+            };
+            let code = format!("{}{}", prefix, value.unwrap_or(self.rng.gen_range(1..100)));
+            if !seen.insert((domain, code.clone())) {
+                continue;
+            }
+
+            coupons.push(CouponListing {
+                code: CouponCode::parse(&code).expect("sandbox codes are valid"),
+                title,
+                description: None,
+                locale: None,
+                merchant_domain: MerchantDomain::parse(domain).expect("sandbox domains are valid"),
+                discount_type: discount_type.to_string(),
+                discount_value: value.map(f64::from),
+                minimum_order: None,
+                source: *sources.choose(&mut self.rng).unwrap(),
+                license: None,
+                extraction_confidence: f64::from(self.rng.gen_range(50..=99u32)) / 100.0,
+                scraped_at: epoch() - Duration::hours(self.rng.gen_range(1..240)),
+                valid_until: None,
+                predicted_success: None,
+                tags: Vec::new(),
+            });
+        }
+        coupons
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_seed_same_catalogue() {
+        let first = Faker::new(7).catalogue(20);
+        let second = Faker::new(7).catalogue(20);
+        let other = Faker::new(8).catalogue(20);
+
+        let json = |deals: &[Deal]| serde_json::to_string(deals).unwrap();
+        assert_eq!(json(&first.deals), json(&second.deals));
+        assert_ne!(json(&first.deals), json(&other.deals));
+        assert!(first.deals.iter().all(|d| d.price.amount < d.original_price.amount));
+        assert_eq!(first.price_history["sbx_prod_1"].len(), HISTORY_DAYS as usize);
+
+        let coupons = Faker::new(7).coupons(30);
+        let codes: Vec<String> = coupons.iter().map(|c| format!("{}/{}", c.merchant_domain, c.code)).collect();
+        assert_eq!(codes.iter().collect::<HashSet<_>>().len(), 30);
+        assert_eq!(serde_json::to_string(&coupons).unwrap(), serde_json::to_string(&Faker::new(7).coupons(30)).unwrap());
+    }
+}
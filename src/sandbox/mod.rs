@@ -0,0 +1,99 @@
+//! Sandbox mode for partner integration testing
+//!
+//! Requests made with one of a tenant's sandbox API keys are served from a sandbox of
+//! the tenant's own instead of production. Its catalogue is synthetic and generated
+//! from `SANDBOX_SEED` (see [`faker`]), so every sandbox starts with the same deals and
+//! coupons. Everything else is in-memory: writes are accepted and visible to later
+//! sandbox requests, but never persisted and never seen by production.
+
+pub mod faker;
+
+use std::collections::HashMap;
+
+use tokio::sync::Mutex;
+
+use crate::app::Services;
+use crate::tenant::TenantId;
+
+pub const DEFAULT_SEED: u64 = 42;
+/// Products in a sandbox catalogue
+pub const SANDBOX_PRODUCTS: usize = 40;
+pub const SANDBOX_COUPONS: usize = 30;
+
+/// One lazily created sandbox per tenant
+pub struct Sandboxes {
+    seed: u64,
+    services: Mutex<HashMap<TenantId, Services>>,
+}
+
+impl Sandboxes {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            services: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Seed from `SANDBOX_SEED`, or [`DEFAULT_SEED`]
+    pub fn from_env() -> Self {
+        let seed = std::env::var("SANDBOX_SEED")
+            .ok()
+            .and_then(|v| v.trim().parse().ok())
+            .unwrap_or(DEFAULT_SEED);
+        Self::new(seed)
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// The tenant's sandbox, created on first use
+    pub async fn services_for(&self, tenant: &TenantId) -> Services {
+        let mut sandboxes = self.services.lock().await;
+        if let Some(services) = sandboxes.get(tenant) {
+            return services.clone();
+        }
+
+        let services = Services::builder().sandbox(self.seed).build().await;
+        sandboxes.insert(tenant.clone(), services.clone());
+        services
+    }
+
+    /// Drop the tenant's sandbox so its next request starts from the seeded catalogue
+    pub async fn reset(&self, tenant: &TenantId) -> bool {
+        self.services.lock().await.remove(tenant).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::domain::{CouponCode, MerchantDomain};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_sandboxes_are_seeded_and_isolated_per_tenant() {
+        let sandboxes = Sandboxes::new(7);
+        let acme = TenantId("acme".to_string());
+        let globex = TenantId("globex".to_string());
+
+        let first = sandboxes.services_for(&acme).await;
+        assert!(Arc::ptr_eq(&first.deal_store, &sandboxes.services_for(&acme).await.deal_store));
+        let expected = faker::Faker::new(7).catalogue(SANDBOX_PRODUCTS).deals;
+        let ids = |deals: Vec<crate::models::deal::Deal>| deals.into_iter().map(|d| (d.id, d.price)).collect::<Vec<_>>();
+        assert_eq!(ids(first.deal_store.list().await), ids(expected));
+
+        let mut coupon = first.coupon_store.list().await[0].clone();
+        coupon.merchant_domain = MerchantDomain::parse("partner-write.test").unwrap();
+        coupon.code = CouponCode::parse("WRITTEN").unwrap();
+        first.coupon_store.upsert(coupon.clone()).await;
+
+        let other = sandboxes.services_for(&globex).await;
+        assert!(other.coupon_store.find(&coupon.merchant_domain, &coupon.code).await.is_none());
+        assert_eq!(other.coupon_store.list().await.len(), SANDBOX_COUPONS);
+
+        assert!(sandboxes.reset(&acme).await);
+        let fresh = sandboxes.services_for(&acme).await;
+        assert!(fresh.coupon_store.find(&coupon.merchant_domain, &coupon.code).await.is_none());
+    }
+}
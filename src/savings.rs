@@ -0,0 +1,414 @@
+//! Per-user savings ledger
+//!
+//! The extension reports what each user actually saved at checkout: the merchant,
+//! the amount and the code or deal behind it. The ledger keeps those entries and
+//! sums them per merchant and per month for the "you saved $X this year" report.
+//! Amounts are never converted between currencies, so every total is per currency.
+//! With `REDIS_URL` set the ledger is shared, one field of the `savings_ledger`
+//! hash per user, and read from Redis so every instance reports the same totals;
+//! otherwise entries are persisted to `SAVINGS_LEDGER_PATH` (default
+//! `data/savings_ledger.json`).
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use chrono::{DateTime, Datelike, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::clock::{self, Clock};
+use crate::models::domain::{CouponCode, Currency, MerchantDomain, Money};
+use crate::storage::persisted::{PersistedStore, StoreError};
+
+const REDIS_KEY: &str = "savings_ledger";
+const STORE_NAME: &str = "savings ledger";
+
+type Ledger = HashMap<String, Vec<SavingsEntry>>;
+
+/// A saving as reported by the client
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SavingsReport {
+    pub merchant: MerchantDomain,
+    pub amount: Money,
+    #[serde(default)]
+    pub code: Option<CouponCode>,
+    #[serde(default)]
+    pub deal_id: Option<String>,
+    /// When the order was placed; defaults to when it is recorded
+    #[serde(default)]
+    pub saved_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct SavingsEntry {
+    pub id: Uuid,
+    pub merchant: MerchantDomain,
+    pub amount: Money,
+    pub code: Option<CouponCode>,
+    pub deal_id: Option<String>,
+    pub saved_at: DateTime<Utc>,
+    pub recorded_at: DateTime<Utc>,
+}
+
+/// Amount saved in one currency over a number of entries
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct SavingsTotal {
+    pub saved: Money,
+    pub entries: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct MerchantSavings {
+    pub merchant: MerchantDomain,
+    #[serde(flatten)]
+    pub total: SavingsTotal,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct MonthSavings {
+    /// `YYYY-MM`
+    pub month: String,
+    #[serde(flatten)]
+    pub total: SavingsTotal,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct SavingsSummary {
+    pub user_id: String,
+    /// Calendar year covered, or `None` for all time
+    pub year: Option<i32>,
+    /// One total per currency, largest first
+    pub totals: Vec<SavingsTotal>,
+    /// Largest first
+    pub by_merchant: Vec<MerchantSavings>,
+    /// Oldest first
+    pub by_month: Vec<MonthSavings>,
+}
+
+/// One user's savings in one currency, as exported for marketing
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct SavingsExportRow {
+    pub user_id: String,
+    pub saved: Money,
+    pub entries: u32,
+    pub merchants: u32,
+    pub first_saved_at: DateTime<Utc>,
+    pub last_saved_at: DateTime<Utc>,
+}
+
+/// Running sums per currency, ordered by currency code
+#[derive(Default)]
+struct Sums(BTreeMap<String, (Currency, Decimal, u32)>);
+
+impl Sums {
+    fn add(&mut self, amount: Money) {
+        let sum = self
+            .0
+            .entry(amount.currency.as_str().to_string())
+            .or_insert((amount.currency, Decimal::ZERO, 0));
+        sum.1 += amount.amount;
+        sum.2 += 1;
+    }
+
+    fn totals(&self) -> impl Iterator<Item = SavingsTotal> + '_ {
+        self.0.values().map(|&(currency, amount, entries)| SavingsTotal {
+            saved: Money::new(amount, currency),
+            entries,
+        })
+    }
+}
+
+pub struct SavingsLedger {
+    /// Every entry when not shared; when shared, only a fallback for failed reads
+    entries: Arc<Mutex<Ledger>>,
+    store: PersistedStore<Ledger>,
+    clock: Arc<dyn Clock>,
+}
+
+impl SavingsLedger {
+    /// A ledger persisted to `path`, or kept in memory only
+    pub fn new(path: Option<PathBuf>) -> Self {
+        Self::with_store(PersistedStore::new(STORE_NAME, REDIS_KEY, path))
+    }
+
+    /// A ledger shared through Redis
+    pub fn shared(redis_url: &str) -> Result<Self, StoreError> {
+        Ok(Self::with_store(PersistedStore::shared(STORE_NAME, REDIS_KEY, redis_url)?))
+    }
+
+    fn with_store(store: PersistedStore<Ledger>) -> Self {
+        Self {
+            entries: Arc::new(Mutex::new(HashMap::new())),
+            store,
+            clock: clock::system(),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Share the ledger through `REDIS_URL` when set, otherwise load it from
+    /// `SAVINGS_LEDGER_PATH` (default `data/savings_ledger.json`)
+    pub async fn from_env() -> Self {
+        let ledger = Self::with_store(PersistedStore::from_env(STORE_NAME, REDIS_KEY, "SAVINGS_LEDGER_PATH", "data/savings_ledger.json"));
+        if !ledger.store.is_shared() {
+            if let Err(e) = ledger.reload().await {
+                tracing::warn!(error = %e, "Starting with an empty savings ledger");
+            }
+        }
+        ledger
+    }
+
+    /// Replace the local copy with the stored entries
+    pub async fn reload(&self) -> Result<(), StoreError> {
+        if let Some(stored) = self.store.load_map(REDIS_KEY).await? {
+            *self.entries.lock().await = stored;
+        }
+        Ok(())
+    }
+
+    /// `user_id`'s stored entries; read from Redis when shared
+    async fn user_entries(&self, user_id: &str) -> Vec<SavingsEntry> {
+        if self.store.is_shared() {
+            match self.store.hash_get(REDIS_KEY, user_id) {
+                Ok(entries) => return entries.unwrap_or_default(),
+                Err(e) => tracing::warn!(user_id, error = %e, "Failed to read savings; using the local copy"),
+            }
+        }
+        self.entries.lock().await.get(user_id).cloned().unwrap_or_default()
+    }
+
+    /// Every user's stored entries; read from Redis when shared
+    async fn all_entries(&self) -> Ledger {
+        if self.store.is_shared() {
+            match self.store.load_map(REDIS_KEY).await {
+                Ok(entries) => return entries.unwrap_or_default(),
+                Err(e) => tracing::warn!(error = %e, "Failed to read the savings ledger; using the local copy"),
+            }
+        }
+        self.entries.lock().await.clone()
+    }
+
+    /// Add a saving to `user_id`'s ledger
+    pub async fn record(&self, user_id: &str, report: SavingsReport) -> Result<SavingsEntry, String> {
+        if user_id.trim().is_empty() {
+            return Err("user id must not be empty".to_string());
+        }
+        if report.amount.amount <= Decimal::ZERO {
+            return Err("amount must be positive".to_string());
+        }
+
+        let now = self.clock.now();
+        let saved_at = report.saved_at.unwrap_or(now);
+        if saved_at > now {
+            return Err("saved_at is in the future".to_string());
+        }
+
+        let entry = SavingsEntry {
+            id: Uuid::new_v4(),
+            merchant: report.merchant,
+            amount: report.amount,
+            code: report.code,
+            deal_id: report.deal_id,
+            saved_at,
+            recorded_at: now,
+        };
+        let _write = self.store.write_lock().await;
+        // Another instance may have recorded savings for the user since
+        let mut user_entries = self.user_entries(user_id).await;
+        user_entries.push(entry.clone());
+        let mut entries = self.entries.lock().await;
+        entries.insert(user_id.to_string(), user_entries.clone());
+        self.store.persist_field(REDIS_KEY, user_id, Some(&user_entries), || entries.clone()).await;
+        Ok(entry)
+    }
+
+    /// `user_id`'s entries, newest first
+    pub async fn entries(&self, user_id: &str) -> Vec<SavingsEntry> {
+        let mut listed = self.user_entries(user_id).await;
+        listed.sort_by_key(|entry| std::cmp::Reverse(entry.saved_at));
+        listed
+    }
+
+    /// Totals for `user_id`, over one calendar year or all time
+    pub async fn summary(&self, user_id: &str, year: Option<i32>) -> SavingsSummary {
+        let entries = self.user_entries(user_id).await;
+        let mut totals = Sums::default();
+        let mut merchants: HashMap<MerchantDomain, Sums> = HashMap::new();
+        let mut months: BTreeMap<String, Sums> = BTreeMap::new();
+
+        for entry in &entries {
+            if year.is_some_and(|year| entry.saved_at.year() != year) {
+                continue;
+            }
+            totals.add(entry.amount);
+            merchants.entry(entry.merchant.clone()).or_default().add(entry.amount);
+            months.entry(entry.saved_at.format("%Y-%m").to_string()).or_default().add(entry.amount);
+        }
+
+        let mut by_merchant: Vec<MerchantSavings> = merchants
+            .iter()
+            .flat_map(|(merchant, sums)| {
+                sums.totals().map(|total| MerchantSavings {
+                    merchant: merchant.clone(),
+                    total,
+                })
+            })
+            .collect();
+        by_merchant.sort_by(|a, b| {
+            b.total
+                .saved
+                .amount
+                .cmp(&a.total.saved.amount)
+                .then_with(|| a.merchant.as_str().cmp(b.merchant.as_str()))
+        });
+        let mut totals: Vec<SavingsTotal> = totals.totals().collect();
+        totals.sort_by_key(|total| std::cmp::Reverse(total.saved.amount));
+
+        SavingsSummary {
+            user_id: user_id.to_string(),
+            year,
+            totals,
+            by_merchant,
+            by_month: months
+                .iter()
+                .flat_map(|(month, sums)| {
+                    sums.totals().map(|total| MonthSavings {
+                        month: month.clone(),
+                        total,
+                    })
+                })
+                .collect(),
+        }
+    }
+
+    /// Every user's entries saved at or after `since`, without the users
+    pub async fn entries_since(&self, since: DateTime<Utc>) -> Vec<SavingsEntry> {
+        let entries = self.all_entries().await;
+        entries.values().flatten().filter(|entry| entry.saved_at >= since).cloned().collect()
+    }
+
+    /// Per-user totals for marketing, over one calendar year or all time.
+    ///
+    /// Only aggregates leave the ledger; individual orders are not exported.
+    pub async fn export(&self, year: Option<i32>) -> Vec<SavingsExportRow> {
+        let entries = self.all_entries().await;
+        let mut rows = Vec::new();
+
+        for (user_id, user_entries) in entries.iter() {
+            let mut by_currency: HashMap<Currency, Vec<&SavingsEntry>> = HashMap::new();
+            for entry in user_entries {
+                if year.is_none_or(|year| entry.saved_at.year() == year) {
+                    by_currency.entry(entry.amount.currency).or_default().push(entry);
+                }
+            }
+
+            for (currency, entries) in by_currency {
+                let mut merchants: Vec<&MerchantDomain> = entries.iter().map(|e| &e.merchant).collect();
+                merchants.sort_by_key(|m| m.as_str());
+                merchants.dedup();
+                rows.push(SavingsExportRow {
+                    user_id: user_id.clone(),
+                    saved: Money::new(entries.iter().map(|e| e.amount.amount).sum(), currency),
+                    entries: entries.len() as u32,
+                    merchants: merchants.len() as u32,
+                    first_saved_at: entries.iter().map(|e| e.saved_at).min().unwrap_or_default(),
+                    last_saved_at: entries.iter().map(|e| e.saved_at).max().unwrap_or_default(),
+                });
+            }
+        }
+
+        rows.sort_by(|a, b| {
+            a.user_id
+                .cmp(&b.user_id)
+                .then_with(|| a.saved.currency.as_str().cmp(b.saved.currency.as_str()))
+        });
+        rows
+    }
+
+    /// Current time on the ledger clock, for "this year" defaults
+    pub fn now(&self) -> DateTime<Utc> {
+        self.clock.now()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use chrono::TimeZone;
+    use rust_decimal_macros::dec;
+
+    fn report(merchant: &str, amount: Decimal, saved_at: DateTime<Utc>) -> SavingsReport {
+        SavingsReport {
+            merchant: MerchantDomain::parse(merchant).unwrap(),
+            amount: Money::usd(amount),
+            code: None,
+            deal_id: None,
+            saved_at: Some(saved_at),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_summary_groups_by_merchant_and_month() {
+        let clock = Arc::new(MockClock::at(Utc.with_ymd_and_hms(2024, 6, 30, 12, 0, 0).unwrap()));
+        let ledger = SavingsLedger::new(None).with_clock(clock);
+        let day = |month, day| Utc.with_ymd_and_hms(2024, month, day, 10, 0, 0).unwrap();
+
+        ledger.record("u1", report("shop.example.com", dec!(5.50), day(5, 2))).await.unwrap();
+        ledger.record("u1", report("shop.example.com", dec!(4.50), day(6, 1))).await.unwrap();
+        ledger.record("u1", report("other.example.com", dec!(12.00), day(6, 20))).await.unwrap();
+        let last_year = Utc.with_ymd_and_hms(2023, 12, 24, 10, 0, 0).unwrap();
+        ledger.record("u1", report("shop.example.com", dec!(100), last_year)).await.unwrap();
+        ledger.record("u2", report("shop.example.com", dec!(3), day(6, 1))).await.unwrap();
+
+        assert!(ledger.record("u1", report("shop.example.com", dec!(0), day(6, 1))).await.is_err());
+        assert!(ledger
+            .record("u1", report("shop.example.com", dec!(1), day(6, 30) + chrono::TimeDelta::days(1)))
+            .await
+            .is_err());
+
+        let summary = ledger.summary("u1", Some(2024)).await;
+        assert_eq!(summary.totals.len(), 1);
+        assert_eq!(summary.totals[0].saved, Money::usd(dec!(22.00)));
+        assert_eq!(summary.totals[0].entries, 3);
+        let merchants: Vec<(&str, Decimal)> = summary
+            .by_merchant
+            .iter()
+            .map(|m| (m.merchant.as_str(), m.total.saved.amount))
+            .collect();
+        assert_eq!(merchants, vec![("other.example.com", dec!(12.00)), ("shop.example.com", dec!(10.00))]);
+        let months: Vec<(&str, Decimal)> = summary.by_month.iter().map(|m| (m.month.as_str(), m.total.saved.amount)).collect();
+        assert_eq!(months, vec![("2024-05", dec!(5.50)), ("2024-06", dec!(16.50))]);
+
+        assert_eq!(ledger.summary("u1", None).await.totals[0].saved, Money::usd(dec!(122.00)));
+
+        let export = ledger.export(Some(2024)).await;
+        assert_eq!(export.len(), 2);
+        assert_eq!((export[0].user_id.as_str(), export[0].entries, export[0].merchants), ("u1", 3, 2));
+        assert_eq!(export[0].first_saved_at, day(5, 2));
+        assert_eq!(export[1].saved, Money::usd(dec!(3)));
+    }
+
+    #[tokio::test]
+    async fn test_entries_are_reloaded_from_the_store() {
+        let path = std::env::temp_dir().join(format!("savings_{}.json", Uuid::new_v4()));
+        let clock = Arc::new(MockClock::at(Utc.with_ymd_and_hms(2024, 6, 30, 12, 0, 0).unwrap()));
+        let ledger = SavingsLedger::new(Some(path.clone())).with_clock(clock.clone());
+        let saved_at = Utc.with_ymd_and_hms(2024, 6, 1, 10, 0, 0).unwrap();
+        ledger.record("u1", report("shop.example.com", dec!(2), saved_at)).await.unwrap();
+        ledger.record("u2", report("shop.example.com", dec!(3), saved_at)).await.unwrap();
+
+        let restarted = SavingsLedger::new(Some(path.clone())).with_clock(clock);
+        restarted.reload().await.unwrap();
+        assert_eq!(restarted.entries("u1").await.len(), 1);
+        assert_eq!(restarted.export(None).await.len(), 2);
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,132 @@
+//! Feature extraction for deal engagement scoring
+
+use chrono::{Datelike, Utc};
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::models::deal::{Deal, PricePoint};
+
+/// Model input features, in the order the exported models expect them
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct DealFeatures {
+    /// Advertised discount as a fraction of the original price
+    pub discount_depth: f64,
+    /// Discount relative to the median observed price (negative when above median)
+    pub history_discount: f64,
+    /// Merchant share of listed deals, normalised against the busiest merchant
+    pub merchant_popularity: f64,
+    /// How in-season the deal's category is for the current month
+    pub category_seasonality: f64,
+}
+
+impl DealFeatures {
+    pub const NAMES: [&'static str; 4] = [
+        "discount_depth",
+        "history_discount",
+        "merchant_popularity",
+        "category_seasonality",
+    ];
+
+    pub fn to_vec(&self) -> Vec<f64> {
+        vec![
+            self.discount_depth,
+            self.history_discount,
+            self.merchant_popularity,
+            self.category_seasonality,
+        ]
+    }
+}
+
+pub struct FeatureExtractor;
+
+impl FeatureExtractor {
+    pub fn new() -> Self {
+        FeatureExtractor
+    }
+
+    pub fn extract(&self, deal: &Deal, history: &[PricePoint], merchant_popularity: f64) -> DealFeatures {
+        let discount_depth = deal
+            .price
+            .percent_off(deal.original_price)
+            .map_or(0.0, |percent| (percent.to_f64().unwrap_or(0.0) / 100.0).clamp(0.0, 1.0));
+
+        let history_discount = match median_price(history) {
+            Some(median) if median > Decimal::ZERO => ((median - deal.price.amount) / median)
+                .to_f64()
+                .unwrap_or(0.0)
+                .clamp(-1.0, 1.0),
+            _ => 0.0,
+        };
+
+        DealFeatures {
+            discount_depth,
+            history_discount,
+            merchant_popularity: merchant_popularity.clamp(0.0, 1.0),
+            category_seasonality: category_seasonality(&deal.category, Utc::now().month()),
+        }
+    }
+}
+
+impl Default for FeatureExtractor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn median_price(history: &[PricePoint]) -> Option<Decimal> {
+    if history.is_empty() {
+        return None;
+    }
+
+    let mut prices: Vec<Decimal> = history.iter().map(|p| p.price).collect();
+    prices.sort();
+
+    let mid = prices.len() / 2;
+    if prices.len() % 2 == 1 {
+        Some(prices[mid])
+    } else {
+        Some((prices[mid - 1] + prices[mid]) / Decimal::TWO)
+    }
+}
+
+/// Seasonal demand for a category in the given month (1-12), from 0.0 (off season) to 1.0 (peak)
+pub fn category_seasonality(category: &str, month: u32) -> f64 {
+    match (category, month) {
+        ("electronics", 11 | 12) => 1.0,
+        ("electronics", 7) => 0.8, // Prime Day
+        ("toys", 11 | 12) => 1.0,
+        ("back_to_school", 7 | 8) => 1.0,
+        ("back_to_school", 9) => 0.6,
+        ("outdoor" | "garden", 4..=6) => 0.9,
+        ("kitchen", 11 | 12) => 0.8,
+        ("fashion", 1 | 7) => 0.7, // clearance seasons
+        ("travel", 1 | 2) => 0.8,
+        _ => 0.5,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_median_price() {
+        let history: Vec<PricePoint> = [dec!(10), dec!(30), dec!(20), dec!(40)]
+            .iter()
+            .map(|&price| PricePoint { price, observed_at: Utc::now() })
+            .collect();
+
+        assert_eq!(median_price(&history), Some(dec!(25)));
+        assert_eq!(median_price(&[]), None);
+    }
+
+    #[test]
+    fn test_seasonality_peaks() {
+        assert_eq!(category_seasonality("electronics", 11), 1.0);
+        assert_eq!(category_seasonality("back_to_school", 8), 1.0);
+        assert_eq!(category_seasonality("books", 3), 0.5);
+    }
+}
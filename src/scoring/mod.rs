@@ -0,0 +1,117 @@
+//! Deal quality ("hotness") scoring pipeline
+//!
+//! Extracts engagement features for each deal, runs them through the configured
+//! model and attaches the result as `score`, which the listing endpoints rank by.
+
+pub mod features;
+pub mod model;
+
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::models::deal::Deal;
+use crate::models::domain::MerchantDomain;
+use crate::storage::deal_store::DealStore;
+use features::{DealFeatures, FeatureExtractor};
+use model::{LinearModel, ScoringModel};
+
+/// One training row as exported for offline model fitting
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FeatureRow {
+    pub deal_id: String,
+    pub category: String,
+    pub merchant_domain: MerchantDomain,
+    pub features: DealFeatures,
+}
+
+pub struct DealScorer {
+    model: Box<dyn ScoringModel>,
+    extractor: FeatureExtractor,
+}
+
+impl DealScorer {
+    pub fn new(model: Box<dyn ScoringModel>) -> Self {
+        Self {
+            model,
+            extractor: FeatureExtractor::new(),
+        }
+    }
+
+    /// Load the model configured via `SCORING_ONNX_MODEL` or `SCORING_MODEL_PATH`,
+    /// falling back to the baseline weights
+    pub fn from_env() -> Self {
+        #[cfg(feature = "onnx")]
+        if let Ok(path) = std::env::var("SCORING_ONNX_MODEL") {
+            match model::onnx::OnnxModel::from_file(&path) {
+                Ok(model) => return Self::new(Box::new(model)),
+                Err(e) => tracing::warn!(path = %path, error = %e, "Failed to load the ONNX model"),
+            }
+        }
+
+        if let Ok(path) = std::env::var("SCORING_MODEL_PATH") {
+            match LinearModel::from_file(&path) {
+                Ok(model) => return Self::new(Box::new(model)),
+                Err(e) => tracing::warn!(path = %path, error = %e, "Failed to load the scoring model"),
+            }
+        }
+
+        Self::new(Box::new(LinearModel::default()))
+    }
+
+    pub fn model_version(&self) -> &str {
+        self.model.version()
+    }
+
+    /// Compute features for every deal using the store's price history and merchant stats
+    pub async fn extract_features(&self, store: &DealStore, deals: &[Deal]) -> Vec<DealFeatures> {
+        let merchant_counts = store.merchant_deal_counts().await;
+        let busiest = merchant_counts.values().copied().max().unwrap_or(1).max(1) as f64;
+
+        let mut rows = Vec::with_capacity(deals.len());
+        for deal in deals {
+            let history = store.price_history(&deal.product_id).await;
+            let popularity = merchant_counts.get(&deal.merchant_domain).copied().unwrap_or(0) as f64 / busiest;
+            rows.push(self.extractor.extract(deal, &history, popularity));
+        }
+        rows
+    }
+
+    /// Attach a score to each deal and sort them best first
+    pub async fn score_and_rank(&self, store: &DealStore, deals: Vec<Deal>) -> Vec<Deal> {
+        let features = self.extract_features(store, &deals).await;
+
+        let mut scored: Vec<Deal> = deals
+            .into_iter()
+            .zip(features)
+            .map(|(mut deal, features)| {
+                deal.score = match self.model.predict(&features) {
+                    Ok(score) => Some((score * 1000.0).round() / 1000.0),
+                    Err(e) => {
+                        tracing::warn!(deal = %deal.id, error = %e, "Failed to score a deal");
+                        None
+                    }
+                };
+                deal
+            })
+            .collect();
+
+        scored.sort_by(|a, b| b.score.unwrap_or(0.0).total_cmp(&a.score.unwrap_or(0.0)));
+        scored
+    }
+
+    /// Feature rows for offline training
+    pub async fn export_features(&self, store: &DealStore, deals: &[Deal]) -> Vec<FeatureRow> {
+        let features = self.extract_features(store, deals).await;
+
+        deals
+            .iter()
+            .zip(features)
+            .map(|(deal, features)| FeatureRow {
+                deal_id: deal.id.clone(),
+                category: deal.category.clone(),
+                merchant_domain: deal.merchant_domain.clone(),
+                features,
+            })
+            .collect()
+    }
+}
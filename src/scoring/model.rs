@@ -0,0 +1,144 @@
+//! Engagement models that turn deal features into a score
+//!
+//! Models are trained offline on the rows exported by `/deals/features` and loaded
+//! at startup, either as exported logistic-regression weights (JSON) or, with the
+//! `onnx` feature enabled, as an ONNX graph.
+
+use serde::{Deserialize, Serialize};
+
+use super::features::DealFeatures;
+
+pub trait ScoringModel: Send + Sync {
+    /// Predicted engagement probability (0.0 - 1.0)
+    fn predict(&self, features: &DealFeatures) -> Result<f64, Box<dyn std::error::Error + Send + Sync>>;
+
+    fn version(&self) -> &str;
+}
+
+/// Logistic regression over [`DealFeatures`], as exported by the offline trainer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LinearModel {
+    pub version: String,
+    pub bias: f64,
+    pub weights: Vec<f64>,
+}
+
+impl LinearModel {
+    pub fn from_file(path: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let content = std::fs::read_to_string(path)?;
+        let model: LinearModel = serde_json::from_str(&content)?;
+
+        if model.weights.len() != DealFeatures::NAMES.len() {
+            return Err(format!(
+                "Model {} has {} weights, expected {}",
+                model.version,
+                model.weights.len(),
+                DealFeatures::NAMES.len()
+            )
+            .into());
+        }
+
+        Ok(model)
+    }
+}
+
+impl Default for LinearModel {
+    /// Hand-tuned baseline used until a trained model is deployed
+    fn default() -> Self {
+        Self {
+            version: "baseline-v1".to_string(),
+            bias: -2.0,
+            weights: vec![2.5, 3.0, 1.0, 1.2],
+        }
+    }
+}
+
+impl ScoringModel for LinearModel {
+    fn predict(&self, features: &DealFeatures) -> Result<f64, Box<dyn std::error::Error + Send + Sync>> {
+        let logit = self.bias
+            + features
+                .to_vec()
+                .iter()
+                .zip(&self.weights)
+                .map(|(x, w)| x * w)
+                .sum::<f64>();
+
+        Ok(1.0 / (1.0 + (-logit).exp()))
+    }
+
+    fn version(&self) -> &str {
+        &self.version
+    }
+}
+
+/// ONNX runtime model (currently disabled - build with `--features onnx` to enable)
+#[cfg(feature = "onnx")]
+pub mod onnx {
+    use super::*;
+    use ort::session::Session;
+    use ort::value::Tensor;
+    use std::sync::Mutex;
+
+    pub struct OnnxModel {
+        session: Mutex<Session>,
+        version: String,
+    }
+
+    impl OnnxModel {
+        pub fn from_file(path: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+            let session = Session::builder()?.commit_from_file(path)?;
+
+            Ok(Self {
+                session: Mutex::new(session),
+                version: format!("onnx:{}", path),
+            })
+        }
+    }
+
+    impl ScoringModel for OnnxModel {
+        fn predict(&self, features: &DealFeatures) -> Result<f64, Box<dyn std::error::Error + Send + Sync>> {
+            let input: Vec<f32> = features.to_vec().into_iter().map(|x| x as f32).collect();
+            let tensor = Tensor::from_array(([1usize, input.len()], input))?;
+
+            let mut session = self.session.lock().map_err(|_| "ONNX session lock poisoned")?;
+            let outputs = session.run(ort::inputs![tensor])?;
+            let (_, scores) = outputs[0].try_extract_tensor::<f32>()?;
+
+            scores
+                .first()
+                .map(|&s| s as f64)
+                .ok_or_else(|| "ONNX model returned no output".into())
+        }
+
+        fn version(&self) -> &str {
+            &self.version
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deeper_discounts_score_higher() {
+        let model = LinearModel::default();
+        let shallow = DealFeatures {
+            discount_depth: 0.1,
+            history_discount: 0.0,
+            merchant_popularity: 0.5,
+            category_seasonality: 0.5,
+        };
+        let deep = DealFeatures {
+            discount_depth: 0.5,
+            history_discount: 0.3,
+            ..shallow.clone()
+        };
+
+        let shallow_score = model.predict(&shallow).unwrap();
+        let deep_score = model.predict(&deep).unwrap();
+
+        assert!(deep_score > shallow_score);
+        assert!((0.0..=1.0).contains(&deep_score));
+    }
+}
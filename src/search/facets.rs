@@ -0,0 +1,162 @@
+//! Facet counts for the search filter sidebar
+//!
+//! Counts are taken over the deals matching the current query, so every value
+//! shown narrows the results rather than emptying them.
+
+use std::collections::HashMap;
+
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::models::deal::Deal;
+
+/// Discount percentage bands, lower bound inclusive
+const DISCOUNT_BANDS: &[(f64, Option<f64>)] = &[(0.0, Some(10.0)), (10.0, Some(25.0)), (25.0, Some(50.0)), (50.0, None)];
+
+/// Price bands in the deal's currency, lower bound inclusive
+const PRICE_BANDS: &[(Decimal, Option<Decimal>)] = &[
+    (dec!(0), Some(dec!(25))),
+    (dec!(25), Some(dec!(50))),
+    (dec!(50), Some(dec!(100))),
+    (dec!(100), Some(dec!(250))),
+    (dec!(250), Some(dec!(500))),
+    (dec!(500), Some(dec!(1000))),
+    (dec!(1000), None),
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct ValueCount {
+    pub value: String,
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+#[aliases(DiscountRange = RangeCount<f64>, PriceRange = RangeCount<Decimal>)]
+pub struct RangeCount<T> {
+    pub label: String,
+    pub min: T,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<T>,
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct Facets {
+    pub categories: Vec<ValueCount>,
+    /// Counts per store
+    pub platforms: Vec<ValueCount>,
+    pub brands: Vec<ValueCount>,
+    /// By honest discount where known, else the advertised discount
+    #[schema(value_type = Vec<DiscountRange>)]
+    pub discounts: Vec<RangeCount<f64>>,
+    #[schema(value_type = Vec<PriceRange>)]
+    pub prices: Vec<RangeCount<Decimal>>,
+    /// Curation tags
+    #[serde(default)]
+    pub tags: Vec<ValueCount>,
+}
+
+pub fn compute_facets<'a>(deals: impl IntoIterator<Item = &'a Deal>) -> Facets {
+    let mut categories = HashMap::new();
+    let mut platforms = HashMap::new();
+    let mut brands = HashMap::new();
+    let mut tags = HashMap::new();
+    let mut discounts = vec![0; DISCOUNT_BANDS.len()];
+    let mut prices = vec![0; PRICE_BANDS.len()];
+
+    for deal in deals {
+        *categories.entry(deal.category.as_str()).or_insert(0) += 1;
+        *platforms.entry(deal.store.as_str()).or_insert(0) += 1;
+        if let Some(brand) = &deal.brand {
+            *brands.entry(brand.as_str()).or_insert(0) += 1;
+        }
+        for tag in &deal.tags {
+            *tags.entry(tag.as_str()).or_insert(0) += 1;
+        }
+
+        let discount = deal.honest_discount.unwrap_or(deal.discount);
+        if let Some(band) = DISCOUNT_BANDS
+            .iter()
+            .position(|&(min, max)| discount >= min && max.is_none_or(|max| discount < max))
+        {
+            discounts[band] += 1;
+        }
+
+        let price = deal.price.amount;
+        if let Some(band) = PRICE_BANDS
+            .iter()
+            .position(|&(min, max)| price >= min && max.is_none_or(|max| price < max))
+        {
+            prices[band] += 1;
+        }
+    }
+
+    Facets {
+        categories: sorted_counts(categories),
+        platforms: sorted_counts(platforms),
+        brands: sorted_counts(brands),
+        discounts: DISCOUNT_BANDS
+            .iter()
+            .zip(discounts)
+            .map(|(&(min, max), count)| RangeCount {
+                label: match max {
+                    Some(max) => format!("{:.0}-{:.0}%", min, max),
+                    None => format!("{:.0}%+", min),
+                },
+                min,
+                max,
+                count,
+            })
+            .collect(),
+        prices: PRICE_BANDS
+            .iter()
+            .zip(prices)
+            .map(|(&(min, max), count)| RangeCount {
+                label: match max {
+                    Some(max) if min.is_zero() => format!("under ${}", max),
+                    Some(max) => format!("${}-${}", min, max),
+                    None => format!("${}+", min),
+                },
+                min,
+                max,
+                count,
+            })
+            .collect(),
+        tags: sorted_counts(tags),
+    }
+}
+
+/// Most common first, ties alphabetical
+fn sorted_counts(counts: HashMap<&str, usize>) -> Vec<ValueCount> {
+    let mut counts: Vec<ValueCount> = counts
+        .into_iter()
+        .map(|(value, count)| ValueCount {
+            value: value.to_string(),
+            count,
+        })
+        .collect();
+    counts.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value)));
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::deal_store::DealStore;
+
+    #[tokio::test]
+    async fn test_every_deal_lands_in_one_band() {
+        let deals = DealStore::with_sample_data().list().await;
+        let facets = compute_facets(&deals);
+
+        let total = |counts: &[ValueCount]| counts.iter().map(|c| c.count).sum::<usize>();
+        assert_eq!(total(&facets.categories), deals.len());
+        assert_eq!(total(&facets.platforms), deals.len());
+        assert_eq!(facets.discounts.iter().map(|b| b.count).sum::<usize>(), deals.len());
+        assert_eq!(facets.prices.iter().map(|b| b.count).sum::<usize>(), deals.len());
+        assert!(facets.platforms.windows(2).all(|w| w[0].count >= w[1].count));
+        assert_eq!(facets.prices[0].label, "under $25");
+    }
+}
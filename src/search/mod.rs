@@ -0,0 +1,128 @@
+//! Deal search
+//!
+//! Structured constraints parsed from the query filter the catalogue; the
+//! remaining keywords are matched against deal text, blended with embedding
+//! similarity so near-synonyms still surface.
+
+pub mod facets;
+pub mod query;
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::models::deal::Deal;
+use crate::recommendations::embeddings::{cosine_similarity, Embedder, HashingEmbedder};
+use query::{parse_query, ParsedQuery, SearchFilters, Vocabulary};
+
+const KEYWORD_WEIGHT: f64 = 0.7;
+const SEMANTIC_WEIGHT: f64 = 0.3;
+/// Hits below this relevance are dropped when the query has keywords
+const MIN_RELEVANCE: f64 = 0.15;
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct SearchHit {
+    pub deal: Deal,
+    pub relevance: f64,
+}
+
+pub struct DealSearch {
+    embedder: Box<dyn Embedder>,
+}
+
+impl DealSearch {
+    pub fn new() -> Self {
+        Self {
+            embedder: Box::new(HashingEmbedder::default()),
+        }
+    }
+
+    /// Interpret the query and rank matching deals; `deals` should already be scored
+    pub fn search(&self, text: &str, deals: Vec<Deal>) -> (ParsedQuery, Vec<SearchHit>) {
+        self.search_filtered(text, &SearchFilters::default(), deals)
+    }
+
+    /// [`DealSearch::search`], with `filters` overriding the text's own constraints
+    pub fn search_filtered(&self, text: &str, filters: &SearchFilters, deals: Vec<Deal>) -> (ParsedQuery, Vec<SearchHit>) {
+        let mut query = parse_query(text, &Vocabulary::from_deals(&deals));
+        query.restrict(filters);
+        let query_embedding = (!query.keywords.is_empty()).then(|| self.embedder.embed_text(&query.keywords.join(" ")));
+
+        let mut hits: Vec<SearchHit> = deals
+            .into_iter()
+            .filter(|deal| query.matches(deal))
+            .filter_map(|deal| {
+                let relevance = match &query_embedding {
+                    None => 1.0,
+                    Some(embedding) => {
+                        let semantic = cosine_similarity(embedding, &self.embedder.embed(&deal)).max(0.0) as f64;
+                        KEYWORD_WEIGHT * keyword_coverage(&query.keywords, &deal) + SEMANTIC_WEIGHT * semantic
+                    }
+                };
+                (relevance >= MIN_RELEVANCE).then(|| SearchHit {
+                    deal,
+                    relevance: (relevance * 1000.0).round() / 1000.0,
+                })
+            })
+            .collect();
+
+        hits.sort_by(|a, b| {
+            b.relevance
+                .total_cmp(&a.relevance)
+                .then_with(|| b.deal.score.unwrap_or(0.0).total_cmp(&a.deal.score.unwrap_or(0.0)))
+        });
+        (query, hits)
+    }
+}
+
+impl Default for DealSearch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Fraction of keywords found in the deal's text, ignoring plural "s"
+fn keyword_coverage(keywords: &[String], deal: &Deal) -> f64 {
+    let haystack = format!(
+        "{} {} {} {}",
+        deal.title,
+        deal.category.replace('_', " "),
+        deal.brand.as_deref().unwrap_or(""),
+        deal.store
+    )
+    .to_lowercase();
+    let tokens: Vec<&str> = haystack
+        .split(|c: char| !c.is_alphanumeric() && c != '-')
+        .map(|t| t.trim_end_matches('s'))
+        .collect();
+
+    let found = keywords
+        .iter()
+        .filter(|k| tokens.contains(&k.trim_end_matches('s')))
+        .count();
+    found as f64 / keywords.len() as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::deal_store::DealStore;
+
+    #[tokio::test]
+    async fn test_keywords_and_filters_combine() {
+        let deals = DealStore::with_sample_data().list().await;
+        let (query, hits) = DealSearch::new().search("laptops under $600", deals);
+
+        assert_eq!(query.max_price, Some(rust_decimal_macros::dec!(600)));
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].deal.id, "deal_1");
+    }
+
+    #[tokio::test]
+    async fn test_filter_only_query_returns_all_matches() {
+        let deals = DealStore::with_sample_data().list().await;
+        let (_, hits) = DealSearch::new().search("at amazon with free shipping", deals);
+
+        assert_eq!(hits.len(), 2);
+        assert!(hits.iter().all(|hit| hit.deal.store == "Amazon"));
+    }
+}
@@ -0,0 +1,352 @@
+//! Query understanding for deal search
+//!
+//! Pulls structured constraints ("under $50", "at walmart", "with free shipping",
+//! known brands and categories, `#tags`) out of the search text. Whatever is left
+//! over is treated as keywords for text/semantic matching.
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::models::deal::Deal;
+use crate::tagging;
+
+lazy_static! {
+    static ref RANGE_PATTERN: Regex = Regex::new(
+        r"(?i)\bbetween\s+\$?(\d[\d,]*(?:\.\d{1,2})?)\s+(?:and|to)\s+\$?(\d[\d,]*(?:\.\d{1,2})?)|\$(\d[\d,]*(?:\.\d{1,2})?)\s*(?:-|to)\s*\$?(\d[\d,]*(?:\.\d{1,2})?)"
+    ).unwrap();
+    static ref DISCOUNT_PATTERN: Regex = Regex::new(
+        r"(?i)(?:\b(?:at\s+least|min(?:imum)?|over|up\s+to)\s+)?(\d{1,2})\s*%\s*(?:off|discount)?"
+    ).unwrap();
+    static ref MAX_PRICE_PATTERN: Regex = Regex::new(
+        r"(?i)(?:\b(?:under|below|less\s+than|cheaper\s+than|up\s+to|at\s+most|max)|<)\s*\$?(\d[\d,]*(?:\.\d{1,2})?)"
+    ).unwrap();
+    static ref MIN_PRICE_PATTERN: Regex = Regex::new(
+        r"(?i)(?:\b(?:over|above|more\s+than|at\s+least|min)|>)\s*\$?(\d[\d,]*(?:\.\d{1,2})?)"
+    ).unwrap();
+    static ref FREE_SHIPPING_PATTERN: Regex = Regex::new(
+        r"(?i)\b(?:with\s+)?free\s+(?:shipping|delivery)\b"
+    ).unwrap();
+    static ref TAG_PATTERN: Regex = Regex::new(r"(?:^|\s)#([\w-]+)").unwrap();
+}
+
+/// Words that carry no meaning for matching once constraints are removed
+const STOPWORDS: &[&str] = &[
+    "a", "an", "the", "for", "with", "and", "or", "of", "in", "on", "at", "from", "deal", "deals", "cheap", "best",
+    "good", "sale", "discount", "discounts", "price", "prices", "offer", "offers", "me", "show", "find",
+];
+
+/// Stores, brands and categories the parser can recognise, taken from the catalogue
+#[derive(Debug, Default)]
+pub struct Vocabulary {
+    stores: Vec<String>,
+    brands: Vec<String>,
+    categories: Vec<String>,
+}
+
+impl Vocabulary {
+    pub fn from_deals(deals: &[Deal]) -> Self {
+        let mut vocabulary = Self::default();
+        for deal in deals {
+            push_unique(&mut vocabulary.stores, &deal.store);
+            if let Some(brand) = &deal.brand {
+                push_unique(&mut vocabulary.brands, brand);
+            }
+            push_unique(&mut vocabulary.categories, &deal.category);
+        }
+        vocabulary
+    }
+}
+
+fn push_unique(list: &mut Vec<String>, value: &str) {
+    if !list.iter().any(|v| v.eq_ignore_ascii_case(value)) {
+        list.push(value.to_string());
+    }
+}
+
+/// The interpreted search query, returned to the client for display
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct ParsedQuery {
+    pub keywords: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_price: Option<Decimal>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_price: Option<Decimal>,
+    /// Minimum discount percentage
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_discount: Option<f64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stores: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub brands: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub categories: Vec<String>,
+    /// Curation tags every result must have
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    pub free_shipping: bool,
+    /// Human-readable interpretation, e.g. `"laptop" under $500 at Walmart`
+    pub summary: String,
+}
+
+/// Constraints given as separate request parameters rather than in the search text
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct SearchFilters {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub store: Option<String>,
+    /// Minimum discount percentage
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_discount: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_price: Option<Decimal>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_price: Option<Decimal>,
+    /// Comma-separated curation tags, all required
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<String>,
+}
+
+impl ParsedQuery {
+    /// Apply explicit filters; each replaces what the text said about the same constraint
+    pub fn restrict(&mut self, filters: &SearchFilters) {
+        if let Some(category) = filters.category.as_deref().map(str::trim).filter(|c| !c.is_empty()) {
+            self.categories = vec![category.to_string()];
+        }
+        if let Some(store) = filters.store.as_deref().map(str::trim).filter(|s| !s.is_empty()) {
+            self.stores = vec![store.to_string()];
+        }
+        self.min_discount = filters.min_discount.or(self.min_discount);
+        self.min_price = filters.min_price.or(self.min_price);
+        self.max_price = filters.max_price.or(self.max_price);
+        let tags = tagging::parse_filter(filters.tags.as_deref());
+        if !tags.is_empty() {
+            self.tags = tags;
+        }
+        self.summary = summarize(self);
+    }
+
+    /// Whether a deal satisfies every structured constraint
+    pub fn matches(&self, deal: &Deal) -> bool {
+        let discount = deal.honest_discount.unwrap_or(deal.discount);
+
+        self.min_price.is_none_or(|min| deal.price.amount >= min)
+            && self.max_price.is_none_or(|max| deal.price.amount <= max)
+            && self.min_discount.is_none_or(|min| discount >= min)
+            && (!self.free_shipping || deal.free_shipping)
+            && (self.stores.is_empty() || self.stores.iter().any(|s| s.eq_ignore_ascii_case(&deal.store)))
+            && (self.brands.is_empty()
+                || deal.brand.as_ref().is_some_and(|b| self.brands.iter().any(|s| s.eq_ignore_ascii_case(b))))
+            && (self.categories.is_empty() || self.categories.iter().any(|c| c.eq_ignore_ascii_case(&deal.category)))
+            && tagging::has_all(&deal.tags, &self.tags)
+    }
+}
+
+pub fn parse_query(text: &str, vocabulary: &Vocabulary) -> ParsedQuery {
+    let mut query = ParsedQuery::default();
+    let mut remaining = text.to_string();
+
+    for caps in TAG_PATTERN.captures_iter(text) {
+        if let Ok(tag) = tagging::slug(&caps[1]) {
+            query.tags.push(tag);
+            remaining = blank(&remaining, caps.get(0).unwrap());
+        }
+    }
+
+    if let Some(caps) = RANGE_PATTERN.captures(&remaining) {
+        let low = caps.get(1).or(caps.get(3)).and_then(|m| parse_amount(m.as_str()));
+        let high = caps.get(2).or(caps.get(4)).and_then(|m| parse_amount(m.as_str()));
+        if let (Some(low), Some(high)) = (low, high) {
+            query.min_price = Some(low.min(high));
+            query.max_price = Some(low.max(high));
+        }
+        remaining = blank(&remaining, caps.get(0).unwrap());
+    }
+
+    // Discounts first so "up to 50% off" is not read as a price ceiling
+    if let Some(caps) = DISCOUNT_PATTERN.captures(&remaining) {
+        query.min_discount = caps[1].parse().ok();
+        remaining = blank(&remaining, caps.get(0).unwrap());
+    }
+
+    if query.max_price.is_none() {
+        if let Some(caps) = MAX_PRICE_PATTERN.captures(&remaining) {
+            query.max_price = parse_amount(&caps[1]);
+            remaining = blank(&remaining, caps.get(0).unwrap());
+        }
+    }
+    if query.min_price.is_none() {
+        if let Some(caps) = MIN_PRICE_PATTERN.captures(&remaining) {
+            query.min_price = parse_amount(&caps[1]);
+            remaining = blank(&remaining, caps.get(0).unwrap());
+        }
+    }
+
+    if let Some(m) = FREE_SHIPPING_PATTERN.find(&remaining) {
+        query.free_shipping = true;
+        remaining = blank(&remaining, m);
+    }
+
+    for store in &vocabulary.stores {
+        // Require a preposition so product words that happen to be store names stay keywords
+        let pattern = format!(r"(?i)\b(?:at|from|on)\s+{}\b", regex::escape(store));
+        if let Some(m) = Regex::new(&pattern).ok().and_then(|re| re.find(&remaining)) {
+            query.stores.push(store.clone());
+            remaining = blank(&remaining, m);
+        }
+    }
+
+    for brand in &vocabulary.brands {
+        if let Some(m) = find_term(&remaining, brand) {
+            query.brands.push(brand.clone());
+            remaining = blank(&remaining, m);
+        }
+    }
+
+    for category in &vocabulary.categories {
+        if let Some(m) = find_term(&remaining, &category.replace('_', " ")) {
+            query.categories.push(category.clone());
+            remaining = blank(&remaining, m);
+        }
+    }
+
+    query.keywords = remaining
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric() && c != '-')
+        .map(|t| t.trim_matches('-'))
+        .filter(|t| !t.is_empty() && !STOPWORDS.contains(t))
+        .map(String::from)
+        .collect();
+    query.summary = summarize(&query);
+    query
+}
+
+fn find_term<'a>(text: &'a str, term: &str) -> Option<regex::Match<'a>> {
+    let pattern = format!(r"(?i)\b{}\b", regex::escape(term));
+    Regex::new(&pattern).ok()?.find(text)
+}
+
+/// Replace a matched span with spaces so later patterns and keywords skip it
+fn blank(text: &str, m: regex::Match) -> String {
+    format!("{}{}{}", &text[..m.start()], " ".repeat(m.len()), &text[m.end()..])
+}
+
+fn parse_amount(raw: &str) -> Option<Decimal> {
+    raw.replace(',', "").parse().ok()
+}
+
+fn summarize(query: &ParsedQuery) -> String {
+    let mut parts = Vec::new();
+    if !query.keywords.is_empty() {
+        parts.push(format!("\"{}\"", query.keywords.join(" ")));
+    }
+    if !query.brands.is_empty() {
+        parts.push(query.brands.join(" or "));
+    }
+    if !query.categories.is_empty() {
+        parts.push(format!("in {}", query.categories.join(" or ").replace('_', " ")));
+    }
+    match (query.min_price, query.max_price) {
+        (Some(min), Some(max)) => parts.push(format!("${:.0}-${:.0}", min, max)),
+        (None, Some(max)) => parts.push(format!("under ${:.0}", max)),
+        (Some(min), None) => parts.push(format!("over ${:.0}", min)),
+        (None, None) => {}
+    }
+    if let Some(discount) = query.min_discount {
+        parts.push(format!("at least {:.0}% off", discount));
+    }
+    if !query.stores.is_empty() {
+        parts.push(format!("at {}", query.stores.join(" or ")));
+    }
+    if query.free_shipping {
+        parts.push("with free shipping".to_string());
+    }
+    if !query.tags.is_empty() {
+        parts.push(format!("tagged {}", query.tags.iter().map(|tag| format!("#{}", tag)).collect::<Vec<_>>().join(" ")));
+    }
+
+    if parts.is_empty() {
+        "All deals".to_string()
+    } else {
+        format!("Deals: {}", parts.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::deal_store::DealStore;
+    use rust_decimal_macros::dec;
+
+    async fn vocabulary() -> Vocabulary {
+        Vocabulary::from_deals(&DealStore::with_sample_data().list().await)
+    }
+
+    #[tokio::test]
+    async fn test_extracts_price_store_and_shipping() {
+        let query = parse_query("backpack under $50 at walmart with free shipping", &vocabulary().await);
+
+        assert_eq!(query.keywords, vec!["backpack".to_string()]);
+        assert_eq!(query.max_price, Some(dec!(50)));
+        assert_eq!(query.stores, vec!["Walmart".to_string()]);
+        assert!(query.free_shipping);
+    }
+
+    #[tokio::test]
+    async fn test_extracts_brand_range_and_discount() {
+        let query = parse_query("sony headphones between 200 and 300 at least 25% off", &vocabulary().await);
+
+        assert_eq!(query.brands, vec!["Sony".to_string()]);
+        assert_eq!((query.min_price, query.max_price), (Some(dec!(200)), Some(dec!(300))));
+        assert_eq!(query.min_discount, Some(25.0));
+        assert_eq!(query.keywords, vec!["headphones".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_explicit_filters_replace_what_the_text_said() {
+        let mut query = parse_query("headphones under $50 at amazon", &vocabulary().await);
+        query.restrict(&SearchFilters {
+            store: Some("Best Buy".to_string()),
+            max_price: Some(dec!(300)),
+            category: Some(" ".to_string()),
+            ..Default::default()
+        });
+
+        assert_eq!(query.stores, vec!["Best Buy".to_string()]);
+        assert_eq!((query.min_price, query.max_price), (None, Some(dec!(300))));
+        assert!(query.categories.is_empty());
+        assert_eq!(query.summary, "Deals: \"headphones\" under $300 at Best Buy");
+    }
+
+    #[tokio::test]
+    async fn test_up_to_percent_is_not_a_price() {
+        let query = parse_query("laptops up to 40% off", &vocabulary().await);
+
+        assert_eq!(query.min_discount, Some(40.0));
+        assert_eq!(query.max_price, None);
+    }
+
+    #[tokio::test]
+    async fn test_hashtags_become_required_tags() {
+        let mut query = parse_query("#student-discount laptops under $800", &vocabulary().await);
+        assert_eq!(query.tags, vec!["student-discount".to_string()]);
+        assert_eq!(query.keywords, vec!["laptops".to_string()]);
+        assert_eq!(query.summary, "Deals: \"laptops\" under $800 tagged #student-discount");
+
+        let mut deal = DealStore::with_sample_data().list().await.remove(0);
+        deal.price = crate::models::domain::Money::usd(dec!(500));
+        assert!(!query.matches(&deal));
+        deal.tags = vec!["app-only".to_string(), "student-discount".to_string()];
+        assert!(query.matches(&deal));
+
+        query.restrict(&SearchFilters {
+            tags: Some("app-only, Editors Pick".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(query.tags, vec!["app-only".to_string(), "editors-pick".to_string()]);
+        assert!(!query.matches(&deal));
+    }
+}
@@ -0,0 +1,523 @@
+//! Cold-start seeding from feed bundles
+//!
+//! A new regional deployment starts without merchants or coupons. A seed bundle
+//! names the merchants a region launches with and the public or partner feeds of
+//! their coupons; a seed run gives each merchant a domain profile (see
+//! [`DomainProfiles`]) and loads the coupons of every feed into the corpus. Runs
+//! are idempotent: merchants that already have a profile keep it, coupons already
+//! in the corpus with the same offer are left alone, and a feed that fails is
+//! reported without stopping the others, so a run can simply be repeated.
+//!
+//! Bundles are read from the JSON array at `SEED_BUNDLES_PATH`; relative feed paths
+//! are resolved against the file's directory. A feed's coupons are attributed to
+//! its merchant, whatever host serves the feed, and validated like scraped ones:
+//! parser `v2` or later is needed for the discounts of feed records to be read.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::runtime::Handle;
+use tokio::sync::{mpsc, Mutex};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::coupon_engine::parser::{Parser, ParserVersion};
+use crate::coupon_engine::profiles::{DomainProfiles, ProfileSettings};
+use crate::coupon_engine::{CouponEngine, EngineConfig};
+use crate::licensing::SourceLicenses;
+use crate::models::domain::MerchantDomain;
+use crate::reprocess::{listing, same_offer, RunStatus};
+use crate::storage::coupon_store::CouponStore;
+
+const FEED_TIMEOUT: Duration = Duration::from_secs(60);
+/// Response chunks of a feed read ahead of its parser
+const FEED_CHUNKS: usize = 16;
+/// Failed feeds listed in a run; the count covers the rest
+const MAX_REPORTED_FAILURES: usize = 50;
+/// Finished runs kept for status lookups; older ones are dropped first
+const MAX_FINISHED_RUNS: usize = 20;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SeedBundle {
+    /// e.g. `de` or `uk-launch`
+    pub name: String,
+    #[serde(default)]
+    pub merchants: Vec<SeedMerchant>,
+    #[serde(default)]
+    pub feeds: Vec<SeedFeed>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SeedMerchant {
+    pub domain: MerchantDomain,
+    /// The merchant's initial profile; only used when it has none yet
+    #[serde(default)]
+    pub settings: ProfileSettings,
+}
+
+/// A coupon feed, at an HTTP(S) `url` or a local `path`
+#[derive(Debug, Clone, Deserialize)]
+pub struct SeedFeed {
+    pub merchant: MerchantDomain,
+    pub url: Option<String>,
+    pub path: Option<PathBuf>,
+}
+
+impl SeedFeed {
+    fn location(&self) -> String {
+        match (&self.url, &self.path) {
+            (Some(url), _) => url.clone(),
+            (None, Some(path)) => path.display().to_string(),
+            (None, None) => format!("(no url or path for {})", self.merchant),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, ToSchema)]
+pub struct SeedRequest {
+    /// Bundles to seed, by name; all of them when empty
+    #[serde(default)]
+    pub bundles: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct FeedFailure {
+    pub merchant: MerchantDomain,
+    pub feed: String,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SeedRun {
+    pub id: Uuid,
+    pub status: RunStatus,
+    pub bundles: Vec<String>,
+    pub merchants_total: u32,
+    /// Merchants given a profile by this run
+    pub merchants_added: u32,
+    /// Merchants that already had a profile
+    pub merchants_existing: u32,
+    pub feeds_total: u32,
+    pub feeds_done: u32,
+    pub feeds_failed: u32,
+    pub coupons_added: u32,
+    pub coupons_changed: u32,
+    pub coupons_unchanged: u32,
+    pub failures: Vec<FeedFailure>,
+    pub error: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SeedError {
+    /// `SEED_BUNDLES_PATH` is not set or lists no bundles
+    NoBundles,
+    UnknownBundle(String),
+    AlreadyRunning(Uuid),
+}
+
+impl fmt::Display for SeedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoBundles => write!(f, "no seed bundles configured (set SEED_BUNDLES_PATH)"),
+            Self::UnknownBundle(name) => write!(f, "no seed bundle named '{}'", name),
+            Self::AlreadyRunning(id) => write!(f, "seed run {} is still running", id),
+        }
+    }
+}
+
+impl std::error::Error for SeedError {}
+
+pub struct Seeder {
+    bundles: Vec<SeedBundle>,
+    /// Where relative feed paths are resolved from
+    base_dir: PathBuf,
+    store: Arc<CouponStore>,
+    profiles: Arc<DomainProfiles>,
+    engine: CouponEngine,
+    http: reqwest::Client,
+    runs: Mutex<HashMap<Uuid, SeedRun>>,
+    runtime: Option<Handle>,
+    licenses: Option<Arc<SourceLicenses>>,
+}
+
+impl Seeder {
+    /// Parse feeds with an offline engine using the parser at `PARSER_VERSION`
+    pub fn new(bundles: Vec<SeedBundle>, store: Arc<CouponStore>, profiles: Arc<DomainProfiles>) -> Self {
+        let version = ParserVersion::from_env("PARSER_VERSION").unwrap_or(ParserVersion::CURRENT);
+        Self {
+            bundles,
+            base_dir: PathBuf::from("."),
+            store,
+            profiles,
+            engine: CouponEngine::builder(EngineConfig::default())
+                .offline()
+                .parser(Arc::new(Parser::with_version(version)))
+                .build(),
+            http: reqwest::Client::builder().timeout(FEED_TIMEOUT).build().unwrap_or_default(),
+            runs: Mutex::new(HashMap::new()),
+            runtime: None,
+            licenses: None,
+        }
+    }
+
+    /// Bundles from `SEED_BUNDLES_PATH`, if set
+    pub fn from_env(store: Arc<CouponStore>, profiles: Arc<DomainProfiles>) -> Self {
+        let Ok(path) = std::env::var("SEED_BUNDLES_PATH") else {
+            return Self::new(Vec::new(), store, profiles);
+        };
+        let path = PathBuf::from(path);
+        let bundles = match Self::load(&path) {
+            Ok(bundles) => bundles,
+            Err(e) => {
+                tracing::warn!(path = %path.display(), error = %e, "Failed to load seed bundles");
+                Vec::new()
+            }
+        };
+        let mut seeder = Self::new(bundles, store, profiles);
+        if let Some(dir) = path.parent() {
+            seeder.base_dir = dir.to_path_buf();
+        }
+        seeder
+    }
+
+    fn load(path: &Path) -> Result<Vec<SeedBundle>, Box<dyn std::error::Error + Send + Sync>> {
+        let content = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    /// Resolve relative feed paths against `dir`
+    pub fn with_base_dir(mut self, dir: PathBuf) -> Self {
+        self.base_dir = dir;
+        self
+    }
+
+    /// Parse through `engine` instead; only [`CouponEngine::process_feed`] is used
+    pub fn with_engine(mut self, engine: CouponEngine) -> Self {
+        self.engine = engine;
+        self
+    }
+
+    /// Run seeding on `runtime` rather than the caller's
+    pub fn with_runtime(mut self, runtime: Handle) -> Self {
+        self.runtime = Some(runtime);
+        self
+    }
+
+    /// Tag seeded listings with their source's license
+    pub fn with_licenses(mut self, licenses: Arc<SourceLicenses>) -> Self {
+        self.licenses = Some(licenses);
+        self
+    }
+
+    /// Start a run in the background
+    pub async fn start(self: &Arc<Self>, request: SeedRequest) -> Result<SeedRun, SeedError> {
+        if self.bundles.is_empty() {
+            return Err(SeedError::NoBundles);
+        }
+        let bundles: Vec<SeedBundle> = match request.bundles.is_empty() {
+            true => self.bundles.clone(),
+            false => request
+                .bundles
+                .iter()
+                .map(|name| {
+                    self.bundles
+                        .iter()
+                        .find(|bundle| &bundle.name == name)
+                        .cloned()
+                        .ok_or_else(|| SeedError::UnknownBundle(name.clone()))
+                })
+                .collect::<Result<_, _>>()?,
+        };
+
+        let run = {
+            let mut runs = self.runs.lock().await;
+            if let Some(running) = runs.values().find(|run| run.status == RunStatus::Running) {
+                return Err(SeedError::AlreadyRunning(running.id));
+            }
+
+            let mut finished: Vec<(DateTime<Utc>, Uuid)> = runs.values().map(|run| (run.started_at, run.id)).collect();
+            if finished.len() >= MAX_FINISHED_RUNS {
+                finished.sort();
+                for (_, id) in &finished[..=finished.len() - MAX_FINISHED_RUNS] {
+                    runs.remove(id);
+                }
+            }
+
+            let run = SeedRun {
+                id: Uuid::new_v4(),
+                status: RunStatus::Running,
+                bundles: bundles.iter().map(|bundle| bundle.name.clone()).collect(),
+                merchants_total: bundles.iter().map(|bundle| bundle.merchants.len() as u32).sum(),
+                merchants_added: 0,
+                merchants_existing: 0,
+                feeds_total: bundles.iter().map(|bundle| bundle.feeds.len() as u32).sum(),
+                feeds_done: 0,
+                feeds_failed: 0,
+                coupons_added: 0,
+                coupons_changed: 0,
+                coupons_unchanged: 0,
+                failures: Vec::new(),
+                error: None,
+                started_at: Utc::now(),
+                finished_at: None,
+            };
+            runs.insert(run.id, run.clone());
+            run
+        };
+
+        let seeder = self.clone();
+        let id = run.id;
+        let seed = async move {
+            let result = seeder.execute(id, &bundles).await;
+            seeder
+                .update(id, |run| {
+                    run.finished_at = Some(Utc::now());
+                    match result {
+                        Ok(()) => run.status = RunStatus::Completed,
+                        Err(e) => {
+                            run.status = RunStatus::Failed;
+                            run.error = Some(e);
+                        }
+                    }
+                })
+                .await;
+        };
+        match &self.runtime {
+            Some(runtime) => runtime.spawn(seed),
+            None => tokio::spawn(seed),
+        };
+        Ok(run)
+    }
+
+    pub async fn get(&self, id: Uuid) -> Option<SeedRun> {
+        self.runs.lock().await.get(&id).cloned()
+    }
+
+    async fn update(&self, id: Uuid, change: impl FnOnce(&mut SeedRun)) {
+        if let Some(run) = self.runs.lock().await.get_mut(&id) {
+            change(run);
+        }
+    }
+
+    async fn execute(&self, id: Uuid, bundles: &[SeedBundle]) -> Result<(), String> {
+        for merchant in bundles.iter().flat_map(|bundle| &bundle.merchants) {
+            let exists = self.profiles.get(&merchant.domain).is_some();
+            if !exists {
+                self.profiles
+                    .put(merchant.domain.clone(), merchant.settings.clone())
+                    .await
+                    .map_err(|e| format!("{}: {}", merchant.domain, e))?;
+            }
+            self.update(id, |run| match exists {
+                true => run.merchants_existing += 1,
+                false => run.merchants_added += 1,
+            })
+            .await;
+        }
+
+        for feed in bundles.iter().flat_map(|bundle| &bundle.feeds) {
+            match self.seed_feed(feed).await {
+                Ok((added, changed, unchanged)) => {
+                    self.update(id, |run| {
+                        run.feeds_done += 1;
+                        run.coupons_added += added;
+                        run.coupons_changed += changed;
+                        run.coupons_unchanged += unchanged;
+                    })
+                    .await
+                }
+                Err(error) => {
+                    tracing::warn!(merchant = %feed.merchant, feed = %feed.location(), %error, "Failed to seed a merchant");
+                    self.update(id, |run| {
+                        run.feeds_done += 1;
+                        run.feeds_failed += 1;
+                        if run.failures.len() < MAX_REPORTED_FAILURES {
+                            run.failures.push(FeedFailure {
+                                merchant: feed.merchant.clone(),
+                                feed: feed.location(),
+                                error,
+                            });
+                        }
+                    })
+                    .await
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Load one feed's coupons into the corpus as they are read: (added, changed,
+    /// unchanged). Coupons read before a feed fails stay loaded.
+    async fn seed_feed(&self, feed: &SeedFeed) -> Result<(u32, u32, u32), String> {
+        let reader: Box<dyn Read + Send> = match (&feed.url, &feed.path) {
+            (Some(url), _) => {
+                let response = self.http.get(url).send().await.map_err(|e| e.to_string())?;
+                if !response.status().is_success() {
+                    return Err(format!("status {}", response.status()));
+                }
+                Box::new(BodyReader::new(response))
+            }
+            (None, Some(path)) => {
+                let file = tokio::fs::File::open(self.base_dir.join(path)).await.map_err(|e| e.to_string())?;
+                Box::new(file.into_std().await)
+            }
+            (None, None) => return Err("the feed has neither a url nor a path".to_string()),
+        };
+
+        let mut coupons = self.engine.process_feed(reader, &format!("https://{}/", feed.merchant));
+        let fetched_at = Utc::now();
+        let (mut added, mut changed, mut unchanged) = (0, 0, 0);
+        while let Some(coupon) = coupons.next().await {
+            let mut listing = listing(&coupon, fetched_at);
+            if let Some(licenses) = &self.licenses {
+                licenses.tag(&mut listing).await;
+            }
+            match self.store.find(&listing.merchant_domain, &listing.code).await {
+                Some(existing) if same_offer(&existing, &listing) => {
+                    unchanged += 1;
+                    continue;
+                }
+                Some(_) => changed += 1,
+                None => added += 1,
+            }
+            self.store.upsert(listing).await;
+        }
+        coupons.finish().await.map_err(|e| e.to_string())?;
+        Ok((added, changed, unchanged))
+    }
+}
+
+/// Blocking reader of a response body for [`CouponEngine::process_feed`], fed a
+/// chunk at a time by a task on the runtime
+struct BodyReader {
+    chunks: mpsc::Receiver<Result<Vec<u8>, String>>,
+    chunk: Vec<u8>,
+    read: usize,
+}
+
+impl BodyReader {
+    fn new(mut response: reqwest::Response) -> Self {
+        let (send, chunks) = mpsc::channel(FEED_CHUNKS);
+        tokio::spawn(async move {
+            loop {
+                let chunk = match response.chunk().await {
+                    Ok(Some(chunk)) => Ok(Vec::from(chunk)),
+                    Ok(None) => break,
+                    Err(e) => Err(e.to_string()),
+                };
+                let failed = chunk.is_err();
+                if send.send(chunk).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+        Self {
+            chunks,
+            chunk: Vec::new(),
+            read: 0,
+        }
+    }
+}
+
+impl Read for BodyReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.read == self.chunk.len() {
+            match self.chunks.blocking_recv() {
+                Some(Ok(chunk)) => (self.chunk, self.read) = (chunk, 0),
+                Some(Err(e)) => return Err(std::io::Error::other(e)),
+                None => return Ok(0),
+            }
+        }
+        let len = buf.len().min(self.chunk.len() - self.read);
+        buf[..len].copy_from_slice(&self.chunk[self.read..self.read + len]);
+        self.read += len;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn wait(seeder: &Seeder, id: Uuid) -> SeedRun {
+        tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                let run = seeder.get(id).await.unwrap();
+                if run.status != RunStatus::Running {
+                    return run;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("run finished")
+    }
+
+    #[tokio::test]
+    async fn test_seeds_merchants_and_coupons_and_reruns_change_nothing() {
+        let dir = std::env::temp_dir().join(format!("seeding_{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("shop.json"),
+            r#"{"coupons": [{"code": "WELCOME10", "title": "10% off your first order"}, {"code": "SHIP5", "title": "$5 off your order"}]}"#,
+        )
+        .unwrap();
+        let shop = MerchantDomain::parse("shop.example.de").unwrap();
+        let bundles: Vec<SeedBundle> = serde_json::from_value(serde_json::json!([{
+            "name": "de",
+            "merchants": [{"domain": "shop.example.de", "settings": {"size": "large"}}, {"domain": "other.example.de"}],
+            "feeds": [
+                {"merchant": "shop.example.de", "path": "shop.json"},
+                {"merchant": "other.example.de", "path": "missing.json"}
+            ]
+        }]))
+        .unwrap();
+        let store = Arc::new(CouponStore::new());
+        let profiles = Arc::new(DomainProfiles::new(None));
+        let engine = CouponEngine::builder(EngineConfig::default())
+            .offline()
+            .parser(Arc::new(Parser::with_version(ParserVersion::V2)))
+            .build();
+        let seeder = Arc::new(
+            Seeder::new(bundles, store.clone(), profiles.clone())
+                .with_engine(engine)
+                .with_base_dir(dir.clone()),
+        );
+
+        assert_eq!(
+            seeder.start(SeedRequest { bundles: vec!["uk".to_string()] }).await.unwrap_err(),
+            SeedError::UnknownBundle("uk".to_string())
+        );
+        let started = seeder.start(SeedRequest::default()).await.unwrap();
+        let run = wait(&seeder, started.id).await;
+        assert_eq!(run.status, RunStatus::Completed);
+        assert_eq!((run.merchants_added, run.merchants_existing), (2, 0));
+        assert_eq!((run.feeds_total, run.feeds_done, run.feeds_failed), (2, 2, 1));
+        assert_eq!(run.failures[0].feed, "missing.json");
+        assert_eq!((run.coupons_added, run.coupons_unchanged), (2, 0));
+        assert_eq!(store.for_merchant(&shop).await.len(), 2);
+        assert_eq!(profiles.get(&shop).unwrap().settings.size, crate::coupon_engine::budget::MerchantSize::Large);
+
+        let again = seeder.start(SeedRequest::default()).await.unwrap();
+        let run = wait(&seeder, again.id).await;
+        assert_eq!((run.merchants_added, run.merchants_existing), (0, 2));
+        assert_eq!((run.coupons_added, run.coupons_changed, run.coupons_unchanged), (0, 0, 2));
+        assert_eq!(store.list().await.len(), 2);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn test_requires_bundles() {
+        let seeder = Arc::new(Seeder::new(Vec::new(), Arc::new(CouponStore::new()), Arc::new(DomainProfiles::new(None))));
+        assert_eq!(seeder.start(SeedRequest::default()).await.unwrap_err(), SeedError::NoBundles);
+    }
+}
@@ -0,0 +1,227 @@
+//! Where and how the binary serves the API
+//!
+//! By default the API listens on `0.0.0.0:8001` over plain HTTP, speaking HTTP/1.1
+//! and, to clients that know to ask for it, HTTP/2. A JSON file at
+//! `SERVER_CONFIG_PATH` can set any of the [`ServerConfig`] fields, and the
+//! `SERVER_BIND_ADDRESS`, `SERVER_PORT`, `SERVER_TLS_CERT_PATH`,
+//! `SERVER_TLS_KEY_PATH` and `SERVER_HTTP2` variables override it.
+//!
+//! With a certificate chain and private key (PEM) the listener terminates TLS
+//! itself, offering HTTP/2 through ALPN, so the service can be deployed without a
+//! proxy in front of it.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use axum::Router;
+use axum_server::tls_rustls::RustlsConfig;
+use hyper_util::rt::TokioExecutor;
+use hyper_util::server::conn::auto::Builder;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use serde::Deserialize;
+
+use crate::config::parse_bool;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct TlsConfig {
+    /// PEM certificate chain, the server's certificate first
+    pub cert_path: PathBuf,
+    /// PEM private key (PKCS#8, PKCS#1 or SEC1)
+    pub key_path: PathBuf,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub bind_address: IpAddr,
+    pub port: u16,
+    /// Terminate TLS on the listener; plain HTTP without
+    pub tls: Option<TlsConfig>,
+    /// Accept HTTP/2 besides HTTP/1.1
+    pub http2: bool,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind_address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port: 8001,
+            tls: None,
+            http2: true,
+        }
+    }
+}
+
+/// A server setting that does not parse, named so startup can point at it
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerConfigError {
+    pub setting: &'static str,
+    pub message: String,
+}
+
+impl fmt::Display for ServerConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.setting, self.message)
+    }
+}
+
+impl std::error::Error for ServerConfigError {}
+
+impl ServerConfig {
+    /// The file at `SERVER_CONFIG_PATH`, if set, overridden by the `SERVER_*` variables
+    pub fn from_env() -> Result<Self, ServerConfigError> {
+        Self::load(&std::env::vars().collect())
+    }
+
+    pub fn load(env: &HashMap<String, String>) -> Result<Self, ServerConfigError> {
+        let get = |name: &str| env.get(name).map(|value| value.trim()).filter(|value| !value.is_empty());
+        let error = |setting: &'static str, message: String| ServerConfigError { setting, message };
+
+        let mut config = match get("SERVER_CONFIG_PATH") {
+            Some(path) => {
+                let contents = std::fs::read_to_string(path).map_err(|e| error("SERVER_CONFIG_PATH", format!("{}: {}", path, e)))?;
+                serde_json::from_str(&contents).map_err(|e| error("SERVER_CONFIG_PATH", format!("{} is not a server config: {}", path, e)))?
+            }
+            None => Self::default(),
+        };
+
+        if let Some(value) = get("SERVER_BIND_ADDRESS") {
+            config.bind_address = value.parse().map_err(|_| error("SERVER_BIND_ADDRESS", format!("'{}' is not an IP address", value)))?;
+        }
+        if let Some(value) = get("SERVER_PORT") {
+            config.port = value.parse().map_err(|_| error("SERVER_PORT", format!("'{}' is not a port", value)))?;
+        }
+        if let Some(value) = get("SERVER_HTTP2") {
+            config.http2 = parse_bool(value).map_err(|e| error("SERVER_HTTP2", e))?;
+        }
+        match (get("SERVER_TLS_CERT_PATH"), get("SERVER_TLS_KEY_PATH")) {
+            (Some(cert), Some(key)) => {
+                config.tls = Some(TlsConfig {
+                    cert_path: cert.into(),
+                    key_path: key.into(),
+                })
+            }
+            (Some(_), None) => return Err(error("SERVER_TLS_KEY_PATH", "not set, but SERVER_TLS_CERT_PATH is".to_string())),
+            (None, Some(_)) => return Err(error("SERVER_TLS_CERT_PATH", "not set, but SERVER_TLS_KEY_PATH is".to_string())),
+            (None, None) => {}
+        }
+        Ok(config)
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        SocketAddr::new(self.bind_address, self.port)
+    }
+
+    /// `http` or `https`, for logs
+    pub fn scheme(&self) -> &'static str {
+        match self.tls {
+            Some(_) => "https",
+            None => "http",
+        }
+    }
+
+    /// Serve `app` until the listener fails
+    pub async fn serve(&self, app: Router) -> std::io::Result<()> {
+        let service = app.into_make_service();
+        match &self.tls {
+            Some(tls) => {
+                let mut server = axum_server::bind_rustls(self.addr(), tls.rustls_config(self.http2)?);
+                *server.http_builder() = self.http_builder();
+                server.serve(service).await
+            }
+            None => {
+                let mut server = axum_server::bind(self.addr());
+                *server.http_builder() = self.http_builder();
+                server.serve(service).await
+            }
+        }
+    }
+
+    fn http_builder(&self) -> Builder<TokioExecutor> {
+        let builder = Builder::new(TokioExecutor::new());
+        match self.http2 {
+            true => builder,
+            false => builder.http1_only(),
+        }
+    }
+}
+
+impl TlsConfig {
+    /// Read the certificate chain and key, offering HTTP/2 through ALPN when `http2`
+    fn rustls_config(&self, http2: bool) -> std::io::Result<RustlsConfig> {
+        let invalid = |path: &Path, e: &dyn fmt::Display| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e));
+
+        let certs = CertificateDer::pem_file_iter(&self.cert_path)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .map_err(|e| invalid(&self.cert_path, &e))?;
+        if certs.is_empty() {
+            return Err(invalid(&self.cert_path, &"no certificates"));
+        }
+        let key = PrivateKeyDer::from_pem_file(&self.key_path).map_err(|e| invalid(&self.key_path, &e))?;
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut config = rustls::ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .and_then(|builder| builder.with_no_client_auth().with_single_cert(certs, key))
+            .map_err(|e| invalid(&self.cert_path, &e))?;
+        config.alpn_protocols = match http2 {
+            true => vec![b"h2".to_vec(), b"http/1.1".to_vec()],
+            false => vec![b"http/1.1".to_vec()],
+        };
+        Ok(RustlsConfig::from_config(Arc::new(config)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn test_variables_override_the_config_file() {
+        assert_eq!(ServerConfig::load(&env(&[])).unwrap().addr().to_string(), "0.0.0.0:8001");
+
+        let path = std::env::temp_dir().join(format!("server_{}.json", uuid::Uuid::new_v4()));
+        std::fs::write(
+            &path,
+            r#"{"bind_address": "::", "port": 8443, "tls": {"cert_path": "/etc/tls/cert.pem", "key_path": "/etc/tls/key.pem"}}"#,
+        )
+        .unwrap();
+        let file = path.to_str().unwrap();
+
+        let config = ServerConfig::load(&env(&[("SERVER_CONFIG_PATH", file)])).unwrap();
+        assert_eq!((config.addr().to_string().as_str(), config.scheme(), config.http2), ("[::]:8443", "https", true));
+
+        let config = ServerConfig::load(&env(&[
+            ("SERVER_CONFIG_PATH", file),
+            ("SERVER_BIND_ADDRESS", "127.0.0.1"),
+            ("SERVER_HTTP2", "false"),
+            ("SERVER_TLS_CERT_PATH", "/run/cert.pem"),
+            ("SERVER_TLS_KEY_PATH", "/run/key.pem"),
+        ]))
+        .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(config.addr().to_string(), "127.0.0.1:8443");
+        assert!(!config.http2);
+        assert_eq!(config.tls.unwrap().key_path, PathBuf::from("/run/key.pem"));
+
+        let setting = |pairs: &[(&str, &str)]| ServerConfig::load(&env(pairs)).unwrap_err().setting;
+        assert_eq!(setting(&[("SERVER_PORT", "80801")]), "SERVER_PORT");
+        assert_eq!(setting(&[("SERVER_TLS_CERT_PATH", "/run/cert.pem")]), "SERVER_TLS_KEY_PATH");
+        assert_eq!(setting(&[("SERVER_CONFIG_PATH", "/nonexistent/server.json")]), "SERVER_CONFIG_PATH");
+
+        // Unreadable key material fails before anything binds
+        let tls = TlsConfig {
+            cert_path: "/nonexistent/cert.pem".into(),
+            key_path: "/nonexistent/key.pem".into(),
+        };
+        assert!(tls.rustls_config(true).is_err());
+    }
+}
@@ -0,0 +1,133 @@
+//! Duplicate deal detection
+//!
+//! Two deals are treated as the same listing when their titles are near-identical,
+//! or when their product images are perceptually close and the prices agree. The
+//! image signal catches re-listings whose titles were reworded.
+
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::images::phash::{from_hex, hamming_distance};
+use crate::models::deal::Deal;
+use crate::models::domain::Money;
+
+/// Token overlap above which titles alone mark a duplicate
+const TITLE_MATCH: f64 = 0.8;
+/// Maximum pHash distance (of 64 bits) for images to count as the same picture
+const IMAGE_MATCH_DISTANCE: u32 = 10;
+/// Relative price difference allowed for an image-only match
+const PRICE_TOLERANCE: Decimal = dec!(0.1);
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchReason {
+    Title,
+    Image,
+    TitleAndImage,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DuplicatePair {
+    pub deal_id: String,
+    pub duplicate_of: String,
+    pub title_similarity: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image_distance: Option<u32>,
+    pub reason: MatchReason,
+}
+
+/// Pairs of duplicate deals; `duplicate_of` is always the earlier-posted deal
+pub fn find_duplicates(deals: &[Deal]) -> Vec<DuplicatePair> {
+    let mut pairs = Vec::new();
+
+    for (i, a) in deals.iter().enumerate() {
+        for b in &deals[i + 1..] {
+            let title_similarity = title_similarity(&a.title, &b.title);
+            let image_distance = match (&a.image_hash, &b.image_hash) {
+                (Some(x), Some(y)) => from_hex(x).zip(from_hex(y)).map(|(x, y)| hamming_distance(x, y)),
+                _ => None,
+            };
+
+            let title_match = title_similarity >= TITLE_MATCH;
+            let image_match =
+                image_distance.is_some_and(|d| d <= IMAGE_MATCH_DISTANCE) && prices_agree(a.price, b.price);
+            let reason = match (title_match, image_match) {
+                (true, true) => MatchReason::TitleAndImage,
+                (true, false) => MatchReason::Title,
+                (false, true) => MatchReason::Image,
+                (false, false) => continue,
+            };
+
+            let (original, duplicate) = if a.posted_at <= b.posted_at { (a, b) } else { (b, a) };
+            pairs.push(DuplicatePair {
+                deal_id: duplicate.id.clone(),
+                duplicate_of: original.id.clone(),
+                title_similarity: (title_similarity * 100.0).round() / 100.0,
+                image_distance,
+                reason,
+            });
+        }
+    }
+
+    pairs
+}
+
+fn prices_agree(a: Money, b: Money) -> bool {
+    a.currency == b.currency && (a.amount - b.amount).abs() <= a.amount.max(b.amount) * PRICE_TOLERANCE
+}
+
+/// Jaccard overlap of lowercase title tokens
+fn title_similarity(a: &str, b: &str) -> f64 {
+    let tokens = |s: &str| -> Vec<String> {
+        let mut tokens: Vec<String> = s
+            .to_lowercase()
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|t| !t.is_empty())
+            .map(String::from)
+            .collect();
+        tokens.sort();
+        tokens.dedup();
+        tokens
+    };
+
+    let (a, b) = (tokens(a), tokens(b));
+    let shared = a.iter().filter(|t| b.contains(t)).count();
+    let union = a.len() + b.len() - shared;
+    if union == 0 {
+        0.0
+    } else {
+        shared as f64 / union as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::deal_store::DealStore;
+
+    #[tokio::test]
+    async fn test_image_match_catches_reworded_relisting() {
+        let deals = DealStore::with_sample_data().list().await;
+        let mut original = deals[2].clone();
+        original.image_hash = Some("f0f0f0f0aaaa5555".to_string());
+
+        let mut relisted = original.clone();
+        relisted.id = "deal_relisted".to_string();
+        relisted.title = "LG 65\" 4K Smart TV - Black Friday Doorbuster".to_string();
+        relisted.price.amount = dec!(1279.99);
+        relisted.posted_at = original.posted_at + chrono::Duration::hours(2);
+        relisted.image_hash = Some("f0f0f0f0aaaa5557".to_string());
+
+        let pairs = find_duplicates(&[original.clone(), relisted.clone(), deals[0].clone()]);
+        assert_eq!(pairs.len(), 1);
+        assert_eq!(pairs[0].reason, MatchReason::Image);
+        assert_eq!(pairs[0].duplicate_of, original.id);
+        assert_eq!(pairs[0].image_distance, Some(1));
+
+        // Same picture but a very different price is a different offer
+        relisted.price.amount = dec!(499);
+        assert!(find_duplicates(&[original, relisted]).is_empty());
+    }
+}
@@ -0,0 +1,2 @@
+pub mod dedup;
+pub mod ranking;
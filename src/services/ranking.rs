@@ -0,0 +1,70 @@
+//! Deal ranking pipeline shared by the listing, trending, search and event endpoints
+
+use std::sync::Arc;
+
+use chrono::Utc;
+
+use crate::events::EventCalendar;
+use crate::experiments::RankingStrategy;
+use crate::models::deal::Deal;
+use crate::pricing::discount_audit::DiscountAuditor;
+use crate::scoring::DealScorer;
+use crate::storage::deal_store::DealStore;
+use crate::tagging::TagRegistry;
+
+pub struct RankingPipeline {
+    store: Arc<DealStore>,
+    scorer: Arc<DealScorer>,
+    auditor: Arc<DiscountAuditor>,
+    events: Arc<EventCalendar>,
+    tags: Option<Arc<TagRegistry>>,
+}
+
+impl RankingPipeline {
+    pub fn new(
+        store: Arc<DealStore>,
+        scorer: Arc<DealScorer>,
+        auditor: Arc<DiscountAuditor>,
+        events: Arc<EventCalendar>,
+    ) -> Self {
+        Self {
+            store,
+            scorer,
+            auditor,
+            events,
+            tags: None,
+        }
+    }
+
+    /// Attach curation tags to the ranked deals
+    pub fn with_tags(mut self, tags: Arc<TagRegistry>) -> Self {
+        self.tags = Some(tags);
+        self
+    }
+
+    pub fn model_version(&self) -> &str {
+        self.scorer.model_version()
+    }
+
+    /// All listed deals with honest discounts, tags and scores attached, ordered by `strategy`
+    pub async fn ranked(&self, tenant: &str, strategy: RankingStrategy) -> Vec<Deal> {
+        let mut deals = self.store.list().await;
+        self.auditor.annotate(&self.store, &mut deals).await;
+        if let Some(tags) = &self.tags {
+            tags.annotate_deals(&mut deals);
+        }
+        let mut deals = self.scorer.score_and_rank(&self.store, deals).await;
+
+        match strategy {
+            RankingStrategy::Scored => self.events.apply(tenant, Utc::now().date_naive(), &mut deals),
+            RankingStrategy::ScoredWithoutEvents => {}
+            RankingStrategy::HonestDiscount => deals.sort_by(|a, b| {
+                b.honest_discount
+                    .unwrap_or(b.discount)
+                    .total_cmp(&a.honest_discount.unwrap_or(a.discount))
+            }),
+            RankingStrategy::Newest => deals.sort_by_key(|deal| std::cmp::Reverse(deal.posted_at)),
+        }
+        deals
+    }
+}
@@ -0,0 +1,379 @@
+//! Coupon share links
+//!
+//! Sharing a coupon creates a [`Share`] and hands back a token for
+//! `/share/{token}`. The token carries the share's id and expiry, signed with
+//! HMAC-SHA256 under `SHARE_SECRET`, so a forged or altered token is turned away
+//! before any lookup. Opening a link counts towards the share's attribution: how
+//! often it was opened, by how many distinct visitors, and when. Links expire after
+//! `SHARE_TTL_HOURS` (default [`DEFAULT_TTL_HOURS`]).
+//!
+//! With `SHARE_BASE_URL` set, shares also get an absolute `url`, which [`qr`]
+//! renders as a QR code. With `REDIS_URL` set, shares are kept in Redis so a link
+//! opens on any instance; otherwise they persist to `SHARES_PATH` (default
+//! `data/shares.json`). Expired shares are dropped.
+
+pub mod qr;
+
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::RwLock;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::clock::{self, Clock};
+use crate::models::domain::{CouponCode, MerchantDomain};
+use crate::storage::persisted::{PersistedStore, StoreError};
+
+const REDIS_KEY: &str = "coupon_shares";
+const STORE_NAME: &str = "coupon shares";
+
+pub const DEFAULT_TTL_HOURS: i64 = 72;
+/// Hex characters of the signature kept in a token
+const SIGNATURE_LEN: usize = 32;
+
+/// A coupon's id in share URLs: `{code}@{merchant}`, e.g. `SAVE10@shop.com`
+pub fn parse_coupon_id(id: &str) -> Result<(MerchantDomain, CouponCode), String> {
+    let (code, merchant) = id
+        .rsplit_once('@')
+        .ok_or_else(|| "coupon id must be {code}@{merchant}".to_string())?;
+    Ok((MerchantDomain::parse(merchant)?, CouponCode::parse(code)?))
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct ShareRequest {
+    /// User sharing the coupon, credited when the link is opened
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shared_by: Option<String>,
+    /// Where the link is posted, e.g. `whatsapp`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Share {
+    pub id: Uuid,
+    pub merchant_domain: MerchantDomain,
+    pub code: CouponCode,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shared_by: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub opens: u64,
+    /// Distinct visitors who opened the link, by their visitor id
+    pub unique_visitors: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_opened_at: Option<DateTime<Utc>>,
+}
+
+/// A share as stored, with the hashed ids of the visitors counted in
+/// `unique_visitors`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StoredShare {
+    #[serde(flatten)]
+    share: Share,
+    #[serde(default)]
+    visitors: HashSet<String>,
+}
+
+/// A new share and how to reach it
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ShareLink {
+    pub share: Share,
+    pub token: String,
+    /// `/share/{token}`
+    pub path: String,
+    /// Absolute link, when `SHARE_BASE_URL` is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShareError {
+    /// Malformed, forged, or for a share that no longer exists
+    NotFound,
+    Expired,
+}
+
+pub struct ShareService {
+    shares: Arc<RwLock<HashMap<Uuid, StoredShare>>>,
+    secret: Vec<u8>,
+    ttl: Duration,
+    base_url: Option<String>,
+    /// Every share in the file, one field of the Redis hash each
+    store: PersistedStore<HashMap<Uuid, StoredShare>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl ShareService {
+    /// Shares persisted to `path`, or kept in memory only
+    pub fn new(secret: Vec<u8>, path: Option<PathBuf>) -> Self {
+        Self::with_store(secret, PersistedStore::new(STORE_NAME, REDIS_KEY, path))
+    }
+
+    /// Shares shared through Redis, so a link works on every instance
+    pub fn shared(secret: Vec<u8>, redis_url: &str) -> Result<Self, StoreError> {
+        Ok(Self::with_store(secret, PersistedStore::shared(STORE_NAME, REDIS_KEY, redis_url)?))
+    }
+
+    fn with_store(secret: Vec<u8>, store: PersistedStore<HashMap<Uuid, StoredShare>>) -> Self {
+        Self {
+            shares: Arc::new(RwLock::new(HashMap::new())),
+            secret,
+            ttl: Duration::hours(DEFAULT_TTL_HOURS),
+            base_url: None,
+            store,
+            clock: clock::system(),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Origin absolute share links are built on, e.g. `https://deals.example.com`
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = Some(base_url.trim_end_matches('/').to_string());
+        self
+    }
+
+    /// A random secret, for tests and sandboxes whose links need not outlive the process
+    pub fn ephemeral(path: Option<PathBuf>) -> Self {
+        Self::new(random_secret(), path)
+    }
+
+    /// Signing secret from `SHARE_SECRET`, settings from `SHARE_TTL_HOURS` and
+    /// `SHARE_BASE_URL`, and shares shared through `REDIS_URL` when set, otherwise
+    /// loaded from `SHARES_PATH` (default `data/shares.json`)
+    pub async fn from_env() -> Self {
+        let store = PersistedStore::from_env(STORE_NAME, REDIS_KEY, "SHARES_PATH", "data/shares.json");
+        let secret = match std::env::var("SHARE_SECRET") {
+            Ok(secret) if !secret.is_empty() => secret.into_bytes(),
+            _ => {
+                tracing::warn!("SHARE_SECRET is not set; share links will stop working on restart");
+                random_secret()
+            }
+        };
+        let mut service = Self::with_store(secret, store);
+        if let Some(hours) = std::env::var("SHARE_TTL_HOURS").ok().and_then(|v| v.parse().ok()) {
+            service = service.with_ttl(Duration::hours(hours));
+        }
+        if let Ok(base_url) = std::env::var("SHARE_BASE_URL") {
+            service = service.with_base_url(&base_url);
+        }
+
+        if let Err(e) = service.load().await {
+            tracing::warn!(error = %e, "Starting without coupon shares");
+        }
+        service
+    }
+
+    /// Shares a shared store keeps in Redis are read as they are needed instead
+    async fn load(&self) -> Result<(), StoreError> {
+        if self.store.is_shared() {
+            return Ok(());
+        }
+        if let Some(stored) = self.store.load().await? {
+            *self.shares.write().await = stored;
+        }
+        Ok(())
+    }
+
+    /// Share `id` as stored; from Redis when shared, since any instance may have
+    /// created or opened it, otherwise from `local`
+    fn stored(&self, local: &HashMap<Uuid, StoredShare>, id: &Uuid) -> Option<StoredShare> {
+        if self.store.is_shared() {
+            match self.store.hash_get(REDIS_KEY, &id.to_string()) {
+                Ok(stored) => return stored,
+                Err(e) => tracing::warn!(share = %id, error = %e, "Failed to read a shared coupon share"),
+            }
+        }
+        local.get(id).cloned()
+    }
+
+    fn sign(&self, payload: &str) -> String {
+        let mac = hmac_sha256(&self.secret, payload.as_bytes());
+        hex(&mac)[..SIGNATURE_LEN].to_string()
+    }
+
+    fn token(&self, share: &Share) -> String {
+        let payload = format!("{}.{}", share.id.simple(), share.expires_at.timestamp());
+        let signature = self.sign(&payload);
+        format!("{}.{}", payload, signature)
+    }
+
+    /// The share id and expiry in `token`, if its signature holds
+    fn verify(&self, token: &str) -> Option<(Uuid, DateTime<Utc>)> {
+        let (payload, signature) = token.rsplit_once('.')?;
+        if !constant_time_eq(self.sign(payload).as_bytes(), signature.as_bytes()) {
+            return None;
+        }
+        let (id, expires) = payload.split_once('.')?;
+        let id = Uuid::parse_str(id).ok()?;
+        let expires = Utc.timestamp_opt(expires.parse().ok()?, 0).single()?;
+        Some((id, expires))
+    }
+
+    fn link(&self, share: Share) -> ShareLink {
+        let token = self.token(&share);
+        let path = format!("/share/{}", token);
+        ShareLink {
+            url: self.base_url.as_ref().map(|base| format!("{}{}", base, path)),
+            share,
+            token,
+            path,
+        }
+    }
+
+    /// Share `merchant_domain`'s `code`, which the caller has checked exists
+    pub async fn create(&self, merchant_domain: MerchantDomain, code: CouponCode, request: ShareRequest) -> ShareLink {
+        let now = self.clock.now();
+        let clean = |value: Option<String>| value.map(|v| v.trim().to_string()).filter(|v| !v.is_empty());
+        let share = Share {
+            id: Uuid::new_v4(),
+            merchant_domain,
+            code,
+            shared_by: clean(request.shared_by),
+            channel: clean(request.channel).map(|channel| channel.to_lowercase()),
+            created_at: now,
+            expires_at: now + self.ttl,
+            opens: 0,
+            unique_visitors: 0,
+            last_opened_at: None,
+        };
+
+        let stored = StoredShare {
+            share: share.clone(),
+            visitors: HashSet::new(),
+        };
+        let mut shares = self.shares.write().await;
+        let expired: Vec<Uuid> = shares.iter().filter(|(_, stored)| stored.share.expires_at <= now).map(|(id, _)| *id).collect();
+        for id in expired {
+            shares.remove(&id);
+            self.store.persist_field(REDIS_KEY, &id.to_string(), None::<&StoredShare>, || shares.clone()).await;
+        }
+        shares.insert(share.id, stored.clone());
+        self.store.persist_field(REDIS_KEY, &share.id.to_string(), Some(&stored), || shares.clone()).await;
+        self.link(share)
+    }
+
+    /// The share behind `token`, without counting an open
+    pub async fn get(&self, token: &str) -> Result<ShareLink, ShareError> {
+        let (id, expires_at) = self.verify(token).ok_or(ShareError::NotFound)?;
+        if expires_at <= self.clock.now() {
+            return Err(ShareError::Expired);
+        }
+        let share = self.stored(&*self.shares.read().await, &id).map(|stored| stored.share).ok_or(ShareError::NotFound)?;
+        Ok(self.link(share))
+    }
+
+    /// The share behind `token`, counting an open by `visitor` if given
+    pub async fn open(&self, token: &str, visitor: Option<&str>) -> Result<Share, ShareError> {
+        let (id, expires_at) = self.verify(token).ok_or(ShareError::NotFound)?;
+        let now = self.clock.now();
+        if expires_at <= now {
+            return Err(ShareError::Expired);
+        }
+
+        let mut shares = self.shares.write().await;
+        let mut stored = self.stored(&shares, &id).ok_or(ShareError::NotFound)?;
+        stored.share.opens += 1;
+        stored.share.last_opened_at = Some(now);
+        let visitor = visitor.map(str::trim).filter(|v| !v.is_empty());
+        if let Some(visitor) = visitor {
+            if stored.visitors.insert(hex(&Sha256::digest(visitor.as_bytes()))) {
+                stored.share.unique_visitors += 1;
+            }
+        }
+        let share = stored.share.clone();
+        shares.insert(id, stored.clone());
+        self.store.persist_field(REDIS_KEY, &id.to_string(), Some(&stored), || shares.clone()).await;
+        Ok(share)
+    }
+}
+
+fn random_secret() -> Vec<u8> {
+    let mut secret = vec![0u8; 32];
+    rand::thread_rng().fill_bytes(&mut secret);
+    secret
+}
+
+fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    const BLOCK: usize = 64;
+    let mut block = [0u8; BLOCK];
+    if key.len() > BLOCK {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let pad = |byte: u8| block.iter().map(|b| b ^ byte).collect::<Vec<u8>>();
+    let inner = Sha256::new().chain_update(pad(0x36)).chain_update(message).finalize();
+    Sha256::new().chain_update(pad(0x5c)).chain_update(inner).finalize().into()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    #[test]
+    fn test_hmac_matches_rfc_4231() {
+        let mac = hmac_sha256(b"Jefe", b"what do ya want for nothing?");
+        assert_eq!(hex(&mac), "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
+    }
+
+    #[tokio::test]
+    async fn test_share_tokens_are_signed_expire_and_count_opens() {
+        let clock = Arc::new(MockClock::new());
+        let shares = ShareService::new(b"secret".to_vec(), None)
+            .with_clock(clock.clone())
+            .with_base_url("https://deals.example.com/");
+        let (merchant, code) = parse_coupon_id("SAVE@10@Shop.com").unwrap();
+        assert_eq!(code.as_str(), "SAVE@10");
+        assert!(parse_coupon_id("SAVE10").is_err());
+
+        let link = shares
+            .create(merchant, code, ShareRequest { shared_by: Some("ana".to_string()), channel: Some(" WhatsApp ".to_string()) })
+            .await;
+        assert_eq!(link.url, Some(format!("https://deals.example.com/share/{}", link.token)));
+        assert_eq!(link.share.channel.as_deref(), Some("whatsapp"));
+
+        shares.open(&link.token, Some("visitor-1")).await.unwrap();
+        shares.open(&link.token, Some("visitor-1")).await.unwrap();
+        let opened = shares.open(&link.token, None).await.unwrap();
+        assert_eq!((opened.opens, opened.unique_visitors), (3, 1));
+        assert_eq!(shares.get(&link.token).await.unwrap().share.opens, 3);
+
+        // Moving the expiry invalidates the signature; another service's secret does too
+        let (payload, signature) = link.token.rsplit_once('.').unwrap();
+        let (id, expires) = payload.split_once('.').unwrap();
+        let extended = format!("{}.{}.{}", id, expires.parse::<i64>().unwrap() + 3600, signature);
+        assert_eq!(shares.open(&extended, None).await, Err(ShareError::NotFound));
+        assert_eq!(ShareService::ephemeral(None).get(&link.token).await.unwrap_err(), ShareError::NotFound);
+
+        clock.advance(std::time::Duration::from_secs(DEFAULT_TTL_HOURS as u64 * 3600));
+        assert_eq!(shares.open(&link.token, None).await, Err(ShareError::Expired));
+    }
+}
@@ -0,0 +1,443 @@
+//! QR codes for share links
+//!
+//! A small encoder for what share links need: byte mode at error correction level
+//! M, versions 1 to 10 (up to 213 bytes), with the mask chosen by the standard
+//! penalty rules. [`png`] renders a code with a four-module quiet zone.
+
+use std::io::Cursor;
+
+use image::{GrayImage, ImageFormat, Luma};
+
+pub const MAX_VERSION: usize = 10;
+/// Error correction codewords per block and blocks, at level M, by version
+const ECC_PER_BLOCK: [usize; MAX_VERSION + 1] = [0, 10, 16, 26, 18, 24, 16, 18, 22, 22, 26];
+const BLOCKS: [usize; MAX_VERSION + 1] = [0, 1, 1, 1, 2, 2, 4, 4, 4, 5, 5];
+/// Format bits for level M
+const LEVEL_M: u32 = 0b00;
+const QUIET_ZONE: u32 = 4;
+
+/// Dark and light modules of a QR code, row by row
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QrCode {
+    pub version: usize,
+    pub size: usize,
+    modules: Vec<Vec<bool>>,
+}
+
+impl QrCode {
+    /// The smallest code holding `data`, or `None` if it is too long
+    pub fn encode(data: &[u8]) -> Option<Self> {
+        let version = (1..=MAX_VERSION).find(|&v| 4 + count_bits(v) + data.len() * 8 <= data_codewords(v) * 8)?;
+        let codewords = add_ecc_and_interleave(version, &data_bits(version, data));
+
+        let mut base = Builder::new(version);
+        base.draw_function_patterns();
+        base.draw_codewords(&codewords);
+        (0..8)
+            .map(|mask| {
+                let mut candidate = base.clone();
+                candidate.apply_mask(mask);
+                candidate.draw_format_bits(mask);
+                candidate
+            })
+            .min_by_key(Builder::penalty)
+            .map(|builder| Self {
+                version,
+                size: builder.size,
+                modules: builder.modules,
+            })
+    }
+
+    pub fn is_dark(&self, x: usize, y: usize) -> bool {
+        self.modules[y][x]
+    }
+}
+
+/// `code` as a PNG, `scale` pixels per module
+pub fn png(code: &QrCode, scale: u32) -> Result<Vec<u8>, image::ImageError> {
+    let side = (code.size as u32 + 2 * QUIET_ZONE) * scale;
+    let image = GrayImage::from_fn(side, side, |x, y| {
+        let (x, y) = ((x / scale).checked_sub(QUIET_ZONE), (y / scale).checked_sub(QUIET_ZONE));
+        let dark = match (x, y) {
+            (Some(x), Some(y)) if (x as usize) < code.size && (y as usize) < code.size => code.is_dark(x as usize, y as usize),
+            _ => false,
+        };
+        Luma([if dark { 0 } else { 255 }])
+    });
+
+    let mut bytes = Cursor::new(Vec::new());
+    image.write_to(&mut bytes, ImageFormat::Png)?;
+    Ok(bytes.into_inner())
+}
+
+fn count_bits(version: usize) -> usize {
+    if version < 10 {
+        8
+    } else {
+        16
+    }
+}
+
+/// Modules left for data and error correction once the function patterns are drawn
+fn raw_data_modules(version: usize) -> usize {
+    let mut modules = (16 * version + 128) * version + 64;
+    if version >= 2 {
+        let alignments = version / 7 + 2;
+        modules -= (25 * alignments - 10) * alignments - 55;
+        if version >= 7 {
+            modules -= 36;
+        }
+    }
+    modules
+}
+
+fn data_codewords(version: usize) -> usize {
+    raw_data_modules(version) / 8 - ECC_PER_BLOCK[version] * BLOCKS[version]
+}
+
+/// Mode, length, data, terminator and padding, as codewords
+fn data_bits(version: usize, data: &[u8]) -> Vec<u8> {
+    let mut bits: Vec<bool> = Vec::new();
+    let mut push = |value: usize, len: usize| bits.extend((0..len).rev().map(|i| (value >> i) & 1 == 1));
+    push(0b0100, 4);
+    push(data.len(), count_bits(version));
+    for byte in data {
+        push(usize::from(*byte), 8);
+    }
+
+    let capacity = data_codewords(version) * 8;
+    let terminator = (capacity - bits.len()).min(4);
+    bits.resize(bits.len() + terminator, false);
+    bits.resize(bits.len().div_ceil(8) * 8, false);
+
+    let mut codewords: Vec<u8> = bits.chunks(8).map(|byte| byte.iter().fold(0, |acc, bit| (acc << 1) | u8::from(*bit))).collect();
+    for pad in [0xEC, 0x11].into_iter().cycle() {
+        if codewords.len() >= capacity / 8 {
+            break;
+        }
+        codewords.push(pad);
+    }
+    codewords
+}
+
+fn add_ecc_and_interleave(version: usize, data: &[u8]) -> Vec<u8> {
+    let (blocks, ecc_len) = (BLOCKS[version], ECC_PER_BLOCK[version]);
+    let raw_codewords = raw_data_modules(version) / 8;
+    let short_blocks = blocks - raw_codewords % blocks;
+    let short_len = raw_codewords / blocks;
+
+    let divisor = reed_solomon_divisor(ecc_len);
+    let mut rest = data;
+    let blocks: Vec<Vec<u8>> = (0..blocks)
+        .map(|i| {
+            let len = short_len - ecc_len + usize::from(i >= short_blocks);
+            let (block, tail) = rest.split_at(len);
+            rest = tail;
+            let mut block = block.to_vec();
+            let ecc = reed_solomon_remainder(&block, &divisor);
+            // Placeholder to line the short blocks' error correction up with the long ones
+            if i < short_blocks {
+                block.push(0);
+            }
+            block.extend(ecc);
+            block
+        })
+        .collect();
+
+    let mut interleaved = Vec::with_capacity(raw_codewords);
+    for i in 0..blocks[0].len() {
+        for (j, block) in blocks.iter().enumerate() {
+            if i != short_len - ecc_len || j >= short_blocks {
+                interleaved.push(block[i]);
+            }
+        }
+    }
+    interleaved
+}
+
+fn gf_multiply(x: u8, y: u8) -> u8 {
+    let mut z: u32 = 0;
+    for i in (0..8).rev() {
+        z = (z << 1) ^ ((z >> 7) * 0x11D);
+        z ^= u32::from((y >> i) & 1) * u32::from(x);
+    }
+    z as u8
+}
+
+fn reed_solomon_divisor(degree: usize) -> Vec<u8> {
+    let mut divisor = vec![0u8; degree];
+    divisor[degree - 1] = 1;
+    let mut root = 1u8;
+    for _ in 0..degree {
+        for j in 0..degree {
+            divisor[j] = gf_multiply(divisor[j], root);
+            if j + 1 < degree {
+                divisor[j] ^= divisor[j + 1];
+            }
+        }
+        root = gf_multiply(root, 0x02);
+    }
+    divisor
+}
+
+fn reed_solomon_remainder(data: &[u8], divisor: &[u8]) -> Vec<u8> {
+    let mut remainder = vec![0u8; divisor.len()];
+    for byte in data {
+        let factor = byte ^ remainder.remove(0);
+        remainder.push(0);
+        for (r, d) in remainder.iter_mut().zip(divisor) {
+            *r ^= gf_multiply(*d, factor);
+        }
+    }
+    remainder
+}
+
+/// Format information: the level and mask with their BCH error correction
+fn format_bits(mask: u32) -> u32 {
+    let data = (LEVEL_M << 3) | mask;
+    let mut remainder = data;
+    for _ in 0..10 {
+        remainder = (remainder << 1) ^ ((remainder >> 9) * 0x537);
+    }
+    ((data << 10) | remainder) ^ 0x5412
+}
+
+fn bit(value: u32, i: usize) -> bool {
+    (value >> i) & 1 == 1
+}
+
+#[derive(Clone)]
+struct Builder {
+    version: usize,
+    size: usize,
+    modules: Vec<Vec<bool>>,
+    is_function: Vec<Vec<bool>>,
+}
+
+impl Builder {
+    fn new(version: usize) -> Self {
+        let size = version * 4 + 17;
+        Self {
+            version,
+            size,
+            modules: vec![vec![false; size]; size],
+            is_function: vec![vec![false; size]; size],
+        }
+    }
+
+    fn set_function(&mut self, x: usize, y: usize, dark: bool) {
+        self.modules[y][x] = dark;
+        self.is_function[y][x] = true;
+    }
+
+    fn draw_function_patterns(&mut self) {
+        let size = self.size;
+        for i in 0..size {
+            self.set_function(6, i, i % 2 == 0);
+            self.set_function(i, 6, i % 2 == 0);
+        }
+        for (x, y) in [(3, 3), (size - 4, 3), (3, size - 4)] {
+            self.draw_finder(x, y);
+        }
+
+        let positions = self.alignment_positions();
+        let last = positions.len().saturating_sub(1);
+        for (i, &x) in positions.iter().enumerate() {
+            for (j, &y) in positions.iter().enumerate() {
+                let on_finder = (i == 0 && (j == 0 || j == last)) || (i == last && j == 0);
+                if !on_finder {
+                    self.draw_alignment(x, y);
+                }
+            }
+        }
+
+        // Reserve the format areas; the real bits are drawn once the mask is known
+        self.draw_format_bits(0);
+        self.draw_version();
+    }
+
+    fn draw_finder(&mut self, x: usize, y: usize) {
+        for dy in -4i32..=4 {
+            for dx in -4i32..=4 {
+                let (xx, yy) = (x as i32 + dx, y as i32 + dy);
+                if (0..self.size as i32).contains(&xx) && (0..self.size as i32).contains(&yy) {
+                    let distance = dx.abs().max(dy.abs());
+                    self.set_function(xx as usize, yy as usize, distance != 2 && distance != 4);
+                }
+            }
+        }
+    }
+
+    fn draw_alignment(&mut self, x: usize, y: usize) {
+        for dy in -2i32..=2 {
+            for dx in -2i32..=2 {
+                let dark = dx.abs().max(dy.abs()) != 1;
+                self.set_function((x as i32 + dx) as usize, (y as i32 + dy) as usize, dark);
+            }
+        }
+    }
+
+    fn alignment_positions(&self) -> Vec<usize> {
+        if self.version == 1 {
+            return Vec::new();
+        }
+        let count = self.version / 7 + 2;
+        let step = (self.version * 8 + count * 3 + 5) / (count * 4 - 4) * 2;
+        let mut positions: Vec<usize> = (0..count - 1).map(|i| self.size - 7 - i * step).collect();
+        positions.push(6);
+        positions.reverse();
+        positions
+    }
+
+    fn draw_format_bits(&mut self, mask: u32) {
+        let bits = format_bits(mask);
+        let size = self.size;
+        for i in 0..=5 {
+            self.set_function(8, i, bit(bits, i));
+        }
+        self.set_function(8, 7, bit(bits, 6));
+        self.set_function(8, 8, bit(bits, 7));
+        self.set_function(7, 8, bit(bits, 8));
+        for i in 9..15 {
+            self.set_function(14 - i, 8, bit(bits, i));
+        }
+
+        for i in 0..8 {
+            self.set_function(size - 1 - i, 8, bit(bits, i));
+        }
+        for i in 8..15 {
+            self.set_function(8, size - 15 + i, bit(bits, i));
+        }
+        self.set_function(8, size - 8, true);
+    }
+
+    fn draw_version(&mut self) {
+        if self.version < 7 {
+            return;
+        }
+        let version = self.version as u32;
+        let mut remainder = version;
+        for _ in 0..12 {
+            remainder = (remainder << 1) ^ ((remainder >> 11) * 0x1F25);
+        }
+        let bits = (version << 12) | remainder;
+        for i in 0..18 {
+            let (a, b) = (self.size - 11 + i % 3, i / 3);
+            self.set_function(a, b, bit(bits, i));
+            self.set_function(b, a, bit(bits, i));
+        }
+    }
+
+    /// Data modules in the zigzag order, two columns at a time from the bottom right
+    fn draw_codewords(&mut self, codewords: &[u8]) {
+        let mut i = 0;
+        let mut right = self.size as i32 - 1;
+        while right >= 1 {
+            if right == 6 {
+                right = 5;
+            }
+            for vertical in 0..self.size {
+                for j in 0..2 {
+                    let x = (right - j) as usize;
+                    let upward = (right + 1) & 2 == 0;
+                    let y = if upward { self.size - 1 - vertical } else { vertical };
+                    if !self.is_function[y][x] && i < codewords.len() * 8 {
+                        self.modules[y][x] = (codewords[i >> 3] >> (7 - (i & 7))) & 1 == 1;
+                        i += 1;
+                    }
+                }
+            }
+            right -= 2;
+        }
+    }
+
+    fn apply_mask(&mut self, mask: u32) {
+        for y in 0..self.size {
+            for x in 0..self.size {
+                let invert = match mask {
+                    0 => (x + y) % 2 == 0,
+                    1 => y % 2 == 0,
+                    2 => x % 3 == 0,
+                    3 => (x + y) % 3 == 0,
+                    4 => (x / 3 + y / 2) % 2 == 0,
+                    5 => x * y % 2 + x * y % 3 == 0,
+                    6 => (x * y % 2 + x * y % 3) % 2 == 0,
+                    _ => ((x + y) % 2 + x * y % 3) % 2 == 0,
+                };
+                if invert && !self.is_function[y][x] {
+                    self.modules[y][x] = !self.modules[y][x];
+                }
+            }
+        }
+    }
+
+    /// Runs, blocks, finder-like patterns and imbalance, scored as the standard does
+    fn penalty(&self) -> usize {
+        const FINDER_LIKE: [bool; 11] = [true, false, true, true, true, false, true, false, false, false, false];
+        let size = self.size;
+        let rows = (0..size).map(|y| (0..size).map(|x| self.modules[y][x]).collect::<Vec<_>>());
+        let columns = (0..size).map(|x| (0..size).map(|y| self.modules[y][x]).collect::<Vec<_>>());
+
+        let mut penalty = 0;
+        for line in rows.chain(columns) {
+            let mut run = 1;
+            for i in 1..=size {
+                if i < size && line[i] == line[i - 1] {
+                    run += 1;
+                    continue;
+                }
+                if run >= 5 {
+                    penalty += run - 2;
+                }
+                run = 1;
+            }
+            for window in line.windows(FINDER_LIKE.len()) {
+                if window.iter().eq(FINDER_LIKE.iter()) || window.iter().eq(FINDER_LIKE.iter().rev()) {
+                    penalty += 40;
+                }
+            }
+        }
+
+        for y in 0..size - 1 {
+            for x in 0..size - 1 {
+                let dark = self.modules[y][x];
+                if self.modules[y][x + 1] == dark && self.modules[y + 1][x] == dark && self.modules[y + 1][x + 1] == dark {
+                    penalty += 3;
+                }
+            }
+        }
+
+        let dark = self.modules.iter().flatten().filter(|dark| **dark).count();
+        let total = size * size;
+        let deviation = (dark * 20).abs_diff(total * 10);
+        penalty + deviation.div_ceil(total).saturating_sub(1) * 10
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encodes_with_level_m_error_correction() {
+        // "HELLO WORLD" at 1-M, from the standard's worked example
+        let data = [32, 91, 11, 120, 209, 114, 220, 77, 67, 64, 236, 17, 236, 17, 236, 17];
+        assert_eq!(
+            reed_solomon_remainder(&data, &reed_solomon_divisor(10)),
+            vec![196, 35, 39, 119, 235, 215, 231, 226, 93, 23]
+        );
+        assert_eq!(format_bits(0), 0b101010000010010);
+        assert_eq!(data_codewords(MAX_VERSION), 216);
+
+        let link = b"https://deals.example.com/share/0123456789abcdef0123456789abcdef.1790000000.0123456789abcdef0123456789abcdef";
+        let code = QrCode::encode(link).unwrap();
+        assert_eq!((code.version, code.size), (7, 45));
+        // Finder corners and the dark module
+        assert!(code.is_dark(0, 0) && code.is_dark(44, 0) && code.is_dark(0, 44) && code.is_dark(8, 37));
+        assert!(!code.is_dark(7, 7));
+        assert!(QrCode::encode(&[b'x'; 214]).is_none());
+
+        let png = png(&code, 4).unwrap();
+        assert_eq!(&png[1..4], b"PNG");
+    }
+}
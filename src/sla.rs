@@ -0,0 +1,330 @@
+//! Soft real-time SLA for the deal stream
+//!
+//! For every new deal delivered to the deal stream (see [`crate::stream`]),
+//! [`SlaMonitor`] records the latency from the platform observation (the deal's
+//! `posted_at`) to delivery, per platform (the merchant domain). Percentiles over a
+//! sliding window are served at `GET /admin/sla` and as Prometheus metrics at
+//! `GET /metrics`.
+//!
+//! When a platform's p95 exceeds the budget an alert is raised: it is logged and, when
+//! `SLA_ALERT_WEBHOOK_URL` is set, posted there. The alert is resolved the same way
+//! once the p95 is back within budget.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt::Write;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::json;
+use tokio::sync::Mutex;
+use utoipa::ToSchema;
+
+use crate::clock::{self, Clock};
+
+/// Samples a platform needs before it can breach the budget
+const MIN_SAMPLES: usize = 20;
+/// Samples kept per platform, however busy the window
+const MAX_SAMPLES: usize = 10_000;
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+pub struct SlaConfig {
+    pub p95_budget: Duration,
+    pub window: Duration,
+    pub alert_webhook: Option<String>,
+}
+
+impl SlaConfig {
+    /// Read `SLA_P95_BUDGET_SECS` (default 300), `SLA_WINDOW_SECS` (default 3600)
+    /// and `SLA_ALERT_WEBHOOK_URL`
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let secs = |name: &str| std::env::var(name).ok()?.trim().parse().ok().map(Duration::from_secs);
+        Self {
+            p95_budget: secs("SLA_P95_BUDGET_SECS").unwrap_or(defaults.p95_budget),
+            window: secs("SLA_WINDOW_SECS").unwrap_or(defaults.window),
+            alert_webhook: std::env::var("SLA_ALERT_WEBHOOK_URL").ok().filter(|url| !url.trim().is_empty()),
+        }
+    }
+}
+
+impl Default for SlaConfig {
+    fn default() -> Self {
+        Self {
+            p95_budget: Duration::from_secs(300),
+            window: Duration::from_secs(3600),
+            alert_webhook: None,
+        }
+    }
+}
+
+struct Sample {
+    delivered_at: DateTime<Utc>,
+    latency: Duration,
+}
+
+/// A platform's latency over the window
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct PlatformSla {
+    pub platform: String,
+    pub samples: usize,
+    pub p50_ms: u64,
+    pub p95_ms: u64,
+    pub p99_ms: u64,
+    pub max_ms: u64,
+    #[serde(skip)]
+    pub sum_ms: u64,
+    pub over_budget: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct SlaAlert {
+    pub platform: String,
+    pub p95_ms: u64,
+    pub budget_ms: u64,
+    pub raised_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SlaReport {
+    pub budget_ms: u64,
+    pub window_secs: u64,
+    pub platforms: Vec<PlatformSla>,
+    /// Platforms currently over budget
+    pub alerts: Vec<SlaAlert>,
+}
+
+pub struct SlaMonitor {
+    config: SlaConfig,
+    samples: Mutex<HashMap<String, VecDeque<Sample>>>,
+    alerts: Mutex<BTreeMap<String, SlaAlert>>,
+    client: reqwest::Client,
+    clock: Arc<dyn Clock>,
+}
+
+impl SlaMonitor {
+    pub fn new(config: SlaConfig) -> Self {
+        Self {
+            config,
+            samples: Mutex::new(HashMap::new()),
+            alerts: Mutex::new(BTreeMap::new()),
+            client: reqwest::Client::builder()
+                .timeout(WEBHOOK_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+            clock: clock::system(),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        Self::new(SlaConfig::from_env())
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Record that a deal the platform posted at `observed_at` was delivered now
+    pub async fn record(&self, platform: &str, observed_at: DateTime<Utc>) {
+        let now = self.clock.now();
+        // A clock ahead of ours counts as no delay rather than a negative one
+        let latency = (now - observed_at).to_std().unwrap_or(Duration::ZERO);
+
+        let stats = {
+            let mut samples = self.samples.lock().await;
+            let window = samples.entry(platform.to_string()).or_default();
+            window.push_back(Sample {
+                delivered_at: now,
+                latency,
+            });
+            self.prune(window, now);
+            self.stats(platform, window)
+        };
+        self.evaluate(stats, now).await;
+    }
+
+    pub async fn report(&self) -> SlaReport {
+        let now = self.clock.now();
+        let platforms = {
+            let mut samples = self.samples.lock().await;
+            samples.retain(|_, window| {
+                self.prune(window, now);
+                !window.is_empty()
+            });
+            let mut platforms: Vec<PlatformSla> = samples.iter().map(|(platform, window)| self.stats(platform, window)).collect();
+            platforms.sort_by(|a, b| a.platform.cmp(&b.platform));
+            platforms
+        };
+
+        SlaReport {
+            budget_ms: self.config.p95_budget.as_millis() as u64,
+            window_secs: self.config.window.as_secs(),
+            platforms,
+            alerts: self.alerts.lock().await.values().cloned().collect(),
+        }
+    }
+
+    /// The report in the Prometheus text exposition format
+    pub async fn prometheus(&self) -> String {
+        let report = self.report().await;
+        let mut out = String::new();
+
+        out.push_str("# HELP deal_stream_latency_seconds Latency from platform observation to stream delivery\n");
+        out.push_str("# TYPE deal_stream_latency_seconds summary\n");
+        for platform in &report.platforms {
+            let label = escape_label(&platform.platform);
+            for (quantile, ms) in [("0.5", platform.p50_ms), ("0.95", platform.p95_ms), ("0.99", platform.p99_ms)] {
+                let _ = writeln!(out, "deal_stream_latency_seconds{{platform=\"{}\",quantile=\"{}\"}} {}", label, quantile, seconds(ms));
+            }
+            let _ = writeln!(out, "deal_stream_latency_seconds_sum{{platform=\"{}\"}} {}", label, seconds(platform.sum_ms));
+            let _ = writeln!(out, "deal_stream_latency_seconds_count{{platform=\"{}\"}} {}", label, platform.samples);
+        }
+
+        out.push_str("# HELP deal_stream_sla_breached Whether the platform's p95 latency is over budget\n");
+        out.push_str("# TYPE deal_stream_sla_breached gauge\n");
+        for platform in &report.platforms {
+            let breached = report.alerts.iter().any(|a| a.platform == platform.platform);
+            let _ = writeln!(out, "deal_stream_sla_breached{{platform=\"{}\"}} {}", escape_label(&platform.platform), u8::from(breached));
+        }
+
+        out.push_str("# HELP deal_stream_sla_p95_budget_seconds Configured p95 latency budget\n");
+        out.push_str("# TYPE deal_stream_sla_p95_budget_seconds gauge\n");
+        let _ = writeln!(out, "deal_stream_sla_p95_budget_seconds {}", seconds(report.budget_ms));
+        out
+    }
+
+    fn prune(&self, window: &mut VecDeque<Sample>, now: DateTime<Utc>) {
+        let horizon = chrono::Duration::from_std(self.config.window).unwrap_or(chrono::Duration::MAX);
+        while window
+            .front()
+            .is_some_and(|sample| now - sample.delivered_at > horizon || window.len() > MAX_SAMPLES)
+        {
+            window.pop_front();
+        }
+    }
+
+    fn stats(&self, platform: &str, window: &VecDeque<Sample>) -> PlatformSla {
+        let mut latencies: Vec<u64> = window.iter().map(|s| s.latency.as_millis() as u64).collect();
+        latencies.sort_unstable();
+        let p95_ms = percentile(&latencies, 0.95);
+
+        PlatformSla {
+            platform: platform.to_string(),
+            samples: latencies.len(),
+            p50_ms: percentile(&latencies, 0.5),
+            p95_ms,
+            p99_ms: percentile(&latencies, 0.99),
+            max_ms: latencies.last().copied().unwrap_or(0),
+            sum_ms: latencies.iter().sum(),
+            over_budget: latencies.len() >= MIN_SAMPLES && p95_ms > self.config.p95_budget.as_millis() as u64,
+        }
+    }
+
+    /// Raise or resolve the platform's alert
+    async fn evaluate(&self, stats: PlatformSla, now: DateTime<Utc>) {
+        let mut alerts = self.alerts.lock().await;
+        let event = match (stats.over_budget, alerts.contains_key(&stats.platform)) {
+            (true, false) => {
+                let alert = SlaAlert {
+                    platform: stats.platform.clone(),
+                    p95_ms: stats.p95_ms,
+                    budget_ms: self.config.p95_budget.as_millis() as u64,
+                    raised_at: now,
+                };
+                tracing::warn!(
+                    platform = %alert.platform,
+                    p95_ms = alert.p95_ms,
+                    budget_ms = alert.budget_ms,
+                    "SLA breach: deal stream p95 over budget"
+                );
+                alerts.insert(stats.platform.clone(), alert.clone());
+                ("raised", alert)
+            }
+            (false, true) => {
+                let mut alert = alerts.remove(&stats.platform).expect("checked above");
+                alert.p95_ms = stats.p95_ms;
+                tracing::info!(platform = %alert.platform, p95_ms = alert.p95_ms, "SLA recovered: deal stream p95 within budget");
+                ("resolved", alert)
+            }
+            _ => return,
+        };
+        drop(alerts);
+
+        if let Some(url) = &self.config.alert_webhook {
+            let request = self.client.post(url).json(&json!({"event": event.0, "alert": event.1}));
+            tokio::spawn(async move {
+                if let Err(e) = request.send().await.and_then(|r| r.error_for_status()) {
+                    tracing::warn!(error = %e, "Failed to deliver an SLA alert");
+                }
+            });
+        }
+    }
+}
+
+impl Default for SlaMonitor {
+    fn default() -> Self {
+        Self::new(SlaConfig::default())
+    }
+}
+
+/// Nearest-rank percentile of sorted values
+fn percentile(sorted: &[u64], p: f64) -> u64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let rank = (p * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+fn seconds(ms: u64) -> f64 {
+    ms as f64 / 1000.0
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    #[tokio::test]
+    async fn test_raises_and_resolves_alert_on_p95() {
+        let clock = Arc::new(MockClock::new());
+        let monitor = SlaMonitor::new(SlaConfig {
+            p95_budget: Duration::from_secs(60),
+            window: Duration::from_secs(600),
+            alert_webhook: None,
+        })
+        .with_clock(clock.clone());
+
+        // 18 quick deliveries and 2 slow ones: p95 lands on a slow one
+        for i in 0..20 {
+            let delay = if i < 18 { 5 } else { 120 };
+            monitor.record("shop.com", clock.now() - chrono::Duration::seconds(delay)).await;
+        }
+        monitor.record("fast.com", clock.now()).await;
+
+        let report = monitor.report().await;
+        let shop = &report.platforms[1];
+        assert_eq!((shop.samples, shop.p50_ms, shop.p95_ms), (20, 5_000, 120_000));
+        assert_eq!(report.alerts.iter().map(|a| a.platform.as_str()).collect::<Vec<_>>(), vec!["shop.com"]);
+        assert!(monitor
+            .prometheus()
+            .await
+            .contains("deal_stream_latency_seconds{platform=\"shop.com\",quantile=\"0.95\"} 120"));
+
+        // The slow samples age out of the window and the alert resolves
+        clock.advance(Duration::from_secs(601));
+        for _ in 0..20 {
+            monitor.record("shop.com", clock.now() - chrono::Duration::seconds(1)).await;
+        }
+        let report = monitor.report().await;
+        assert!(report.alerts.is_empty());
+        assert_eq!(report.platforms.len(), 1);
+    }
+}
@@ -0,0 +1,218 @@
+use rust_decimal::prelude::FromPrimitive;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use reqwest;
+use utoipa::ToSchema;
+
+use crate::models::domain::{MerchantDomain, Money};
+use crate::pricing::rewards::{EffectivePrice, RewardsValuator};
+use crate::storage::shipping_rules::ShippingRule;
+
+pub mod optimizer;
+pub mod rules;
+
+pub use optimizer::{Cart, CartItem, CartPlan};
+pub use rules::{CodeOrder, StackPlan, StackRules, StackingRule, StackingRules};
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash, ToSchema)]
+pub enum DealType {
+    #[serde(rename = "coupon")]
+    Coupon,
+    #[serde(rename = "cashback")]
+    Cashback,
+    #[serde(rename = "discount")]
+    Discount,
+    #[serde(rename = "card_offer")]
+    CardOffer,
+    #[serde(rename = "wallet_offer")]
+    WalletOffer,
+    #[serde(rename = "membership")]
+    Membership,
+    #[serde(rename = "referral")]
+    Referral,
+    #[serde(rename = "bundle")]
+    Bundle,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+#[schema(as = StackableDeal)]
+pub struct Deal {
+    pub id: String,
+    pub title: String,
+    pub description: String,
+    pub deal_type: DealType,
+    pub value: f64,
+    pub value_type: String,
+    pub code: Option<String>,
+    pub min_purchase: Option<f64>,
+    pub max_discount: Option<f64>,
+    pub platform: String,
+    pub confidence: f64,
+    pub stackable: bool,
+    pub terms: Vec<String>,
+    pub priority: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StackedDealResult {
+    pub deals: Vec<Deal>,
+    pub total_savings: f64,
+    pub final_price: f64,
+    pub original_price: f64,
+    pub confidence: f64,
+    pub application_order: Vec<String>,
+    /// Codes in the order to enter them at the merchant's checkout
+    #[serde(default)]
+    pub apply_order: Vec<String>,
+    /// Gift card to buy first and points earned on `final_price`, valued in USD
+    #[serde(default)]
+    pub rewards: Option<EffectivePrice>,
+    pub warnings: Vec<String>,
+    pub processing_time: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StackDealsRequest {
+    pub deals: Vec<Deal>,
+    pub base_price: f64,
+    pub user_context: Option<HashMap<String, serde_json::Value>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ValidateStackRequest {
+    pub deals: Vec<Deal>,
+    pub base_price: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ValidateStackResponse {
+    pub valid: bool,
+    pub total_savings: Option<f64>,
+    pub final_price: Option<f64>,
+    pub confidence: Option<f64>,
+    pub warnings: Vec<String>,
+    pub error: Option<String>,
+}
+
+pub struct StackSmartEngine {
+    rules: StackRules,
+    rewards: Option<Arc<RewardsValuator>>,
+}
+
+impl Default for StackSmartEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StackSmartEngine {
+    pub fn new() -> Self {
+        StackSmartEngine {
+            rules: StackRules::default(),
+            rewards: None,
+        }
+    }
+
+    /// Per-merchant code order and stacking rules
+    pub fn with_rules(mut self, rules: StackRules) -> Self {
+        self.rules = rules;
+        self
+    }
+
+    /// Gift card and loyalty valuation of the stacked price
+    pub fn with_rewards(mut self, rewards: Arc<RewardsValuator>) -> Self {
+        self.rewards = Some(rewards);
+        self
+    }
+
+    pub async fn optimize_deals(&self, request: StackDealsRequest) -> StackedDealResult {
+        let client = reqwest::Client::new();
+        let mut res = client
+            .post("http://localhost:8001/optimize-deals")
+            .json(&request)
+            .send()
+            .await
+            .unwrap()
+            .json::<StackedDealResult>()
+            .await
+            .unwrap();
+
+        let plan = self.rules.plan(&res.deals, Decimal::from_f64(res.original_price).unwrap_or_default());
+        if plan.order_penalty.round_dp(2) > Decimal::ZERO {
+            res.warnings.push(format!(
+                "Enter codes in this order: {}. The order given costs {:.2} more",
+                plan.apply_order.join(", "),
+                plan.order_penalty
+            ));
+        }
+        res.apply_order = plan.apply_order;
+        if let Some(violation) = self.violation(&res.deals) {
+            res.warnings.push(format!("{}; the merchant may reject this stack", violation));
+        }
+
+        let merchant = res.deals.first().and_then(|d| MerchantDomain::parse(&d.platform).ok());
+        let final_price = Decimal::from_f64(res.final_price).map(|p| Money::usd(p.round_dp(2)));
+        if let (Some(rewards), Some(merchant), Some(final_price)) = (&self.rewards, merchant, final_price) {
+            let valued = rewards.effective_price(&merchant, final_price, 0);
+            if let Some(gift_card) = &valued.gift_card {
+                res.warnings.push(format!(
+                    "Buy a {} gift card from {} for {} first to save another {}",
+                    gift_card.face_value, gift_card.seller, gift_card.cost, gift_card.savings
+                ));
+            }
+            res.rewards = Some(valued);
+        }
+        res
+    }
+
+    /// Cheapest combination of the cart's coupons under the merchant's `shipping` rule
+    pub fn optimize_cart(&self, cart: &Cart, shipping: Option<&ShippingRule>) -> CartPlan {
+        let mut plan = optimizer::optimize(&self.rules, cart, shipping);
+
+        if let Some(rewards) = &self.rewards {
+            let valued = rewards.effective_price(&cart.merchant, plan.total, 0);
+            if let Some(gift_card) = &valued.gift_card {
+                plan.warnings.push(format!(
+                    "Buy a {} gift card from {} for {} first to save another {}",
+                    gift_card.face_value, gift_card.seller, gift_card.cost, gift_card.savings
+                ));
+            }
+            plan.rewards = Some(valued);
+        }
+        plan
+    }
+
+    /// Why the merchant of the first deal would not take `deals` together
+    fn violation(&self, deals: &[Deal]) -> Option<String> {
+        let merchant = self.rules.for_merchant(&deals.first()?.platform);
+        merchant.violation(&deals.iter().collect::<Vec<_>>())
+    }
+
+    pub async fn validate_deal_stack(&self, request: ValidateStackRequest) -> ValidateStackResponse {
+        if let Some(violation) = self.violation(&request.deals) {
+            return ValidateStackResponse {
+                valid: false,
+                total_savings: None,
+                final_price: None,
+                confidence: None,
+                warnings: vec![],
+                error: Some(violation),
+            };
+        }
+
+        // This is a placeholder for the validation logic.
+        let final_price = request.base_price * 0.9; // a dummy 10% discount
+        let total_savings = request.base_price - final_price;
+
+        ValidateStackResponse {
+            valid: true,
+            total_savings: Some(total_savings),
+            final_price: Some(final_price),
+            confidence: Some(0.9),
+            warnings: vec![],
+            error: None,
+        }
+    }
+}
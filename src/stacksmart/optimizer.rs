@@ -0,0 +1,514 @@
+//! Cart optimization
+//!
+//! [`optimize`] picks, out of the coupons offered for a cart, the combination the
+//! shopper pays least for once shipping and cashback are counted. Codes lower the
+//! subtotal in the order the merchant's [`CodeOrder`](super::CodeOrder) applies
+//! them; a lower subtotal can drop under the merchant's free-shipping threshold, and
+//! cashback is paid on what is left, so the biggest discount is not always the
+//! cheapest order. A code that is not `stackable` is only entered alone, a
+//! `free_shipping` code waives the base delivery cost once its minimum is met, and
+//! at most one cashback offer applies. Combinations the merchant's
+//! [`StackingRules`](super::StackingRules) forbid are skipped, and codes only take
+//! off the items those rules leave discountable.
+//!
+//! Stackable codes are searched by branch and bound over subsets. A code never
+//! takes off more than it would from the full subtotal, so a branch is dropped once
+//! even those discounts, free delivery and the most cashback its prices could earn
+//! would not beat the best combination found.
+//!
+//! Amounts are exact decimals throughout and only rounded to cents when the
+//! [`CartPlan`] is serialized.
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize, Serializer};
+use utoipa::ToSchema;
+
+use super::rules::{decimal, discount, plan_in, StackRules, StackingRules};
+use super::{Deal, DealType};
+use crate::models::domain::{Currency, MerchantDomain, Money};
+use crate::pricing::rewards::EffectivePrice;
+use crate::pricing::shipping::ShippingContext;
+use crate::storage::shipping_rules::ShippingRule;
+
+/// Stackable codes searched in combination; the least valuable ones beyond are left out
+pub const MAX_SEARCHED_CODES: usize = 16;
+pub const MAX_CART_COUPONS: usize = 100;
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CartItem {
+    pub id: String,
+    #[serde(default)]
+    pub title: String,
+    /// Unit price; every item of a cart is in the same currency
+    pub price: Money,
+    #[serde(default = "one")]
+    pub quantity: u32,
+    #[serde(default)]
+    pub category: Option<String>,
+    /// Already marked down, which some merchants exclude from codes
+    #[serde(default)]
+    pub on_sale: bool,
+}
+
+fn one() -> u32 {
+    1
+}
+
+impl CartItem {
+    fn total(&self) -> Decimal {
+        self.price.amount * Decimal::from(self.quantity)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Cart {
+    /// Whose shipping rule and code order apply
+    #[schema(value_type = String)]
+    pub merchant: MerchantDomain,
+    pub items: Vec<CartItem>,
+    /// Codes, free-shipping codes and cashback offers to choose from
+    #[serde(default)]
+    #[schema(value_type = Vec<StackableDeal>)]
+    pub coupons: Vec<Deal>,
+    #[serde(default)]
+    pub shipping: ShippingContext,
+}
+
+impl Cart {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.items.is_empty() {
+            return Err("cart has no items".to_string());
+        }
+        if self.items.iter().any(|item| item.price.amount < Decimal::ZERO || item.quantity == 0) {
+            return Err("item prices must not be negative and quantities must be at least 1".to_string());
+        }
+        if self.items.iter().any(|item| item.price.currency != self.currency()) {
+            return Err("item prices must all be in the same currency".to_string());
+        }
+        if self.coupons.len() > MAX_CART_COUPONS {
+            return Err(format!("at most {} coupons per cart", MAX_CART_COUPONS));
+        }
+        if self.coupons.iter().any(|coupon| !coupon.value.is_finite() || coupon.value < 0.0) {
+            return Err("coupon values must not be negative".to_string());
+        }
+        Ok(())
+    }
+
+    /// Currency of the cart's prices, which the plan is in too
+    pub fn currency(&self) -> Currency {
+        self.items.first().map_or(Currency::USD, |item| item.price.currency)
+    }
+
+    pub fn subtotal(&self) -> Money {
+        Money::new(self.items.iter().map(CartItem::total).sum(), self.currency())
+    }
+}
+
+/// Serialize `money` rounded to cents
+fn cents<S: Serializer>(money: &Money, serializer: S) -> Result<S::Ok, S::Error> {
+    Money::new(money.amount.round_dp(2), money.currency).serialize(serializer)
+}
+
+/// Where the savings against entering nothing come from
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SavingsBreakdown {
+    /// Taken off the items by codes
+    #[serde(serialize_with = "cents")]
+    pub codes: Money,
+    /// Negative when the codes take the order under the free-shipping threshold
+    #[serde(serialize_with = "cents")]
+    pub shipping: Money,
+    #[serde(serialize_with = "cents")]
+    pub cashback: Money,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CartPlan {
+    #[serde(serialize_with = "cents")]
+    pub subtotal: Money,
+    pub codes: Vec<Deal>,
+    /// Codes in the order to enter them at checkout
+    pub apply_order: Vec<String>,
+    pub cashback: Option<Deal>,
+    #[serde(serialize_with = "cents")]
+    pub shipping: Money,
+    /// Paid at checkout: the items after codes, plus shipping
+    #[serde(serialize_with = "cents")]
+    pub total: Money,
+    /// `total` less cashback
+    #[serde(serialize_with = "cents")]
+    pub net_total: Money,
+    pub savings: SavingsBreakdown,
+    #[serde(serialize_with = "cents")]
+    pub total_savings: Money,
+    pub combinations_evaluated: usize,
+    /// Gift card to buy first and points earned on `total`
+    #[serde(default)]
+    pub rewards: Option<EffectivePrice>,
+    pub warnings: Vec<String>,
+}
+
+fn is_free_shipping(deal: &Deal) -> bool {
+    matches!(deal.value_type.to_lowercase().as_str(), "free_shipping" | "freeshipping")
+}
+
+/// Delivery under the merchant's rule; without one, shipping is not counted
+struct Shipping {
+    base: Decimal,
+    free_over: Option<Decimal>,
+    /// The shopper's membership ships free
+    member: bool,
+    surcharge: Decimal,
+}
+
+impl Shipping {
+    /// `None` too when the rule is in another currency than the cart
+    fn new(rule: Option<&ShippingRule>, context: &ShippingContext, currency: Currency) -> Option<Self> {
+        let rule = rule.filter(|rule| rule.currency == currency)?;
+        let surcharge = context
+            .region
+            .as_ref()
+            .and_then(|region| rule.regional_surcharges.get(&region.trim().to_ascii_uppercase()))
+            .copied()
+            .unwrap_or_default();
+        let member = rule
+            .free_with_membership
+            .as_deref()
+            .is_some_and(|needed| context.memberships.iter().any(|m| m.trim().eq_ignore_ascii_case(needed)));
+
+        Some(Self {
+            base: rule.base_cost,
+            free_over: rule.free_over,
+            member,
+            surcharge,
+        })
+    }
+
+    fn cost(&self, items: Decimal, free_code: bool) -> Decimal {
+        let free = free_code || self.member || self.free_over.is_some_and(|over| items >= over);
+        if free {
+            self.surcharge
+        } else {
+            self.base + self.surcharge
+        }
+    }
+}
+
+/// One combination, priced
+#[derive(Clone)]
+struct Priced<'a> {
+    codes: Vec<&'a Deal>,
+    apply_order: Vec<String>,
+    items: Decimal,
+    shipping: Decimal,
+    cashback: Option<(&'a Deal, Decimal)>,
+}
+
+impl Priced<'_> {
+    fn earned(&self) -> Decimal {
+        self.cashback.map_or(Decimal::ZERO, |(_, earned)| earned)
+    }
+
+    fn net(&self) -> Decimal {
+        self.items + self.shipping - self.earned()
+    }
+
+    /// Cheaper, or as cheap with fewer codes to enter
+    fn beats(&self, other: &Priced) -> bool {
+        let (net, other_net) = (self.net(), other.net());
+        net < other_net || (net == other_net && self.codes.len() < other.codes.len())
+    }
+}
+
+struct Search<'a> {
+    merchant: StackingRules,
+    /// Subtotal of the items codes take off
+    discountable: Decimal,
+    /// Subtotal of the items codes leave alone
+    excluded: Decimal,
+    shipping: Option<Shipping>,
+    cashback: Vec<&'a Deal>,
+    evaluated: usize,
+}
+
+impl<'a> Search<'a> {
+    fn best_cashback(&self, items: Decimal) -> Option<(&'a Deal, Decimal)> {
+        self.cashback
+            .iter()
+            .map(|deal| (*deal, discount(deal, items)))
+            .filter(|(_, earned)| *earned > Decimal::ZERO)
+            .fold(None, |best, candidate| match best {
+                Some((_, earned)) if earned >= candidate.1 => best,
+                _ => Some(candidate),
+            })
+    }
+
+    fn price(&mut self, codes: &[&'a Deal]) -> Priced<'a> {
+        self.evaluated += 1;
+        let (free, discounting): (Vec<&Deal>, Vec<&Deal>) = codes.iter().partition(|d| is_free_shipping(d));
+        let plan = plan_in(self.merchant.order, discounting, self.discountable);
+        let items = plan.final_price + self.excluded;
+        let free_code = free.iter().any(|d| d.min_purchase.is_none_or(|min| items >= decimal(min)));
+
+        let mut apply_order = plan.apply_order;
+        apply_order.extend(free.iter().filter_map(|d| d.code.clone()));
+        Priced {
+            codes: codes.to_vec(),
+            apply_order,
+            items,
+            shipping: self.shipping.as_ref().map_or(Decimal::ZERO, |shipping| shipping.cost(items, free_code)),
+            cashback: self.best_cashback(items),
+        }
+    }
+
+    /// Every subset of `codes[from..]` added to `chosen`, pruned against `best`
+    fn search(&mut self, codes: &[&'a Deal], bounds: &[Decimal], from: usize, chosen: &mut Vec<&'a Deal>, best: &mut Priced<'a>) {
+        let priced = self.price(chosen);
+        if priced.beats(best) {
+            *best = priced.clone();
+        }
+
+        let lowest_shipping = self.shipping.as_ref().map_or(Decimal::ZERO, |shipping| shipping.surcharge);
+        let most_cashback = self.best_cashback(priced.items).map_or(Decimal::ZERO, |(_, earned)| earned);
+        for next in from..codes.len() {
+            // `bounds` only shrinks, so no later branch can do better either. Ties are
+            // still searched, for a combination with fewer codes
+            let lowest = (priced.items - bounds[next]).max(self.excluded) + lowest_shipping - most_cashback;
+            if lowest > best.net() {
+                break;
+            }
+            chosen.push(codes[next]);
+            // Rules only ever forbid adding codes, so neither can any superset
+            if !self.merchant.allows(chosen) {
+                chosen.pop();
+                continue;
+            }
+            self.search(codes, bounds, next + 1, chosen, best);
+            chosen.pop();
+        }
+    }
+}
+
+/// Cheapest combination of `cart`'s coupons at its merchant
+pub fn optimize(rules: &StackRules, cart: &Cart, shipping: Option<&ShippingRule>) -> CartPlan {
+    let currency = cart.currency();
+    let subtotal = cart.subtotal().amount;
+    let merchant = rules.for_merchant(cart.merchant.as_str());
+    let (discountable, excluded): (Vec<&CartItem>, Vec<&CartItem>) = cart.items.iter().partition(|item| merchant.discountable(item));
+    let discountable: Decimal = discountable.iter().map(|item| item.total()).sum();
+    let mut warnings = Vec::new();
+    if !excluded.is_empty() {
+        warnings.push(format!("{} items are on sale or in categories {} excludes from codes", excluded.len(), cart.merchant));
+    }
+    let cashback = cart.coupons.iter().filter(|d| d.deal_type == DealType::Cashback);
+    let all_cashback = cashback.clone().count();
+    let mut search = Search {
+        cashback: cashback.filter(|d| merchant.allows(&[*d])).collect(),
+        merchant,
+        discountable,
+        excluded: subtotal - discountable,
+        shipping: Shipping::new(shipping, &cart.shipping, currency),
+        evaluated: 0,
+    };
+    if search.shipping.is_none() {
+        warnings.push(format!("No shipping rule for {}; shipping is not counted", cart.merchant));
+    }
+
+    let codes: Vec<&Deal> = cart.coupons.iter().filter(|d| d.deal_type != DealType::Cashback && d.code.is_some()).collect();
+    let ignored = cart.coupons.len() - codes.len() - all_cashback;
+    if ignored > 0 {
+        warnings.push(format!("{} offers without a code or cashback were left out", ignored));
+    }
+
+    // Worth alone: a code's discount on the full subtotal, or the delivery it waives
+    let worth = |d: &Deal| match is_free_shipping(d) {
+        true => search.shipping.as_ref().map_or(Decimal::ZERO, |shipping| shipping.base),
+        false => discount(d, discountable),
+    };
+    let (mut stackable, alone): (Vec<&Deal>, Vec<&Deal>) = codes.into_iter().partition(|d| d.stackable);
+    stackable.sort_by_key(|d| std::cmp::Reverse(worth(d)));
+    if stackable.len() > MAX_SEARCHED_CODES {
+        warnings.push(format!(
+            "Only the {} most valuable stackable codes were combined; {} more were left out",
+            MAX_SEARCHED_CODES,
+            stackable.len() - MAX_SEARCHED_CODES
+        ));
+        stackable.truncate(MAX_SEARCHED_CODES);
+    }
+    let mut bounds: Vec<Decimal> = stackable
+        .iter()
+        .rev()
+        .scan(Decimal::ZERO, |total, d| {
+            *total += if is_free_shipping(d) { Decimal::ZERO } else { discount(d, discountable) };
+            Some(*total)
+        })
+        .collect();
+    bounds.reverse();
+
+    let baseline = search.price(&[]);
+    let mut best = baseline.clone();
+    for code in alone {
+        if !search.merchant.allows(&[code]) {
+            continue;
+        }
+        let priced = search.price(&[code]);
+        if priced.beats(&best) {
+            best = priced;
+        }
+    }
+    search.search(&stackable, &bounds, 0, &mut Vec::new(), &mut best);
+
+    let money = |amount: Decimal| Money::new(amount, currency);
+    let savings = SavingsBreakdown {
+        codes: money(subtotal - best.items),
+        shipping: money(baseline.shipping - best.shipping),
+        cashback: money(best.earned()),
+    };
+    CartPlan {
+        subtotal: money(subtotal),
+        codes: best.codes.iter().map(|d| (*d).clone()).collect(),
+        apply_order: best.apply_order.clone(),
+        cashback: best.cashback.map(|(deal, _)| deal.clone()),
+        shipping: money(best.shipping),
+        total: money(best.items + best.shipping),
+        net_total: money(best.net()),
+        total_savings: money(subtotal + baseline.shipping - best.net()),
+        savings,
+        combinations_evaluated: search.evaluated,
+        rewards: None,
+        warnings,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    use crate::stacksmart::{StackingRule, StackingRules};
+
+    fn offer(code: Option<&str>, deal_type: DealType, value: f64, value_type: &str) -> Deal {
+        Deal {
+            id: code.unwrap_or("cashback").to_lowercase(),
+            title: String::new(),
+            description: String::new(),
+            deal_type,
+            value,
+            value_type: value_type.to_string(),
+            code: code.map(str::to_string),
+            min_purchase: None,
+            max_discount: None,
+            platform: "shop.com".to_string(),
+            confidence: 0.9,
+            stackable: true,
+            terms: vec![],
+            priority: 0,
+        }
+    }
+
+    fn cart(coupons: Vec<Deal>) -> Cart {
+        Cart {
+            merchant: MerchantDomain::parse("shop.com").unwrap(),
+            items: vec![
+                CartItem { id: "a".to_string(), title: String::new(), price: Money::usd(dec!(30)), quantity: 2, category: None, on_sale: false },
+                CartItem { id: "b".to_string(), title: String::new(), price: Money::usd(dec!(45)), quantity: 1, category: None, on_sale: false },
+            ],
+            coupons,
+            shipping: ShippingContext::default(),
+        }
+    }
+
+    #[test]
+    fn test_cart_optimizer_weighs_shipping_and_cashback_against_discounts() {
+        let rule = ShippingRule {
+            currency: Currency::USD,
+            base_cost: Decimal::from(10),
+            free_over: Some(Decimal::from(100)),
+            free_with_membership: None,
+            regional_surcharges: Default::default(),
+        };
+        let mut solo = offer(Some("SOLO30"), DealType::Coupon, 30.0, "percentage");
+        solo.stackable = false;
+        let mut cashback = offer(None, DealType::Cashback, 10.0, "percentage");
+        cashback.max_discount = Some(5.0);
+        let coupons = vec![
+            offer(Some("TEN"), DealType::Coupon, 10.0, "fixed"),
+            offer(Some("FIVE"), DealType::Coupon, 5.0, "fixed"),
+            solo,
+            cashback,
+            offer(None, DealType::CardOffer, 5.0, "percentage"),
+        ];
+        assert!(cart(vec![]).validate().is_ok());
+        let mut empty = cart(vec![]);
+        empty.items.clear();
+        assert!(empty.validate().is_err());
+        let mut mixed = cart(vec![]);
+        mixed.items[1].price = Money::new(dec!(45), Currency::parse("EUR").unwrap());
+        assert!(mixed.validate().is_err());
+
+        // $105 ships free. TEN and FIVE take it to $90 and $10 shipping: $100 - $5 back.
+        // SOLO30 alone: $73.50 + $10 shipping - $5 back beats staying at $105 - $5 back
+        let plan = optimize(&StackRules::default(), &cart(coupons.clone()), Some(&rule));
+        assert_eq!(plan.apply_order, vec!["SOLO30"]);
+        assert_eq!((plan.subtotal.amount, plan.total.amount, plan.net_total.amount), (dec!(105), dec!(83.5), dec!(78.5)));
+        assert_eq!(
+            (plan.savings.codes.amount, plan.savings.shipping.amount, plan.savings.cashback.amount),
+            (dec!(31.5), dec!(-10), dec!(5))
+        );
+        assert_eq!(plan.total_savings, Money::usd(dec!(26.5)));
+        assert_eq!(plan.cashback.unwrap().deal_type, DealType::Cashback);
+        assert_eq!(plan.warnings.len(), 1);
+
+        // Without SOLO30, FIVE alone keeps the order over the threshold
+        let stacked: Vec<Deal> = coupons.into_iter().filter(|d| d.stackable).collect();
+        let plan = optimize(&StackRules::default(), &cart(stacked.clone()), Some(&rule));
+        assert_eq!(plan.apply_order, vec!["FIVE"]);
+        assert_eq!((plan.shipping.amount, plan.net_total.amount), (dec!(0), dec!(95)));
+
+        // A free-shipping code makes both codes worth entering
+        let mut free = stacked;
+        free.push(offer(Some("SHIPFREE"), DealType::Coupon, 0.0, "free_shipping"));
+        let plan = optimize(&StackRules::default(), &cart(free), Some(&rule));
+        assert_eq!(plan.apply_order.last().map(String::as_str), Some("SHIPFREE"));
+        assert_eq!((plan.codes.len(), plan.shipping.amount, plan.net_total.amount), (3, dec!(0), dec!(85)));
+        assert!(plan.combinations_evaluated < 8);
+
+        // Amounts stay exact until they are serialized
+        let plan = optimize(&StackRules::default(), &cart(vec![offer(Some("EIGHTH"), DealType::Coupon, 12.5, "percentage")]), None);
+        assert_eq!(plan.total.amount, dec!(91.875));
+        let json = serde_json::to_value(&plan).unwrap();
+        assert_eq!((json["total"]["amount"].as_str(), json["savings"]["codes"]["amount"].as_str()), (Some("91.88"), Some("13.12")));
+    }
+
+    #[test]
+    fn test_cart_optimizer_follows_merchant_stacking_rules() {
+        let mut sale = cart(vec![
+            offer(Some("TEN"), DealType::Coupon, 10.0, "fixed"),
+            offer(Some("FIVE"), DealType::Coupon, 5.0, "fixed"),
+            offer(Some("SAVE20"), DealType::Coupon, 20.0, "percentage"),
+        ]);
+        sale.items[1].on_sale = true;
+        let rules = |rules: Vec<StackingRule>| StackRules {
+            merchants: [("shop.com".to_string(), StackingRules { order: Default::default(), rules })].into(),
+        };
+
+        // Codes only take off the $60 not on sale, and only one of them is allowed
+        let limited = rules(vec![
+            StackingRule::NoCodesOnSale,
+            StackingRule::Limit { deal_type: DealType::Coupon, max: 1 },
+        ]);
+        let plan = optimize(&limited, &sale, None);
+        assert_eq!(plan.apply_order, vec!["SAVE20"]);
+        assert_eq!((plan.savings.codes.amount, plan.net_total.amount), (dec!(12), dec!(93)));
+        assert_eq!(plan.warnings.len(), 2);
+
+        // SAVE20 and TEN never together: 20% of $60, then $5
+        let exclusive = rules(vec![
+            StackingRule::NoCodesOnSale,
+            StackingRule::Exclusive { codes: vec!["save20".to_string(), "ten".to_string()] },
+        ]);
+        let plan = optimize(&exclusive, &sale, None);
+        assert_eq!(plan.apply_order, vec!["SAVE20", "FIVE"]);
+        assert_eq!(plan.net_total.amount, dec!(88));
+    }
+}
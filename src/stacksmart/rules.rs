@@ -0,0 +1,386 @@
+//! Per-merchant stacking rules
+//!
+//! Checkouts differ in how they combine several codes. Some apply each code to the
+//! running total in the order it was entered, so 20% then $10 off a $100 cart costs
+//! $70 while $10 then 20% costs $72. Others sort the codes themselves or take every
+//! discount from the original price. Merchants also limit what may be combined: one
+//! promo code and one cashback offer, never two particular codes together, or no
+//! codes on sale items and some categories.
+//!
+//! [`StackRules`] holds each merchant's [`StackingRules`], loaded from the JSON file
+//! at `STACKING_RULES_PATH`:
+//!
+//! ```json
+//! {"merchants": {
+//!     "shop.com": {"order": "fixed_first", "rules": [
+//!         {"rule": "limit", "deal_type": "coupon", "max": 1},
+//!         {"rule": "exclusive", "codes": ["WELCOME", "VIP20"]},
+//!         {"rule": "exclude_categories", "categories": ["gift cards"]},
+//!         {"rule": "no_codes_on_sale"}
+//!     ]},
+//!     "other.com": "percentage_first"
+//! }}
+//! ```
+//!
+//! A merchant given only a [`CodeOrder`] has no other rules. [`StackRules::plan`]
+//! picks the entry order that costs the least.
+
+use std::collections::HashMap;
+
+use rust_decimal::prelude::FromPrimitive;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use super::optimizer::CartItem;
+use super::{Deal, DealType};
+
+/// Codes beyond this are ordered percentage-first instead of trying every order
+const MAX_SEARCHED_CODES: usize = 6;
+
+/// How a merchant combines codes at checkout
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CodeOrder {
+    /// Each code applies to the running total, in the order entered
+    #[default]
+    Sequential,
+    /// Percentage codes are applied before fixed ones, whatever the entry order
+    PercentageFirst,
+    /// Fixed codes are applied before percentage ones, whatever the entry order
+    FixedFirst,
+    /// Every code is taken from the original price, so order does not matter
+    OriginalPrice,
+}
+
+/// One restriction on what a merchant lets shoppers combine
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "rule", rename_all = "snake_case")]
+pub enum StackingRule {
+    /// At most `max` offers of `deal_type` together, e.g. one coupon
+    Limit { deal_type: DealType, max: usize },
+    /// At most one of these codes together
+    Exclusive { codes: Vec<String> },
+    /// Codes take nothing off items in these categories
+    ExcludeCategories { categories: Vec<String> },
+    /// Codes take nothing off items already on sale
+    NoCodesOnSale,
+}
+
+/// How one merchant applies codes and what it lets shoppers combine
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(from = "MerchantEntry")]
+pub struct StackingRules {
+    pub order: CodeOrder,
+    pub rules: Vec<StackingRule>,
+}
+
+/// A merchant's rules as written in the file, where a bare order means no other rules
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum MerchantEntry {
+    Order(CodeOrder),
+    Rules {
+        #[serde(default)]
+        order: CodeOrder,
+        #[serde(default)]
+        rules: Vec<StackingRule>,
+    },
+}
+
+impl From<MerchantEntry> for StackingRules {
+    fn from(entry: MerchantEntry) -> Self {
+        match entry {
+            MerchantEntry::Order(order) => order.into(),
+            MerchantEntry::Rules { order, rules } => Self { order, rules },
+        }
+    }
+}
+
+impl From<CodeOrder> for StackingRules {
+    fn from(order: CodeOrder) -> Self {
+        Self { order, rules: Vec::new() }
+    }
+}
+
+impl StackingRules {
+    /// Why the merchant would not take `deals` together, if it would not
+    pub fn violation(&self, deals: &[&Deal]) -> Option<String> {
+        self.rules.iter().find_map(|rule| match rule {
+            StackingRule::Limit { deal_type, max } => {
+                let count = deals.iter().filter(|d| d.deal_type == *deal_type).count();
+                (count > *max).then(|| format!("At most {} {:?} offers can be combined", max, deal_type))
+            }
+            StackingRule::Exclusive { codes } => {
+                let used: Vec<&str> = deals
+                    .iter()
+                    .filter_map(|d| d.code.as_deref())
+                    .filter(|code| codes.iter().any(|c| c.eq_ignore_ascii_case(code)))
+                    .collect();
+                (used.len() > 1).then(|| format!("{} cannot be combined", used.join(" and ")))
+            }
+            StackingRule::ExcludeCategories { .. } | StackingRule::NoCodesOnSale => None,
+        })
+    }
+
+    pub fn allows(&self, deals: &[&Deal]) -> bool {
+        self.violation(deals).is_none()
+    }
+
+    /// Whether codes may take anything off `item`
+    pub fn discountable(&self, item: &CartItem) -> bool {
+        self.rules.iter().all(|rule| match rule {
+            StackingRule::ExcludeCategories { categories } => !item
+                .category
+                .as_deref()
+                .is_some_and(|category| categories.iter().any(|c| c.trim().eq_ignore_ascii_case(category.trim()))),
+            StackingRule::NoCodesOnSale => !item.on_sale,
+            StackingRule::Limit { .. } | StackingRule::Exclusive { .. } => true,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StackRules {
+    /// Rules by platform; unlisted merchants are [`CodeOrder::Sequential`] without
+    /// restrictions
+    #[serde(default)]
+    pub merchants: HashMap<String, StackingRules>,
+}
+
+/// Recommended way to enter a set of codes
+#[derive(Debug, Clone, PartialEq)]
+pub struct StackPlan {
+    pub order: CodeOrder,
+    /// Codes in the order to enter them
+    pub apply_order: Vec<String>,
+    pub final_price: Decimal,
+    /// How much more entering the codes in the given order would cost
+    pub order_penalty: Decimal,
+}
+
+impl StackRules {
+    /// Rules from `STACKING_RULES_PATH`, or none
+    pub fn from_env() -> Self {
+        let Ok(path) = std::env::var("STACKING_RULES_PATH") else {
+            return Self::default();
+        };
+
+        match Self::load(&path) {
+            Ok(rules) => rules,
+            Err(e) => {
+                tracing::warn!(path = %path, error = %e, "Failed to load stacking rules");
+                Self::default()
+            }
+        }
+    }
+
+    fn load(path: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let content = std::fs::read_to_string(path)?;
+        let mut rules: Self = serde_json::from_str(&content)?;
+        rules.merchants = rules.merchants.into_iter().map(|(k, v)| (k.to_lowercase(), v)).collect();
+        Ok(rules)
+    }
+
+    pub fn order_for(&self, platform: &str) -> CodeOrder {
+        self.merchants.get(&platform.to_lowercase()).map(|rules| rules.order).unwrap_or_default()
+    }
+
+    pub fn for_merchant(&self, platform: &str) -> StackingRules {
+        self.merchants.get(&platform.to_lowercase()).cloned().unwrap_or_default()
+    }
+
+    /// Entry order for the code deals in `deals`, in the merchant of the first code
+    pub fn plan(&self, deals: &[Deal], base_price: Decimal) -> StackPlan {
+        let codes: Vec<&Deal> = deals.iter().filter(|d| d.code.is_some()).collect();
+        let order = codes.first().map_or(CodeOrder::Sequential, |d| self.order_for(&d.platform));
+        plan_in(order, codes, base_price)
+    }
+}
+
+/// Entry order for `codes` at a merchant applying them by `order`
+pub(super) fn plan_in(order: CodeOrder, codes: Vec<&Deal>, base_price: Decimal) -> StackPlan {
+    let given = final_price(order, &codes, base_price);
+    let entered = match order {
+        CodeOrder::Sequential if codes.len() <= MAX_SEARCHED_CODES => cheapest_sequence(&codes, base_price),
+        CodeOrder::Sequential | CodeOrder::PercentageFirst => sorted(&codes, true),
+        CodeOrder::FixedFirst => sorted(&codes, false),
+        CodeOrder::OriginalPrice => codes,
+    };
+    let final_price = final_price(order, &entered, base_price);
+
+    StackPlan {
+        order,
+        apply_order: entered.iter().filter_map(|d| d.code.clone()).collect(),
+        final_price,
+        order_penalty: given - final_price,
+    }
+}
+
+fn is_percentage(deal: &Deal) -> bool {
+    matches!(deal.value_type.to_lowercase().as_str(), "percentage" | "percent")
+}
+
+/// An offer's value as an exact amount; offers carry plain numbers
+pub(super) fn decimal(value: f64) -> Decimal {
+    Decimal::from_f64(value).unwrap_or_default()
+}
+
+/// Discount of one code on a `price` subtotal
+pub(super) fn discount(deal: &Deal, price: Decimal) -> Decimal {
+    if deal.min_purchase.is_some_and(|min| price < decimal(min)) {
+        return Decimal::ZERO;
+    }
+    let value = decimal(deal.value);
+    let amount = if is_percentage(deal) { price * value / Decimal::ONE_HUNDRED } else { value };
+    deal.max_discount.map_or(amount, |max| amount.min(decimal(max))).clamp(Decimal::ZERO, price)
+}
+
+/// Stable sort into percentage-first (or fixed-first) order
+fn sorted<'a>(codes: &[&'a Deal], percentage_first: bool) -> Vec<&'a Deal> {
+    let mut sorted = codes.to_vec();
+    sorted.sort_by_key(|d| is_percentage(d) != percentage_first);
+    sorted
+}
+
+/// Price after the merchant applies `codes`, entered in that order
+fn final_price(order: CodeOrder, codes: &[&Deal], base_price: Decimal) -> Decimal {
+    let applied = match order {
+        CodeOrder::Sequential => codes.to_vec(),
+        CodeOrder::PercentageFirst => sorted(codes, true),
+        CodeOrder::FixedFirst => sorted(codes, false),
+        CodeOrder::OriginalPrice => {
+            let total: Decimal = codes.iter().map(|d| discount(d, base_price)).sum();
+            return (base_price - total).max(Decimal::ZERO);
+        }
+    };
+    applied.iter().fold(base_price, |price, d| price - discount(d, price))
+}
+
+/// Cheapest entry order at a sequential merchant; the given order wins ties
+fn cheapest_sequence<'a>(codes: &[&'a Deal], base_price: Decimal) -> Vec<&'a Deal> {
+    fn search<'a>(
+        remaining: &mut Vec<&'a Deal>,
+        current: &mut Vec<&'a Deal>,
+        base_price: Decimal,
+        best: &mut (Decimal, Vec<&'a Deal>),
+    ) {
+        if remaining.is_empty() {
+            let price = final_price(CodeOrder::Sequential, current, base_price);
+            if price < best.0 {
+                *best = (price, current.clone());
+            }
+            return;
+        }
+        for i in 0..remaining.len() {
+            current.push(remaining.remove(i));
+            search(remaining, current, base_price, best);
+            remaining.insert(i, current.pop().expect("pushed above"));
+        }
+    }
+
+    let mut best = (final_price(CodeOrder::Sequential, codes, base_price), codes.to_vec());
+    search(&mut codes.to_vec(), &mut Vec::new(), base_price, &mut best);
+    best.1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    use crate::models::domain::Money;
+    use crate::stacksmart::DealType;
+
+    fn code(code: &str, value: f64, value_type: &str) -> Deal {
+        Deal {
+            id: code.to_lowercase(),
+            title: code.to_string(),
+            description: String::new(),
+            deal_type: DealType::Coupon,
+            value,
+            value_type: value_type.to_string(),
+            code: Some(code.to_string()),
+            min_purchase: None,
+            max_discount: None,
+            platform: "Shop".to_string(),
+            confidence: 0.9,
+            stackable: true,
+            terms: vec![],
+            priority: 0,
+        }
+    }
+
+    fn rules(order: CodeOrder) -> StackRules {
+        StackRules {
+            merchants: HashMap::from([("shop".to_string(), order.into())]),
+        }
+    }
+
+    #[test]
+    fn test_sequential_merchant_gets_cheapest_order() {
+        let mut ten = code("TEN", 10.0, "fixed");
+        ten.min_purchase = Some(95.0);
+        let deals = vec![code("FIVE", 5.0, "fixed"), code("SAVE20", 20.0, "percentage"), ten];
+
+        // TEN only qualifies while the subtotal is still 100, then 20% of 90, then 5
+        let plan = StackRules::default().plan(&deals, dec!(100));
+        assert_eq!(plan.order, CodeOrder::Sequential);
+        assert_eq!(plan.apply_order, vec!["TEN", "SAVE20", "FIVE"]);
+        assert_eq!(plan.final_price, dec!(67));
+        // As given: 95, then 20% of 95, and TEN no longer qualifies
+        assert_eq!(plan.order_penalty, dec!(9));
+    }
+
+    #[test]
+    fn test_merchant_order_semantics() {
+        let deals = vec![code("TENOFF", 10.0, "fixed"), code("SAVE20", 20.0, "percentage")];
+
+        let plan = rules(CodeOrder::FixedFirst).plan(&deals, dec!(100));
+        assert_eq!(plan.apply_order, vec!["TENOFF", "SAVE20"]);
+        assert_eq!(plan.final_price, dec!(72));
+        assert_eq!(plan.order_penalty, Decimal::ZERO);
+
+        let plan = rules(CodeOrder::PercentageFirst).plan(&deals, dec!(100));
+        assert_eq!(plan.apply_order, vec!["SAVE20", "TENOFF"]);
+        assert_eq!(plan.final_price, dec!(70));
+
+        let plan = rules(CodeOrder::OriginalPrice).plan(&deals, dec!(100));
+        assert_eq!(plan.apply_order, vec!["TENOFF", "SAVE20"]);
+        assert_eq!(plan.final_price, dec!(70));
+    }
+
+    #[test]
+    fn test_stacking_rules_parse_and_flag_forbidden_combinations() {
+        let rules: StackRules = serde_json::from_value(serde_json::json!({"merchants": {
+            "shop": {"order": "fixed_first", "rules": [
+                {"rule": "limit", "deal_type": "cashback", "max": 0},
+                {"rule": "exclusive", "codes": ["TENOFF", "SAVE20"]},
+                {"rule": "exclude_categories", "categories": ["Gift Cards"]}
+            ]},
+            "other": "percentage_first"
+        }}))
+        .unwrap();
+        assert_eq!(rules.order_for("other"), CodeOrder::PercentageFirst);
+        assert!(rules.for_merchant("other").rules.is_empty());
+
+        let shop = rules.for_merchant("Shop");
+        assert_eq!(shop.order, CodeOrder::FixedFirst);
+        let (ten, save) = (code("TENOFF", 10.0, "fixed"), code("save20", 20.0, "percentage"));
+        assert!(shop.allows(&[&ten]));
+        assert_eq!(shop.violation(&[&ten, &save]).as_deref(), Some("TENOFF and save20 cannot be combined"));
+        let mut cashback = code("BACK", 5.0, "percentage");
+        cashback.deal_type = DealType::Cashback;
+        assert!(!shop.allows(&[&cashback]));
+
+        let item = |category: &str| CartItem {
+            id: "a".to_string(),
+            title: String::new(),
+            price: Money::usd(dec!(10)),
+            quantity: 1,
+            category: Some(category.to_string()),
+            on_sale: true,
+        };
+        assert!(!shop.discountable(&item("gift cards")));
+        assert!(shop.discountable(&item("toys")));
+    }
+}
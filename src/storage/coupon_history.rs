@@ -0,0 +1,414 @@
+//! Versioned coupon history
+//!
+//! The coupon store holds each code's latest listing only. [`CouponHistory`] follows
+//! its upserts and records a version whenever a listing's offer changes (title,
+//! description, locale, discount, source or `valid_until`), so the catalogue can be
+//! reconstructed as it stood at a past time: which codes were known, and which of
+//! those had not expired. Re-scrapes that only refresh `scraped_at` or the model's
+//! scores add no version.
+//!
+//! Versions are timestamped when the upsert is observed. Listings already in the
+//! store at startup and missing from the history are recorded as of their
+//! `scraped_at`. The store never deletes a code, so a code once known stays known.
+//!
+//! Versions are appended to a JSON-lines file (`COUPON_HISTORY_PATH`, default
+//! `data/coupon_history.jsonl`) and kept for `COUPON_HISTORY_RETENTION_DAYS`
+//! (default [`DEFAULT_RETENTION_DAYS`]). Each code keeps its last version from before
+//! that horizon, so any time within it can still be answered.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::Mutex;
+use utoipa::ToSchema;
+
+use crate::clock::{self, Clock};
+use crate::models::coupon_listing::CouponListing;
+use crate::models::domain::{CouponCode, MerchantDomain};
+use crate::storage::coupon_store::CouponStore;
+
+pub const DEFAULT_RETENTION_DAYS: i64 = 365;
+/// How often versions past the retention horizon are dropped
+const COMPACT_INTERVAL: Duration = Duration::from_secs(24 * 3600);
+
+/// A listing as it stood from `recorded_at` until the code's next version
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CouponVersion {
+    pub recorded_at: DateTime<Utc>,
+    pub listing: CouponListing,
+}
+
+/// A code as it stood at the time asked about
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct HistoricalCoupon {
+    #[serde(flatten)]
+    pub listing: CouponListing,
+    /// When the code's first version was recorded
+    pub known_since: DateTime<Utc>,
+    /// When `listing` was recorded
+    pub version_recorded_at: DateTime<Utc>,
+    /// Had not reached `valid_until` yet
+    pub valid: bool,
+}
+
+/// When a code was first seen and when it stops working, if known
+#[derive(Debug, Clone, PartialEq)]
+pub struct CodeLifetime {
+    pub merchant: MerchantDomain,
+    pub code: CouponCode,
+    pub known_since: DateTime<Utc>,
+    /// From the code's latest version
+    pub valid_until: Option<DateTime<Utc>>,
+}
+
+type Versions = HashMap<MerchantDomain, HashMap<CouponCode, Vec<CouponVersion>>>;
+
+pub struct CouponHistory {
+    store: Arc<CouponStore>,
+    /// Each code's versions, oldest first
+    versions: RwLock<Versions>,
+    path: Option<PathBuf>,
+    /// Serializes appends and rewrites of `path`
+    file: Mutex<()>,
+    retention: chrono::Duration,
+    clock: Arc<dyn Clock>,
+}
+
+impl CouponHistory {
+    /// Record `store`'s versions, in memory only unless `path` is given
+    pub fn new(store: Arc<CouponStore>, path: Option<PathBuf>) -> Self {
+        Self {
+            store,
+            versions: RwLock::new(HashMap::new()),
+            path,
+            file: Mutex::new(()),
+            retention: chrono::Duration::days(DEFAULT_RETENTION_DAYS),
+            clock: clock::system(),
+        }
+    }
+
+    pub fn with_retention(mut self, retention: chrono::Duration) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Load persisted versions from `COUPON_HISTORY_PATH` (default
+    /// `data/coupon_history.jsonl`), kept for `COUPON_HISTORY_RETENTION_DAYS`
+    pub async fn from_env(store: Arc<CouponStore>) -> Self {
+        let path = std::env::var("COUPON_HISTORY_PATH").unwrap_or_else(|_| "data/coupon_history.jsonl".to_string());
+        let days = std::env::var("COUPON_HISTORY_RETENTION_DAYS")
+            .ok()
+            .and_then(|days| days.trim().parse().ok())
+            .unwrap_or(DEFAULT_RETENTION_DAYS);
+        let history = Self::new(store, Some(PathBuf::from(path))).with_retention(chrono::Duration::days(days));
+
+        if let Err(e) = history.load().await {
+            tracing::warn!(error = %e, "Starting with an empty coupon history");
+        }
+        history
+    }
+
+    async fn load(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        let content = tokio::fs::read_to_string(path).await?;
+        let mut versions = Versions::new();
+        let mut unreadable = 0;
+        for line in content.lines().filter(|line| !line.trim().is_empty()) {
+            // A crash mid-append leaves a torn last line
+            let Ok(version) = serde_json::from_str::<CouponVersion>(line) else {
+                unreadable += 1;
+                continue;
+            };
+            versions
+                .entry(version.listing.merchant_domain.clone())
+                .or_default()
+                .entry(version.listing.code.clone())
+                .or_default()
+                .push(version);
+        }
+        for version in versions.values_mut().flat_map(HashMap::values_mut) {
+            version.sort_by_key(|version| version.recorded_at);
+        }
+        if unreadable > 0 {
+            tracing::warn!(path = %path.display(), unreadable, "Skipped unreadable coupon history lines");
+        }
+        *self.versions.write().unwrap() = versions;
+        Ok(())
+    }
+
+    async fn append(&self, recorded: &[CouponVersion]) {
+        let Some(path) = &self.path else {
+            return;
+        };
+
+        let _file = self.file.lock().await;
+        let result = async {
+            if let Some(dir) = path.parent() {
+                tokio::fs::create_dir_all(dir).await?;
+            }
+            let mut lines = String::new();
+            for version in recorded {
+                lines.push_str(&serde_json::to_string(version)?);
+                lines.push('\n');
+            }
+            let mut file = tokio::fs::OpenOptions::new().create(true).append(true).open(path).await?;
+            file.write_all(lines.as_bytes()).await?;
+            Ok::<(), Box<dyn std::error::Error + Send + Sync>>(())
+        }
+        .await;
+
+        if let Err(e) = result {
+            tracing::warn!(path = %path.display(), error = %e, "Failed to append to the coupon history");
+        }
+    }
+
+    /// Record a version of each listing whose offer differs from its latest one;
+    /// `seeding` dates codes the history has never seen by their `scraped_at`
+    pub(crate) async fn sync(&self, listings: Vec<CouponListing>, seeding: bool) {
+        let now = self.clock.now();
+        let mut recorded = Vec::new();
+        {
+            let mut versions = self.versions.write().unwrap();
+            for listing in listings {
+                let code_versions = versions
+                    .entry(listing.merchant_domain.clone())
+                    .or_default()
+                    .entry(listing.code.clone())
+                    .or_default();
+                if code_versions.last().is_some_and(|latest| same_offer(&latest.listing, &listing)) {
+                    continue;
+                }
+                let recorded_at = match seeding && code_versions.is_empty() {
+                    true => listing.scraped_at.min(now),
+                    false => now,
+                };
+                let version = CouponVersion { recorded_at, listing };
+                code_versions.push(version.clone());
+                recorded.push(version);
+            }
+        }
+        if !recorded.is_empty() {
+            self.append(&recorded).await;
+        }
+    }
+
+    /// Drop versions superseded before the retention horizon, and rewrite the file
+    /// without them
+    async fn compact(&self) {
+        let horizon = self.clock.now() - self.retention;
+        let kept = {
+            let mut versions = self.versions.write().unwrap();
+            let mut dropped = 0;
+            for code_versions in versions.values_mut().flat_map(HashMap::values_mut) {
+                // The last version from before the horizon still says how the code stood at it
+                let superseded = code_versions.iter().filter(|version| version.recorded_at <= horizon).count();
+                dropped += superseded.saturating_sub(1);
+                code_versions.drain(..superseded.saturating_sub(1));
+            }
+            if dropped == 0 {
+                return;
+            }
+            versions.values().flat_map(HashMap::values).flatten().cloned().collect::<Vec<_>>()
+        };
+
+        let Some(path) = &self.path else {
+            return;
+        };
+        let _file = self.file.lock().await;
+        let result = async {
+            let mut content = String::new();
+            for version in &kept {
+                content.push_str(&serde_json::to_string(version)?);
+                content.push('\n');
+            }
+            let staged = path.with_extension("jsonl.tmp");
+            tokio::fs::write(&staged, content).await?;
+            tokio::fs::rename(&staged, path).await?;
+            Ok::<(), Box<dyn std::error::Error + Send + Sync>>(())
+        }
+        .await;
+
+        if let Err(e) = result {
+            tracing::warn!(path = %path.display(), error = %e, "Failed to compact the coupon history");
+        }
+    }
+
+    /// `merchant`'s codes as they stood at `at`, valid ones first, then by code
+    pub fn as_of(&self, merchant: &MerchantDomain, at: DateTime<Utc>) -> Result<Vec<HistoricalCoupon>, String> {
+        let now = self.clock.now();
+        if at > now {
+            return Err("timestamp is in the future".to_string());
+        }
+        let horizon = now - self.retention;
+        if at < horizon {
+            return Err(format!("coupon history is only kept back to {}", horizon.to_rfc3339()));
+        }
+
+        let versions = self.versions.read().unwrap();
+        let mut coupons: Vec<HistoricalCoupon> = versions
+            .get(merchant)
+            .into_iter()
+            .flat_map(HashMap::values)
+            .filter_map(|code_versions| {
+                let first = code_versions.first()?;
+                let current = code_versions.iter().take_while(|version| version.recorded_at <= at).last()?;
+                Some(HistoricalCoupon {
+                    listing: current.listing.clone(),
+                    known_since: first.recorded_at,
+                    version_recorded_at: current.recorded_at,
+                    valid: current.listing.valid_until.is_none_or(|until| until > at),
+                })
+            })
+            .collect();
+        coupons.sort_by(|a, b| b.valid.cmp(&a.valid).then_with(|| a.listing.code.as_str().cmp(b.listing.code.as_str())));
+        Ok(coupons)
+    }
+
+    /// Every code's lifetime, as far as its versions tell
+    pub fn lifetimes(&self) -> Vec<CodeLifetime> {
+        let versions = self.versions.read().unwrap();
+        versions
+            .iter()
+            .flat_map(|(merchant, codes)| {
+                codes.iter().filter_map(move |(code, code_versions)| {
+                    Some(CodeLifetime {
+                        merchant: merchant.clone(),
+                        code: code.clone(),
+                        known_since: code_versions.first()?.recorded_at,
+                        valid_until: code_versions.last()?.listing.valid_until,
+                    })
+                })
+            })
+            .collect()
+    }
+
+    /// Record the store's current listings, then a version for every upsert that
+    /// changes an offer; compacts once a day
+    pub async fn start_background_tasks(self: Arc<Self>) {
+        // Subscribed before the store is read, so no upsert falls in between
+        let mut updates = self.store.subscribe();
+        self.sync(self.store.list().await, true).await;
+
+        let mut compaction = tokio::time::interval(COMPACT_INTERVAL);
+        loop {
+            tokio::select! {
+                update = updates.recv() => match update {
+                    Ok(merchant) => self.sync(self.store.for_merchant(&merchant).await, false).await,
+                    // Too many upserts to tell which merchants changed
+                    Err(RecvError::Lagged(_)) => self.sync(self.store.list().await, false).await,
+                    Err(RecvError::Closed) => return,
+                },
+                _ = compaction.tick() => self.compact().await,
+            }
+        }
+    }
+}
+
+/// Whether two listings of a code make the same offer
+fn same_offer(a: &CouponListing, b: &CouponListing) -> bool {
+    a.title == b.title
+        && a.description == b.description
+        && a.locale == b.locale
+        && a.discount_type == b.discount_type
+        && a.discount_value == b.discount_value
+        && a.source == b.source
+        && a.valid_until == b.valid_until
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::models::coupon_listing::CouponSource;
+
+    fn listing(code: &str, title: &str, scraped_at: DateTime<Utc>, valid_until: Option<DateTime<Utc>>) -> CouponListing {
+        CouponListing {
+            code: CouponCode::parse(code).unwrap(),
+            title: title.to_string(),
+            description: None,
+            locale: None,
+            merchant_domain: MerchantDomain::parse("shop.com").unwrap(),
+            discount_type: "percentage".to_string(),
+            discount_value: Some(20.0),
+            minimum_order: None,
+            source: CouponSource::WebScraping,
+            license: None,
+            extraction_confidence: 0.9,
+            scraped_at,
+            valid_until,
+            predicted_success: None,
+            tags: Vec::new(),
+        }
+    }
+
+    fn codes(coupons: &[HistoricalCoupon]) -> Vec<(&str, &str, bool)> {
+        coupons
+            .iter()
+            .map(|coupon| (coupon.listing.code.as_str(), coupon.listing.title.as_str(), coupon.valid))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_reconstructs_coupons_as_they_stood_and_reloads_from_file() {
+        let clock = Arc::new(MockClock::new());
+        let start = clock.now();
+        let hours = |h: i64| start + chrono::Duration::hours(h);
+        let merchant = MerchantDomain::parse("shop.com").unwrap();
+        let path = std::env::temp_dir().join(format!("coupon_history_{}.jsonl", uuid::Uuid::new_v4()));
+        let store = Arc::new(CouponStore::with_coupons(vec![listing("SAVE20", "20% off", hours(-48), None)]));
+        let history = CouponHistory::new(store.clone(), Some(path.clone())).with_clock(clock.clone());
+
+        // Listings present at startup date from their scrape
+        history.sync(store.list().await, true).await;
+        assert!(history.as_of(&merchant, hours(-49)).unwrap().is_empty());
+        assert_eq!(codes(&history.as_of(&merchant, hours(-1)).unwrap()), vec![("SAVE20", "20% off", true)]);
+
+        // A changed offer is a new version; a re-scrape of the same offer is not
+        clock.advance(Duration::from_secs(3600));
+        store.upsert(listing("SAVE20", "20% off shoes", hours(1), Some(hours(5)))).await;
+        store.upsert(listing("FREESHIP", "Free shipping", hours(1), None)).await;
+        history.sync(store.for_merchant(&merchant).await, false).await;
+        clock.advance(Duration::from_secs(3600));
+        store.upsert(listing("FREESHIP", "Free shipping", hours(2), None)).await;
+        history.sync(store.for_merchant(&merchant).await, false).await;
+        clock.advance(Duration::from_secs(8 * 3600));
+
+        assert_eq!(codes(&history.as_of(&merchant, hours(0)).unwrap()), vec![("SAVE20", "20% off", true)]);
+        let later = history.as_of(&merchant, hours(3)).unwrap();
+        assert_eq!(codes(&later), vec![("FREESHIP", "Free shipping", true), ("SAVE20", "20% off shoes", true)]);
+        assert_eq!((later[0].known_since, later[0].version_recorded_at), (hours(1), hours(1)));
+        assert_eq!((later[1].known_since, later[1].version_recorded_at), (hours(-48), hours(1)));
+        assert_eq!(
+            codes(&history.as_of(&merchant, hours(6)).unwrap()),
+            vec![("FREESHIP", "Free shipping", true), ("SAVE20", "20% off shoes", false)]
+        );
+        assert!(history.as_of(&merchant, hours(11)).is_err());
+
+        // The file holds the three versions
+        let reloaded = CouponHistory::new(store.clone(), Some(path.clone())).with_clock(clock.clone());
+        reloaded.load().await.unwrap();
+        assert_eq!(codes(&reloaded.as_of(&merchant, hours(6)).unwrap()), codes(&history.as_of(&merchant, hours(6)).unwrap()));
+
+        // Past the horizon only the version in force at it is kept
+        let reloaded = reloaded.with_retention(chrono::Duration::hours(8));
+        reloaded.compact().await;
+        assert!(reloaded.as_of(&merchant, hours(1)).is_err());
+        assert_eq!(codes(&reloaded.as_of(&merchant, hours(2)).unwrap())[1], ("SAVE20", "20% off shoes", true));
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 2);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,121 @@
+//! In-memory coupon catalogue
+
+use std::sync::Arc;
+
+use chrono::{Duration, Utc};
+use tokio::sync::{broadcast, Notify, RwLock};
+
+use crate::models::coupon_listing::{CouponListing, CouponSource};
+use crate::models::domain::{CouponCode, MerchantDomain};
+
+/// Upserted merchants a subscriber may fall behind by before it is told it lagged
+const UPDATE_CAPACITY: usize = 1024;
+
+pub struct CouponStore {
+    coupons: Arc<RwLock<Vec<CouponListing>>>,
+    changes: Notify,
+    updates: broadcast::Sender<MerchantDomain>,
+}
+
+impl CouponStore {
+    pub fn new() -> Self {
+        Self::with_coupons(Vec::new())
+    }
+
+    pub fn with_coupons(coupons: Vec<CouponListing>) -> Self {
+        Self {
+            coupons: Arc::new(RwLock::new(coupons)),
+            changes: Notify::new(),
+            updates: broadcast::channel(UPDATE_CAPACITY).0,
+        }
+    }
+
+    /// Create a store pre-populated with sample coupons
+    pub fn with_sample_data() -> Self {
+        let now = Utc::now();
+        let coupon = |code: &str, title: &str, domain: &str, kind: &str, value: Option<f64>, source, confidence, hours| {
+            CouponListing {
+                code: CouponCode::parse(code).expect("sample codes are valid"),
+                title: title.to_string(),
+                description: None,
+                locale: None,
+                merchant_domain: MerchantDomain::parse(domain).expect("sample domains are valid"),
+                discount_type: kind.to_string(),
+                discount_value: value,
+                minimum_order: None,
+                source,
+                license: None,
+                extraction_confidence: confidence,
+                scraped_at: now - Duration::hours(hours),
+                valid_until: None,
+                predicted_success: None,
+                tags: Vec::new(),
+            }
+        };
+
+        Self::with_coupons(vec![
+            coupon("SAVE20", "20% off sitewide", "techstore.com", "percentage", Some(20.0), CouponSource::AffiliateApi, 0.95, 6),
+            coupon("FLAT50", "$50 off orders over $250", "bestbuy.com", "fixed", Some(50.0), CouponSource::WebScraping, 0.8, 30),
+            coupon("FREESHIP", "Free shipping", "target.com", "free_shipping", None, CouponSource::PartnerApi, 0.9, 2),
+            coupon("BOOKWORM10", "10% off books", "bookstore.com", "percentage", Some(10.0), CouponSource::UserSubmitted, 0.6, 200),
+            coupon("sitewide", "Deals of the day", "walmart.com", "unknown", None, CouponSource::WebScraping, 0.3, 12),
+        ])
+    }
+
+    pub async fn list(&self) -> Vec<CouponListing> {
+        self.coupons.read().await.clone()
+    }
+
+    /// Listings of one merchant
+    pub async fn for_merchant(&self, merchant_domain: &MerchantDomain) -> Vec<CouponListing> {
+        self.coupons
+            .read()
+            .await
+            .iter()
+            .filter(|c| &c.merchant_domain == merchant_domain)
+            .cloned()
+            .collect()
+    }
+
+    /// Insert a coupon or replace the listing with the same merchant and code
+    pub async fn upsert(&self, listing: CouponListing) {
+        let merchant_domain = listing.merchant_domain.clone();
+        let mut coupons = self.coupons.write().await;
+        match coupons
+            .iter_mut()
+            .find(|c| c.merchant_domain == listing.merchant_domain && c.code == listing.code)
+        {
+            Some(existing) => *existing = listing,
+            None => coupons.push(listing),
+        }
+        drop(coupons);
+        self.changes.notify_one();
+        let _ = self.updates.send(merchant_domain);
+    }
+
+    /// Wait until a coupon has been upserted since the last call returned
+    pub async fn changed(&self) {
+        self.changes.notified().await;
+    }
+
+    /// The merchant of every upsert from now on. Unlike [`CouponStore::changed`], any
+    /// number of subscribers each see every upsert.
+    pub fn subscribe(&self) -> broadcast::Receiver<MerchantDomain> {
+        self.updates.subscribe()
+    }
+
+    pub async fn find(&self, merchant_domain: &MerchantDomain, code: &CouponCode) -> Option<CouponListing> {
+        self.coupons
+            .read()
+            .await
+            .iter()
+            .find(|c| &c.merchant_domain == merchant_domain && &c.code == code)
+            .cloned()
+    }
+}
+
+impl Default for CouponStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
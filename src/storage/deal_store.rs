@@ -0,0 +1,422 @@
+//! Deal catalogue with per-product price history
+//!
+//! [`DealStore`] is what services use. It keeps deals in a [`DealRepository`]:
+//! [`MemoryDeals`] by default, or Postgres (`storage::postgres::PgDeals`) when built
+//! with the `postgres` feature and `DATABASE_URL` is set. A repository that fails is
+//! logged and read as empty, so a database outage degrades listings rather than
+//! failing requests.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use axum::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use tokio::sync::RwLock;
+
+use crate::models::deal::{Deal, DealStatus, PricePoint};
+use crate::models::domain::{MerchantDomain, Money};
+
+pub type RepositoryResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// What the store holds for one merchant
+#[derive(Debug, Clone, PartialEq)]
+pub struct MerchantDeals {
+    /// Store name of the merchant's most recently ingested deal
+    pub store: String,
+    pub deals: usize,
+    /// When a price of one of the merchant's deals was last recorded
+    pub last_ingested_at: Option<DateTime<Utc>>,
+}
+
+/// Sum up `(merchant, store, last price recorded)` of every deal, in catalogue order
+pub(crate) fn merchant_ingests(
+    deals: impl IntoIterator<Item = (MerchantDomain, String, Option<DateTime<Utc>>)>,
+) -> HashMap<MerchantDomain, MerchantDeals> {
+    let mut merchants: HashMap<MerchantDomain, MerchantDeals> = HashMap::new();
+    for (domain, store, ingested_at) in deals {
+        let entry = merchants.entry(domain).or_insert_with(|| MerchantDeals {
+            store: store.clone(),
+            deals: 0,
+            last_ingested_at: None,
+        });
+        entry.deals += 1;
+        if ingested_at > entry.last_ingested_at {
+            entry.store = store;
+            entry.last_ingested_at = ingested_at;
+        }
+    }
+    merchants
+}
+
+/// Where [`DealStore`] keeps deals and their price history
+#[async_trait]
+pub trait DealRepository: Send + Sync {
+    /// Every deal, in the order they were first added
+    async fn list(&self) -> RepositoryResult<Vec<Deal>>;
+
+    async fn get(&self, id: &str) -> RepositoryResult<Option<Deal>>;
+
+    /// Insert a deal or replace the one with the same id, recording its price in the
+    /// product's history
+    async fn upsert(&self, deal: Deal) -> RepositoryResult<()>;
+
+    /// False if the deal is unknown
+    async fn set_status(&self, id: &str, status: DealStatus, confidence: f64) -> RepositoryResult<bool>;
+
+    /// False if the deal is unknown
+    async fn set_image_hash(&self, id: &str, hash: String) -> RepositoryResult<bool>;
+
+    /// Every listing of a product, across merchants
+    async fn for_product(&self, product_id: &str) -> RepositoryResult<Vec<Deal>>;
+
+    /// Price history for a product, oldest first
+    async fn price_history(&self, product_id: &str) -> RepositoryResult<Vec<PricePoint>>;
+
+    /// Deal count, store name and last ingest time per merchant domain
+    async fn merchant_ingests(&self) -> RepositoryResult<HashMap<MerchantDomain, MerchantDeals>>;
+}
+
+/// Deals held in memory, lost on restart
+#[derive(Default)]
+pub struct MemoryDeals {
+    deals: RwLock<Vec<Deal>>,
+    price_history: RwLock<HashMap<String, Vec<PricePoint>>>,
+}
+
+impl MemoryDeals {
+    pub fn new(deals: Vec<Deal>, price_history: HashMap<String, Vec<PricePoint>>) -> Self {
+        Self {
+            deals: RwLock::new(deals),
+            price_history: RwLock::new(price_history),
+        }
+    }
+}
+
+#[async_trait]
+impl DealRepository for MemoryDeals {
+    async fn list(&self) -> RepositoryResult<Vec<Deal>> {
+        Ok(self.deals.read().await.clone())
+    }
+
+    async fn get(&self, id: &str) -> RepositoryResult<Option<Deal>> {
+        Ok(self.deals.read().await.iter().find(|deal| deal.id == id).cloned())
+    }
+
+    async fn upsert(&self, deal: Deal) -> RepositoryResult<()> {
+        self.price_history
+            .write()
+            .await
+            .entry(deal.product_id.clone())
+            .or_default()
+            .push(PricePoint {
+                price: deal.price.amount,
+                observed_at: Utc::now(),
+            });
+
+        let mut deals = self.deals.write().await;
+        match deals.iter_mut().find(|existing| existing.id == deal.id) {
+            Some(existing) => *existing = deal,
+            None => deals.push(deal),
+        }
+        Ok(())
+    }
+
+    async fn set_status(&self, id: &str, status: DealStatus, confidence: f64) -> RepositoryResult<bool> {
+        let mut deals = self.deals.write().await;
+        match deals.iter_mut().find(|deal| deal.id == id) {
+            Some(deal) => {
+                deal.status = status;
+                deal.status_confidence = Some(confidence);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn set_image_hash(&self, id: &str, hash: String) -> RepositoryResult<bool> {
+        let mut deals = self.deals.write().await;
+        match deals.iter_mut().find(|deal| deal.id == id) {
+            Some(deal) => {
+                deal.image_hash = Some(hash);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn for_product(&self, product_id: &str) -> RepositoryResult<Vec<Deal>> {
+        Ok(self
+            .deals
+            .read()
+            .await
+            .iter()
+            .filter(|deal| deal.product_id == product_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn price_history(&self, product_id: &str) -> RepositoryResult<Vec<PricePoint>> {
+        Ok(self.price_history.read().await.get(product_id).cloned().unwrap_or_default())
+    }
+
+    async fn merchant_ingests(&self) -> RepositoryResult<HashMap<MerchantDomain, MerchantDeals>> {
+        let history = self.price_history.read().await;
+        let deals = self.deals.read().await;
+        Ok(merchant_ingests(deals.iter().map(|deal| {
+            let ingested_at = history.get(&deal.product_id).and_then(|points| points.iter().map(|p| p.observed_at).max());
+            (deal.merchant_domain.clone(), deal.store.clone(), ingested_at)
+        })))
+    }
+}
+
+pub struct DealStore {
+    repository: Arc<dyn DealRepository>,
+}
+
+impl DealStore {
+    pub fn new() -> Self {
+        Self::with_deals(Vec::new(), HashMap::new())
+    }
+
+    pub fn with_deals(deals: Vec<Deal>, price_history: HashMap<String, Vec<PricePoint>>) -> Self {
+        Self::with_repository(Arc::new(MemoryDeals::new(deals, price_history)))
+    }
+
+    pub fn with_repository(repository: Arc<dyn DealRepository>) -> Self {
+        Self { repository }
+    }
+
+    /// Deals in Postgres at `DATABASE_URL` when built with the `postgres` feature and
+    /// it is set, else the sample catalogue in memory
+    pub async fn from_env() -> Self {
+        #[cfg(feature = "postgres")]
+        if let Ok(url) = std::env::var("DATABASE_URL") {
+            match crate::storage::postgres::connect(&url).await {
+                Ok(client) => return Self::with_repository(Arc::new(crate::storage::postgres::PgDeals::new(client))),
+                Err(e) => tracing::warn!(error = %e, "Failed to connect to Postgres, serving sample deals"),
+            }
+        }
+        Self::with_sample_data()
+    }
+
+    /// Create a store pre-populated with sample deals and 90 days of price history
+    pub fn with_sample_data() -> Self {
+        let mut deals = Vec::new();
+        let mut price_history = HashMap::new();
+
+        for (i, sample) in SAMPLE_DEALS.iter().enumerate() {
+            let product_id = format!("prod_{}", i + 1);
+            let price = Money::usd(sample.price);
+            let original_price = Money::usd(sample.original_price);
+            let discount = price
+                .percent_off(original_price)
+                .and_then(|percent| percent.round().to_f64())
+                .unwrap_or(0.0);
+
+            deals.push(Deal {
+                id: format!("deal_{}", i + 1),
+                product_id: product_id.clone(),
+                title: sample.title.to_string(),
+                store: sample.store.to_string(),
+                merchant_domain: MerchantDomain::parse(sample.domain).expect("sample domains are valid"),
+                category: sample.category.to_string(),
+                brand: sample.brand.map(String::from),
+                price,
+                original_price,
+                discount,
+                image_url: None,
+                product_url: None,
+                image_hash: None,
+                free_shipping: sample.free_shipping,
+                posted_at: Utc::now() - Duration::hours(i as i64 * 5),
+                score: None,
+                honest_discount: None,
+                discount_inflated: false,
+                verified_at: None,
+                price_mismatch: false,
+                status: DealStatus::Active,
+                status_confidence: None,
+                events: Vec::new(),
+                tags: Vec::new(),
+            });
+
+            let mut history = Self::generate_sample_history(sample.typical_price);
+            if let Some(latest) = history.last_mut() {
+                latest.price = sample.price;
+            }
+            price_history.insert(product_id, history);
+        }
+
+        Self::with_deals(deals, price_history)
+    }
+
+    /// The value in `result`, or the default after logging why there is none
+    fn or_default<T: Default>(result: RepositoryResult<T>, action: &str) -> T {
+        result.unwrap_or_else(|e| {
+            tracing::error!(action, error = %e, "Deal repository failed");
+            T::default()
+        })
+    }
+
+    pub async fn list(&self) -> Vec<Deal> {
+        Self::or_default(self.repository.list().await, "list")
+    }
+
+    pub async fn get(&self, id: &str) -> Option<Deal> {
+        Self::or_default(self.repository.get(id).await, "get")
+    }
+
+    /// Insert a deal or replace the one with the same id, recording its price in the
+    /// product's history
+    pub async fn upsert(&self, deal: Deal) {
+        Self::or_default(self.repository.upsert(deal).await, "upsert")
+    }
+
+    /// Update a deal's community-derived status; returns false if the deal is unknown
+    pub async fn set_status(&self, id: &str, status: DealStatus, confidence: f64) -> bool {
+        Self::or_default(self.repository.set_status(id, status, confidence).await, "set_status")
+    }
+
+    pub async fn set_image_hash(&self, id: &str, hash: String) -> bool {
+        Self::or_default(self.repository.set_image_hash(id, hash).await, "set_image_hash")
+    }
+
+    /// Every listing of a product, across merchants
+    pub async fn for_product(&self, product_id: &str) -> Vec<Deal> {
+        Self::or_default(self.repository.for_product(product_id).await, "for_product")
+    }
+
+    /// Price history for a product, oldest first
+    pub async fn price_history(&self, product_id: &str) -> Vec<PricePoint> {
+        Self::or_default(self.repository.price_history(product_id).await, "price_history")
+    }
+
+    /// Number of listed deals per merchant domain
+    pub async fn merchant_deal_counts(&self) -> HashMap<MerchantDomain, usize> {
+        self.merchant_ingests().await.into_iter().map(|(domain, merchant)| (domain, merchant.deals)).collect()
+    }
+
+    /// Deal count, store name and last ingest time per merchant domain
+    pub async fn merchant_ingests(&self) -> HashMap<MerchantDomain, MerchantDeals> {
+        Self::or_default(self.repository.merchant_ingests().await, "merchant_ingests")
+    }
+
+    fn generate_sample_history(typical_price: Decimal) -> Vec<PricePoint> {
+        // Weekly oscillation around the typical price with a dip every 30 days
+        let now = Utc::now();
+        (0..90)
+            .rev()
+            .map(|days_ago| {
+                let day = (90 - days_ago) as f64;
+                let weekly = (day / 7.0 * std::f64::consts::TAU).sin() * 0.04;
+                let monthly_sale = if (days_ago % 30) < 3 { -0.12 } else { 0.0 };
+                let factor = Decimal::from_f64(1.0 + weekly + monthly_sale).unwrap_or(Decimal::ONE);
+                PricePoint {
+                    price: (typical_price * factor).round_dp(2),
+                    observed_at: now - Duration::days(days_ago),
+                }
+            })
+            .collect()
+    }
+}
+
+impl Default for DealStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct SampleDeal {
+    title: &'static str,
+    store: &'static str,
+    domain: &'static str,
+    category: &'static str,
+    brand: Option<&'static str>,
+    price: Decimal,
+    original_price: Decimal,
+    typical_price: Decimal,
+    free_shipping: bool,
+}
+
+const SAMPLE_DEALS: &[SampleDeal] = &[
+    SampleDeal {
+        title: "50% off Laptops",
+        store: "TechStore",
+        domain: "techstore.com",
+        category: "electronics",
+        brand: Some("Lenovo"),
+        price: dec!(499.99),
+        original_price: dec!(999.99),
+        typical_price: dec!(749.99),
+        free_shipping: false,
+    },
+    SampleDeal {
+        title: "Buy 2 Get 1 Free",
+        store: "BookStore",
+        domain: "bookstore.com",
+        category: "books",
+        brand: None,
+        price: dec!(19.99),
+        original_price: dec!(29.99),
+        typical_price: dec!(27.99),
+        free_shipping: false,
+    },
+    SampleDeal {
+        title: "65-inch OLED TV",
+        store: "Best Buy",
+        domain: "bestbuy.com",
+        category: "electronics",
+        brand: Some("LG"),
+        price: dec!(1299.99),
+        original_price: dec!(2499.99),
+        typical_price: dec!(1599.99),
+        free_shipping: true,
+    },
+    SampleDeal {
+        title: "Wireless Noise Cancelling Headphones",
+        store: "Amazon",
+        domain: "amazon.com",
+        category: "electronics",
+        brand: Some("Sony"),
+        price: dec!(278.00),
+        original_price: dec!(399.99),
+        typical_price: dec!(348.00),
+        free_shipping: true,
+    },
+    SampleDeal {
+        title: "Stand Mixer 5qt",
+        store: "Target",
+        domain: "target.com",
+        category: "kitchen",
+        brand: Some("KitchenAid"),
+        price: dec!(329.99),
+        original_price: dec!(449.99),
+        typical_price: dec!(399.99),
+        free_shipping: false,
+    },
+    SampleDeal {
+        title: "Kids Backpack Bundle",
+        store: "Walmart",
+        domain: "walmart.com",
+        category: "back_to_school",
+        brand: None,
+        price: dec!(24.97),
+        original_price: dec!(39.97),
+        typical_price: dec!(29.97),
+        free_shipping: true,
+    },
+    SampleDeal {
+        title: "Gaming Laptop RTX 4060",
+        store: "Amazon",
+        domain: "amazon.com",
+        category: "electronics",
+        brand: Some("ASUS"),
+        price: dec!(999.99),
+        original_price: dec!(1399.99),
+        typical_price: dec!(1199.99),
+        free_shipping: true,
+    },
+];
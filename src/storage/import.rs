@@ -0,0 +1,258 @@
+//! Bulk deal import from partner feeds
+//!
+//! Feeds are newline-delimited JSON, one [`Deal`] per line, and run to hundreds of
+//! megabytes, so lines are parsed and applied as the body streams in instead of
+//! buffering the whole feed. Limits apply to the decoded bytes, so a small
+//! compressed body cannot expand without bound.
+
+use axum::body::Bytes;
+use futures_util::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::models::deal::Deal;
+use crate::pricing::verification::PriceVerifier;
+use crate::storage::deal_store::DealStore;
+use crate::stream::DealStream;
+
+/// Parse errors echoed back to the partner; the rest are only counted
+const MAX_REPORTED_ERRORS: usize = 20;
+
+#[derive(Debug, Clone)]
+pub struct ImportLimits {
+    /// Decoded size of the whole feed
+    pub max_bytes: u64,
+    pub max_line_bytes: usize,
+}
+
+impl ImportLimits {
+    /// Read `IMPORT_MAX_BYTES` (default 1 GiB) and `IMPORT_MAX_LINE_BYTES` (default 1 MiB)
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            max_bytes: env_number("IMPORT_MAX_BYTES").unwrap_or(defaults.max_bytes),
+            max_line_bytes: env_number("IMPORT_MAX_LINE_BYTES").unwrap_or(defaults.max_line_bytes),
+        }
+    }
+}
+
+impl Default for ImportLimits {
+    fn default() -> Self {
+        Self {
+            max_bytes: 1 << 30,
+            max_line_bytes: 1 << 20,
+        }
+    }
+}
+
+fn env_number<T: std::str::FromStr>(name: &str) -> Option<T> {
+    std::env::var(name).ok()?.trim().parse().ok()
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct ImportReport {
+    pub imported: usize,
+    pub rejected: usize,
+    /// Imported deals kept off the stream until their price is verified
+    #[serde(default)]
+    pub held: usize,
+    /// The first few parse errors, prefixed with their line number
+    pub errors: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum ImportError {
+    /// The feed exceeded a limit; deals before that point were imported
+    TooLarge { reason: String, report: ImportReport },
+    /// The body could not be read or decoded
+    Body(String),
+}
+
+/// Apply an NDJSON deal feed to `store`, upserting valid lines and counting invalid ones.
+///
+/// Deals are checked by `verifier`, and new deals and price drops published to
+/// `stream` unless it holds them.
+pub async fn import_ndjson<S, E>(
+    store: &DealStore,
+    body: S,
+    limits: &ImportLimits,
+    stream: &DealStream,
+    verifier: &PriceVerifier,
+) -> Result<ImportReport, ImportError>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    E: std::fmt::Display,
+{
+    let mut body = body;
+    let mut report = ImportReport::default();
+    let mut buffer: Vec<u8> = Vec::new();
+    let mut total: u64 = 0;
+    let mut line_number = 0;
+
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.map_err(|e| ImportError::Body(e.to_string()))?;
+        total += chunk.len() as u64;
+        if total > limits.max_bytes {
+            return Err(ImportError::TooLarge {
+                reason: format!("feed exceeds {} bytes", limits.max_bytes),
+                report,
+            });
+        }
+
+        buffer.extend_from_slice(&chunk);
+        while let Some(end) = buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=end).collect();
+            line_number += 1;
+            import_line(store, stream, verifier, &line, line_number, &mut report).await;
+        }
+        if buffer.len() > limits.max_line_bytes {
+            return Err(ImportError::TooLarge {
+                reason: format!("line {} exceeds {} bytes", line_number + 1, limits.max_line_bytes),
+                report,
+            });
+        }
+    }
+
+    if !buffer.is_empty() {
+        import_line(store, stream, verifier, &buffer, line_number + 1, &mut report).await;
+    }
+    Ok(report)
+}
+
+async fn import_line(
+    store: &DealStore,
+    stream: &DealStream,
+    verifier: &PriceVerifier,
+    line: &[u8],
+    line_number: usize,
+    report: &mut ImportReport,
+) {
+    if line.iter().all(u8::is_ascii_whitespace) {
+        return;
+    }
+
+    match serde_json::from_slice::<Deal>(line) {
+        Ok(mut deal) => {
+            let previous = store.get(&deal.id).await;
+            verifier.verify(previous.as_ref(), &mut deal).await;
+            store.upsert(deal.clone()).await;
+            report.imported += 1;
+            if verifier.holds(&deal) {
+                report.held += 1;
+                return;
+            }
+            // A deal held until now is news once it is let through
+            let previous = previous.filter(|previous| !verifier.holds(previous));
+            stream.publish(previous.as_ref(), &deal).await;
+        }
+        Err(e) => {
+            report.rejected += 1;
+            if report.errors.len() < MAX_REPORTED_ERRORS {
+                report.errors.push(format!("line {}: {}", line_number, e));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coupon_engine::proxy_manager::ProxyConfig;
+    use crate::coupon_engine::scraper::Fetcher;
+    use crate::models::domain::Money;
+    use crate::pricing::verification::VerificationPolicy;
+    use crate::sla::SlaMonitor;
+    use crate::stream::{EventJournal, EventKind};
+    use rust_decimal_macros::dec;
+    use futures_util::stream;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn stream() -> DealStream {
+        DealStream::new(EventJournal::new(Duration::from_secs(60)), Arc::new(SlaMonitor::default()))
+    }
+
+    async fn feed() -> String {
+        let deal = DealStore::with_sample_data().list().await.remove(0);
+        let mut updated = deal.clone();
+        updated.id = "partner-1".to_string();
+        updated.title = "Partner listing".to_string();
+
+        format!(
+            "{}\n\n{{\"id\": \"broken\"}}\n{}",
+            serde_json::to_string(&deal).unwrap(),
+            serde_json::to_string(&updated).unwrap()
+        )
+    }
+
+    fn chunked(text: &str, size: usize) -> impl Stream<Item = Result<Bytes, std::convert::Infallible>> + Unpin {
+        let chunks: Vec<_> = text
+            .as_bytes()
+            .chunks(size)
+            .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+            .collect();
+        stream::iter(chunks)
+    }
+
+    #[tokio::test]
+    async fn test_imports_lines_split_across_chunks() {
+        let store = DealStore::with_sample_data();
+        let before = store.list().await.len();
+
+        let report = import_ndjson(&store, chunked(&feed().await, 7), &ImportLimits::default(), &stream(), &PriceVerifier::disabled())
+            .await
+            .unwrap();
+
+        assert_eq!((report.imported, report.rejected), (2, 1));
+        assert!(report.errors[0].starts_with("line 3:"));
+        assert_eq!(store.list().await.len(), before + 1);
+        assert_eq!(store.get("partner-1").await.unwrap().title, "Partner listing");
+    }
+
+    #[tokio::test]
+    async fn test_stops_at_size_limit() {
+        let store = DealStore::new();
+        let limits = ImportLimits {
+            max_bytes: 64,
+            ..ImportLimits::default()
+        };
+
+        let result = import_ndjson(&store, chunked(&feed().await, 32), &limits, &stream(), &PriceVerifier::disabled()).await;
+
+        assert!(matches!(result, Err(ImportError::TooLarge { .. })));
+        assert!(store.list().await.is_empty());
+    }
+
+    struct Page(&'static str);
+
+    #[axum::async_trait]
+    impl Fetcher for Page {
+        async fn fetch(&self, _url: &str, _proxy: Option<&ProxyConfig>) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+            Ok(self.0.to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_holds_too_good_deals_back_until_their_page_confirms_them() {
+        let policy = VerificationPolicy { min_price: dec!(500), too_good_discount: 70.0, hold_unverified: true };
+        let verifier = PriceVerifier::new(Arc::new(Page("<b>$99.00</b> <s>$499.00</s>")), Some(policy));
+        let (store, stream) = (DealStore::new(), stream());
+        let mut live = stream.subscribe();
+        let mut deal = DealStore::with_sample_data().list().await.remove(0);
+        (deal.price, deal.original_price, deal.discount) = (Money::usd(dec!(49)), Money::usd(dec!(499)), 90.0);
+        deal.product_url = Some("https://shop.example.com/p/1".to_string());
+
+        let feed = serde_json::to_string(&deal).unwrap();
+        let report = import_ndjson(&store, chunked(&feed, 64), &ImportLimits::default(), &stream, &verifier).await.unwrap();
+        assert_eq!((report.imported, report.held), (1, 1));
+        assert!(store.get(&deal.id).await.unwrap().price_mismatch);
+        assert!(live.try_recv().is_err());
+
+        // Corrected to the price the page shows, it is streamed as a new deal
+        (deal.price, deal.discount) = (Money::usd(dec!(99)), 80.0);
+        let feed = serde_json::to_string(&deal).unwrap();
+        let report = import_ndjson(&store, chunked(&feed, 64), &ImportLimits::default(), &stream, &verifier).await.unwrap();
+        assert_eq!(report.held, 0);
+        assert_eq!(live.try_recv().unwrap().body.kind, EventKind::NewDeal);
+    }
+}
@@ -0,0 +1,16 @@
+//! Deal and coupon catalogues, coupon history, merchant shipping rules, and where
+//! services keep their own state between restarts
+//!
+//! The coupon catalogue is in-memory and seeded with sample data for now. The deal
+//! catalogue is kept in a [`DealRepository`](deal_store::DealRepository), in memory
+//! or in Postgres. Callers should only rely on the stores' async methods, so
+//! repositories can be swapped without API changes.
+
+pub mod coupon_history;
+pub mod coupon_store;
+pub mod deal_store;
+pub mod import;
+pub(crate) mod persisted;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+pub mod shipping_rules;
@@ -0,0 +1,304 @@
+//! State that services keep between restarts
+//!
+//! A [`PersistedStore`] keeps one serializable value of a service: in memory only,
+//! in a JSON file, or in Redis under the store's key, shared by every instance
+//! pointed at the same server. Services that change one record at a time keep
+//! their records in Redis hashes instead, one field each (see
+//! [`PersistedStore::write_field`]); their file still holds the whole value.
+
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::sync::{Mutex, MutexGuard};
+
+use crate::clock::Clock;
+
+pub(crate) type StoreError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Where the value lives
+enum Backend {
+    Memory,
+    File(PathBuf),
+    /// Shared by every instance pointed at the same Redis
+    Redis(redis::Client),
+}
+
+pub(crate) struct PersistedStore<T> {
+    backend: Backend,
+    /// What is stored, for log lines, e.g. `scrape opt-outs`
+    name: &'static str,
+    /// Redis key of the value
+    key: &'static str,
+    /// Channel announcing the fields [`PersistedStore::write_field`] changes
+    channel: Option<&'static str>,
+    /// Indent the file, for state operators read or edit by hand
+    pretty: bool,
+    /// Serializes writes so the file or Redis never goes back to an older state
+    writes: Mutex<()>,
+    value: PhantomData<fn() -> T>,
+}
+
+impl<T> PersistedStore<T> {
+    fn with_backend(name: &'static str, key: &'static str, backend: Backend) -> Self {
+        Self {
+            backend,
+            name,
+            key,
+            channel: None,
+            pretty: false,
+            writes: Mutex::new(()),
+            value: PhantomData,
+        }
+    }
+
+    /// `name` persisted to `path`, or kept in memory only
+    pub fn new(name: &'static str, key: &'static str, path: Option<PathBuf>) -> Self {
+        Self::with_backend(name, key, path.map_or(Backend::Memory, Backend::File))
+    }
+
+    /// `name` shared through the Redis at `redis_url`, under `key`
+    pub fn shared(name: &'static str, key: &'static str, redis_url: &str) -> Result<Self, StoreError> {
+        Ok(Self::with_backend(name, key, Backend::Redis(redis::Client::open(redis_url)?)))
+    }
+
+    /// Shared through `REDIS_URL` when set, otherwise persisted to the file named
+    /// by `path_var` (default `default_path`)
+    pub fn from_env(name: &'static str, key: &'static str, path_var: &str, default_path: &str) -> Self {
+        if let Ok(url) = std::env::var("REDIS_URL") {
+            match Self::shared(name, key, &url) {
+                Ok(store) => return store,
+                Err(e) => tracing::warn!(store = name, error = %e, "Invalid REDIS_URL, keeping the store local"),
+            }
+        }
+        let path = std::env::var(path_var).unwrap_or_else(|_| default_path.to_string());
+        Self::new(name, key, Some(PathBuf::from(path)))
+    }
+
+    /// Announce the fields changed by [`write_field`](Self::write_field) on `channel`
+    pub fn with_change_channel(mut self, channel: &'static str) -> Self {
+        self.channel = Some(channel);
+        self
+    }
+
+    /// Write the file indented
+    pub fn pretty(mut self) -> Self {
+        self.pretty = true;
+        self
+    }
+
+    pub fn is_shared(&self) -> bool {
+        matches!(self.backend, Backend::Redis(_))
+    }
+
+    /// The Redis of a shared store, for what the service keeps beside the value
+    pub fn redis(&self) -> Option<&redis::Client> {
+        match &self.backend {
+            Backend::Redis(client) => Some(client),
+            _ => None,
+        }
+    }
+
+    /// Hold while changing the stored value
+    pub async fn write_lock(&self) -> MutexGuard<'_, ()> {
+        self.writes.lock().await
+    }
+
+    /// Call `reload` every `interval` to pick up changes made through other
+    /// instances; returns at once for a store that is not shared
+    pub async fn refresh_every<F, Fut>(&self, clock: &dyn Clock, interval: Duration, mut reload: F)
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<(), StoreError>>,
+    {
+        if !self.is_shared() {
+            return;
+        }
+        loop {
+            clock.sleep(interval).await;
+            if let Err(e) = reload().await {
+                tracing::warn!(store = self.name, error = %e, "Failed to refresh a shared store");
+            }
+        }
+    }
+
+    /// Every record in Redis hash `hash`; `None` when not shared
+    pub fn hash_values<V: DeserializeOwned>(&self, hash: &str) -> Result<Option<Vec<V>>, StoreError> {
+        Ok(self.hash_entries(hash)?.map(|entries| entries.into_values().collect()))
+    }
+
+    /// Every record in Redis hash `hash`, by field; `None` when not shared
+    pub fn hash_entries<V: DeserializeOwned>(&self, hash: &str) -> Result<Option<BTreeMap<String, V>>, StoreError> {
+        let Some(client) = self.redis() else {
+            return Ok(None);
+        };
+        let mut con = client.get_connection()?;
+        let entries: BTreeMap<String, String> = redis::cmd("HGETALL").arg(hash).query(&mut con)?;
+        let entries = entries
+            .into_iter()
+            .map(|(field, value)| Ok((field, serde_json::from_str(&value)?)))
+            .collect::<Result<_, serde_json::Error>>()?;
+        Ok(Some(entries))
+    }
+
+    /// Record `field` of Redis hash `hash`; `None` when not shared or not stored
+    pub fn hash_get<V: DeserializeOwned>(&self, hash: &str, field: &str) -> Result<Option<V>, StoreError> {
+        let Some(client) = self.redis() else {
+            return Ok(None);
+        };
+        let mut con = client.get_connection()?;
+        let value: Option<String> = redis::cmd("HGET").arg(hash).arg(field).query(&mut con)?;
+        Ok(value.map(|value| serde_json::from_str(&value)).transpose()?)
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> PersistedStore<T> {
+    /// The stored value; `None` in memory only, or when nothing was stored yet
+    pub async fn load(&self) -> Result<Option<T>, StoreError> {
+        let content = match &self.backend {
+            Backend::Memory => return Ok(None),
+            Backend::File(path) => match tokio::fs::read_to_string(path).await {
+                Ok(content) => content,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+                Err(e) => return Err(e.into()),
+            },
+            Backend::Redis(client) => {
+                let mut con = client.get_connection()?;
+                let content: Option<String> = redis::cmd("GET").arg(self.key).query(&mut con)?;
+                match content {
+                    Some(content) => content,
+                    None => return Ok(None),
+                }
+            }
+        };
+        Ok(Some(serde_json::from_str(&content)?))
+    }
+
+    /// A map whose records are kept one field of Redis hash `hash` each when shared,
+    /// otherwise the stored value; `None` when nothing was stored yet
+    pub async fn load_map<K, V>(&self, hash: &str) -> Result<Option<T>, StoreError>
+    where
+        T: FromIterator<(K, V)>,
+        K: DeserializeOwned,
+        V: DeserializeOwned,
+    {
+        let Some(entries) = self.hash_entries::<V>(hash)? else {
+            return self.load().await;
+        };
+        let map = entries
+            .into_iter()
+            .map(|(field, record)| Ok((serde_json::from_value(serde_json::Value::String(field))?, record)))
+            .collect::<Result<T, serde_json::Error>>()?;
+        Ok(Some(map))
+    }
+
+    /// Store `value`, replacing what was stored
+    pub async fn save(&self, value: &T) -> Result<(), StoreError> {
+        match &self.backend {
+            Backend::Memory => Ok(()),
+            Backend::File(path) => {
+                if let Some(dir) = path.parent() {
+                    tokio::fs::create_dir_all(dir).await?;
+                }
+                let content = match self.pretty {
+                    true => serde_json::to_string_pretty(value)?,
+                    false => serde_json::to_string(value)?,
+                };
+                tokio::fs::write(path, content).await?;
+                Ok(())
+            }
+            Backend::Redis(client) => {
+                let mut con = client.get_connection()?;
+                redis::cmd("SET").arg(self.key).arg(serde_json::to_string(value)?).query::<()>(&mut con)?;
+                Ok(())
+            }
+        }
+    }
+
+    /// [`save`](Self::save), logging a failure instead of returning it
+    pub async fn persist(&self, value: &T) {
+        if let Err(e) = self.save(value).await {
+            tracing::warn!(store = self.name, error = %e, "Failed to persist a store");
+        }
+    }
+
+    /// Set `field` of Redis hash `hash` to `record`, or delete it, when shared;
+    /// otherwise store `whole()`, the value with the change applied
+    pub async fn write_field<V: Serialize>(
+        &self,
+        hash: &str,
+        field: &str,
+        record: Option<&V>,
+        whole: impl FnOnce() -> T,
+    ) -> Result<(), StoreError> {
+        self.write_fields(hash, &[(field, record)], whole).await
+    }
+
+    /// [`write_field`](Self::write_field) for several fields, in one round trip to
+    /// Redis or one save
+    pub async fn write_fields<V: Serialize>(
+        &self,
+        hash: &str,
+        records: &[(&str, Option<&V>)],
+        whole: impl FnOnce() -> T,
+    ) -> Result<(), StoreError> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        let Some(client) = self.redis() else {
+            return self.save(&whole()).await;
+        };
+        let mut pipe = redis::pipe();
+        for (field, record) in records {
+            match record {
+                Some(record) => pipe.hset(hash, *field, serde_json::to_string(record)?).ignore(),
+                None => pipe.hdel(hash, *field).ignore(),
+            };
+            if let Some(channel) = self.channel {
+                pipe.publish(channel, *field).ignore();
+            }
+        }
+        let mut con = client.get_connection()?;
+        pipe.query::<()>(&mut con)?;
+        Ok(())
+    }
+
+    /// [`write_field`](Self::write_field), logging a failure instead of returning it
+    pub async fn persist_field<V: Serialize>(&self, hash: &str, field: &str, record: Option<&V>, whole: impl FnOnce() -> T) {
+        if let Err(e) = self.write_field(hash, field, record, whole).await {
+            tracing::warn!(store = self.name, field, error = %e, "Failed to persist a record");
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_file_store_round_trips_and_starts_empty() {
+        let path = std::env::temp_dir().join(format!("persisted_{}", uuid::Uuid::new_v4())).join("state.json");
+        let store: PersistedStore<Vec<u32>> = PersistedStore::new("test state", "test_state", Some(path.clone()));
+        assert_eq!(store.load().await.unwrap(), None);
+
+        store.save(&vec![1, 2]).await.unwrap();
+        assert_eq!(store.load().await.unwrap(), Some(vec![1, 2]));
+        // Not shared, so the field goes into the whole value
+        store.write_field("test_state", "3", Some(&3), || vec![1, 2, 3]).await.unwrap();
+        assert_eq!(store.load().await.unwrap(), Some(vec![1, 2, 3]));
+        assert_eq!(store.hash_values::<u32>("test_state").unwrap(), None);
+
+        let map: PersistedStore<BTreeMap<String, u32>> = PersistedStore::new("test map", "test_map", Some(path.with_file_name("map.json")));
+        map.write_field("test_map", "a", Some(&1), || BTreeMap::from([("a".to_string(), 1)])).await.unwrap();
+        assert_eq!(map.load_map("test_map").await.unwrap(), Some(BTreeMap::from([("a".to_string(), 1)])));
+
+        let memory: PersistedStore<Vec<u32>> = PersistedStore::new("test state", "test_state", None);
+        memory.save(&vec![1]).await.unwrap();
+        assert_eq!(memory.load().await.unwrap(), None);
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+}
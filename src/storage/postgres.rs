@@ -0,0 +1,485 @@
+//! Batched coupon upserts into Postgres, and the deal catalogue
+//!
+//! Writing listings one `INSERT` at a time costs a round trip each and tops out at a
+//! few thousand per second. [`upsert_batch`] instead sends a whole batch as one
+//! `INSERT ... SELECT FROM UNNEST(...) ON CONFLICT DO UPDATE` with one array
+//! parameter per column. [`CouponWriter`] collects listings into such batches in the
+//! background. Its queue is bounded, so producers wait while Postgres falls behind
+//! instead of buffering without limit.
+//!
+//! Listings are keyed by merchant domain and code. Codes compare case-insensitively,
+//! as [`CouponCode`](crate::models::domain::CouponCode) does.
+//!
+//! [`PgDeals`] keeps deals for [`DealStore`](crate::storage::deal_store::DealStore):
+//! each as JSON in the `deals` table, with its price history in `deal_prices`.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_postgres::{Client, NoTls, Row};
+
+use crate::models::coupon_listing::{CouponListing, CouponSource, License};
+use crate::models::deal::{Deal, DealStatus, PricePoint};
+use crate::models::domain::MerchantDomain;
+use crate::storage::deal_store::{merchant_ingests, DealRepository, MerchantDeals, RepositoryResult};
+
+/// Creates the `coupon_listings` table [`upsert_batch`] writes to, and the tables of
+/// [`PgDeals`]
+pub const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS coupon_listings (
+    merchant_domain TEXT NOT NULL,
+    code_key TEXT NOT NULL,
+    code TEXT NOT NULL,
+    title TEXT NOT NULL,
+    description TEXT,
+    locale TEXT,
+    discount_type TEXT NOT NULL,
+    discount_value DOUBLE PRECISION,
+    source TEXT NOT NULL,
+    extraction_confidence DOUBLE PRECISION NOT NULL,
+    scraped_at TIMESTAMPTZ NOT NULL,
+    valid_until TIMESTAMPTZ,
+    predicted_success DOUBLE PRECISION,
+    minimum_order NUMERIC,
+    license TEXT,
+    attribution TEXT,
+    PRIMARY KEY (merchant_domain, code_key)
+);
+ALTER TABLE coupon_listings ADD COLUMN IF NOT EXISTS minimum_order NUMERIC;
+ALTER TABLE coupon_listings ADD COLUMN IF NOT EXISTS license TEXT;
+ALTER TABLE coupon_listings ADD COLUMN IF NOT EXISTS attribution TEXT;
+CREATE TABLE IF NOT EXISTS deals (
+    id TEXT PRIMARY KEY,
+    -- Keeps listings in the order deals were first added
+    position BIGSERIAL,
+    product_id TEXT NOT NULL,
+    deal JSONB NOT NULL
+);
+CREATE INDEX IF NOT EXISTS deals_product_id ON deals (product_id);
+CREATE TABLE IF NOT EXISTS deal_prices (
+    product_id TEXT NOT NULL,
+    price NUMERIC NOT NULL,
+    observed_at TIMESTAMPTZ NOT NULL
+);
+CREATE INDEX IF NOT EXISTS deal_prices_product_id ON deal_prices (product_id, observed_at)";
+
+const UPSERT: &str = "
+INSERT INTO coupon_listings (
+    merchant_domain, code_key, code, title, description, locale, discount_type, discount_value,
+    source, extraction_confidence, scraped_at, valid_until, predicted_success, minimum_order,
+    license, attribution
+)
+SELECT * FROM UNNEST(
+    $1::TEXT[], $2::TEXT[], $3::TEXT[], $4::TEXT[], $5::TEXT[], $6::TEXT[], $7::TEXT[],
+    $8::DOUBLE PRECISION[], $9::TEXT[], $10::DOUBLE PRECISION[], $11::TIMESTAMPTZ[],
+    $12::TIMESTAMPTZ[], $13::DOUBLE PRECISION[], $14::TEXT[]::NUMERIC[], $15::TEXT[],
+    $16::TEXT[]
+)
+ON CONFLICT (merchant_domain, code_key) DO UPDATE SET
+    code = EXCLUDED.code,
+    title = EXCLUDED.title,
+    description = EXCLUDED.description,
+    locale = EXCLUDED.locale,
+    discount_type = EXCLUDED.discount_type,
+    discount_value = EXCLUDED.discount_value,
+    source = EXCLUDED.source,
+    extraction_confidence = EXCLUDED.extraction_confidence,
+    scraped_at = EXCLUDED.scraped_at,
+    valid_until = EXCLUDED.valid_until,
+    predicted_success = EXCLUDED.predicted_success,
+    minimum_order = EXCLUDED.minimum_order,
+    license = EXCLUDED.license,
+    attribution = EXCLUDED.attribution";
+
+/// Batching for [`CouponWriter`]
+#[derive(Debug, Clone)]
+pub struct BatchConfig {
+    /// Listings per `INSERT`
+    pub batch_size: usize,
+    /// Listings that may wait for the batch being written before `write` blocks
+    pub queue_capacity: usize,
+    /// How long a partial batch waits for more listings before it is written anyway
+    pub flush_interval: Duration,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            batch_size: 5_000,
+            queue_capacity: 20_000,
+            flush_interval: Duration::from_millis(500),
+        }
+    }
+}
+
+impl BatchConfig {
+    /// `COUPON_WRITE_BATCH_SIZE`, `COUPON_WRITE_QUEUE_CAPACITY` and
+    /// `COUPON_WRITE_FLUSH_MS`, each falling back to the default
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        let var = |name: &str| std::env::var(name).ok().and_then(|value| value.parse::<u64>().ok()).filter(|value| *value > 0);
+        Self {
+            batch_size: var("COUPON_WRITE_BATCH_SIZE").map_or(default.batch_size, |value| value as usize),
+            queue_capacity: var("COUPON_WRITE_QUEUE_CAPACITY").map_or(default.queue_capacity, |value| value as usize),
+            flush_interval: var("COUPON_WRITE_FLUSH_MS").map_or(default.flush_interval, Duration::from_millis),
+        }
+    }
+}
+
+/// Connect to `url` (e.g. `DATABASE_URL`) and create the schema if needed
+pub async fn connect(url: &str) -> Result<Client, tokio_postgres::Error> {
+    let (client, connection) = tokio_postgres::connect(url, NoTls).await?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            tracing::error!(error = %e, "Postgres connection closed");
+        }
+    });
+    client.batch_execute(SCHEMA).await?;
+    Ok(client)
+}
+
+/// Insert or replace `listings` in one statement, returning the rows written. When
+/// the batch holds the same merchant and code more than once, the last listing wins.
+pub async fn upsert_batch(client: &Client, listings: &[CouponListing]) -> Result<u64, tokio_postgres::Error> {
+    // ON CONFLICT cannot update the same row twice in one statement
+    let mut latest: HashMap<(&str, String), usize> = HashMap::with_capacity(listings.len());
+    for (i, listing) in listings.iter().enumerate() {
+        latest.insert((listing.merchant_domain.as_str(), listing.code.as_str().to_ascii_lowercase()), i);
+    }
+    let mut rows: Vec<(String, &CouponListing)> = latest.into_iter().map(|((_, key), i)| (key, &listings[i])).collect();
+    // A stable row order keeps concurrent batches from deadlocking on each other's rows
+    rows.sort_unstable_by(|a, b| (a.1.merchant_domain.as_str(), &a.0).cmp(&(b.1.merchant_domain.as_str(), &b.0)));
+
+    let column = |f: for<'r> fn(&'r (String, &'r CouponListing)) -> Option<&'r str>| rows.iter().map(f).collect::<Vec<_>>();
+    let domains = column(|(_, l)| Some(l.merchant_domain.as_str()));
+    let keys = column(|(key, _)| Some(key.as_str()));
+    let codes = column(|(_, l)| Some(l.code.as_str()));
+    let titles = column(|(_, l)| Some(l.title.as_str()));
+    let descriptions = column(|(_, l)| l.description.as_deref());
+    let locales = column(|(_, l)| l.locale.as_deref());
+    let discount_types = column(|(_, l)| Some(l.discount_type.as_str()));
+    let sources = column(|(_, l)| Some(source_name(l.source)));
+    let licenses = column(|(_, l)| l.license.as_ref().map(|tag| license_name(tag.license)));
+    let attributions = column(|(_, l)| l.license.as_ref().and_then(|tag| tag.attribution.as_deref()));
+    let discount_values: Vec<Option<f64>> = rows.iter().map(|(_, l)| l.discount_value).collect();
+    let confidences: Vec<f64> = rows.iter().map(|(_, l)| l.extraction_confidence).collect();
+    let scraped_at: Vec<DateTime<Utc>> = rows.iter().map(|(_, l)| l.scraped_at).collect();
+    let valid_until: Vec<Option<DateTime<Utc>>> = rows.iter().map(|(_, l)| l.valid_until).collect();
+    let predicted: Vec<Option<f64>> = rows.iter().map(|(_, l)| l.predicted_success).collect();
+    // Sent as text, so the driver needs no decimal support
+    let minimum_orders: Vec<Option<String>> = rows.iter().map(|(_, l)| l.minimum_order.map(|v| v.to_string())).collect();
+
+    client
+        .execute(
+            UPSERT,
+            &[
+                &domains,
+                &keys,
+                &codes,
+                &titles,
+                &descriptions,
+                &locales,
+                &discount_types,
+                &discount_values,
+                &sources,
+                &confidences,
+                &scraped_at,
+                &valid_until,
+                &predicted,
+                &minimum_orders,
+                &licenses,
+                &attributions,
+            ],
+        )
+        .await
+}
+
+fn license_name(license: License) -> &'static str {
+    match license {
+        License::Open => "open",
+        License::Attribution => "attribution",
+        License::Restricted => "restricted",
+    }
+}
+
+fn source_name(source: CouponSource) -> &'static str {
+    match source {
+        CouponSource::AffiliateApi => "affiliate_api",
+        CouponSource::PartnerApi => "partner_api",
+        CouponSource::WebScraping => "web_scraping",
+        CouponSource::UserSubmitted => "user_submitted",
+    }
+}
+
+/// What a [`CouponWriter`] wrote
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WriteStats {
+    pub batches: usize,
+    /// Rows inserted or updated
+    pub written: u64,
+    /// Listings in batches that failed
+    pub failed: usize,
+}
+
+/// Writes listings to Postgres in batches from a background task
+pub struct CouponWriter {
+    queue: mpsc::Sender<CouponListing>,
+    task: JoinHandle<WriteStats>,
+}
+
+impl CouponWriter {
+    pub fn spawn(client: Client, config: BatchConfig) -> Self {
+        let (queue, listings) = mpsc::channel(config.queue_capacity.max(1));
+        let task = tokio::spawn(write_batches(client, config, listings));
+        Self { queue, task }
+    }
+
+    /// Queue a listing, waiting while the queue is full
+    pub async fn write(&self, listing: CouponListing) -> Result<(), String> {
+        self.queue.send(listing).await.map_err(|_| "coupon writer has stopped".to_string())
+    }
+
+    /// Write what is still queued and stop
+    pub async fn finish(self) -> Result<WriteStats, String> {
+        drop(self.queue);
+        self.task.await.map_err(|e| format!("coupon writer failed: {}", e))
+    }
+}
+
+async fn write_batches(client: Client, config: BatchConfig, mut listings: mpsc::Receiver<CouponListing>) -> WriteStats {
+    let batch_size = config.batch_size.max(1);
+    let mut stats = WriteStats::default();
+    let mut batch = Vec::with_capacity(batch_size);
+    // Start a batch with the next listing, then fill it for up to `flush_interval`
+    while listings.recv_many(&mut batch, batch_size).await > 0 {
+        let deadline = tokio::time::Instant::now() + config.flush_interval;
+        while batch.len() < batch_size {
+            let room = batch_size - batch.len();
+            match tokio::time::timeout_at(deadline, listings.recv_many(&mut batch, room)).await {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {}
+            }
+        }
+
+        stats.batches += 1;
+        match upsert_batch(&client, &batch).await {
+            Ok(written) => stats.written += written,
+            Err(e) => {
+                tracing::error!(coupons = batch.len(), error = %e, "Failed to write coupons to Postgres");
+                stats.failed += batch.len();
+            }
+        }
+        batch.clear();
+    }
+    stats
+}
+
+/// Deals in Postgres, see the module docs. JSON and decimals are sent as text, so
+/// the driver needs no serde or decimal support.
+pub struct PgDeals {
+    client: Client,
+}
+
+impl PgDeals {
+    /// `client` must have the schema, as [`connect`] creates it
+    pub fn new(client: Client) -> Self {
+        Self { client }
+    }
+
+    fn deals(rows: Vec<Row>) -> RepositoryResult<Vec<Deal>> {
+        rows.iter().map(|row| Ok(serde_json::from_str(row.try_get(0)?)?)).collect()
+    }
+
+    /// Merge `fields` into a deal's JSON in one statement; false if the deal is unknown
+    async fn merge(&self, id: &str, fields: serde_json::Value) -> RepositoryResult<bool> {
+        let updated = self
+            .client
+            .execute("UPDATE deals SET deal = deal || $2::TEXT::JSONB WHERE id = $1", &[&id, &fields.to_string()])
+            .await?;
+        Ok(updated > 0)
+    }
+}
+
+#[async_trait]
+impl DealRepository for PgDeals {
+    async fn list(&self) -> RepositoryResult<Vec<Deal>> {
+        Self::deals(self.client.query("SELECT deal::TEXT FROM deals ORDER BY position", &[]).await?)
+    }
+
+    async fn get(&self, id: &str) -> RepositoryResult<Option<Deal>> {
+        Ok(Self::deals(self.client.query("SELECT deal::TEXT FROM deals WHERE id = $1", &[&id]).await?)?.pop())
+    }
+
+    async fn upsert(&self, deal: Deal) -> RepositoryResult<()> {
+        let json = serde_json::to_string(&deal)?;
+        let price = deal.price.amount.to_string();
+        self.client
+            .execute(
+                "WITH price AS (
+                    INSERT INTO deal_prices (product_id, price, observed_at) VALUES ($2, $4::TEXT::NUMERIC, $5)
+                )
+                INSERT INTO deals (id, product_id, deal) VALUES ($1, $2, $3::TEXT::JSONB)
+                ON CONFLICT (id) DO UPDATE SET product_id = EXCLUDED.product_id, deal = EXCLUDED.deal",
+                &[&deal.id, &deal.product_id, &json, &price, &Utc::now()],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn set_status(&self, id: &str, status: DealStatus, confidence: f64) -> RepositoryResult<bool> {
+        self.merge(id, serde_json::json!({"status": status, "status_confidence": confidence})).await
+    }
+
+    async fn set_image_hash(&self, id: &str, hash: String) -> RepositoryResult<bool> {
+        self.merge(id, serde_json::json!({"image_hash": hash})).await
+    }
+
+    async fn for_product(&self, product_id: &str) -> RepositoryResult<Vec<Deal>> {
+        Self::deals(
+            self.client
+                .query("SELECT deal::TEXT FROM deals WHERE product_id = $1 ORDER BY position", &[&product_id])
+                .await?,
+        )
+    }
+
+    async fn price_history(&self, product_id: &str) -> RepositoryResult<Vec<PricePoint>> {
+        let rows = self
+            .client
+            .query(
+                "SELECT price::TEXT, observed_at FROM deal_prices WHERE product_id = $1 ORDER BY observed_at",
+                &[&product_id],
+            )
+            .await?;
+        rows.iter()
+            .map(|row| {
+                Ok(PricePoint {
+                    price: row.try_get::<_, &str>(0)?.parse()?,
+                    observed_at: row.try_get(1)?,
+                })
+            })
+            .collect()
+    }
+
+    async fn merchant_ingests(&self) -> RepositoryResult<HashMap<MerchantDomain, MerchantDeals>> {
+        let rows = self
+            .client
+            .query(
+                "SELECT deal->>'merchant_domain', deal->>'store', prices.last
+                FROM deals
+                LEFT JOIN (SELECT product_id, max(observed_at) AS last FROM deal_prices GROUP BY product_id) prices
+                    USING (product_id)
+                ORDER BY position",
+                &[],
+            )
+            .await?;
+        let deals = rows
+            .iter()
+            .map(|row| Ok((MerchantDomain::parse(row.try_get(0)?)?, row.try_get(1)?, row.try_get(2)?)))
+            .collect::<RepositoryResult<Vec<_>>>()?;
+        Ok(merchant_ingests(deals))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::domain::{CouponCode, MerchantDomain};
+
+    fn listing(domain: &str, code: &str, title: &str) -> CouponListing {
+        CouponListing {
+            code: CouponCode::parse(code).unwrap(),
+            title: title.to_string(),
+            description: None,
+            locale: None,
+            merchant_domain: MerchantDomain::parse(domain).unwrap(),
+            discount_type: "percentage".to_string(),
+            discount_value: Some(10.0),
+            minimum_order: None,
+            source: CouponSource::WebScraping,
+            license: None,
+            extraction_confidence: 0.8,
+            scraped_at: Utc::now(),
+            valid_until: None,
+            predicted_success: None,
+            tags: Vec::new(),
+        }
+    }
+
+    /// Runs against `DATABASE_URL`, and passes without checking anything when it is unset
+    #[tokio::test]
+    async fn test_writer_batches_and_upserts_by_merchant_and_code() {
+        let Ok(url) = std::env::var("DATABASE_URL") else {
+            eprintln!("DATABASE_URL is not set, skipping the Postgres writer test");
+            return;
+        };
+        let client = connect(&url).await.unwrap();
+        let domain = format!("{}.writer-test.example", uuid::Uuid::new_v4().simple());
+        let config = BatchConfig {
+            batch_size: 1_000,
+            queue_capacity: 10,
+            flush_interval: Duration::from_millis(50),
+        };
+
+        let writer = CouponWriter::spawn(client, config);
+        for i in 0..2_500 {
+            writer.write(listing(&domain, &format!("save{}", i), "first")).await.unwrap();
+        }
+        writer.write(listing(&domain, "SAVE7", "second")).await.unwrap();
+        let stats = writer.finish().await.unwrap();
+        assert_eq!(stats.failed, 0);
+        assert!(stats.batches >= 3);
+        assert_eq!(stats.written, 2_501);
+
+        let client = connect(&url).await.unwrap();
+        let rows = client
+            .query("SELECT code, title FROM coupon_listings WHERE merchant_domain = $1 AND code_key = 'save7'", &[&domain])
+            .await
+            .unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].get::<_, &str>(0), "SAVE7");
+        assert_eq!(rows[0].get::<_, &str>(1), "second");
+        let count: i64 = client
+            .query_one("SELECT count(*) FROM coupon_listings WHERE merchant_domain = $1", &[&domain])
+            .await
+            .unwrap()
+            .get(0);
+        assert_eq!(count, 2_500);
+        client.execute("DELETE FROM coupon_listings WHERE merchant_domain = $1", &[&domain]).await.unwrap();
+    }
+
+    /// Runs against `DATABASE_URL`, and passes without checking anything when it is unset
+    #[tokio::test]
+    async fn test_deals_round_trip_with_their_price_history() {
+        let Ok(url) = std::env::var("DATABASE_URL") else {
+            eprintln!("DATABASE_URL is not set, skipping the Postgres deals test");
+            return;
+        };
+        let deals = PgDeals::new(connect(&url).await.unwrap());
+        let run = uuid::Uuid::new_v4().simple().to_string();
+        let mut deal = crate::storage::deal_store::DealStore::with_sample_data().list().await.remove(0);
+        deal.id = format!("deal-{}", run);
+        deal.product_id = format!("product-{}", run);
+
+        deals.upsert(deal.clone()).await.unwrap();
+        deal.price.amount -= rust_decimal::Decimal::ONE;
+        deals.upsert(deal.clone()).await.unwrap();
+        assert!(deals.set_status(&deal.id, DealStatus::Dead, 0.9).await.unwrap());
+        assert!(!deals.set_status("no-such-deal", DealStatus::Dead, 0.9).await.unwrap());
+
+        let stored = deals.get(&deal.id).await.unwrap().unwrap();
+        assert_eq!(stored.price, deal.price);
+        assert_eq!((stored.status, stored.status_confidence), (DealStatus::Dead, Some(0.9)));
+        assert_eq!(deals.for_product(&deal.product_id).await.unwrap().len(), 1);
+        let history = deals.price_history(&deal.product_id).await.unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[1].price, deal.price.amount);
+        assert!(deals.merchant_ingests().await.unwrap()[&deal.merchant_domain].last_ingested_at.is_some());
+
+        deals.client.execute("DELETE FROM deals WHERE id = $1", &[&deal.id]).await.unwrap();
+        deals.client.execute("DELETE FROM deal_prices WHERE product_id = $1", &[&deal.product_id]).await.unwrap();
+    }
+}
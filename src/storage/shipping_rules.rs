@@ -0,0 +1,135 @@
+//! Per-merchant shipping rules
+//!
+//! With `REDIS_URL` set the rules are shared and re-read every
+//! [`REFRESH_INTERVAL`]; otherwise they are persisted to `SHIPPING_RULES_PATH`
+//! (default `data/shipping_rules.json`).
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+use utoipa::ToSchema;
+
+use crate::clock::{self, Clock};
+use crate::models::domain::{Currency, MerchantDomain};
+use crate::storage::persisted::{PersistedStore, StoreError};
+
+const REDIS_KEY: &str = "shipping_rules";
+const STORE_NAME: &str = "shipping rules";
+/// How often shared rules are re-read
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How a merchant charges for standard delivery
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct ShippingRule {
+    pub currency: Currency,
+    /// Standard delivery cost when nothing makes it free
+    pub base_cost: Decimal,
+    /// Orders at or above this price ship free
+    #[serde(default)]
+    pub free_over: Option<Decimal>,
+    /// Membership that ships free, e.g. `prime`
+    #[serde(default)]
+    pub free_with_membership: Option<String>,
+    /// Extra charge by region code, e.g. `{"HI": "15.00"}`; charged even when shipping is free
+    #[serde(default)]
+    pub regional_surcharges: BTreeMap<String, Decimal>,
+}
+
+impl ShippingRule {
+    pub fn validate(&self) -> Result<(), String> {
+        let negative = |amount: &Decimal| amount.is_sign_negative();
+        if negative(&self.base_cost)
+            || self.free_over.as_ref().is_some_and(negative)
+            || self.regional_surcharges.values().any(negative)
+        {
+            return Err("shipping amounts must not be negative".to_string());
+        }
+        Ok(())
+    }
+}
+
+pub struct ShippingRuleStore {
+    rules: Arc<RwLock<HashMap<MerchantDomain, ShippingRule>>>,
+    /// Every rule in the file, one field of the Redis hash each
+    store: PersistedStore<HashMap<MerchantDomain, ShippingRule>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl ShippingRuleStore {
+    /// Rules persisted to `path`, or kept in memory only
+    pub fn new(path: Option<PathBuf>) -> Self {
+        Self::with_store(PersistedStore::new(STORE_NAME, REDIS_KEY, path))
+    }
+
+    /// Rules shared through Redis
+    pub fn shared(redis_url: &str) -> Result<Self, StoreError> {
+        Ok(Self::with_store(PersistedStore::shared(STORE_NAME, REDIS_KEY, redis_url)?))
+    }
+
+    fn with_store(store: PersistedStore<HashMap<MerchantDomain, ShippingRule>>) -> Self {
+        Self {
+            rules: Arc::new(RwLock::new(HashMap::new())),
+            store,
+            clock: clock::system(),
+        }
+    }
+
+    /// Share rules through `REDIS_URL` when set, otherwise load them from
+    /// `SHIPPING_RULES_PATH` (default `data/shipping_rules.json`)
+    pub async fn from_env() -> Self {
+        let store = Self::with_store(PersistedStore::from_env(STORE_NAME, REDIS_KEY, "SHIPPING_RULES_PATH", "data/shipping_rules.json"));
+        if let Err(e) = store.reload().await {
+            tracing::warn!(error = %e, "Starting without shipping rules");
+        }
+        store
+    }
+
+    /// Replace the local copy with every stored rule
+    pub async fn reload(&self) -> Result<(), StoreError> {
+        if let Some(stored) = self.store.load_map(REDIS_KEY).await? {
+            *self.rules.write().await = stored;
+        }
+        Ok(())
+    }
+
+    /// Re-read shared rules every [`REFRESH_INTERVAL`], picking up changes made
+    /// through other instances
+    pub async fn start_background_tasks(self: Arc<Self>) {
+        self.store.refresh_every(self.clock.as_ref(), REFRESH_INTERVAL, || self.reload()).await
+    }
+
+    /// Every merchant's rule, by domain
+    pub async fn list(&self) -> BTreeMap<MerchantDomain, ShippingRule> {
+        self.rules.read().await.iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+    }
+
+    pub async fn get(&self, merchant: &MerchantDomain) -> Option<ShippingRule> {
+        self.rules.read().await.get(merchant).cloned()
+    }
+
+    pub async fn put(&self, merchant: MerchantDomain, mut rule: ShippingRule) {
+        rule.regional_surcharges = rule
+            .regional_surcharges
+            .into_iter()
+            .map(|(region, surcharge)| (region.trim().to_ascii_uppercase(), surcharge))
+            .collect();
+        let mut rules = self.rules.write().await;
+        rules.insert(merchant.clone(), rule.clone());
+        self.store.persist_field(REDIS_KEY, merchant.as_str(), Some(&rule), || rules.clone()).await;
+    }
+
+    /// Returns false if the merchant had no rule
+    pub async fn delete(&self, merchant: &MerchantDomain) -> bool {
+        let mut rules = self.rules.write().await;
+        let removed = rules.remove(merchant).is_some();
+        if removed {
+            self.store.persist_field(REDIS_KEY, merchant.as_str(), None::<&ShippingRule>, || rules.clone()).await;
+        }
+        removed
+    }
+}
@@ -0,0 +1,295 @@
+//! Replayable journal of deal stream events
+//!
+//! Events are kept for a retention window (`STREAM_RETENTION_SECS`, default one hour)
+//! so a client that reconnects with the ID of the last event it saw gets what it
+//! missed. The journal is a Redis Stream when `REDIS_URL` is set, shared by every
+//! instance, and an in-memory log otherwise.
+//!
+//! Event IDs are Redis Stream IDs, `<milliseconds>-<sequence>`, in both cases. Since
+//! they start with a timestamp, a client whose last event is older than the window
+//! may have missed events that were trimmed; its replay is marked [`Replay::gap`].
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use super::{EventBody, StreamEvent};
+use crate::clock::{self, Clock};
+
+const REDIS_KEY: &str = "deal_stream";
+/// Events replayed to one reconnecting client at most; beyond that it should resync
+pub const MAX_REPLAY: usize = 1_000;
+
+/// A Redis Stream ID; ordered by time, then sequence
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct EventId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl EventId {
+    fn next(self) -> Self {
+        Self {
+            ms: self.ms,
+            seq: self.seq + 1,
+        }
+    }
+}
+
+impl fmt::Display for EventId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+impl FromStr for EventId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (ms, seq) = s.trim().split_once('-').unwrap_or((s.trim(), "0"));
+        match (ms.parse(), seq.parse()) {
+            (Ok(ms), Ok(seq)) => Ok(Self { ms, seq }),
+            _ => Err(format!("'{}' is not a stream event ID", s)),
+        }
+    }
+}
+
+impl TryFrom<String> for EventId {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<EventId> for String {
+    fn from(id: EventId) -> Self {
+        id.to_string()
+    }
+}
+
+/// Events after a client's last seen event
+#[derive(Debug, Default)]
+pub struct Replay {
+    pub events: Vec<StreamEvent>,
+    /// Events may be missing: they were trimmed, or there were too many to replay
+    pub gap: bool,
+}
+
+enum JournalStore {
+    Memory,
+    Redis(redis::Client),
+}
+
+pub struct EventJournal {
+    store: JournalStore,
+    retention: Duration,
+    /// The in-memory log; also the fallback while Redis is unavailable
+    memory: Mutex<VecDeque<StreamEvent>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl EventJournal {
+    /// Journal kept in memory for `retention`
+    pub fn new(retention: Duration) -> Self {
+        Self::with_store(JournalStore::Memory, retention)
+    }
+
+    /// Journal shared through a Redis Stream
+    pub fn shared(redis_url: &str, retention: Duration) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(Self::with_store(JournalStore::Redis(redis::Client::open(redis_url)?), retention))
+    }
+
+    fn with_store(store: JournalStore, retention: Duration) -> Self {
+        Self {
+            store,
+            retention,
+            memory: Mutex::new(VecDeque::new()),
+            clock: clock::system(),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Share the journal through `REDIS_URL` when set; keep it in memory otherwise
+    pub fn from_env() -> Self {
+        let retention = std::env::var("STREAM_RETENTION_SECS")
+            .ok()
+            .and_then(|v| v.trim().parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(3600));
+
+        if let Ok(url) = std::env::var("REDIS_URL") {
+            match Self::shared(&url, retention) {
+                Ok(journal) => return journal,
+                Err(e) => tracing::warn!(error = %e, "Invalid REDIS_URL, keeping the deal stream journal in memory"),
+            }
+        }
+        Self::new(retention)
+    }
+
+    pub fn is_shared(&self) -> bool {
+        matches!(self.store, JournalStore::Redis(_))
+    }
+
+    /// Oldest ID still guaranteed to be in the journal
+    fn horizon(&self) -> EventId {
+        let now_ms = self.clock.now().timestamp_millis().max(0) as u64;
+        EventId {
+            ms: now_ms.saturating_sub(self.retention.as_millis() as u64),
+            seq: 0,
+        }
+    }
+
+    /// Append an event, assigning its ID; also whether it went to the shared journal
+    pub async fn append(&self, body: EventBody) -> (StreamEvent, bool) {
+        if let JournalStore::Redis(client) = &self.store {
+            match append_shared(client, &body, self.horizon()) {
+                Ok(id) => return (StreamEvent { id, body }, true),
+                Err(e) => tracing::warn!(error = %e, "Shared deal stream journal unavailable, journaling locally"),
+            }
+        }
+
+        let now = EventId {
+            ms: self.clock.now().timestamp_millis().max(0) as u64,
+            seq: 0,
+        };
+        let horizon = self.horizon();
+        let mut memory = self.memory.lock().await;
+        let id = match memory.back() {
+            Some(last) if last.id >= now => last.id.next(),
+            _ => now,
+        };
+        let event = StreamEvent { id, body };
+        memory.push_back(event.clone());
+        while memory.front().is_some_and(|e| e.id < horizon) {
+            memory.pop_front();
+        }
+        (event, false)
+    }
+
+    /// Events after `after`, oldest first
+    pub async fn after(&self, after: EventId) -> Replay {
+        let gap = after < self.horizon();
+        let events = match &self.store {
+            JournalStore::Redis(client) => match range_shared(client, after) {
+                Ok(events) => events,
+                Err(e) => {
+                    tracing::warn!(error = %e, "Shared deal stream journal unavailable, replaying local events");
+                    self.after_local(after).await
+                }
+            },
+            JournalStore::Memory => self.after_local(after).await,
+        };
+
+        Replay {
+            gap: gap || events.len() > MAX_REPLAY,
+            events: events.into_iter().take(MAX_REPLAY).collect(),
+        }
+    }
+
+    async fn after_local(&self, after: EventId) -> Vec<StreamEvent> {
+        let horizon = self.horizon();
+        let mut memory = self.memory.lock().await;
+        while memory.front().is_some_and(|e| e.id < horizon) {
+            memory.pop_front();
+        }
+        memory
+            .iter()
+            .filter(|e| e.id > after)
+            .take(MAX_REPLAY + 1)
+            .cloned()
+            .collect()
+    }
+
+    /// Wait up to `block` for shared events after `after`, moving `after` past them.
+    ///
+    /// Starts from the newest event when `after` is unset; always empty for an
+    /// in-memory journal.
+    pub(super) fn tail(&self, after: &mut Option<EventId>, block: Duration) -> redis::RedisResult<Vec<StreamEvent>> {
+        let JournalStore::Redis(client) = &self.store else {
+            return Ok(Vec::new());
+        };
+
+        let mut con = client.get_connection()?;
+        let start = match *after {
+            Some(id) => id,
+            None => {
+                let newest: Vec<RawEntry> = redis::cmd("XREVRANGE").arg(REDIS_KEY).arg("+").arg("-").arg("COUNT").arg(1).query(&mut con)?;
+                let newest = newest.first().and_then(|(id, _)| id.parse().ok()).unwrap_or_default();
+                *after = Some(newest);
+                newest
+            }
+        };
+        let reply: Option<Vec<(String, Vec<RawEntry>)>> = redis::cmd("XREAD")
+            .arg("BLOCK")
+            .arg(block.as_millis() as u64)
+            .arg("COUNT")
+            .arg(MAX_REPLAY)
+            .arg("STREAMS")
+            .arg(REDIS_KEY)
+            .arg(start.to_string())
+            .query(&mut con)?;
+        let entries: Vec<RawEntry> = reply.into_iter().flatten().flat_map(|(_, entries)| entries).collect();
+        // Even an unreadable entry is behind us
+        if let Some(id) = entries.last().and_then(|(id, _)| id.parse().ok()) {
+            *after = Some(id);
+        }
+        Ok(entries.into_iter().filter_map(decode).collect())
+    }
+}
+
+/// `(id, [field, value, ...])` as returned by `XRANGE` and `XREAD`
+type RawEntry = (String, Vec<String>);
+
+fn append_shared(client: &redis::Client, body: &EventBody, horizon: EventId) -> redis::RedisResult<EventId> {
+    let content = serde_json::to_string(body)
+        .map_err(|e| redis::RedisError::from((redis::ErrorKind::TypeError, "unserializable stream event", e.to_string())))?;
+
+    let mut con = client.get_connection()?;
+    // `~` lets Redis trim whole nodes only; nothing newer than the horizon is dropped
+    let id: String = redis::cmd("XADD")
+        .arg(REDIS_KEY)
+        .arg("MINID")
+        .arg("~")
+        .arg(horizon.to_string())
+        .arg("*")
+        .arg("event")
+        .arg(content)
+        .query(&mut con)?;
+    id.parse()
+        .map_err(|e: String| redis::RedisError::from((redis::ErrorKind::TypeError, "unexpected stream ID", e)))
+}
+
+fn range_shared(client: &redis::Client, after: EventId) -> redis::RedisResult<Vec<StreamEvent>> {
+    let mut con = client.get_connection()?;
+    let entries: Vec<RawEntry> = redis::cmd("XRANGE")
+        .arg(REDIS_KEY)
+        .arg(format!("({}", after))
+        .arg("+")
+        .arg("COUNT")
+        .arg(MAX_REPLAY + 1)
+        .query(&mut con)?;
+    Ok(entries.into_iter().filter_map(decode).collect())
+}
+
+fn decode((id, fields): RawEntry) -> Option<StreamEvent> {
+    let id = id.parse().ok()?;
+    let content = fields.chunks(2).find(|pair| pair[0] == "event").and_then(|pair| pair.get(1))?;
+    match serde_json::from_str(content) {
+        Ok(body) => Some(StreamEvent { id, body }),
+        Err(e) => {
+            tracing::warn!(event = %id, error = %e, "Skipping an unreadable deal stream event");
+            None
+        }
+    }
+}
@@ -0,0 +1,198 @@
+//! Deal stream
+//!
+//! New deals and price drops are published as [`StreamEvent`]s and served to clients
+//! over server-sent events at `GET /deals/stream`. Every event is written to the
+//! [`journal`] before it is delivered, so a client that reconnects with
+//! `Last-Event-ID` is replayed the events it missed within the retention window.
+//!
+//! With a shared (Redis) journal, delivery goes through the journal: each instance
+//! tails the Redis Stream (see [`DealStream::start_background_tasks`]), so clients
+//! connected to any instance see every instance's events.
+
+pub mod journal;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use crate::models::deal::Deal;
+use crate::models::domain::Money;
+use crate::sla::SlaMonitor;
+
+pub use journal::{EventId, EventJournal, Replay};
+
+/// Live events buffered per subscriber before it lags
+const SUBSCRIBER_BUFFER: usize = 1_024;
+/// How long one Redis tail read blocks
+const TAIL_BLOCK: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    NewDeal,
+    PriceDrop,
+}
+
+impl EventKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EventKind::NewDeal => "new_deal",
+            EventKind::PriceDrop => "price_drop",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventBody {
+    pub kind: EventKind,
+    pub deal: Deal,
+    /// The price before a drop
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_price: Option<Money>,
+    pub published_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StreamEvent {
+    pub id: EventId,
+    #[serde(flatten)]
+    pub body: EventBody,
+}
+
+pub struct DealStream {
+    journal: EventJournal,
+    live: broadcast::Sender<StreamEvent>,
+    sla: Arc<SlaMonitor>,
+}
+
+impl DealStream {
+    /// Stream over `journal`; deliveries of new deals are recorded with `sla`
+    pub fn new(journal: EventJournal, sla: Arc<SlaMonitor>) -> Self {
+        Self {
+            journal,
+            live: broadcast::channel(SUBSCRIBER_BUFFER).0,
+            sla,
+        }
+    }
+
+    pub fn from_env(sla: Arc<SlaMonitor>) -> Self {
+        Self::new(EventJournal::from_env(), sla)
+    }
+
+    /// Publish the change from `previous` to `deal`, if it is news: a new deal or a lower price
+    pub async fn publish(&self, previous: Option<&Deal>, deal: &Deal) {
+        let (kind, previous_price) = match previous {
+            None => (EventKind::NewDeal, None),
+            Some(previous)
+                if previous.price.currency == deal.price.currency && deal.price.amount < previous.price.amount =>
+            {
+                (EventKind::PriceDrop, Some(previous.price))
+            }
+            Some(_) => return,
+        };
+
+        let (event, shared) = self
+            .journal
+            .append(EventBody {
+                kind,
+                deal: deal.clone(),
+                previous_price,
+                published_at: Utc::now(),
+            })
+            .await;
+        if !shared {
+            // Shared events reach subscribers through the tail. No subscribers is fine
+            let _ = self.live.send(event);
+        }
+        if kind == EventKind::NewDeal {
+            self.sla.record(&deal.merchant_domain.to_string(), deal.posted_at).await;
+        }
+    }
+
+    /// Live events from now on; pair with [`replay`](Self::replay) after subscribing
+    pub fn subscribe(&self) -> broadcast::Receiver<StreamEvent> {
+        self.live.subscribe()
+    }
+
+    pub async fn replay(&self, after: EventId) -> Replay {
+        self.journal.after(after).await
+    }
+
+    /// Deliver events journaled by any instance to this instance's subscribers.
+    ///
+    /// Only needed with a shared journal; returns at once otherwise.
+    pub async fn start_background_tasks(self: Arc<Self>) {
+        if !self.journal.is_shared() {
+            return;
+        }
+
+        let mut last: Option<EventId> = None;
+        loop {
+            let stream = self.clone();
+            let tailed = tokio::task::spawn_blocking(move || {
+                let events = stream.journal.tail(&mut last, TAIL_BLOCK);
+                (events, last)
+            })
+            .await;
+            match tailed {
+                Ok((Ok(events), tailed_to)) => {
+                    last = tailed_to;
+                    for event in events {
+                        let _ = self.live.send(event);
+                    }
+                }
+                Ok((Err(e), tailed_to)) => {
+                    last = tailed_to;
+                    tracing::warn!(error = %e, "Failed to tail the deal stream journal");
+                    tokio::time::sleep(TAIL_BLOCK).await;
+                }
+                Err(e) => tracing::error!(error = %e, "Deal stream tail task failed"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::storage::deal_store::DealStore;
+    use rust_decimal_macros::dec;
+
+    #[tokio::test]
+    async fn test_replays_missed_events_within_retention() {
+        let clock = Arc::new(MockClock::new());
+        let journal = EventJournal::new(Duration::from_secs(60)).with_clock(clock.clone());
+        let stream = DealStream::new(journal, Arc::new(SlaMonitor::default()));
+        let mut live = stream.subscribe();
+
+        let deal = DealStore::with_sample_data().list().await.remove(0);
+        stream.publish(None, &deal).await;
+        let first = live.recv().await.unwrap();
+        assert_eq!(first.body.kind, EventKind::NewDeal);
+
+        let mut cheaper = deal.clone();
+        cheaper.price.amount -= dec!(1);
+        stream.publish(Some(&deal), &cheaper).await;
+        // Not news: same or higher price
+        stream.publish(Some(&cheaper), &cheaper).await;
+        stream.publish(Some(&cheaper), &deal).await;
+
+        let replay = stream.replay(first.id).await;
+        assert!(!replay.gap);
+        assert_eq!(replay.events.len(), 1);
+        assert_eq!(replay.events[0].body.kind, EventKind::PriceDrop);
+        assert_eq!(replay.events[0].body.previous_price, Some(deal.price));
+        assert!(replay.events[0].id > first.id);
+
+        // Once the client's last event is past retention, missed events may be gone
+        clock.advance(Duration::from_secs(61));
+        let stale = stream.replay(first.id).await;
+        assert!(stale.gap);
+        assert!(stale.events.is_empty());
+        assert_eq!("1700000000000-3".parse::<EventId>().unwrap().to_string(), "1700000000000-3");
+    }
+}
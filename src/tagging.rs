@@ -0,0 +1,480 @@
+//! Tags on deals and coupons for curation
+//!
+//! Editors label deals and coupons (`student-discount`, `app-only`, ...) through
+//! `/admin/tags/deals/{id}` and `/admin/tags/coupons/{code}@{merchant}` instead of a
+//! schema change per label. Tags are slugs: lowercase letters, digits and dashes.
+//!
+//! The controlled vocabulary at `/admin/tags` names and describes tags. A definition
+//! with `keywords` is also a classifier: it applies its tag to every deal or coupon
+//! whose text mentions one of them, as a whole word. Editors can suppress a keyword
+//! tag on one deal or coupon where it is wrong. Tags outside the vocabulary are
+//! accepted as free-form tags.
+//!
+//! Tags are attached as deals are ranked and coupons are listed, and every list and
+//! search endpoint filters on them with `tags=a,b` (all must match). With `REDIS_URL`
+//! set the vocabulary and editor tags are shared and re-read every
+//! [`REFRESH_INTERVAL`]; otherwise they are persisted to `TAGS_PATH` (default
+//! `data/tags.json`).
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::clock::{self, Clock};
+use crate::models::coupon_listing::CouponListing;
+use crate::models::deal::Deal;
+use crate::storage::persisted::{PersistedStore, StoreError};
+
+const REDIS_VOCABULARY_KEY: &str = "tag_vocabulary";
+const REDIS_ASSIGNMENTS_KEY: &str = "tag_assignments";
+const STORE_NAME: &str = "tags";
+/// How often shared tags are re-read
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+const MAX_TAG_LEN: usize = 50;
+/// Most tags one deal or coupon can be given, and keywords one definition can have
+const MAX_TAGS: usize = 50;
+
+/// What a tag is applied to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TagTarget {
+    Deal,
+    Coupon,
+}
+
+impl TagTarget {
+    /// `id` is a deal id, or a coupon's `{code}@{merchant}`
+    fn key(self, id: &str) -> String {
+        match self {
+            Self::Deal => format!("deal:{}", id),
+            Self::Coupon => format!("coupon:{}", id),
+        }
+    }
+}
+
+/// A tag in the controlled vocabulary
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct TagDefinition {
+    /// Set from the path
+    #[serde(default)]
+    pub slug: String,
+    pub label: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Words or phrases that apply the tag when a title, description or category
+    /// mentions them; editors apply it by hand without
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keywords: Vec<String>,
+    /// What the keywords apply the tag to, deals and coupons by default
+    #[serde(default = "every_target")]
+    pub applies_to: Vec<TagTarget>,
+    #[serde(default = "Utc::now")]
+    pub updated_at: DateTime<Utc>,
+}
+
+fn every_target() -> Vec<TagTarget> {
+    vec![TagTarget::Deal, TagTarget::Coupon]
+}
+
+/// The tags editors set on one deal or coupon
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct TagAssignment {
+    /// Applied by hand, from the vocabulary or free-form
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Keyword tags taken off where their keywords misfire
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub suppressed: Vec<String>,
+    #[serde(default = "Utc::now")]
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, ToSchema)]
+pub struct TagRequest {
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub suppressed: Vec<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TagError {
+    /// A tag, label or keyword that cannot be used
+    Invalid(String),
+    /// The file or Redis could not be written
+    Storage(String),
+}
+
+impl fmt::Display for TagError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Invalid(e) => write!(f, "{}", e),
+            Self::Storage(e) => write!(f, "failed to store the tags: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for TagError {}
+
+/// `tag` as a slug: trimmed, lowercased, with spaces and underscores as dashes
+pub fn slug(tag: &str) -> Result<String, String> {
+    let slug: String = tag
+        .trim()
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_whitespace() || c == '_' { '-' } else { c })
+        .collect();
+    if slug.is_empty() || slug.len() > MAX_TAG_LEN || !slug.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-') {
+        return Err(format!("'{}' is not a tag: use 1 to {} letters, digits and dashes", tag.trim(), MAX_TAG_LEN));
+    }
+    Ok(slug)
+}
+
+/// The tags of a comma-separated `tags` filter, skipping ones that cannot be tags
+pub fn parse_filter(value: Option<&str>) -> Vec<String> {
+    value.into_iter().flat_map(|value| value.split(',')).filter_map(|tag| slug(tag).ok()).collect()
+}
+
+/// Whether `tags` include every one of `wanted`
+pub fn has_all(tags: &[String], wanted: &[String]) -> bool {
+    wanted.iter().all(|tag| tags.contains(tag))
+}
+
+fn slugs(tags: &[String]) -> Result<Vec<String>, String> {
+    if tags.len() > MAX_TAGS {
+        return Err(format!("at most {} tags", MAX_TAGS));
+    }
+    let mut slugs = tags.iter().map(|tag| slug(tag)).collect::<Result<Vec<_>, _>>()?;
+    slugs.sort();
+    slugs.dedup();
+    Ok(slugs)
+}
+
+struct LoadedTag {
+    definition: TagDefinition,
+    /// Any keyword, as a whole word
+    matcher: Option<Regex>,
+}
+
+impl LoadedTag {
+    fn load(definition: TagDefinition) -> Result<Self, String> {
+        let keywords: Vec<String> = definition
+            .keywords
+            .iter()
+            .map(|keyword| keyword.split_whitespace().map(regex::escape).collect::<Vec<_>>().join(r"\s+"))
+            .filter(|keyword| !keyword.is_empty())
+            .collect();
+        let matcher = match keywords.is_empty() {
+            true => None,
+            false => Some(
+                RegexBuilder::new(&format!(r"\b(?:{})\b", keywords.join("|")))
+                    .case_insensitive(true)
+                    .build()
+                    .map_err(|e| format!("keywords of {}: {}", definition.slug, e))?,
+            ),
+        };
+        Ok(Self { definition, matcher })
+    }
+
+    fn classifies(&self, target: TagTarget, texts: &[&str]) -> bool {
+        self.definition.applies_to.contains(&target)
+            && self.matcher.as_ref().is_some_and(|matcher| texts.iter().any(|text| matcher.is_match(text)))
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct TagsFile {
+    #[serde(default)]
+    vocabulary: Vec<TagDefinition>,
+    /// By `deal:{id}` or `coupon:{code}@{merchant}`
+    #[serde(default)]
+    assignments: BTreeMap<String, TagAssignment>,
+}
+
+pub struct TagRegistry {
+    vocabulary: RwLock<BTreeMap<String, LoadedTag>>,
+    assignments: RwLock<BTreeMap<String, TagAssignment>>,
+    /// Everything in the file; definitions and assignments one field of a Redis
+    /// hash each
+    store: PersistedStore<TagsFile>,
+    clock: Arc<dyn Clock>,
+}
+
+impl TagRegistry {
+    /// Tags persisted to `path`, or kept in memory only
+    pub fn new(path: Option<PathBuf>) -> Self {
+        Self::with_store(PersistedStore::new(STORE_NAME, REDIS_VOCABULARY_KEY, path))
+    }
+
+    /// Tags shared through Redis
+    pub fn shared(redis_url: &str) -> Result<Self, StoreError> {
+        Ok(Self::with_store(PersistedStore::shared(STORE_NAME, REDIS_VOCABULARY_KEY, redis_url)?))
+    }
+
+    fn with_store(store: PersistedStore<TagsFile>) -> Self {
+        Self {
+            vocabulary: RwLock::new(BTreeMap::new()),
+            assignments: RwLock::new(BTreeMap::new()),
+            store: store.pretty(),
+            clock: clock::system(),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Share tags through `REDIS_URL` when set, otherwise load them from `TAGS_PATH`
+    /// (default `data/tags.json`)
+    pub async fn from_env() -> Self {
+        let registry = Self::with_store(PersistedStore::from_env(STORE_NAME, REDIS_VOCABULARY_KEY, "TAGS_PATH", "data/tags.json"));
+        if let Err(e) = registry.reload().await {
+            tracing::warn!(error = %e, "Starting without tags");
+        }
+        registry
+    }
+
+    /// Replace the local copy with every stored definition and editor tag
+    pub async fn reload(&self) -> Result<(), StoreError> {
+        let stored = match (self.store.hash_values(REDIS_VOCABULARY_KEY)?, self.store.hash_entries(REDIS_ASSIGNMENTS_KEY)?) {
+            (Some(vocabulary), Some(assignments)) => TagsFile { vocabulary, assignments },
+            _ => match self.store.load().await? {
+                Some(stored) => stored,
+                None => return Ok(()),
+            },
+        };
+
+        let mut vocabulary = BTreeMap::new();
+        for definition in stored.vocabulary {
+            match LoadedTag::load(definition) {
+                Ok(loaded) => {
+                    vocabulary.insert(loaded.definition.slug.clone(), loaded);
+                }
+                Err(e) => tracing::warn!(error = %e, "Skipping a tag definition"),
+            }
+        }
+        *self.vocabulary.write().unwrap() = vocabulary;
+        *self.assignments.write().unwrap() = stored.assignments;
+        Ok(())
+    }
+
+    /// Re-read shared tags every [`REFRESH_INTERVAL`], picking up changes made
+    /// through other instances
+    pub async fn start_background_tasks(self: Arc<Self>) {
+        self.store.refresh_every(self.clock.as_ref(), REFRESH_INTERVAL, || self.reload()).await
+    }
+
+    /// Store the current state with `key` of `hash` set to `value`, or deleted
+    async fn write<V: Serialize>(&self, hash: &str, key: &str, value: Option<&V>) -> Result<(), StoreError> {
+        self.store
+            .write_field(hash, key, value, || TagsFile {
+                vocabulary: self.vocabulary(),
+                assignments: self.assignments.read().unwrap().clone(),
+            })
+            .await
+    }
+
+    /// The controlled vocabulary, by slug
+    pub fn vocabulary(&self) -> Vec<TagDefinition> {
+        self.vocabulary.read().unwrap().values().map(|loaded| loaded.definition.clone()).collect()
+    }
+
+    /// Add or replace the definition of `slug`; its keywords apply from the next listing on
+    pub async fn define(&self, slug: &str, mut definition: TagDefinition) -> Result<TagDefinition, TagError> {
+        definition.slug = self::slug(slug).map_err(TagError::Invalid)?;
+        definition.label = definition.label.trim().to_string();
+        if definition.label.is_empty() {
+            return Err(TagError::Invalid("label must not be empty".to_string()));
+        }
+        definition.description = definition.description.map(|d| d.trim().to_string()).filter(|d| !d.is_empty());
+        definition.keywords = definition.keywords.iter().map(|k| k.trim().to_string()).filter(|k| !k.is_empty()).collect();
+        if definition.keywords.len() > MAX_TAGS {
+            return Err(TagError::Invalid(format!("at most {} keywords", MAX_TAGS)));
+        }
+        definition.applies_to.dedup();
+        definition.updated_at = self.clock.now();
+        let loaded = LoadedTag::load(definition.clone()).map_err(TagError::Invalid)?;
+
+        let _write = self.store.write_lock().await;
+        let previous = self.vocabulary.write().unwrap().insert(definition.slug.clone(), loaded);
+        if let Err(e) = self.write(REDIS_VOCABULARY_KEY, &definition.slug, Some(&definition)).await {
+            let mut vocabulary = self.vocabulary.write().unwrap();
+            match previous {
+                Some(previous) => vocabulary.insert(definition.slug.clone(), previous),
+                None => vocabulary.remove(&definition.slug),
+            };
+            return Err(TagError::Storage(e.to_string()));
+        }
+        Ok(definition)
+    }
+
+    /// Drop `slug` from the vocabulary, ending its keyword tagging; editors' uses of
+    /// it stay as free-form tags. False when it was not defined.
+    pub async fn undefine(&self, slug: &str) -> Result<bool, TagError> {
+        let _write = self.store.write_lock().await;
+        let Some(previous) = self.vocabulary.write().unwrap().remove(slug) else {
+            return Ok(false);
+        };
+        if let Err(e) = self.write::<TagDefinition>(REDIS_VOCABULARY_KEY, slug, None).await {
+            self.vocabulary.write().unwrap().insert(slug.to_string(), previous);
+            return Err(TagError::Storage(e.to_string()));
+        }
+        Ok(true)
+    }
+
+    /// What editors set on a deal, or a coupon's `{code}@{merchant}`
+    pub fn assignment(&self, target: TagTarget, id: &str) -> Option<TagAssignment> {
+        self.assignments.read().unwrap().get(&target.key(id)).cloned()
+    }
+
+    /// Replace what editors set on a deal or coupon; nothing clears it
+    pub async fn assign(&self, target: TagTarget, id: &str, request: TagRequest) -> Result<TagAssignment, TagError> {
+        let assignment = TagAssignment {
+            tags: slugs(&request.tags).map_err(TagError::Invalid)?,
+            suppressed: slugs(&request.suppressed).map_err(TagError::Invalid)?,
+            updated_at: self.clock.now(),
+        };
+        let key = target.key(id);
+        let cleared = assignment.tags.is_empty() && assignment.suppressed.is_empty();
+
+        let _write = self.store.write_lock().await;
+        let previous = {
+            let mut assignments = self.assignments.write().unwrap();
+            match cleared {
+                true => assignments.remove(&key),
+                false => assignments.insert(key.clone(), assignment.clone()),
+            }
+        };
+        if let Err(e) = self.write(REDIS_ASSIGNMENTS_KEY, &key, (!cleared).then_some(&assignment)).await {
+            let mut assignments = self.assignments.write().unwrap();
+            match previous {
+                Some(previous) => assignments.insert(key, previous),
+                None => assignments.remove(&key),
+            };
+            return Err(TagError::Storage(e.to_string()));
+        }
+        Ok(assignment)
+    }
+
+    /// Editor tags and matching keyword tags, less the suppressed ones
+    fn tags(&self, target: TagTarget, id: &str, texts: &[&str]) -> Vec<String> {
+        let assignment = self.assignments.read().unwrap().get(&target.key(id)).cloned().unwrap_or_default();
+        let mut tags = assignment.tags;
+        for loaded in self.vocabulary.read().unwrap().values() {
+            if !assignment.suppressed.contains(&loaded.definition.slug) && loaded.classifies(target, texts) {
+                tags.push(loaded.definition.slug.clone());
+            }
+        }
+        tags.sort();
+        tags.dedup();
+        tags
+    }
+
+    pub fn deal_tags(&self, deal: &Deal) -> Vec<String> {
+        self.tags(TagTarget::Deal, &deal.id, &[&deal.title, &deal.category.replace('_', " ")])
+    }
+
+    pub fn coupon_tags(&self, coupon: &CouponListing) -> Vec<String> {
+        let id = format!("{}@{}", coupon.code, coupon.merchant_domain);
+        self.tags(TagTarget::Coupon, &id, &[&coupon.title, coupon.description.as_deref().unwrap_or_default()])
+    }
+
+    pub fn annotate_deals(&self, deals: &mut [Deal]) {
+        for deal in deals {
+            deal.tags = self.deal_tags(deal);
+        }
+    }
+
+    pub fn annotate_coupons(&self, coupons: &mut [CouponListing]) {
+        for coupon in coupons {
+            coupon.tags = self.coupon_tags(coupon);
+        }
+    }
+}
+
+impl Default for TagRegistry {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::coupon_store::CouponStore;
+    use crate::storage::deal_store::DealStore;
+
+    fn definition(label: &str, keywords: &[&str], applies_to: Vec<TagTarget>) -> TagDefinition {
+        TagDefinition {
+            slug: String::new(),
+            label: label.to_string(),
+            description: None,
+            keywords: keywords.iter().map(|k| k.to_string()).collect(),
+            applies_to,
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_keyword_editor_and_suppressed_tags_combine() {
+        let path = std::env::temp_dir().join(format!("tags_{}.json", uuid::Uuid::new_v4()));
+        let registry = TagRegistry::new(Some(path.clone()));
+        let mut deal = DealStore::with_sample_data().list().await.remove(0);
+        deal.title = "Galaxy Book for students, 20% off with student ID".to_string();
+        let mut coupon = CouponStore::with_sample_data().list().await.remove(0);
+        coupon.title = "Student discount: 15% off".to_string();
+        coupon.description = None;
+
+        assert!(registry.define("Bad Slug!", definition("Bad", &[], every_target())).await.is_err());
+        assert!(registry.define("student", definition(" ", &[], every_target())).await.is_err());
+        registry
+            .define("Student Discount", definition("Student discount", &["student id", "student discount"], every_target()))
+            .await
+            .unwrap();
+        registry.define("app-only", definition("App only", &["in the app"], vec![TagTarget::Coupon])).await.unwrap();
+        assert_eq!(registry.deal_tags(&deal), vec!["student-discount"]);
+        assert_eq!(registry.coupon_tags(&coupon), vec!["student-discount"]);
+
+        // Free-form tags next to vocabulary ones; a misfiring keyword tag is suppressed
+        let coupon_id = format!("{}@{}", coupon.code, coupon.merchant_domain);
+        let request = TagRequest {
+            tags: vec!["App Only".to_string(), "editors_pick".to_string(), "app-only".to_string()],
+            suppressed: vec!["student-discount".to_string()],
+        };
+        let assignment = registry.assign(TagTarget::Coupon, &coupon_id, request).await.unwrap();
+        assert_eq!(assignment.tags, vec!["app-only", "editors-pick"]);
+        registry.annotate_coupons(std::slice::from_mut(&mut coupon));
+        assert_eq!(coupon.tags, vec!["app-only", "editors-pick"]);
+        let bad = TagRequest {
+            tags: vec!["no/slashes".to_string()],
+            ..Default::default()
+        };
+        assert!(registry.assign(TagTarget::Deal, &deal.id, bad).await.is_err());
+
+        let restarted = TagRegistry::new(Some(path.clone()));
+        restarted.reload().await.unwrap();
+        assert_eq!(restarted.vocabulary().len(), 2);
+        assert_eq!(restarted.coupon_tags(&coupon), vec!["app-only", "editors-pick"]);
+
+        // Undefined tags stop classifying, but editors' uses of them stay
+        assert!(restarted.undefine("app-only").await.unwrap());
+        assert!(!restarted.undefine("app-only").await.unwrap());
+        assert_eq!(restarted.coupon_tags(&coupon), vec!["app-only", "editors-pick"]);
+        restarted.assign(TagTarget::Coupon, &coupon_id, TagRequest::default()).await.unwrap();
+        assert_eq!(restarted.assignment(TagTarget::Coupon, &coupon_id), None);
+        assert_eq!(restarted.coupon_tags(&coupon), vec!["student-discount"]);
+
+        assert_eq!(parse_filter(Some("Student Discount, app-only,,bad/tag")), vec!["student-discount", "app-only"]);
+        assert!(has_all(&coupon.tags, &parse_filter(Some("editors-pick"))));
+        assert!(!has_all(&coupon.tags, &parse_filter(Some("editors-pick,student-discount"))));
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,64 @@
+//! Log output, and the request work is done for
+//!
+//! [`init`] prints `tracing` events to stderr with the fields of the spans they
+//! happen in, so a scraper warning logged while serving a request carries the
+//! `request_id` and `trace_id` of the API's `request` span. `RUST_LOG` filters
+//! them, `info` by default.
+//!
+//! The task serving a request also keeps its [`TraceContext`], and scraper fetches
+//! send it on as `x-request-id` and `traceparent`, so a merchant's or proxy's logs
+//! can be matched to ours. Scrape jobs keep the context of the request that queued
+//! them.
+
+use std::future::Future;
+use std::io::IsTerminal;
+
+use serde::{Deserialize, Serialize};
+use tracing_subscriber::EnvFilter;
+use utoipa::ToSchema;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+
+tokio::task_local! {
+    static CURRENT: TraceContext;
+}
+
+/// The IDs a request passes on to what it calls
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct TraceContext {
+    pub request_id: String,
+    /// W3C trace context naming this service's span as the parent
+    pub traceparent: String,
+}
+
+impl TraceContext {
+    /// The context of the request the current task serves
+    pub fn current() -> Option<Self> {
+        CURRENT.try_with(Clone::clone).ok()
+    }
+
+    /// Run `future` for `context`'s request; as it is without one
+    pub async fn scope<F: Future>(context: Option<Self>, future: F) -> F::Output {
+        match context {
+            Some(context) => CURRENT.scope(context, future).await,
+            None => future.await,
+        }
+    }
+
+    /// Headers carrying the context on an outgoing request
+    pub fn headers(&self) -> [(&'static str, &str); 2] {
+        [(REQUEST_ID_HEADER, &self.request_id), (TRACEPARENT_HEADER, &self.traceparent)]
+    }
+}
+
+/// Print log events to stderr; does nothing when a subscriber is already installed
+pub fn init() {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let _ = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        // Plain text for log collectors
+        .with_ansi(std::io::stderr().is_terminal())
+        .try_init();
+}
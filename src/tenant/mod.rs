@@ -0,0 +1,230 @@
+//! Tenant resolution for multi-tenant (white-label) deployments
+//!
+//! A request's tenant comes from its `X-Api-Key`, when the key belongs to a tenant
+//! record, and otherwise from the `X-Tenant-Id` header. Tenant records are read from
+//! the JSON file at `TENANTS_CONFIG_PATH` and carry the tenant's API key hashes and
+//! how its API responses are shaped (see [`shaping`]) and how PII is scrubbed from
+//! what it submits (see [`crate::privacy`]) and which coupon licenses its keys may
+//! receive (see [`crate::licensing`]) and the country it serves by default. Sandbox keys resolve to the
+//! same tenant but put the request in sandbox mode (see [`crate::sandbox`]).
+//! Requests made with a key are rate limited per key (see [`usage`]).
+
+pub mod shaping;
+pub mod usage;
+
+use std::collections::{BTreeSet, HashMap};
+
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{request::Parts, HeaderMap},
+};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::models::coupon_listing::License;
+use crate::privacy::PiiPolicy;
+
+pub use shaping::ResponseShape;
+
+pub const DEFAULT_TENANT: &str = "default";
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Tenant the request is served for
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TenantId(pub String);
+
+impl TenantId {
+    /// Tenant named by the `X-Tenant-Id` header, or the default one
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let tenant = headers
+            .get("x-tenant-id")
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.trim().to_lowercase())
+            .filter(|v| !v.is_empty())
+            .unwrap_or_else(|| DEFAULT_TENANT.to_string());
+
+        TenantId(tenant)
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for TenantId {
+    type Rejection = std::convert::Infallible;
+
+    /// The tenant resolved from the API key by the router, else the header
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        match parts.extensions.get::<TenantId>() {
+            Some(tenant) => Ok(tenant.clone()),
+            None => Ok(TenantId::from_headers(&parts.headers)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TenantRecord {
+    /// SHA-256 (hex) of each API key issued to the tenant
+    #[serde(default)]
+    pub api_key_sha256: Vec<String>,
+    /// SHA-256 (hex) of each sandbox API key issued to the tenant
+    #[serde(default)]
+    pub sandbox_api_key_sha256: Vec<String>,
+    #[serde(default)]
+    pub response: ResponseShape,
+    #[serde(default)]
+    pub pii: PiiPolicy,
+    /// Quota for each of the tenant's keys; `API_QUOTA_PER_MINUTE` when unset
+    #[serde(default)]
+    pub requests_per_minute: Option<u32>,
+    /// Licenses of the coupons the tenant's keys receive, as its agreements allow;
+    /// [`License::REDISTRIBUTABLE`] when unset
+    #[serde(default)]
+    pub licenses: Option<BTreeSet<License>>,
+    /// Country whose compliance rules apply to requests that do not name one
+    /// (see [`crate::compliance`])
+    #[serde(default)]
+    pub country: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct TenantsConfig {
+    #[serde(default)]
+    tenants: HashMap<String, TenantRecord>,
+}
+
+/// Who a request was made by
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Caller {
+    pub tenant: TenantId,
+    /// Made with a sandbox key
+    pub sandbox: bool,
+    /// SHA-256 (hex) of the API key the request was made with
+    pub api_key: Option<String>,
+}
+
+/// Unknown `X-Api-Key`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownApiKey;
+
+#[derive(Default)]
+pub struct TenantRegistry {
+    tenants: HashMap<String, TenantRecord>,
+    /// Key hash to tenant, and whether it is a sandbox key
+    api_keys: HashMap<String, (String, bool)>,
+}
+
+impl TenantRegistry {
+    pub fn new(tenants: HashMap<String, TenantRecord>) -> Self {
+        let tenants: HashMap<String, TenantRecord> = tenants.into_iter().map(|(id, record)| (id.to_lowercase(), record)).collect();
+        let api_keys = tenants
+            .iter()
+            .flat_map(|(id, record)| {
+                let live = record.api_key_sha256.iter().map(move |hash| (hash.to_lowercase(), (id.clone(), false)));
+                let sandbox = record.sandbox_api_key_sha256.iter().map(move |hash| (hash.to_lowercase(), (id.clone(), true)));
+                live.chain(sandbox)
+            })
+            .collect();
+        Self { tenants, api_keys }
+    }
+
+    /// Tenant records from `TENANTS_CONFIG_PATH`, or none
+    pub fn from_env() -> Self {
+        let Ok(path) = std::env::var("TENANTS_CONFIG_PATH") else {
+            return Self::default();
+        };
+
+        match Self::load_config(&path) {
+            Ok(config) => Self::new(config.tenants),
+            Err(e) => {
+                tracing::warn!(path = %path, error = %e, "Failed to load the tenants config");
+                Self::default()
+            }
+        }
+    }
+
+    fn load_config(path: &str) -> Result<TenantsConfig, Box<dyn std::error::Error + Send + Sync>> {
+        let content = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    pub fn get(&self, tenant: &str) -> Option<&TenantRecord> {
+        self.tenants.get(tenant)
+    }
+
+    /// The tenant's scrubbing policy; tenants without a record redact everything
+    pub fn pii_policy(&self, tenant: &TenantId) -> PiiPolicy {
+        self.get(&tenant.0).map(|record| record.pii.clone()).unwrap_or_default()
+    }
+
+    /// Per-minute quota for the tenant's keys, if it has its own
+    pub fn quota(&self, tenant: &TenantId) -> Option<u32> {
+        self.get(&tenant.0).and_then(|record| record.requests_per_minute)
+    }
+
+    /// Licenses of the coupons the caller may receive; `None` for requests without
+    /// an API key, which may receive every coupon
+    pub fn licenses(&self, caller: &Caller) -> Option<BTreeSet<License>> {
+        caller.api_key.as_ref()?;
+        let licenses = self.get(&caller.tenant.0).and_then(|record| record.licenses.clone());
+        Some(licenses.unwrap_or_else(|| BTreeSet::from(License::REDISTRIBUTABLE)))
+    }
+
+    /// The request's caller; a present but unknown API key is an error
+    pub fn resolve(&self, headers: &HeaderMap) -> Result<Caller, UnknownApiKey> {
+        let Some(key) = headers.get(API_KEY_HEADER) else {
+            return Ok(Caller {
+                tenant: TenantId::from_headers(headers),
+                sandbox: false,
+                api_key: None,
+            });
+        };
+
+        let key = hash_key(key.to_str().map_err(|_| UnknownApiKey)?.trim());
+        let (tenant, sandbox) = self.api_keys.get(&key).ok_or(UnknownApiKey)?;
+        Ok(Caller {
+            tenant: TenantId(tenant.clone()),
+            sandbox: *sandbox,
+            api_key: Some(key),
+        })
+    }
+}
+
+fn hash_key(api_key: &str) -> String {
+    Sha256::digest(api_key.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolves_tenant_by_api_key_then_header() {
+        let registry = TenantRegistry::new(HashMap::from([(
+            "Acme".to_string(),
+            TenantRecord {
+                api_key_sha256: vec![hash_key("acme-secret")],
+                sandbox_api_key_sha256: vec![hash_key("acme-test")],
+                ..TenantRecord::default()
+            },
+        )]));
+
+        let caller = |tenant: &str, sandbox, key: Option<&str>| Ok(Caller {
+            tenant: TenantId(tenant.to_string()),
+            sandbox,
+            api_key: key.map(hash_key),
+        });
+
+        let mut headers = HeaderMap::new();
+        assert_eq!(registry.resolve(&headers), caller(DEFAULT_TENANT, false, None));
+        headers.insert("x-tenant-id", "Globex".parse().unwrap());
+        assert_eq!(registry.resolve(&headers), caller("globex", false, None));
+
+        // The key wins over a claimed tenant header
+        headers.insert(API_KEY_HEADER, "acme-secret".parse().unwrap());
+        assert_eq!(registry.resolve(&headers), caller("acme", false, Some("acme-secret")));
+        headers.insert(API_KEY_HEADER, "acme-test".parse().unwrap());
+        assert_eq!(registry.resolve(&headers), caller("acme", true, Some("acme-test")));
+        headers.insert(API_KEY_HEADER, "guess".parse().unwrap());
+        assert_eq!(registry.resolve(&headers), Err(UnknownApiKey));
+    }
+}
@@ -0,0 +1,109 @@
+//! Per-tenant response shaping
+//!
+//! White-label partners should only see the fields they are meant to. A tenant's
+//! [`ResponseShape`] is applied to every JSON response served for it:
+//!
+//! - `fields` is an allow-list for records, i.e. the objects inside arrays such as
+//!   the `deals` of a listing. The response envelope is kept as it is.
+//! - `exclude_fields` removes fields wherever they appear, including single records.
+//! - `rename_fields` renames fields wherever they appear, after filtering.
+//! - `branding` is added to the top-level object as `"branding"`.
+
+use std::collections::{HashMap, HashSet};
+
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ResponseShape {
+    /// Fields kept on records; every field when unset
+    #[serde(default)]
+    pub fields: Option<HashSet<String>>,
+    #[serde(default)]
+    pub exclude_fields: HashSet<String>,
+    /// Original name to the name the tenant sees
+    #[serde(default)]
+    pub rename_fields: HashMap<String, String>,
+    #[serde(default)]
+    pub branding: Option<Map<String, Value>>,
+}
+
+impl ResponseShape {
+    /// Whether applying the shape leaves every response unchanged
+    pub fn is_identity(&self) -> bool {
+        self.fields.is_none() && self.exclude_fields.is_empty() && self.rename_fields.is_empty() && self.branding.is_none()
+    }
+
+    pub fn apply(&self, value: &mut Value) {
+        self.shape(value, false);
+        if let (Some(branding), Value::Object(object)) = (&self.branding, value) {
+            object.insert("branding".to_string(), Value::Object(branding.clone()));
+        }
+    }
+
+    fn shape(&self, value: &mut Value, is_record: bool) {
+        match value {
+            Value::Array(items) => {
+                for item in items {
+                    self.shape(item, true);
+                }
+            }
+            Value::Object(object) => {
+                object.retain(|key, _| {
+                    !self.exclude_fields.contains(key)
+                        && (!is_record || self.fields.as_ref().is_none_or(|fields| fields.contains(key)))
+                });
+                // Objects nested in a record belong to it, e.g. a deal's price
+                for nested in object.values_mut() {
+                    self.shape(nested, false);
+                }
+                if !self.rename_fields.is_empty() {
+                    *object = std::mem::take(object)
+                        .into_iter()
+                        .map(|(key, value)| match self.rename_fields.get(&key) {
+                            Some(renamed) => (renamed.clone(), value),
+                            None => (key, value),
+                        })
+                        .collect();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_filters_records_renames_and_brands() {
+        let shape: ResponseShape = serde_json::from_value(json!({
+            "fields": ["code", "title", "price", "source_url"],
+            "exclude_fields": ["source_url", "confidence"],
+            "rename_fields": {"code": "coupon_code"},
+            "branding": {"name": "Acme Deals"}
+        }))
+        .unwrap();
+
+        let mut response = json!({
+            "coupons": [
+                {"code": "SAVE10", "title": "10% off", "confidence": 0.8, "source_url": "https://x", "price": {"amount": "5", "currency": "USD"}}
+            ],
+            "coupon": {"code": "SAVE10", "confidence": 0.8, "merchant": "shop.com"},
+            "service": "deal-service"
+        });
+        shape.apply(&mut response);
+
+        assert_eq!(
+            response,
+            json!({
+                "coupons": [{"coupon_code": "SAVE10", "title": "10% off", "price": {"amount": "5", "currency": "USD"}}],
+                "coupon": {"coupon_code": "SAVE10", "merchant": "shop.com"},
+                "service": "deal-service",
+                "branding": {"name": "Acme Deals"}
+            })
+        );
+    }
+}
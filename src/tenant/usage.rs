@@ -0,0 +1,247 @@
+//! Inbound rate limits and usage per API key
+//!
+//! Every request made with an `X-Api-Key` is counted against the key's quota for
+//! the current one-minute window: the tenant's `requests_per_minute`, else
+//! `API_QUOTA_PER_MINUTE` (default [`DEFAULT_QUOTA_PER_MINUTE`]). Requests over
+//! the quota are refused until the window ends. The same counters are what
+//! `GET /account/usage` reports to the key's holder, together with requests per
+//! endpoint in the window and per day for the last [`DAILY_HISTORY_DAYS`] days.
+//! Requests without a key are neither limited nor counted. Counters are kept in
+//! memory.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use utoipa::ToSchema;
+
+use crate::clock::{self, Clock};
+
+pub const DEFAULT_QUOTA_PER_MINUTE: u32 = 600;
+/// Days of daily counts kept per key, today included
+pub const DAILY_HISTORY_DAYS: usize = 30;
+
+/// The quota left after a request was let through
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Admitted {
+    pub limit: u32,
+    pub remaining: u32,
+    pub resets_at: DateTime<Utc>,
+}
+
+/// Refused, because the key used its quota for the window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuotaExceeded {
+    pub limit: u32,
+    pub retry_after: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct WindowUsage {
+    pub start: DateTime<Utc>,
+    pub resets_at: DateTime<Utc>,
+    pub limit: u32,
+    pub used: u32,
+    pub remaining: u32,
+    /// Requests by `METHOD /route`, e.g. `GET /deals/:id/similar`
+    pub endpoints: BTreeMap<String, u32>,
+    /// Requests refused in the window
+    pub rejected: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct DailyUsage {
+    pub date: NaiveDate,
+    pub requests: u64,
+    pub rejected: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct KeyUsage {
+    pub window: WindowUsage,
+    /// Oldest first, ending with today
+    pub daily: Vec<DailyUsage>,
+}
+
+#[derive(Default)]
+struct Counters {
+    window_start: Option<DateTime<Utc>>,
+    used: u32,
+    rejected: u32,
+    endpoints: BTreeMap<String, u32>,
+    daily: BTreeMap<NaiveDate, (u64, u64)>,
+}
+
+impl Counters {
+    /// Start a new window if the current one is over
+    fn roll(&mut self, now: DateTime<Utc>) -> DateTime<Utc> {
+        match self.window_start {
+            Some(start) if now - start < TimeDelta::minutes(1) => start,
+            _ => {
+                self.window_start = Some(now);
+                self.used = 0;
+                self.rejected = 0;
+                self.endpoints.clear();
+                now
+            }
+        }
+    }
+
+    fn count_day(&mut self, now: DateTime<Utc>, rejected: bool) {
+        let day = self.daily.entry(now.date_naive()).or_default();
+        match rejected {
+            true => day.1 += 1,
+            false => day.0 += 1,
+        }
+        while self.daily.len() > DAILY_HISTORY_DAYS {
+            self.daily.pop_first();
+        }
+    }
+}
+
+/// The key a request is metered under, for handlers that report its usage
+#[derive(Clone)]
+pub struct MeteredKey {
+    pub meter: Arc<UsageMeter>,
+    pub key: String,
+    pub quota: Option<u32>,
+}
+
+impl MeteredKey {
+    pub async fn usage(&self) -> KeyUsage {
+        self.meter.usage(&self.key, self.quota).await
+    }
+}
+
+pub struct UsageMeter {
+    default_per_minute: u32,
+    /// By API key hash
+    counters: Mutex<HashMap<String, Counters>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for UsageMeter {
+    fn default() -> Self {
+        Self::new(DEFAULT_QUOTA_PER_MINUTE)
+    }
+}
+
+impl UsageMeter {
+    pub fn new(default_per_minute: u32) -> Self {
+        Self {
+            default_per_minute,
+            counters: Mutex::new(HashMap::new()),
+            clock: clock::system(),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Quota from `API_QUOTA_PER_MINUTE`, or [`DEFAULT_QUOTA_PER_MINUTE`]
+    pub fn from_env() -> Self {
+        let default_per_minute = std::env::var("API_QUOTA_PER_MINUTE")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_QUOTA_PER_MINUTE);
+        Self::new(default_per_minute)
+    }
+
+    fn limit(&self, quota: Option<u32>) -> u32 {
+        quota.unwrap_or(self.default_per_minute)
+    }
+
+    /// Count a request to `endpoint` made with `key`, unless it is over `quota`
+    /// (the default when `None`)
+    pub async fn admit(&self, key: &str, endpoint: &str, quota: Option<u32>) -> Result<Admitted, QuotaExceeded> {
+        let limit = self.limit(quota);
+        let now = self.clock.now();
+        let mut counters = self.counters.lock().await;
+        let counters = counters.entry(key.to_string()).or_default();
+        let start = counters.roll(now);
+        let resets_at = start + TimeDelta::minutes(1);
+
+        if counters.used >= limit {
+            counters.rejected += 1;
+            counters.count_day(now, true);
+            let retry_after = (resets_at - now).to_std().unwrap_or_default();
+            return Err(QuotaExceeded { limit, retry_after });
+        }
+        counters.used += 1;
+        *counters.endpoints.entry(endpoint.to_string()).or_default() += 1;
+        counters.count_day(now, false);
+        Ok(Admitted {
+            limit,
+            remaining: limit - counters.used,
+            resets_at,
+        })
+    }
+
+    /// What `key` has used in the current window and the past days
+    pub async fn usage(&self, key: &str, quota: Option<u32>) -> KeyUsage {
+        let limit = self.limit(quota);
+        let now = self.clock.now();
+        let mut counters = self.counters.lock().await;
+        let counters = counters.entry(key.to_string()).or_default();
+        let start = counters.roll(now);
+
+        KeyUsage {
+            window: WindowUsage {
+                start,
+                resets_at: start + TimeDelta::minutes(1),
+                limit,
+                used: counters.used,
+                remaining: limit.saturating_sub(counters.used),
+                endpoints: counters.endpoints.clone(),
+                rejected: counters.rejected,
+            },
+            daily: counters
+                .daily
+                .iter()
+                .map(|(date, (requests, rejected))| DailyUsage {
+                    date: *date,
+                    requests: *requests,
+                    rejected: *rejected,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    #[tokio::test]
+    async fn test_limits_each_key_per_window_and_reports_usage() {
+        let clock = Arc::new(MockClock::new());
+        let meter = UsageMeter::new(2).with_clock(clock.clone());
+
+        let admitted = meter.admit("acme", "GET /deals", None).await.unwrap();
+        assert_eq!((admitted.limit, admitted.remaining), (2, 1));
+        meter.admit("acme", "GET /coupons", None).await.unwrap();
+        let refused = meter.admit("acme", "GET /deals", None).await.unwrap_err();
+        assert_eq!(refused.retry_after, Duration::from_secs(60));
+        // Other keys and per-tenant quotas are counted apart
+        assert_eq!(meter.admit("globex", "GET /deals", Some(5)).await.unwrap().remaining, 4);
+
+        let usage = meter.usage("acme", None).await;
+        assert_eq!((usage.window.used, usage.window.remaining, usage.window.rejected), (2, 0, 1));
+        assert_eq!(usage.window.endpoints, BTreeMap::from([("GET /coupons".to_string(), 1), ("GET /deals".to_string(), 1)]));
+
+        // A new window restores the quota; the day keeps counting
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(meter.admit("acme", "GET /deals", None).await.unwrap().remaining, 1);
+        let usage = meter.usage("acme", None).await;
+        assert_eq!(usage.window.endpoints.len(), 1);
+        let requests: u64 = usage.daily.iter().map(|day| day.requests).sum();
+        let rejected: u64 = usage.daily.iter().map(|day| day.rejected).sum();
+        assert_eq!((requests, rejected), (3, 1));
+    }
+}
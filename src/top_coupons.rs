@@ -0,0 +1,326 @@
+//! Per-merchant top coupons, materialized for the extension
+//!
+//! The extension asks for a merchant's coupons on every checkout page it sees.
+//! Ranking them per request means copying the merchant's listings out of the coupon
+//! store, which queues behind ingest writes, and scoring each one with the success
+//! model. [`TopCoupons`] instead keeps the best few codes of every merchant asked
+//! about ranked in memory, so a request takes one read lock on a map.
+//!
+//! Lists are re-ranked in the background, never on the request path once a merchant
+//! is cached. Every coupon store upsert (ingest) and every reported outcome
+//! (feedback) marks the merchant stale, and stale merchants are re-ranked once per
+//! [`SETTLE_DELAY`], so a burst of upserts costs one re-rank per merchant; the old
+//! list is served until then. Moderation decisions and expired codes go through
+//! [`TopCoupons::invalidate`] instead, which drops the list at once. A list also
+//! lapses by itself when one of its codes reaches `valid_until`.
+//!
+//! With `REDIS_URL` set, ranked lists are also kept in Redis for [`REDIS_TTL`], and
+//! an instance without a list of its own takes it from there before ranking.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::Notify;
+
+use crate::clock::{self, Clock};
+use crate::coupon_success::CouponSuccessPredictor;
+use crate::models::coupon_listing::CouponListing;
+use crate::models::domain::MerchantDomain;
+use crate::reputation::ReputationService;
+use crate::storage::coupon_store::CouponStore;
+
+/// Codes kept per merchant unless `TOP_COUPONS_LIMIT` says otherwise
+pub const DEFAULT_LIMIT: usize = 10;
+/// Quiet time after a merchant goes stale before it is re-ranked
+pub const SETTLE_DELAY: Duration = Duration::from_millis(250);
+/// How long a ranked list stays in Redis
+pub const REDIS_TTL: Duration = Duration::from_secs(300);
+/// A Redis lookup on the request path gives up after this long
+const REDIS_TIMEOUT: Duration = Duration::from_millis(50);
+const REDIS_PREFIX: &str = "top_coupons:";
+
+struct Entry {
+    coupons: Arc<Vec<CouponListing>>,
+    /// When the first of `coupons` expires
+    lapses_at: Option<DateTime<Utc>>,
+}
+
+impl Entry {
+    fn new(coupons: Vec<CouponListing>) -> Self {
+        Self {
+            lapses_at: coupons.iter().filter_map(|c| c.valid_until).min(),
+            coupons: Arc::new(coupons),
+        }
+    }
+
+    fn lapsed(&self, now: DateTime<Utc>) -> bool {
+        self.lapses_at.is_some_and(|at| at <= now)
+    }
+}
+
+pub struct TopCoupons {
+    limit: usize,
+    store: Arc<CouponStore>,
+    predictor: Arc<CouponSuccessPredictor>,
+    reputation: Arc<ReputationService>,
+    entries: RwLock<HashMap<MerchantDomain, Arc<Entry>>>,
+    stale: Mutex<HashSet<MerchantDomain>>,
+    wake: Notify,
+    redis: Option<redis::Client>,
+    clock: Arc<dyn Clock>,
+}
+
+impl TopCoupons {
+    /// Keep the best `limit` codes of each merchant, in memory only
+    pub fn new(store: Arc<CouponStore>, predictor: Arc<CouponSuccessPredictor>, reputation: Arc<ReputationService>, limit: usize) -> Self {
+        Self {
+            limit: limit.max(1),
+            store,
+            predictor,
+            reputation,
+            entries: RwLock::new(HashMap::new()),
+            stale: Mutex::new(HashSet::new()),
+            wake: Notify::new(),
+            redis: None,
+            clock: clock::system(),
+        }
+    }
+
+    /// `TOP_COUPONS_LIMIT` codes per merchant (default [`DEFAULT_LIMIT`]), shared
+    /// through `REDIS_URL` when set
+    pub fn from_env(store: Arc<CouponStore>, predictor: Arc<CouponSuccessPredictor>, reputation: Arc<ReputationService>) -> Self {
+        let limit = std::env::var("TOP_COUPONS_LIMIT")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_LIMIT);
+        let mut top = Self::new(store, predictor, reputation, limit);
+        if let Ok(url) = std::env::var("REDIS_URL") {
+            match redis::Client::open(url) {
+                Ok(client) => top.redis = Some(client),
+                Err(e) => tracing::warn!(error = %e, "Invalid REDIS_URL, keeping top coupons local"),
+            }
+        }
+        top
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// The merchant's best unexpired codes, most likely to work first
+    pub async fn get(&self, merchant: &MerchantDomain) -> Arc<Vec<CouponListing>> {
+        let now = self.clock.now();
+        let cached = self.entries.read().unwrap().get(merchant).cloned();
+        if let Some(entry) = cached.filter(|entry| !entry.lapsed(now)) {
+            return entry.coupons.clone();
+        }
+
+        if let Some(entry) = self.load_shared(merchant).filter(|entry| !entry.lapsed(now)) {
+            let entry = Arc::new(entry);
+            self.entries.write().unwrap().insert(merchant.clone(), entry.clone());
+            return entry.coupons.clone();
+        }
+        self.refresh(merchant).await
+    }
+
+    /// Re-rank the merchant's codes now
+    pub async fn refresh(&self, merchant: &MerchantDomain) -> Arc<Vec<CouponListing>> {
+        let now = self.clock.now();
+        let mut listed = self.store.for_merchant(merchant).await;
+        listed.retain(|c| c.valid_until.is_none_or(|until| until > now));
+        self.predictor.annotate(&mut listed, &self.reputation).await;
+        listed.sort_by(|a, b| {
+            b.predicted_success
+                .unwrap_or(0.0)
+                .total_cmp(&a.predicted_success.unwrap_or(0.0))
+        });
+        listed.truncate(self.limit);
+
+        self.store_shared(merchant, &listed);
+        let entry = Arc::new(Entry::new(listed));
+        self.entries.write().unwrap().insert(merchant.clone(), entry.clone());
+        entry.coupons.clone()
+    }
+
+    /// Re-rank the merchant in the background, serving its current list until then.
+    /// Merchants nobody has asked about are left alone.
+    pub fn mark_stale(&self, merchant: &MerchantDomain) {
+        if !self.entries.read().unwrap().contains_key(merchant) {
+            return;
+        }
+        self.stale.lock().unwrap().insert(merchant.clone());
+        self.wake.notify_one();
+    }
+
+    /// Drop the merchant's list here and in Redis, so the next request ranks it
+    /// afresh. For changes that must not wait for a re-rank, such as a moderator
+    /// rejecting a code or the best code expiring.
+    pub fn invalidate(&self, merchant: &MerchantDomain) {
+        self.entries.write().unwrap().remove(merchant);
+        self.stale.lock().unwrap().remove(merchant);
+        if let Some(client) = &self.redis {
+            let deleted = client
+                .get_connection_with_timeout(REDIS_TIMEOUT)
+                .and_then(|mut con| redis::cmd("DEL").arg(redis_key(merchant)).query::<()>(&mut con));
+            if let Err(e) = deleted {
+                tracing::warn!(merchant = %merchant, error = %e, "Failed to drop shared top coupons");
+            }
+        }
+    }
+
+    fn load_shared(&self, merchant: &MerchantDomain) -> Option<Entry> {
+        let client = self.redis.as_ref()?;
+        let stored: redis::RedisResult<Option<String>> = client
+            .get_connection_with_timeout(REDIS_TIMEOUT)
+            .and_then(|mut con| redis::cmd("GET").arg(redis_key(merchant)).query(&mut con));
+        match stored {
+            Ok(stored) => serde_json::from_str(&stored?).ok().map(Entry::new),
+            Err(e) => {
+                tracing::warn!(merchant = %merchant, error = %e, "Failed to read shared top coupons");
+                None
+            }
+        }
+    }
+
+    fn store_shared(&self, merchant: &MerchantDomain, coupons: &[CouponListing]) {
+        let Some(client) = &self.redis else {
+            return;
+        };
+        let Ok(json) = serde_json::to_string(coupons) else {
+            return;
+        };
+        let stored = client.get_connection_with_timeout(REDIS_TIMEOUT).and_then(|mut con| {
+            redis::cmd("SET")
+                .arg(redis_key(merchant))
+                .arg(json)
+                .arg("EX")
+                .arg(REDIS_TTL.as_secs())
+                .query::<()>(&mut con)
+        });
+        if let Err(e) = stored {
+            tracing::warn!(merchant = %merchant, error = %e, "Failed to share top coupons");
+        }
+    }
+
+    /// Mark merchants stale as the coupon store changes, and re-rank stale merchants
+    /// after each burst. Runs until the process exits.
+    pub async fn start_background_tasks(self: Arc<Self>) {
+        let mut updates = self.store.subscribe();
+        let ingest = self.clone();
+        tokio::spawn(async move {
+            loop {
+                match updates.recv().await {
+                    Ok(merchant) => ingest.mark_stale(&merchant),
+                    // Too many upserts to tell which merchants changed
+                    Err(RecvError::Lagged(_)) => {
+                        let cached: Vec<MerchantDomain> = ingest.entries.read().unwrap().keys().cloned().collect();
+                        cached.iter().for_each(|merchant| ingest.mark_stale(merchant));
+                    }
+                    Err(RecvError::Closed) => return,
+                }
+            }
+        });
+
+        loop {
+            self.wake.notified().await;
+            self.clock.sleep(SETTLE_DELAY).await;
+            let stale = std::mem::take(&mut *self.stale.lock().unwrap());
+            for merchant in stale {
+                self.refresh(&merchant).await;
+            }
+        }
+    }
+}
+
+fn redis_key(merchant: &MerchantDomain) -> String {
+    format!("{}{}", REDIS_PREFIX, merchant)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::coupon_success::CouponSuccessModel;
+    use crate::models::coupon_listing::CouponSource;
+    use crate::models::domain::CouponCode;
+
+    fn listing(code: &str, domain: &str, confidence: f64, valid_until: Option<DateTime<Utc>>) -> CouponListing {
+        CouponListing {
+            code: CouponCode::parse(code).unwrap(),
+            title: format!("{} off", code),
+            description: None,
+            locale: None,
+            merchant_domain: MerchantDomain::parse(domain).unwrap(),
+            discount_type: "percentage".to_string(),
+            discount_value: Some(10.0),
+            minimum_order: None,
+            source: CouponSource::AffiliateApi,
+            license: None,
+            extraction_confidence: confidence,
+            scraped_at: Utc::now(),
+            valid_until,
+            predicted_success: None,
+            tags: Vec::new(),
+        }
+    }
+
+    fn codes(coupons: &[CouponListing]) -> Vec<&str> {
+        coupons.iter().map(|c| c.code.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_serves_ranked_list_until_stale_merchants_are_re_ranked_or_invalidated() {
+        let clock = Arc::new(MockClock::new());
+        let shop = MerchantDomain::parse("shop.example").unwrap();
+        let store = Arc::new(CouponStore::with_coupons(vec![
+            listing("LOW", "shop.example", 0.2, None),
+            listing("HIGH", "shop.example", 0.95, None),
+            listing("MID", "shop.example", 0.6, Some(clock.now() + chrono::Duration::hours(1))),
+            listing("OTHER", "elsewhere.example", 0.9, None),
+        ]));
+        let top = Arc::new(
+            TopCoupons::new(
+                store.clone(),
+                Arc::new(CouponSuccessPredictor::new(CouponSuccessModel::default())),
+                Arc::new(ReputationService::new(None)),
+                2,
+            )
+            .with_clock(clock.clone()),
+        );
+
+        let ranked = top.get(&shop).await;
+        assert_eq!(codes(&ranked), ["HIGH", "MID"]);
+        assert!(ranked.iter().all(|c| c.predicted_success.is_some()));
+
+        // Ingest marks the merchant stale; the old list is served until the re-rank
+        let mut updates = store.subscribe();
+        store.upsert(listing("HIGH", "shop.example", 0.05, None)).await;
+        assert_eq!(updates.recv().await.unwrap(), shop);
+        top.mark_stale(&shop);
+        assert!(Arc::ptr_eq(&top.get(&shop).await, &ranked));
+        let stale = std::mem::take(&mut *top.stale.lock().unwrap());
+        assert_eq!(stale, HashSet::from([shop.clone()]));
+        assert_eq!(codes(&top.refresh(&shop).await), ["MID", "LOW"]);
+
+        // A code reaching valid_until lapses the list
+        clock.advance(Duration::from_secs(2 * 3600));
+        assert_eq!(codes(&top.get(&shop).await), ["LOW", "HIGH"]);
+
+        // Invalidation drops the list at once
+        let before = top.get(&shop).await;
+        top.invalidate(&shop);
+        assert!(!Arc::ptr_eq(&top.get(&shop).await, &before));
+
+        // Merchants nobody asked about are not re-ranked
+        top.mark_stale(&MerchantDomain::parse("elsewhere.example").unwrap());
+        assert!(top.stale.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,195 @@
+//! Savings widgets embedded in partner blogs
+//!
+//! A partner's page shows a merchant's top few coupons and its best live deal next
+//! to an article, from `GET /widget/{merchant}`. The [`Widget`] holds only what the
+//! embed displays, so it stays small enough to inline, and is the same for every
+//! visitor, so CDNs can cache it (see [`crate::api`] for the cache headers and how
+//! widget keys are checked against the embedding page).
+//!
+//! Widgets are shown on pages we do not control, so they only carry coupons whose
+//! license allows redistribution ([`License::REDISTRIBUTABLE`]), with the
+//! attribution the license requires.
+//!
+//! With the `widget-html` feature a widget can also be served as an HTML fragment,
+//! rendered from `templates/widget.html`, for pages that embed it without script.
+//!
+//! [`License::REDISTRIBUTABLE`]: crate::models::coupon_listing::License::REDISTRIBUTABLE
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::compliance::JurisdictionProfile;
+use crate::models::coupon_listing::CouponListing;
+use crate::models::deal::{Deal, DealStatus};
+use crate::models::domain::{CouponCode, MerchantDomain, Money};
+
+/// Coupons a widget shows
+pub const MAX_COUPONS: usize = 3;
+
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct WidgetCoupon {
+    pub code: CouponCode,
+    pub title: String,
+    pub merchant_domain: MerchantDomain,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub valid_until: Option<DateTime<Utc>>,
+    /// To be shown with the code, as its license requires
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attribution: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct WidgetDeal {
+    pub title: String,
+    pub merchant_domain: MerchantDomain,
+    pub category: String,
+    pub price: Money,
+    pub original_price: Money,
+    /// Percent off; the honest discount when the list price was found inflated
+    pub discount: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image_url: Option<String>,
+    /// Shown next to the price where the viewer's country requires it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub price_disclosure: Option<String>,
+}
+
+/// What a widget displays for one merchant
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct Widget {
+    pub merchant: MerchantDomain,
+    /// Best first, at most [`MAX_COUPONS`]
+    pub coupons: Vec<WidgetCoupon>,
+    /// The merchant's live deal with the largest discount
+    pub best_deal: Option<WidgetDeal>,
+}
+
+#[cfg(feature = "widget-html")]
+#[derive(askama::Template)]
+#[template(path = "widget.html")]
+struct HtmlWidget<'a> {
+    widget: &'a Widget,
+}
+
+impl Widget {
+    /// The widget of `merchant` from its ranked, license-checked `coupons` and any `deals`
+    pub fn build(merchant: MerchantDomain, coupons: &[CouponListing], deals: &[Deal]) -> Self {
+        let coupons = coupons
+            .iter()
+            .take(MAX_COUPONS)
+            .map(|coupon| WidgetCoupon {
+                code: coupon.code.clone(),
+                title: coupon.title.clone(),
+                merchant_domain: coupon.merchant_domain.clone(),
+                valid_until: coupon.valid_until,
+                attribution: coupon.license.as_ref().and_then(|tag| tag.attribution.clone()),
+            })
+            .collect();
+
+        let discount = |deal: &Deal| deal.honest_discount.unwrap_or(deal.discount);
+        let best_deal = deals
+            .iter()
+            .filter(|deal| deal.merchant_domain == merchant && deal.status == DealStatus::Active)
+            .max_by(|a, b| discount(a).total_cmp(&discount(b)))
+            .map(|deal| WidgetDeal {
+                title: deal.title.clone(),
+                merchant_domain: deal.merchant_domain.clone(),
+                category: deal.category.clone(),
+                price: deal.price,
+                original_price: deal.original_price,
+                discount: discount(deal),
+                image_url: deal.image_url.clone(),
+                price_disclosure: None,
+            });
+
+        Self {
+            merchant,
+            coupons,
+            best_deal,
+        }
+    }
+
+    /// Drop the offers `profile` excludes and disclose prices as it requires
+    pub fn comply(&mut self, profile: &JurisdictionProfile) {
+        self.coupons.retain(|coupon| !profile.excludes(None, &[&coupon.title]));
+        if self.best_deal.as_ref().is_some_and(|deal| profile.excludes(Some(&deal.category), &[&deal.title])) {
+            self.best_deal = None;
+        }
+        if let Some(deal) = &mut self.best_deal {
+            deal.price_disclosure = profile.price_disclosure.clone();
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.coupons.is_empty() && self.best_deal.is_none()
+    }
+
+    /// The widget as an HTML fragment, to be placed inside the partner's page
+    #[cfg(feature = "widget-html")]
+    pub fn render_html(&self) -> Result<String, askama::Error> {
+        askama::Template::render(&HtmlWidget { widget: self })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::*;
+    use crate::sandbox::faker::Faker;
+
+    #[test]
+    fn test_builds_a_compact_widget_and_applies_compliance() {
+        let mut faker = Faker::new(7);
+        let mut deals = faker.catalogue(20).deals;
+        let merchant = deals[0].merchant_domain.clone();
+        let coupons: Vec<CouponListing> = faker
+            .coupons(30)
+            .into_iter()
+            .filter(|coupon| coupon.merchant_domain == merchant)
+            .collect();
+        assert!(coupons.len() > MAX_COUPONS);
+        // The best deal is a dead one, then an inflated one
+        let mine: Vec<usize> = (0..deals.len()).filter(|&i| deals[i].merchant_domain == merchant).collect();
+        deals[mine[0]].discount = 99.0;
+        deals[mine[0]].status = DealStatus::Dead;
+        deals[mine[1]].discount = 98.0;
+        deals[mine[1]].honest_discount = Some(1.0);
+
+        let mut widget = Widget::build(merchant.clone(), &coupons, &deals);
+        assert_eq!(widget.coupons.len(), MAX_COUPONS);
+        assert_eq!(widget.coupons[0].code, coupons[0].code);
+        let best = widget.best_deal.clone().unwrap();
+        assert!(best.discount < 98.0);
+        assert!(deals.iter().any(|deal| deal.title == best.title && deal.merchant_domain == merchant));
+
+        widget.comply(&JurisdictionProfile {
+            price_disclosure: Some("Alle Preise inkl. MwSt.".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(widget.best_deal.as_ref().unwrap().price_disclosure.as_deref(), Some("Alle Preise inkl. MwSt."));
+        widget.comply(&JurisdictionProfile {
+            excluded_categories: BTreeSet::from([best.category.clone()]),
+            excluded_keywords: vec![widget.coupons[0].title.clone()],
+            price_disclosure: None,
+        });
+        assert!(widget.best_deal.is_none());
+        assert!(widget.coupons.iter().all(|coupon| coupon.title != coupons[0].title));
+
+        let other = MerchantDomain::parse("elsewhere.example").unwrap();
+        assert!(Widget::build(other, &[], &deals).is_empty());
+    }
+
+    #[cfg(feature = "widget-html")]
+    #[test]
+    fn test_renders_an_escaped_fragment() {
+        let mut coupons = Faker::new(7).coupons(1);
+        coupons[0].title = "<script>alert(1)</script> 10% off".to_string();
+        let merchant = coupons[0].merchant_domain.clone();
+        let html = Widget::build(merchant, &coupons, &[]).render_html().unwrap();
+        assert!(html.starts_with("<div class=\"dealmate-widget\""));
+        assert!(html.contains(coupons[0].code.as_str()));
+        assert!(!html.contains("<script>"));
+    }
+}
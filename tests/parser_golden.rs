@@ -0,0 +1,22 @@
+//! Parser output over the fixture corpus must match the blessed goldens
+//!
+//! After an intended change in extraction, review the diff this test prints and run
+//! `cargo run -- parser bless` to update the goldens.
+
+use std::path::Path;
+
+use deal_service::coupon_engine::golden;
+
+#[tokio::test]
+async fn test_parser_output_matches_goldens() {
+    let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join(golden::DEFAULT_CORPUS);
+    assert!(!golden::fixtures(&corpus).unwrap().is_empty(), "empty corpus at {}", corpus.display());
+
+    let mismatches = golden::check(&corpus).await.unwrap();
+    let report: Vec<String> = mismatches.iter().map(ToString::to_string).collect();
+    assert!(
+        mismatches.is_empty(),
+        "parser output differs from goldens:\n{}\nRun `cargo run -- parser bless` if this is intended.",
+        report.join("\n")
+    );
+}
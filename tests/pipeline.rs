@@ -0,0 +1,260 @@
+//! End-to-end coupon pipeline tests
+//!
+//! A local mock merchant site serves the fixtures in `tests/fixtures/merchant` and
+//! every batch goes through the real scraper, parser, validator, deduplicator and
+//! scrape job queue. Mock clocks stand in for retry backoff and rate-limit waits.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::http::{StatusCode, Uri};
+use axum::response::IntoResponse;
+use axum::Router;
+
+use deal_service::clock::{Clock, MockClock};
+use deal_service::coupon_engine::proxy_manager::{ProxyConfig, ProxyManager, ProxySource, ProxyType};
+use deal_service::coupon_engine::rate_limiter::RateLimiter;
+use deal_service::coupon_engine::scraper::Scraper;
+use deal_service::jobs::{JobPriority, JobStatus, ScrapeQueue};
+use deal_service::{CouponEngine, EngineConfig, RawCoupon};
+
+const COUPONS_HTML: &str = include_str!("fixtures/merchant/coupons.html");
+const OFFERS_JSON: &str = include_str!("fixtures/merchant/offers.json");
+const PARTNER_CSV: &str = include_str!("fixtures/merchant/partner.csv");
+
+#[derive(Clone, Default)]
+struct SiteLog {
+    /// Requests per path
+    hits: Arc<Mutex<HashMap<String, usize>>>,
+    /// Requests that arrived in absolute form, i.e. through the site acting as a proxy
+    proxied: Arc<Mutex<Vec<String>>>,
+}
+
+impl SiteLog {
+    fn hits(&self, path: &str) -> usize {
+        self.hits.lock().unwrap().get(path).copied().unwrap_or(0)
+    }
+}
+
+/// A merchant site on a random local port. It also answers proxy requests, since an
+/// HTTP proxy receives the same request with the full URL as its target.
+struct MerchantSite {
+    addr: SocketAddr,
+    log: SiteLog,
+}
+
+impl MerchantSite {
+    async fn start() -> Self {
+        let log = SiteLog::default();
+        let handler_log = log.clone();
+        let app = Router::new().fallback(move |uri: Uri| serve(handler_log.clone(), uri));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        Self { addr, log }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+}
+
+async fn serve(log: SiteLog, uri: Uri) -> impl IntoResponse {
+    let path = uri.path().to_string();
+    let hits = {
+        let mut hits = log.hits.lock().unwrap();
+        let count = hits.entry(path.clone()).or_insert(0);
+        *count += 1;
+        *count
+    };
+    if uri.authority().is_some() {
+        log.proxied.lock().unwrap().push(uri.to_string());
+    }
+
+    match path.as_str() {
+        "/coupons.html" => (StatusCode::OK, COUPONS_HTML),
+        "/offers.json" => (StatusCode::OK, OFFERS_JSON),
+        "/partner.csv" => (StatusCode::OK, PARTNER_CSV),
+        // Overloaded on the first request only
+        "/flaky/partner.csv" if hits == 1 => (StatusCode::SERVICE_UNAVAILABLE, "try again later"),
+        "/flaky/partner.csv" => (StatusCode::OK, PARTNER_CSV),
+        _ => (StatusCode::NOT_FOUND, "not found"),
+    }
+}
+
+/// A local address with nothing listening on it
+fn closed_addr() -> SocketAddr {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap()
+}
+
+fn config() -> EngineConfig {
+    EngineConfig {
+        request_timeout_secs: 5,
+        proxy_rotation_enabled: false,
+        user_agent_rotation: false,
+        ..EngineConfig::default()
+    }
+}
+
+fn engine(config: EngineConfig, clock: Arc<dyn Clock>) -> deal_service::CouponEngineBuilder {
+    let rate = config.rate_limit_per_domain;
+    CouponEngine::builder(config.clone())
+        .fetcher(Arc::new(Scraper::new(config).with_clock(clock.clone())))
+        .rate_limiter(Arc::new(RateLimiter::new(rate).with_clock(clock)))
+}
+
+/// `(code, discount type, discount value, source path)`, sorted by code
+fn summary(coupons: &[RawCoupon]) -> Vec<(String, String, Option<f64>, String)> {
+    let mut summary: Vec<_> = coupons
+        .iter()
+        .map(|c| {
+            let path = url::Url::parse(&c.source_url).unwrap().path().to_string();
+            (c.code.to_string(), c.discount_type.as_str().to_string(), c.discount_value, path)
+        })
+        .collect();
+    summary.sort_by(|a, b| a.0.cmp(&b.0));
+    summary
+}
+
+fn row(code: &str, kind: &str, value: Option<f64>, path: &str) -> (String, String, Option<f64>, String) {
+    (code.to_string(), kind.to_string(), value, path.to_string())
+}
+
+/// The partner CSV after validation and deduplication: MEGA150 is rejected and the
+/// second SUMMER20 row dropped
+fn partner_csv_coupons(path: &str) -> Vec<(String, String, Option<f64>, String)> {
+    vec![
+        row("SAVE5", "fixed", Some(5.0), path),
+        row("SUMMER20", "percentage", Some(20.0), path),
+        row("WELCOME10", "percentage", Some(10.0), path),
+    ]
+}
+
+#[tokio::test]
+async fn test_scrape_job_stores_exact_coupons() {
+    let site = MerchantSite::start().await;
+    let path = std::env::temp_dir().join(format!("pipeline_jobs_{}.json", uuid::Uuid::new_v4()));
+    let queue = Arc::new(ScrapeQueue::new(Some(path.clone())));
+    let engine = Arc::new(engine(config(), Arc::new(MockClock::new())).build());
+
+    let urls = vec![site.url("/coupons.html"), site.url("/offers.json"), site.url("/partner.csv")];
+    let job = queue.submit("acme", urls, JobPriority::Interactive).await.unwrap();
+    let workers = tokio::spawn(queue.clone().start_background_tasks(engine));
+
+    let finished = tokio::time::timeout(Duration::from_secs(20), async {
+        loop {
+            let current = queue.get("acme", job.id).await.unwrap();
+            if current.status.is_finished() {
+                return current;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .expect("job finished");
+    workers.abort();
+    assert_eq!(finished.status, JobStatus::Completed);
+
+    // Read back what the queue persisted rather than the in-memory copy
+    let stored: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    let coupons: Vec<RawCoupon> =
+        serde_json::from_value(stored["jobs"][job.id.to_string()]["coupons"].clone()).unwrap();
+    let _ = std::fs::remove_file(&path);
+
+    // Codes picked out of markup or JSON carry no discount type, so only the one
+    // mentioned in running text with its discount survives validation
+    let mut expected = partner_csv_coupons("/partner.csv");
+    expected.insert(2, row("TRAIL15", "percentage", Some(15.0), "/coupons.html"));
+    assert_eq!(summary(&coupons), expected);
+    for path in ["/coupons.html", "/offers.json", "/partner.csv"] {
+        assert_eq!(site.log.hits(path), 1, "{}", path);
+    }
+}
+
+#[tokio::test]
+async fn test_retries_a_failing_source_with_backoff() {
+    let site = MerchantSite::start().await;
+    let clock = Arc::new(MockClock::new());
+    let start = clock.instant();
+    let engine = engine(config(), clock.clone()).build();
+
+    let coupons = engine.process_batch(vec![site.url("/flaky/partner.csv")]).await.unwrap();
+
+    assert_eq!(site.log.hits("/flaky/partner.csv"), 2);
+    assert_eq!(clock.instant() - start, Duration::from_secs(2));
+    assert_eq!(summary(&coupons), partner_csv_coupons("/flaky/partner.csv"));
+}
+
+#[tokio::test]
+async fn test_rate_limit_holds_back_requests_over_quota() {
+    let site = MerchantSite::start().await;
+    let clock = Arc::new(MockClock::new());
+    let start = clock.instant();
+    let config = EngineConfig {
+        rate_limit_per_domain: 2,
+        max_concurrent_requests: 1,
+        ..config()
+    };
+    let engine = engine(config, clock.clone()).build();
+
+    let urls = vec![site.url("/offers.json"), site.url("/coupons.html"), site.url("/partner.csv")];
+    let coupons = engine.process_batch(urls).await.unwrap();
+
+    // The third request waits for the first to leave the one-minute window
+    assert!(clock.instant() - start >= Duration::from_secs(60));
+    assert_eq!(site.log.hits("/partner.csv"), 1);
+    assert_eq!(summary(&coupons).len(), 4);
+}
+
+#[tokio::test]
+async fn test_fails_over_to_the_next_proxy() {
+    let site = MerchantSite::start().await;
+    let proxy = |url: String| ProxyConfig {
+        url,
+        username: None,
+        password: None,
+        proxy_type: ProxyType::Http,
+    };
+    let dead_proxy = format!("http://{}", closed_addr());
+    let proxies = Arc::new(ProxyManager::new());
+    proxies
+        .add_proxies(vec![proxy(dead_proxy.clone()), proxy(site.url(""))])
+        .await;
+
+    // The origin itself is unreachable, so only a working proxy can fetch the page
+    let origin = format!("http://{}/partner.csv", closed_addr());
+    let engine = engine(config(), Arc::new(MockClock::new()))
+        .proxies(proxies.clone() as Arc<dyn ProxySource>)
+        .build();
+
+    let coupons = engine.process_batch(vec![origin.clone()]).await.unwrap();
+
+    assert_eq!(*site.log.proxied.lock().unwrap(), vec![origin]);
+    let stats = proxies.get_stats().await;
+    assert_eq!((stats.total_success, stats.total_failures), (1, 1));
+    assert_eq!(summary(&coupons), partner_csv_coupons("/partner.csv"));
+}
+
+#[tokio::test]
+async fn test_memory_cap_slows_fetches_without_stalling_the_batch() {
+    let site = MerchantSite::start().await;
+    let config = EngineConfig {
+        memory_cap_bytes: 1,
+        ..config()
+    };
+    let engine = engine(config, Arc::new(MockClock::new())).build();
+
+    let urls = vec![site.url("/coupons.html"), site.url("/offers.json"), site.url("/partner.csv")];
+    let coupons = tokio::time::timeout(Duration::from_secs(20), engine.process_batch(urls)).await.expect("batch finished").unwrap();
+
+    // One page at a time: every fetch after the first waited for the one before
+    let memory = engine.memory();
+    assert_eq!(memory.fetches_delayed, 2);
+    assert_eq!((memory.held_bytes, memory.batches.len()), (0, 0));
+    assert!(memory.peak_bytes > 1);
+    assert_eq!(summary(&coupons).len(), 4);
+}